@@ -0,0 +1,108 @@
+#![allow(missing_docs)]
+
+// Measures the work done per command on the control channel: parsing the command lines of a
+// typical session and formatting the lines of a LIST reply. Run with:
+//
+//     cargo bench --features bench --bench control_channel
+
+use bytes::Bytes;
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use libunftp::storage::{Fileinfo, Metadata};
+use std::{path::PathBuf, time::SystemTime};
+
+const SESSION: &[&str] = &[
+    "USER alice\r\n",
+    "PASS secret\r\n",
+    "TYPE I\r\n",
+    "PWD\r\n",
+    "CWD /reports/2024\r\n",
+    "PASV\r\n",
+    "LIST -la\r\n",
+    "REST 1048576\r\n",
+    "RETR quarterly results.pdf\r\n",
+    "STOR uploads/some file with a rather long name.csv\r\n",
+    "SITE MD5 quarterly results.pdf\r\n",
+    "XYZZY plugh\r\n",
+    "QUIT\r\n",
+];
+
+fn parse(c: &mut Criterion) {
+    let lines: Vec<Bytes> = SESSION.iter().map(|line| Bytes::from_static(line.as_bytes())).collect();
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Elements(lines.len() as u64));
+    group.bench_function("session", |b| {
+        b.iter(|| {
+            for line in &lines {
+                black_box(libunftp::bench::parse_command(line.clone()));
+            }
+        })
+    });
+    group.finish();
+}
+
+#[derive(Debug, Clone)]
+struct Meta {
+    len: u64,
+    dir: bool,
+}
+
+impl Metadata for Meta {
+    fn len(&self) -> u64 {
+        self.len
+    }
+
+    fn is_dir(&self) -> bool {
+        self.dir
+    }
+
+    fn is_file(&self) -> bool {
+        !self.dir
+    }
+
+    fn is_symlink(&self) -> bool {
+        false
+    }
+
+    fn modified(&self) -> libunftp::storage::Result<SystemTime> {
+        Ok(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000))
+    }
+
+    fn gid(&self) -> u32 {
+        1000
+    }
+
+    fn uid(&self) -> u32 {
+        1000
+    }
+}
+
+fn list(c: &mut Criterion) {
+    let entries: Vec<Fileinfo<PathBuf, Meta>> = (0..1000)
+        .map(|i| Fileinfo {
+            path: PathBuf::from(format!("/reports/2024/file-{:04}.csv", i)),
+            metadata: Meta {
+                len: i * 1024,
+                dir: i % 10 == 0,
+            },
+        })
+        .collect();
+    let mut group = c.benchmark_group("list");
+    group.throughput(Throughput::Elements(entries.len() as u64));
+    group.bench_function("format", |b| {
+        b.iter_batched(
+            || Vec::with_capacity(100 * entries.len()),
+            |mut out: Vec<u8>| {
+                use std::io::Write;
+                for entry in &entries {
+                    write!(out, "{}\r\n", entry).unwrap();
+                }
+                out
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, parse, list);
+criterion_main!(benches);
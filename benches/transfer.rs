@@ -0,0 +1,198 @@
+#![allow(missing_docs)]
+
+// Measures RETR and STOR throughput against the filesystem back-end, over plain data connections
+// and over FTPS with PROT P, so that regressions in the data channel copy loop, like a too small
+// buffer, show up as lower numbers. Run with:
+//
+//     cargo bench --bench transfer
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use rustls::pki_types::{CertificateDer, ServerName};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    runtime::Runtime,
+};
+use tokio_rustls::TlsConnector;
+use unftp_sbe_fs::ServerExt;
+
+const FILE_SIZE: usize = 16 * 1024 * 1024;
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> Stream for S {}
+
+struct Client {
+    control: BufReader<Box<dyn Stream>>,
+    tls: Option<TlsConnector>,
+}
+
+impl Client {
+    async fn connect(port: u16, tls: Option<TlsConnector>) -> Client {
+        let mut attempts = 0;
+        let stream = loop {
+            match TcpStream::connect(("127.0.0.1", port)).await {
+                Ok(stream) => break stream,
+                Err(err) if attempts > 20 => panic!("{}", err),
+                Err(_) => {
+                    attempts += 1;
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+            }
+        };
+        let mut client = Client {
+            control: BufReader::new(Box::new(stream)),
+            tls: None,
+        };
+        assert!(client.reply().await.starts_with("220"));
+        if let Some(connector) = tls {
+            assert!(client.cmd("AUTH TLS").await.starts_with("234"));
+            let stream = client.control.into_inner();
+            let stream = connector.connect(server_name(), stream).await.unwrap();
+            client = Client {
+                control: BufReader::new(Box::new(stream)),
+                tls: Some(connector),
+            };
+            assert!(client.cmd("PBSZ 0").await.starts_with("200"));
+            assert!(client.cmd("PROT P").await.starts_with("200"));
+        }
+        assert!(client.cmd("USER anonymous").await.starts_with("331"));
+        assert!(client.cmd("PASS anonymous").await.starts_with("230"));
+        assert!(client.cmd("TYPE I").await.starts_with("200"));
+        client
+    }
+
+    async fn reply(&mut self) -> String {
+        let mut line = String::new();
+        self.control.read_line(&mut line).await.unwrap();
+        line.trim_end().to_string()
+    }
+
+    async fn cmd(&mut self, command: &str) -> String {
+        self.control.write_all(format!("{}\r\n", command).as_bytes()).await.unwrap();
+        self.control.flush().await.unwrap();
+        self.reply().await
+    }
+
+    async fn data_connection(&mut self) -> TcpStream {
+        let reply = self.cmd("PASV").await;
+        assert!(reply.starts_with("227"), "{}", reply);
+        let numbers: Vec<u16> = reply[reply.find('(').unwrap() + 1..reply.find(')').unwrap()]
+            .split(',')
+            .map(|n| n.parse().unwrap())
+            .collect();
+        TcpStream::connect(("127.0.0.1", numbers[4] * 256 + numbers[5])).await.unwrap()
+    }
+
+    // The server starts TLS on the data connection once it runs the transfer command.
+    async fn secure(&self, stream: TcpStream) -> Box<dyn Stream> {
+        match &self.tls {
+            Some(connector) => Box::new(connector.connect(server_name(), stream).await.unwrap()),
+            None => Box::new(stream),
+        }
+    }
+
+    async fn retr(&mut self) {
+        let data = self.data_connection().await;
+        let reply = self.cmd("RETR bench.bin").await;
+        assert!(reply.starts_with("150"), "{}", reply);
+        let data = self.secure(data).await;
+        let received = tokio::io::copy(&mut BufReader::new(data), &mut tokio::io::sink()).await.unwrap();
+        assert_eq!(received as usize, FILE_SIZE);
+        assert!(self.reply().await.starts_with("226"));
+    }
+
+    async fn stor(&mut self, content: &[u8]) {
+        let data = self.data_connection().await;
+        let reply = self.cmd("STOR upload.bin").await;
+        assert!(reply.starts_with("150"), "{}", reply);
+        let mut data = self.secure(data).await;
+        data.write_all(content).await.unwrap();
+        data.shutdown().await.unwrap();
+        drop(data);
+        assert!(self.reply().await.starts_with("226"));
+    }
+}
+
+fn server_name() -> ServerName<'static> {
+    ServerName::try_from("localhost").unwrap()
+}
+
+// Writes a self-signed certificate for localhost and its key next to the served files and returns
+// a connector that trusts it.
+fn certificate(dir: &Path) -> (PathBuf, PathBuf, TlsConnector) {
+    let rcgen::CertifiedKey { cert, key_pair } = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let certs_file = dir.join("cert.pem");
+    let key_file = dir.join("key.pem");
+    std::fs::write(&certs_file, cert.pem()).unwrap();
+    std::fs::write(&key_file, key_pair.serialize_pem()).unwrap();
+
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(CertificateDer::from(cert.der().to_vec())).unwrap();
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::aws_lc_rs::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    (certs_file, key_file, TlsConnector::from(Arc::new(config)))
+}
+
+fn transfer(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let root = std::env::temp_dir().join(format!("libunftp-bench-{}", std::process::id()));
+    let files = root.join("files");
+    std::fs::create_dir_all(&files).unwrap();
+    let content: Vec<u8> = (0..FILE_SIZE).map(|i| (i % 251) as u8).collect();
+    std::fs::write(files.join("bench.bin"), &content).unwrap();
+    let (certs_file, key_file, connector) = certificate(&root);
+
+    let mut clients = rt.block_on(async {
+        let plain = libunftp::Server::with_fs(files.clone()).build().unwrap();
+        tokio::spawn(plain.listen("127.0.0.1:2180"));
+        let ftps = libunftp::Server::with_fs(files.clone()).ftps(certs_file, key_file).build().unwrap();
+        tokio::spawn(ftps.listen("127.0.0.1:2181"));
+        vec![
+            ("plain", Client::connect(2180, None).await),
+            ("tls", Client::connect(2181, Some(connector)).await),
+        ]
+    });
+
+    let mut group = c.benchmark_group("transfer");
+    group.throughput(Throughput::Bytes(FILE_SIZE as u64));
+    group.sample_size(10);
+    for (name, client) in clients.iter_mut() {
+        group.bench_function(format!("retr/{}", name), |b| {
+            b.iter_custom(|iters| {
+                rt.block_on(async {
+                    let start = Instant::now();
+                    for _ in 0..iters {
+                        client.retr().await;
+                    }
+                    start.elapsed()
+                })
+            })
+        });
+        group.bench_function(format!("stor/{}", name), |b| {
+            b.iter_custom(|iters| {
+                rt.block_on(async {
+                    let start = Instant::now();
+                    for _ in 0..iters {
+                        client.stor(&content).await;
+                    }
+                    start.elapsed()
+                })
+            })
+        });
+    }
+    group.finish();
+
+    let _ = std::fs::remove_dir_all(&root);
+}
+
+criterion_group!(benches, transfer);
+criterion_main!(benches);
@@ -150,15 +150,20 @@ use bytes::Bytes;
 use flate2::read::GzDecoder;
 use ipnet::Ipv4Net;
 use iprange::IpRange;
-use libunftp::auth::{AuthenticationError, Authenticator, DefaultUser};
+use libunftp::auth::{AuthenticationError, Authenticator, DefaultUser, SecretString};
 use ring::{
-    digest::SHA256_OUTPUT_LEN,
+    constant_time::verify_slices_are_equal,
+    digest::{digest, SHA256, SHA256_OUTPUT_LEN},
     pbkdf2::{verify, PBKDF2_HMAC_SHA256},
+    rand::{SecureRandom, SystemRandom},
 };
 use serde::Deserialize;
 use std::io::prelude::*;
 use std::{collections::HashMap, fs, num::NonZeroU32, path::Path, time::Duration};
-use tokio::time::sleep;
+use tokio::time::{sleep_until, Instant};
+
+// How long a failed attempt takes at least, unless set otherwise.
+const DEFAULT_FAILURE_DELAY: Duration = Duration::from_millis(1500);
 use valid::{constraint::Length, Validate};
 
 #[derive(Deserialize, Clone, Debug)]
@@ -189,12 +194,16 @@ enum Credentials {
 #[derive(Clone, Debug)]
 pub struct JsonFileAuthenticator {
     credentials_map: HashMap<String, UserCreds>,
+    // Checked against for users that don't exist, so that they take as long as the others.
+    unknown_user_password: Password,
+    failure_delay: Duration,
+    failure_delay_jitter: Duration,
 }
 
 #[derive(Clone, Debug)]
 enum Password {
     PlainPassword {
-        password: Option<String>,
+        password: Option<SecretString>,
     },
     Pbkdf2Password {
         pbkdf2_salt: Bytes,
@@ -251,7 +260,60 @@ impl JsonFileAuthenticator {
     pub fn from_json<T: Into<String>>(json: T) -> Result<Self, Box<dyn std::error::Error>> {
         let credentials_list: Vec<Credentials> = serde_json::from_str::<Vec<Credentials>>(&json.into())?;
         let map: Result<HashMap<String, UserCreds>, _> = credentials_list.into_iter().map(Self::list_entry_to_map_entry).collect();
-        Ok(JsonFileAuthenticator { credentials_map: map? })
+        let credentials_map = map?;
+        let unknown_user_password = Self::unknown_user_password(&credentials_map);
+        Ok(JsonFileAuthenticator {
+            credentials_map,
+            unknown_user_password,
+            failure_delay: DEFAULT_FAILURE_DELAY,
+            failure_delay_jitter: Duration::ZERO,
+        })
+    }
+
+    /// Sets how long a failed authentication takes at least, counted from the moment it started,
+    /// so that failures take equally long whether the user exists and whatever was wrong. This
+    /// slows down password guessing as well. Defaults to 1.5 seconds.
+    pub fn failure_delay(mut self, delay: Duration) -> Self {
+        self.failure_delay = delay;
+        self
+    }
+
+    /// Adds a random duration of up to the given one to the [failure delay](Self::failure_delay)
+    /// of every failed authentication. Defaults to zero.
+    pub fn failure_delay_jitter(mut self, jitter: Duration) -> Self {
+        self.failure_delay_jitter = jitter;
+        self
+    }
+
+    // Costs as much to check as the most expensive password of the known users.
+    fn unknown_user_password(credentials_map: &HashMap<String, UserCreds>) -> Password {
+        let pbkdf2_iter = credentials_map
+            .values()
+            .filter_map(|creds| match creds.password {
+                Password::Pbkdf2Password { pbkdf2_iter, .. } => Some(pbkdf2_iter),
+                Password::PlainPassword { .. } => None,
+            })
+            .max();
+        match pbkdf2_iter {
+            Some(pbkdf2_iter) => Password::Pbkdf2Password {
+                pbkdf2_salt: Bytes::from_static(b"unknown user"),
+                pbkdf2_key: Bytes::from_static(&[0; SHA256_OUTPUT_LEN]),
+                pbkdf2_iter,
+            },
+            None => Password::PlainPassword {
+                password: Some(SecretString::default()),
+            },
+        }
+    }
+
+    fn failure_deadline(&self, start: Instant) -> Instant {
+        let mut deadline = start + self.failure_delay;
+        let jitter = u64::try_from(self.failure_delay_jitter.as_nanos()).unwrap_or(u64::MAX);
+        let mut random = [0u8; 8];
+        if jitter > 0 && SystemRandom::new().fill(&mut random).is_ok() {
+            deadline += Duration::from_nanos(u64::from_le_bytes(random) % jitter);
+        }
+        deadline
     }
 
     fn list_entry_to_map_entry(user_info: Credentials) -> Result<(String, UserCreds), Box<dyn std::error::Error>> {
@@ -264,7 +326,9 @@ impl JsonFileAuthenticator {
             } => (
                 username.clone(),
                 UserCreds {
-                    password: Password::PlainPassword { password },
+                    password: Password::PlainPassword {
+                        password: password.map(SecretString::from),
+                    },
                     client_cert,
                     allowed_ip_ranges: Self::parse_ip_range(username, ip_ranges)?,
                 },
@@ -316,17 +380,14 @@ impl JsonFileAuthenticator {
 
     fn check_password(given_password: &str, actual_password: &Password) -> Result<(), ()> {
         match actual_password {
-            Password::PlainPassword { password } => {
-                if let Some(pwd) = password {
-                    if pwd == given_password {
-                        Ok(())
-                    } else {
-                        Err(())
-                    }
-                } else {
-                    Err(())
-                }
+            // The digests are compared in constant time, so that neither the length nor the
+            // content of the password can be learned from how long the comparison takes.
+            Password::PlainPassword { password: Some(pwd) } => {
+                let given = digest(&SHA256, given_password.as_bytes());
+                let actual = digest(&SHA256, pwd.expose_secret().as_bytes());
+                verify_slices_are_equal(given.as_ref(), actual.as_ref()).map_err(|_| ())
             }
+            Password::PlainPassword { password: None } => Err(()),
             Password::Pbkdf2Password {
                 pbkdf2_iter,
                 pbkdf2_salt,
@@ -350,6 +411,7 @@ impl JsonFileAuthenticator {
 impl Authenticator<DefaultUser> for JsonFileAuthenticator {
     #[tracing_attributes::instrument]
     async fn authenticate(&self, username: &str, creds: &libunftp::auth::Credentials) -> Result<DefaultUser, AuthenticationError> {
+        let start = Instant::now();
         let res = if let Some(actual_creds) = self.credentials_map.get(username) {
             let client_cert = &actual_creds.client_cert;
             let certificate = &creds.certificate_chain.as_ref().and_then(|x| x.first());
@@ -383,7 +445,7 @@ impl Authenticator<DefaultUser> for JsonFileAuthenticator {
 
             let pass_check_result = match &creds.password {
                 Some(ref given_password) => {
-                    if Self::check_password(given_password, &actual_creds.password).is_ok() {
+                    if Self::check_password(given_password.expose_secret(), &actual_creds.password).is_ok() {
                         Some(Ok(DefaultUser {}))
                     } else {
                         Some(Err(AuthenticationError::BadPassword))
@@ -419,11 +481,14 @@ impl Authenticator<DefaultUser> for JsonFileAuthenticator {
                 },
             }
         } else {
+            if let Some(given_password) = &creds.password {
+                let _ = Self::check_password(given_password.expose_secret(), &self.unknown_user_password);
+            }
             Err(AuthenticationError::BadUser)
         };
 
         if res.is_err() {
-            sleep(Duration::from_millis(1500)).await;
+            sleep_until(self.failure_deadline(start)).await;
         }
 
         res
@@ -544,6 +609,35 @@ mod test {
         }
     }
 
+    // The clock is paused and only moves when every task waits for a timer, so the time spent on
+    // hashing doesn't count and the measured delays are exact.
+    #[tokio::test(start_paused = true)]
+    async fn test_json_failure_delay() {
+        use super::*;
+
+        let json: &str = r#"[
+  {
+    "username": "carol",
+    "password": "not so secure"
+  }
+]"#;
+        let json_authenticator = JsonFileAuthenticator::from_json(json)
+            .unwrap()
+            .failure_delay(Duration::from_millis(100))
+            .failure_delay_jitter(Duration::from_millis(50));
+
+        for (username, password) in [("carol", "not so secure!"), ("carol", "not so"), ("chuck", "not so secure")] {
+            let start = tokio::time::Instant::now();
+            assert!(json_authenticator.authenticate(username, &password.into()).await.is_err());
+            let elapsed = start.elapsed();
+            assert!(elapsed >= Duration::from_millis(100) && elapsed < Duration::from_millis(150), "{:?}", elapsed);
+        }
+
+        let start = tokio::time::Instant::now();
+        assert!(json_authenticator.authenticate("carol", &"not so secure".into()).await.is_ok());
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+
     #[tokio::test]
     async fn test_json_cert_sufficient() {
         use super::*;
@@ -36,7 +36,7 @@ impl Authenticator<DefaultUser> for PamAuthenticator {
 
         let mut auth = pam_auth::Authenticator::with_password(&service).map_err(|e| AuthenticationError::with_source("pam error", e))?;
 
-        auth.get_handler().set_credentials(&username, password);
+        auth.get_handler().set_credentials(&username, password.expose_secret());
         auth.authenticate().map_err(|e| AuthenticationError::with_source("pam error", e))?;
         Ok(DefaultUser {})
     }
@@ -4,7 +4,7 @@
 
 use async_trait::async_trait;
 use hyper::{http::uri::InvalidUri, Body, Client, Method, Request};
-use libunftp::auth::{AuthenticationError, Authenticator, Credentials, DefaultUser};
+use libunftp::auth::{AuthenticationError, Authenticator, Credentials, DefaultUser, SecretString};
 use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
 use regex::Regex;
 use serde_json::{json, Value};
@@ -207,7 +207,7 @@ trait TrimQuotes {
     fn trim_quotes(&self) -> &str;
 }
 
-impl TrimQuotes for String {
+impl TrimQuotes for str {
     // Used to trim quotes from a json-string formatted string
     fn trim_quotes(&self) -> &str {
         if self.starts_with('"') && self.ends_with('"') && self.len() > 1 {
@@ -223,27 +223,25 @@ impl Authenticator<DefaultUser> for RestAuthenticator {
     #[tracing_attributes::instrument]
     async fn authenticate(&self, username: &str, creds: &Credentials) -> Result<DefaultUser, AuthenticationError> {
         let username_url = utf8_percent_encode(username, NON_ALPHANUMERIC).collect::<String>();
-        let password = creds.password.as_ref().ok_or(AuthenticationError::BadPassword)?.as_ref();
-        let password_url = utf8_percent_encode(password, NON_ALPHANUMERIC).collect::<String>();
+        let password = creds.password.as_ref().ok_or(AuthenticationError::BadPassword)?.expose_secret();
+        // The encoded forms of the password are wiped as well, the request itself isn't ours to wipe.
+        let password_url = SecretString::new(utf8_percent_encode(password, NON_ALPHANUMERIC).collect::<String>());
         let source_ip = creds.source_ip.to_string();
         let source_ip_url = utf8_percent_encode(&source_ip, NON_ALPHANUMERIC).collect::<String>();
 
-        let url = self.fill_encoded_placeholders(&self.url, &username_url, &password_url, &source_ip_url);
+        let url = self.fill_encoded_placeholders(&self.url, &username_url, password_url.expose_secret(), &source_ip_url);
 
         let username = serde_json::to_string(username)
             .map_err(|e| AuthenticationError::ImplPropagated(e.to_string(), None))?
             .trim_quotes()
             .to_string();
-        let password = serde_json::to_string(password)
-            .map_err(|e| AuthenticationError::ImplPropagated(e.to_string(), None))?
-            .trim_quotes()
-            .to_string();
+        let password = SecretString::new(serde_json::to_string(password).map_err(|e| AuthenticationError::ImplPropagated(e.to_string(), None))?);
         let source_ip = serde_json::to_string(&source_ip)
             .map_err(|e| AuthenticationError::ImplPropagated(e.to_string(), None))?
             .trim_quotes()
             .to_string();
 
-        let body = self.fill_encoded_placeholders(&self.body, &username, &password, &source_ip);
+        let body = self.fill_encoded_placeholders(&self.body, &username, password.expose_secret().trim_quotes(), &source_ip);
 
         let req = Request::builder()
             .method(&self.method)
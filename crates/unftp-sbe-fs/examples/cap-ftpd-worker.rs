@@ -124,7 +124,7 @@ mod auth {
             let res = if let Some(actual_creds) = self.credentials_map.get(username) {
                 let pass_check_result = match &creds.password {
                     Some(ref given_password) => {
-                        if Self::check_password(given_password, &actual_creds.password).is_ok() {
+                        if Self::check_password(given_password.expose_secret(), &actual_creds.password).is_ok() {
                             Some(Ok(User::new(username, &actual_creds.home)))
                         } else {
                             Some(Err(AuthenticationError::BadPassword))
@@ -0,0 +1,39 @@
+//! A server that serves every session in a process of its own, using the connection helper
+//! protocol: the listening process starts this same program for each connection it accepts,
+//! passing it the file descriptor of the connected socket as its last argument.
+//!
+//! Start it without arguments. The processes serving the sessions are started by the server.
+#![allow(unsafe_code)]
+use libunftp::Server;
+use std::{
+    env,
+    os::fd::{FromRawFd, RawFd},
+};
+use tokio::net::TcpStream;
+use unftp_sbe_fs::ServerExt;
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    pretty_env_logger::init();
+
+    let args: Vec<String> = env::args().collect();
+    let root = std::env::temp_dir();
+    match args.get(1) {
+        None => {
+            let addr = "127.0.0.1:2121";
+            let server = Server::with_fs(root).connection_helper(args[0].clone().into(), vec![]).build().unwrap();
+            println!("Starting ftp server on {}", addr);
+            server.listen(addr).await.unwrap();
+        }
+        Some(fd) => {
+            let fd: RawFd = fd.parse().expect("the argument must be a file descriptor");
+            // Safe because the server passed us the descriptor of a socket that it no longer uses.
+            let std_stream = unsafe { std::net::TcpStream::from_raw_fd(fd) };
+            std_stream.set_nonblocking(true).unwrap();
+            let control_sock = TcpStream::from_std(std_stream).unwrap();
+            println!("Serving connection from {:?} in process {}", control_sock.peer_addr(), std::process::id());
+            let server = Server::with_fs(root).build().unwrap();
+            server.service(control_sock).await.unwrap();
+        }
+    }
+}
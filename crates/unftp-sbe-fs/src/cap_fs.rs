@@ -2,13 +2,13 @@
 // Most of these functions are copied almost verbatim from tokio::fs, but with the std parts
 // replaced by cap_std.
 
-use std::{io, path::Path, sync::Arc};
+use std::{io, path::Path, sync::Arc, time::SystemTime};
 
 use tokio::{sync::mpsc, task::spawn_blocking};
 use tokio_stream::wrappers::ReceiverStream;
 
 /// Exact copy of tokio::fs::asyncify
-async fn asyncify<F, T>(f: F) -> io::Result<T>
+pub async fn asyncify<F, T>(f: F) -> io::Result<T>
 where
     F: FnOnce() -> io::Result<T> + Send + 'static,
     T: Send + 'static,
@@ -71,6 +71,15 @@ pub async fn remove_dir(root: Arc<cap_std::fs::Dir>, path: impl AsRef<Path>) ->
     asyncify(move || root.remove_dir(path)).await
 }
 
+/// Removes a directory with everything in it. Symlinks are removed, not followed.
+///
+/// This is a capability-based, async version of
+/// [`std::fs::remove_dir_all`](std::fs::remove_dir_all)
+pub async fn remove_dir_all(root: Arc<cap_std::fs::Dir>, path: impl AsRef<Path>) -> io::Result<()> {
+    let path = path.as_ref().to_owned();
+    asyncify(move || root.remove_dir_all(path)).await
+}
+
 /// Removes a file from the filesystem.
 ///
 /// Note that there is no guarantee that the file is immediately deleted (e.g.
@@ -85,6 +94,45 @@ pub async fn remove_file(root: Arc<cap_std::fs::Dir>, path: impl AsRef<Path>) ->
     asyncify(move || root.remove_file(path)).await
 }
 
+/// Copies the contents of one file to another, replacing the destination if it exists. Returns
+/// the number of bytes copied.
+///
+/// This is a capabilities-based async version of
+/// [`std::fs::copy`](std::fs::copy)
+pub async fn copy(root: Arc<cap_std::fs::Dir>, from: impl AsRef<Path>, to: impl AsRef<Path>) -> io::Result<u64> {
+    let from = from.as_ref().to_owned();
+    let to = to.as_ref().to_owned();
+
+    asyncify(move || root.copy(from, &root, to)).await
+}
+
+/// Changes the owner and/or group of a file or directory, following symlinks within the root.
+#[cfg(unix)]
+pub async fn chown(root: Arc<cap_std::fs::Dir>, path: impl AsRef<Path>, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+    let path = path.as_ref().to_owned();
+
+    asyncify(move || std::os::unix::fs::fchown(root.open(path)?, uid, gid)).await
+}
+
+/// Sets the modification time of a file, following symlinks within the root.
+pub async fn set_modified(root: Arc<cap_std::fs::Dir>, path: impl AsRef<Path>, modified: SystemTime) -> io::Result<()> {
+    let path = path.as_ref().to_owned();
+
+    asyncify(move || root.open(path)?.into_std().set_modified(modified)).await
+}
+
+/// Creates a new symbolic link named `link` that points to `target`.
+///
+/// This is a capabilities-based async version of
+/// [`std::os::unix::fs::symlink`](std::os::unix::fs::symlink)
+#[cfg(unix)]
+pub async fn symlink(root: Arc<cap_std::fs::Dir>, target: impl AsRef<Path>, link: impl AsRef<Path>) -> io::Result<()> {
+    let target = target.as_ref().to_owned();
+    let link = link.as_ref().to_owned();
+
+    asyncify(move || root.symlink(target, link)).await
+}
+
 /// Renames a file or directory to a new name, replacing the original file if
 /// `to` already exists.
 ///
@@ -99,6 +147,12 @@ pub async fn rename(root: Arc<cap_std::fs::Dir>, from: impl AsRef<Path>, to: imp
     asyncify(move || root.rename(from, &root, to)).await
 }
 
+/// Queries the file system metadata for a path, following symlinks within the root.
+pub async fn metadata<P: AsRef<Path>>(root: Arc<cap_std::fs::Dir>, path: P) -> io::Result<cap_std::fs::Metadata> {
+    let path = path.as_ref().to_owned();
+    asyncify(move || root.metadata(path)).await
+}
+
 /// Queries the file system metadata for a path.
 pub async fn symlink_metadata<P: AsRef<Path>>(root: Arc<cap_std::fs::Dir>, path: P) -> io::Result<cap_std::fs::Metadata> {
     let path = path.as_ref().to_owned();
@@ -0,0 +1,127 @@
+//! Computing and caching the MD5 checksums that `SITE MD5` asks for.
+
+use md5::{Digest, Md5};
+use std::{
+    io::{self, Read},
+    path::PathBuf,
+    time::SystemTime,
+};
+
+// The size of the buffer that files are read with while hashing.
+const BUFFER_SIZE: usize = 1024 * 1024;
+
+// The extended attribute that checksums are stored in.
+#[cfg(target_os = "linux")]
+const XATTR_NAME: &str = "user.unftp.md5";
+
+// A checksum is only valid for the version of the file with this size and modification time.
+type Key = (PathBuf, u64, SystemTime);
+
+/// A bounded, in-memory cache of the MD5 checksums computed for `SITE MD5`, so that repeated
+/// checks of a large file don't read it every time. Checksums are kept per path, size and
+/// modification time: a file that changes gets a fresh checksum.
+///
+/// Clones share their entries. Since a [`Filesystem`](crate::Filesystem) is created for every
+/// session, create the cache once and hand each of them a clone:
+///
+/// ```no_run
+/// use unftp_sbe_fs::{ChecksumCache, Filesystem};
+///
+/// let cache = ChecksumCache::new(10_000);
+/// let server = libunftp::ServerBuilder::new(Box::new(move || Filesystem::new("/srv/ftp").checksum_cache(cache.clone())))
+///     .sitemd5(libunftp::options::SiteMd5::All)
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct ChecksumCache {
+    entries: moka::sync::Cache<Key, String>,
+}
+
+impl ChecksumCache {
+    /// Creates a cache that keeps the checksums of at most the given number of files, evicting the
+    /// least used ones first.
+    pub fn new(max_files: u64) -> Self {
+        ChecksumCache {
+            entries: moka::sync::CacheBuilder::new(max_files).build(),
+        }
+    }
+}
+
+// Returns the MD5 checksum of the file, which is known by the given path. Takes it from the cache
+// or from the extended attribute of the file if the file didn't change since it was computed, and
+// stores it in both otherwise.
+pub(crate) fn md5(mut file: std::fs::File, path: PathBuf, cache: Option<&ChecksumCache>, xattrs: bool) -> io::Result<String> {
+    let (size, modified) = version(&file)?;
+    let key = (path, size, modified);
+    if let Some(md5) = cache.and_then(|cache| cache.entries.get(&key)) {
+        return Ok(md5);
+    }
+    if let Some(md5) = xattrs.then(|| read_xattr(&file, size, modified)).flatten() {
+        if let Some(cache) = cache {
+            cache.entries.insert(key, md5.clone());
+        }
+        return Ok(md5);
+    }
+
+    let mut md5sum = Md5::new();
+    let mut buffer = vec![0_u8; BUFFER_SIZE];
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        md5sum.update(&buffer[..n]);
+    }
+    let md5 = format!("{:x}", md5sum.finalize());
+
+    // A file that was written to while it was read may not match the checksum.
+    if version(&file)? == (size, modified) {
+        if xattrs {
+            // File systems without user attributes just don't keep the checksum.
+            let _ = write_xattr(&file, size, modified, &md5);
+        }
+        if let Some(cache) = cache {
+            cache.entries.insert(key, md5.clone());
+        }
+    }
+    Ok(md5)
+}
+
+fn version(file: &std::fs::File) -> io::Result<(u64, SystemTime)> {
+    let metadata = file.metadata()?;
+    Ok((metadata.len(), metadata.modified()?))
+}
+
+// The attribute holds the size and modification time of the file next to the checksum, for
+// instance `11 1700000000.123456789 5eb63bbbe01eeed093cb22bb8f5acdc3`.
+#[cfg(target_os = "linux")]
+fn xattr_value(size: u64, modified: SystemTime, md5: &str) -> String {
+    let since_epoch = modified.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    format!("{} {}.{:09} {}", size, since_epoch.as_secs(), since_epoch.subsec_nanos(), md5)
+}
+
+#[cfg(target_os = "linux")]
+fn read_xattr(file: &std::fs::File, size: u64, modified: SystemTime) -> Option<String> {
+    let mut buffer = [0_u8; 128];
+    let len = rustix::fs::fgetxattr(file, XATTR_NAME, &mut buffer[..]).ok()?;
+    let value = std::str::from_utf8(&buffer[..len]).ok()?;
+    let (_, md5) = value.rsplit_once(' ')?;
+    (value == xattr_value(size, modified, md5)).then(|| md5.to_string())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn read_xattr(_file: &std::fs::File, _size: u64, _modified: SystemTime) -> Option<String> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn write_xattr(file: &std::fs::File, size: u64, modified: SystemTime, md5: &str) -> io::Result<()> {
+    let value = xattr_value(size, modified, md5);
+    Ok(rustix::fs::fsetxattr(file, XATTR_NAME, value.as_bytes(), rustix::fs::XattrFlags::empty())?)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn write_xattr(_file: &std::fs::File, _size: u64, _modified: SystemTime, _md5: &str) -> io::Result<()> {
+    Ok(())
+}
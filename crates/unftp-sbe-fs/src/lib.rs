@@ -24,16 +24,26 @@ pub use ext::ServerExt;
 
 mod cap_fs;
 
+mod checksum;
+pub use checksum::ChecksumCache;
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring;
+
 use async_trait::async_trait;
 use cfg_if::cfg_if;
-use futures::{future::TryFutureExt, stream::TryStreamExt};
+use futures::{
+    future,
+    stream::{self, StreamExt, TryStreamExt},
+};
 use lazy_static::lazy_static;
 use libunftp::auth::UserDetail;
 use libunftp::storage::{Error, ErrorKind, Fileinfo, Metadata, Permissions, Result, StorageBackend};
 use std::{
+    collections::HashMap,
     fmt::Debug,
     io,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
     sync::Arc,
     time::SystemTime,
 };
@@ -42,9 +52,21 @@ use tokio::io::AsyncSeekExt;
 #[cfg(unix)]
 use cap_std::fs::{MetadataExt, PermissionsExt};
 
+// The maximum number of metadata lookups that metadata_many runs at the same time. Each lookup
+// occupies a thread of the blocking thread pool.
+const METADATA_CONCURRENCY: usize = 32;
+
+// The size of the buffers used for reading and writing files, unless set otherwise.
+const DEFAULT_BUFFER_SIZE: usize = 4096;
+
 /// The Filesystem struct is an implementation of the StorageBackend trait that keeps its files
 /// inside a specific root directory on local disk.
 ///
+/// File content is read and written on the blocking thread pool of Tokio. With the `io-uring`
+/// feature it goes through io_uring on Linux instead, on a thread of its own, which saves the
+/// copies and thread switches of the thread pool for large files. When the kernel doesn't allow
+/// io_uring the thread pool is used after all.
+///
 /// [`Filesystem`]: ./trait.Filesystem.html
 #[derive(Debug)]
 pub struct Filesystem {
@@ -53,6 +75,32 @@ pub struct Filesystem {
     // cost of switching a thread.
     root_fd: Arc<cap_std::fs::Dir>,
     root: PathBuf,
+    // The home directory of the user relative to the root, if entered.
+    home: PathBuf,
+    atomic_uploads: bool,
+    read_buffer_size: usize,
+    write_buffer_size: usize,
+    owners: HashMap<String, (u32, u32)>,
+    site_chown: bool,
+    symlinks: Symlinks,
+    checksum_cache: Option<ChecksumCache>,
+    checksum_xattrs: bool,
+}
+
+/// Determines how the [`Filesystem`] back-end treats symbolic links. Links never lead outside of
+/// the root, whatever the policy: following a link that points outside of it fails.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Symlinks {
+    /// Links are resolved transparently: listings and metadata show what they point to, and
+    /// clients can download the files and enter the directories they point to.
+    Follow,
+    /// Links show up as links in listings, with their target. Clients can still download the files
+    /// and enter the directories they point to.
+    #[default]
+    Show,
+    /// Links are left out of listings and every operation on a path that goes through a link fails
+    /// as if it does not exist. Clients can't create links with `SITE SYMLINK` either.
+    Hide,
 }
 
 /// Metadata for the storage back-end
@@ -63,17 +111,51 @@ pub struct Meta {
 }
 
 /// Strip the "/" prefix, if any, from a path.  Suitable for preprocessing the input pathnames
-/// supplied by the FTP client.
+/// supplied by the FTP client. On Windows drive letters and UNC prefixes like `C:` or
+/// `\\server\share` are stripped as well, they can only ever refer to the root of the server.
 fn strip_prefixes(path: &Path) -> &Path {
     lazy_static! {
         static ref DOT: PathBuf = PathBuf::from(".");
-        static ref SLASH: PathBuf = PathBuf::from("/");
     }
-    if path == SLASH.as_path() {
+    let mut components = path.components();
+    while let Some(Component::Prefix(_) | Component::RootDir) = components.clone().next() {
+        components.next();
+    }
+    let relative = components.as_path();
+    if relative.as_os_str().is_empty() {
         DOT.as_path()
     } else {
-        path.strip_prefix("/").unwrap_or(path)
+        relative
+    }
+}
+
+// The permissions shown for a file on systems without Unix modes, where the only thing to go by
+// is the read-only attribute: `rwxr-xr-x` for directories and `rw-r--r--` for files, without the
+// write bits for read-only ones.
+#[cfg(any(not(unix), test))]
+fn attribute_mode(is_dir: bool, readonly: bool) -> u32 {
+    let mode = if is_dir { 0o755 } else { 0o644 };
+    if readonly {
+        mode & !0o222
+    } else {
+        mode
+    }
+}
+
+// Turns the target of a symlink that is absolute from the client's point of view into one that is
+// relative to the directory of the link, since the root of the server is not the root of the
+// system, e.g. `/data/file` for the link `dir/link` becomes `../data/file`.
+#[cfg(unix)]
+fn link_target(target: &Path, link: &Path) -> PathBuf {
+    if !target.has_root() {
+        return target.to_path_buf();
     }
+    let depth = link
+        .parent()
+        .map_or(0, |dir| dir.components().filter(|c| matches!(c, Component::Normal(_))).count());
+    let mut relative: PathBuf = std::iter::repeat("..").take(depth).collect();
+    relative.push(strip_prefixes(target));
+    relative
 }
 
 impl Filesystem {
@@ -84,7 +166,177 @@ impl Filesystem {
         let path = root.into();
         let aa = cap_std::ambient_authority();
         let root_fd = Arc::new(cap_std::fs::Dir::open_ambient_dir(&path, aa).unwrap());
-        Filesystem { root_fd, root: path }
+        Filesystem {
+            root_fd,
+            root: path,
+            home: PathBuf::new(),
+            atomic_uploads: false,
+            read_buffer_size: DEFAULT_BUFFER_SIZE,
+            write_buffer_size: DEFAULT_BUFFER_SIZE,
+            owners: HashMap::new(),
+            site_chown: false,
+            symlinks: Symlinks::default(),
+            checksum_cache: None,
+            checksum_xattrs: false,
+        }
+    }
+
+    /// Makes uploads atomic: files are written under a [temporary name](libunftp::storage::temp_upload_path)
+    /// in the same directory and renamed to their final name once they were stored completely.
+    /// Uploads that resume at an offset are written to the final name directly.
+    pub fn atomic_uploads(mut self, enabled: bool) -> Self {
+        self.atomic_uploads = enabled;
+        self
+    }
+
+    /// Sets the size of the buffer used when reading files for downloads. Larger buffers mean fewer
+    /// system calls for large files. Defaults to 4 KiB. Downloads over unencrypted data connections
+    /// don't use it on Linux, the kernel sends those files directly.
+    pub fn read_buffer_size(mut self, size: usize) -> Self {
+        self.read_buffer_size = size;
+        self
+    }
+
+    /// Sets the size of the buffers used when writing uploads to files. Larger buffers mean fewer
+    /// system calls for large files. Defaults to 4 KiB.
+    pub fn write_buffer_size(mut self, size: usize) -> Self {
+        self.write_buffer_size = size;
+        self
+    }
+
+    /// Makes the files and directories that the given FTP user stores owned by the given system
+    /// user and group instead of by the user the server runs as. Users are matched on their
+    /// `Display` representation, which is the username for the user types that come with libunftp.
+    /// Changing ownership requires running as root or with the `CAP_CHOWN` capability, uploads
+    /// fail otherwise.
+    #[cfg(unix)]
+    pub fn map_owner<S: Into<String>>(mut self, username: S, uid: u32, gid: u32) -> Self {
+        self.owners.insert(username.into(), (uid, gid));
+        self
+    }
+
+    /// Enables the `SITE CHOWN` command, with which users can change the owner and group of files.
+    /// Off by default since it lets FTP users hand files to any system user. Like
+    /// [`map_owner`](Self::map_owner) it requires running as root or with the `CAP_CHOWN` capability.
+    #[cfg(unix)]
+    pub fn site_chown(mut self, enabled: bool) -> Self {
+        self.site_chown = enabled;
+        self
+    }
+
+    /// Sets how symbolic links are treated, see [`Symlinks`]. Defaults to [`Symlinks::Show`].
+    pub fn symlinks(mut self, policy: Symlinks) -> Self {
+        self.symlinks = policy;
+        self
+    }
+
+    /// Keeps the MD5 checksums computed for `SITE MD5` in the given cache, see [`ChecksumCache`].
+    /// Without it every `SITE MD5` reads the whole file.
+    pub fn checksum_cache(mut self, cache: ChecksumCache) -> Self {
+        self.checksum_cache = Some(cache);
+        self
+    }
+
+    /// Stores the MD5 checksums computed for `SITE MD5` in the `user.unftp.md5` extended attribute
+    /// of the files, together with their size and modification time, so that they survive restarts
+    /// of the server. Files that change are hashed again. Off by default. Has no effect on file
+    /// systems that don't support user extended attributes.
+    #[cfg(target_os = "linux")]
+    pub fn checksum_xattrs(mut self, enabled: bool) -> Self {
+        self.checksum_xattrs = enabled;
+        self
+    }
+}
+
+// The number of names put_unique tries before it gives up.
+const MAX_UNIQUE_NAME_ATTEMPTS: usize = 10;
+
+impl Filesystem {
+    async fn write_file<R: tokio::io::AsyncRead + Send + Sync + 'static + Unpin>(&self, bytes: R, path: &Path, start_pos: u64) -> Result<u64> {
+        let mut oo = cap_std::fs::OpenOptions::new();
+        oo.write(true).create(true);
+        let file = cap_fs::open_with(self.root_fd.clone(), path, oo).await?;
+        self.copy_into(file, bytes, start_pos).await
+    }
+
+    async fn copy_into<R: tokio::io::AsyncRead + Send + Sync + 'static + Unpin>(&self, file: cap_std::fs::File, bytes: R, start_pos: u64) -> Result<u64> {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if let Some(ring) = uring::Ring::get() {
+            return Ok(ring.write(file.into_std(), bytes, start_pos, self.write_buffer_size).await?);
+        }
+        let mut file = tokio::fs::File::from_std(file.into_std());
+        file.set_len(start_pos).await?;
+        file.seek(std::io::SeekFrom::Start(start_pos)).await?;
+
+        let mut reader = tokio::io::BufReader::with_capacity(self.write_buffer_size, bytes);
+        let mut writer = tokio::io::BufWriter::with_capacity(self.write_buffer_size, file);
+
+        let bytes_copied = tokio::io::copy(&mut reader, &mut writer).await?;
+        Ok(bytes_copied)
+    }
+
+    // Writes to a temporary file that is renamed to the given path once it was written completely.
+    async fn write_file_atomically<R: tokio::io::AsyncRead + Send + Sync + 'static + Unpin>(&self, bytes: R, path: &Path) -> Result<u64> {
+        let temp_path = libunftp::storage::temp_upload_path(path);
+        let result = match self.write_file(bytes, &temp_path, 0).await {
+            Ok(bytes_copied) => cap_fs::rename(self.root_fd.clone(), &temp_path, path)
+                .await
+                .map(|_| bytes_copied)
+                .map_err(|e| Error::new(ErrorKind::PermanentFileNotAvailable, e)),
+            Err(err) => Err(err),
+        };
+        if result.is_err() {
+            let _ = cap_fs::remove_file(self.root_fd.clone(), &temp_path).await;
+        }
+        result
+    }
+
+    // Hands the given file or directory over to the system user that the FTP user maps to, if any.
+    #[cfg(unix)]
+    async fn assign_owner<User: UserDetail>(&self, user: &User, path: &Path) -> Result<()> {
+        match self.owners.get(&user.to_string()) {
+            Some((uid, gid)) => Ok(cap_fs::chown(self.root_fd.clone(), path, Some(*uid), Some(*gid)).await?),
+            None => Ok(()),
+        }
+    }
+
+    #[cfg(not(unix))]
+    async fn assign_owner<User: UserDetail>(&self, _user: &User, _path: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    // Fails if symlinks are hidden and the path goes through one. Stops at the first component that
+    // doesn't exist, e.g. the name of a file that is about to be uploaded.
+    async fn refuse_symlinks(&self, path: &Path) -> Result<()> {
+        if self.symlinks != Symlinks::Hide {
+            return Ok(());
+        }
+        let mut current = PathBuf::new();
+        for component in path.components() {
+            current.push(component);
+            match cap_fs::symlink_metadata(self.root_fd.clone(), &current).await {
+                Ok(metadata) if metadata.is_symlink() => return Err(Error::from(ErrorKind::PermanentFileNotAvailable)),
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
+        Ok(())
+    }
+
+    // Claims a new name in the given directory by creating the file exclusively, so that no existing
+    // file can be overwritten. Another name is tried if it happens to exist already.
+    async fn create_unique(&self, dir: &Path) -> Result<(String, cap_std::fs::File)> {
+        let mut attempts = 1;
+        loop {
+            let name = libunftp::storage::unique_file_name();
+            let mut oo = cap_std::fs::OpenOptions::new();
+            oo.write(true).create_new(true);
+            match cap_fs::open_with(self.root_fd.clone(), dir.join(&name), oo).await {
+                Ok(file) => return Ok((name, file)),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists && attempts < MAX_UNIQUE_NAME_ATTEMPTS => attempts += 1,
+                Err(err) => return Err(err.into()),
+            }
+        }
     }
 }
 
@@ -99,20 +351,36 @@ impl<User: UserDetail> StorageBackend<User> for Filesystem {
                 Err(_) => return Err(io::Error::new(io::ErrorKind::Other, "Path not a descendant of the previous root")),
             };
             self.root_fd = Arc::new(self.root_fd.open_dir(relpath)?);
+            self.home = relpath.to_path_buf();
         }
         Ok(())
     }
 
     fn supported_features(&self) -> u32 {
-        libunftp::storage::FEATURE_RESTART | libunftp::storage::FEATURE_SITEMD5
+        let mut features = libunftp::storage::FEATURE_RESTART | libunftp::storage::FEATURE_SITEMD5;
+        if self.atomic_uploads {
+            features |= libunftp::storage::FEATURE_ATOMIC_UPLOADS;
+        }
+        if self.site_chown {
+            features |= libunftp::storage::FEATURE_CHOWN;
+        }
+        if cfg!(unix) && self.symlinks != Symlinks::Hide {
+            features |= libunftp::storage::FEATURE_SYMLINK;
+        }
+        features
     }
 
     #[tracing_attributes::instrument]
     async fn metadata<P: AsRef<Path> + Send + Debug>(&self, _user: &User, path: P) -> Result<Self::Metadata> {
         let path = strip_prefixes(path.as_ref());
-        let fs_meta = cap_fs::symlink_metadata(self.root_fd.clone(), &path)
-            .await
-            .map_err(|_| Error::from(ErrorKind::PermanentFileNotAvailable))?;
+        self.refuse_symlinks(path).await?;
+        let fs_meta = cap_fs::symlink_metadata(self.root_fd.clone(), &path).await?;
+        if fs_meta.is_symlink() && self.symlinks == Symlinks::Follow {
+            // Links that are broken or lead outside of the root are still shown as links.
+            if let Ok(fs_meta) = cap_fs::metadata(self.root_fd.clone(), &path).await {
+                return Ok(Meta { inner: fs_meta, target: None });
+            }
+        }
         let target = if fs_meta.is_symlink() {
             match self.root_fd.read_link_contents(path) {
                 Ok(p) => Some(p),
@@ -128,57 +396,90 @@ impl<User: UserDetail> StorageBackend<User> for Filesystem {
         Ok(Meta { inner: fs_meta, target })
     }
 
+    // Looks up the metadata of several files concurrently. The order of the results matches the
+    // order of the paths.
+    #[tracing_attributes::instrument]
+    async fn metadata_many<P>(&self, user: &User, paths: Vec<P>) -> Vec<Result<Self::Metadata>>
+    where
+        P: AsRef<Path> + Send + Debug,
+    {
+        stream::iter(paths)
+            .map(|path| StorageBackend::<User>::metadata(self, user, path))
+            .buffered(METADATA_CONCURRENCY)
+            .collect()
+            .await
+    }
+
     #[allow(clippy::type_complexity)]
     #[tracing_attributes::instrument]
-    async fn list<P>(&self, _user: &User, path: P) -> Result<Vec<Fileinfo<std::path::PathBuf, Self::Metadata>>>
+    async fn list<P>(&self, user: &User, path: P) -> Result<Vec<Fileinfo<std::path::PathBuf, Self::Metadata>>>
     where
         P: AsRef<Path> + Send + Debug,
         <Self as StorageBackend<User>>::Metadata: Metadata,
     {
         let path = strip_prefixes(path.as_ref());
+        self.refuse_symlinks(path).await?;
 
-        let fis: Vec<Fileinfo<std::path::PathBuf, Self::Metadata>> = cap_fs::read_dir(self.root_fd.clone(), path)
-            .and_then(|dirent| {
-                let entry_path: PathBuf = dirent.file_name().into();
-                let fullpath = path.join(entry_path.clone());
-                cap_fs::symlink_metadata(self.root_fd.clone(), fullpath.clone()).map_ok(move |meta| {
-                    let target = if meta.is_symlink() {
-                        match self.root_fd.read_link_contents(&fullpath) {
-                            Ok(p) => Some(p),
-                            Err(_e) => {
-                                // XXX We should really log an error here.  But a logger object is
-                                // not available.
-                                None
-                            }
-                        }
-                    } else {
-                        None
-                    };
-                    let metadata = Meta { inner: meta, target };
-                    Fileinfo { path: entry_path, metadata }
-                })
-            })
-            .try_collect::<Vec<_>>()
+        let hide_symlinks = self.symlinks == Symlinks::Hide;
+        let entry_paths: Vec<PathBuf> = cap_fs::read_dir(self.root_fd.clone(), path)
+            .try_filter(|dirent| future::ready(!(hide_symlinks && dirent.file_type().is_ok_and(|file_type| file_type.is_symlink()))))
+            .map_ok(|dirent| dirent.file_name().into())
+            .try_collect()
             .await?;
-
-        Ok(fis)
+        let metadata = self
+            .metadata_many(user, entry_paths.iter().map(|entry_path| path.join(entry_path)).collect())
+            .await;
+
+        entry_paths
+            .into_iter()
+            .zip(metadata)
+            .map(|(path, metadata)| Ok(Fileinfo { path, metadata: metadata? }))
+            .collect()
     }
 
     //#[tracing_attributes::instrument]
     async fn get<P: AsRef<Path> + Send + Debug>(&self, _user: &User, path: P, start_pos: u64) -> Result<Box<dyn tokio::io::AsyncRead + Send + Sync + Unpin>> {
         let path = strip_prefixes(path.as_ref());
+        self.refuse_symlinks(path).await?;
         let file = cap_fs::open(self.root_fd.clone(), path).await?;
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if let Some(ring) = uring::Ring::get() {
+            let reader = ring.reader(file.into_std(), start_pos, self.read_buffer_size)?;
+            return Ok(Box::new(reader) as Box<dyn tokio::io::AsyncRead + Send + Sync + Unpin>);
+        }
         let mut file = tokio::fs::File::from_std(file.into_std());
         if start_pos > 0 {
             file.seek(std::io::SeekFrom::Start(start_pos)).await?;
         }
 
-        Ok(Box::new(tokio::io::BufReader::with_capacity(4096, file)) as Box<dyn tokio::io::AsyncRead + Send + Sync + Unpin>)
+        Ok(Box::new(tokio::io::BufReader::with_capacity(self.read_buffer_size, file)) as Box<dyn tokio::io::AsyncRead + Send + Sync + Unpin>)
+    }
+
+    // Only regular files qualify, e.g. reading from a FIFO has to go through get.
+    async fn get_file<P: AsRef<Path> + Send + Debug>(&self, _user: &User, path: P) -> Result<Option<std::fs::File>> {
+        let path = strip_prefixes(path.as_ref());
+        self.refuse_symlinks(path).await?;
+        let file = cap_fs::open(self.root_fd.clone(), path).await?;
+        if !file.metadata()?.is_file() {
+            return Ok(None);
+        }
+        Ok(Some(file.into_std()))
+    }
+
+    #[tracing_attributes::instrument]
+    async fn md5<P: AsRef<Path> + Send + Debug>(&self, _user: &User, path: P) -> Result<String> {
+        let path = strip_prefixes(path.as_ref());
+        self.refuse_symlinks(path).await?;
+        let file = cap_fs::open(self.root_fd.clone(), path).await?.into_std();
+        let key = self.root.join(&self.home).join(path);
+        let cache = self.checksum_cache.clone();
+        let xattrs = self.checksum_xattrs;
+        Ok(cap_fs::asyncify(move || checksum::md5(file, key, cache.as_ref(), xattrs)).await?)
     }
 
     async fn put<P: AsRef<Path> + Send, R: tokio::io::AsyncRead + Send + Sync + 'static + Unpin>(
         &self,
-        _user: &User,
+        user: &User,
         bytes: R,
         path: P,
         start_pos: u64,
@@ -186,48 +487,110 @@ impl<User: UserDetail> StorageBackend<User> for Filesystem {
         // TODO: Add permission checks
 
         let path = strip_prefixes(path.as_ref());
-        let mut oo = cap_std::fs::OpenOptions::new();
-        oo.write(true).create(true);
-        let file = cap_fs::open_with(self.root_fd.clone(), path, oo).await?;
-        let mut file = tokio::fs::File::from_std(file.into_std());
-        file.set_len(start_pos).await?;
-        file.seek(std::io::SeekFrom::Start(start_pos)).await?;
+        self.refuse_symlinks(path).await?;
+        let bytes_copied = if !self.atomic_uploads || start_pos > 0 {
+            self.write_file(bytes, path, start_pos).await?
+        } else {
+            self.write_file_atomically(bytes, path).await?
+        };
+        self.assign_owner(user, path).await?;
+        Ok(bytes_copied)
+    }
 
-        let mut reader = tokio::io::BufReader::with_capacity(4096, bytes);
-        let mut writer = tokio::io::BufWriter::with_capacity(4096, file);
+    async fn put_unique<P: AsRef<Path> + Send + Debug, R: tokio::io::AsyncRead + Send + Sync + 'static + Unpin>(
+        &self,
+        user: &User,
+        bytes: R,
+        dir: P,
+    ) -> Result<(String, u64)> {
+        let dir = strip_prefixes(dir.as_ref());
+        self.refuse_symlinks(dir).await?;
+        let (name, file) = self.create_unique(dir).await?;
+        let result = if self.atomic_uploads {
+            // The empty file keeps the name reserved until the temporary file replaces it.
+            drop(file);
+            self.write_file_atomically(bytes, &dir.join(&name)).await
+        } else {
+            self.copy_into(file, bytes, 0).await
+        };
+        match result {
+            Ok(bytes_copied) => {
+                self.assign_owner(user, &dir.join(&name)).await?;
+                Ok((name, bytes_copied))
+            }
+            Err(err) => {
+                let _ = cap_fs::remove_file(self.root_fd.clone(), dir.join(&name)).await;
+                Err(err)
+            }
+        }
+    }
 
-        let bytes_copied = tokio::io::copy(&mut reader, &mut writer).await?;
-        Ok(bytes_copied)
+    #[tracing_attributes::instrument]
+    async fn abort_put<P: AsRef<Path> + Send + Debug>(&self, _user: &User, path: P) -> Result<()> {
+        if !self.atomic_uploads {
+            return Ok(());
+        }
+        // Uploads that resumed at an offset didn't use the temporary file.
+        let temp_path = libunftp::storage::temp_upload_path(strip_prefixes(path.as_ref()));
+        match cap_fs::remove_file(self.root_fd.clone(), temp_path).await {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
     }
 
     #[tracing_attributes::instrument]
     async fn del<P: AsRef<Path> + Send + Debug>(&self, _user: &User, path: P) -> Result<()> {
         let path = strip_prefixes(path.as_ref());
+        self.refuse_symlinks(path).await?;
         cap_fs::remove_file(self.root_fd.clone(), path)
             .await
             .map_err(|error: std::io::Error| error.into())
     }
 
+    // The root must still be there, it may be on a file system that got unmounted.
+    #[tracing_attributes::instrument]
+    async fn check_health(&self) -> Result<()> {
+        match tokio::fs::metadata(&self.root).await {
+            Ok(meta) if meta.is_dir() => Ok(()),
+            Ok(_) => Err(Error::from(ErrorKind::PermanentDirectoryNotAvailable)),
+            Err(err) => Err(err.into()),
+        }
+    }
+
     #[tracing_attributes::instrument]
     async fn rmd<P: AsRef<Path> + Send + Debug>(&self, _user: &User, path: P) -> Result<()> {
         let path = strip_prefixes(path.as_ref());
+        self.refuse_symlinks(path).await?;
         cap_fs::remove_dir(self.root_fd.clone(), path)
             .await
             .map_err(|error: std::io::Error| error.into())
     }
 
+    // Unlike the default implementation, this never descends into linked directories, also not
+    // when links are followed.
     #[tracing_attributes::instrument]
-    async fn mkd<P: AsRef<Path> + Send + Debug>(&self, _user: &User, path: P) -> Result<()> {
+    async fn rmd_recursive<P: AsRef<Path> + Send + Debug>(&self, _user: &User, path: P) -> Result<()> {
         let path = strip_prefixes(path.as_ref());
-        cap_fs::create_dir(self.root_fd.clone(), path)
+        self.refuse_symlinks(path).await?;
+        cap_fs::remove_dir_all(self.root_fd.clone(), path)
             .await
             .map_err(|error: std::io::Error| error.into())
     }
 
+    #[tracing_attributes::instrument]
+    async fn mkd<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        let path = strip_prefixes(path.as_ref());
+        self.refuse_symlinks(path).await?;
+        cap_fs::create_dir(self.root_fd.clone(), path).await?;
+        self.assign_owner(user, path).await
+    }
+
     #[tracing_attributes::instrument]
     async fn rename<P: AsRef<Path> + Send + Debug>(&self, _user: &User, from: P, to: P) -> Result<()> {
-        let from = from.as_ref().strip_prefix("/").unwrap_or(from.as_ref());
-        let to = to.as_ref().strip_prefix("/").unwrap_or(to.as_ref());
+        let from = strip_prefixes(from.as_ref());
+        let to = strip_prefixes(to.as_ref());
+        self.refuse_symlinks(from).await?;
+        self.refuse_symlinks(to).await?;
 
         let r = cap_fs::symlink_metadata(self.root_fd.clone(), &from).await;
         match r {
@@ -246,6 +609,47 @@ impl<User: UserDetail> StorageBackend<User> for Filesystem {
         }
     }
 
+    #[tracing_attributes::instrument]
+    async fn copy<P: AsRef<Path> + Send + Debug>(&self, user: &User, from: P, to: P) -> Result<()> {
+        let from = strip_prefixes(from.as_ref());
+        let to = strip_prefixes(to.as_ref());
+        self.refuse_symlinks(from).await?;
+        self.refuse_symlinks(to).await?;
+
+        // cap-std lets the kernel copy the data where possible (copy_file_range).
+        cap_fs::copy(self.root_fd.clone(), from, to).await?;
+        self.assign_owner(user, to).await
+    }
+
+    #[cfg(unix)]
+    #[tracing_attributes::instrument]
+    async fn chown<P: AsRef<Path> + Send + Debug>(&self, _user: &User, path: P, uid: Option<u32>, gid: Option<u32>) -> Result<()> {
+        if !self.site_chown {
+            return Err(Error::from(ErrorKind::CommandNotImplemented));
+        }
+        let path = strip_prefixes(path.as_ref());
+        self.refuse_symlinks(path).await?;
+        Ok(cap_fs::chown(self.root_fd.clone(), path, uid, gid).await?)
+    }
+
+    #[cfg(unix)]
+    #[tracing_attributes::instrument]
+    async fn symlink<P: AsRef<Path> + Send + Debug>(&self, _user: &User, target: P, link: P) -> Result<()> {
+        if self.symlinks == Symlinks::Hide {
+            return Err(Error::from(ErrorKind::CommandNotImplemented));
+        }
+        let link = strip_prefixes(link.as_ref());
+        let target = link_target(target.as_ref(), link);
+        Ok(cap_fs::symlink(self.root_fd.clone(), target, link).await?)
+    }
+
+    #[tracing_attributes::instrument]
+    async fn set_modified<P: AsRef<Path> + Send + Debug>(&self, _user: &User, path: P, modified: SystemTime) -> Result<()> {
+        let path = strip_prefixes(path.as_ref());
+        self.refuse_symlinks(path).await?;
+        Ok(cap_fs::set_modified(self.root_fd.clone(), path, modified).await?)
+    }
+
     #[tracing_attributes::instrument]
     async fn cwd<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
         self.list(user, path).await.map(drop)
@@ -273,6 +677,12 @@ impl Metadata for Meta {
         self.inner.modified().map(cap_std::time::SystemTime::into_std).map_err(|e| e.into())
     }
 
+    // Not every platform and file system keeps the birth time, and Linux only reports it through
+    // statx.
+    fn created(&self) -> Option<SystemTime> {
+        self.inner.created().map(cap_std::time::SystemTime::into_std).ok()
+    }
+
     fn gid(&self) -> u32 {
         cfg_if! {
             if #[cfg(unix)] {
@@ -308,7 +718,7 @@ impl Metadata for Meta {
             if #[cfg(unix)] {
                 Permissions(self.inner.permissions().mode())
             } else {
-                Permissions(0o7755)
+                Permissions(attribute_mode(self.inner.is_dir(), self.inner.permissions().readonly()))
             }
         }
     }
@@ -316,6 +726,32 @@ impl Metadata for Meta {
     fn readlink(&self) -> Option<&Path> {
         self.target.as_deref()
     }
+
+    // The device, inode and modification time identify a version of a local file.
+    fn attributes(&self) -> Vec<(String, String)> {
+        cfg_if! {
+            if #[cfg(unix)] {
+                vec![
+                    ("dev".to_string(), self.inner.dev().to_string()),
+                    ("inode".to_string(), self.inner.ino().to_string()),
+                    ("mtime".to_string(), format!("{}.{:09}", self.inner.mtime(), self.inner.mtime_nsec())),
+                ]
+            } else {
+                vec![]
+            }
+        }
+    }
+
+    // The device and inode don't change when a file is renamed within the same file system.
+    fn unique_id(&self) -> Option<String> {
+        cfg_if! {
+            if #[cfg(unix)] {
+                Some(format!("{:x}g{:x}", self.inner.dev(), self.inner.ino()))
+            } else {
+                None
+            }
+        }
+    }
 }
 
 #[cfg(test)]
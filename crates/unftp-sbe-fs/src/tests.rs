@@ -10,6 +10,50 @@ fn fs_strip_prefixes() {
     assert_eq!(strip_prefixes(Path::new("foo/bar")), Path::new("foo/bar"));
     assert_eq!(strip_prefixes(Path::new("/foo/bar")), Path::new("foo/bar"));
     assert_eq!(strip_prefixes(Path::new("/")), Path::new("."));
+    assert_eq!(strip_prefixes(Path::new("//foo/bar")), Path::new("foo/bar"));
+    assert_eq!(strip_prefixes(Path::new("")), Path::new("."));
+}
+
+#[cfg(windows)]
+#[test]
+fn fs_strip_windows_prefixes() {
+    assert_eq!(strip_prefixes(Path::new(r"C:\foo\bar")), Path::new(r"foo\bar"));
+    assert_eq!(strip_prefixes(Path::new("C:/foo")), Path::new("foo"));
+    assert_eq!(strip_prefixes(Path::new("C:")), Path::new("."));
+    assert_eq!(strip_prefixes(Path::new(r"\\server\share\foo")), Path::new("foo"));
+    assert_eq!(strip_prefixes(Path::new(r"\\?\C:\foo")), Path::new("foo"));
+    assert_eq!(strip_prefixes(Path::new(r"\foo")), Path::new("foo"));
+}
+
+#[test]
+fn fs_attribute_mode() {
+    assert_eq!(format!("{}", Permissions(attribute_mode(false, false))), "rw-r--r--");
+    assert_eq!(format!("{}", Permissions(attribute_mode(false, true))), "r--r--r--");
+    assert_eq!(format!("{}", Permissions(attribute_mode(true, false))), "rwxr-xr-x");
+    assert_eq!(format!("{}", Permissions(attribute_mode(true, true))), "r-xr-xr-x");
+}
+
+// Windows has no modes, the permissions follow from the read-only attribute.
+#[cfg(windows)]
+#[test]
+fn fs_windows_permissions() {
+    let root = tempfile::tempdir().unwrap();
+    let path = root.path().join("readonly.txt");
+    File::create(&path).unwrap();
+    let mut permissions = std::fs::metadata(&path).unwrap().permissions();
+    permissions.set_readonly(true);
+    std::fs::set_permissions(&path, permissions).unwrap();
+    File::create(root.path().join("writable.txt")).unwrap();
+
+    let fs = Filesystem::new(root.path());
+    let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    let readonly = rt.block_on(fs.metadata(&DefaultUser {}, "/readonly.txt")).unwrap();
+    let writable = rt.block_on(fs.metadata(&DefaultUser {}, "/writable.txt")).unwrap();
+    let dir = rt.block_on(fs.metadata(&DefaultUser {}, "/")).unwrap();
+
+    assert_eq!(readonly.permissions().to_string(), "r--r--r--");
+    assert_eq!(writable.permissions().to_string(), "rw-r--r--");
+    assert_eq!(dir.permissions().to_string(), "rwxr-xr-x");
 }
 
 #[test]
@@ -37,6 +81,21 @@ fn fs_stat() {
     assert_eq!(meta.modified().unwrap(), my_meta.modified().unwrap());
 }
 
+#[cfg(unix)]
+#[test]
+fn fs_stat_error_kinds() {
+    let root = tempfile::tempdir().unwrap();
+    File::create(root.path().join("file.txt")).unwrap();
+
+    let fs = Filesystem::new(root.path());
+    let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    let missing = rt.block_on(fs.metadata(&DefaultUser {}, "/missing.txt")).unwrap_err();
+    let below_file = rt.block_on(fs.metadata(&DefaultUser {}, "/file.txt/child")).unwrap_err();
+
+    assert_eq!(missing.kind(), ErrorKind::PermanentFileNotAvailable);
+    assert_eq!(below_file.kind(), ErrorKind::PermanentDirectoryNotAvailable);
+}
+
 #[test]
 fn fs_list() {
     // Create a temp directory and create some files in it
@@ -133,6 +192,66 @@ fn fs_put() {
     assert_eq!(orig_content, written_content.as_slice());
 }
 
+// Small buffers make the transfers take several chunks, also when they go through io_uring.
+#[test]
+fn fs_get_and_put_from_offset() {
+    let root = tempfile::TempDir::new().unwrap().into_path();
+    std::fs::write(root.join("resume.txt"), b"hello world, how are you").unwrap();
+    let fs = Filesystem::new(&root).read_buffer_size(4).write_buffer_size(4);
+
+    let rt = Runtime::new().unwrap();
+    let content = rt.block_on(async {
+        let mut reader = fs.get(&DefaultUser {}, "resume.txt", 6).await.unwrap();
+        let mut content = Vec::new();
+        tokio::io::copy(&mut reader, &mut content).await.unwrap();
+        content
+    });
+    assert_eq!(b"world, how are you".as_ref(), content.as_slice());
+
+    let written = rt.block_on(fs.put(&DefaultUser {}, b"there, bye".as_ref(), "resume.txt", 6)).unwrap();
+    assert_eq!(10, written);
+    assert_eq!(b"hello there, bye".as_ref(), std::fs::read(root.join("resume.txt")).unwrap().as_slice());
+}
+
+#[test]
+fn fs_put_atomic() {
+    let root = tempfile::TempDir::new().unwrap().into_path();
+    let orig_content = b"hallo";
+    let fs = Filesystem::new(&root).atomic_uploads(true);
+
+    let rt = Runtime::new().unwrap();
+
+    rt.block_on(fs.put(&DefaultUser {}, orig_content.as_ref(), "greeting.txt", 0))
+        .expect("Failed to `put` file");
+
+    let mut written_content = Vec::new();
+    let mut f = File::open(root.join("greeting.txt")).unwrap();
+    f.read_to_end(&mut written_content).unwrap();
+
+    assert_eq!(orig_content, written_content.as_slice());
+    assert!(!root.join(".in.greeting.txt.").exists());
+}
+
+#[test]
+fn fs_put_unique() {
+    let root = tempfile::TempDir::new().unwrap().into_path();
+    let orig_content = b"hallo";
+    let fs = Filesystem::new(&root);
+
+    let rt = Runtime::new().unwrap();
+
+    let (name, bytes) = rt
+        .block_on(fs.put_unique(&DefaultUser {}, orig_content.as_ref(), "/"))
+        .expect("Failed to `put_unique` file");
+
+    let mut written_content = Vec::new();
+    let mut f = File::open(root.join(name)).unwrap();
+    f.read_to_end(&mut written_content).unwrap();
+
+    assert_eq!(bytes, orig_content.len() as u64);
+    assert_eq!(orig_content, written_content.as_slice());
+}
+
 #[test]
 fn fileinfo_fmt() {
     struct MockMetadata {}
@@ -192,6 +311,93 @@ fn fs_mkd() {
     assert!(metadata.is_dir());
 }
 
+#[test]
+fn fs_set_modified() {
+    let root = tempfile::TempDir::new().unwrap().into_path();
+    File::create(root.join("file.txt")).unwrap().write_all(b"data").unwrap();
+    let modified = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_709_210_096);
+
+    let rt = Runtime::new().unwrap();
+    let fs = Filesystem::new(&root);
+    rt.block_on(fs.set_modified(&DefaultUser {}, "/file.txt", modified))
+        .expect("Failed to set the modification time");
+
+    assert_eq!(std::fs::metadata(root.join("file.txt")).unwrap().modified().unwrap(), modified);
+    let err = rt.block_on(fs.set_modified(&DefaultUser {}, "/missing.txt", modified)).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::PermanentFileNotAvailable);
+}
+
+#[cfg(unix)]
+#[test]
+fn fs_link_target() {
+    assert_eq!(link_target(Path::new("../data/file"), Path::new("dir/link")), Path::new("../data/file"));
+    assert_eq!(link_target(Path::new("/data/file"), Path::new("dir/link")), Path::new("../data/file"));
+    assert_eq!(link_target(Path::new("/data/file"), Path::new("link")), Path::new("data/file"));
+}
+
+#[cfg(unix)]
+#[test]
+fn fs_symlink() {
+    let root = tempfile::TempDir::new().unwrap().into_path();
+    std::fs::create_dir(root.join("links")).unwrap();
+    File::create(root.join("target.txt")).unwrap().write_all(b"data").unwrap();
+
+    let rt = Runtime::new().unwrap();
+    let fs = Filesystem::new(&root);
+    rt.block_on(fs.symlink(&DefaultUser {}, "/target.txt", "/links/link.txt"))
+        .expect("Failed to create symlink");
+
+    let link = root.join("links/link.txt");
+    assert_eq!(std::fs::read_link(&link).unwrap(), Path::new("../target.txt"));
+    assert_eq!(std::fs::read(&link).unwrap(), b"data");
+}
+
+#[cfg(unix)]
+#[test]
+fn fs_symlink_policies() {
+    let root = tempfile::TempDir::new().unwrap().into_path();
+    std::fs::create_dir(root.join("dir")).unwrap();
+    File::create(root.join("dir/file.txt")).unwrap().write_all(b"data").unwrap();
+    std::os::unix::fs::symlink("dir", root.join("link")).unwrap();
+
+    let rt = Runtime::new().unwrap();
+    let user = DefaultUser {};
+
+    let fs = Filesystem::new(&root);
+    let meta = rt.block_on(fs.metadata(&user, "/link")).unwrap();
+    assert!(meta.is_symlink());
+    assert_eq!(meta.readlink(), Some(Path::new("dir")));
+
+    let fs = Filesystem::new(&root).symlinks(Symlinks::Follow);
+    let meta = rt.block_on(fs.metadata(&user, "/link")).unwrap();
+    assert!(meta.is_dir());
+    assert_eq!(meta.readlink(), None);
+
+    let fs = Filesystem::new(&root).symlinks(Symlinks::Hide);
+    assert!(rt.block_on(fs.metadata(&user, "/link")).is_err());
+    assert!(rt.block_on(fs.get(&user, "/link/file.txt", 0)).is_err());
+    let names: Vec<PathBuf> = rt.block_on(fs.list(&user, "/")).unwrap().into_iter().map(|fi| fi.path).collect();
+    assert_eq!(names, vec![PathBuf::from("dir")]);
+}
+
+#[cfg(unix)]
+#[test]
+fn fs_rmd_recursive_keeps_linked_dirs() {
+    let root = tempfile::TempDir::new().unwrap().into_path();
+    std::fs::create_dir_all(root.join("kept")).unwrap();
+    File::create(root.join("kept/file.txt")).unwrap().write_all(b"data").unwrap();
+    std::fs::create_dir_all(root.join("tree/sub")).unwrap();
+    File::create(root.join("tree/sub/file.txt")).unwrap().write_all(b"data").unwrap();
+    std::os::unix::fs::symlink("../kept", root.join("tree/link")).unwrap();
+
+    let rt = Runtime::new().unwrap();
+    let fs = Filesystem::new(&root).symlinks(Symlinks::Follow);
+    rt.block_on(fs.rmd_recursive(&DefaultUser {}, "/tree")).expect("Failed to remove the tree");
+
+    assert!(!root.join("tree").exists());
+    assert_eq!(std::fs::read(root.join("kept/file.txt")).unwrap(), b"data");
+}
+
 #[test]
 fn fs_rename_file() {
     let root = tempfile::TempDir::new().unwrap().into_path();
@@ -214,6 +420,38 @@ fn fs_rename_file() {
     std::fs::symlink_metadata(old_full_path).expect_err("Old filename should not exists anymore");
 }
 
+#[cfg(unix)]
+#[test]
+fn fs_unique_id_survives_rename() {
+    let root = tempfile::TempDir::new().unwrap().into_path();
+    std::fs::write(root.join("old.txt"), b"data").unwrap();
+    std::fs::write(root.join("other.txt"), b"data").unwrap();
+    let rt = Runtime::new().unwrap();
+    let fs = Filesystem::new(&root);
+    let user = DefaultUser {};
+
+    let before = rt.block_on(fs.metadata(&user, "/old.txt")).unwrap().unique_id();
+    assert!(before.is_some());
+    rt.block_on(fs.rename(&user, "/old.txt", "/new.txt")).unwrap();
+    assert_eq!(rt.block_on(fs.metadata(&user, "/new.txt")).unwrap().unique_id(), before);
+    assert_ne!(rt.block_on(fs.metadata(&user, "/other.txt")).unwrap().unique_id(), before);
+}
+
+#[test]
+fn fs_created() {
+    let root = tempfile::TempDir::new().unwrap().into_path();
+    let before = SystemTime::now() - std::time::Duration::from_secs(1);
+    std::fs::write(root.join("file.txt"), b"data").unwrap();
+    let rt = Runtime::new().unwrap();
+    let fs = Filesystem::new(&root);
+
+    // Only some file systems keep the birth time.
+    let meta = rt.block_on(fs.metadata(&DefaultUser {}, "/file.txt")).unwrap();
+    if let Some(created) = meta.created() {
+        assert!(created >= before && created <= meta.modified().unwrap());
+    }
+}
+
 #[test]
 fn fs_rename_dir() {
     let root = tempfile::TempDir::new().unwrap().into_path();
@@ -257,3 +495,56 @@ fn fs_md5() {
 
     assert_eq!("ced0b2edc3ec36e8d914320cb0268359", my_md5);
 }
+
+#[test]
+fn fs_md5_cache() {
+    let root = tempfile::TempDir::new().unwrap().into_path();
+    let path = root.join("file.txt");
+    std::fs::write(&path, "Some known content.").unwrap();
+    let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+    let rt = Runtime::new().unwrap();
+    let fs = Filesystem::new(&root).checksum_cache(ChecksumCache::new(10));
+    let user = DefaultUser {};
+
+    assert_eq!(rt.block_on(fs.md5(&user, "/file.txt")).unwrap(), "ced0b2edc3ec36e8d914320cb0268359");
+
+    // The checksum is only looked up by path, size and modification time.
+    std::fs::write(&path, "Some other content!").unwrap();
+    File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+    assert_eq!(rt.block_on(fs.md5(&user, "/file.txt")).unwrap(), "ced0b2edc3ec36e8d914320cb0268359");
+
+    File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(modified + std::time::Duration::from_secs(1))
+        .unwrap();
+    assert_eq!(rt.block_on(fs.md5(&user, "/file.txt")).unwrap(), "34388e6f9ed717e9949a613774b6fdf2");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn fs_md5_xattrs() {
+    let root = tempfile::TempDir::new().unwrap().into_path();
+    let path = root.join("file.txt");
+    std::fs::write(&path, "Some known content.").unwrap();
+    let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+    let rt = Runtime::new().unwrap();
+    let user = DefaultUser {};
+
+    let fs = Filesystem::new(&root).checksum_xattrs(true);
+    assert_eq!(rt.block_on(fs.md5(&user, "/file.txt")).unwrap(), "ced0b2edc3ec36e8d914320cb0268359");
+    let mut value = [0_u8; 128];
+    if rustix::fs::getxattr(&path, "user.unftp.md5", &mut value[..]).is_err() {
+        // The file system of the temporary directory doesn't support user attributes.
+        return;
+    }
+
+    // Another session finds the checksum in the attribute, as long as the file didn't change.
+    std::fs::write(&path, "Some other content!").unwrap();
+    File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+    let fs = Filesystem::new(&root).checksum_xattrs(true);
+    assert_eq!(rt.block_on(fs.md5(&user, "/file.txt")).unwrap(), "ced0b2edc3ec36e8d914320cb0268359");
+    let fs = Filesystem::new(&root);
+    assert_ne!(rt.block_on(fs.md5(&user, "/file.txt")).unwrap(), "ced0b2edc3ec36e8d914320cb0268359");
+}
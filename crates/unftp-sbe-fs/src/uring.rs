@@ -0,0 +1,137 @@
+//! Reads and writes files with io_uring instead of on the blocking thread pool of Tokio.
+// tokio-uring needs a runtime of its own, so the files are handed to a thread that runs one and
+// the data goes back and forth over channels. Everything else, like opening the files through the
+// capabilities of cap_std, stays the same.
+
+use std::{
+    future::Future,
+    io::{self, Cursor},
+    pin::Pin,
+    sync::OnceLock,
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    sync::{mpsc, oneshot},
+};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::io::StreamReader;
+
+// The number of chunks that may be underway between the ring and the caller for a single file.
+const CHUNKS_IN_FLIGHT: usize = 4;
+
+type Job = Box<dyn FnOnce() -> Pin<Box<dyn Future<Output = ()>>> + Send>;
+
+/// The thread that runs the io_uring runtime.
+#[derive(Debug)]
+pub(crate) struct Ring {
+    jobs: mpsc::UnboundedSender<Job>,
+}
+
+impl Ring {
+    /// Returns the ring, starting it the first time. Returns None when the kernel doesn't support
+    /// io_uring or doesn't allow it, in which case the blocking thread pool is used instead.
+    pub(crate) fn get() -> Option<&'static Ring> {
+        static RING: OnceLock<Option<Ring>> = OnceLock::new();
+        RING.get_or_init(Ring::start).as_ref()
+    }
+
+    fn start() -> Option<Ring> {
+        // tokio-uring panics when it can't set up the ring, so find out up front.
+        io_uring::IoUring::new(8).ok()?;
+        let (jobs, mut rx) = mpsc::unbounded_channel::<Job>();
+        std::thread::Builder::new()
+            .name("unftp-sbe-fs-uring".to_string())
+            .spawn(move || {
+                tokio_uring::start(async move {
+                    while let Some(job) = rx.recv().await {
+                        tokio_uring::spawn(job());
+                    }
+                })
+            })
+            .ok()?;
+        Some(Ring { jobs })
+    }
+
+    fn submit(&self, job: Job) -> io::Result<()> {
+        self.jobs
+            .send(job)
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "the io_uring thread stopped"))
+    }
+
+    /// Reads the file from the given position in chunks of the given size.
+    pub(crate) fn reader(&self, file: std::fs::File, start_pos: u64, chunk_size: usize) -> io::Result<impl AsyncRead + Send + Sync + Unpin> {
+        let (tx, rx) = mpsc::channel(CHUNKS_IN_FLIGHT);
+        self.submit(Box::new(move || Box::pin(read_chunks(file, start_pos, chunk_size, tx))))?;
+        Ok(StreamReader::new(ReceiverStream::new(rx)))
+    }
+
+    /// Writes everything from the reader to the file from the given position on, after truncating
+    /// the file to that position. Returns the number of bytes written.
+    pub(crate) async fn write<R: AsyncRead + Unpin>(&self, file: std::fs::File, mut bytes: R, start_pos: u64, chunk_size: usize) -> io::Result<u64> {
+        let (tx, rx) = mpsc::channel(CHUNKS_IN_FLIGHT);
+        let (done_tx, done) = oneshot::channel();
+        self.submit(Box::new(move || {
+            Box::pin(async move {
+                let _ = done_tx.send(write_chunks(file, start_pos, rx).await);
+            })
+        }))?;
+        loop {
+            let mut chunk = Vec::with_capacity(chunk_size);
+            if (&mut bytes).take(chunk_size as u64).read_to_end(&mut chunk).await? == 0 {
+                break;
+            }
+            // The ring stopped writing, its error is in the result.
+            if tx.send(chunk).await.is_err() {
+                break;
+            }
+        }
+        drop(tx);
+        done.await.map_err(|_| io::Error::new(io::ErrorKind::Other, "the io_uring thread stopped"))?
+    }
+}
+
+async fn read_chunks(file: std::fs::File, start_pos: u64, chunk_size: usize, tx: mpsc::Sender<io::Result<Cursor<Vec<u8>>>>) {
+    let file = tokio_uring::fs::File::from_std(file);
+    let mut pos = start_pos;
+    loop {
+        let (result, chunk) = file.read_at(Vec::with_capacity(chunk_size), pos).await;
+        let chunk = match result {
+            Ok(0) => break,
+            Ok(n) => {
+                pos += n as u64;
+                Ok(Cursor::new(chunk))
+            }
+            Err(err) => Err(err),
+        };
+        let failed = chunk.is_err();
+        // The reader was dropped, e.g. because the client aborted the transfer.
+        if tx.send(chunk).await.is_err() || failed {
+            break;
+        }
+    }
+    let _ = file.close().await;
+}
+
+async fn write_chunks(file: std::fs::File, start_pos: u64, mut rx: mpsc::Receiver<Vec<u8>>) -> io::Result<u64> {
+    file.set_len(start_pos)?;
+    let file = tokio_uring::fs::File::from_std(file);
+    let mut pos = start_pos;
+    let result = async {
+        while let Some(mut chunk) = rx.recv().await {
+            while !chunk.is_empty() {
+                let (result, written) = file.write_at(chunk, pos).await;
+                let n = result?;
+                if n == 0 {
+                    return Err(io::Error::from(io::ErrorKind::WriteZero));
+                }
+                pos += n as u64;
+                chunk = written;
+                chunk.drain(..n);
+            }
+        }
+        Ok(pos - start_pos)
+    }
+    .await;
+    file.close().await?;
+    result
+}
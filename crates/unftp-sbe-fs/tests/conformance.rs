@@ -0,0 +1,10 @@
+#![allow(missing_docs)]
+
+use unftp_sbe_fs::ServerExt;
+use unftp_test_util::conformance::Suite;
+
+#[tokio::test]
+async fn filesystem_conforms() {
+    let root = tempfile::TempDir::new().unwrap();
+    Suite::new().run(libunftp::Server::with_fs(root.path().to_path_buf())).await;
+}
@@ -7,7 +7,6 @@ use rstest::{fixture, rstest};
 use std::fmt::Debug;
 use std::path::PathBuf;
 use std::str;
-use std::sync::atomic::{AtomicU16, Ordering};
 use unftp_sbe_fs::{Filesystem, ServerExt};
 
 fn ensure_login_required<T: Debug>(r: Result<T>) {
@@ -24,11 +23,10 @@ fn ensure_ftps_required<T: Debug>(r: Result<T>) {
     }
 }
 
-static TESTPORT: AtomicU16 = AtomicU16::new(1234);
-
 struct Harness {
     root: PathBuf,
     _tempdir: tempfile::TempDir,
+    _server: unftp_test_util::Harness,
     addr: String,
 }
 
@@ -36,19 +34,16 @@ async fn custom_server_harness<S>(s: S) -> Harness
 where
     S: Fn(PathBuf) -> ServerBuilder<Filesystem, DefaultUser>,
 {
-    let port = TESTPORT.fetch_add(1, Ordering::Relaxed);
-    let addr = format!("127.0.0.1:{}", port);
     let tempdir = tempfile::TempDir::new().unwrap();
     let root = tempdir.path().to_path_buf();
+    let server = unftp_test_util::Harness::start(s(root.clone())).await.unwrap();
 
-    let server = s(root.clone()).build().unwrap().listen(addr.clone());
-
-    tokio::spawn(server);
-    while async_ftp::FtpStream::connect(&addr).await.is_err() {
-        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    Harness {
+        root,
+        addr: server.addr().to_string(),
+        _tempdir: tempdir,
+        _server: server,
     }
-
-    Harness { root, addr, _tempdir: tempdir }
 }
 
 #[fixture]
@@ -256,7 +251,7 @@ mod list {
 
         // Create a filename in the ftp root that we will look for in the `LIST` output
         let path = harness.root.join("test.txt");
-        let f = std::fs::OpenOptions::new().read(true).write(true).create(true).mode(0o754).open(path).unwrap();
+        let f = std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).mode(0o754).open(path).unwrap();
         // Because most OSes set the file's gid to its parent directory's, and the parent
         // directory's is often root, deliberately set it to something more interesting.
         fchown(&f, None, Some(nix::unistd::Gid::effective().as_raw())).unwrap();
@@ -406,6 +401,7 @@ mod mdtm {
     }
 
     /// Get the modification time of a symlink
+    #[cfg(unix)]
     #[rstest]
     #[case::relative(harness(), false)]
     #[case::absolute(harness(), true)]
@@ -600,6 +596,82 @@ async fn rename(#[future] harness: Harness) {
     assert!(metadata.is_file());
 }
 
+/// Windows specific behaviour: permissions come from the read-only attribute and paths with drive
+/// letters or backslashes stay inside the root.
+#[cfg(windows)]
+mod windows {
+    use super::*;
+
+    #[rstest]
+    #[awt]
+    #[tokio::test]
+    async fn list_permissions(#[future] harness: Harness) {
+        let readonly = harness.root.join("readonly.txt");
+        std::fs::File::create(&readonly).unwrap();
+        let mut permissions = std::fs::metadata(&readonly).unwrap().permissions();
+        permissions.set_readonly(true);
+        std::fs::set_permissions(&readonly, permissions).unwrap();
+        std::fs::File::create(harness.root.join("writable.txt")).unwrap();
+        std::fs::create_dir(harness.root.join("dir")).unwrap();
+
+        let mut ftp_stream = FtpStream::connect(harness.addr).await.unwrap();
+        ftp_stream.login("hoi", "jij").await.unwrap();
+        let list = ftp_stream.list(None).await.unwrap();
+
+        let line = |name: &str| list.iter().find(|entry| entry.ends_with(name)).cloned().unwrap();
+        assert!(line("readonly.txt").starts_with("-r--r--r--"), "{list:?}");
+        assert!(line("writable.txt").starts_with("-rw-r--r--"), "{list:?}");
+        assert!(line("dir").starts_with("drwxr-xr-x"), "{list:?}");
+    }
+
+    #[rstest]
+    #[awt]
+    #[tokio::test]
+    async fn backslash_paths(#[future] harness: Harness) {
+        std::fs::create_dir(harness.root.join("dir")).unwrap();
+        std::fs::write(harness.root.join("dir").join("file.txt"), b"hello").unwrap();
+
+        let mut ftp_stream = FtpStream::connect(harness.addr).await.unwrap();
+        ftp_stream.login("hoi", "jij").await.unwrap();
+        let remote_data = ftp_stream.simple_retr(r"dir\file.txt").await.unwrap().into_inner();
+        assert_eq!(remote_data, b"hello");
+
+        ftp_stream.cwd(r"\dir").await.unwrap();
+        assert_eq!(ftp_stream.pwd().await.unwrap(), "/dir");
+    }
+
+    #[rstest]
+    #[awt]
+    #[tokio::test]
+    async fn drive_letters_stay_inside_the_root(#[future] harness: Harness) {
+        std::fs::write(harness.root.join("file.txt"), b"inside").unwrap();
+        let system_root = std::env::var("SystemRoot").unwrap_or_else(|_| r"C:\Windows".to_string());
+
+        let mut ftp_stream = FtpStream::connect(harness.addr).await.unwrap();
+        ftp_stream.login("hoi", "jij").await.unwrap();
+
+        // The drive letter refers to the root of the server, not to the drive.
+        let remote_data = ftp_stream.simple_retr("C:/file.txt").await.unwrap().into_inner();
+        assert_eq!(remote_data, b"inside");
+        ftp_stream.cwd(&system_root).await.unwrap_err();
+        ftp_stream.simple_retr(r"\\localhost\C$\Windows\win.ini").await.unwrap_err();
+    }
+
+    #[rstest]
+    #[awt]
+    #[tokio::test]
+    async fn rename_with_drive_letter(#[future] harness: Harness) {
+        std::fs::write(harness.root.join("old.txt"), b"data").unwrap();
+
+        let mut ftp_stream = FtpStream::connect(harness.addr).await.unwrap();
+        ftp_stream.login("hoi", "jij").await.unwrap();
+        ftp_stream.rename("C:/old.txt", r"C:\new.txt").await.unwrap();
+
+        assert!(!harness.root.join("old.txt").exists());
+        assert!(harness.root.join("new.txt").exists());
+    }
+}
+
 // This test hang on the latest Rust version it seems. Disabling till we fix
 // #[tokio::test]
 // async fn size() {
@@ -1,4 +1,4 @@
-use bytes::Buf;
+use bytes::{Buf, Bytes, BytesMut};
 use futures::prelude::*;
 use hyper::{client::HttpConnector, header, Body, Client, Method, Request, Response, Uri};
 use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
@@ -6,31 +6,53 @@ use libunftp::storage::{Error, ErrorKind};
 use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
 use serde::de::DeserializeOwned;
 use std::fmt;
+use std::time::Duration;
 use std::{
+    collections::HashMap,
     fmt::Debug,
     path::{Path, PathBuf},
     sync::Arc,
 };
-use tokio::sync::RwLock;
-use tokio_util::{
-    codec::{BytesCodec, FramedRead},
-    compat::FuturesAsyncReadCompatExt,
-};
+use tokio::{io::AsyncReadExt, sync::RwLock};
+use tokio_util::compat::FuturesAsyncReadCompatExt;
 use yup_oauth2::ServiceAccountAuthenticator;
 
 use crate::{
-    options::AuthMethod,
-    response_body::{Item, ResponseBody},
+    options::{AuthMethod, UploadOptions},
+    response_body::{Item, ResponseBody, RewriteResponse},
+    response_cache::ResponseCache,
     workload_identity,
 };
 
 type HttpClient = Client<HttpsConnector<HttpConnector>>;
 
+// The amount of objects copied or deleted at the same time when renaming a directory.
+const RENAME_BATCH_SIZE: usize = 16;
+
+// GCS requires the chunks of a resumable upload, except the last one, to be a multiple of this.
+const UPLOAD_CHUNK_GRANULARITY: usize = 256 * 1024;
+
+const DEFAULT_UPLOAD_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+// How many times sending a chunk of an upload is retried after a transient failure.
+const MAX_CHUNK_RETRIES: u32 = 5;
+
+// The outcome of sending a chunk of a resumable upload, or of asking GCS how far it got.
+enum ChunkOutcome {
+    // The upload is complete and the object exists.
+    Complete(Item),
+    // GCS has stored this many bytes of the upload so far.
+    Persisted(u64),
+}
+
 #[derive(Clone, Debug)]
 pub(crate) struct GcsClient {
     base_url: String,
     bucket_name: String,
     root: PathBuf,
+    upload_chunk_size: usize,
+    user_project: Option<String>,
+    cache: Option<ResponseCache>,
 
     http: HttpClient,
 
@@ -68,11 +90,32 @@ impl GcsClient {
             base_url,
             bucket_name,
             root,
+            upload_chunk_size: DEFAULT_UPLOAD_CHUNK_SIZE,
+            user_project: None,
+            cache: None,
             http,
             tokens: token_manager,
         }
     }
 
+    // Sets the chunk size of uploads, rounded up to what GCS accepts.
+    pub fn with_upload_chunk_size(mut self, size: usize) -> Self {
+        self.upload_chunk_size = size.max(1).div_ceil(UPLOAD_CHUNK_GRANULARITY) * UPLOAD_CHUNK_GRANULARITY;
+        self
+    }
+
+    // Caches the responses to GET requests for metadata and listings for the given time.
+    pub fn with_response_cache(mut self, ttl: Duration) -> Self {
+        self.cache = Some(ResponseCache::new(ttl));
+        self
+    }
+
+    // Sets the project that is billed for the requests, needed for requester pays buckets.
+    pub fn with_user_project(mut self, project: String) -> Self {
+        self.user_project = Some(project);
+        self
+    }
+
     pub async fn item<P: AsRef<Path>>(&self, path: P) -> Result<Item, Error> {
         let uri = make_uri(format!(
             "{}/storage/v1/b/{}/o/{}",
@@ -84,6 +127,59 @@ impl GcsClient {
         self.http_get(uri).await
     }
 
+    // Looks up the objects with the given paths, returning the results in the same order. Objects
+    // that share a parent directory are found with a single (paginated) list request on that
+    // directory instead of a request per object.
+    pub async fn items<P: AsRef<Path>>(&self, paths: &[P]) -> Vec<Result<Item, Error>> {
+        let mut by_parent: HashMap<PathBuf, Vec<usize>> = HashMap::new();
+        for (idx, path) in paths.iter().enumerate() {
+            let parent = path.as_ref().parent().map(Path::to_path_buf).unwrap_or_default();
+            by_parent.entry(parent).or_default().push(idx);
+        }
+
+        let mut results: Vec<Option<Result<Item, Error>>> = paths.iter().map(|_| None).collect();
+        for (parent, indices) in by_parent {
+            if indices.len() == 1 {
+                results[indices[0]] = Some(self.item(&paths[indices[0]]).await);
+                continue;
+            }
+            match self.list_items(&parent).await {
+                Ok(found) => {
+                    for idx in indices {
+                        let name = self.real_path(&paths[idx]);
+                        let item = name
+                            .to_str()
+                            .and_then(|name| found.get(name).or_else(|| found.get(&format!("{}/", name.trim_end_matches('/')))).cloned());
+                        results[idx] = Some(item.ok_or_else(|| Error::from(ErrorKind::PermanentFileNotAvailable)));
+                    }
+                }
+                Err(err) => {
+                    for idx in indices {
+                        results[idx] = Some(Err(Error::from(err.kind())));
+                    }
+                }
+            }
+        }
+        results
+            .into_iter()
+            .map(|result| result.unwrap_or_else(|| Err(Error::from(ErrorKind::LocalError))))
+            .collect()
+    }
+
+    // Returns all objects directly within the given directory, keyed by object name.
+    async fn list_items<P: AsRef<Path>>(&self, path: P) -> Result<HashMap<String, Item>, Error> {
+        let mut found = HashMap::new();
+        let mut next_token = None;
+        loop {
+            let resp = self.list(&path, next_token).await?;
+            next_token = resp.next_token();
+            found.extend(resp.into_items().into_iter().map(|item| (item.name().to_string(), item)));
+            if next_token.is_none() {
+                return Ok(found);
+            }
+        }
+    }
+
     pub async fn list<P: AsRef<Path>>(&self, path: P, next_page_token: Option<String>) -> Result<ResponseBody, Error> {
         // includeTrailingDelimiter makes our prefix ('subdirs') end up in the items[] as objects
         // We need this to get access to the 'updated' field
@@ -92,7 +188,7 @@ impl GcsClient {
             "{}/storage/v1/b/{}/o?prettyPrint=false&fields={}&delimiter=/&includeTrailingDelimiter=true",
             self.base_url,
             self.bucket_name,
-            "kind,prefixes,items(id,name,size,updated),nextPageToken", // limit the fields
+            "kind,prefixes,items(id,name,size,updated,timeCreated),nextPageToken", // limit the fields
         );
 
         if let Some(token) = next_page_token {
@@ -130,33 +226,259 @@ impl GcsClient {
         Ok(Box::new(reader))
     }
 
-    pub async fn upload<P: AsRef<Path>, R>(&self, path: P, src: R) -> Result<Item, Error>
+    pub async fn upload<P: AsRef<Path>, R>(&self, path: P, src: R, options: &UploadOptions) -> Result<Item, Error>
+    where
+        R: tokio::io::AsyncRead + Send + Sync + Unpin + 'static,
+    {
+        self.upload_object(path, src, options, "").await
+    }
+
+    // Like upload but fails instead of replacing an object that already exists.
+    pub async fn upload_new<P: AsRef<Path>, R>(&self, path: P, src: R, options: &UploadOptions) -> Result<Item, Error>
     where
         R: tokio::io::AsyncRead + Send + Sync + Unpin + 'static,
     {
+        self.upload_object(path, src, options, "&ifGenerationMatch=0").await
+    }
+
+    // Uploads with the resumable upload protocol: the data is sent in chunks of the configured size,
+    // and a chunk that fails transiently is sent again from where GCS says it left off instead of
+    // restarting the whole upload.
+    // See https://cloud.google.com/storage/docs/performing-resumable-uploads
+    async fn upload_object<P: AsRef<Path>, R>(&self, path: P, mut src: R, options: &UploadOptions, preconditions: &str) -> Result<Item, Error>
+    where
+        R: tokio::io::AsyncRead + Send + Sync + Unpin + 'static,
+    {
+        let session_uri = self.start_resumable_upload(path, options, preconditions).await?;
+        let mut offset: u64 = 0;
+        loop {
+            let chunk = read_chunk(&mut src, self.upload_chunk_size).await?;
+            // Only a chunk that isn't full is known to be the last one. When the data ends exactly at
+            // a chunk boundary, the next round sends an empty chunk to finish the upload.
+            let total = if chunk.len() < self.upload_chunk_size {
+                Some(offset + chunk.len() as u64)
+            } else {
+                None
+            };
+            let chunk_len = chunk.len() as u64;
+            if let Some(item) = self.upload_chunk(&session_uri, chunk, offset, total).await? {
+                return Ok(item);
+            }
+            offset += chunk_len;
+        }
+    }
+
+    // Starts a resumable upload and returns the session URI to send the data to. The object's
+    // metadata is sent along in the body of this request.
+    async fn start_resumable_upload<P: AsRef<Path>>(&self, path: P, options: &UploadOptions, preconditions: &str) -> Result<String, Error> {
+        let kms_param = match options.kms_key() {
+            Some(key) => format!("&kmsKeyName={}", utf8_percent_encode(key, NON_ALPHANUMERIC)),
+            None => String::new(),
+        };
         let uri = make_uri(format!(
-            "{}/upload/storage/v1/b/{}/o?uploadType=media&name={}",
+            "{}/upload/storage/v1/b/{}/o?uploadType=resumable&name={}{}{}",
             self.base_url,
             self.bucket_name,
-            self.path_str(path, TrailingSlash::Trim)?,
+            self.path_str(&path, TrailingSlash::Trim)?,
+            kms_param,
+            preconditions,
         ))?;
 
-        let reader = tokio::io::BufReader::with_capacity(4096, src);
-        let body = Body::wrap_stream(FramedRead::new(reader, BytesCodec::new()).map_ok(|b| b.freeze()));
-        let item = self
-            .http_post(uri, body, &[(header::CONTENT_TYPE.as_str(), mime::APPLICATION_OCTET_STREAM.as_ref())])
+        let resource = options.object_resource(path.as_ref()).to_string();
+        let response = self
+            .http_post_raw(uri, Body::from(resource), &[(header::CONTENT_TYPE.as_str(), "application/json; charset=UTF-8")])
+            .await?;
+
+        response
+            .headers()
+            .get(header::LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .map(String::from)
+            .ok_or_else(|| Error::from(ErrorKind::LocalError))
+    }
+
+    // Sends a chunk that starts at the given offset of the upload, retrying it when it fails
+    // transiently. Returns the object once the upload completed, which happens with the last chunk.
+    async fn upload_chunk(&self, session_uri: &str, chunk: Bytes, offset: u64, total: Option<u64>) -> Result<Option<Item>, Error> {
+        let chunk_end = offset + chunk.len() as u64;
+        let mut sent: usize = 0;
+        let mut retries: u32 = 0;
+        let mut resync = false;
+        loop {
+            let outcome = if resync {
+                // After a failure it is unknown how much of the chunk arrived, so ask GCS.
+                self.upload_status(session_uri, total).await
+            } else {
+                self.send_chunk(session_uri, chunk.slice(sent..), offset + sent as u64, total).await
+            };
+            match outcome {
+                Ok(ChunkOutcome::Complete(item)) => return Ok(Some(item)),
+                Ok(ChunkOutcome::Persisted(persisted)) if persisted >= chunk_end && total.is_none() => return Ok(None),
+                Ok(ChunkOutcome::Persisted(persisted)) => {
+                    let progressed = persisted > offset + sent as u64;
+                    sent = persisted.saturating_sub(offset).min(chunk.len() as u64) as usize;
+                    if !resync && !progressed {
+                        retries += 1;
+                        if retries > MAX_CHUNK_RETRIES {
+                            return Err(Error::from(ErrorKind::TransientFileNotAvailable));
+                        }
+                    }
+                    resync = false;
+                }
+                Err(err) if err.kind() == ErrorKind::TransientFileNotAvailable && retries < MAX_CHUNK_RETRIES => {
+                    retries += 1;
+                    tokio::time::sleep(retry_delay(retries)).await;
+                    resync = true;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    async fn send_chunk(&self, session_uri: &str, data: Bytes, start: u64, total: Option<u64>) -> Result<ChunkOutcome, Error> {
+        let content_range = content_range(start, data.len() as u64, total);
+        let response = self
+            .http_request(
+                Method::PUT,
+                make_uri(session_uri.to_string())?,
+                Body::from(data),
+                &[(header::CONTENT_RANGE.as_str(), &content_range)],
+            )
             .await?;
+        chunk_outcome(response).await
+    }
 
-        Ok(item)
+    async fn upload_status(&self, session_uri: &str, total: Option<u64>) -> Result<ChunkOutcome, Error> {
+        let content_range = match total {
+            Some(total) => format!("bytes */{}", total),
+            None => String::from("bytes */*"),
+        };
+        let response = self
+            .http_request(
+                Method::PUT,
+                make_uri(session_uri.to_string())?,
+                Body::empty(),
+                &[(header::CONTENT_RANGE.as_str(), &content_range), (header::CONTENT_LENGTH.as_str(), "0")],
+            )
+            .await?;
+        chunk_outcome(response).await
+    }
+
+    // Copies an object within the bucket with the rewrite API, so that the data doesn't leave GCS.
+    // The copy is encrypted with the KMS key of the options, if any.
+    pub async fn copy<P: AsRef<Path>>(&self, from: P, to: P, options: &UploadOptions) -> Result<(), Error> {
+        self.rewrite(
+            &self.path_str(from, TrailingSlash::Trim)?,
+            &self.path_str(to, TrailingSlash::Trim)?,
+            options.kms_key(),
+        )
+        .await
+    }
+
+    // GCS has no rename, so the object is copied to its new name and deleted afterwards.
+    pub async fn rename<P: AsRef<Path>>(&self, from: P, to: P, options: &UploadOptions) -> Result<(), Error> {
+        self.copy(&from, &to, options).await?;
+        self.delete(from).await
+    }
+
+    // Renames a directory by renaming every object with its prefix, including those in
+    // subdirectories and the directory object itself. All objects are copied before any of the
+    // originals is deleted. If a copy fails the copies made so far are removed again, so the
+    // directory stays as it was.
+    pub async fn rename_dir<P: AsRef<Path>>(&self, from: P, to: P, options: &UploadOptions) -> Result<(), Error> {
+        if Self::path_is_root(&from) || Self::path_is_root(&to) {
+            return Err(Error::from(ErrorKind::FileNameNotAllowedError));
+        }
+        let from_prefix = self.prefix_str(&from)?;
+        let to_prefix = self.prefix_str(&to)?;
+        let names = self.objects_with_prefix(&from_prefix).await?;
+        if names.is_empty() {
+            return Err(Error::from(ErrorKind::PermanentFileNotAvailable));
+        }
+        let renames: Vec<(String, String)> = names
+            .into_iter()
+            .map(|name| {
+                let to = renamed(&name, &from_prefix, &to_prefix);
+                (name, to)
+            })
+            .collect();
+
+        let copies: Vec<Result<String, Error>> = stream::iter(renames.clone())
+            .map(|(from, to): (String, String)| async move { self.rewrite(&object_str(&from), &object_str(&to), options.kms_key()).await.map(|()| to) })
+            .buffer_unordered(RENAME_BATCH_SIZE)
+            .collect()
+            .await;
+        let (copied, failed): (Vec<_>, Vec<_>) = copies.into_iter().partition(Result::is_ok);
+        if let Some(Err(err)) = failed.into_iter().next() {
+            stream::iter(copied.into_iter().flatten())
+                .map(|name: String| self.delete_object(object_str(&name)))
+                .buffer_unordered(RENAME_BATCH_SIZE)
+                .for_each(|_| future::ready(()))
+                .await;
+            return Err(err);
+        }
+
+        stream::iter(renames)
+            .map(|(from, _): (String, String)| self.delete_object(object_str(&from)))
+            .buffer_unordered(RENAME_BATCH_SIZE)
+            .try_collect()
+            .await
+    }
+
+    // Returns the names of all objects that start with the given (unencoded) prefix.
+    async fn objects_with_prefix(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        let mut names = vec![];
+        let mut next_token: Option<String> = None;
+        loop {
+            let mut url_str = format!(
+                "{}/storage/v1/b/{}/o?prettyPrint=false&fields={}&prefix={}",
+                self.base_url,
+                self.bucket_name,
+                "items(id,name,size,updated),nextPageToken",
+                object_str(prefix),
+            );
+            if let Some(token) = next_token {
+                url_str.push_str("&pageToken=");
+                url_str.push_str(&token);
+            }
+            let resp: ResponseBody = self.http_get(make_uri(url_str)?).await?;
+            next_token = resp.next_token();
+            names.extend(resp.into_items().into_iter().map(|item| item.name().to_string()));
+            if next_token.is_none() {
+                return Ok(names);
+            }
+        }
+    }
+
+    async fn rewrite(&self, from: &str, to: &str, kms_key: Option<&str>) -> Result<(), Error> {
+        let mut rewrite_token: Option<String> = None;
+        loop {
+            let mut params = vec![];
+            if let Some(key) = kms_key {
+                params.push(format!("destinationKmsKeyName={}", utf8_percent_encode(key, NON_ALPHANUMERIC)));
+            }
+            if let Some(token) = &rewrite_token {
+                params.push(format!("rewriteToken={}", utf8_percent_encode(token, NON_ALPHANUMERIC)));
+            }
+            let query = if params.is_empty() { String::new() } else { format!("?{}", params.join("&")) };
+            let uri = make_uri(format!(
+                "{}/storage/v1/b/{}/o/{}/rewriteTo/b/{}/o/{}{}",
+                self.base_url, self.bucket_name, from, self.bucket_name, to, query
+            ))?;
+            let response: RewriteResponse = self.http_post(uri, Body::empty(), &[]).await?;
+            if response.done {
+                return Ok(());
+            }
+            rewrite_token = response.rewrite_token;
+        }
     }
 
     pub async fn delete<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
-        let uri = make_uri(format!(
-            "{}/storage/v1/b/{}/o/{}",
-            self.base_url,
-            self.bucket_name,
-            self.path_str(path, TrailingSlash::Trim)?
-        ))?;
+        self.delete_object(self.path_str(path, TrailingSlash::Trim)?).await
+    }
+
+    async fn delete_object(&self, name: String) -> Result<(), Error> {
+        let uri = make_uri(format!("{}/storage/v1/b/{}/o/{}", self.base_url, self.bucket_name, name))?;
 
         self.http_delete_raw(uri).await?;
 
@@ -198,6 +520,22 @@ impl GcsClient {
         Ok(())
     }
 
+    // Deletes the directory object and all objects under its prefix, in batches like rename_dir.
+    pub async fn rmd_recursive<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        if Self::path_is_root(&path) {
+            return Err(Error::from(ErrorKind::FileNameNotAllowedError));
+        }
+        let names = self.objects_with_prefix(&self.prefix_str(&path)?).await?;
+        if names.is_empty() {
+            return Err(Error::from(ErrorKind::PermanentDirectoryNotAvailable));
+        }
+        stream::iter(names)
+            .map(|name: String| self.delete_object(object_str(&name)))
+            .buffer_unordered(RENAME_BATCH_SIZE)
+            .try_collect()
+            .await
+    }
+
     pub async fn dir_empty<P>(&self, path: P) -> Result<ResponseBody, Error>
     where
         P: AsRef<Path> + Send + Debug,
@@ -261,10 +599,36 @@ impl GcsClient {
         self.encode_path(self.real_path(path), trailing_slash)
     }
 
+    // The unencoded object name prefix shared by everything within the given directory.
+    fn prefix_str<P: AsRef<Path>>(&self, path: P) -> Result<String, Error> {
+        match self.real_path(path).to_str() {
+            Some(path) => Ok(format!("{}/", path.trim_end_matches('/'))),
+            None => Err(Error::from(ErrorKind::PermanentFileNotAvailable)),
+        }
+    }
+
     async fn http_raw<B>(&self, method: Method, uri: Uri, body: B, headers: &[(&str, &str)]) -> Result<Response<Body>, Error>
     where
         B: Into<Body>,
     {
+        let response = self.http_request(method, uri, body, headers).await?;
+
+        if !response.status().is_success() {
+            return Err(http_error(response).await);
+        }
+
+        Ok(response)
+    }
+
+    // Sends a request without looking at the status of the response.
+    async fn http_request<B>(&self, method: Method, uri: Uri, body: B, headers: &[(&str, &str)]) -> Result<Response<Body>, Error>
+    where
+        B: Into<Body>,
+    {
+        let uri = match &self.user_project {
+            Some(project) => with_user_project(uri, project)?,
+            None => uri,
+        };
         let token = self.tokens.token().await?;
         let mut request = Request::builder().uri(uri).header(header::AUTHORIZATION, format!("Bearer {}", token));
 
@@ -272,6 +636,9 @@ impl GcsClient {
             request = request.header(*hk, *hv);
         }
 
+        // Anything but a GET may change the bucket, which makes the cached responses unreliable.
+        let changes = method != Method::GET;
+
         // Return permanent error for now, even though this is likely a bug in unFTP
         let request = request
             .method(method)
@@ -279,37 +646,13 @@ impl GcsClient {
             .map_err(|e| Error::new(ErrorKind::PermanentFileNotAvailable, e))?;
 
         // Return retryable error if there's a connection error to GCS
-        let response = self
-            .http
-            .request(request)
-            .await
-            .map_err(|e| Error::new(ErrorKind::TransientFileNotAvailable, e))?;
+        let response = self.http.request(request).await;
 
-        if !response.status().is_success() {
-            let err_kind = match response.status().as_u16() {
-                404 => ErrorKind::PermanentFileNotAvailable,
-                401 | 403 => ErrorKind::PermissionDenied,
-                429 => ErrorKind::TransientFileNotAvailable,
-                _ => ErrorKind::LocalError,
-            };
-
-            let status = response.status();
-            let body = hyper::body::aggregate(response).await.map_err(|e| Error::new(err_kind, e))?;
-
-            let body_string = String::from_utf8_lossy(body.chunk());
-            let error_message = format!("HTTP error: {} {}", status, body_string);
-
-            // Create the HttpError with additional information
-            let http_error = HttpError {
-                status_code: status.as_u16(),
-                status_text: status.canonical_reason().unwrap_or("Unknown").to_string(),
-                body: error_message,
-            };
-
-            return Err(Error::new(err_kind, http_error));
+        if let (true, Some(cache)) = (changes, &self.cache) {
+            cache.clear();
         }
 
-        Ok(response)
+        response.map_err(|e| Error::new(ErrorKind::TransientFileNotAvailable, e))
     }
 
     async fn http_delete_raw(&self, uri: Uri) -> Result<Response<Body>, Error> {
@@ -337,9 +680,25 @@ impl GcsClient {
     where
         T: DeserializeOwned,
     {
-        let response = self.http_get_raw(uri, &[]).await?;
+        let cache = match &self.cache {
+            Some(cache) => cache,
+            None => return deserialize(self.http_get_raw(uri, &[]).await?).await,
+        };
 
-        deserialize(response).await
+        let key = uri.to_string();
+        let body = match cache.get(&key) {
+            Some(body) => body,
+            None => {
+                let response = self.http_get_raw(uri, &[]).await?;
+                let body = hyper::body::to_bytes(response.into_body())
+                    .await
+                    .map_err(|e| Error::new(ErrorKind::PermanentFileNotAvailable, e))?;
+                cache.insert(key, body.clone());
+                body
+            }
+        };
+
+        serde_json::from_slice(&body).map_err(|e| Error::new(ErrorKind::PermanentFileNotAvailable, e))
     }
 }
 
@@ -349,6 +708,85 @@ enum TrailingSlash {
     AsIs,
 }
 
+// Turns an unsuccessful response into an error.
+async fn http_error(response: Response<Body>) -> Error {
+    let err_kind = match response.status().as_u16() {
+        404 => ErrorKind::PermanentFileNotAvailable,
+        401 | 403 => ErrorKind::PermissionDenied,
+        429 | 500 | 502 | 503 | 504 => ErrorKind::TransientFileNotAvailable,
+        _ => ErrorKind::LocalError,
+    };
+
+    let status = response.status();
+    let body = match hyper::body::aggregate(response).await {
+        Ok(body) => body,
+        Err(e) => return Error::new(err_kind, e),
+    };
+
+    let body_string = String::from_utf8_lossy(body.chunk());
+    let error_message = format!("HTTP error: {} {}", status, body_string);
+
+    // Create the HttpError with additional information
+    let http_error = HttpError {
+        status_code: status.as_u16(),
+        status_text: status.canonical_reason().unwrap_or("Unknown").to_string(),
+        body: error_message,
+    };
+
+    Error::new(err_kind, http_error)
+}
+
+// Interprets the response to a chunk of a resumable upload. GCS replies with 308 as long as the
+// upload is incomplete, telling how much it stored in the Range header.
+async fn chunk_outcome(response: Response<Body>) -> Result<ChunkOutcome, Error> {
+    match response.status().as_u16() {
+        200 | 201 => deserialize(response).await.map(ChunkOutcome::Complete),
+        308 => Ok(ChunkOutcome::Persisted(persisted_bytes(
+            response.headers().get(header::RANGE).and_then(|range| range.to_str().ok()),
+        ))),
+        _ => Err(http_error(response).await),
+    }
+}
+
+// Parses the Range header of a 308 response, e.g. `bytes=0-42`, into the amount of bytes stored. An
+// absent header means nothing was stored yet.
+fn persisted_bytes(range: Option<&str>) -> u64 {
+    range
+        .and_then(|range| range.strip_prefix("bytes=0-"))
+        .and_then(|last| last.parse::<u64>().ok())
+        .map_or(0, |last| last + 1)
+}
+
+fn content_range(start: u64, len: u64, total: Option<u64>) -> String {
+    let total = total.map_or(String::from("*"), |total| total.to_string());
+    if len == 0 {
+        format!("bytes */{}", total)
+    } else {
+        format!("bytes {}-{}/{}", start, start + len - 1, total)
+    }
+}
+
+// Reads until the chunk is full or the data ends.
+async fn read_chunk<R: tokio::io::AsyncRead + Unpin>(src: &mut R, size: usize) -> Result<Bytes, Error> {
+    let mut chunk = BytesMut::with_capacity(size);
+    while chunk.len() < size {
+        let limit = (size - chunk.len()) as u64;
+        let read = (&mut *src)
+            .take(limit)
+            .read_buf(&mut chunk)
+            .await
+            .map_err(|e| Error::new(ErrorKind::TransientFileNotAvailable, e))?;
+        if read == 0 {
+            break;
+        }
+    }
+    Ok(chunk.freeze())
+}
+
+fn retry_delay(retry: u32) -> Duration {
+    Duration::from_millis(500 * 2u64.pow(retry.min(6)))
+}
+
 async fn deserialize<T>(response: Response<Body>) -> Result<T, Error>
 where
     T: DeserializeOwned,
@@ -360,6 +798,21 @@ where
     serde_json::from_reader(body.reader()).map_err(|e| Error::new(ErrorKind::PermanentFileNotAvailable, e))
 }
 
+fn object_str(name: &str) -> String {
+    utf8_percent_encode(name, NON_ALPHANUMERIC).collect()
+}
+
+// Returns the name an object gets when the directory it is in moves from one prefix to another.
+fn renamed(name: &str, from_prefix: &str, to_prefix: &str) -> String {
+    format!("{}{}", to_prefix, name.strip_prefix(from_prefix).unwrap_or(name))
+}
+
+// Adds the userProject parameter that every request on a requester pays bucket needs.
+fn with_user_project(uri: Uri, project: &str) -> Result<Uri, Error> {
+    let separator = if uri.query().is_some() { '&' } else { '?' };
+    make_uri(format!("{}{}userProject={}", uri, separator, utf8_percent_encode(project, NON_ALPHANUMERIC)))
+}
+
 fn make_uri(path_and_query: String) -> Result<Uri, Error> {
     Uri::from_maybe_shared(path_and_query).map_err(|_| Error::from(ErrorKind::FileNameNotAllowedError))
 }
@@ -483,6 +936,45 @@ mod tests {
     use super::*;
     use pretty_assertions::assert_eq;
 
+    #[test]
+    fn upload_ranges() {
+        assert_eq!(content_range(0, 262144, None), "bytes 0-262143/*");
+        assert_eq!(content_range(262144, 10, Some(262154)), "bytes 262144-262153/262154");
+        assert_eq!(content_range(262144, 0, Some(262144)), "bytes */262144");
+        assert_eq!(content_range(0, 0, Some(0)), "bytes */0");
+        assert_eq!(persisted_bytes(Some("bytes=0-262143")), 262144);
+        assert_eq!(persisted_bytes(None), 0);
+    }
+
+    #[tokio::test]
+    async fn read_chunk_fills_up_to_the_size() {
+        let mut src = std::io::Cursor::new(vec![1u8; 10]);
+        assert_eq!(read_chunk(&mut src, 4).await.unwrap().len(), 4);
+        assert_eq!(read_chunk(&mut src, 4).await.unwrap().len(), 4);
+        assert_eq!(read_chunk(&mut src, 4).await.unwrap().len(), 2);
+        assert_eq!(read_chunk(&mut src, 4).await.unwrap().len(), 0);
+    }
+
+    #[test]
+    fn user_project_parameter() {
+        let uri = make_uri(String::from("https://storage.googleapis.com/storage/v1/b/bucket/o/file")).unwrap();
+        assert_eq!(
+            with_user_project(uri, "my-project").unwrap().to_string(),
+            "https://storage.googleapis.com/storage/v1/b/bucket/o/file?userProject=my%2Dproject"
+        );
+        let uri = make_uri(String::from("https://storage.googleapis.com/storage/v1/b/bucket/o?prefix=dir")).unwrap();
+        assert_eq!(
+            with_user_project(uri, "billing").unwrap().to_string(),
+            "https://storage.googleapis.com/storage/v1/b/bucket/o?prefix=dir&userProject=billing"
+        );
+    }
+
+    #[test]
+    fn renamed_keeps_the_path_within_the_directory() {
+        assert_eq!(renamed("root/old/sub/file.txt", "root/old/", "root/new/"), "root/new/sub/file.txt");
+        assert_eq!(renamed("root/old/", "root/old/", "root/new/"), "root/new/");
+    }
+
     /*
     #[test]
     fn list() {
@@ -34,16 +34,15 @@
 //! ```
 //!
 //! This example uses the `ServerExt` extension trait. You can also call one of the other
-//! constructors of `Server` e.g.
+//! constructors of `ServerBuilder` e.g.
 //!
 //! ```no_run
-//! use libunftp::Server;
 //! use unftp_sbe_gcs::{CloudStorage, options::AuthMethod};
 //! use std::path::PathBuf;
 //!
 //! #[tokio::main]
 //! pub async fn main() {
-//!     let server = libunftp::Server::new(
+//!     let server = libunftp::ServerBuilder::new(
 //!         Box::new(move || CloudStorage::with_bucket_root("my-bucket", PathBuf::from("/ftp-root"), AuthMethod::WorkloadIdentity(None)))
 //!       )
 //!       .greeting("Welcome to my FTP server")
@@ -63,6 +62,7 @@ mod gcs_client;
 pub mod object_metadata;
 pub mod options;
 mod response_body;
+mod response_cache;
 mod workload_identity;
 
 pub use ext::ServerExt;
@@ -74,10 +74,12 @@ use libunftp::{
     storage::{Error, ErrorKind, Fileinfo, Metadata, StorageBackend},
 };
 use object_metadata::ObjectMetadata;
-use options::AuthMethod;
+use options::{AuthMethod, UploadOptions};
 use std::{
+    collections::HashMap,
     fmt::Debug,
     path::{Path, PathBuf},
+    time::Duration,
 };
 
 /// A [`StorageBackend`] that uses Cloud storage from Google.
@@ -85,6 +87,8 @@ use std::{
 #[derive(Clone, Debug)]
 pub struct CloudStorage {
     gcs: GcsClient,
+    upload_options: UploadOptions,
+    user_upload_options: HashMap<String, UploadOptions>,
 }
 
 impl CloudStorage {
@@ -120,8 +124,89 @@ impl CloudStorage {
     {
         Self {
             gcs: GcsClient::new(base_url.into(), bucket.into(), root, auth),
+            upload_options: UploadOptions::default(),
+            user_upload_options: HashMap::new(),
         }
     }
+
+    /// Sets the size of the chunks in which files are uploaded to GCS, in bytes. A chunk that fails
+    /// to upload because of a transient error is retried on its own, so a large upload survives a
+    /// flaky connection without starting over. Every upload buffers one chunk in memory. The size is
+    /// rounded up to a multiple of 256 KiB as GCS requires. Defaults to 8 MiB.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use unftp_sbe_gcs::{CloudStorage, options::AuthMethod};
+    ///
+    /// let storage = CloudStorage::new("my-bucket", AuthMethod::WorkloadIdentity(None)).upload_chunk_size(32 * 1024 * 1024);
+    /// ```
+    pub fn upload_chunk_size(mut self, size: usize) -> Self {
+        self.gcs = self.gcs.with_upload_chunk_size(size);
+        self
+    }
+
+    /// Caches the metadata and directory listings retrieved from GCS for the given time. Clients like
+    /// FileZilla look up every entry of a listing, which otherwise causes a request to GCS for each
+    /// of them. The cache is specific to a session and is emptied on every change the session
+    /// makes, so it only hides changes made by others, for at most the given time. Disabled by default.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use unftp_sbe_gcs::{CloudStorage, options::AuthMethod};
+    /// use std::time::Duration;
+    ///
+    /// let storage = CloudStorage::new("my-bucket", AuthMethod::WorkloadIdentity(None)).list_cache(Duration::from_secs(10));
+    /// ```
+    pub fn list_cache(mut self, ttl: Duration) -> Self {
+        self.gcs = self.gcs.with_response_cache(ttl);
+        self
+    }
+
+    /// Bills the requests to GCS to the given project. This is required for buckets that have
+    /// [requester pays](https://cloud.google.com/storage/docs/requester-pays) enabled, requests on
+    /// them fail otherwise.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use unftp_sbe_gcs::{CloudStorage, options::AuthMethod};
+    ///
+    /// let storage = CloudStorage::new("my-bucket", AuthMethod::WorkloadIdentity(None)).user_project("my-billing-project");
+    /// ```
+    pub fn user_project<S: Into<String>>(mut self, project: S) -> Self {
+        self.gcs = self.gcs.with_user_project(project.into());
+        self
+    }
+
+    /// Sets the [`UploadOptions`] for objects uploaded by any user, for instance the KMS key to
+    /// encrypt them with.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use unftp_sbe_gcs::{CloudStorage, options::{AuthMethod, UploadOptions}};
+    ///
+    /// let storage = CloudStorage::new("my-bucket", AuthMethod::WorkloadIdentity(None))
+    ///     .upload_options(UploadOptions::default().detect_content_type(true).cache_control("no-store"));
+    /// ```
+    pub fn upload_options(mut self, options: UploadOptions) -> Self {
+        self.upload_options = options;
+        self
+    }
+
+    /// Sets the [`UploadOptions`] for objects uploaded by the given user, overriding those set with
+    /// [`upload_options`](Self::upload_options). Users are matched on their `Display` representation,
+    /// which is the username for the user types that come with libunftp.
+    pub fn user_upload_options<S: Into<String>>(mut self, username: S, options: UploadOptions) -> Self {
+        self.user_upload_options.insert(username.into(), options);
+        self
+    }
+
+    fn upload_options_for<User: UserDetail>(&self, user: &User) -> &UploadOptions {
+        self.user_upload_options.get(&user.to_string()).unwrap_or(&self.upload_options)
+    }
 }
 
 #[async_trait]
@@ -129,7 +214,8 @@ impl<User: UserDetail> StorageBackend<User> for CloudStorage {
     type Metadata = ObjectMetadata;
 
     fn supported_features(&self) -> u32 {
-        libunftp::storage::FEATURE_SITEMD5
+        // Objects only become visible once their upload completed.
+        libunftp::storage::FEATURE_SITEMD5 | libunftp::storage::FEATURE_ATOMIC_UPLOADS
     }
 
     #[tracing_attributes::instrument]
@@ -140,6 +226,20 @@ impl<User: UserDetail> StorageBackend<User> for CloudStorage {
         self.gcs.item(path).await?.to_metadata()
     }
 
+    #[tracing_attributes::instrument]
+    async fn metadata_many<P>(&self, _user: &User, paths: Vec<P>) -> Vec<Result<Self::Metadata, Error>>
+    where
+        P: AsRef<Path> + Send + Debug,
+    {
+        let paths: Vec<PathBuf> = paths.iter().map(|path| path.as_ref().to_path_buf()).collect();
+        self.gcs
+            .items(&paths)
+            .await
+            .into_iter()
+            .map(|item| item.and_then(|item| item.to_metadata()))
+            .collect()
+    }
+
     async fn md5<P: AsRef<Path> + Send + Debug>(&self, _user: &User, path: P) -> Result<String, Error>
     where
         P: AsRef<Path> + Send + Debug,
@@ -183,16 +283,29 @@ impl<User: UserDetail> StorageBackend<User> for CloudStorage {
         self.gcs.get(path, start_pos).await
     }
 
-    async fn put<P, B>(&self, _user: &User, reader: B, path: P, _start_pos: u64) -> Result<u64, Error>
+    async fn put<P, B>(&self, user: &User, reader: B, path: P, _start_pos: u64) -> Result<u64, Error>
     where
         P: AsRef<Path> + Send + Debug,
         B: tokio::io::AsyncRead + Send + Sync + Unpin + 'static,
     {
-        let item = self.gcs.upload(path, reader).await?;
+        let item = self.gcs.upload(path, reader, self.upload_options_for(user)).await?;
 
         Ok(item.to_metadata()?.len())
     }
 
+    async fn put_unique<P, B>(&self, user: &User, reader: B, dir: P) -> Result<(String, u64), Error>
+    where
+        P: AsRef<Path> + Send + Debug,
+        B: tokio::io::AsyncRead + Send + Sync + Unpin + 'static,
+    {
+        // The precondition makes sure an existing object is never replaced. The data can't be sent
+        // twice so there is no retry with another name, but with random names it won't come to that.
+        let name = libunftp::storage::unique_file_name();
+        let item = self.gcs.upload_new(dir.as_ref().join(&name), reader, self.upload_options_for(user)).await?;
+
+        Ok((name, item.to_metadata()?.len()))
+    }
+
     #[tracing_attributes::instrument]
     async fn del<P>(&self, _user: &User, path: P) -> Result<(), Error>
     where
@@ -207,9 +320,21 @@ impl<User: UserDetail> StorageBackend<User> for CloudStorage {
     }
 
     #[tracing_attributes::instrument]
-    async fn rename<P: AsRef<Path> + Send + Debug>(&self, _user: &User, _from: P, _to: P) -> Result<(), Error> {
-        // TODO: implement this
-        Err(Error::from(ErrorKind::CommandNotImplemented))
+    async fn rename<P: AsRef<Path> + Send + Debug>(&self, user: &User, from: P, to: P) -> Result<(), Error> {
+        let options = self.upload_options_for(user);
+        // A path without an object of its own may still be a directory, i.e. the prefix of other objects.
+        let (from, to): (PathBuf, PathBuf) = (from.as_ref().into(), to.as_ref().into());
+        match self.gcs.item(&from).await {
+            Ok(item) if item.to_metadata()?.is_file() => self.gcs.rename(from, to, options).await,
+            Ok(_) => self.gcs.rename_dir(from, to, options).await,
+            Err(err) if err.kind() == ErrorKind::PermanentFileNotAvailable => self.gcs.rename_dir(from, to, options).await,
+            Err(err) => Err(err),
+        }
+    }
+
+    #[tracing_attributes::instrument]
+    async fn copy<P: AsRef<Path> + Send + Debug>(&self, user: &User, from: P, to: P) -> Result<(), Error> {
+        self.gcs.copy(from, to, self.upload_options_for(user)).await
     }
 
     #[tracing_attributes::instrument]
@@ -229,6 +354,12 @@ impl<User: UserDetail> StorageBackend<User> for CloudStorage {
         self.gcs.rmd(path).await
     }
 
+    // Deletes every object under the prefix, instead of listing and deleting directory by directory.
+    #[tracing_attributes::instrument]
+    async fn rmd_recursive<P: AsRef<Path> + Send + Debug>(&self, _user: &User, path: P) -> Result<(), Error> {
+        self.gcs.rmd_recursive(path).await
+    }
+
     #[tracing_attributes::instrument]
     async fn cwd<P>(&self, _user: &User, path: P) -> Result<(), Error>
     where
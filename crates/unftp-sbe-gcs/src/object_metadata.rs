@@ -8,8 +8,12 @@ use std::time::SystemTime;
 #[derive(Clone, Debug)]
 pub struct ObjectMetadata {
     pub(crate) last_updated: SystemTime,
+    pub(crate) created: Option<SystemTime>,
     pub(crate) is_file: bool,
     pub(crate) size: u64,
+    pub(crate) generation: Option<String>,
+    pub(crate) md5: Option<String>,
+    pub(crate) name_hash: u64,
 }
 
 impl Metadata for ObjectMetadata {
@@ -38,6 +42,11 @@ impl Metadata for ObjectMetadata {
         Ok(self.last_updated)
     }
 
+    /// Returns the time the object was created, as far as it is known.
+    fn created(&self) -> Option<SystemTime> {
+        self.created
+    }
+
     /// Returns the `gid` of the file.
     fn gid(&self) -> u32 {
         //TODO: implement this
@@ -49,4 +58,25 @@ impl Metadata for ObjectMetadata {
         //TODO: implement this
         0
     }
+
+    /// Returns the generation of the object and the MD5 hash of its content, as far as they are
+    /// known.
+    fn attributes(&self) -> Vec<(String, String)> {
+        let generation = self.generation.iter().map(|generation| ("generation".to_string(), generation.clone()));
+        let md5 = self.md5.iter().map(|md5| ("md5".to_string(), md5.clone()));
+        generation.chain(md5).collect()
+    }
+
+    /// Returns the generation of the object together with a hash of its name. The generation alone
+    /// may be shared by objects that were written at the same moment.
+    fn unique_id(&self) -> Option<String> {
+        self.generation.as_ref().map(|generation| format!("{}.{:x}", generation, self.name_hash))
+    }
+}
+
+/// Hashes an object name with FNV-1a, which unlike the hasher of the standard library gives the
+/// same result in every build, so that the unique identifiers of objects stay stable.
+pub(crate) fn name_hash(name: &str) -> u64 {
+    name.bytes()
+        .fold(0xcbf29ce484222325, |hash, byte| (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3))
 }
@@ -1,7 +1,11 @@
 //! Contains code pertaining to initialization options for the [`Cloud Storage Backend`](super::CloudStorage)
 
 use core::fmt;
-use std::path::PathBuf;
+use serde_json::{json, Map, Value};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
 use yup_oauth2::ServiceAccountKey;
 
 /// Used with [`CloudStorage::new`](super::CloudStorage::new()) to specify how the storage back-end
@@ -81,3 +85,97 @@ impl fmt::Debug for AuthMethod {
         }
     }
 }
+
+/// Used with [`CloudStorage::upload_options`](super::CloudStorage::upload_options) and
+/// [`CloudStorage::user_upload_options`](super::CloudStorage::user_upload_options) to set properties
+/// of the objects that are uploaded.
+///
+/// # Example
+///
+/// ```rust
+/// use unftp_sbe_gcs::options::UploadOptions;
+///
+/// let options = UploadOptions::default()
+///     .kms_key_name("projects/my-project/locations/europe-west4/keyRings/ftp/cryptoKeys/uploads")
+///     .cache_control("no-store")
+///     .detect_content_type(true)
+///     .metadata("source", "ftp");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UploadOptions {
+    kms_key_name: Option<String>,
+    cache_control: Option<String>,
+    detect_content_type: bool,
+    metadata: BTreeMap<String, String>,
+}
+
+impl UploadOptions {
+    /// Encrypts uploaded objects with the given customer-managed encryption key (CMEK) from Cloud KMS,
+    /// instead of the default key of the bucket. Objects created by renaming or copying use it too.
+    pub fn kms_key_name<S: Into<String>>(mut self, key_name: S) -> Self {
+        self.kms_key_name = Some(key_name.into());
+        self
+    }
+
+    /// Sets the `Cache-Control` metadata of uploaded objects.
+    pub fn cache_control<S: Into<String>>(mut self, cache_control: S) -> Self {
+        self.cache_control = Some(cache_control.into());
+        self
+    }
+
+    /// Derives the content type of uploaded objects from their file extension. Objects with an
+    /// unknown extension, and all objects when this is off (the default), get `application/octet-stream`.
+    pub fn detect_content_type(mut self, detect: bool) -> Self {
+        self.detect_content_type = detect;
+        self
+    }
+
+    /// Adds a custom metadata entry to uploaded objects.
+    pub fn metadata<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+
+    pub(crate) fn kms_key(&self) -> Option<&str> {
+        self.kms_key_name.as_deref()
+    }
+
+    // The object resource sent along when starting an upload of the object with the given name.
+    pub(crate) fn object_resource(&self, name: &Path) -> Value {
+        let content_type = if self.detect_content_type {
+            mime_guess::from_path(name).first_or_octet_stream()
+        } else {
+            mime::APPLICATION_OCTET_STREAM
+        };
+        let mut resource = Map::new();
+        resource.insert(String::from("contentType"), Value::from(content_type.as_ref()));
+        if let Some(cache_control) = &self.cache_control {
+            resource.insert(String::from("cacheControl"), Value::from(cache_control.as_str()));
+        }
+        if !self.metadata.is_empty() {
+            resource.insert(String::from("metadata"), json!(self.metadata));
+        }
+        Value::Object(resource)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn object_resource() {
+        let options = UploadOptions::default().cache_control("no-store").metadata("source", "ftp");
+        assert_eq!(
+            options.object_resource(Path::new("report.csv")),
+            json!({"contentType": "application/octet-stream", "cacheControl": "no-store", "metadata": {"source": "ftp"}})
+        );
+        let options = UploadOptions::default().detect_content_type(true);
+        assert_eq!(options.object_resource(Path::new("dir/report.csv")), json!({"contentType": "text/csv"}));
+        assert_eq!(
+            options.object_resource(Path::new("dir/report")),
+            json!({"contentType": "application/octet-stream"})
+        );
+    }
+}
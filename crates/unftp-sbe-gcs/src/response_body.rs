@@ -1,3 +1,4 @@
+use super::object_metadata::name_hash;
 use super::ObjectMetadata;
 use base64::Engine;
 use chrono::prelude::*;
@@ -16,16 +17,30 @@ pub(crate) struct ResponseBody {
     next_page_token: Option<String>,
 }
 
+// The response to a rewrite request. Large objects take several requests, each one continuing
+// where the previous one left off through the rewrite token.
 #[derive(Deserialize, Debug)]
+pub(crate) struct RewriteResponse {
+    pub(crate) done: bool,
+    #[serde(rename = "rewriteToken")]
+    pub(crate) rewrite_token: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
 pub(crate) struct Item {
     name: String,
     updated: DateTime<Utc>,
+    #[serde(default, rename = "timeCreated")]
+    time_created: Option<DateTime<Utc>>,
 
     // GCS API defines `size` as json string, doh
     #[serde(default, deserialize_with = "item_size_deserializer")]
     size: u64,
     #[serde(default, rename = "md5Hash")]
     md5_hash: String,
+    // Also a string in the JSON API.
+    #[serde(default)]
+    generation: Option<String>,
 }
 
 // TODO: this is a generic string->* deserializer, move to a util package
@@ -76,8 +91,12 @@ impl ResponseBody {
                     path: prefix.into(),
                     metadata: ObjectMetadata {
                         last_updated: SystemTime::now(),
+                        created: None,
                         is_file: false,
                         size: 0,
+                        generation: None,
+                        md5: None,
+                        name_hash: name_hash(prefix),
                     },
                 })
                 .collect()
@@ -110,14 +129,29 @@ impl ResponseBody {
     pub(crate) fn next_token(&self) -> Option<String> {
         self.next_page_token.as_ref().cloned()
     }
+
+    pub(crate) fn into_items(self) -> Vec<Item> {
+        self.items.unwrap_or_default()
+    }
 }
 
 impl Item {
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
     pub(crate) fn to_metadata(&self) -> Result<ObjectMetadata, Error> {
         Ok(ObjectMetadata {
             size: self.size,
             last_updated: self.updated.into(),
+            created: self.time_created.map(SystemTime::from),
             is_file: !self.name.ends_with('/'),
+            generation: self.generation.clone(),
+            md5: match self.md5_hash.is_empty() {
+                true => None,
+                false => self.to_md5().ok(),
+            },
+            name_hash: name_hash(&self.name),
         })
     }
 
@@ -152,8 +186,10 @@ mod test {
         let item: Item = Item {
             name: "".into(),
             updated: date_time,
+            time_created: None,
             size: 50,
             md5_hash: "".into(),
+            generation: None,
         };
 
         let metadata: ObjectMetadata = item.to_metadata().unwrap();
@@ -162,6 +198,43 @@ mod test {
         assert!(metadata.is_file);
     }
 
+    #[test]
+    fn to_metadata_attributes() {
+        let item: Item = serde_json::from_str(
+            r#"{"name":"a.csv", "updated":"2020-09-01T12:13:14Z", "size":"8", "generation":"1598962394000000", "md5Hash":"XUFAKrxLKna5cZ2REBfFkg=="}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            item.to_metadata().unwrap().attributes(),
+            vec![
+                ("generation".to_string(), "1598962394000000".to_string()),
+                ("md5".to_string(), "5d41402abc4b2a76b9719d911017c592".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn to_metadata_unique_id() {
+        let item: Item = serde_json::from_str(r#"{"name":"a.csv", "updated":"2020-09-01T12:13:14Z", "size":"8", "generation":"1598962394000000"}"#).unwrap();
+        let other: Item = serde_json::from_str(r#"{"name":"b.csv", "updated":"2020-09-01T12:13:14Z", "size":"8", "generation":"1598962394000000"}"#).unwrap();
+        assert_eq!(item.to_metadata().unwrap().unique_id(), Some("1598962394000000.eee5bb517eccbf6".to_string()));
+        assert_ne!(item.to_metadata().unwrap().unique_id(), other.to_metadata().unwrap().unique_id());
+
+        let item: Item = serde_json::from_str(r#"{"name":"a.csv", "updated":"2020-09-01T12:13:14Z", "size":"8"}"#).unwrap();
+        assert_eq!(item.to_metadata().unwrap().unique_id(), None);
+    }
+
+    #[test]
+    fn to_metadata_created() {
+        let item: Item =
+            serde_json::from_str(r#"{"name":"a.csv", "updated":"2020-09-01T12:13:14Z", "timeCreated":"2020-08-01T10:11:12Z", "size":"8"}"#).unwrap();
+        let created: DateTime<Utc> = "2020-08-01T10:11:12Z".parse().unwrap();
+        assert_eq!(item.to_metadata().unwrap().created(), Some(created.into()));
+
+        let item: Item = serde_json::from_str(r#"{"name":"a.csv", "updated":"2020-09-01T12:13:14Z", "size":"8"}"#).unwrap();
+        assert_eq!(item.to_metadata().unwrap().created(), None);
+    }
+
     #[test]
     fn to_metadata_parse_error() {
         let response: serde_json::error::Result<Item> = serde_json::from_str(r#"{"name":"", "updated":"2020-09-01T12:13:14Z", "size":8}"#);
@@ -0,0 +1,71 @@
+use bytes::Bytes;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+// Bounds the memory used by the cache. When it is full, it starts over.
+const MAX_ENTRIES: usize = 10_000;
+
+// Caches the bodies of the JSON API responses to metadata and list requests for a limited time, so
+// that clients that look up every entry of a directory listing don't cause a request per entry. The
+// cache lives as long as the storage back-end, i.e. it is specific to a session.
+#[derive(Clone, Debug)]
+pub(crate) struct ResponseCache {
+    ttl: Duration,
+    entries: Arc<Mutex<HashMap<String, (Instant, Bytes)>>>,
+}
+
+impl ResponseCache {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        ResponseCache {
+            ttl,
+            entries: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Option<Bytes> {
+        let entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some((stored, body)) if stored.elapsed() < self.ttl => Some(body.clone()),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn insert(&self, key: String, body: Bytes) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_ENTRIES {
+            entries.retain(|_, (stored, _)| stored.elapsed() < self.ttl);
+            if entries.len() >= MAX_ENTRIES {
+                entries.clear();
+            }
+        }
+        entries.insert(key, (Instant::now(), body));
+    }
+
+    // Called whenever something changes in the bucket.
+    pub(crate) fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn entries_expire_and_can_be_cleared() {
+        let cache = ResponseCache::new(Duration::from_secs(60));
+        cache.insert(String::from("a"), Bytes::from_static(b"{}"));
+        assert_eq!(cache.get("a"), Some(Bytes::from_static(b"{}")));
+        assert_eq!(cache.get("b"), None);
+        cache.clear();
+        assert_eq!(cache.get("a"), None);
+
+        let cache = ResponseCache::new(Duration::ZERO);
+        cache.insert(String::from("a"), Bytes::from_static(b"{}"));
+        assert_eq!(cache.get("a"), None);
+    }
+}
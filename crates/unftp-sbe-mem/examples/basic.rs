@@ -0,0 +1,17 @@
+//! A throwaway server that keeps uploads in memory
+
+use unftp_sbe_mem::{MemoryStorage, ServerExt};
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    pretty_env_logger::init();
+
+    let storage = MemoryStorage::new();
+    storage.add_file("/README.txt", "Uploads to this server are gone once it stops.\n");
+
+    let addr = "127.0.0.1:2121";
+    let server = libunftp::Server::with_mem(storage).build().unwrap();
+
+    println!("Starting ftp server on {}", addr);
+    server.listen(addr).await.unwrap();
+}
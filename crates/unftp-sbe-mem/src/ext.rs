@@ -0,0 +1,23 @@
+use crate::MemoryStorage;
+use libunftp::auth::DefaultUser;
+use libunftp::{Server, ServerBuilder};
+
+/// Extension trait purely for construction convenience.
+pub trait ServerExt {
+    /// Create a new `Server` that keeps its files in the given [`MemoryStorage`]. All sessions
+    /// share its contents, and so does the caller when it holds on to a clone of it.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use unftp_sbe_mem::{MemoryStorage, ServerExt};
+    ///
+    /// let server = Server::with_mem(MemoryStorage::new());
+    /// ```
+    fn with_mem(storage: MemoryStorage) -> ServerBuilder<MemoryStorage, DefaultUser> {
+        libunftp::ServerBuilder::new(Box::new(move || storage.clone()))
+    }
+}
+
+impl ServerExt for Server<MemoryStorage, DefaultUser> {}
@@ -0,0 +1,366 @@
+//! A libunftp [`StorageBackend`] that keeps its files in memory. Nothing touches the disk, which
+//! makes it a good fit for tests and throwaway servers.
+//!
+//! Here is an example for using this storage backend
+//!
+//! ```no_run
+//! use unftp_sbe_mem::{MemoryStorage, ServerExt};
+//!
+//! #[tokio::main]
+//! pub async fn main() {
+//!     let storage = MemoryStorage::new();
+//!     storage.add_file("/hello.txt", "Hello world!\n");
+//!
+//!     let server = libunftp::Server::with_mem(storage.clone())
+//!         .greeting("Welcome to my FTP server")
+//!         .passive_ports(50000..65535)
+//!         .build()
+//!         .unwrap();
+//!
+//!     server.listen("127.0.0.1:2121").await;
+//! }
+//! ```
+
+mod ext;
+pub use ext::ServerExt;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use libunftp::auth::UserDetail;
+use libunftp::storage::{Error, ErrorKind, Fileinfo, Metadata, Result, StorageBackend};
+use std::{
+    collections::BTreeMap,
+    fmt::Debug,
+    io::Cursor,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    time::SystemTime,
+};
+use tokio::io::AsyncReadExt;
+
+/// A storage back-end that keeps files and directories in memory. Clones share their contents, so
+/// every session of a server sees the same files, and a clone kept by the caller can be used to
+/// prepare files or to inspect uploads, for instance in tests.
+///
+/// Uploads are stored once they were received completely, so they are atomic.
+#[derive(Debug, Clone)]
+pub struct MemoryStorage {
+    nodes: Arc<RwLock<BTreeMap<PathBuf, Node>>>,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    File { data: Bytes, modified: SystemTime },
+    Dir { modified: SystemTime },
+}
+
+/// Metadata for the storage back-end
+#[derive(Debug)]
+pub struct Meta {
+    len: u64,
+    dir: bool,
+    modified: SystemTime,
+}
+
+// Turns the path given by the client into the key used in the map: absolute, and without `.`
+// and `..` components. Going up from the root stays at the root.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::from("/");
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => normalized.push(name),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            _ => {}
+        }
+    }
+    normalized
+}
+
+impl Default for MemoryStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryStorage {
+    /// Creates an empty storage back-end that holds just the root directory.
+    pub fn new() -> Self {
+        let mut nodes = BTreeMap::new();
+        nodes.insert(PathBuf::from("/"), Node::Dir { modified: SystemTime::now() });
+        MemoryStorage {
+            nodes: Arc::new(RwLock::new(nodes)),
+        }
+    }
+
+    /// Stores a file with the given contents, replacing any file with that name and creating its
+    /// parent directories as needed. Useful to prepare files before a test.
+    pub fn add_file<P: AsRef<Path>, B: Into<Bytes>>(&self, path: P, data: B) {
+        let path = normalize(path.as_ref());
+        let mut nodes = self.write();
+        for dir in path.ancestors().skip(1) {
+            nodes.entry(dir.to_path_buf()).or_insert(Node::Dir { modified: SystemTime::now() });
+        }
+        nodes.insert(
+            path,
+            Node::File {
+                data: data.into(),
+                modified: SystemTime::now(),
+            },
+        );
+    }
+
+    /// Returns the contents of the given file, or `None` if there is no such file. Useful to check
+    /// uploads in a test.
+    pub fn read_file<P: AsRef<Path>>(&self, path: P) -> Option<Bytes> {
+        match self.read().get(&normalize(path.as_ref())) {
+            Some(Node::File { data, .. }) => Some(data.clone()),
+            _ => None,
+        }
+    }
+
+    // A panic while holding the lock can't leave the map in an inconsistent state, so poisoning
+    // is ignored.
+    fn read(&self) -> RwLockReadGuard<'_, BTreeMap<PathBuf, Node>> {
+        self.nodes.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, BTreeMap<PathBuf, Node>> {
+        self.nodes.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// Fails unless the parent of the given path is an existing directory.
+fn check_parent(nodes: &BTreeMap<PathBuf, Node>, path: &Path) -> Result<()> {
+    match path.parent().and_then(|parent| nodes.get(parent)) {
+        Some(Node::Dir { .. }) => Ok(()),
+        _ => Err(Error::from(ErrorKind::PermanentDirectoryNotAvailable)),
+    }
+}
+
+impl Node {
+    fn meta(&self) -> Meta {
+        match self {
+            Node::File { data, modified } => Meta {
+                len: data.len() as u64,
+                dir: false,
+                modified: *modified,
+            },
+            Node::Dir { modified } => Meta {
+                len: 0,
+                dir: true,
+                modified: *modified,
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl<User: UserDetail> StorageBackend<User> for MemoryStorage {
+    type Metadata = Meta;
+
+    fn supported_features(&self) -> u32 {
+        libunftp::storage::FEATURE_RESTART | libunftp::storage::FEATURE_SITEMD5 | libunftp::storage::FEATURE_ATOMIC_UPLOADS
+    }
+
+    async fn metadata<P: AsRef<Path> + Send + Debug>(&self, _user: &User, path: P) -> Result<Self::Metadata> {
+        match self.read().get(&normalize(path.as_ref())) {
+            Some(node) => Ok(node.meta()),
+            None => Err(Error::from(ErrorKind::PermanentFileNotAvailable)),
+        }
+    }
+
+    async fn list<P: AsRef<Path> + Send + Debug>(&self, _user: &User, path: P) -> Result<Vec<Fileinfo<PathBuf, Self::Metadata>>>
+    where
+        <Self as StorageBackend<User>>::Metadata: Metadata,
+    {
+        let path = normalize(path.as_ref());
+        let nodes = self.read();
+        if !matches!(nodes.get(&path), Some(Node::Dir { .. })) {
+            return Err(Error::from(ErrorKind::PermanentDirectoryNotAvailable));
+        }
+        // Descendants sort right after the directory itself.
+        Ok(nodes
+            .range(path.clone()..)
+            .skip(1)
+            .take_while(|(entry, _)| entry.starts_with(&path))
+            .filter(|(entry, _)| entry.parent() == Some(&path))
+            .map(|(entry, node)| Fileinfo {
+                path: entry.file_name().map(PathBuf::from).unwrap_or_default(),
+                metadata: node.meta(),
+            })
+            .collect())
+    }
+
+    async fn get<P: AsRef<Path> + Send + Debug>(&self, _user: &User, path: P, start_pos: u64) -> Result<Box<dyn tokio::io::AsyncRead + Send + Sync + Unpin>> {
+        match self.read().get(&normalize(path.as_ref())) {
+            Some(Node::File { data, .. }) => {
+                let start = usize::try_from(start_pos).unwrap_or(usize::MAX).min(data.len());
+                Ok(Box::new(Cursor::new(data.slice(start..))))
+            }
+            _ => Err(Error::from(ErrorKind::PermanentFileNotAvailable)),
+        }
+    }
+
+    async fn put<P: AsRef<Path> + Send + Debug, R: tokio::io::AsyncRead + Send + Sync + Unpin + 'static>(
+        &self,
+        _user: &User,
+        mut input: R,
+        path: P,
+        start_pos: u64,
+    ) -> Result<u64> {
+        let path = normalize(path.as_ref());
+        {
+            let nodes = self.read();
+            check_parent(&nodes, &path)?;
+            if let Some(Node::Dir { .. }) = nodes.get(&path) {
+                return Err(Error::from(ErrorKind::FileNameNotAllowedError));
+            }
+        }
+
+        let mut received = Vec::new();
+        input.read_to_end(&mut received).await?;
+        let bytes_copied = received.len() as u64;
+
+        let mut nodes = self.write();
+        // The directory may have been removed while the data was received.
+        check_parent(&nodes, &path)?;
+        let data = match nodes.get(&path) {
+            Some(Node::Dir { .. }) => return Err(Error::from(ErrorKind::FileNameNotAllowedError)),
+            Some(Node::File { data, .. }) if start_pos > 0 => {
+                // Resumed uploads keep what was stored up to the offset.
+                let mut resumed = data.to_vec();
+                resumed.resize(
+                    usize::try_from(start_pos).map_err(|_| Error::from(ErrorKind::ExceededStorageAllocationError))?,
+                    0,
+                );
+                resumed.extend(received);
+                resumed
+            }
+            _ => received,
+        };
+        nodes.insert(
+            path,
+            Node::File {
+                data: Bytes::from(data),
+                modified: SystemTime::now(),
+            },
+        );
+        Ok(bytes_copied)
+    }
+
+    async fn del<P: AsRef<Path> + Send + Debug>(&self, _user: &User, path: P) -> Result<()> {
+        let path = normalize(path.as_ref());
+        let mut nodes = self.write();
+        match nodes.get(&path) {
+            Some(Node::File { .. }) => {
+                nodes.remove(&path);
+                Ok(())
+            }
+            _ => Err(Error::from(ErrorKind::PermanentFileNotAvailable)),
+        }
+    }
+
+    async fn set_modified<P: AsRef<Path> + Send + Debug>(&self, _user: &User, path: P, time: SystemTime) -> Result<()> {
+        let path = normalize(path.as_ref());
+        match self.write().get_mut(&path) {
+            Some(Node::File { modified, .. }) | Some(Node::Dir { modified }) => {
+                *modified = time;
+                Ok(())
+            }
+            None => Err(Error::from(ErrorKind::PermanentFileNotAvailable)),
+        }
+    }
+
+    async fn mkd<P: AsRef<Path> + Send + Debug>(&self, _user: &User, path: P) -> Result<()> {
+        let path = normalize(path.as_ref());
+        let mut nodes = self.write();
+        check_parent(&nodes, &path)?;
+        if nodes.contains_key(&path) {
+            return Err(Error::from(ErrorKind::PermanentFileNotAvailable));
+        }
+        nodes.insert(path, Node::Dir { modified: SystemTime::now() });
+        Ok(())
+    }
+
+    async fn rename<P: AsRef<Path> + Send + Debug>(&self, _user: &User, from: P, to: P) -> Result<()> {
+        let from = normalize(from.as_ref());
+        let to = normalize(to.as_ref());
+        let mut nodes = self.write();
+        check_parent(&nodes, &to)?;
+        match (nodes.get(&from), nodes.get(&to)) {
+            (None, _) => return Err(Error::from(ErrorKind::PermanentFileNotAvailable)),
+            (Some(_), Some(Node::Dir { .. })) => return Err(Error::from(ErrorKind::FileNameNotAllowedError)),
+            (Some(Node::Dir { .. }), Some(_)) => return Err(Error::from(ErrorKind::FileNameNotAllowedError)),
+            _ if from == Path::new("/") || to.starts_with(&from) => return Err(Error::from(ErrorKind::FileNameNotAllowedError)),
+            _ => {}
+        }
+        // Move the entry along with everything below it.
+        let moved: Vec<PathBuf> = nodes
+            .range(from.clone()..)
+            .take_while(|(path, _)| path.starts_with(&from))
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in moved {
+            if let Some(node) = nodes.remove(&path) {
+                let relative = path.strip_prefix(&from).unwrap_or(Path::new(""));
+                nodes.insert(to.join(relative), node);
+            }
+        }
+        Ok(())
+    }
+
+    async fn rmd<P: AsRef<Path> + Send + Debug>(&self, _user: &User, path: P) -> Result<()> {
+        let path = normalize(path.as_ref());
+        let mut nodes = self.write();
+        if path == Path::new("/") || !matches!(nodes.get(&path), Some(Node::Dir { .. })) {
+            return Err(Error::from(ErrorKind::PermanentDirectoryNotAvailable));
+        }
+        if nodes.range(path.clone()..).nth(1).is_some_and(|(entry, _)| entry.starts_with(&path)) {
+            return Err(Error::from(ErrorKind::PermanentDirectoryNotEmpty));
+        }
+        nodes.remove(&path);
+        Ok(())
+    }
+
+    async fn cwd<P: AsRef<Path> + Send + Debug>(&self, _user: &User, path: P) -> Result<()> {
+        match self.read().get(&normalize(path.as_ref())) {
+            Some(Node::Dir { .. }) => Ok(()),
+            _ => Err(Error::from(ErrorKind::PermanentDirectoryNotAvailable)),
+        }
+    }
+}
+
+impl Metadata for Meta {
+    fn len(&self) -> u64 {
+        self.len
+    }
+
+    fn is_dir(&self) -> bool {
+        self.dir
+    }
+
+    fn is_file(&self) -> bool {
+        !self.dir
+    }
+
+    fn is_symlink(&self) -> bool {
+        false
+    }
+
+    fn modified(&self) -> Result<SystemTime> {
+        Ok(self.modified)
+    }
+
+    fn gid(&self) -> u32 {
+        0
+    }
+
+    fn uid(&self) -> u32 {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests;
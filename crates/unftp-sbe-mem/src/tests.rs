@@ -0,0 +1,91 @@
+use super::*;
+use libunftp::auth::DefaultUser;
+use pretty_assertions::assert_eq;
+
+#[test]
+fn mem_normalize() {
+    assert_eq!(normalize(Path::new("foo/bar")), Path::new("/foo/bar"));
+    assert_eq!(normalize(Path::new("/foo/./bar/")), Path::new("/foo/bar"));
+    assert_eq!(normalize(Path::new("/foo/../../bar")), Path::new("/bar"));
+    assert_eq!(normalize(Path::new("")), Path::new("/"));
+}
+
+#[tokio::test]
+async fn mem_put_and_get() {
+    let storage = MemoryStorage::new();
+    let user = DefaultUser {};
+
+    let written = storage.put(&user, &b"hello world"[..], "/hello.txt", 0).await.unwrap();
+    assert_eq!(written, 11);
+    assert_eq!(storage.read_file("hello.txt"), Some(Bytes::from_static(b"hello world")));
+
+    let mut contents = String::new();
+    let mut reader = storage.get(&user, "/hello.txt", 6).await.unwrap();
+    reader.read_to_string(&mut contents).await.unwrap();
+    assert_eq!(contents, "world");
+
+    // Resuming keeps what was stored up to the offset.
+    storage.put(&user, &b"there"[..], "/hello.txt", 6).await.unwrap();
+    assert_eq!(storage.read_file("/hello.txt"), Some(Bytes::from_static(b"hello there")));
+
+    let err = storage.put(&user, &b"data"[..], "/missing/file.txt", 0).await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::PermanentDirectoryNotAvailable);
+}
+
+#[tokio::test]
+async fn mem_list() {
+    let storage = MemoryStorage::new();
+    storage.add_file("/dir/a.txt", "a");
+    storage.add_file("/dir/sub/b.txt", "bb");
+    storage.add_file("/dir b/c.txt", "c");
+    let user = DefaultUser {};
+
+    let list = storage.list(&user, "/dir").await.unwrap();
+    let entries: Vec<(PathBuf, bool, u64)> = list.into_iter().map(|fi| (fi.path, fi.metadata.is_dir(), fi.metadata.len())).collect();
+    assert_eq!(entries, vec![(PathBuf::from("a.txt"), false, 1), (PathBuf::from("sub"), true, 0)]);
+
+    let err = storage.list(&user, "/dir/a.txt").await.map(drop).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::PermanentDirectoryNotAvailable);
+}
+
+#[tokio::test]
+async fn mem_rename_dir() {
+    let storage = MemoryStorage::new();
+    storage.add_file("/old/sub/file.txt", "data");
+    let user = DefaultUser {};
+
+    storage.rename(&user, "/old", "/new").await.unwrap();
+    assert_eq!(storage.read_file("/new/sub/file.txt"), Some(Bytes::from_static(b"data")));
+    assert!(storage.metadata(&user, "/old").await.is_err());
+
+    let err = storage.rename(&user, "/new", "/new/sub/inside").await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::FileNameNotAllowedError);
+}
+
+#[tokio::test]
+async fn mem_rmd() {
+    let storage = MemoryStorage::new();
+    storage.add_file("/dir/file.txt", "data");
+    let user = DefaultUser {};
+
+    let err = storage.rmd(&user, "/dir").await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::PermanentDirectoryNotEmpty);
+
+    storage.del(&user, "/dir/file.txt").await.unwrap();
+    storage.rmd(&user, "/dir").await.unwrap();
+    assert!(storage.cwd(&user, "/dir").await.is_err());
+}
+
+#[tokio::test]
+async fn mem_rmd_recursive() {
+    let storage = MemoryStorage::new();
+    storage.add_file("/dir/file.txt", "data");
+    storage.add_file("/dir/sub/deeper/file.txt", "data");
+    storage.add_file("/dir b/file.txt", "data");
+    let user = DefaultUser {};
+
+    storage.rmd_recursive(&user, "/dir").await.unwrap();
+    assert!(storage.cwd(&user, "/dir").await.is_err());
+    assert!(storage.read_file("/dir/sub/deeper/file.txt").is_none());
+    assert_eq!(storage.read_file("/dir b/file.txt"), Some(Bytes::from_static(b"data")));
+}
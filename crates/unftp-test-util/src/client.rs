@@ -0,0 +1,361 @@
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+
+/// The error returned by the [`Client`].
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The connection with the server failed.
+    #[error("connection failed: {0}")]
+    Io(#[from] std::io::Error),
+    /// The server replied with another code than the command expects.
+    #[error("unexpected reply to {command}: {reply}")]
+    UnexpectedReply {
+        /// The command as it was sent, with the password of PASS left out.
+        command: String,
+        /// The reply that the server sent.
+        reply: Reply,
+    },
+    /// The server sent something that is not a valid FTP reply.
+    #[error("malformed reply: {0}")]
+    Malformed(String),
+}
+
+/// The result type of the [`Client`].
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// A reply from the server, which may span several lines.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reply {
+    /// The three digit reply code.
+    pub code: u16,
+    /// The lines of the reply, without the line endings.
+    pub lines: Vec<String>,
+}
+
+impl Reply {
+    /// The text of the last line, after the reply code.
+    pub fn text(&self) -> &str {
+        self.lines.last().map(|line| line.get(4..).unwrap_or("")).unwrap_or("")
+    }
+
+    /// Whether this is a positive completion (2xx) reply.
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.code)
+    }
+}
+
+impl fmt::Display for Reply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.lines.join("\n"))
+    }
+}
+
+/// How the [`Client`] sets up data connections.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DataMode {
+    /// Uses PASV and its `227` reply.
+    #[default]
+    Pasv,
+    /// Uses EPSV and its `229` reply.
+    Epsv,
+}
+
+/// A plain FTP client that talks to a server through raw commands.
+///
+/// Besides [`cmd`](Client::cmd), which sends any command and returns whatever the server replied,
+/// it has typed helpers for the common commands. These check the reply code and fail with
+/// [`Error::UnexpectedReply`] otherwise, so a test can use `?` or `unwrap` on them.
+#[derive(Debug)]
+pub struct Client {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+    peer: IpAddr,
+    data_mode: DataMode,
+    greeting: Reply,
+}
+
+impl Client {
+    /// Connects to the server and reads its greeting.
+    pub async fn connect(addr: SocketAddr) -> Result<Client> {
+        let stream = TcpStream::connect(addr).await?;
+        let (reader, writer) = stream.into_split();
+        let mut client = Client {
+            reader: BufReader::new(reader),
+            writer,
+            peer: addr.ip(),
+            data_mode: DataMode::default(),
+            greeting: Reply { code: 0, lines: vec![] },
+        };
+        client.greeting = check("connect", client.reply().await?, 220)?;
+        Ok(client)
+    }
+
+    /// The greeting that the server sent on connect.
+    pub fn greeting(&self) -> &Reply {
+        &self.greeting
+    }
+
+    /// Sets how the data connections of the typed helpers are set up. Defaults to [`DataMode::Pasv`].
+    pub fn set_data_mode(&mut self, mode: DataMode) {
+        self.data_mode = mode;
+    }
+
+    /// Sends a command and returns the reply of the server, whatever it is.
+    pub async fn cmd(&mut self, command: &str) -> Result<Reply> {
+        self.send(command).await?;
+        self.reply().await
+    }
+
+    /// Sends a command without waiting for the reply, e.g. to send several before reading their
+    /// replies with [`reply`](Client::reply).
+    pub async fn send(&mut self, command: &str) -> Result<()> {
+        self.send_bytes(command.as_bytes()).await
+    }
+
+    /// Sends a command that may not be valid UTF-8, like one with a file name in another encoding.
+    /// Read the reply with [`raw_line`](Client::raw_line) if it may not be valid UTF-8 either.
+    pub async fn send_bytes(&mut self, command: &[u8]) -> Result<()> {
+        self.writer.write_all(&[command, b"\r\n"].concat()).await?;
+        Ok(())
+    }
+
+    /// Reads the next line that the server sent as is, with its line ending.
+    pub async fn raw_line(&mut self) -> Result<Vec<u8>> {
+        let mut line = vec![];
+        if self.reader.read_until(b'\n', &mut line).await? == 0 {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+        Ok(line)
+    }
+
+    /// Reads the next reply of the server, for instance the one that follows a transfer.
+    pub async fn reply(&mut self) -> Result<Reply> {
+        let first = self.read_line().await?;
+        let code: u16 = first
+            .get(..3)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| Error::Malformed(first.clone()))?;
+        let mut lines = vec![first];
+        if lines[0].as_bytes().get(3) == Some(&b'-') {
+            let last = format!("{} ", code);
+            loop {
+                let line = self.read_line().await?;
+                let done = line.starts_with(&last);
+                lines.push(line);
+                if done {
+                    break;
+                }
+            }
+        }
+        Ok(Reply { code, lines })
+    }
+
+    /// Logs in with USER and PASS.
+    pub async fn login(&mut self, username: &str, password: &str) -> Result<()> {
+        let reply = self.cmd(&format!("USER {}", username)).await?;
+        match reply.code {
+            230 => Ok(()),
+            331 => {
+                let reply = self.cmd(&format!("PASS {}", password)).await?;
+                check("PASS", reply, 230).map(drop)
+            }
+            _ => Err(Error::UnexpectedReply {
+                command: format!("USER {}", username),
+                reply,
+            }),
+        }
+    }
+
+    /// Opens a data connection with PASV.
+    pub async fn pasv(&mut self) -> Result<TcpStream> {
+        let reply = self.expect("PASV", 227).await?;
+        self.connect_pasv(&reply).await
+    }
+
+    /// Opens the data connection that the `227` reply to a PASV tells about, for a PASV that was
+    /// sent with [`send`](Client::send).
+    pub async fn connect_pasv(&self, reply: &Reply) -> Result<TcpStream> {
+        let text = reply.text();
+        let numbers: Vec<u16> = text
+            .find('(')
+            .zip(text.find(')'))
+            .and_then(|(start, end)| text.get(start + 1..end))
+            .map(|inner| inner.split(',').filter_map(|n| n.trim().parse().ok()).collect())
+            .unwrap_or_default();
+        if numbers.len() != 6 {
+            return Err(Error::Malformed(reply.to_string()));
+        }
+        // The address in the reply may be one that only works from outside a gateway, so only the
+        // port is used.
+        Ok(TcpStream::connect((self.peer, numbers[4] * 256 + numbers[5])).await?)
+    }
+
+    /// Opens a data connection with EPSV.
+    pub async fn epsv(&mut self) -> Result<TcpStream> {
+        let reply = self.expect("EPSV", 229).await?;
+        let text = reply.text();
+        let port = text
+            .find("(|||")
+            .and_then(|start| text[start + 4..].split('|').next())
+            .and_then(|port| port.parse::<u16>().ok())
+            .ok_or_else(|| Error::Malformed(reply.to_string()))?;
+        Ok(TcpStream::connect((self.peer, port)).await?)
+    }
+
+    /// Returns the current directory, as replied to PWD.
+    pub async fn pwd(&mut self) -> Result<String> {
+        let reply = self.expect("PWD", 257).await?;
+        let text = reply.text();
+        match (text.find('"'), text.rfind('"')) {
+            (Some(start), Some(end)) if start < end => Ok(text[start + 1..end].replace("\"\"", "\"")),
+            _ => Err(Error::Malformed(reply.to_string())),
+        }
+    }
+
+    /// Changes the current directory with CWD.
+    pub async fn cwd(&mut self, path: &str) -> Result<()> {
+        self.expect(&format!("CWD {}", path), 250).await.map(drop)
+    }
+
+    /// Creates a directory with MKD.
+    pub async fn mkd(&mut self, path: &str) -> Result<()> {
+        self.expect(&format!("MKD {}", path), 257).await.map(drop)
+    }
+
+    /// Removes a directory with RMD.
+    pub async fn rmd(&mut self, path: &str) -> Result<()> {
+        self.expect(&format!("RMD {}", path), 250).await.map(drop)
+    }
+
+    /// Deletes a file with DELE.
+    pub async fn dele(&mut self, path: &str) -> Result<()> {
+        self.expect(&format!("DELE {}", path), 250).await.map(drop)
+    }
+
+    /// Renames a file or directory with RNFR and RNTO.
+    pub async fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        self.expect(&format!("RNFR {}", from), 350).await?;
+        self.expect(&format!("RNTO {}", to), 250).await.map(drop)
+    }
+
+    /// Returns the size of a file, as replied to SIZE.
+    pub async fn size(&mut self, path: &str) -> Result<u64> {
+        let reply = self.expect(&format!("SIZE {}", path), 213).await?;
+        reply.text().trim().parse().map_err(|_| Error::Malformed(reply.to_string()))
+    }
+
+    /// Downloads a file with RETR.
+    pub async fn retr(&mut self, path: &str) -> Result<Vec<u8>> {
+        self.read_data(&format!("RETR {}", path)).await
+    }
+
+    /// Uploads a file with STOR.
+    pub async fn stor(&mut self, path: &str, content: &[u8]) -> Result<()> {
+        self.write_data(&format!("STOR {}", path), content).await
+    }
+
+    /// Returns the lines of a LIST.
+    pub async fn list(&mut self, path: Option<&str>) -> Result<Vec<String>> {
+        self.read_lines(&with_arg("LIST", path)).await
+    }
+
+    /// Returns the names of an NLST.
+    pub async fn nlst(&mut self, path: Option<&str>) -> Result<Vec<String>> {
+        self.read_lines(&with_arg("NLST", path)).await
+    }
+
+    /// Returns the lines of an MLSD, each a list of facts followed by a name.
+    pub async fn mlsd(&mut self, path: Option<&str>) -> Result<Vec<String>> {
+        self.read_lines(&with_arg("MLSD", path)).await
+    }
+
+    /// Sends QUIT.
+    pub async fn quit(mut self) -> Result<()> {
+        self.expect("QUIT", 221).await.map(drop)
+    }
+
+    async fn read_line(&mut self) -> Result<String> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line).await? == 0 {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    }
+
+    async fn expect(&mut self, command: &str, code: u16) -> Result<Reply> {
+        let reply = self.cmd(command).await?;
+        check(command, reply, code)
+    }
+
+    async fn data_connection(&mut self) -> Result<TcpStream> {
+        match self.data_mode {
+            DataMode::Pasv => self.pasv().await,
+            DataMode::Epsv => self.epsv().await,
+        }
+    }
+
+    async fn read_data(&mut self, command: &str) -> Result<Vec<u8>> {
+        let mut data = self.data_connection().await?;
+        let reply = self.cmd(command).await?;
+        if reply.code != 125 && reply.code != 150 {
+            return Err(Error::UnexpectedReply {
+                command: command.to_string(),
+                reply,
+            });
+        }
+        let mut content = vec![];
+        data.read_to_end(&mut content).await?;
+        let reply = self.reply().await?;
+        check(command, reply, 226)?;
+        Ok(content)
+    }
+
+    async fn write_data(&mut self, command: &str, content: &[u8]) -> Result<()> {
+        let mut data = self.data_connection().await?;
+        let reply = self.cmd(command).await?;
+        if reply.code != 125 && reply.code != 150 {
+            return Err(Error::UnexpectedReply {
+                command: command.to_string(),
+                reply,
+            });
+        }
+        data.write_all(content).await?;
+        data.shutdown().await?;
+        drop(data);
+        let reply = self.reply().await?;
+        match reply.code {
+            226 | 250 => Ok(()),
+            _ => Err(Error::UnexpectedReply {
+                command: command.to_string(),
+                reply,
+            }),
+        }
+    }
+
+    async fn read_lines(&mut self, command: &str) -> Result<Vec<String>> {
+        let content = self.read_data(command).await?;
+        let content = String::from_utf8(content).map_err(|err| Error::Malformed(err.to_string()))?;
+        Ok(content.lines().map(str::to_string).collect())
+    }
+}
+
+fn with_arg(command: &str, arg: Option<&str>) -> String {
+    match arg {
+        Some(arg) => format!("{} {}", command, arg),
+        None => command.to_string(),
+    }
+}
+
+fn check(command: &str, reply: Reply, code: u16) -> Result<Reply> {
+    if reply.code == code {
+        Ok(reply)
+    } else {
+        Err(Error::UnexpectedReply {
+            command: command.to_string(),
+            reply,
+        })
+    }
+}
@@ -0,0 +1,289 @@
+//! A standard suite of checks that a [`StorageBackend`](libunftp::storage::StorageBackend) should
+//! pass when it is served by libunftp.
+//!
+//! The suite talks plain FTP to the server, so it checks the whole chain from command to back-end
+//! and back to reply. Each check runs in a fresh directory of its own, which it creates with MKD,
+//! so the back-end only needs to start out with a writable root.
+//!
+//! ```no_run
+//! use unftp_sbe_mem::{MemoryStorage, ServerExt};
+//! use unftp_test_util::conformance::Suite;
+//!
+//! #[tokio::test]
+//! async fn conformance() {
+//!     Suite::new().run(libunftp::Server::with_mem(MemoryStorage::new())).await;
+//! }
+//! ```
+
+use crate::client::{self, Client, DataMode, Error};
+use crate::Harness;
+use libunftp::auth::UserDetail;
+use libunftp::storage::{Metadata, StorageBackend};
+use libunftp::ServerBuilder;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use tokio::io::AsyncWriteExt;
+
+/// A check of the suite that did not pass.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    /// The name of the check.
+    pub check: &'static str,
+    /// What went wrong.
+    pub reason: String,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.check, self.reason)
+    }
+}
+
+/// Runs the conformance checks against a server.
+#[derive(Debug, Clone)]
+pub struct Suite {
+    username: String,
+    password: String,
+}
+
+impl Default for Suite {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Suite {
+    /// Creates a suite that logs in as the anonymous user.
+    pub fn new() -> Self {
+        Suite {
+            username: "anonymous".to_string(),
+            password: "anonymous".to_string(),
+        }
+    }
+
+    /// Sets the credentials to log in with, for servers that don't allow anonymous users.
+    pub fn credentials(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.username = username.into();
+        self.password = password.into();
+        self
+    }
+
+    /// Starts the server and runs all checks against it. Panics with the checks that failed.
+    pub async fn run<Storage, User>(&self, builder: ServerBuilder<Storage, User>)
+    where
+        Storage: StorageBackend<User> + 'static,
+        Storage::Metadata: Metadata,
+        User: UserDetail + 'static,
+    {
+        let failures = self.check(builder).await;
+        if !failures.is_empty() {
+            let report: Vec<String> = failures.iter().map(Failure::to_string).collect();
+            panic!("{} conformance check(s) failed:\n{}", failures.len(), report.join("\n"));
+        }
+    }
+
+    /// Starts the server and runs all checks against it, returning the ones that failed.
+    pub async fn check<Storage, User>(&self, builder: ServerBuilder<Storage, User>) -> Vec<Failure>
+    where
+        Storage: StorageBackend<User> + 'static,
+        Storage::Metadata: Metadata,
+        User: UserDetail + 'static,
+    {
+        let harness = match Harness::start(builder).await {
+            Ok(harness) => harness,
+            Err(err) => {
+                return vec![Failure {
+                    check: "start",
+                    reason: err.to_string(),
+                }]
+            }
+        };
+        let mut failures = vec![];
+        for (name, check) in CHECKS {
+            if let Err(Reason(reason)) = self.run_check(&harness, name, *check).await {
+                failures.push(Failure { check: name, reason });
+            }
+        }
+        failures
+    }
+
+    async fn run_check(&self, harness: &Harness, name: &str, check: Check) -> Outcome {
+        let mut client = harness.login(&self.username, &self.password).await?;
+        let dir = format!("conformance-{}", name);
+        client.mkd(&dir).await?;
+        client.cwd(&dir).await?;
+        check(&mut client).await
+    }
+}
+
+struct Reason(String);
+
+impl From<Error> for Reason {
+    fn from(err: Error) -> Self {
+        Reason(err.to_string())
+    }
+}
+
+type Outcome = Result<(), Reason>;
+
+type Check = for<'a> fn(&'a mut Client) -> Pin<Box<dyn Future<Output = Outcome> + Send + 'a>>;
+
+const CHECKS: &[(&str, Check)] = &[
+    ("store-and-retrieve", |client| Box::pin(store_and_retrieve(client))),
+    ("epsv", |client| Box::pin(epsv(client))),
+    ("overwrite", |client| Box::pin(overwrite(client))),
+    ("store-unique", |client| Box::pin(store_unique(client))),
+    ("restart", |client| Box::pin(restart(client))),
+    ("list", |client| Box::pin(list(client))),
+    ("directories", |client| Box::pin(directories(client))),
+    ("delete", |client| Box::pin(delete(client))),
+    ("rename", |client| Box::pin(rename(client))),
+    ("not-found", |client| Box::pin(not_found(client))),
+];
+
+macro_rules! ensure {
+    ($cond:expr, $($arg:tt)+) => {
+        if !$cond {
+            return Err(Reason(format!($($arg)+)));
+        }
+    };
+}
+
+// Large enough to take several reads and writes on the data connection.
+fn content() -> Vec<u8> {
+    (0..100_000u32).map(|i| (i % 251) as u8).collect()
+}
+
+fn ensure_code<T>(result: client::Result<T>, code: u16, what: &str) -> Outcome {
+    match result {
+        Err(Error::UnexpectedReply { reply, .. }) if reply.code == code => Ok(()),
+        Err(err) => Err(err.into()),
+        Ok(_) => Err(Reason(format!("{} succeeded, expected a {} reply", what, code))),
+    }
+}
+
+async fn store_and_retrieve(client: &mut Client) -> Outcome {
+    let content = content();
+    client.stor("file.bin", &content).await?;
+    ensure!(client.retr("file.bin").await? == content, "RETR returned other content than was stored");
+    let size = client.size("file.bin").await?;
+    ensure!(size == content.len() as u64, "SIZE replied {} for a file of {} bytes", size, content.len());
+    Ok(())
+}
+
+async fn epsv(client: &mut Client) -> Outcome {
+    client.set_data_mode(DataMode::Epsv);
+    let result = async {
+        client.stor("file.txt", b"extended").await?;
+        client.retr("file.txt").await
+    }
+    .await;
+    client.set_data_mode(DataMode::Pasv);
+    match result {
+        // Not every server version knows EPSV, that is not up to the back-end.
+        Err(Error::UnexpectedReply { reply, .. }) if reply.code == 500 || reply.code == 502 => Ok(()),
+        result => {
+            ensure!(result? == b"extended", "RETR over EPSV returned other content than was stored");
+            Ok(())
+        }
+    }
+}
+
+async fn overwrite(client: &mut Client) -> Outcome {
+    client.stor("file.txt", b"the first version").await?;
+    client.stor("file.txt", b"second").await?;
+    ensure!(client.retr("file.txt").await? == b"second", "STOR did not replace the existing file");
+    Ok(())
+}
+
+async fn store_unique(client: &mut Client) -> Outcome {
+    client.stor("file.txt", b"existing").await?;
+    let mut data = client.pasv().await?;
+    let reply = client.cmd("STOU").await?;
+    ensure!(reply.code == 150, "STOU replied {}", reply);
+    // RFC 1123 has the name in the preliminary reply.
+    let Some(name) = reply.text().strip_prefix("FILE: ").map(str::to_string) else {
+        return Err(Reason(format!("STOU did not name the file in {}", reply)));
+    };
+    data.write_all(b"unique").await.map_err(Error::from)?;
+    data.shutdown().await.map_err(Error::from)?;
+    drop(data);
+    let reply = client.reply().await?;
+    ensure!(reply.is_success(), "STOU replied {} after the transfer", reply);
+    ensure!(name != "file.txt", "STOU chose the name of an existing file");
+    ensure!(client.retr(&name).await? == b"unique", "RETR of the STOU file returned other content");
+    ensure!(client.retr("file.txt").await? == b"existing", "STOU changed an existing file");
+    Ok(())
+}
+
+async fn restart(client: &mut Client) -> Outcome {
+    client.stor("file.txt", b"hello world").await?;
+    let reply = client.cmd("REST 6").await?;
+    ensure!(reply.code == 350, "REST replied {}", reply);
+    let tail = client.retr("file.txt").await?;
+    ensure!(tail == b"world", "RETR after REST 6 returned {:?}", String::from_utf8_lossy(&tail));
+    Ok(())
+}
+
+async fn list(client: &mut Client) -> Outcome {
+    client.stor("a.txt", b"a").await?;
+    client.stor("b.txt", b"bb").await?;
+    client.mkd("sub").await?;
+
+    let mut names = client.nlst(None).await?;
+    names.sort();
+    ensure!(names == ["a.txt", "b.txt", "sub"], "NLST listed {:?}", names);
+
+    let lines = client.list(None).await?;
+    for name in ["a.txt", "b.txt", "sub"] {
+        ensure!(
+            lines.iter().any(|line| line.ends_with(&format!(" {}", name))),
+            "LIST did not list {}: {:?}",
+            name,
+            lines
+        );
+    }
+    ensure!(
+        lines.iter().any(|line| line.starts_with('d') && line.ends_with(" sub")),
+        "LIST did not list sub as a directory: {:?}",
+        lines
+    );
+    Ok(())
+}
+
+async fn directories(client: &mut Client) -> Outcome {
+    let parent = client.pwd().await?;
+    client.mkd("sub").await?;
+    client.cwd("sub").await?;
+    let pwd = client.pwd().await?;
+    ensure!(pwd == format!("{}/sub", parent), "PWD replied {} after CWD sub from {}", pwd, parent);
+    client.stor("file.txt", b"nested").await?;
+    client.cwd("..").await?;
+    ensure!(client.retr("sub/file.txt").await? == b"nested", "RETR of a nested path returned other content");
+    client.dele("sub/file.txt").await?;
+    client.rmd("sub").await?;
+    ensure_code(client.cwd("sub").await, 550, "CWD to a removed directory")
+}
+
+async fn delete(client: &mut Client) -> Outcome {
+    client.stor("file.txt", b"delete me").await?;
+    client.dele("file.txt").await?;
+    ensure_code(client.size("file.txt").await, 550, "SIZE of a deleted file")?;
+    let names = client.nlst(None).await?;
+    ensure!(names.is_empty(), "NLST still lists {:?} after DELE", names);
+    Ok(())
+}
+
+async fn rename(client: &mut Client) -> Outcome {
+    client.stor("old.txt", b"moving").await?;
+    client.rename("old.txt", "new.txt").await?;
+    ensure!(client.retr("new.txt").await? == b"moving", "RETR of the renamed file returned other content");
+    ensure_code(client.size("old.txt").await, 550, "SIZE of the old name")
+}
+
+async fn not_found(client: &mut Client) -> Outcome {
+    ensure_code(client.size("missing.txt").await, 550, "SIZE of a missing file")?;
+    ensure_code(client.retr("missing.txt").await, 550, "RETR of a missing file")?;
+    ensure_code(client.cwd("missing").await, 550, "CWD to a missing directory")
+}
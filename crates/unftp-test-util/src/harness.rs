@@ -0,0 +1,82 @@
+use crate::client::{self, Client};
+use libunftp::auth::UserDetail;
+use libunftp::storage::{Metadata, StorageBackend};
+use libunftp::{ServerBuilder, ServerError};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// A server that runs in the background on a free port of the loopback interface, for as long as
+/// the harness lives.
+///
+/// # Example
+///
+/// ```no_run
+/// use unftp_sbe_mem::{MemoryStorage, ServerExt};
+/// use unftp_test_util::Harness;
+///
+/// # async fn test() {
+/// let harness = Harness::start(libunftp::Server::with_mem(MemoryStorage::new())).await.unwrap();
+/// let mut client = harness.login("anonymous", "anonymous").await.unwrap();
+/// client.stor("hello.txt", b"hello").await.unwrap();
+/// assert_eq!(client.retr("hello.txt").await.unwrap(), b"hello");
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Harness {
+    addr: SocketAddr,
+    server: JoinHandle<Result<(), ServerError>>,
+}
+
+impl Harness {
+    /// Builds the server, starts it on the tokio runtime and waits until it accepts connections.
+    pub async fn start<Storage, User>(builder: ServerBuilder<Storage, User>) -> Result<Harness, ServerError>
+    where
+        Storage: StorageBackend<User> + 'static,
+        Storage::Metadata: Metadata,
+        User: UserDetail + 'static,
+    {
+        let server = builder.build()?;
+        // Another process could take the port before the server binds it again, but the kernel
+        // hands out ephemeral ports round-robin so that is unlikely.
+        let addr = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?.local_addr()?;
+        let mut server = tokio::spawn(server.listen(addr.to_string()));
+
+        let mut attempts = 0;
+        while tokio::net::TcpStream::connect(addr).await.is_err() {
+            if server.is_finished() || attempts == 100 {
+                server.abort();
+                return match (&mut server).await {
+                    Ok(Err(err)) => Err(err),
+                    _ => Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "the server did not start listening").into()),
+                };
+            }
+            attempts += 1;
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        Ok(Harness { addr, server })
+    }
+
+    /// The address that the server listens on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Connects a new client to the server.
+    pub async fn connect(&self) -> client::Result<Client> {
+        Client::connect(self.addr).await
+    }
+
+    /// Connects a new client to the server and logs it in.
+    pub async fn login(&self, username: &str, password: &str) -> client::Result<Client> {
+        let mut client = self.connect().await?;
+        client.login(username, password).await?;
+        Ok(client)
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
@@ -0,0 +1,24 @@
+//! Test utilities for libunftp and its storage back-ends.
+//!
+//! The [`Harness`] runs a libunftp server in the background on a free port, and the [`Client`]
+//! talks to it with raw FTP commands. The [`conformance`] module has a standard suite of checks
+//! that back-end authors can run against their own [`StorageBackend`] implementation:
+//!
+//! ```no_run
+//! use unftp_sbe_mem::{MemoryStorage, ServerExt};
+//! use unftp_test_util::conformance::Suite;
+//!
+//! #[tokio::test]
+//! async fn conformance() {
+//!     Suite::new().run(libunftp::Server::with_mem(MemoryStorage::new())).await;
+//! }
+//! ```
+//!
+//! [`StorageBackend`]: libunftp::storage::StorageBackend
+
+mod client;
+pub mod conformance;
+mod harness;
+
+pub use client::{Client, DataMode, Error, Reply, Result};
+pub use harness::Harness;
@@ -0,0 +1,53 @@
+#![allow(missing_docs)]
+
+use libunftp::storage::{ErrorKind, Operation, Rule, Script, Scripted};
+use unftp_sbe_mem::{MemoryStorage, ServerExt};
+use unftp_test_util::conformance::Suite;
+use unftp_test_util::{DataMode, Harness};
+
+#[tokio::test]
+async fn memory_storage_conforms() {
+    Suite::new().run(libunftp::Server::with_mem(MemoryStorage::new())).await;
+}
+
+#[tokio::test]
+async fn client_helpers() {
+    let storage = MemoryStorage::new();
+    storage.add_file("/dir/hello.txt", "hello world");
+    let harness = Harness::start(libunftp::Server::with_mem(storage.clone())).await.unwrap();
+
+    let mut client = harness.login("anonymous", "anonymous").await.unwrap();
+    assert_eq!(client.greeting().code, 220);
+    client.cwd("dir").await.unwrap();
+    assert_eq!(client.pwd().await.unwrap(), "/dir");
+    assert_eq!(client.nlst(None).await.unwrap(), vec!["hello.txt"]);
+    assert_eq!(client.retr("hello.txt").await.unwrap(), b"hello world");
+    client.stor("upload.txt", b"uploaded").await.unwrap();
+    assert_eq!(storage.read_file("/dir/upload.txt").unwrap(), "uploaded");
+
+    let reply = client.cmd("NOOP").await.unwrap();
+    assert_eq!(reply.code, 200);
+    client.set_data_mode(DataMode::Epsv);
+    match client.mlsd(None).await {
+        Err(unftp_test_util::Error::UnexpectedReply { reply, .. }) => assert_eq!(reply.code, 500),
+        other => panic!("{:?}", other),
+    }
+    client.quit().await.unwrap();
+}
+
+#[tokio::test]
+async fn reports_failed_checks() {
+    let script = Script::new();
+    script.add(
+        Rule::path("/conformance-store-and-retrieve/file.bin")
+            .operation(Operation::Put)
+            .fail(ErrorKind::LocalError),
+    );
+    let storage = MemoryStorage::new();
+    let builder = libunftp::ServerBuilder::new(Box::new(move || Scripted::new(storage.clone(), script.clone())));
+
+    let failures = Suite::new().check(builder).await;
+    assert_eq!(failures.len(), 1, "{:?}", failures);
+    assert_eq!(failures[0].check, "store-and-retrieve");
+    assert!(failures[0].reason.contains("451"), "{}", failures[0].reason);
+}
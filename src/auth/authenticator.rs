@@ -1,6 +1,6 @@
 //! The service provider interface (SPI) for auth
 
-use super::UserDetail;
+use super::{SecretString, UserDetail};
 use crate::BoxError;
 
 use async_trait::async_trait;
@@ -78,7 +78,7 @@ impl AuthenticationError {
 #[derive(Clone, Debug)]
 pub struct Credentials {
     /// The password that the client sent.
-    pub password: Option<String>,
+    pub password: Option<SecretString>,
     /// DER encoded x509 certificate chain coming from the client.
     pub certificate_chain: Option<Vec<ClientCert>>,
     /// The IP address of the user's connection
@@ -88,7 +88,7 @@ pub struct Credentials {
 impl From<&str> for Credentials {
     fn from(s: &str) -> Self {
         Credentials {
-            password: Some(String::from(s)),
+            password: Some(SecretString::from(s)),
             certificate_chain: None,
             source_ip: [127, 0, 0, 1].into(),
         }
@@ -99,10 +99,12 @@ impl From<&str> for Credentials {
 #[derive(Clone, Eq, PartialEq)]
 pub struct ClientCert(pub Vec<u8>);
 
+#[cfg(feature = "ftps")]
 use x509_parser::prelude::parse_x509_certificate;
 
 impl ClientCert {
     /// Returns true if the Common Name from the client certificate matches the allowed_cn
+    #[cfg(feature = "ftps")]
     pub fn verify_cn(&self, allowed_cn: &str) -> Result<bool, std::io::Error> {
         let client_cert = parse_x509_certificate(&self.0);
         let subject = match client_cert {
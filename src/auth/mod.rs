@@ -45,7 +45,7 @@
 //! ```
 //! # // Make it compile
 //! # type RandomAuthenticator = libunftp::auth::AnonymousAuthenticator;
-//! let server = libunftp::Server::with_authenticator(
+//! let server = libunftp::ServerBuilder::with_authenticator(
 //!   Box::new(move || { unftp_sbe_fs::Filesystem::new("/srv/ftp") }),
 //!   std::sync::Arc::new(RandomAuthenticator{})
 //! );
@@ -62,5 +62,8 @@ pub(crate) mod authenticator;
 #[allow(unused_imports)]
 pub use authenticator::{AuthenticationError, Authenticator, ClientCert, Credentials};
 
+mod secret;
+pub use secret::SecretString;
+
 mod user;
 pub use user::{DefaultUser, UserDetail};
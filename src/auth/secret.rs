@@ -0,0 +1,52 @@
+use std::fmt::{self, Debug, Formatter};
+use zeroize::Zeroizing;
+
+/// A string that holds a secret, like the password that a client sent. Its memory is overwritten
+/// with zeros when it is dropped and it doesn't show its content when it is formatted with
+/// `Debug`, so that it doesn't end up in logs or traces.
+#[derive(Clone, Default)]
+pub struct SecretString(Zeroizing<String>);
+
+impl SecretString {
+    /// Wraps the given string, without copying it.
+    pub fn new(secret: String) -> Self {
+        SecretString(Zeroizing::new(secret))
+    }
+
+    /// Returns the secret. Take care not to copy it into places that aren't wiped.
+    pub fn expose_secret(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(secret: String) -> Self {
+        SecretString::new(secret)
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(secret: &str) -> Self {
+        SecretString::new(secret.to_string())
+    }
+}
+
+impl Debug for SecretString {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretString(***)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SecretString;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn hides_the_secret_from_debug_output() {
+        let secret = SecretString::from("s3cr3t");
+        assert_eq!("SecretString(***)", format!("{:?}", secret));
+        assert_eq!("Some(SecretString(***))", format!("{:?}", Some(secret.clone())));
+        assert_eq!("s3cr3t", secret.expose_secret());
+    }
+}
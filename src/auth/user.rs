@@ -1,5 +1,7 @@
+use crate::options::{AccessMode, CommandPolicy, Dotfiles, GeoRestriction, PassiveHost};
 use std::{
     fmt::{self, Debug, Display, Formatter},
+    ops::Range,
     path::Path,
 };
 
@@ -27,12 +29,69 @@ pub trait UserDetail: Send + Sync + Display + Debug {
     fn home(&self) -> Option<&Path> {
         None
     }
+
+    /// Returns the dotfile visibility for this user, overriding the one set with
+    /// [ServerBuilder::dotfiles](crate::ServerBuilder::dotfiles). This default implementation
+    /// returns None, meaning the server-wide setting applies.
+    fn dotfiles(&self) -> Option<Dotfiles> {
+        None
+    }
+
+    /// Returns the FTP commands this user may use, overriding the policy set with
+    /// [ServerBuilder::command_policy](crate::ServerBuilder::command_policy). This allows for
+    /// instance upload-only accounts that can't delete files. This default implementation returns
+    /// None, meaning the server-wide setting applies.
+    fn command_policy(&self) -> Option<CommandPolicy> {
+        None
+    }
+
+    /// Returns whether this user may read back what is stored, overriding the mode set with
+    /// [ServerBuilder::access_mode](crate::ServerBuilder::access_mode). This allows drop box
+    /// accounts next to regular ones. This default implementation returns None, meaning the
+    /// server-wide setting applies.
+    fn access_mode(&self) -> Option<AccessMode> {
+        None
+    }
+
+    /// Returns the message sent to this user after logging in, overriding the one set with
+    /// [ServerBuilder::login_message](crate::ServerBuilder::login_message). This default
+    /// implementation returns None, meaning the server-wide message, if any, is sent.
+    fn login_message(&self) -> Option<String> {
+        None
+    }
+
+    /// Returns the range of ports to use for passive data connections of this user, overriding
+    /// the one set with [ServerBuilder::passive_ports](crate::ServerBuilder::passive_ports). This
+    /// allows routing different users through different firewall port windows. This default
+    /// implementation returns None, meaning the server-wide setting applies.
+    ///
+    /// In proxy protocol mode the proxy needs to forward these ports as well.
+    fn passive_ports(&self) -> Option<Range<u16>> {
+        None
+    }
+
+    /// Returns the host name or IP address to send to this user in the `PASV` reply, overriding the
+    /// one set with [ServerBuilder::passive_host](crate::ServerBuilder::passive_host). This default
+    /// implementation returns None, meaning the server-wide setting applies.
+    fn passive_host(&self) -> Option<PassiveHost> {
+        None
+    }
+
+    /// Returns the countries and networks this user may log in from, replacing the server-wide
+    /// restriction of the `GeoIpPolicy` set with
+    /// [ServerBuilder::connection_policy](crate::ServerBuilder::connection_policy). The server-wide
+    /// restriction is already checked when clients connect, before they tell who they are, so it
+    /// should be at least as wide as the ones of the users. This default implementation returns
+    /// None, meaning the server-wide restriction applies.
+    fn geo_restriction(&self) -> Option<GeoRestriction> {
+        None
+    }
 }
 
 /// DefaultUser is a default implementation of the `UserDetail` trait that doesn't hold any user
 /// information. Having a default implementation like this allows for quicker prototyping with
 /// libunftp because otherwise the library user would have to implement the `UserDetail` trait first.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DefaultUser;
 
 impl UserDetail for DefaultUser {}
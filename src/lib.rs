@@ -43,12 +43,58 @@
 //! ```sh
 //! lftp -p 2121 localhost
 //! ```
+//!
+//! # Cargo features
+//!
+//! The following features are enabled by default and can be turned off for a smaller build:
+//!
+//! - `ftps`: FTPS support through rustls. Without it [`build`](ServerBuilder::build) fails when
+//!   certificates are configured and `AUTH TLS` is refused.
+//! - `prometheus`: The Prometheus metrics enabled with `ServerBuilder::metrics`.
+//! - `proxy-protocol`: Support for running behind a proxy that speaks the PROXY protocol, enabled
+//!   with `ServerBuilder::proxy_protocol_mode`.
+//!
+//! The following features are off by default:
+//!
+//! - `admin`: The `admin` module with an HTTP API to manage the running server, see
+//!   [`ServerHandle`].
+//! - `config`: The `config` module with a `ServerConfig` that can be deserialized with serde, to
+//!   configure the server from a file with `ServerBuilder::from_config`.
+//! - `test-util`: `storage::Scripted`, a wrapper around a storage back-end that delays, fails or
+//!   cuts off its operations as a test scripts it.
 pub mod auth;
+#[cfg(feature = "prometheus")]
+pub(crate) mod metrics;
+#[cfg(not(feature = "prometheus"))]
+#[path = "metrics_disabled.rs"]
 pub(crate) mod metrics;
 pub mod notification;
 pub(crate) mod server;
 pub mod storage;
 
-pub use crate::server::ftpserver::{error::ServerError, options, Server, ServerBuilder};
+#[cfg(feature = "prometheus")]
+pub use crate::metrics::MetricsCollector;
+#[cfg(feature = "admin")]
+pub use crate::server::ftpserver::admin;
+#[cfg(feature = "config")]
+pub use crate::server::ftpserver::config;
+pub use crate::server::ftpserver::{
+    error::ServerError,
+    handle::{ConfigHandle, ServerHandle},
+    health::{HealthCheck, HealthStatus},
+    middleware, options, site, Server, ServerBuilder,
+};
+pub use crate::server::sessions::{SessionContext, SessionInfo, TransferInfo, UserStats};
+
+/// Entry points for the benchmarks in `benches/`, enabled with the `bench` feature. Not part of the
+/// API.
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench {
+    /// Parses a line of the control channel into a command, telling if it is a valid one.
+    pub fn parse_command(line: bytes::Bytes) -> bool {
+        crate::server::controlchan::parse_command(line).is_ok()
+    }
+}
 
 type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
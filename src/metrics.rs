@@ -4,7 +4,13 @@ use crate::server::{Command, ControlChanError, ControlChanErrorKind, ControlChan
 
 use async_trait::async_trait;
 use lazy_static::*;
-use prometheus::{opts, register_int_counter, register_int_counter_vec, register_int_gauge, IntCounter, IntCounterVec, IntGauge};
+use prometheus::{
+    core::{Collector, Desc},
+    opts,
+    proto::MetricFamily,
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+};
+use std::time::{Duration, Instant};
 
 // Control channel middleware that adds metrics
 pub struct MetricsMiddleware<Next>
@@ -25,8 +31,13 @@ where
             add_event_metric(&event);
         }
         let (evt_type_label, evt_label) = event_to_labels(&event);
+        let is_command = matches!(event, Event::Command(_));
+        let start_time = Instant::now();
         let result: Result<Reply, ControlChanError> = self.next.handle(event).await;
         if self.collect_metrics {
+            if is_command {
+                add_duration_metric(&result, &evt_label, start_time);
+            }
             match &result {
                 Ok(reply) => add_reply_metric(reply, evt_type_label, evt_label),
                 Err(e) => add_error_metric(e.kind(), evt_type_label, evt_label),
@@ -72,6 +83,104 @@ lazy_static! {
         &["command", "status"]
     )
     .unwrap();
+    static ref FTP_COMMAND_DURATION: HistogramVec = register_histogram_vec!(
+        "ftp_command_duration_seconds",
+        "The time it took to handle commands, until the reply was known.",
+        &["command", "code"]
+    )
+    .unwrap();
+    static ref FTP_BACKEND_BYTES: IntCounterVec = register_int_counter_vec!(
+        "ftp_backend_bytes",
+        "Total number of bytes moved to (in) and from (out) the backend per operation.",
+        &["operation", "direction"]
+    )
+    .unwrap();
+    static ref FTP_DATA_CONNECTIONS: IntGauge = register_int_gauge!(opts!("ftp_data_connections", "Number of open data connections.")).unwrap();
+    static ref FTP_PASSIVE_PORTS: IntGauge =
+        register_int_gauge!(opts!("ftp_passive_ports", "Number of passive ports listening for a data connection.")).unwrap();
+    static ref FTP_CLIENTS: IntCounterVec =
+        register_int_counter_vec!("ftp_clients_total", "Total number of sessions per recognised client.", &["client"]).unwrap();
+    static ref FTP_BACKEND_OPERATION_DURATION: HistogramVec = register_histogram_vec!(
+        "ftp_backend_operation_duration_seconds",
+        "The time storage back-end operations took, as measured by storage::Instrumented.",
+        &["backend", "operation"]
+    )
+    .unwrap();
+    static ref FTP_BACKEND_OPERATION_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "ftp_backend_operation_errors_total",
+        "Total number of failed storage back-end operations, as counted by storage::Instrumented.",
+        &["backend", "operation", "kind"]
+    )
+    .unwrap();
+}
+
+// All the metrics above, for the MetricsCollector.
+fn collectors() -> [&'static dyn Collector; 20] {
+    [
+        &*FTP_AUTH_FAILURES,
+        &*FTP_SESSIONS,
+        &*FTP_SESSIONS_COUNT,
+        &*FTP_BACKEND_WRITE_BYTES,
+        &*FTP_BACKEND_READ_BYTES,
+        &*FTP_BACKEND_WRITE_FILES,
+        &*FTP_BACKEND_READ_FILES,
+        &*FTP_COMMAND_TOTAL,
+        &*FTP_REPLY_TOTAL,
+        &*FTP_ERROR_TOTAL,
+        &*FTP_SENT_BYTES,
+        &*FTP_RECEIVED_BYTES,
+        &*FTP_TRANSFERRED_TOTAL,
+        &*FTP_COMMAND_DURATION,
+        &*FTP_BACKEND_BYTES,
+        &*FTP_DATA_CONNECTIONS,
+        &*FTP_PASSIVE_PORTS,
+        &*FTP_CLIENTS,
+        &*FTP_BACKEND_OPERATION_DURATION,
+        &*FTP_BACKEND_OPERATION_ERRORS,
+    ]
+}
+
+/// A prometheus [`Collector`] for the metrics gathered by libunftp once they are enabled with
+/// [`ServerBuilder::metrics`](crate::ServerBuilder::metrics).
+///
+/// The metrics are always registered in the default prometheus registry. Embedders that use a
+/// registry of their own can register this collector in it.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::MetricsCollector;
+///
+/// let registry = prometheus::Registry::new();
+/// registry.register(Box::new(MetricsCollector::new())).unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MetricsCollector;
+
+impl MetricsCollector {
+    /// Creates the collector. All instances gather the same metrics.
+    pub fn new() -> Self {
+        MetricsCollector
+    }
+}
+
+impl Collector for MetricsCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        collectors().into_iter().flat_map(|collector| collector.desc()).collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        collectors().into_iter().flat_map(|collector| collector.collect()).collect()
+    }
+}
+
+/// Keeps a gauge increased for as long as it lives.
+pub struct GaugeGuard(&'static IntGauge);
+
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
 }
 
 /// Add a metric for an event.
@@ -112,6 +221,35 @@ pub fn inc_transferred(command: &'static str, status: &'static str) {
     FTP_TRANSFERRED_TOTAL.with_label_values(&[command, status]).inc();
 }
 
+/// Increase the amount of bytes moved to or from the storage back-end by the given operation
+pub fn inc_backend_bytes(operation: &'static str, direction: &'static str, bytes: u64) {
+    FTP_BACKEND_BYTES.with_label_values(&[operation, direction]).inc_by(bytes);
+}
+
+/// Add the time a storage back-end operation took
+pub fn add_backend_duration(backend: &str, operation: &'static str, duration: Duration) {
+    FTP_BACKEND_OPERATION_DURATION
+        .with_label_values(&[backend, operation])
+        .observe(duration.as_secs_f64());
+}
+
+/// Increase the number of failed storage back-end operations
+pub fn inc_backend_error(backend: &str, operation: &'static str, kind: &'static str) {
+    FTP_BACKEND_OPERATION_ERRORS.with_label_values(&[backend, operation, kind]).inc();
+}
+
+/// Count an open data connection until the returned guard is dropped
+pub fn track_data_connection() -> GaugeGuard {
+    FTP_DATA_CONNECTIONS.inc();
+    GaugeGuard(&FTP_DATA_CONNECTIONS)
+}
+
+/// Count a passive port that is listened on until the returned guard is dropped
+pub fn track_passive_port() -> GaugeGuard {
+    FTP_PASSIVE_PORTS.inc();
+    GaugeGuard(&FTP_PASSIVE_PORTS)
+}
+
 /// Increase the metrics gauge for client sessions
 pub fn inc_session() {
     FTP_SESSIONS.inc();
@@ -123,6 +261,11 @@ pub fn dec_session() {
     FTP_SESSIONS.dec();
 }
 
+/// Count a session of a client that was recognised
+pub fn inc_client(client: &'static str) {
+    FTP_CLIENTS.with_label_values(&[client]).inc();
+}
+
 fn add_command_metric(cmd: &Command) {
     let label = command_to_label(cmd);
     FTP_COMMAND_TOTAL.with_label_values(&[&label]).inc();
@@ -144,6 +287,18 @@ fn add_reply_metric(reply: &Reply, evt_type_label: String, evt_label: String) {
     }
 }
 
+/// Add the time it took to handle a command.
+fn add_duration_metric(result: &Result<Reply, ControlChanError>, command: &str, start_time: Instant) {
+    let code = match result {
+        Ok(Reply::CodeAndMsg { code, .. }) | Ok(Reply::MultiLine { code, .. }) => (*code as u32).to_string(),
+        Ok(Reply::None) => "none".to_string(),
+        Err(_) => "error".to_string(),
+    };
+    FTP_COMMAND_DURATION
+        .with_label_values(&[command, &code])
+        .observe(start_time.elapsed().as_secs_f64());
+}
+
 fn add_replycode_metric(code: ReplyCode, evt_type_label: String, evt_label: String) {
     let range = format!("{}xx", code as u32 / 100 % 10);
     FTP_REPLY_TOTAL.with_label_values(&[&range, &evt_type_label, &evt_label]).inc();
@@ -0,0 +1,54 @@
+//! Stands in for the metrics module when libunftp is built without the `prometheus` feature. The
+//! functions have the same signatures and do nothing.
+
+use crate::server::{ControlChanError, ControlChanMiddleware, Event, Reply};
+
+use async_trait::async_trait;
+
+// Control channel middleware that passes every event on
+pub struct MetricsMiddleware<Next>
+where
+    Next: ControlChanMiddleware,
+{
+    #[allow(dead_code)]
+    pub collect_metrics: bool,
+    pub next: Next,
+}
+
+#[async_trait]
+impl<Next> ControlChanMiddleware for MetricsMiddleware<Next>
+where
+    Next: ControlChanMiddleware,
+{
+    async fn handle(&mut self, event: Event) -> Result<Reply, ControlChanError> {
+        self.next.handle(event).await
+    }
+}
+
+pub struct GaugeGuard;
+
+pub fn inc_sent_bytes(_bytes: usize, _command: &'static str) {}
+
+pub fn inc_received_bytes(_bytes: usize, _command: &'static str) {}
+
+pub fn inc_transferred(_command: &'static str, _status: &'static str) {}
+
+pub fn inc_backend_bytes(_operation: &'static str, _direction: &'static str, _bytes: u64) {}
+
+pub fn add_backend_duration(_backend: &str, _operation: &'static str, _duration: std::time::Duration) {}
+
+pub fn inc_backend_error(_backend: &str, _operation: &'static str, _kind: &'static str) {}
+
+pub fn track_data_connection() -> GaugeGuard {
+    GaugeGuard
+}
+
+pub fn track_passive_port() -> GaugeGuard {
+    GaugeGuard
+}
+
+pub fn inc_session() {}
+
+pub fn dec_session() {}
+
+pub fn inc_client(_client: &'static str) {}
@@ -0,0 +1,218 @@
+use crate::middleware::{Middleware, MiddlewareError, Next, Request, Response};
+use crate::server::Command;
+use async_trait::async_trait;
+use chrono::{DateTime, SecondsFormat, Utc};
+use sha2::{Digest, Sha256};
+use std::io::{self, BufRead};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::SystemTime;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+// The hash that the first record of an audit trail is chained to.
+const FIRST_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// An append-only audit trail of authentication attempts and changes to the files, for
+/// deployments that need to prove what happened on the server (PCI DSS, SOX). Every record
+/// contains the SHA-256 hash of itself and the record before it, so that changing, inserting or
+/// removing a record in the middle of the file breaks the chain. Use [`AuditLog::verify`] to check
+/// the chain.
+///
+/// The audit log is a [`Middleware`], register it with
+/// [`ServerBuilder::middleware`](crate::ServerBuilder::middleware). It records `USER` and `PASS`
+/// and the commands that change files or directories: `STOR`, `APPE`, `STOU`, `DELE`, `MKD`,
+/// `RMD`, `RNFR`, `RNTO`, `CPTO`, `SYMLINK`, `CHOWN`, `SITE CHMOD` and `MDTM` when it sets a time.
+/// A record holds the time, the client address, the user, the command, its paths and the reply
+/// code. Uploads are answered when the transfer completes, so their records have `-` as the reply
+/// code and tell that the upload started. Passwords are never written.
+///
+/// Each record is a line of space separated fields:
+///
+/// ```text
+/// 2026-10-16T12:34:56.789Z 192.168.1.10:50123 "alice" DELE ["/reports/old.csv"] 250 <hash>
+/// ```
+///
+/// # Example
+///
+/// ```no_run
+/// use libunftp::Server;
+/// use libunftp::notification::AuditLog;
+/// use unftp_sbe_fs::ServerExt;
+///
+/// let server = Server::with_fs("/srv/ftp")
+///              .middleware(AuditLog::open("/var/log/unftp-audit.log").unwrap())
+///              .build();
+/// ```
+#[derive(Debug)]
+pub struct AuditLog {
+    state: Mutex<AuditState>,
+}
+
+#[derive(Debug)]
+struct AuditState {
+    file: tokio::fs::File,
+    last_hash: String,
+}
+
+impl AuditLog {
+    /// Opens the file at the given path for appending, creating it if it doesn't exist. The
+    /// records are chained to the last record in the file.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = std::fs::OpenOptions::new().create(true).append(true).read(true).open(path)?;
+        let mut last_hash = FIRST_HASH.to_string();
+        for line in io::BufReader::new(&file).lines() {
+            if let Some((_, hash)) = line?.rsplit_once(' ') {
+                last_hash = hash.to_string();
+            }
+        }
+        Ok(AuditLog {
+            state: Mutex::new(AuditState {
+                file: tokio::fs::File::from_std(file),
+                last_hash,
+            }),
+        })
+    }
+
+    /// Checks the hash chain of the audit trail at the given path. Returns the number of the first
+    /// line, counting from 1, that doesn't match its hash or the line before it, or `None` if the
+    /// whole file is intact.
+    pub fn verify<P: AsRef<Path>>(path: P) -> io::Result<Option<usize>> {
+        let file = std::fs::File::open(path)?;
+        let mut last_hash = FIRST_HASH.to_string();
+        for (index, line) in io::BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            match line.rsplit_once(' ') {
+                Some((record, hash)) if chain(&last_hash, record) == hash => last_hash = hash.to_string(),
+                _ => return Ok(Some(index + 1)),
+            }
+        }
+        Ok(None)
+    }
+
+    async fn append(&self, record: String) {
+        let mut state = self.state.lock().await;
+        let hash = chain(&state.last_hash, &record);
+        let line = format!("{} {}\n", record, hash);
+        // Like the other listeners, a record that can't be written doesn't fail the command. The
+        // chain only moves on when the record was written, so that it stays verifiable.
+        if state.file.write_all(line.as_bytes()).await.is_ok() {
+            let _ = state.file.flush().await;
+            state.last_hash = hash;
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware for AuditLog {
+    async fn handle(&self, request: Request, next: Next<'_>) -> Result<Response, MiddlewareError> {
+        let Some(arguments) = audited(&request.command) else {
+            return next.run(request).await;
+        };
+        let time = SystemTime::now();
+        let user = match &request.command {
+            Command::User { username } => Some(String::from_utf8_lossy(username).into_owned()),
+            _ => request.username.clone(),
+        };
+        let verb = request.verb().to_string();
+        let source = request.source;
+        let arguments = arguments.unwrap_or_else(|| request.paths());
+        let result = next.run(request).await;
+        let code = match &result {
+            Ok(response) => response.code().map_or_else(|| "-".to_string(), |code| code.to_string()),
+            Err(_) => "error".to_string(),
+        };
+        self.append(format_record(time, source, user.as_deref(), &verb, &arguments, &code)).await;
+        result
+    }
+}
+
+// Tells if the command is recorded in the audit trail. If it is, the arguments to record are
+// returned when they aren't the paths of the command.
+fn audited(command: &Command) -> Option<Option<Vec<String>>> {
+    match command {
+        Command::User { .. } | Command::Pass { .. } => Some(Some(vec![])),
+        Command::Stor { .. }
+        | Command::Stou
+        | Command::Dele { .. }
+        | Command::Mkd { .. }
+        | Command::Rmd { .. }
+        | Command::Rnfr { .. }
+        | Command::Rnto { .. }
+        | Command::Cpto { .. }
+        | Command::Symlink { .. }
+        | Command::Chown { .. }
+        | Command::Mdtm { modified: Some(_), .. } => Some(None),
+        Command::Other { command_name, arguments } if command_name.eq_ignore_ascii_case("APPE") => Some(Some(vec![arguments.clone()])),
+        Command::Site { name, arguments } if name.eq_ignore_ascii_case("CHMOD") => Some(Some(vec![arguments.clone()])),
+        _ => None,
+    }
+}
+
+fn format_record(time: SystemTime, source: SocketAddr, user: Option<&str>, verb: &str, arguments: &[String], code: &str) -> String {
+    format!(
+        "{} {} {} {} {:?} {}",
+        DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Millis, true),
+        source,
+        // Quoted, so that spaces in names and paths can't be mistaken for field separators.
+        user.map_or_else(|| "-".to_string(), |user| format!("{:?}", user)),
+        verb,
+        arguments,
+        code
+    )
+}
+
+// The hash of a record, chained to the hash of the record before it.
+fn chain(last_hash: &str, record: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(last_hash.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(record.as_bytes());
+    hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_record, AuditLog};
+    use pretty_assertions::assert_eq;
+    use std::time::{Duration, SystemTime};
+
+    fn record(verb: &str, code: &str) -> String {
+        // Fri Oct 16 12:34:56 2026 UTC
+        let time = SystemTime::UNIX_EPOCH + Duration::from_millis(1_792_154_096_789);
+        format_record(
+            time,
+            "192.168.1.10:50123".parse().unwrap(),
+            Some("alice"),
+            verb,
+            &["/reports/march 2026.csv".to_string()],
+            code,
+        )
+    }
+
+    #[test]
+    fn formats_records() {
+        assert_eq!(
+            r#"2026-10-16T12:34:56.789Z 192.168.1.10:50123 "alice" DELE ["/reports/march 2026.csv"] 250"#,
+            record("DELE", "250")
+        );
+    }
+
+    #[tokio::test]
+    async fn detects_changed_records() {
+        let path = std::env::temp_dir().join(format!("libunftp-audit-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        AuditLog::open(&path).unwrap().append(record("DELE", "250")).await;
+        // A reopened log continues the chain.
+        let log = AuditLog::open(&path).unwrap();
+        log.append(record("RMD", "550")).await;
+        log.append(record("MKD", "257")).await;
+        assert_eq!(None, AuditLog::verify(&path).unwrap());
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, content.replacen(" RMD ", " MKD ", 1)).unwrap();
+        let verified = AuditLog::verify(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(Some(2), verified);
+    }
+}
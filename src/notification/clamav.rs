@@ -0,0 +1,182 @@
+//! An [`UploadScanner`] that streams uploads to a ClamAV daemon (`clamd`) using its `INSTREAM`
+//! command.
+
+use super::hook::{UploadRejection, UploadScanner};
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::{future::Future, time::Duration};
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+    sync::mpsc::Receiver,
+};
+
+// The time allowed for every interaction with clamd unless set otherwise.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Scans uploads for viruses with a ClamAV daemon. The data is streamed to clamd while the upload is
+/// received, so scanning doesn't need an extra pass over the stored file. Uploads in which clamd
+/// finds a virus fail with a `451` reply, and so do uploads that could not be scanned, for instance
+/// because clamd is unreachable or the upload exceeds its `StreamMaxLength`.
+///
+/// Requires the `clamav` feature.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::Server;
+/// use libunftp::notification::ClamAvScanner;
+/// use unftp_sbe_fs::ServerExt;
+///
+/// let server = Server::with_fs("/tmp")
+///              .upload_scanner(ClamAvScanner::new("localhost:3310"))
+///              .build();
+/// ```
+#[derive(Debug, Clone)]
+pub struct ClamAvScanner {
+    address: String,
+    timeout: Duration,
+}
+
+impl ClamAvScanner {
+    /// Creates a scanner that talks to clamd at the given address. This is either a TCP address in
+    /// `host:port` form or, when it starts with a `/`, the path of a Unix domain socket. Unix domain
+    /// sockets are only supported on Unix, elsewhere every upload fails the scan.
+    pub fn new<A: Into<String>>(address: A) -> Self {
+        ClamAvScanner {
+            address: address.into(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Sets the time allowed for connecting to clamd, for every chunk of data sent to it and for
+    /// its verdict once the upload ended. Defaults to 30 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    async fn timed<T, F>(&self, future: F) -> Result<T, UploadRejection>
+    where
+        F: Future<Output = std::io::Result<T>>,
+    {
+        match tokio::time::timeout(self.timeout, future).await {
+            Ok(Ok(result)) => Ok(result),
+            Ok(Err(_)) | Err(_) => Err(UploadRejection::new("Virus scanner unavailable")),
+        }
+    }
+
+    async fn instream<S>(&self, mut stream: S, mut data: Receiver<Bytes>) -> Result<(), UploadRejection>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        self.timed(stream.write_all(b"zINSTREAM\0")).await?;
+        while let Some(chunk) = data.recv().await {
+            let length = (chunk.len() as u32).to_be_bytes();
+            let sent = self
+                .timed(async {
+                    stream.write_all(&length).await?;
+                    stream.write_all(&chunk).await
+                })
+                .await;
+            if sent.is_err() {
+                // Clamd closes the connection when the stream exceeds its size limit. It still
+                // tells why, so go and read the reply.
+                break;
+            }
+        }
+        let _ = self.timed(stream.write_all(&0u32.to_be_bytes())).await;
+
+        let mut reply = Vec::new();
+        self.timed(stream.read_to_end(&mut reply)).await?;
+        verdict(&String::from_utf8_lossy(&reply))
+    }
+}
+
+// Interprets the reply of clamd to a z-prefixed INSTREAM command, e.g. `stream: OK\0` or
+// `stream: Eicar-Test-Signature FOUND\0`.
+fn verdict(reply: &str) -> Result<(), UploadRejection> {
+    let reply = reply.trim_end_matches('\0').trim();
+    let result = reply.strip_prefix("stream:").map(str::trim);
+    match result {
+        Some("OK") => Ok(()),
+        Some(result) if result.ends_with("FOUND") => Err(UploadRejection::new(format!("Virus found: {}", result.trim_end_matches("FOUND").trim()))),
+        _ => Err(UploadRejection::new("Virus scanner failed to scan the upload")),
+    }
+}
+
+#[async_trait]
+impl UploadScanner for ClamAvScanner {
+    async fn scan(&self, _path: String, data: Receiver<Bytes>) -> Result<(), UploadRejection> {
+        if self.address.starts_with('/') {
+            #[cfg(unix)]
+            {
+                let stream = self.timed(UnixStream::connect(&self.address)).await?;
+                self.instream(stream, data).await
+            }
+            #[cfg(not(unix))]
+            {
+                drop(data);
+                Err(UploadRejection::new("Virus scanner unavailable"))
+            }
+        } else {
+            let stream = self.timed(TcpStream::connect(&self.address)).await?;
+            self.instream(stream, data).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn interprets_clamd_replies() {
+        assert!(verdict("stream: OK\0").is_ok());
+        assert_eq!(
+            verdict("stream: Eicar-Test-Signature FOUND\0").unwrap_err().to_string(),
+            "Virus found: Eicar-Test-Signature"
+        );
+        assert!(verdict("INSTREAM size limit exceeded. ERROR\0").is_err());
+        assert!(verdict("").is_err());
+    }
+
+    #[tokio::test]
+    async fn streams_chunks_to_clamd() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let scanner = ClamAvScanner::new(listener.local_addr().unwrap().to_string());
+        let clamd = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut command = [0u8; 10];
+            socket.read_exact(&mut command).await.unwrap();
+            let mut received = Vec::new();
+            loop {
+                let length = socket.read_u32().await.unwrap() as usize;
+                if length == 0 {
+                    break;
+                }
+                let mut chunk = vec![0u8; length];
+                socket.read_exact(&mut chunk).await.unwrap();
+                received.extend(chunk);
+            }
+            socket.write_all(b"stream: Eicar-Test-Signature FOUND\0").await.unwrap();
+            (command, received)
+        });
+
+        let (tx, rx) = tokio::sync::mpsc::channel(2);
+        let scan = tokio::spawn(async move { scanner.scan("/upload.txt".to_string(), rx).await });
+        tx.send(Bytes::from_static(b"hello ")).await.unwrap();
+        tx.send(Bytes::from_static(b"world")).await.unwrap();
+        drop(tx);
+
+        let rejection = scan.await.unwrap().unwrap_err();
+        let (command, received) = clamd.await.unwrap();
+        assert_eq!(&command, b"zINSTREAM\0");
+        assert_eq!(received, b"hello world");
+        assert_eq!(rejection.to_string(), "Virus found: Eicar-Test-Signature");
+    }
+}
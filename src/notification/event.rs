@@ -1,6 +1,8 @@
 use async_trait::async_trait;
 use std::fmt::Debug;
 use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
 
 /// An event pertaining to a client's login and logout actions in order to allow detection of the
 /// presence of a client. Instances of these will be passed to an [`PresenceListener`](crate::notification::PresenceListener).
@@ -24,6 +26,14 @@ pub enum DataEvent {
 
         /// The amount of bytes transferred to the client
         bytes: u64,
+
+        /// Identifies the transfer. The messages about the transfer are logged with it as
+        /// `request-id`, so that the event can be correlated with the logs.
+        request_id: String,
+
+        /// The attributes of the file as provided by the storage back-end, if the listener asked
+        /// for them with [`DataListener::file_attributes`].
+        attributes: Vec<(String, String)>,
     },
     /// A STOR command finished successfully
     Put {
@@ -32,6 +42,42 @@ pub enum DataEvent {
 
         /// The amount of bytes stored
         bytes: u64,
+
+        /// How long the transfer took
+        duration: Duration,
+
+        /// The checksum of the uploaded data if [`ServerBuilder::upload_checksum`](crate::ServerBuilder::upload_checksum)
+        /// is enabled. Not available for resumed uploads.
+        checksum: Option<String>,
+
+        /// Identifies the transfer. The messages about the transfer are logged with it as
+        /// `request-id`, so that the event can be correlated with the logs.
+        request_id: String,
+
+        /// The attributes of the stored file as provided by the storage back-end, like the
+        /// generation of a cloud storage object, if the listener asked for them with
+        /// [`DataListener::file_attributes`]. Together with the `request_id` they allow downstream
+        /// systems to process every upload exactly once.
+        attributes: Vec<(String, String)>,
+    },
+    /// A transfer is in progress. These are only sent when enabled with
+    /// [`ServerBuilder::transfer_progress_interval`](crate::ServerBuilder::transfer_progress_interval),
+    /// at the interval set there.
+    Progress {
+        /// The FTP command that started the transfer, like `RETR` or `STOR`.
+        command: String,
+
+        /// The path that is transferred or listed
+        path: String,
+
+        /// The amount of bytes transferred so far
+        bytes: u64,
+
+        /// How long the transfer has been running
+        duration: Duration,
+
+        /// The average transfer rate so far
+        bytes_per_second: u64,
     },
     /// A DEL command finished successfully
     Deleted {
@@ -55,6 +101,13 @@ pub enum DataEvent {
         /// The new path
         to: String,
     },
+    /// A SITE CPFR & SITE CPTO command sequence finished successfully.
+    Copied {
+        /// The path of the original file
+        from: String,
+        /// The path of the copy
+        to: String,
+    },
 }
 
 /// Metadata relating to an event that can be used to to identify the user and session. A sequence
@@ -77,6 +130,23 @@ pub trait DataListener: Sync + Send + Debug {
     /// Called after the event happened. Event metadata is also passed to allow pinpointing the user
     /// session for which it happened.
     async fn receive_data_event(&self, e: DataEvent, m: EventMeta);
+
+    /// Called instead of [`receive_data_event`](DataListener::receive_data_event) when the
+    /// sessions wait for the events to be delivered, see
+    /// [`ServerBuilder::event_delivery`](crate::ServerBuilder::event_delivery). An error tells that
+    /// the event wasn't handled, so that it is retried or the transfer fails. The default
+    /// implementation calls `receive_data_event` and always succeeds.
+    async fn try_receive_data_event(&self, e: DataEvent, m: EventMeta) -> Result<(), EventError> {
+        self.receive_data_event(e, m).await;
+        Ok(())
+    }
+
+    /// Tells if the [`DataEvent::Got`] and [`DataEvent::Put`] events should include the
+    /// [attributes](crate::storage::Metadata::attributes) of the file. This costs a metadata lookup
+    /// on the storage back-end after every transfer, so it is off by default.
+    fn file_attributes(&self) -> bool {
+        false
+    }
 }
 
 /// A listener for [`PresenceEvent`](crate::notification::PresenceEvent)s. Implementations can
@@ -87,6 +157,31 @@ pub trait PresenceListener: Sync + Send + Debug {
     /// Called after the event happened. Event metadata is also passed to allow pinpointing the user
     /// session for which it happened.
     async fn receive_presence_event(&self, e: PresenceEvent, m: EventMeta);
+
+    /// Called instead of [`receive_presence_event`](PresenceListener::receive_presence_event) when
+    /// the sessions wait for the events to be delivered, see
+    /// [`ServerBuilder::event_delivery`](crate::ServerBuilder::event_delivery). An error tells that
+    /// the event wasn't handled, so that it is retried. The default implementation calls
+    /// `receive_presence_event` and always succeeds.
+    async fn try_receive_presence_event(&self, e: PresenceEvent, m: EventMeta) -> Result<(), EventError> {
+        self.receive_presence_event(e, m).await;
+        Ok(())
+    }
+}
+
+/// Returned by [`DataListener::try_receive_data_event`] and
+/// [`PresenceListener::try_receive_presence_event`] when the event couldn't be handled.
+#[derive(Debug, Error)]
+#[error("{message}")]
+pub struct EventError {
+    message: String,
+}
+
+impl EventError {
+    /// Creates a new error with a message that tells what went wrong.
+    pub fn new<M: Into<String>>(message: M) -> Self {
+        EventError { message: message.into() }
+    }
 }
 
 #[async_trait]
@@ -94,6 +189,14 @@ impl DataListener for Box<dyn DataListener> {
     async fn receive_data_event(&self, e: DataEvent, m: EventMeta) {
         self.as_ref().receive_data_event(e, m).await
     }
+
+    async fn try_receive_data_event(&self, e: DataEvent, m: EventMeta) -> Result<(), EventError> {
+        self.as_ref().try_receive_data_event(e, m).await
+    }
+
+    fn file_attributes(&self) -> bool {
+        self.as_ref().file_attributes()
+    }
 }
 
 #[async_trait]
@@ -101,6 +204,10 @@ impl PresenceListener for Box<dyn PresenceListener> {
     async fn receive_presence_event(&self, e: PresenceEvent, m: EventMeta) {
         self.as_ref().receive_presence_event(e, m).await
     }
+
+    async fn try_receive_presence_event(&self, e: PresenceEvent, m: EventMeta) -> Result<(), EventError> {
+        self.as_ref().try_receive_presence_event(e, m).await
+    }
 }
 
 #[async_trait]
@@ -108,6 +215,14 @@ impl DataListener for Arc<dyn DataListener> {
     async fn receive_data_event(&self, e: DataEvent, m: EventMeta) {
         self.as_ref().receive_data_event(e, m).await
     }
+
+    async fn try_receive_data_event(&self, e: DataEvent, m: EventMeta) -> Result<(), EventError> {
+        self.as_ref().try_receive_data_event(e, m).await
+    }
+
+    fn file_attributes(&self) -> bool {
+        self.as_ref().file_attributes()
+    }
 }
 
 #[async_trait]
@@ -115,4 +230,8 @@ impl PresenceListener for Arc<dyn PresenceListener> {
     async fn receive_presence_event(&self, e: PresenceEvent, m: EventMeta) {
         self.as_ref().receive_presence_event(e, m).await
     }
+
+    async fn try_receive_presence_event(&self, e: PresenceEvent, m: EventMeta) -> Result<(), EventError> {
+        self.as_ref().try_receive_presence_event(e, m).await
+    }
 }
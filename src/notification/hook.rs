@@ -0,0 +1,122 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::fmt::Debug;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Describes an upload that was written to the storage back-end but not yet confirmed to the
+/// client. Instances of these are passed to an [`UploadHook`](crate::notification::UploadHook).
+#[derive(Debug, Clone)]
+pub struct CompletedUpload {
+    /// The user that uploaded the file.
+    pub username: String,
+    /// Identifies the session in which the file was uploaded.
+    pub trace_id: String,
+    /// The absolute path of the file on the storage back-end, that is with the client's working
+    /// directory taken into account.
+    pub path: String,
+    /// Where the data is stored while the hook runs: a [temporary name](crate::storage::temp_upload_path)
+    /// next to `path`. The file is renamed to `path` once the hook approved it.
+    pub staged_path: String,
+    /// The amount of bytes stored.
+    pub bytes: u64,
+    /// How long the transfer took.
+    pub duration: Duration,
+    /// The checksum of the uploaded data if [`ServerBuilder::upload_checksum`](crate::ServerBuilder::upload_checksum)
+    /// is enabled. Not available for resumed uploads.
+    pub checksum: Option<String>,
+}
+
+/// Returned by an [`UploadHook`] or an [`UploadScanner`] to reject an upload. The message is sent to
+/// the client along with a `550` reply code in case of the hook, or `451` in case of the scanner.
+#[derive(Debug, Error)]
+#[error("{message}")]
+pub struct UploadRejection {
+    message: String,
+}
+
+impl UploadRejection {
+    /// Creates a new rejection with the message that should be shown to the client.
+    pub fn new<M: Into<String>>(message: M) -> Self {
+        UploadRejection { message: message.into() }
+    }
+}
+
+/// A hook that gets to inspect every upload after it was written to the storage back-end but before
+/// the client receives the `226` reply, for instance to scan it for viruses. Unlike the
+/// [`DataListener`](crate::notification::DataListener) it is awaited by the session, so it can
+/// reject the upload: the file is then deleted and the client receives a `550` reply instead.
+///
+/// The upload is stored under a temporary name until the hook approved it, so a file that existed
+/// under the name before is only replaced by uploads that passed. Since the hook has to see the
+/// whole file, uploads can't be resumed with `REST` while a hook is set.
+///
+/// Implementations can be passed to [`ServerBuilder::upload_hook`](crate::ServerBuilder::upload_hook).
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::notification::{CompletedUpload, UploadHook, UploadRejection};
+/// use async_trait::async_trait;
+///
+/// #[derive(Debug)]
+/// struct NoEmptyFiles;
+///
+/// #[async_trait]
+/// impl UploadHook for NoEmptyFiles {
+///     async fn pre_complete(&self, upload: &CompletedUpload) -> Result<(), UploadRejection> {
+///         if upload.bytes == 0 {
+///             return Err(UploadRejection::new("Empty files are not accepted"));
+///         }
+///         Ok(())
+///     }
+/// }
+/// ```
+#[async_trait]
+pub trait UploadHook: Sync + Send + Debug {
+    /// Called after the upload was stored. Returning an error rejects it.
+    async fn pre_complete(&self, upload: &CompletedUpload) -> Result<(), UploadRejection>;
+}
+
+/// Scans the data of uploads while it is being received, for instance by streaming it to a virus
+/// scanner. The data is handed to the scanner as the storage back-end consumes it, but the upload
+/// only completes once the scanner approved it. If it doesn't, the transfer fails, the stored file is
+/// deleted and the client receives a `451` reply.
+///
+/// Like with an [`UploadHook`] the upload is stored under a temporary name until it passed, and
+/// uploads can't be resumed with `REST` since the scanner would only see the resumed part.
+///
+/// Implementations can be passed to [`ServerBuilder::upload_scanner`](crate::ServerBuilder::upload_scanner).
+/// With the `clamav` feature enabled libunftp ships with `ClamAvScanner`, which uses a ClamAV daemon, and
+/// with the `icap` feature with `IcapScanner`, which uses an ICAP server.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::notification::{UploadRejection, UploadScanner};
+/// use async_trait::async_trait;
+/// use bytes::Bytes;
+/// use tokio::sync::mpsc::Receiver;
+///
+/// #[derive(Debug)]
+/// struct NoExecutables;
+///
+/// #[async_trait]
+/// impl UploadScanner for NoExecutables {
+///     async fn scan(&self, _path: String, mut data: Receiver<Bytes>) -> Result<(), UploadRejection> {
+///         if let Some(chunk) = data.recv().await {
+///             if chunk.starts_with(b"\x7fELF") {
+///                 return Err(UploadRejection::new("Executables are not accepted"));
+///             }
+///         }
+///         Ok(())
+///     }
+/// }
+/// ```
+#[async_trait]
+pub trait UploadScanner: Sync + Send + Debug {
+    /// Called when an upload starts, with the absolute path of the file on the storage back-end. The
+    /// data arrives in chunks on the receiver, which is closed once the upload ended. The scanner may
+    /// return before that, the remaining data then isn't passed to it.
+    async fn scan(&self, path: String, data: tokio::sync::mpsc::Receiver<Bytes>) -> Result<(), UploadRejection>;
+}
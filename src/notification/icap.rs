@@ -0,0 +1,255 @@
+//! An [`UploadScanner`] that streams uploads to an ICAP server (RFC 3507) in a `RESPMOD` request.
+
+use super::hook::{UploadRejection, UploadScanner};
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::{future::Future, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::mpsc::Receiver,
+};
+
+// The time allowed for every interaction with the ICAP server unless set otherwise.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+// The port of ICAP servers unless the URL says otherwise.
+const DEFAULT_PORT: u16 = 1344;
+
+// The most we read of the reply headers before giving up on the reply.
+const MAX_REPLY_HEADERS: usize = 64 * 1024;
+
+/// Scans uploads for viruses with an ICAP server, for instance c-icap with squidclamav or one of
+/// the commercial scanners that speak ICAP. The upload is sent as the body of an HTTP response in a
+/// `RESPMOD` request while it is received, so scanning doesn't need an extra pass over the stored
+/// file.
+///
+/// Uploads that the server answers with `204 No Content` pass. A `200 OK` means the server wants to
+/// replace the content, which scanners do for infected files: these uploads fail with a `451`
+/// reply, showing the threat from the `X-Infection-Found` or `X-Virus-ID` header if there is one.
+/// So do uploads that could not be scanned, for instance because the server is unreachable.
+///
+/// Requires the `icap` feature.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::Server;
+/// use libunftp::notification::IcapScanner;
+/// use unftp_sbe_fs::ServerExt;
+///
+/// let server = Server::with_fs("/tmp")
+///              .upload_scanner(IcapScanner::new("icap://localhost:1344/avscan"))
+///              .build();
+/// ```
+#[derive(Debug, Clone)]
+pub struct IcapScanner {
+    url: String,
+    timeout: Duration,
+}
+
+impl IcapScanner {
+    /// Creates a scanner that sends uploads to the ICAP service at the given URL, e.g.
+    /// `icap://localhost:1344/avscan`. The port defaults to 1344.
+    pub fn new<U: Into<String>>(url: U) -> Self {
+        IcapScanner {
+            url: url.into(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Sets the time allowed for connecting to the ICAP server, for every chunk of data sent to it
+    /// and for its reply once the upload ended. Defaults to 30 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    async fn timed<T, F>(&self, future: F) -> Result<T, UploadRejection>
+    where
+        F: Future<Output = std::io::Result<T>>,
+    {
+        match tokio::time::timeout(self.timeout, future).await {
+            Ok(Ok(result)) => Ok(result),
+            Ok(Err(_)) | Err(_) => Err(UploadRejection::new("Virus scanner unavailable")),
+        }
+    }
+
+    async fn respmod(&self, host: &str, address: &str, path: &str, mut data: Receiver<Bytes>) -> Result<(), UploadRejection> {
+        let mut stream = self.timed(TcpStream::connect(address)).await?;
+        self.timed(stream.write_all(respmod_header(&self.url, host, path).as_bytes())).await?;
+        while let Some(chunk) = data.recv().await {
+            if chunk.is_empty() {
+                continue;
+            }
+            let sent = self
+                .timed(async {
+                    stream.write_all(format!("{:x}\r\n", chunk.len()).as_bytes()).await?;
+                    stream.write_all(&chunk).await?;
+                    stream.write_all(b"\r\n").await
+                })
+                .await;
+            if sent.is_err() {
+                // The server may reply before it read the whole body, so go and read the reply.
+                break;
+            }
+        }
+        let _ = self.timed(stream.write_all(b"0\r\n\r\n")).await;
+
+        let mut reply = Vec::new();
+        let mut buffer = [0u8; 4096];
+        while !reply.windows(4).any(|window| window == b"\r\n\r\n") && reply.len() < MAX_REPLY_HEADERS {
+            let read = self.timed(stream.read(&mut buffer)).await?;
+            if read == 0 {
+                break;
+            }
+            reply.extend_from_slice(&buffer[..read]);
+        }
+        verdict(&String::from_utf8_lossy(&reply))
+    }
+}
+
+// Splits an ICAP URL into the host for the Host header and the address to connect to.
+fn server(url: &str) -> Option<(String, String)> {
+    let authority = url.strip_prefix("icap://")?.split('/').next()?;
+    if authority.is_empty() {
+        return None;
+    }
+    let has_port = match authority.rfind(':') {
+        Some(colon) => !authority[colon..].contains(']'),
+        None => false,
+    };
+    let address = match has_port {
+        true => authority.to_string(),
+        false => format!("{}:{}", authority, DEFAULT_PORT),
+    };
+    Some((authority.to_string(), address))
+}
+
+// The ICAP request headers followed by the encapsulated HTTP request and response headers. The
+// upload's path is passed as the URL of the HTTP request, so that it shows up in the logs of the
+// scanner.
+fn respmod_header(url: &str, host: &str, path: &str) -> String {
+    let http_request = format!("GET {} HTTP/1.1\r\nHost: ftp\r\n\r\n", encode_path(path));
+    let http_response = "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\n\r\n";
+    format!(
+        "RESPMOD {} ICAP/1.0\r\nHost: {}\r\nAllow: 204\r\nEncapsulated: req-hdr=0, res-hdr={}, res-body={}\r\n\r\n{}{}",
+        url,
+        host,
+        http_request.len(),
+        http_request.len() + http_response.len(),
+        http_request,
+        http_response
+    )
+}
+
+// Percent-encodes everything but the characters that may appear in a URL path as they are.
+fn encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'.' | b'_' | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+// Interprets the status line and headers of the ICAP server's reply.
+fn verdict(reply: &str) -> Result<(), UploadRejection> {
+    let mut lines = reply.split("\r\n");
+    let status = lines.next().unwrap_or_default().split_whitespace().nth(1);
+    match status {
+        Some("204") => Ok(()),
+        Some("200") => {
+            let threat = lines.take_while(|line| !line.is_empty()).find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                match name.trim().to_ascii_lowercase().as_str() {
+                    "x-infection-found" => value.split(';').find_map(|field| field.trim().strip_prefix("Threat=")).map(str::to_string),
+                    "x-virus-id" => Some(value.trim().to_string()),
+                    _ => None,
+                }
+            });
+            match threat {
+                Some(threat) => Err(UploadRejection::new(format!("Virus found: {}", threat))),
+                None => Err(UploadRejection::new("Virus found")),
+            }
+        }
+        _ => Err(UploadRejection::new("Virus scanner failed to scan the upload")),
+    }
+}
+
+#[async_trait]
+impl UploadScanner for IcapScanner {
+    async fn scan(&self, path: String, data: Receiver<Bytes>) -> Result<(), UploadRejection> {
+        match server(&self.url) {
+            Some((host, address)) => self.respmod(&host, &address, &path, data).await,
+            None => Err(UploadRejection::new("Virus scanner unavailable")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn finds_the_server_in_the_url() {
+        assert_eq!(server("icap://scanner/avscan"), Some(("scanner".to_string(), "scanner:1344".to_string())));
+        assert_eq!(
+            server("icap://scanner:1345/avscan"),
+            Some(("scanner:1345".to_string(), "scanner:1345".to_string()))
+        );
+        assert_eq!(server("icap://[::1]/avscan"), Some(("[::1]".to_string(), "[::1]:1344".to_string())));
+        assert_eq!(server("http://scanner/avscan"), None);
+    }
+
+    #[test]
+    fn interprets_icap_replies() {
+        assert!(verdict("ICAP/1.0 204 No Content\r\nISTag: \"1\"\r\n\r\n").is_ok());
+        assert_eq!(
+            verdict("ICAP/1.0 200 OK\r\nX-Infection-Found: Type=0; Resolution=2; Threat=Eicar-Test-Signature;\r\n\r\n")
+                .unwrap_err()
+                .to_string(),
+            "Virus found: Eicar-Test-Signature"
+        );
+        assert_eq!(
+            verdict("ICAP/1.0 200 OK\r\nX-Virus-ID: EICAR\r\n\r\n").unwrap_err().to_string(),
+            "Virus found: EICAR"
+        );
+        assert_eq!(verdict("ICAP/1.0 200 OK\r\n\r\n").unwrap_err().to_string(), "Virus found");
+        assert!(verdict("ICAP/1.0 500 Server Error\r\n\r\n").is_err());
+        assert!(verdict("").is_err());
+    }
+
+    #[tokio::test]
+    async fn streams_chunks_in_a_respmod_request() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let scanner = IcapScanner::new(format!("icap://{}/avscan", listener.local_addr().unwrap()));
+        let icap = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut received = Vec::new();
+            while !received.ends_with(b"0\r\n\r\n") {
+                let mut buffer = [0u8; 1024];
+                let read = socket.read(&mut buffer).await.unwrap();
+                received.extend_from_slice(&buffer[..read]);
+            }
+            socket.write_all(b"ICAP/1.0 204 No Content\r\n\r\n").await.unwrap();
+            String::from_utf8(received).unwrap()
+        });
+
+        let (tx, rx) = tokio::sync::mpsc::channel(2);
+        let scan = tokio::spawn(async move { scanner.scan("/my upload.txt".to_string(), rx).await });
+        tx.send(Bytes::from_static(b"hello ")).await.unwrap();
+        tx.send(Bytes::from_static(b"world")).await.unwrap();
+        drop(tx);
+
+        assert!(scan.await.unwrap().is_ok());
+        let request = icap.await.unwrap();
+        assert!(request.starts_with("RESPMOD icap://127.0.0.1:"), "{}", request);
+        assert!(request.contains("GET /my%20upload.txt HTTP/1.1\r\n"), "{}", request);
+        assert!(request.ends_with("\r\n\r\n6\r\nhello \r\n5\r\nworld\r\n0\r\n\r\n"), "{}", request);
+    }
+}
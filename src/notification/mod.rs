@@ -9,8 +9,50 @@
 //! trait and use the [`ServerBuilder::notify_presence`](crate::ServerBuilder::notify_data) method
 //! to make libunftp use it.
 //!
+//! To inspect and possibly reject uploads before the client is told they completed implement the
+//! [`UploadHook`] trait and use the [`ServerBuilder::upload_hook`](crate::ServerBuilder::upload_hook)
+//! method. To inspect uploads while they are being received, for instance to scan them for
+//! viruses, implement the [`UploadScanner`] trait and use the
+//! [`ServerBuilder::upload_scanner`](crate::ServerBuilder::upload_scanner) method. With the
+//! `clamav` feature enabled, the `ClamAvScanner` does this with a ClamAV daemon, and with the `icap`
+//! feature enabled the `IcapScanner` does it with an ICAP server.
+//!
+//! To keep a log of completed transfers implement the [`TransferLogListener`] trait and use the
+//! [`ServerBuilder::transfer_log`](crate::ServerBuilder::transfer_log) method. The
+//! [`XferLogFile`] writes them to a file in the xferlog format of wu-ftpd.
+//!
+//! With the `webhook` feature enabled, the `WebhookNotifier` is a [`DataListener`] that posts the
+//! events that change files to an HTTP endpoint. With the `pubsub` feature enabled, the
+//! `PubSubPublisher` publishes the data and presence events to a Google Cloud Pub/Sub topic.
+//!
+//! To keep a tamper-evident audit trail of authentication attempts and file changes register an
+//! [`AuditLog`] with [`ServerBuilder::middleware`](crate::ServerBuilder::middleware).
+//!
 
+pub(crate) mod audit;
+#[cfg(feature = "clamav")]
+pub(crate) mod clamav;
 pub(crate) mod event;
+pub(crate) mod hook;
+#[cfg(feature = "icap")]
+pub(crate) mod icap;
 pub(crate) mod nop;
+#[cfg(feature = "pubsub")]
+pub(crate) mod pubsub;
+#[cfg(feature = "webhook")]
+pub(crate) mod webhook;
+pub(crate) mod xferlog;
+
+pub use audit::AuditLog;
+pub use event::{DataEvent, DataListener, EventError, EventMeta, PresenceEvent, PresenceListener};
+pub use hook::{CompletedUpload, UploadHook, UploadRejection, UploadScanner};
+pub use xferlog::{TransferDirection, TransferLogListener, TransferRecord, XferLogFile};
 
-pub use event::{DataEvent, DataListener, EventMeta, PresenceEvent, PresenceListener};
+#[cfg(feature = "clamav")]
+pub use clamav::ClamAvScanner;
+#[cfg(feature = "icap")]
+pub use icap::IcapScanner;
+#[cfg(feature = "pubsub")]
+pub use pubsub::{PubSubAuth, PubSubPublisher};
+#[cfg(feature = "webhook")]
+pub use webhook::WebhookNotifier;
@@ -0,0 +1,586 @@
+//! A [`DataListener`] and [`PresenceListener`] that publishes the events to a Google Cloud Pub/Sub
+//! topic.
+
+use super::event::{DataEvent, DataListener, EventMeta, PresenceEvent, PresenceListener};
+use async_trait::async_trait;
+use base64::Engine;
+use chrono::{SecondsFormat, Utc};
+use hyper::{client::HttpConnector, http::header, Body, Client, Method, Request, StatusCode};
+use hyper_rustls::HttpsConnector;
+use serde_json::{json, Value};
+use slog::Drain;
+use std::{
+    collections::VecDeque,
+    fmt, io,
+    path::PathBuf,
+    sync::{Arc, OnceLock},
+    time::{Duration, Instant},
+};
+use tokio::{io::AsyncWriteExt, sync::mpsc};
+use yup_oauth2::{authenticator::Authenticator, ServiceAccountAuthenticator};
+
+type HttpClient = Client<HttpsConnector<HttpConnector>>;
+
+const PUBSUB_SCOPE: &str = "https://www.googleapis.com/auth/pubsub";
+
+// The longest time to wait before trying to publish again.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// How the [`PubSubPublisher`] authenticates with Google Cloud.
+#[derive(Clone, PartialEq, Eq)]
+pub enum PubSubAuth {
+    /// Doesn't authenticate, for the Pub/Sub emulator.
+    None,
+    /// Authenticates with the JSON key of a service account.
+    ServiceAccountKey(Vec<u8>),
+    /// Authenticates with the service account of the machine or GKE workload, optionally naming
+    /// the service account to use. This is the default.
+    WorkloadIdentity(Option<String>),
+}
+
+impl fmt::Debug for PubSubAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PubSubAuth::None => write!(f, "None"),
+            PubSubAuth::ServiceAccountKey(_) => write!(f, "ServiceAccountKey(********)"),
+            PubSubAuth::WorkloadIdentity(service) => f.debug_tuple("WorkloadIdentity").field(service).finish(),
+        }
+    }
+}
+
+/// Publishes the data and presence events to a Google Cloud Pub/Sub topic, so that other systems
+/// can subscribe to what happens on the server. Register the same publisher with both
+/// [`ServerBuilder::notify_data`](crate::ServerBuilder::notify_data) and
+/// [`ServerBuilder::notify_presence`](crate::ServerBuilder::notify_presence); clones share their
+/// connection and queue.
+///
+/// Every event is a message with a JSON body like this:
+///
+/// ```json
+/// {
+///   "event": "put",
+///   "path": "/incoming/orders.csv",
+///   "bytes": 1024,
+///   "duration_ms": 12,
+///   "checksum": null,
+///   "request_id": "0x7c1d9e44",
+///   "attributes": { "generation": "1760618096789000" },
+///   "username": "alice",
+///   "trace_id": "0x5f3e2a1b",
+///   "sequence_number": 4,
+///   "time": "2026-10-16T12:34:56.789Z"
+/// }
+/// ```
+///
+/// The `event` and `username` are also set as message attributes, for subscription filters.
+///
+/// Events are published in the background in batches of up to 100 messages or after 1 second,
+/// see [`PubSubPublisher::batching`]. Batches that fail are retried with an exponential backoff
+/// for as long as the server runs. By default the queue is kept in memory, so events that weren't
+/// published yet are lost when the server stops. With a [spool file](PubSubPublisher::spool) they
+/// are kept on disk and published after a restart, which makes the delivery at-least-once.
+///
+/// Requires the `pubsub` feature.
+///
+/// # Example
+///
+/// ```no_run
+/// use libunftp::Server;
+/// use libunftp::notification::{PubSubAuth, PubSubPublisher};
+/// use unftp_sbe_fs::ServerExt;
+///
+/// let publisher = PubSubPublisher::new("my-project", "ftp-events")
+///     .auth(PubSubAuth::WorkloadIdentity(None))
+///     .spool("/var/lib/unftp/pubsub.spool");
+/// let server = Server::with_fs("/srv/ftp")
+///     .notify_data(publisher.clone())
+///     .notify_presence(publisher)
+///     .build();
+/// ```
+#[derive(Clone)]
+pub struct PubSubPublisher {
+    inner: Arc<Publisher>,
+}
+
+#[derive(Clone)]
+struct Publisher {
+    base_url: String,
+    project: String,
+    topic: String,
+    auth: PubSubAuth,
+    batch_size: usize,
+    batch_delay: Duration,
+    max_pending: usize,
+    spool: Option<PathBuf>,
+    logger: slog::Logger,
+    // Started with the first event, so that the publisher can be created outside of a runtime.
+    queue: OnceLock<mpsc::UnboundedSender<Message>>,
+}
+
+// A message as it is kept in the queue and the spool file.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+struct Message {
+    data: String,
+    attributes: serde_json::Map<String, Value>,
+}
+
+impl PubSubPublisher {
+    /// Creates a publisher for the given topic in the given Google Cloud project.
+    pub fn new<P: Into<String>, T: Into<String>>(project: P, topic: T) -> Self {
+        PubSubPublisher {
+            inner: Arc::new(Publisher {
+                base_url: "https://pubsub.googleapis.com".to_string(),
+                project: project.into(),
+                topic: topic.into(),
+                auth: PubSubAuth::WorkloadIdentity(None),
+                batch_size: 100,
+                batch_delay: Duration::from_secs(1),
+                max_pending: 100_000,
+                spool: None,
+                logger: slog::Logger::root(slog_stdlog::StdLog {}.fuse(), slog::o!()),
+                queue: OnceLock::new(),
+            }),
+        }
+    }
+
+    /// Sets how to authenticate with Google Cloud. Defaults to
+    /// [`PubSubAuth::WorkloadIdentity`].
+    pub fn auth(mut self, auth: PubSubAuth) -> Self {
+        self.publisher().auth = auth;
+        self
+    }
+
+    /// Publishes to another Pub/Sub endpoint than `https://pubsub.googleapis.com`, for instance the
+    /// emulator.
+    pub fn base_url<S: Into<String>>(mut self, base_url: S) -> Self {
+        self.publisher().base_url = base_url.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Sets the most messages published in one request and how long to wait for a batch to fill
+    /// up. Defaults to 100 messages and 1 second. Pub/Sub accepts at most 1000 messages per
+    /// request.
+    pub fn batching(mut self, max_messages: usize, max_delay: Duration) -> Self {
+        let publisher = self.publisher();
+        publisher.batch_size = max_messages.clamp(1, 1000);
+        publisher.batch_delay = max_delay;
+        self
+    }
+
+    /// Sets how many events are queued at most while Pub/Sub can't be reached. When the queue is
+    /// full the oldest events are dropped. Defaults to 100 000.
+    pub fn max_pending(mut self, max_pending: usize) -> Self {
+        self.publisher().max_pending = max_pending.max(1);
+        self
+    }
+
+    /// Keeps the events that weren't published yet in the file at the given path, so that they are
+    /// published after a restart. Events left by an earlier run are published with the first new
+    /// event.
+    pub fn spool<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.publisher().spool = Some(path.into());
+        self
+    }
+
+    /// Sets the logger that failed publications are logged to. By default they go to the `log`
+    /// crate.
+    pub fn logger(mut self, logger: slog::Logger) -> Self {
+        self.publisher().logger = logger;
+        self
+    }
+
+    // The publisher is normally configured before it is shared, so this rarely clones.
+    fn publisher(&mut self) -> &mut Publisher {
+        Arc::make_mut(&mut self.inner)
+    }
+
+    fn enqueue(&self, message: Message) {
+        let queue = self.inner.queue.get_or_init(|| {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            let publisher = (*self.inner).clone();
+            tokio::spawn(async move { publisher.run(receiver).await });
+            sender
+        });
+        // The task only stops when all senders are gone, so this doesn't fail.
+        let _ = queue.send(message);
+    }
+}
+
+impl Publisher {
+    async fn run(self, mut receiver: mpsc::UnboundedReceiver<Message>) {
+        let http: HttpClient = Client::builder().build(
+            hyper_rustls::HttpsConnectorBuilder::new()
+                .with_native_roots()
+                .https_or_http()
+                .enable_http1()
+                .build(),
+        );
+        let mut tokens = TokenSource::new(self.auth.clone(), http.clone());
+        let mut pending = match self.load_spool().await {
+            Ok(pending) => pending,
+            Err(err) => {
+                slog::error!(self.logger, "Could not read the Pub/Sub spool file: {}", err);
+                VecDeque::new()
+            }
+        };
+        let mut backoff = self.batch_delay.max(Duration::from_millis(100));
+        let mut open = true;
+        while open || !pending.is_empty() {
+            if pending.is_empty() {
+                match receiver.recv().await {
+                    Some(message) => self.push(&mut pending, message).await,
+                    None => break,
+                }
+            }
+            // Waits for the batch to fill up.
+            let deadline = tokio::time::Instant::now() + self.batch_delay;
+            while open && pending.len() < self.batch_size {
+                tokio::select! {
+                    message = receiver.recv() => match message {
+                        Some(message) => self.push(&mut pending, message).await,
+                        None => open = false,
+                    },
+                    _ = tokio::time::sleep_until(deadline) => break,
+                }
+            }
+            let batch = pending.len().min(self.batch_size);
+            match self.publish(&http, &mut tokens, pending.range(..batch)).await {
+                Ok(()) => {
+                    pending.drain(..batch);
+                    backoff = self.batch_delay.max(Duration::from_millis(100));
+                    if let Err(err) = self.save_spool(&pending).await {
+                        slog::error!(self.logger, "Could not write the Pub/Sub spool file: {}", err);
+                    }
+                }
+                Err(err) => {
+                    slog::warn!(self.logger, "Could not publish {} events to Pub/Sub topic {}: {}", batch, self.topic, err);
+                    if !open {
+                        // The server stopped. The events that are left stay in the spool file.
+                        break;
+                    }
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+
+    async fn push(&self, pending: &mut VecDeque<Message>, message: Message) {
+        if let Some(path) = &self.spool {
+            if let Err(err) = append_spool(path, &message).await {
+                slog::error!(self.logger, "Could not write the Pub/Sub spool file: {}", err);
+            }
+        }
+        pending.push_back(message);
+        if pending.len() > self.max_pending {
+            pending.pop_front();
+            slog::error!(
+                self.logger,
+                "Dropped an event, more than {} events are waiting to be published to Pub/Sub",
+                self.max_pending
+            );
+        }
+    }
+
+    async fn load_spool(&self) -> io::Result<VecDeque<Message>> {
+        let Some(path) = &self.spool else {
+            return Ok(VecDeque::new());
+        };
+        let content = match tokio::fs::read_to_string(path).await {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(VecDeque::new()),
+            content => content?,
+        };
+        // A line that was cut off by a crash is skipped.
+        Ok(content.lines().filter_map(|line| serde_json::from_str(line).ok()).collect())
+    }
+
+    // Replaces the spool file with the events that are still pending.
+    async fn save_spool(&self, pending: &VecDeque<Message>) -> io::Result<()> {
+        let Some(path) = &self.spool else {
+            return Ok(());
+        };
+        let mut content = String::new();
+        for message in pending {
+            content.push_str(&serde_json::to_string(message)?);
+            content.push('\n');
+        }
+        let temporary = path.with_extension("tmp");
+        tokio::fs::write(&temporary, content).await?;
+        tokio::fs::rename(&temporary, path).await
+    }
+
+    async fn publish(&self, http: &HttpClient, tokens: &mut TokenSource, batch: impl Iterator<Item = &Message>) -> Result<(), String> {
+        let body = publish_body(batch);
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri(format!("{}/v1/projects/{}/topics/{}:publish", self.base_url, self.project, self.topic))
+            .header(header::CONTENT_TYPE, "application/json");
+        if let Some(token) = tokens.token().await? {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let request = request.body(Body::from(body.to_string())).map_err(|err| err.to_string())?;
+        let response = http.request(request).await.map_err(|err| err.to_string())?;
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            StatusCode::UNAUTHORIZED => {
+                tokens.clear();
+                Err("the access token was refused".to_string())
+            }
+            status => Err(format!("Pub/Sub replied {}", status)),
+        }
+    }
+}
+
+// Gets and caches the access tokens.
+struct TokenSource {
+    auth: PubSubAuth,
+    http: HttpClient,
+    // The authenticator of a service account key, which caches the tokens itself.
+    authenticator: Option<Authenticator<HttpsConnector<HttpConnector>>>,
+    cached: Option<(String, Instant)>,
+}
+
+impl TokenSource {
+    fn new(auth: PubSubAuth, http: HttpClient) -> Self {
+        TokenSource {
+            auth,
+            http,
+            authenticator: None,
+            cached: None,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.cached = None;
+        self.authenticator = None;
+    }
+
+    async fn token(&mut self) -> Result<Option<String>, String> {
+        if let Some((token, expires)) = &self.cached {
+            if Instant::now() < *expires {
+                return Ok(Some(token.clone()));
+            }
+        }
+        let (token, expires_in) = match &self.auth {
+            PubSubAuth::None => return Ok(None),
+            PubSubAuth::ServiceAccountKey(key) => {
+                if self.authenticator.is_none() {
+                    let key = yup_oauth2::parse_service_account_key(key).map_err(|err| format!("bad service account key: {}", err))?;
+                    let authenticator = ServiceAccountAuthenticator::builder(key)
+                        .hyper_client(self.http.clone())
+                        .build()
+                        .await
+                        .map_err(|err| err.to_string())?;
+                    self.authenticator = Some(authenticator);
+                }
+                let authenticator = self.authenticator.as_ref().ok_or("no authenticator")?;
+                let token = authenticator.token(&[PUBSUB_SCOPE]).await.map_err(|err| err.to_string())?;
+                return Ok(token.token().map(str::to_string));
+            }
+            PubSubAuth::WorkloadIdentity(service) => self.metadata_token(service.as_deref().unwrap_or("default")).await?,
+        };
+        // Refreshed a little early, so that it doesn't expire while it is used.
+        let expires = Instant::now() + Duration::from_secs(expires_in.saturating_sub(30));
+        self.cached = Some((token.clone(), expires));
+        Ok(Some(token))
+    }
+
+    // Asks the metadata server of the machine for a token of its service account.
+    async fn metadata_token(&self, service: &str) -> Result<(String, u64), String> {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(format!(
+                "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/{}/token",
+                service
+            ))
+            .header("Metadata-Flavor", "Google")
+            .body(Body::empty())
+            .map_err(|err| err.to_string())?;
+        let response = self.http.request(request).await.map_err(|err| err.to_string())?;
+        let body = hyper::body::to_bytes(response.into_body()).await.map_err(|err| err.to_string())?;
+        let token: Value = serde_json::from_slice(&body).map_err(|err| err.to_string())?;
+        match (token["access_token"].as_str(), token["expires_in"].as_u64()) {
+            (Some(access_token), Some(expires_in)) => Ok((access_token.to_string(), expires_in)),
+            _ => Err("the metadata server didn't return a token".to_string()),
+        }
+    }
+}
+
+// The body of a publish request, see
+// https://cloud.google.com/pubsub/docs/reference/rest/v1/projects.topics/publish
+fn publish_body<'a>(batch: impl Iterator<Item = &'a Message>) -> Value {
+    let messages: Vec<Value> = batch
+        .map(|message| {
+            json!({
+                "data": base64::engine::general_purpose::STANDARD.encode(&message.data),
+                "attributes": message.attributes,
+            })
+        })
+        .collect();
+    json!({ "messages": messages })
+}
+
+fn message(mut payload: Value, meta: &EventMeta) -> Message {
+    let mut attributes = serde_json::Map::new();
+    attributes.insert("event".to_string(), payload["event"].clone());
+    attributes.insert("username".to_string(), json!(meta.username));
+    if let Some(fields) = payload.as_object_mut() {
+        fields.insert("username".to_string(), json!(meta.username));
+        fields.insert("trace_id".to_string(), json!(meta.trace_id));
+        fields.insert("sequence_number".to_string(), json!(meta.sequence_number));
+        fields.insert("time".to_string(), json!(Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)));
+    }
+    Message {
+        data: payload.to_string(),
+        attributes,
+    }
+}
+
+fn data_payload(event: &DataEvent) -> Value {
+    match event {
+        DataEvent::Got {
+            path,
+            bytes,
+            request_id,
+            attributes,
+        } => json!({
+            "event": "got",
+            "path": path,
+            "bytes": bytes,
+            "request_id": request_id,
+            "attributes": attributes_json(attributes),
+        }),
+        DataEvent::Put {
+            path,
+            bytes,
+            duration,
+            checksum,
+            request_id,
+            attributes,
+        } => json!({
+            "event": "put",
+            "path": path,
+            "bytes": bytes,
+            "duration_ms": duration.as_millis() as u64,
+            "checksum": checksum,
+            "request_id": request_id,
+            "attributes": attributes_json(attributes),
+        }),
+        DataEvent::Progress {
+            command,
+            path,
+            bytes,
+            duration,
+            bytes_per_second,
+        } => json!({
+            "event": "progress",
+            "command": command,
+            "path": path,
+            "bytes": bytes,
+            "duration_ms": duration.as_millis() as u64,
+            "bytes_per_second": bytes_per_second,
+        }),
+        DataEvent::Deleted { path } => json!({ "event": "deleted", "path": path }),
+        DataEvent::MadeDir { path } => json!({ "event": "made_dir", "path": path }),
+        DataEvent::RemovedDir { path } => json!({ "event": "removed_dir", "path": path }),
+        DataEvent::Renamed { from, to } => json!({ "event": "renamed", "from": from, "to": to }),
+        DataEvent::Copied { from, to } => json!({ "event": "copied", "from": from, "to": to }),
+    }
+}
+
+fn attributes_json(attributes: &[(String, String)]) -> Value {
+    Value::Object(attributes.iter().map(|(name, value)| (name.clone(), json!(value))).collect())
+}
+
+async fn append_spool(path: &PathBuf, message: &Message) -> io::Result<()> {
+    let mut line = serde_json::to_string(message)?;
+    line.push('\n');
+    let mut file = tokio::fs::OpenOptions::new().create(true).append(true).open(path).await?;
+    file.write_all(line.as_bytes()).await?;
+    file.flush().await
+}
+
+#[async_trait]
+impl DataListener for PubSubPublisher {
+    async fn receive_data_event(&self, e: DataEvent, m: EventMeta) {
+        self.enqueue(message(data_payload(&e), &m));
+    }
+
+    fn file_attributes(&self) -> bool {
+        true
+    }
+}
+
+#[async_trait]
+impl PresenceListener for PubSubPublisher {
+    async fn receive_presence_event(&self, e: PresenceEvent, m: EventMeta) {
+        let event = match e {
+            PresenceEvent::LoggedIn => "logged_in",
+            PresenceEvent::LoggedOut => "logged_out",
+        };
+        self.enqueue(message(json!({ "event": event }), &m));
+    }
+}
+
+impl fmt::Debug for PubSubPublisher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PubSubPublisher")
+            .field("base_url", &self.inner.base_url)
+            .field("project", &self.inner.project)
+            .field("topic", &self.inner.topic)
+            .field("auth", &self.inner.auth)
+            .field("batch_size", &self.inner.batch_size)
+            .field("batch_delay", &self.inner.batch_delay)
+            .field("spool", &self.inner.spool)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{data_payload, message, publish_body, PubSubPublisher};
+    use crate::notification::{DataEvent, EventMeta};
+    use pretty_assertions::assert_eq;
+    use serde_json::Value;
+
+    fn meta() -> EventMeta {
+        EventMeta {
+            username: "alice".to_string(),
+            trace_id: "0x1".to_string(),
+            sequence_number: 3,
+        }
+    }
+
+    #[test]
+    fn encodes_events_as_messages() {
+        let event = DataEvent::Deleted {
+            path: "/incoming/a.csv".to_string(),
+        };
+        let message = message(data_payload(&event), &meta());
+        let body = publish_body([message].iter());
+        let encoded = &body["messages"][0];
+        assert_eq!(encoded["attributes"]["event"], "deleted");
+        assert_eq!(encoded["attributes"]["username"], "alice");
+        let data = base64::Engine::decode(&base64::engine::general_purpose::STANDARD, encoded["data"].as_str().unwrap()).unwrap();
+        let data: Value = serde_json::from_slice(&data).unwrap();
+        assert_eq!(data["path"], "/incoming/a.csv");
+        assert_eq!(data["sequence_number"], 3);
+    }
+
+    #[tokio::test]
+    async fn keeps_pending_events_in_the_spool() {
+        let path = std::env::temp_dir().join(format!("libunftp-pubsub-{}", std::process::id()));
+        let publisher = PubSubPublisher::new("project", "topic").spool(&path);
+        let mut pending = Default::default();
+        for path in ["/a", "/b"] {
+            let event = DataEvent::MadeDir { path: path.to_string() };
+            publisher.inner.push(&mut pending, message(data_payload(&event), &meta())).await;
+        }
+        assert_eq!(publisher.inner.load_spool().await.unwrap(), pending);
+        pending.pop_front();
+        publisher.inner.save_spool(&pending).await.unwrap();
+        let spooled = publisher.inner.load_spool().await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(spooled, pending);
+    }
+}
@@ -0,0 +1,304 @@
+//! A [`DataListener`] that posts the events as JSON to an HTTP endpoint.
+
+use super::event::{DataEvent, DataListener, EventError, EventMeta};
+use async_trait::async_trait;
+use chrono::{SecondsFormat, Utc};
+use hmac::{Hmac, Mac};
+use hyper::{client::HttpConnector, Body, Client, Method, Request, StatusCode, Uri};
+use hyper_rustls::HttpsConnector;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use slog::Drain;
+use std::{fmt, sync::Arc, time::Duration};
+
+/// Posts the data events that change files to a webhook, so that downstream pipelines can be
+/// triggered without writing a listener. These are uploads (`put`), deletions (`deleted`),
+/// renames (`renamed`), copies (`copied`) and created and removed directories (`made_dir`,
+/// `removed_dir`). Downloads and progress events are not posted.
+///
+/// Every event is a `POST` with a JSON body like this:
+///
+/// ```json
+/// {
+///   "event": "put",
+///   "path": "/incoming/orders.csv",
+///   "bytes": 1024,
+///   "duration_ms": 12,
+///   "checksum": null,
+///   "request_id": "0x7c1d9e44",
+///   "attributes": { "generation": "1760618096789000" },
+///   "username": "alice",
+///   "trace_id": "0x5f3e2a1b",
+///   "sequence_number": 4,
+///   "time": "2026-10-16T12:34:56.789Z"
+/// }
+/// ```
+///
+/// With a secret set, the `X-Unftp-Signature` header holds `sha256=` followed by the hexadecimal
+/// HMAC-SHA256 of the body, so that the receiver can check where the event came from. Deliveries
+/// that fail, or that are answered with a `429` or `5xx` status, are retried with an exponential
+/// backoff. Delivery happens in the background, the client doesn't wait for it, unless the server
+/// waits for the events with [`ServerBuilder::event_delivery`](crate::ServerBuilder::event_delivery).
+///
+/// Requires the `webhook` feature.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::Server;
+/// use libunftp::notification::WebhookNotifier;
+/// use unftp_sbe_fs::ServerExt;
+///
+/// let webhook = WebhookNotifier::new("https://pipeline.example.com/ftp-events")
+///     .unwrap()
+///     .secret("s3cr3t")
+///     .path_prefix("/incoming");
+/// let server = Server::with_fs("/tmp").notify_data(webhook).build();
+/// ```
+#[derive(Clone)]
+pub struct WebhookNotifier {
+    inner: Arc<Webhook>,
+}
+
+#[derive(Clone)]
+struct Webhook {
+    url: Uri,
+    secret: Option<Vec<u8>>,
+    path_prefixes: Vec<String>,
+    max_attempts: u32,
+    initial_backoff: Duration,
+    client: Client<HttpsConnector<HttpConnector>>,
+    logger: slog::Logger,
+}
+
+impl WebhookNotifier {
+    /// Creates a notifier that posts to the given HTTP or HTTPS URL. Fails if the URL is invalid.
+    /// HTTPS servers are verified with the certificate authorities of the platform.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the platform has no certificate authorities installed.
+    pub fn new(url: &str) -> Result<Self, hyper::http::uri::InvalidUri> {
+        let https = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_native_roots()
+            .https_or_http()
+            .enable_http1()
+            .build();
+        Ok(WebhookNotifier {
+            inner: Arc::new(Webhook {
+                url: url.parse()?,
+                secret: None,
+                path_prefixes: vec![],
+                max_attempts: 5,
+                initial_backoff: Duration::from_secs(1),
+                client: Client::builder().build(https),
+                logger: slog::Logger::root(slog_stdlog::StdLog {}.fuse(), slog::o!()),
+            }),
+        })
+    }
+
+    /// Signs the events with HMAC-SHA256 using the given secret.
+    pub fn secret<S: Into<Vec<u8>>>(mut self, secret: S) -> Self {
+        self.webhook().secret = Some(secret.into());
+        self
+    }
+
+    /// Only posts events about paths under the given directory. Can be called more than once to
+    /// post events for several directories. By default events about all paths are posted.
+    pub fn path_prefix<P: Into<String>>(mut self, prefix: P) -> Self {
+        self.webhook().path_prefixes.push(prefix.into());
+        self
+    }
+
+    /// Sets how many times an event is delivered at most and how long to wait before the first
+    /// retry. The wait doubles with every retry. Defaults to 5 attempts and 1 second.
+    pub fn retries(mut self, max_attempts: u32, initial_backoff: Duration) -> Self {
+        let webhook = self.webhook();
+        webhook.max_attempts = max_attempts.max(1);
+        webhook.initial_backoff = initial_backoff;
+        self
+    }
+
+    /// Sets the logger that failed deliveries are logged to. By default they go to the `log`
+    /// crate.
+    pub fn logger(mut self, logger: slog::Logger) -> Self {
+        self.webhook().logger = logger;
+        self
+    }
+
+    // The notifier is normally configured before it is shared, so this rarely clones.
+    fn webhook(&mut self) -> &mut Webhook {
+        Arc::make_mut(&mut self.inner)
+    }
+}
+
+impl Webhook {
+    fn matches(&self, path: &str) -> bool {
+        self.path_prefixes.is_empty()
+            || self.path_prefixes.iter().any(|prefix| {
+                let prefix = prefix.trim_end_matches('/');
+                path == prefix || path.strip_prefix(prefix).is_some_and(|rest| rest.starts_with('/'))
+            })
+    }
+
+    // Builds the body to post for the event, or None if the event isn't posted.
+    fn payload(&self, event: &DataEvent, meta: &EventMeta) -> Option<Value> {
+        let mut payload = match event {
+            DataEvent::Put {
+                path,
+                bytes,
+                duration,
+                checksum,
+                request_id,
+                attributes,
+            } if self.matches(path) => json!({
+                "event": "put",
+                "path": path,
+                "bytes": bytes,
+                "duration_ms": duration.as_millis() as u64,
+                "checksum": checksum,
+                "request_id": request_id,
+                "attributes": attributes_json(attributes),
+            }),
+            DataEvent::Deleted { path } if self.matches(path) => json!({ "event": "deleted", "path": path }),
+            DataEvent::MadeDir { path } if self.matches(path) => json!({ "event": "made_dir", "path": path }),
+            DataEvent::RemovedDir { path } if self.matches(path) => json!({ "event": "removed_dir", "path": path }),
+            DataEvent::Renamed { from, to } if self.matches(from) || self.matches(to) => json!({ "event": "renamed", "from": from, "to": to }),
+            DataEvent::Copied { from, to } if self.matches(from) || self.matches(to) => json!({ "event": "copied", "from": from, "to": to }),
+            _ => return None,
+        };
+        let fields = payload.as_object_mut()?;
+        fields.insert("username".to_string(), json!(meta.username));
+        fields.insert("trace_id".to_string(), json!(meta.trace_id));
+        fields.insert("sequence_number".to_string(), json!(meta.sequence_number));
+        fields.insert("time".to_string(), json!(Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)));
+        Some(payload)
+    }
+
+    async fn deliver(&self, body: String) -> Result<(), EventError> {
+        let signature = self.secret.as_ref().map(|secret| sign(secret, body.as_bytes()));
+        let mut backoff = self.initial_backoff;
+        for attempt in 1..=self.max_attempts {
+            let mut request = Request::builder()
+                .method(Method::POST)
+                .uri(self.url.clone())
+                .header("Content-Type", "application/json");
+            if let Some(signature) = &signature {
+                request = request.header("X-Unftp-Signature", signature.as_str());
+            }
+            let request = match request.body(Body::from(body.clone())) {
+                Ok(request) => request,
+                Err(err) => {
+                    slog::warn!(self.logger, "Could not build webhook request: {}", err);
+                    return Err(EventError::new(err.to_string()));
+                }
+            };
+            let retry = match self.client.request(request).await {
+                Ok(response) if response.status().is_success() => return Ok(()),
+                Ok(response) => {
+                    slog::warn!(self.logger, "Webhook {} replied {} (attempt {})", self.url, response.status(), attempt);
+                    response.status() == StatusCode::TOO_MANY_REQUESTS || response.status().is_server_error()
+                }
+                Err(err) => {
+                    slog::warn!(self.logger, "Could not post to webhook {}: {} (attempt {})", self.url, err, attempt);
+                    true
+                }
+            };
+            if !retry || attempt == self.max_attempts {
+                break;
+            }
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+        slog::error!(self.logger, "Gave up delivering an event to webhook {}", self.url);
+        Err(EventError::new(format!("could not deliver the event to {}", self.url)))
+    }
+}
+
+fn attributes_json(attributes: &[(String, String)]) -> Value {
+    Value::Object(attributes.iter().map(|(name, value)| (name.clone(), json!(value))).collect())
+}
+
+// The value of the signature header.
+fn sign(secret: &[u8], body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any size");
+    mac.update(body);
+    let hex: String = mac.finalize().into_bytes().iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("sha256={}", hex)
+}
+
+#[async_trait]
+impl DataListener for WebhookNotifier {
+    async fn receive_data_event(&self, e: DataEvent, m: EventMeta) {
+        if let Some(payload) = self.inner.payload(&e, &m) {
+            let webhook = self.inner.clone();
+            tokio::spawn(async move { webhook.deliver(payload.to_string()).await });
+        }
+    }
+
+    async fn try_receive_data_event(&self, e: DataEvent, m: EventMeta) -> Result<(), EventError> {
+        match self.inner.payload(&e, &m) {
+            Some(payload) => self.inner.deliver(payload.to_string()).await,
+            None => Ok(()),
+        }
+    }
+
+    fn file_attributes(&self) -> bool {
+        true
+    }
+}
+
+impl fmt::Debug for WebhookNotifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebhookNotifier")
+            .field("url", &self.inner.url)
+            .field("signed", &self.inner.secret.is_some())
+            .field("path_prefixes", &self.inner.path_prefixes)
+            .field("max_attempts", &self.inner.max_attempts)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sign, WebhookNotifier};
+    use crate::notification::{DataEvent, EventMeta};
+    use pretty_assertions::assert_eq;
+
+    fn meta() -> EventMeta {
+        EventMeta {
+            username: "alice".to_string(),
+            trace_id: "0x1".to_string(),
+            sequence_number: 3,
+        }
+    }
+
+    #[test]
+    fn signs_with_hmac_sha256() {
+        // Test case 2 of RFC 4231
+        assert_eq!(
+            sign(b"Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn posts_file_changes_under_the_prefixes() {
+        let webhook = WebhookNotifier::new("http://localhost:8080/events").unwrap().path_prefix("/incoming/");
+        let deleted = |path: &str| DataEvent::Deleted { path: path.to_string() };
+        let payload = webhook.inner.payload(&deleted("/incoming/a.csv"), &meta()).unwrap();
+        assert_eq!(payload["event"], "deleted");
+        assert_eq!(payload["path"], "/incoming/a.csv");
+        assert_eq!(payload["username"], "alice");
+        assert_eq!(payload["sequence_number"], 3);
+        assert!(webhook.inner.payload(&deleted("/incoming"), &meta()).is_some());
+        assert!(webhook.inner.payload(&deleted("/incomingx/a.csv"), &meta()).is_none());
+        let got = DataEvent::Got {
+            path: "/incoming/a.csv".to_string(),
+            bytes: 1,
+            request_id: "0x2".to_string(),
+            attributes: vec![],
+        };
+        assert!(webhook.inner.payload(&got, &meta()).is_none());
+    }
+}
@@ -0,0 +1,188 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Local, TimeZone};
+use std::fmt::{Debug, Display};
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+/// The direction of a transfer, as seen from the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferDirection {
+    /// The client downloaded a file with RETR.
+    Outgoing,
+    /// The client uploaded a file with STOR, APPE or STOU.
+    Incoming,
+}
+
+/// Describes a transfer that completed. Instances of these are passed to a
+/// [`TransferLogListener`](crate::notification::TransferLogListener).
+#[derive(Debug, Clone)]
+pub struct TransferRecord {
+    /// When the transfer finished.
+    pub finished: SystemTime,
+    /// How long the transfer took.
+    pub duration: Duration,
+    /// The IP address of the client. This is the address from the PROXY protocol header if the
+    /// server runs behind a proxy.
+    pub remote_host: IpAddr,
+    /// The amount of bytes transferred.
+    pub bytes: u64,
+    /// The absolute path of the file on the storage back-end.
+    pub path: String,
+    /// Whether the file was transferred in ASCII mode (`TYPE A`) instead of binary mode.
+    pub ascii: bool,
+    /// Whether the file was downloaded or uploaded.
+    pub direction: TransferDirection,
+    /// The user that transferred the file.
+    pub username: String,
+    /// Identifies the session in which the file was transferred.
+    pub trace_id: String,
+}
+
+impl TransferRecord {
+    /// Formats the record as a line of the xferlog format of wu-ftpd, without the trailing newline
+    /// and with the time in the local time zone.
+    pub fn to_xferlog(&self) -> String {
+        self.to_xferlog_in(&Local)
+    }
+
+    fn to_xferlog_in<Tz: TimeZone>(&self, tz: &Tz) -> String
+    where
+        Tz::Offset: Display,
+    {
+        // Like wu-ftpd, round to whole seconds but never report less than one.
+        let seconds = ((self.duration.as_millis() + 500) / 1000).max(1);
+        // The fields are separated by spaces, so spaces in the path would confuse the parsers.
+        let path: String = self.path.chars().map(|c| if c.is_whitespace() { '_' } else { c }).collect();
+        let access_mode = match self.username.as_str() {
+            "anonymous" | "ftp" => 'a',
+            _ => 'r',
+        };
+        format!(
+            "{} {} {} {} {} {} _ {} {} {} ftp 0 * c",
+            DateTime::<chrono::Utc>::from(self.finished).with_timezone(tz).format("%a %b %e %H:%M:%S %Y"),
+            seconds,
+            self.remote_host,
+            self.bytes,
+            path,
+            if self.ascii { 'a' } else { 'b' },
+            match self.direction {
+                TransferDirection::Outgoing => 'o',
+                TransferDirection::Incoming => 'i',
+            },
+            access_mode,
+            self.username,
+        )
+    }
+}
+
+/// Listens for completed file transfers, for instance to write them to a transfer log. Unlike the
+/// [`DataListener`](crate::notification::DataListener) it gets to know the client's address and
+/// the transfer type, which is what the classic xferlog format needs. [`XferLogFile`] writes
+/// the transfers to a file in that format.
+///
+/// Implementations can be passed to [`ServerBuilder::transfer_log`](crate::ServerBuilder::transfer_log).
+#[async_trait]
+pub trait TransferLogListener: Sync + Send + Debug {
+    /// Called after a transfer completed, before the client receives the `226` reply.
+    async fn receive_transfer(&self, transfer: TransferRecord);
+}
+
+/// A [`TransferLogListener`] that appends a line in the xferlog format of wu-ftpd to a file for
+/// every completed transfer, so that existing tools that parse xferlog can be used.
+///
+/// # Example
+///
+/// ```no_run
+/// use libunftp::Server;
+/// use libunftp::notification::XferLogFile;
+/// use unftp_sbe_fs::ServerExt;
+///
+/// let server = Server::with_fs("/srv/ftp")
+///              .transfer_log(XferLogFile::open("/var/log/xferlog").unwrap())
+///              .build();
+/// ```
+#[derive(Debug)]
+pub struct XferLogFile {
+    file: Mutex<tokio::fs::File>,
+}
+
+impl XferLogFile {
+    /// Opens the file at the given path for appending, creating it if it doesn't exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(XferLogFile {
+            file: Mutex::new(tokio::fs::File::from_std(file)),
+        })
+    }
+}
+
+#[async_trait]
+impl TransferLogListener for XferLogFile {
+    async fn receive_transfer(&self, transfer: TransferRecord) {
+        let line = format!("{}\n", transfer.to_xferlog());
+        let mut file = self.file.lock().await;
+        // A transfer that can't be logged doesn't fail, like with the other listeners.
+        if file.write_all(line.as_bytes()).await.is_ok() {
+            let _ = file.flush().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TransferDirection, TransferLogListener, TransferRecord, XferLogFile};
+    use pretty_assertions::assert_eq;
+    use std::time::{Duration, SystemTime};
+
+    fn record() -> TransferRecord {
+        TransferRecord {
+            // Fri Oct 16 12:34:56 2026 UTC
+            finished: SystemTime::UNIX_EPOCH + Duration::from_secs(1_792_154_096),
+            duration: Duration::from_millis(2600),
+            remote_host: "192.168.1.10".parse().unwrap(),
+            bytes: 1234,
+            path: "/reports/march 2026.csv".to_string(),
+            ascii: false,
+            direction: TransferDirection::Outgoing,
+            username: "alice".to_string(),
+            trace_id: "0x1".to_string(),
+        }
+    }
+
+    #[test]
+    fn formats_xferlog_lines() {
+        assert_eq!(
+            "Fri Oct 16 12:34:56 2026 3 192.168.1.10 1234 /reports/march_2026.csv b _ o r alice ftp 0 * c",
+            record().to_xferlog_in(&chrono::Utc)
+        );
+        let upload = TransferRecord {
+            duration: Duration::from_millis(10),
+            ascii: true,
+            direction: TransferDirection::Incoming,
+            username: "anonymous".to_string(),
+            ..record()
+        };
+        assert_eq!(
+            "Fri Oct 16 12:34:56 2026 1 192.168.1.10 1234 /reports/march_2026.csv a _ i a anonymous ftp 0 * c",
+            upload.to_xferlog_in(&chrono::Utc)
+        );
+    }
+
+    #[tokio::test]
+    async fn appends_to_the_file() {
+        let path = std::env::temp_dir().join(format!("libunftp-xferlog-{}", std::process::id()));
+        std::fs::write(&path, "existing\n").unwrap();
+        let log = XferLogFile::open(&path).unwrap();
+        log.receive_transfer(record()).await;
+        log.receive_transfer(record()).await;
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(3, lines.len());
+        assert_eq!(record().to_xferlog(), lines[1]);
+    }
+}
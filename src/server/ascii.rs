@@ -0,0 +1,170 @@
+//! Line ending translation for transfers in ASCII mode (`TYPE A`).
+//!
+//! On the wire lines end with CRLF, as RFC 959 prescribes for the ASCII representation type, while
+//! files are stored with the LF line endings of the server. Downloads have a CR inserted before
+//! every LF that doesn't already follow one and uploads have the CR of every CRLF pair removed.
+//! Both directions keep their state across reads and writes, so that a line ending split over two
+//! chunks is translated like any other.
+
+use std::{
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+const BUFFER_SIZE: usize = 8 * 1024;
+
+/// Translates the LF line endings of the data written to it into CRLF.
+pub struct AsciiWriter<W> {
+    inner: W,
+    // Translated data that was not yet passed on to the inner writer.
+    pending: Vec<u8>,
+    position: usize,
+    // Whether the last byte written was a CR, so that an LF following it is left alone.
+    after_cr: bool,
+}
+
+impl<W: AsyncWrite + Unpin> AsciiWriter<W> {
+    pub fn new(inner: W) -> Self {
+        AsciiWriter {
+            inner,
+            pending: Vec::new(),
+            position: 0,
+            after_cr: false,
+        }
+    }
+
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        while self.position < self.pending.len() {
+            let written = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.pending[self.position..]))?;
+            if written == 0 {
+                return Poll::Ready(Err(std::io::Error::from(std::io::ErrorKind::WriteZero)));
+            }
+            self.position += written;
+        }
+        self.pending.clear();
+        self.position = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for AsciiWriter<W> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        for &byte in buf {
+            if byte == b'\n' && !this.after_cr {
+                this.pending.push(b'\r');
+            }
+            this.pending.push(byte);
+            this.after_cr = byte == b'\r';
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+/// Translates the CRLF line endings of the data read from it into LF. A CR that isn't followed by
+/// an LF is kept.
+pub struct AsciiReader<R> {
+    inner: R,
+    buffer: Box<[u8]>,
+    position: usize,
+    end: usize,
+    // Whether a CR was read that is yet to be decided on.
+    after_cr: bool,
+    eof: bool,
+}
+
+impl<R: AsyncRead + Unpin> AsciiReader<R> {
+    pub fn new(inner: R) -> Self {
+        AsciiReader {
+            inner,
+            buffer: vec![0; BUFFER_SIZE].into_boxed_slice(),
+            position: 0,
+            end: 0,
+            after_cr: false,
+            eof: false,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for AsciiReader<R> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.position == this.end {
+                if this.eof {
+                    if this.after_cr && buf.remaining() > 0 {
+                        this.after_cr = false;
+                        buf.put_slice(b"\r");
+                    }
+                    return Poll::Ready(Ok(()));
+                }
+                let mut read_buf = ReadBuf::new(&mut this.buffer);
+                ready!(Pin::new(&mut this.inner).poll_read(cx, &mut read_buf))?;
+                this.position = 0;
+                this.end = read_buf.filled().len();
+                this.eof = this.end == 0;
+                continue;
+            }
+
+            let filled_before = buf.filled().len();
+            while this.position < this.end && buf.remaining() > 0 {
+                let byte = this.buffer[this.position];
+                if this.after_cr {
+                    this.after_cr = false;
+                    if byte != b'\n' {
+                        buf.put_slice(b"\r");
+                        continue;
+                    }
+                } else if byte == b'\r' {
+                    this.after_cr = true;
+                    this.position += 1;
+                    continue;
+                }
+                buf.put_slice(&[byte]);
+                this.position += 1;
+            }
+            // Only a CR may have been consumed, in which case we need to know what follows it.
+            if buf.filled().len() > filled_before || buf.remaining() == 0 {
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn writer_inserts_cr() {
+        let mut writer = AsciiWriter::new(Vec::new());
+        writer.write_all(b"one\ntwo\r").await.unwrap();
+        writer.write_all(b"\nthree\r\n\n").await.unwrap();
+        writer.shutdown().await.unwrap();
+        assert_eq!(writer.inner, b"one\r\ntwo\r\nthree\r\n\r\n");
+    }
+
+    #[tokio::test]
+    async fn reader_strips_cr() {
+        let input = (&b"one\r\ntwo\r"[..]).chain(&b"\nthree\rfour\r"[..]);
+        let mut output = Vec::new();
+        AsciiReader::new(input).read_to_end(&mut output).await.unwrap();
+        assert_eq!(output, b"one\ntwo\nthree\rfour\r");
+    }
+}
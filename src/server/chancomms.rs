@@ -7,12 +7,19 @@ use crate::{
     server::session::TraceId,
     storage::{Error, StorageBackend},
 };
-use std::fmt;
+use std::{fmt, time::Duration};
 use tokio::{
     net::TcpStream,
-    sync::mpsc::{Receiver, Sender},
+    sync::{
+        mpsc::{Receiver, Sender},
+        oneshot,
+    },
 };
 
+// Asks the data channel to abort. The data channel replies to ABOR itself and then acknowledges
+// the abort. If it closed already, the acknowledgement is dropped instead.
+pub type DataAbort = oneshot::Sender<()>;
+
 // Commands that can be send to the data channel / data loop.
 #[derive(PartialEq, Eq, Debug)]
 pub enum DataChanMsg {
@@ -25,10 +32,18 @@ pub enum DataChanCmd {
     Retr {
         /// The path to the file the client would like to retrieve.
         path: String,
+        /// The offset set by REST to resume from.
+        start_pos: u64,
     },
     Stor {
         /// The path to the file the client would like to store.
         path: String,
+        /// The offset set by REST to resume from.
+        start_pos: u64,
+    },
+    Stou {
+        /// The path of the file that the storage back-end created with a unique name for the upload.
+        path: String,
     },
     List {
         /// Arguments passed along with the list command.
@@ -37,6 +52,8 @@ pub enum DataChanCmd {
         path: Option<String>,
     },
     Nlst {
+        /// Arguments passed along with the nlst command.
+        options: Option<String>,
         /// The path of the file/directory the clients wants to list.
         path: Option<String>,
     },
@@ -48,6 +65,7 @@ impl DataChanCmd {
         match self {
             DataChanCmd::Retr { path, .. } => Some(path.clone()),
             DataChanCmd::Stor { path, .. } => Some(path.clone()),
+            DataChanCmd::Stou { path, .. } => Some(path.clone()),
             DataChanCmd::List { path, .. } => path.clone(),
             DataChanCmd::Nlst { path, .. } => path.clone(),
         }
@@ -68,6 +86,10 @@ pub enum ControlChanMsg {
         path: String,
         /// The number of bytes transferred
         bytes: u64,
+        /// Identifies the transfer in the logs
+        request_id: String,
+        /// The attributes of the file, if the data listener wants them
+        attributes: Vec<(String, String)>,
     },
     /// We've written the data from the client to the StorageBackend
     WrittenData {
@@ -75,6 +97,26 @@ pub enum ControlChanMsg {
         path: String,
         /// The number of bytes transferred
         bytes: u64,
+        /// How long the transfer took
+        duration: Duration,
+        /// The checksum of the uploaded data, if it was computed
+        checksum: Option<String>,
+        /// The name the StorageBackend chose for the file in case of STOU
+        unique_name: Option<String>,
+        /// Identifies the transfer in the logs
+        request_id: String,
+        /// The attributes of the file, if the data listener wants them
+        attributes: Vec<(String, String)>,
+    },
+    /// The upload hook rejected the data written to the StorageBackend, which was then deleted
+    UploadRejected {
+        /// The reason given by the hook
+        reason: String,
+    },
+    /// The upload scanner rejected the data while it was written to the StorageBackend
+    UploadScanFailed {
+        /// The reason given by the scanner
+        reason: String,
     },
     /// Data connection was unexpectedly closed
     ConnectionReset,
@@ -105,10 +147,19 @@ pub enum ControlChanMsg {
         /// The new path as specified by the client
         new_path: String,
     },
+    /// File successfully copied
+    CopySuccess {
+        /// The path of the original file
+        from: String,
+        /// The path of the copy
+        to: String,
+    },
     /// Failed to delete file
     DelFail,
     /// Quit the client connection
     ExitControlLoop,
+    /// Replay the oldest command that was held back during a transfer
+    ReplayHeldCommand,
     /// Successfully created directory
     MkDirSuccess { path: String },
     /// Failed to crate directory
@@ -136,6 +187,7 @@ impl fmt::Display for ControlChanMsg {
 // ProxyLoopMsg is sent to the proxy loop when proxy protocol mode is enabled. See the
 // Server::proxy_protocol_mode and Server::listen_proxy_protocol_mode methods.
 #[derive(Debug)]
+#[cfg_attr(not(feature = "proxy-protocol"), allow(dead_code))]
 pub(crate) enum ProxyLoopMsg<Storage, User>
 where
     Storage: StorageBackend<User>,
@@ -150,4 +202,5 @@ where
 }
 
 pub(crate) type ProxyLoopSender<Storage, User> = Sender<ProxyLoopMsg<Storage, User>>;
+#[cfg_attr(not(feature = "proxy-protocol"), allow(dead_code))]
 pub(crate) type ProxyLoopReceiver<Storage, User> = Receiver<ProxyLoopMsg<Storage, User>>;
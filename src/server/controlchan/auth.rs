@@ -42,6 +42,7 @@ where
             | Event::Command(Command::Prot { .. })
             | Event::Command(Command::Pbsz { .. })
             | Event::Command(Command::Feat)
+            | Event::Command(Command::Lang { .. })
             | Event::Command(Command::Noop)
             | Event::Command(Command::Opts { option: Opt::Utf8 { .. } })
             | Event::Command(Command::Quit) => self.next.handle(event).await,
@@ -1,8 +1,11 @@
-use super::{command::Command, error::ControlChanError, line_parser, Reply};
+use super::{command::Command, error::ControlChanError, line_parser, ControlChanErrorKind, Reply};
+use crate::server::encoding::Charset;
 
-use bytes::BytesMut;
+use bytes::{Bytes, BytesMut};
+use std::borrow::Cow;
 use std::io::Write;
 use tokio_util::codec::{Decoder, Encoder};
+use zeroize::Zeroize;
 
 // FTPCodec implements tokio's `Decoder` and `Encoder` traits for the control channel, that we'll
 // use to decode FTP commands and encode their responses.
@@ -12,11 +15,47 @@ pub struct FtpCodec {
     // is the next index to examine. The next time `decode` is called with `abcde\n`, we will only
     // look at `de\n` before returning.
     next_index: usize,
+    // Converts between the character set of the client and UTF-8.
+    charset: Charset,
+    // The longest line we accept, including the line ending. Without it a client could make us
+    // buffer data forever by never sending a newline.
+    max_line_length: usize,
+    // Whether a carriage return that isn't followed by a newline ends the line too, for clients
+    // that don't send CRLF.
+    bare_cr: bool,
+    // Set if the last line ended in a carriage return at the end of the buffer, so that a newline
+    // that arrives after it is dropped.
+    skip_newline: bool,
 }
 
 impl FtpCodec {
-    pub fn new() -> Self {
-        FtpCodec { next_index: 0 }
+    pub fn new(charset: Charset, max_line_length: usize) -> Self {
+        FtpCodec {
+            next_index: 0,
+            charset,
+            max_line_length,
+            bare_cr: false,
+            skip_newline: false,
+        }
+    }
+
+    pub fn bare_cr(mut self, enabled: bool) -> Self {
+        self.bare_cr = enabled;
+        self
+    }
+
+    // Finds the index of the byte that ends the next line.
+    fn line_end(&self, buf: &[u8]) -> Option<usize> {
+        buf[self.next_index..]
+            .iter()
+            .enumerate()
+            .position(|(i, b)| match b {
+                b'\n' => true,
+                // A carriage return followed by a NUL byte is part of a path, see RFC 959.
+                b'\r' => self.bare_cr && !matches!(buf.get(self.next_index + i + 1), Some(b'\n') | Some(b'\0')),
+                _ => false,
+            })
+            .map(|offset| offset + self.next_index)
     }
 }
 
@@ -27,11 +66,37 @@ impl Decoder for FtpCodec {
     // Here we decode the incoming bytes into a meaningful command. We'll split on newlines, and
     // parse the resulting line using `Command::parse()`. This method will be called by tokio.
     fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Command>, Self::Error> {
-        if let Some(newline_offset) = buf[self.next_index..].iter().position(|b| *b == b'\n') {
-            let newline_index = newline_offset + self.next_index;
-            let line = buf.split_to(newline_index + 1);
+        if self.skip_newline && !buf.is_empty() {
+            self.skip_newline = false;
+            if buf[0] == b'\n' {
+                let _ = buf.split_to(1);
+            }
+        }
+        if let Some(newline_index) = self.line_end(buf) {
+            if newline_index >= self.max_line_length {
+                return Err(ControlChanErrorKind::LineTooLong.into());
+            }
+            let mut line = buf.split_to(newline_index + 1);
             self.next_index = 0;
-            Ok(Some(line_parser::parse(line)?))
+            if line.ends_with(b"\r") {
+                // The parser expects a newline at the end.
+                line.extend_from_slice(b"\n");
+                self.skip_newline = buf.is_empty();
+            }
+            // A PASS line shares its memory with the read buffer, so the parser gets a copy of its
+            // own that it can wipe and this one is wiped here.
+            let secret = is_pass(&line);
+            let decoded = match self.charset.decode(&line) {
+                Cow::Borrowed(decoded) if secret => Bytes::copy_from_slice(decoded),
+                Cow::Borrowed(_) => line.split().freeze(),
+                Cow::Owned(decoded) => Bytes::from(decoded),
+            };
+            if secret {
+                line[..].zeroize();
+            }
+            Ok(Some(line_parser::parse(decoded)?))
+        } else if buf.len() >= self.max_line_length {
+            Err(ControlChanErrorKind::LineTooLong.into())
         } else {
             self.next_index = buf.len();
             Ok(None)
@@ -39,6 +104,10 @@ impl Decoder for FtpCodec {
     }
 }
 
+fn is_pass(line: &[u8]) -> bool {
+    line.get(..4).is_some_and(|verb| verb.eq_ignore_ascii_case(b"PASS"))
+}
+
 impl Encoder<Reply> for FtpCodec {
     type Error = ControlChanError;
 
@@ -60,20 +129,103 @@ impl Encoder<Reply> for FtpCodec {
                 // Get the last line since it needs to be preceded by the response code.
                 let last_line = lines.pop().unwrap_or_default();
 
-                // Lines starting with a digit should be indented
-                for it in lines.iter_mut() {
-                    if it.chars().next().unwrap().is_ascii_digit() {
-                        it.insert(0, ' ');
-                    }
-                }
                 if lines.is_empty() {
                     writeln!(buffer, "{} {}\r", code as u32, last_line)?;
                 } else {
-                    write!(buffer, "{}-{}\r\n{} {}\r\n", code as u32, lines.join("\r\n"), code as u32, last_line)?;
+                    // The first line follows the code and a dash. Per RFC 959 the lines in between
+                    // that start with a digit are padded, so that the client doesn't take them for
+                    // the last line. Empty ones are padded too since some clients skip them.
+                    write!(buffer, "{}-{}\r\n", code as u32, lines[0])?;
+                    for line in &lines[1..] {
+                        if line.is_empty() || line.starts_with(|c: char| c.is_ascii_digit()) {
+                            write!(buffer, " {}\r\n", line)?;
+                        } else {
+                            write!(buffer, "{}\r\n", line)?;
+                        }
+                    }
+                    writeln!(buffer, "{} {}\r", code as u32, last_line)?;
                 }
             }
         }
-        buf.extend(&buffer);
+        buf.extend(&*self.charset.encode(&String::from_utf8_lossy(&buffer)));
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::Encoding;
+    use crate::server::{controlchan::ReplyCode, encoding::Charset};
+    use pretty_assertions::assert_eq;
+
+    fn encode(reply: Reply) -> String {
+        let mut codec = FtpCodec::new(Charset::new(Encoding::Utf8), 8192);
+        let mut buf = BytesMut::new();
+        codec.encode(reply, &mut buf).unwrap();
+        String::from_utf8(buf.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn encodes_multiline_replies() {
+        let reply = Reply::multiline(ReplyCode::FileStatus)
+            .line("Status of /:")
+            .line("213 looks like the end")
+            .line("")
+            .line("two\r\nlines")
+            .line("End of status")
+            .build();
+        assert_eq!(
+            encode(reply),
+            "213-Status of /:\r\n 213 looks like the end\r\n \r\ntwo\r\nlines\r\n213 End of status\r\n"
+        );
+    }
+
+    #[test]
+    fn encodes_single_line_multiline_reply() {
+        assert_eq!(encode(Reply::new_multiline(ReplyCode::SystemStatus, ["only"])), "211 only\r\n");
+    }
+
+    #[test]
+    fn decodes_pass_from_a_copy_of_the_line() {
+        let mut codec = FtpCodec::new(Charset::new(Encoding::Utf8), 8192);
+        let mut buf = BytesMut::from("pass s3cr3t\r\nNOOP\r\n");
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(Command::Pass { password: "s3cr3t".into() }));
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(Command::Noop));
+        assert!(is_pass(b"PASS\r\n"));
+        assert!(!is_pass(b"PASV\r\n"));
+    }
+
+    #[test]
+    fn rejects_lines_that_are_too_long() {
+        let mut codec = FtpCodec::new(Charset::new(Encoding::Utf8), 8);
+        let mut buf = BytesMut::from("NOOP\r\n");
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(Command::Noop));
+        let mut buf = BytesMut::from("STAT /a\r\n");
+        assert_eq!(codec.decode(&mut buf).unwrap_err().kind(), &ControlChanErrorKind::LineTooLong);
+        // Also without a newline, so that nothing is buffered endlessly.
+        let mut codec = FtpCodec::new(Charset::new(Encoding::Utf8), 8);
+        let mut buf = BytesMut::from("NOOP");
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        buf.extend_from_slice(b"NOOP");
+        assert_eq!(codec.decode(&mut buf).unwrap_err().kind(), &ControlChanErrorKind::LineTooLong);
+    }
+
+    #[test]
+    fn decodes_lines_that_end_in_a_bare_carriage_return() {
+        let mut codec = FtpCodec::new(Charset::new(Encoding::Utf8), 8192).bare_cr(true);
+        let mut buf = BytesMut::from("NOOP\rPWD\r\nSYST\nCWD a\r\0b\r");
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(Command::Noop));
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(Command::Pwd));
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(Command::Syst));
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(Command::Cwd { path: "a\rb".into() }));
+        // The newline of a CRLF that was split over two reads is dropped.
+        buf.extend_from_slice(b"\nNOOP\r\n");
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(Command::Noop));
+        assert!(buf.is_empty());
+
+        let mut codec = FtpCodec::new(Charset::new(Encoding::Utf8), 8192);
+        let mut buf = BytesMut::from("NOOP\rPWD\r\n");
+        assert_ne!(codec.decode(&mut buf).ok().flatten(), Some(Command::Noop));
+    }
+}
@@ -1,10 +1,10 @@
 use crate::server::{
-    controlchan::commands::{AuthParam, ModeParam, Opt, ProtParam, StruParam},
+    controlchan::commands::{AuthParam, ModeParam, Opt, ProtParam, StruParam, TypeParam},
     password::Password,
 };
 
 use bytes::Bytes;
-use std::{fmt, path::PathBuf};
+use std::{fmt, path::PathBuf, time::SystemTime};
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Command {
@@ -22,10 +22,14 @@ pub enum Command {
     },
     Syst,
     Stat {
-        /// The bytes making up the path about which information is requested, if given.
-        path: Option<Bytes>,
+        /// The path about which information is requested, if given.
+        path: Option<String>,
+    },
+    Type {
+        /// The representation type to which the client would like to switch. Only the `Ascii` and
+        /// `Image` types are supported by us.
+        type_: TypeParam,
     },
-    Type,
     Stru {
         /// The structure to which the client would like to switch. Only the `File` structure is
         /// supported by us.
@@ -58,10 +62,17 @@ pub enum Command {
         path: Option<String>,
     },
     Nlst {
+        /// Arguments passed along with the nlst command, like -R.
+        options: Option<String>,
         /// The path of the file/directory the clients wants to list.
         path: Option<String>,
     },
     Feat,
+    /// RFC 2640 language negotiation
+    Lang {
+        /// The language tag, or None to go back to the default language.
+        language: Option<String>,
+    },
     Pwd,
     Cwd {
         /// The path the client would like to change directory to.
@@ -115,20 +126,189 @@ pub enum Command {
     },
     /// Modification Time (MDTM) as specified in RFC 3659.
     /// This command can be used to determine when a file in the server NVFS was last modified.
+    /// Some clients also use the non-standard `MDTM YYYYMMDDHHMMSS path` form to set it.
     Mdtm {
         file: PathBuf,
+        /// The time to set, in the setter form.
+        modified: Option<SystemTime>,
     },
     Md5 {
         file: PathBuf,
     },
+    /// SITE CHOWN, changes the owner and/or group (`<owner>[:<group>]`) of a file.
+    Chown {
+        owner: String,
+        file: PathBuf,
+    },
+    /// SITE SYMLINK, creates a symbolic link to `target` named `link`.
+    Symlink {
+        target: PathBuf,
+        link: PathBuf,
+    },
+    /// SITE CPFR, the first half of a server-side copy as done by ProFTPD's mod_copy.
+    Cpfr {
+        file: PathBuf,
+    },
+    /// SITE CPTO, completes the server-side copy started with CPFR.
+    Cpto {
+        file: PathBuf,
+    },
+    /// A SITE subcommand that isn't built in, handled by the site commands registered with the
+    /// ServerBuilder.
+    Site {
+        name: String,
+        arguments: String,
+    },
     Other {
         command_name: String,
         arguments: String,
     },
 }
 
+impl Command {
+    // Returns the FTP verb of the command in upper case. Site commands are named without the SITE
+    // prefix.
+    pub(crate) fn name(&self) -> &str {
+        match self {
+            Command::User { .. } => "USER",
+            Command::Pass { .. } => "PASS",
+            Command::Acct { .. } => "ACCT",
+            Command::Syst => "SYST",
+            Command::Stat { .. } => "STAT",
+            Command::Type { .. } => "TYPE",
+            Command::Stru { .. } => "STRU",
+            Command::Mode { .. } => "MODE",
+            Command::Help => "HELP",
+            Command::Noop => "NOOP",
+            Command::Pasv => "PASV",
+            Command::Port { .. } => "PORT",
+            Command::Retr { .. } => "RETR",
+            Command::Stor { .. } => "STOR",
+            Command::List { .. } => "LIST",
+            Command::Nlst { .. } => "NLST",
+            Command::Feat => "FEAT",
+            Command::Lang { .. } => "LANG",
+            Command::Pwd => "PWD",
+            Command::Cwd { .. } => "CWD",
+            Command::Cdup => "CDUP",
+            Command::Opts { .. } => "OPTS",
+            Command::Dele { .. } => "DELE",
+            Command::Rmd { .. } => "RMD",
+            Command::Quit => "QUIT",
+            Command::Mkd { .. } => "MKD",
+            Command::Allo { .. } => "ALLO",
+            Command::Abor => "ABOR",
+            Command::Stou => "STOU",
+            Command::Rnfr { .. } => "RNFR",
+            Command::Rnto { .. } => "RNTO",
+            Command::Auth { .. } => "AUTH",
+            Command::Ccc => "CCC",
+            Command::Pbsz {} => "PBSZ",
+            Command::Prot { .. } => "PROT",
+            Command::Size { .. } => "SIZE",
+            Command::Rest { .. } => "REST",
+            Command::Mdtm { .. } => "MDTM",
+            Command::Md5 { .. } => "MD5",
+            Command::Chown { .. } => "CHOWN",
+            Command::Symlink { .. } => "SYMLINK",
+            Command::Cpfr { .. } => "CPFR",
+            Command::Cpto { .. } => "CPTO",
+            Command::Site { name, .. } => name,
+            Command::Other { command_name, .. } => command_name,
+        }
+    }
+
+    // Passes the paths the client supplied with the command through the given function, replacing
+    // them with what it returns. Stops at the first error.
+    pub(crate) fn try_map_paths<E, F>(self, mut filter: F) -> Result<Command, E>
+    where
+        F: FnMut(String) -> Result<String, E>,
+    {
+        let filter_buf = |path: PathBuf, filter: &mut F| filter(path.to_string_lossy().into_owned()).map(PathBuf::from);
+        let command = match self {
+            Command::Stat { path: Some(path) } => Command::Stat { path: Some(filter(path)?) },
+            Command::Retr { path } => Command::Retr { path: filter(path)? },
+            Command::Stor { path } => Command::Stor { path: filter(path)? },
+            Command::List { options, path: Some(path) } => Command::List {
+                options,
+                path: Some(filter(path)?),
+            },
+            Command::Nlst { options, path: Some(path) } => Command::Nlst {
+                options,
+                path: Some(filter(path)?),
+            },
+            Command::Cwd { path } => Command::Cwd {
+                path: filter_buf(path, &mut filter)?,
+            },
+            Command::Dele { path } => Command::Dele { path: filter(path)? },
+            Command::Rmd { path } => Command::Rmd { path: filter(path)? },
+            Command::Mkd { path } => Command::Mkd {
+                path: filter_buf(path, &mut filter)?,
+            },
+            Command::Rnfr { file } => Command::Rnfr {
+                file: filter_buf(file, &mut filter)?,
+            },
+            Command::Rnto { file } => Command::Rnto {
+                file: filter_buf(file, &mut filter)?,
+            },
+            Command::Size { file } => Command::Size {
+                file: filter_buf(file, &mut filter)?,
+            },
+            Command::Mdtm { file, modified } => Command::Mdtm {
+                file: filter_buf(file, &mut filter)?,
+                modified,
+            },
+            Command::Md5 { file } => Command::Md5 {
+                file: filter_buf(file, &mut filter)?,
+            },
+            Command::Chown { owner, file } => Command::Chown {
+                owner,
+                file: filter_buf(file, &mut filter)?,
+            },
+            Command::Symlink { target, link } => Command::Symlink {
+                target: filter_buf(target, &mut filter)?,
+                link: filter_buf(link, &mut filter)?,
+            },
+            Command::Cpfr { file } => Command::Cpfr {
+                file: filter_buf(file, &mut filter)?,
+            },
+            Command::Cpto { file } => Command::Cpto {
+                file: filter_buf(file, &mut filter)?,
+            },
+            command => command,
+        };
+        Ok(command)
+    }
+}
+
 impl fmt::Display for Command {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         fmt::Debug::fmt(self, f)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::controlchan::line_parser::parse;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn maps_stat_paths_without_losing_raw_bytes() {
+        let command = parse(&b"STAT caf\xe9.txt\r\n"[..]).unwrap();
+        let mut seen = vec![];
+        let command = command
+            .try_map_paths(|path| {
+                seen.push(path.clone());
+                Ok::<_, std::convert::Infallible>(format!("/{}", path))
+            })
+            .unwrap();
+        assert_eq!(seen, vec!["caf\u{efe9}.txt".to_string()]);
+        assert_eq!(
+            command,
+            Command::Stat {
+                path: Some("/caf\u{efe9}.txt".to_string())
+            }
+        );
+    }
+}
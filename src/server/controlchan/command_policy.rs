@@ -0,0 +1,122 @@
+use crate::{
+    auth::UserDetail,
+    options::{AccessMode, CommandPolicy},
+    server::{
+        controlchan::{error::ControlChanError, middleware::ControlChanMiddleware},
+        session::SharedSession,
+        Command, Event, Reply, ReplyCode,
+    },
+    storage::{Metadata, StorageBackend},
+};
+use async_trait::async_trait;
+
+// Control channel middleware that rejects the commands the configured
+// [`CommandPolicy`](crate::options::CommandPolicy) doesn't allow, as well as the commands that read
+// when in [`AccessMode::UploadOnly`](crate::options::AccessMode::UploadOnly). The settings of the
+// logged in user, if it has them, take precedence over the server-wide ones.
+pub struct CommandPolicyMiddleware<Storage, User, Next>
+where
+    User: UserDetail + 'static,
+    Storage: StorageBackend<User> + 'static,
+    Storage::Metadata: Metadata,
+    Next: ControlChanMiddleware,
+{
+    pub session: SharedSession<Storage, User>,
+    pub policy: CommandPolicy,
+    pub access_mode: AccessMode,
+    pub next: Next,
+}
+
+#[async_trait]
+impl<Storage, User, Next> ControlChanMiddleware for CommandPolicyMiddleware<Storage, User, Next>
+where
+    User: UserDetail + 'static,
+    Storage: StorageBackend<User> + 'static,
+    Storage::Metadata: Metadata,
+    Next: ControlChanMiddleware,
+{
+    async fn handle(&mut self, event: Event) -> Result<Reply, ControlChanError> {
+        if let Event::Command(command) = &event {
+            let (user_policy, user_access_mode) = {
+                let session = self.session.lock().await;
+                match &*session.user {
+                    Some(user) => (user.command_policy(), user.access_mode()),
+                    None => (None, None),
+                }
+            };
+            if !user_policy.as_ref().unwrap_or(&self.policy).allows(policy_name(command)) {
+                return Ok(Reply::new(ReplyCode::CommandNotImplemented, "Command not allowed"));
+            }
+            if user_access_mode.unwrap_or(self.access_mode) == AccessMode::UploadOnly && reads(command) {
+                return Ok(Reply::new(ReplyCode::FileError, "Permission denied, this is an upload-only account"));
+            }
+        }
+        self.next.handle(event).await
+    }
+}
+
+// Returns the name the policy knows the command by. SITE RMDIR removes directories like RMD does,
+// so it falls under the same name.
+fn policy_name(command: &Command) -> &str {
+    match command {
+        Command::Site { name, .. } if name.eq_ignore_ascii_case("RMDIR") => "RMD",
+        command => command.name(),
+    }
+}
+
+// Tells if the command would reveal stored content or names.
+fn reads(command: &Command) -> bool {
+    matches!(
+        command,
+        Command::Retr { .. }
+            | Command::List { .. }
+            | Command::Nlst { .. }
+            | Command::Size { .. }
+            | Command::Mdtm { .. }
+            | Command::Md5 { .. }
+            | Command::Cpfr { .. }
+            | Command::Stat { path: Some(_) }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policy_allows() {
+        let policy = CommandPolicy::deny(["dele", "RMD"]);
+        assert!(!policy.allows("DELE"));
+        assert!(!policy.allows("rmd"));
+        assert!(policy.allows("STOR"));
+
+        let policy = CommandPolicy::allow_only(["STOR", "PWD"]);
+        assert!(policy.allows("stor"));
+        assert!(!policy.allows("RETR"));
+        assert!(policy.allows("USER"));
+        assert!(policy.allows("QUIT"));
+    }
+
+    #[test]
+    fn site_rmdir_falls_under_rmd() {
+        let rmdir = Command::Site {
+            name: "rmdir".to_string(),
+            arguments: "-R dir".to_string(),
+        };
+        assert_eq!(policy_name(&rmdir), "RMD");
+        assert!(!CommandPolicy::deny(["RMD"]).allows(policy_name(&rmdir)));
+        let age = Command::Site {
+            name: "AGE".to_string(),
+            arguments: "file".to_string(),
+        };
+        assert_eq!(policy_name(&age), "AGE");
+    }
+
+    #[test]
+    fn detects_reading_commands() {
+        assert!(reads(&Command::Retr { path: "file".to_string() }));
+        assert!(reads(&Command::Nlst { options: None, path: None }));
+        assert!(!reads(&Command::Stor { path: "file".to_string() }));
+        assert!(!reads(&Command::Stat { path: None }));
+    }
+}
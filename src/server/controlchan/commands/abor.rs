@@ -11,14 +11,18 @@
 
 use crate::auth::UserDetail;
 use crate::{
-    server::controlchan::{
-        error::ControlChanError,
-        handler::{CommandContext, CommandHandler},
-        Reply, ReplyCode,
+    server::{
+        chancomms::ControlChanMsg,
+        controlchan::{
+            error::ControlChanError,
+            handler::{CommandContext, CommandHandler},
+            Reply, ReplyCode,
+        },
     },
     storage::{Metadata, StorageBackend},
 };
 use async_trait::async_trait;
+use tokio::sync::oneshot;
 
 #[derive(Debug)]
 pub struct Abor;
@@ -33,16 +37,29 @@ where
     #[tracing_attributes::instrument]
     async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
         let mut session = args.session.lock().await;
-        let logger = args.logger;
+        let (ack_tx, ack_rx) = oneshot::channel();
         match session.data_abort_tx.take() {
-            Some(tx) => {
-                tokio::spawn(async move {
-                    if let Err(err) = tx.send(()).await {
-                        slog::warn!(logger, "abort failed: {}", err);
-                    }
-                });
-                Ok(Reply::new(ReplyCode::ClosingDataConnection, "Closed data channel"))
-            }
+            Some(tx) => match tx.try_send(ack_tx) {
+                // The data channel replies with 426 for an interrupted transfer and with 226 for
+                // ABOR. Only if it closed before it got to the abort, it is left to us.
+                Ok(()) => {
+                    let logger = args.logger;
+                    let tx_control_chan = args.tx_control_chan;
+                    tokio::spawn(async move {
+                        if ack_rx.await.is_err() {
+                            let reply = Reply::new(ReplyCode::ClosingDataConnection, "Data channel already closed");
+                            if let Err(err) = tx_control_chan.send(ControlChanMsg::CommandChannelReply(reply)).await {
+                                slog::warn!(logger, "Could not send internal message to reply to ABOR: {}", err);
+                            }
+                        }
+                    });
+                    Ok(Reply::none())
+                }
+                Err(err) => {
+                    slog::debug!(args.logger, "Data channel no longer listening for abort: {}", err);
+                    Ok(Reply::new(ReplyCode::ClosingDataConnection, "Data channel already closed"))
+                }
+            },
             None => Ok(Reply::new(ReplyCode::ClosingDataConnection, "Data channel already closed")),
         }
     }
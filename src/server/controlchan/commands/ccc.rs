@@ -1,11 +1,21 @@
 //! The RFC 2228 Clear Command Channel (`CCC`) command
+//
+// Ends TLS on the control channel after it was secured with AUTH TLS, so that NAT helpers in
+// between can see and rewrite the replies to PASV. The data channel keeps the protection level that
+// was set with PROT. RFC 4217 describes how the TLS session is closed: the server replies with 200,
+// sends a TLS close_notify alert and waits for the one of the client.
 
-use crate::auth::UserDetail;
-use crate::server::controlchan::error::ControlChanError;
-use crate::server::controlchan::handler::{CommandContext, CommandHandler};
-use crate::server::{Reply, ReplyCode};
-use crate::storage::{Metadata, StorageBackend};
-
+use crate::{
+    auth::UserDetail,
+    server::{
+        controlchan::{
+            error::ControlChanError,
+            handler::{CommandContext, CommandHandler},
+        },
+        Reply, ReplyCode,
+    },
+    storage::{Metadata, StorageBackend},
+};
 use async_trait::async_trait;
 
 #[derive(Debug)]
@@ -19,20 +29,13 @@ where
     Storage::Metadata: Metadata,
 {
     #[tracing_attributes::instrument]
-    async fn handle(&self, _args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
-        // let mut tx: Sender<InternalMsg> = args.tx.clone();
-        // let session = args.session.lock().await;
-        // let logger = args.logger;
-        // if session.cmd_tls {
-        //     tokio::spawn(async move {
-        //         if let Err(err) = tx.send(InternalMsg::PlaintextControlChannel).await {
-        //             slog::warn!(logger, "{}", err);
-        //         }
-        //     });
-        //     Ok(Reply::new(ReplyCode::CommandOkay, "control channel in plaintext now"))
-        // } else {
-        //     Ok(Reply::new(ReplyCode::Resp533, "control channel already in plaintext mode"))
-        // }
-        Ok(Reply::new(ReplyCode::CommandNotImplemented, "CCC not implemented"))
+    async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
+        let session = args.session.lock().await;
+        if !session.cmd_tls {
+            return Ok(Reply::new(ReplyCode::Resp533, "Control channel already in plaintext mode"));
+        }
+        // The control loop ends TLS right after this reply has been sent, before it reads another
+        // command, and then passes on ControlChanMsg::PlaintextControlChannel.
+        Ok(Reply::new(ReplyCode::CommandOkay, "Control channel in plaintext now"))
     }
 }
@@ -5,18 +5,23 @@
 // directory trees between operating systems having different
 // syntaxes for naming the parent directory.  The reply codes
 // shall be identical to the reply codes of CWD.
+//
+// It goes through the storage back-end like CWD does, so that back-ends that keep their own working
+// directory follow along.
 
 use crate::{
     auth::UserDetail,
     server::controlchan::{
         error::ControlChanError,
         handler::{CommandContext, CommandHandler},
-        Reply, ReplyCode,
+        Reply,
     },
     storage::{Metadata, StorageBackend},
 };
 use async_trait::async_trait;
 
+use super::Cwd;
+
 #[derive(Debug)]
 pub struct Cdup;
 
@@ -29,8 +34,10 @@ where
 {
     #[tracing_attributes::instrument]
     async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
-        let mut session = args.session.lock().await;
-        session.cwd.pop();
-        Ok(Reply::new(ReplyCode::FileActionOkay, "OK"))
+        let parent = {
+            let session = args.session.lock().await;
+            session.cwd.parent().unwrap_or(&session.cwd).to_path_buf()
+        };
+        Cwd::new(parent).handle(args).await
     }
 }
@@ -0,0 +1,120 @@
+//! The `SITE CHOWN` command, which changes the owner and/or group of a file like chown(1) does:
+//! `SITE CHOWN <owner>[:<group>] <path>`. Owners and groups are given by name or numeric id. Only
+//! available with storage back-ends that advertise [`FEATURE_CHOWN`].
+
+use crate::server::path;
+use crate::{
+    auth::UserDetail,
+    server::{
+        chancomms::ControlChanMsg,
+        controlchan::{
+            error::ControlChanError,
+            handler::{CommandContext, CommandHandler},
+            Reply, ReplyCode,
+        },
+    },
+    storage::{StorageBackend, FEATURE_CHOWN},
+};
+use async_trait::async_trait;
+use nix::unistd::{Group, User as SystemUser};
+use std::{path::PathBuf, sync::Arc};
+use tokio::sync::mpsc::Sender;
+
+#[derive(Debug)]
+pub struct Chown {
+    owner: String,
+    path: PathBuf,
+}
+
+impl Chown {
+    pub fn new(owner: String, path: PathBuf) -> Self {
+        Chown { owner, path }
+    }
+}
+
+// Resolves an `<owner>[:<group>]` argument to a uid and gid. Either part may be left out. Returns
+// the name that couldn't be resolved on failure.
+fn resolve_owner(spec: &str) -> Result<(Option<u32>, Option<u32>), String> {
+    let (owner, group) = match spec.split_once(':') {
+        Some((owner, group)) => (owner, group),
+        None => (spec, ""),
+    };
+    let uid = match owner {
+        "" => None,
+        owner => match owner.parse::<u32>() {
+            Ok(uid) => Some(uid),
+            Err(_) => match SystemUser::from_name(owner) {
+                Ok(Some(user)) => Some(user.uid.as_raw()),
+                _ => return Err(owner.to_string()),
+            },
+        },
+    };
+    let gid = match group {
+        "" => None,
+        group => match group.parse::<u32>() {
+            Ok(gid) => Some(gid),
+            Err(_) => match Group::from_name(group) {
+                Ok(Some(group)) => Some(group.gid.as_raw()),
+                _ => return Err(group.to_string()),
+            },
+        },
+    };
+    Ok((uid, gid))
+}
+
+#[async_trait]
+impl<Storage, User> CommandHandler<Storage, User> for Chown
+where
+    User: UserDetail + 'static,
+    Storage: StorageBackend<User> + 'static,
+{
+    #[tracing_attributes::instrument]
+    async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
+        if args.storage_features & FEATURE_CHOWN == 0 {
+            return Ok(Reply::new(ReplyCode::CommandNotImplemented, "Not supported by the selected storage back-end."));
+        }
+        let (uid, gid) = match resolve_owner(&self.owner) {
+            Ok((None, None)) => return Ok(Reply::new(ReplyCode::ParameterSyntaxError, "Owner or group expected")),
+            Ok(ids) => ids,
+            Err(name) => return Ok(Reply::new_with_string(ReplyCode::FileError, format!("Unknown user or group: {}", name))),
+        };
+
+        let session = args.session.lock().await;
+        let user = session.user.clone();
+        let storage = Arc::clone(&session.storage);
+        let path = path::resolve(&session.cwd, self.path.clone());
+        let tx: Sender<ControlChanMsg> = args.tx_control_chan.clone();
+        let logger = args.logger;
+        tokio::spawn(async move {
+            let msg = match storage.chown((*user).as_ref().unwrap(), &path, uid, gid).await {
+                Ok(()) => {
+                    slog::info!(logger, "SITE CHOWN: Changed owner of {:?} to {:?}:{:?}", path, uid, gid);
+                    ControlChanMsg::CommandChannelReply(Reply::new(ReplyCode::CommandOkay, "SITE CHOWN command successful"))
+                }
+                Err(err) => {
+                    slog::warn!(logger, "SITE CHOWN: Failed to change owner of {:?}: {}", path, err);
+                    ControlChanMsg::StorageError(err.with_context("SITE CHOWN", &path))
+                }
+            };
+            if let Err(err) = tx.send(msg).await {
+                slog::warn!(logger, "SITE CHOWN: Could not send internal message to notify of the result: {}", err);
+            }
+        });
+        Ok(Reply::none())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn resolves_owner_and_group() {
+        assert_eq!(resolve_owner("1000:100"), Ok((Some(1000), Some(100))));
+        assert_eq!(resolve_owner("1000"), Ok((Some(1000), None)));
+        assert_eq!(resolve_owner(":100"), Ok((None, Some(100))));
+        assert_eq!(resolve_owner("root:root"), Ok((Some(0), Some(0))));
+        assert_eq!(resolve_owner("no-such-user-here"), Err(String::from("no-such-user-here")));
+    }
+}
@@ -0,0 +1,50 @@
+//! The `SITE CPFR` command, which selects the file to copy with `SITE CPTO`. Compatible with
+//! ProFTPD's mod_copy.
+
+use crate::server::path;
+use crate::{
+    auth::UserDetail,
+    server::controlchan::{
+        error::ControlChanError,
+        handler::{CommandContext, CommandHandler},
+        Reply, ReplyCode,
+    },
+    storage::{Metadata, StorageBackend},
+};
+use async_trait::async_trait;
+use std::{path::PathBuf, sync::Arc};
+
+#[derive(Debug)]
+pub struct Cpfr {
+    path: PathBuf,
+}
+
+impl Cpfr {
+    pub fn new(path: PathBuf) -> Self {
+        Cpfr { path }
+    }
+}
+
+#[async_trait]
+impl<Storage, User> CommandHandler<Storage, User> for Cpfr
+where
+    User: UserDetail + 'static,
+    Storage: StorageBackend<User> + 'static,
+    Storage::Metadata: Metadata,
+{
+    #[tracing_attributes::instrument]
+    async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
+        let mut session = args.session.lock().await;
+        let storage = Arc::clone(&session.storage);
+        let from = path::resolve(&session.cwd, self.path.clone());
+        let user = (*session.user).as_ref().unwrap();
+        match storage.metadata(user, &from).await {
+            Ok(metadata) if metadata.is_file() => {
+                session.copy_from = Some(from);
+                Ok(Reply::new(ReplyCode::FileActionPending, "File exists, ready for destination name"))
+            }
+            Ok(_) => Ok(Reply::new(ReplyCode::FileError, "Not a regular file")),
+            Err(_) => Ok(Reply::new(ReplyCode::FileError, "File not found")),
+        }
+    }
+}
@@ -0,0 +1,74 @@
+//! The `SITE CPTO` command, which copies the file selected with `SITE CPFR` on the server side,
+//! without the client having to download and upload it again. Compatible with ProFTPD's mod_copy.
+
+use crate::server::path;
+use crate::server::ControlChanMsg;
+use crate::storage::{Metadata, StorageBackend};
+use crate::{
+    auth::UserDetail,
+    server::controlchan::{
+        error::ControlChanError,
+        handler::{CommandContext, CommandHandler},
+        Reply, ReplyCode,
+    },
+};
+use async_trait::async_trait;
+use std::{path::PathBuf, sync::Arc};
+
+#[derive(Debug)]
+pub struct Cpto {
+    path: PathBuf,
+}
+
+impl Cpto {
+    pub fn new(path: PathBuf) -> Self {
+        Cpto { path }
+    }
+}
+
+#[async_trait]
+impl<Storage, User> CommandHandler<Storage, User> for Cpto
+where
+    User: UserDetail + 'static,
+    Storage: StorageBackend<User> + 'static,
+    Storage::Metadata: Metadata,
+{
+    #[tracing_attributes::instrument]
+    async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
+        let CommandContext {
+            logger,
+            session,
+            tx_control_chan,
+            ..
+        } = args;
+        // The session is not kept locked while copying since that may take a while.
+        let (storage, user, from, to) = {
+            let mut session = session.lock().await;
+            let from = match session.copy_from.take() {
+                Some(from) => from,
+                None => return Ok(Reply::new(ReplyCode::TransientFileError, "Please tell me what file you want to copy first")),
+            };
+            let to = path::resolve(&session.cwd, self.path.clone());
+            (Arc::clone(&session.storage), session.user.clone(), from, to)
+        };
+        let user = (*user).as_ref().unwrap();
+        match storage.copy(user, &from, &to).await {
+            Ok(_) => {
+                slog::info!(logger, "CPTO: Successfully copied {:?} to {:?}", from, to);
+                let msg = ControlChanMsg::CopySuccess {
+                    from: from.to_string_lossy().to_string(),
+                    to: to.to_string_lossy().to_string(),
+                };
+                if let Err(err) = tx_control_chan.send(msg).await {
+                    slog::warn!(logger, "CPTO: Could not send internal message to notify of CPTO success: {}", err);
+                }
+            }
+            Err(err) => {
+                if let Err(err) = tx_control_chan.send(ControlChanMsg::StorageError(err.with_context("SITE CPTO", &to))).await {
+                    slog::warn!(logger, "CPTO: Could not send internal message to notify of CPTO failure: {}", err);
+                }
+            }
+        }
+        Ok(Reply::none())
+    }
+}
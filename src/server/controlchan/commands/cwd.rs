@@ -7,6 +7,7 @@
 // pathname specifying a directory or other system dependent
 // file group designator.
 
+use crate::server::path;
 use crate::{
     auth::UserDetail,
     server::{
@@ -44,22 +45,26 @@ where
     async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
         let mut session = args.session.lock().await;
         let storage: Arc<Storage> = Arc::clone(&session.storage);
-        let path = session.cwd.join(self.path.clone());
+        let path = path::resolve(&session.cwd, self.path.clone());
         let tx_success = args.tx_control_chan.clone();
         let tx_fail = args.tx_control_chan.clone();
         let logger = args.logger;
 
-        if let Err(err) = storage.cwd((*session.user).as_ref().unwrap(), path.clone()).await {
-            slog::warn!(logger, "CWD: Failed to change directory {:?}: {} ", path, err);
-            let r = tx_fail.send(ControlChanMsg::StorageError(err)).await;
-            if let Err(e) = r {
-                slog::warn!(logger, "CWD: Could not send internal message to notify of CWD error: {}", e);
+        match storage.change_dir((*session.user).as_ref().unwrap(), path.clone()).await {
+            Err(err) => {
+                slog::warn!(logger, "CWD: Failed to change directory {:?}: {} ", path, err);
+                let r = tx_fail.send(ControlChanMsg::StorageError(err.with_context("CWD", &path))).await;
+                if let Err(e) = r {
+                    slog::warn!(logger, "CWD: Could not send internal message to notify of CWD error: {}", e);
+                }
             }
-        } else {
-            let r = tx_success.send(ControlChanMsg::CwdSuccess).await;
-            session.cwd.push(path);
-            if let Err(e) = r {
-                slog::warn!(logger, "CWD: Could not send internal message to notify of CWD success: {}", e);
+            Ok(cwd) => {
+                let r = tx_success.send(ControlChanMsg::CwdSuccess).await;
+                session.cwd = cwd;
+                session.activity.set_cwd(session.cwd.clone());
+                if let Err(e) = r {
+                    slog::warn!(logger, "CWD: Could not send internal message to notify of CWD success: {}", e);
+                }
             }
         }
 
@@ -5,6 +5,7 @@
 // is desired (such as the query, "Do you really wish to delete?"),
 // it should be provided by the user-FTP process.
 
+use crate::server::path;
 use crate::{
     auth::UserDetail,
     server::{
@@ -44,7 +45,7 @@ where
         let session = args.session.lock().await;
         let storage = Arc::clone(&session.storage);
         let user = session.user.clone();
-        let path = session.cwd.join(self.path.clone());
+        let path = path::resolve(&session.cwd, self.path.clone());
         let path_str = path.to_string_lossy().to_string();
         let tx_success: Sender<ControlChanMsg> = args.tx_control_chan.clone();
         let tx_fail: Sender<ControlChanMsg> = args.tx_control_chan.clone();
@@ -58,7 +59,7 @@ where
                     }
                 }
                 Err(err) => {
-                    if let Err(err) = tx_fail.send(ControlChanMsg::StorageError(err)).await {
+                    if let Err(err) = tx_fail.send(ControlChanMsg::StorageError(err.with_context("DELE", path_str.as_str()))).await {
                         slog::warn!(logger, "DELE: Could not send internal message to notify of DELE error: {}", err);
                     }
                 }
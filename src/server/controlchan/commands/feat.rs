@@ -4,13 +4,14 @@ use crate::{
     auth::UserDetail,
     server::{
         controlchan::{
+            commands::lang::DEFAULT_LANGUAGE,
             error::ControlChanError,
             handler::{CommandContext, CommandHandler},
             Reply, ReplyCode,
         },
-        ftpserver::options::SiteMd5,
+        ftpserver::options::{ModeZ, SiteMd5},
     },
-    storage::{Metadata, StorageBackend, FEATURE_RESTART, FEATURE_SITEMD5},
+    storage::{Metadata, StorageBackend, FEATURE_CHOWN, FEATURE_RESTART, FEATURE_SITEMD5, FEATURE_SYMLINK},
 };
 use async_trait::async_trait;
 
@@ -26,7 +27,30 @@ where
 {
     #[tracing_attributes::instrument]
     async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
-        let mut feat_text = vec![" SIZE", " MDTM", " UTF8"];
+        let mut feat_text = vec![" SIZE", " MDTM"];
+        let lang_feat;
+        {
+            let session = args.session.lock().await;
+            if session.charset.supports_utf8() {
+                feat_text.push(" UTF8");
+            }
+            if session.mode_z != ModeZ::Disabled {
+                feat_text.push(" MODE Z");
+            }
+            // The languages of RFC 2640, the current one marked with an asterisk.
+            if let Some(catalog) = &session.message_catalog {
+                let current = session.language.as_deref().unwrap_or(DEFAULT_LANGUAGE);
+                let languages: Vec<String> = std::iter::once(DEFAULT_LANGUAGE.to_string())
+                    .chain(catalog.languages())
+                    .map(|language| match language.eq_ignore_ascii_case(current) {
+                        true => format!("{}*", language.to_uppercase()),
+                        false => language.to_uppercase(),
+                    })
+                    .collect();
+                lang_feat = format!(" LANG {}", languages.join(";"));
+                feat_text.push(&lang_feat);
+            }
+        }
         // Add the features. According to the spec each feature line must be
         // indented by a space.
         if args.tls_configured {
@@ -40,13 +64,21 @@ where
         if args.sitemd5 != SiteMd5::None && args.storage_features & FEATURE_SITEMD5 > 0 {
             feat_text.push(" SITE MD5");
         }
+        if args.storage_features & FEATURE_CHOWN > 0 {
+            feat_text.push(" SITE CHOWN");
+        }
+        if args.storage_features & FEATURE_SYMLINK > 0 {
+            feat_text.push(" SITE SYMLINK");
+        }
 
         // Show them in alphabetical order.
         feat_text.sort_unstable();
-        feat_text.insert(0, "Extensions supported:");
-        feat_text.push("END");
 
-        let reply = Reply::new_multiline(ReplyCode::SystemStatus, feat_text);
+        let reply = Reply::multiline(ReplyCode::SystemStatus)
+            .line("Extensions supported:")
+            .lines(feat_text)
+            .line("END")
+            .build();
         Ok(reply)
     }
 }
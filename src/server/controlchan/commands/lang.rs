@@ -0,0 +1,102 @@
+//! The RFC 2640 Language Negotiation (`LANG`) command
+//
+// The LANG command is used to negotiate the language to be used by the server-PI for the
+// presentation of messages on the control connection. The argument is a language tag, or nothing
+// to return to the default language. If the server-PI does not support the requested language, it
+// replies with 504. The supported languages are listed by FEAT.
+
+use crate::{
+    auth::UserDetail,
+    options::MessageCatalog,
+    server::controlchan::{
+        error::ControlChanError,
+        handler::{CommandContext, CommandHandler},
+        Reply, ReplyCode,
+    },
+    storage::{Metadata, StorageBackend},
+};
+use async_trait::async_trait;
+
+// The language replies are written in.
+pub const DEFAULT_LANGUAGE: &str = "en";
+
+#[derive(Debug)]
+pub struct Lang {
+    language: Option<String>,
+}
+
+impl Lang {
+    pub fn new(language: Option<String>) -> Self {
+        Lang { language }
+    }
+}
+
+#[async_trait]
+impl<Storage, User> CommandHandler<Storage, User> for Lang
+where
+    User: UserDetail + 'static,
+    Storage: StorageBackend<User> + 'static,
+    Storage::Metadata: Metadata,
+{
+    #[tracing_attributes::instrument]
+    async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
+        let mut session = args.session.lock().await;
+        let requested = self.language.as_deref().unwrap_or(DEFAULT_LANGUAGE);
+        match negotiate(session.message_catalog.as_deref(), requested) {
+            Some(language) => {
+                session.language = language;
+                Ok(Reply::new_with_string(
+                    ReplyCode::CommandOkay,
+                    format!("Changed language to {}", session.language.as_deref().unwrap_or(DEFAULT_LANGUAGE)),
+                ))
+            }
+            None => Ok(Reply::new(ReplyCode::CommandNotImplementedForParameter, "Language not supported")),
+        }
+    }
+}
+
+// Picks the language of the catalog that matches the requested one. A language with a region, like
+// fr-CA, falls back to the language itself. Returns Some(None) for English and None if the
+// language is not available.
+fn negotiate(catalog: Option<&dyn MessageCatalog>, requested: &str) -> Option<Option<String>> {
+    let primary = requested.split('-').next().unwrap_or_default();
+    if primary.eq_ignore_ascii_case(DEFAULT_LANGUAGE) {
+        return Some(None);
+    }
+    let languages = catalog?.languages();
+    languages
+        .iter()
+        .find(|language| language.eq_ignore_ascii_case(requested))
+        .or_else(|| languages.iter().find(|language| language.eq_ignore_ascii_case(primary)))
+        .map(|language| Some(language.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[derive(Debug)]
+    struct Catalog;
+
+    impl MessageCatalog for Catalog {
+        fn languages(&self) -> Vec<String> {
+            vec!["fr".to_string(), "pt-BR".to_string()]
+        }
+
+        fn translate(&self, _language: &str, _code: u32, _message: &str) -> Option<String> {
+            None
+        }
+    }
+
+    #[test]
+    fn negotiates_language() {
+        assert_eq!(negotiate(Some(&Catalog), "EN-us"), Some(None));
+        assert_eq!(negotiate(Some(&Catalog), "FR"), Some(Some("fr".to_string())));
+        assert_eq!(negotiate(Some(&Catalog), "fr-CA"), Some(Some("fr".to_string())));
+        assert_eq!(negotiate(Some(&Catalog), "pt-br"), Some(Some("pt-BR".to_string())));
+        assert_eq!(negotiate(Some(&Catalog), "pt"), None);
+        assert_eq!(negotiate(None, "fr"), None);
+        assert_eq!(negotiate(None, "en"), Some(None));
+    }
+}
@@ -14,6 +14,7 @@
 // in a program, but may be quite useful to a human user.
 
 use crate::server::chancomms::DataChanCmd;
+use crate::server::glob;
 use crate::{
     auth::UserDetail,
     server::controlchan::{
@@ -46,6 +47,10 @@ where
             _ => panic!("Programmer error, expected command to be LIST"),
         };
         let logger = args.logger;
+        if let Some(Err(err)) = path_opt.as_deref().map(glob::compile_path) {
+            slog::warn!(logger, "LIST: refusing wildcard pattern in {:?}: {}", path_opt, err);
+            return Ok(Reply::new(ReplyCode::ParameterSyntaxError, "Wildcard pattern too long or too complex"));
+        }
         match session.data_cmd_tx.take() {
             Some(tx) => {
                 tokio::spawn(async move {
@@ -1,3 +1,4 @@
+use crate::server::path;
 use crate::{
     auth::UserDetail,
     server::{
@@ -12,7 +13,7 @@ use crate::{
 };
 use async_trait::async_trait;
 use chrono::{offset::Utc, DateTime};
-use std::{path::PathBuf, sync::Arc};
+use std::{path::PathBuf, sync::Arc, time::SystemTime};
 use tokio::sync::mpsc::Sender;
 
 const RFC3659_TIME: &str = "%Y%m%d%H%M%S";
@@ -20,11 +21,13 @@ const RFC3659_TIME: &str = "%Y%m%d%H%M%S";
 #[derive(Debug)]
 pub struct Mdtm {
     path: PathBuf,
+    // Set for the non-standard `MDTM YYYYMMDDHHMMSS path` form that changes the time.
+    modified: Option<SystemTime>,
 }
 
 impl Mdtm {
-    pub fn new(path: PathBuf) -> Self {
-        Mdtm { path }
+    pub fn new(path: PathBuf, modified: Option<SystemTime>) -> Self {
+        Mdtm { path, modified }
     }
 }
 
@@ -40,11 +43,36 @@ where
         let session = args.session.lock().await;
         let user = session.user.clone();
         let storage = Arc::clone(&session.storage);
-        let path = session.cwd.join(self.path.clone());
+        let path = path::resolve(&session.cwd, self.path.clone());
         let tx_success: Sender<ControlChanMsg> = args.tx_control_chan.clone();
         let tx_fail: Sender<ControlChanMsg> = args.tx_control_chan.clone();
         let logger = args.logger;
 
+        if let Some(modified) = self.modified {
+            if !session.mdtm_setter {
+                return Ok(Reply::new(
+                    ReplyCode::CommandNotImplementedForParameter,
+                    "Setting the modification time is not allowed",
+                ));
+            }
+            tokio::spawn(async move {
+                let msg = match storage.set_modified((*user).as_ref().unwrap(), &path, modified).await {
+                    Ok(()) => {
+                        slog::info!(logger, "MDTM: Successfully set the modification time of path {:?}", path);
+                        ControlChanMsg::CommandChannelReply(Reply::new_with_string(
+                            ReplyCode::FileStatus,
+                            DateTime::<Utc>::from(modified).format(RFC3659_TIME).to_string(),
+                        ))
+                    }
+                    Err(err) => ControlChanMsg::StorageError(err.with_context("MDTM", &path)),
+                };
+                if let Err(err) = tx_success.send(msg).await {
+                    slog::warn!(logger, "MDTM: Could not send internal message to notify of MDTM result: {}", err);
+                }
+            });
+            return Ok(Reply::none());
+        }
+
         tokio::spawn(async move {
             match storage.metadata((*user).as_ref().unwrap(), &path).await {
                 Ok(metadata) => {
@@ -57,7 +85,7 @@ where
                                 path,
                                 err
                             );
-                            if let Err(err) = tx_fail.send(ControlChanMsg::StorageError(err)).await {
+                            if let Err(err) = tx_fail.send(ControlChanMsg::StorageError(err.with_context("MDTM", &path))).await {
                                 slog::warn!(logger, "MDTM: Could not send internal message to notify of MDTM failure: {}", err);
                             };
                             None
@@ -78,7 +106,7 @@ where
                     }
                 }
                 Err(err) => {
-                    if let Err(err) = tx_fail.send(ControlChanMsg::StorageError(err)).await {
+                    if let Err(err) = tx_fail.send(ControlChanMsg::StorageError(err.with_context("MDTM", &path))).await {
                         slog::warn!(logger, "{}", err);
                     }
                 }
@@ -5,6 +5,7 @@
 // or as a subdirectory of the current working directory (if
 // the pathname is relative).
 
+use crate::server::path;
 use crate::{
     auth::UserDetail,
     server::{
@@ -44,14 +45,14 @@ where
         let session = args.session.lock().await;
         let user = session.user.clone();
         let storage = Arc::clone(&session.storage);
-        let path: PathBuf = session.cwd.join(self.path.clone());
+        let path: PathBuf = path::resolve(&session.cwd, self.path.clone());
         let path_str = path.to_string_lossy().to_string();
         let tx: Sender<ControlChanMsg> = args.tx_control_chan.clone();
         let logger = args.logger;
         tokio::spawn(async move {
             if let Err(err) = storage.mkd((*user).as_ref().unwrap(), &path).await {
                 slog::warn!(logger, "MKD: Failure creating directory {:?} {}", path_str, err);
-                if let Err(err) = tx.send(ControlChanMsg::StorageError(err)).await {
+                if let Err(err) = tx.send(ControlChanMsg::StorageError(err.with_context("MKD", &path))).await {
                     slog::warn!(logger, "MKD: Could not send internal message to notify of MKD failure: {}", err);
                 }
             } else {
@@ -3,6 +3,7 @@
 //! - [RFC 959 - FTP](https://tools.ietf.org/html/rfc959)
 //! - [RFC 3659 - Extensions to FTP](https://tools.ietf.org/html/rfc3659)
 //! - [RFC 2228 - FTP Security Extensions](https://tools.ietf.org/html/rfc2228)
+//! - [RFC 2640 - Internationalization of FTP](https://tools.ietf.org/html/rfc2640)
 
 mod abor;
 mod acct;
@@ -10,12 +11,15 @@ mod allo;
 mod auth;
 mod ccc;
 mod cdup;
+mod chown;
+mod cpfr;
+mod cpto;
 mod cwd;
 mod dele;
 mod feat;
 mod help;
+mod lang;
 mod list;
-mod md5;
 mod mdtm;
 mod mkd;
 mod mode;
@@ -34,26 +38,31 @@ mod retr;
 mod rmd;
 mod rnfr;
 mod rnto;
+mod site;
 mod size;
 mod stat;
 mod stor;
 mod stou;
 mod stru;
+mod symlink;
 mod syst;
 mod type_;
 mod user;
 
-pub use self::md5::Md5;
 pub use abor::Abor;
 pub use acct::Acct;
 pub use allo::Allo;
 pub use auth::{Auth, AuthParam};
 pub use ccc::Ccc;
 pub use cdup::Cdup;
+pub use chown::Chown;
+pub use cpfr::Cpfr;
+pub use cpto::Cpto;
 pub use cwd::Cwd;
 pub use dele::Dele;
 pub use feat::Feat;
 pub use help::Help;
+pub use lang::Lang;
 pub use list::List;
 pub use mdtm::Mdtm;
 pub use mkd::Mkd;
@@ -62,7 +71,9 @@ pub use nlst::Nlst;
 pub use noop::Noop;
 pub use opts::{Opt, Opts};
 pub use pass::Pass;
-pub use pasv::{make_pasv_reply, Pasv};
+#[cfg(feature = "proxy-protocol")]
+pub use pasv::make_pasv_reply;
+pub use pasv::Pasv;
 pub use pbsz::Pbsz;
 pub use port::Port;
 pub use prot::{Prot, ProtParam};
@@ -73,11 +84,13 @@ pub use retr::Retr;
 pub use rmd::Rmd;
 pub use rnfr::Rnfr;
 pub use rnto::Rnto;
+pub use site::Site;
 pub use size::Size;
 pub use stat::Stat;
 pub use stor::Stor;
 pub use stou::Stou;
 pub use stru::{Stru, StruParam};
+pub use symlink::Symlink;
 pub use syst::Syst;
-pub use type_::Type;
+pub use type_::{Type, TypeParam};
 pub use user::User;
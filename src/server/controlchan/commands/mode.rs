@@ -11,9 +11,12 @@
 // C - Compressed
 //
 // The default transfer mode is Stream.
+//
+// Z - Deflate, from draft-preston-ftpext-deflate, if enabled with ServerBuilder::mode_z.
 
 use crate::{
     auth::UserDetail,
+    options::ModeZ,
     server::controlchan::{
         error::ControlChanError,
         handler::{CommandContext, CommandHandler},
@@ -23,9 +26,8 @@ use crate::{
 };
 use async_trait::async_trait;
 
-/// The parameter that can be given to the `MODE` command. The `MODE` command is obsolete, and we
-/// only support the `Stream` mode. We still have to support the command itself for compatibility
-/// reasons, though.
+/// The parameter that can be given to the `MODE` command. Of the RFC 959 modes we only support the
+/// `Stream` mode, and the `Deflate` mode if the server allows it.
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum ModeParam {
     /// Data is sent in a continuous stream of bytes.
@@ -34,6 +36,8 @@ pub enum ModeParam {
     Block,
     /// Some round-about way of sending compressed data.
     Compressed,
+    /// Data is sent as a continuous zlib stream.
+    Deflate,
 }
 
 #[derive(Debug)]
@@ -55,9 +59,17 @@ where
     Storage::Metadata: Metadata,
 {
     #[tracing_attributes::instrument]
-    async fn handle(&self, _args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
+    async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
+        let mut session = args.session.lock().await;
         match &self.params {
-            ModeParam::Stream => Ok(Reply::new(ReplyCode::CommandOkay, "Using Stream transfer mode")),
+            ModeParam::Stream => {
+                session.deflate = false;
+                Ok(Reply::new(ReplyCode::CommandOkay, "Using Stream transfer mode"))
+            }
+            ModeParam::Deflate if session.mode_z != ModeZ::Disabled => {
+                session.deflate = true;
+                Ok(Reply::new(ReplyCode::CommandOkay, "Using Deflate transfer mode"))
+            }
             _ => Ok(Reply::new(
                 ReplyCode::CommandNotImplementedForParameter,
                 "Only Stream transfer mode is supported",
@@ -14,6 +14,7 @@
 // the implementation of a "multiple get" function.
 
 use crate::server::chancomms::DataChanCmd;
+use crate::server::glob;
 use crate::{
     auth::UserDetail,
     server::controlchan::{
@@ -40,13 +41,17 @@ where
     async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
         let mut session = args.session.lock().await;
         let (cmd, path_opt): (DataChanCmd, Option<String>) = match args.parsed_command.clone() {
-            Command::Nlst { path } => {
+            Command::Nlst { options, path } => {
                 let path_clone = path.clone();
-                (DataChanCmd::Nlst { path }, path_clone)
+                (DataChanCmd::Nlst { options, path }, path_clone)
             }
             _ => panic!("Programmer error, expected command to be NLST"),
         };
         let logger = args.logger;
+        if let Some(Err(err)) = path_opt.as_deref().map(glob::compile_path) {
+            slog::warn!(logger, "NLST: refusing wildcard pattern in {:?}: {}", path_opt, err);
+            return Ok(Reply::new(ReplyCode::ParameterSyntaxError, "Wildcard pattern too long or too complex"));
+        }
         match session.data_cmd_tx.take() {
             Some(tx) => {
                 tokio::spawn(async move {
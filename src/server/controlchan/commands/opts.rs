@@ -45,10 +45,13 @@ where
     Storage::Metadata: Metadata,
 {
     #[tracing_attributes::instrument]
-    async fn handle(&self, _args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
-        match &self.option {
-            Opt::Utf8 { on: true } => Ok(Reply::new(ReplyCode::CommandOkay, "Always in UTF-8 mode.")),
-            Opt::Utf8 { on: false } => Ok(Reply::new(ReplyCode::CommandNotImplementedForParameter, "Non UTF-8 mode not supported")),
+    async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
+        let session = args.session.lock().await;
+        match (&self.option, session.charset.set_utf8(self.option == Opt::Utf8 { on: true })) {
+            (Opt::Utf8 { on: true }, true) => Ok(Reply::new(ReplyCode::CommandOkay, "UTF-8 mode on.")),
+            (Opt::Utf8 { on: false }, true) => Ok(Reply::new(ReplyCode::CommandOkay, "UTF-8 mode off.")),
+            (Opt::Utf8 { on: true }, false) => Ok(Reply::new(ReplyCode::CommandNotImplementedForParameter, "UTF-8 mode not supported")),
+            (Opt::Utf8 { on: false }, false) => Ok(Reply::new(ReplyCode::CommandNotImplementedForParameter, "Non UTF-8 mode not supported")),
         }
     }
 }
@@ -12,7 +12,8 @@
 
 use crate::server::failed_logins::LockState;
 use crate::{
-    auth::UserDetail,
+    auth::{SecretString, UserDetail},
+    options::ConnectionDecision,
     server::{
         chancomms::ControlChanMsg,
         controlchan::{
@@ -56,7 +57,7 @@ where
         match &session.state {
             SessionState::WaitPass => {
                 let pass: &str = std::str::from_utf8(self.password.as_ref())?;
-                let pass: String = pass.to_string();
+                let pass = SecretString::new(pass.to_string());
                 let username: String = match session.username.clone() {
                     Some(v) => v,
                     None => {
@@ -78,6 +79,10 @@ where
                 };
                 let failed_logins = session.failed_logins.clone();
                 let source_ip = session.source.ip();
+                // How the user and the address appear in the log.
+                let shown_user = session.redaction.user(&username);
+                let shown_ip = session.redaction.ip(source_ip);
+                let connection_policy = session.connection_policy.clone();
                 tokio::spawn(async move {
                     let msg = match auther.authenticate(&username, &creds).await {
                         Ok(user) => {
@@ -88,7 +93,7 @@ where
                                         slog::warn!(
                                             logger,
                                             "PASS: User authenticated but currently locked out due to previous failed login attempts according to the policy! (Username={}. Note: the account automatically unlocks after the configured period if no further failed login attempts occur. state={:?})",
-                                            username,
+                                            shown_user,
                                             state
                                         );
                                         true
@@ -99,9 +104,17 @@ where
                                 None => false,
                             };
 
+                            let decision = match &connection_policy {
+                                Some(policy) if !is_locked => policy.check_login(source_ip, &user).await,
+                                _ => ConnectionDecision::Allow,
+                            };
+
                             if is_locked {
                                 sleep(Duration::from_millis(1500)).await;
                                 ControlChanMsg::AuthFailed
+                            } else if let ConnectionDecision::Reject(reason) = decision {
+                                slog::warn!(logger, "PASS: User {} may not log in from {}: {}", shown_user, shown_ip, reason);
+                                ControlChanMsg::AuthFailed
                             } else if user.account_enabled() {
                                 let mut session = session2clone.lock().await;
                                 // Using Arc::get_mut means that this won't work if the Session is
@@ -118,7 +131,7 @@ where
                                         ControlChanMsg::AuthFailed
                                     }
                                     Some(Ok(())) => {
-                                        slog::info!(logger, "PASS: User {} logged in", user);
+                                        slog::info!(logger, "PASS: User {} logged in", shown_user);
                                         session.user = Arc::new(Some(user));
                                         ControlChanMsg::AuthSuccess {
                                             username,
@@ -127,16 +140,16 @@ where
                                     }
                                 }
                             } else {
-                                slog::warn!(logger, "PASS: User {} authenticated but account is disabled", user);
+                                slog::warn!(logger, "PASS: User {} authenticated but account is disabled", shown_user);
                                 ControlChanMsg::AuthFailed
                             }
                         }
                         Err(crate::auth::AuthenticationError::BadUser) => {
-                            slog::warn!(logger, "PASS: Login attempt for unknown user {}", username);
+                            slog::warn!(logger, "PASS: Login attempt for unknown user {}", shown_user);
                             ControlChanMsg::AuthFailed
                         }
                         Err(err) => {
-                            slog::warn!(logger, "PASS: Failed login attempt for user {}, reason={}", username, err);
+                            slog::warn!(logger, "PASS: Failed login attempt for user {}, reason={}", shown_user, err);
                             if let Some(failed_logins) = failed_logins {
                                 let result = failed_logins.failed(source_ip, username.clone()).await;
                                 if let Some(state) = result {
@@ -145,8 +158,8 @@ where
                                             slog::warn!(
                                                 logger,
                                                 "PASS: Maximum number bad login attempts reached according to the policy so the locking policy is now active (Username={}, IP={}, LockState={:?})",
-                                                username,
-                                                source_ip,
+                                                shown_user,
+                                                shown_ip,
                                                 state
                                             );
                                         }
@@ -154,8 +167,8 @@ where
                                             slog::info!(
                                                 logger,
                                                 "PASS: Another bad login attempt but the locking policy is already active (Username={}, IP={}, LockState={:?})",
-                                                username,
-                                                source_ip,
+                                                shown_user,
+                                                shown_ip,
                                                 state
                                             );
                                         }
@@ -8,15 +8,16 @@
 
 use crate::{
     auth::UserDetail,
+    metrics,
     server::{
-        chancomms::{DataChanCmd, ProxyLoopMsg, ProxyLoopSender},
+        chancomms::{DataAbort, DataChanCmd, ProxyLoopMsg, ProxyLoopSender},
         controlchan::{
             error::ControlChanError,
             handler::{CommandContext, CommandHandler},
             Reply, ReplyCode,
         },
         datachan,
-        ftpserver::options::PassiveHost,
+        ftpserver::options::{DataConnectionSource, PassiveHost, PassivePortMapper},
         session::SharedSession,
         ControlChanErrorKind, ControlChanMsg,
     },
@@ -69,21 +70,22 @@ impl Pasv {
     // modifies the session by adding channels that are used to communicate with the data connection
     // processing loop.
     #[tracing_attributes::instrument]
-    async fn setup_inter_loop_comms<S, U>(&self, session: SharedSession<S, U>, control_loop_tx: Sender<ControlChanMsg>)
+    async fn setup_inter_loop_comms<S, U>(&self, session: SharedSession<S, U>, control_loop_tx: Sender<ControlChanMsg>) -> Sender<DataChanCmd>
     where
         U: UserDetail + 'static,
         S: StorageBackend<U> + 'static,
         S::Metadata: Metadata,
     {
         let (cmd_tx, cmd_rx): (Sender<DataChanCmd>, Receiver<DataChanCmd>) = channel(1);
-        let (data_abort_tx, data_abort_rx): (Sender<()>, Receiver<()>) = channel(1);
+        let (data_abort_tx, data_abort_rx): (Sender<DataAbort>, Receiver<DataAbort>) = channel(1);
 
         let mut session = session.lock().await;
-        session.data_cmd_tx = Some(cmd_tx);
+        session.data_cmd_tx = Some(cmd_tx.clone());
         session.data_cmd_rx = Some(cmd_rx);
         session.data_abort_tx = Some(data_abort_tx);
         session.data_abort_rx = Some(data_abort_rx);
         session.control_msg_tx = Some(control_loop_tx);
+        cmd_tx
     }
 
     // For non-proxy mode we choose a data port here and start listening on it while letting the control
@@ -98,6 +100,7 @@ impl Pasv {
         let CommandContext {
             logger,
             passive_host,
+            passive_ports,
             tx_control_chan: tx,
             session,
             ..
@@ -112,35 +115,81 @@ impl Pasv {
             }
         };
 
-        let listener = if let Some(ref mut binder) = session.lock().await.binder {
-            binder.bind(args.local_addr.ip(), args.passive_ports).await
+        let mut session_guard = session.lock().await;
+        // The user may have its own passive port range and host.
+        let user = (*session_guard.user).as_ref();
+        let passive_ports = user.and_then(|u| u.passive_ports()).unwrap_or(passive_ports);
+        if passive_ports.is_empty() {
+            slog::warn!(logger, "PASV: the passive port range {:?} is empty", passive_ports);
+            return Ok(Reply::new(ReplyCode::CantOpenDataConnection, "No data connection established"));
+        }
+        let passive_host = user.and_then(|u| u.passive_host()).unwrap_or(passive_host);
+        // Data connections may have to go through another interface than the control connection.
+        let conn_addr = session_guard.data_bind_address.unwrap_or(*conn_addr.ip());
+        let listener = if let Some(ref mut binder) = session_guard.binder {
+            binder.bind(IpAddr::V4(conn_addr), passive_ports).await
         } else {
-            Pasv::try_port_range(args.local_addr.ip(), args.passive_ports)
+            Pasv::try_port_range(IpAddr::V4(conn_addr), passive_ports)
         };
+        let socket_options = session_guard.data_socket_options;
+        let port_mapper = session_guard.passive_port_mapper.clone();
+        drop(session_guard);
         let listener = match listener {
             Err(_) => return Ok(Reply::new(ReplyCode::CantOpenDataConnection, "No data connection established")),
             Ok(l) => l,
         };
+        if let Err(err) = socket_options.apply_buffers(&listener) {
+            slog::warn!(logger, "Could not set the buffer sizes of the passive data port: {}", err);
+        }
         let listener = listener.listen(1024).unwrap();
 
         let port = listener.local_addr()?.port();
 
-        let reply = make_pasv_reply(&logger, passive_host, conn_addr.ip(), port).await;
+        let reply = make_pasv_reply(&logger, passive_host, port_mapper.as_deref(), &conn_addr, port).await;
         if let Reply::CodeAndMsg {
             code: ReplyCode::EnteringPassiveMode,
             ..
         } = reply
         {
-            self.setup_inter_loop_comms(session.clone(), tx).await;
+            let cmd_tx = self.setup_inter_loop_comms(session.clone(), tx).await;
             // Open the data connection in a new task and process it.
             // We cannot await this since we first need to let the client know where to connect :-)
+            let port_in_use = metrics::track_passive_port();
             tokio::spawn(async move {
+                let _port_in_use = port_in_use;
                 // Timeout if the client doesn't connect to the socket in a while, to avoid leaving the socket hanging open permanently.
-                let r = tokio::time::timeout(Duration::from_secs(15), listener.accept()).await;
-                match r {
-                    Ok(Ok((socket, _socket_addr))) => datachan::spawn_processing(logger, session, socket).await,
-                    Ok(Err(e)) => slog::error!(logger, "Error waiting for data connection: {}", e),
-                    Err(_) => slog::warn!(logger, "Client did not connect to data port in time"),
+                let deadline = tokio::time::Instant::now() + Duration::from_secs(15);
+                loop {
+                    match tokio::time::timeout_at(deadline, listener.accept()).await {
+                        Ok(Ok((socket, socket_addr))) => {
+                            let session_guard = session.lock().await;
+                            if session_guard.data_connection_source == DataConnectionSource::SameIp && socket_addr.ip() != session_guard.source.ip() {
+                                slog::warn!(
+                                    logger,
+                                    "Refused data connection from {} that doesn't match the control connection from {}",
+                                    socket_addr,
+                                    session_guard.source
+                                );
+                                // Keep waiting for the client, others may not take its data connection away.
+                                continue;
+                            }
+                            drop(session_guard);
+                            return datachan::spawn_processing(logger, session, socket).await;
+                        }
+                        Ok(Err(e)) => slog::error!(logger, "Error waiting for data connection: {}", e),
+                        Err(_) => slog::warn!(logger, "Client did not connect to data port in time"),
+                    }
+                    break;
+                }
+                // A transfer command that was handed over already would otherwise hold back the
+                // commands that follow it for good.
+                let mut session_guard = session.lock().await;
+                let ours = |tx: &Option<Sender<DataChanCmd>>| tx.as_ref().is_some_and(|tx| tx.same_channel(&cmd_tx));
+                if ours(&session_guard.data_cmd_tx) || ours(&session_guard.transfer) {
+                    session_guard.data_cmd_tx = None;
+                    session_guard.data_cmd_rx = None;
+                    session_guard.data_abort_tx = None;
+                    session_guard.data_abort_rx = None;
                 }
             });
         }
@@ -180,12 +229,23 @@ where
     }
 }
 
-pub async fn make_pasv_reply(logger: &slog::Logger, passive_host: PassiveHost, conn_ip: &Ipv4Addr, port: u16) -> Reply {
-    let p1 = port >> 8;
-    let p2 = port - (p1 * 256);
+pub async fn make_pasv_reply(
+    logger: &slog::Logger,
+    passive_host: PassiveHost,
+    port_mapper: Option<&dyn PassivePortMapper>,
+    conn_ip: &Ipv4Addr,
+    port: u16,
+) -> Reply {
     let octets = match passive_host {
         PassiveHost::Ip(ip) => ip.octets(),
         PassiveHost::FromConnection => conn_ip.octets(),
+        PassiveHost::Resolver(ref resolver) => match resolver.resolve().await {
+            Ok(ip) => ip.octets(),
+            Err(e) => {
+                slog::warn!(logger, "make_pasv_reply: Could not resolve the passive host with {:?}: {}", resolver, e);
+                return Reply::new(ReplyCode::CantOpenDataConnection, "Could not determine the passive address");
+            }
+        },
         PassiveHost::Dns(ref dns_name) => {
             let x = dns_name.split(':').take(1).map(|s| format!("{}:2121", s)).next().unwrap();
             match tokio::net::lookup_host(x).await {
@@ -205,6 +265,20 @@ pub async fn make_pasv_reply(logger: &slog::Logger, passive_host: PassiveHost, c
         }
     };
     slog::info!(logger, "Listening on passive port {}:{}", conn_ip, port);
+    // A gateway in front of the server may forward another port to the one we listen on.
+    let (octets, port) = match port_mapper.map(|mapper| mapper.map(octets.into(), port)) {
+        None => (octets, port),
+        Some(Ok((ip, mapped))) => {
+            slog::info!(logger, "Advertising passive port {} as {}:{}", port, ip, mapped);
+            (ip.octets(), mapped)
+        }
+        Some(Err(e)) => {
+            slog::warn!(logger, "make_pasv_reply: Could not map passive port {} with {:?}: {}", port, port_mapper, e);
+            return Reply::new(ReplyCode::CantOpenDataConnection, "Could not determine the passive address");
+        }
+    };
+    let p1 = port >> 8;
+    let p2 = port - (p1 * 256);
     Reply::new_with_string(
         ReplyCode::EnteringPassiveMode,
         format!("Entering Passive Mode ({},{},{},{},{},{})", octets[0], octets[1], octets[2], octets[3], p1, p2),
@@ -8,6 +8,8 @@
 //! is not required. Thus, the PBSZ command MUST still be issued, but must have a parameter
 //! of '0' to indicate that no buffering is taking place and the data connection should
 //! not be encapsulated.
+//!
+//! PBSZ is only accepted once the control channel is secured with AUTH TLS.
 
 use crate::{
     auth::UserDetail,
@@ -31,7 +33,11 @@ where
     Storage::Metadata: Metadata,
 {
     #[tracing_attributes::instrument]
-    async fn handle(&self, _args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
-        Ok(Reply::new(ReplyCode::CommandOkay, "OK"))
+    async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
+        let mut session = args.session.lock().await;
+        match session.set_buffer_size() {
+            Ok(()) => Ok(Reply::new(ReplyCode::CommandOkay, "PBSZ=0")),
+            Err(_) => Ok(Reply::new(ReplyCode::BadCommandSequence, "PBSZ must follow AUTH TLS")),
+        }
     }
 }
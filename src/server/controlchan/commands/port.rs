@@ -19,7 +19,7 @@
 use crate::{
     auth::UserDetail,
     server::{
-        chancomms::{DataChanCmd, ProxyLoopSender},
+        chancomms::{DataAbort, DataChanCmd, ProxyLoopSender},
         controlchan::{
             error::ControlChanError,
             handler::{CommandContext, CommandHandler},
@@ -33,8 +33,8 @@ use crate::{
 };
 use async_trait::async_trait;
 use std::io;
-use std::net::{Ipv4Addr, SocketAddrV4};
-use tokio::net::TcpStream;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+use tokio::net::{TcpSocket, TcpStream};
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 
 #[derive(Debug)]
@@ -57,7 +57,7 @@ impl Port {
         S::Metadata: Metadata,
     {
         let (cmd_tx, cmd_rx): (Sender<DataChanCmd>, Receiver<DataChanCmd>) = channel(1);
-        let (data_abort_tx, data_abort_rx): (Sender<()>, Receiver<()>) = channel(1);
+        let (data_abort_tx, data_abort_rx): (Sender<DataAbort>, Receiver<DataAbort>) = channel(1);
 
         let mut session = session.lock().await;
         session.data_cmd_tx = Some(cmd_tx);
@@ -88,7 +88,29 @@ impl Port {
         let port = ((bytes[4] as u16) << 8) | bytes[5] as u16;
         let addr = SocketAddrV4::new(Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3]), port);
 
-        let stream: io::Result<TcpStream> = TcpStream::connect(addr).await;
+        // The buffer sizes are set before connecting so that they count for the TCP window.
+        let (socket_options, bind_address) = {
+            let session = session.lock().await;
+            (session.data_socket_options, session.data_bind_address)
+        };
+        let stream: io::Result<TcpStream> = match TcpSocket::new_v4() {
+            Ok(socket) => {
+                if let Err(err) = socket_options.apply_buffers(&socket) {
+                    slog::warn!(logger, "Could not set the buffer sizes of the active data connection: {}", err);
+                }
+                match bind_address {
+                    Some(ip) => match socket.bind(SocketAddr::new(IpAddr::V4(ip), 0)) {
+                        Ok(()) => socket.connect(addr.into()).await,
+                        Err(err) => {
+                            slog::warn!(logger, "Could not bind the active data connection to {}: {}", ip, err);
+                            Err(err)
+                        }
+                    },
+                    None => socket.connect(addr.into()).await,
+                }
+            }
+            Err(err) => Err(err),
+        };
 
         let stream = match stream {
             Err(_) => return Ok(Reply::new(ReplyCode::CantOpenDataConnection, "No data connection established")),
@@ -45,15 +45,14 @@ where
     #[tracing_attributes::instrument]
     async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
         match (args.tls_configured, self.param.clone()) {
-            (true, ProtParam::Clear) => {
+            (true, param @ (ProtParam::Clear | ProtParam::Private)) => {
                 let mut session = args.session.lock().await;
-                session.data_tls = false;
-                Ok(Reply::new(ReplyCode::CommandOkay, "PROT OK. Switching data channel to plaintext"))
-            }
-            (true, ProtParam::Private) => {
-                let mut session = args.session.lock().await;
-                session.data_tls = true;
-                Ok(Reply::new(ReplyCode::CommandOkay, "PROT OK. Securing data channel"))
+                let private = param == ProtParam::Private;
+                match session.protect_data(private) {
+                    Ok(()) if private => Ok(Reply::new(ReplyCode::CommandOkay, "PROT OK. Securing data channel")),
+                    Ok(()) => Ok(Reply::new(ReplyCode::CommandOkay, "PROT OK. Switching data channel to plaintext")),
+                    Err(_) => Ok(Reply::new(ReplyCode::BadCommandSequence, "PBSZ is required before PROT")),
+                }
             }
             (true, _) => Ok(Reply::new(ReplyCode::CommandNotImplementedForParameter, "PROT S/E not implemented")),
             (false, _) => Ok(Reply::new(ReplyCode::CommandNotImplemented, "TLS/SSL not configured")),
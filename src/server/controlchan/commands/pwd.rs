@@ -10,6 +10,7 @@ use crate::{
         handler::{CommandContext, CommandHandler},
         Reply, ReplyCode,
     },
+    server::encoding,
     storage::{Metadata, StorageBackend},
 };
 use async_trait::async_trait;
@@ -29,7 +30,11 @@ where
         let session = args.session.lock().await;
         // TODO: properly escape double quotes in `cwd`
 
-        let result = format!("\"{}\"", session.cwd.as_path().display());
+        let cwd = match &*session.user {
+            Some(user) => session.storage.current_dir(user).unwrap_or_else(|| session.cwd.clone()),
+            None => session.cwd.clone(),
+        };
+        let result = format!("\"{}\"", encoding::path_to_text(cwd.as_os_str()));
 
         // On Windows systems, the path will be formatted with Windows style separators ('\')
         // Most FTP clients expect normal UNIX separators ('/'), and they have trouble handling
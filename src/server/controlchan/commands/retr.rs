@@ -5,6 +5,7 @@
 // at the other end of the data connection.  The status and
 // contents of the file at the server site shall be unaffected.
 
+use crate::server::path;
 use crate::{
     auth::UserDetail,
     server::{
@@ -15,6 +16,7 @@ use crate::{
             handler::{CommandContext, CommandHandler},
             Reply,
         },
+        session::Session,
         ReplyCode,
     },
     storage::{Metadata, StorageBackend},
@@ -34,17 +36,23 @@ where
     #[tracing_attributes::instrument]
     async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
         let mut session = args.session.lock().await;
-        let (cmd, path): (DataChanCmd, String) = match args.parsed_command.clone() {
-            Command::Retr { path } => {
-                let path_clone = path.clone();
-                (DataChanCmd::Retr { path }, path_clone)
-            }
-            _ => panic!("Programmer error, expected command to be LIST"),
+        let path = match args.parsed_command.clone() {
+            Command::Retr { path } => path,
+            _ => panic!("Programmer error, expected command to be RETR"),
         };
 
         let logger = args.logger;
+        if session.data_cmd_tx.is_some() {
+            if let Some(reply) = check_resumption(&mut session, &path, &logger).await {
+                return Ok(reply);
+            }
+        }
         match session.data_cmd_tx.take() {
             Some(tx) => {
+                let cmd = DataChanCmd::Retr {
+                    path,
+                    start_pos: session.take_start_pos(),
+                };
                 tokio::spawn(async move {
                     if let Err(err) = tx.send(cmd).await {
                         slog::warn!(logger, "RETR: could not notify data channel to respond with RETR. {}", err);
@@ -60,3 +68,48 @@ where
         }
     }
 }
+
+// With a session cache, checks the offset given with REST. Offsets up to where an interrupted
+// download of the file got are accepted as they are, others must lie within the file. Returns the
+// reply to refuse the offset with.
+async fn check_resumption<Storage, User>(session: &mut Session<Storage, User>, path: &str, logger: &slog::Logger) -> Option<Reply>
+where
+    User: UserDetail + 'static,
+    Storage: StorageBackend<User> + 'static,
+    Storage::Metadata: Metadata,
+{
+    let session_cache = session.session_cache.clone()?;
+    let start_pos = session.start_pos;
+    if start_pos == 0 {
+        return None;
+    }
+    let username = session.username.clone().unwrap_or_else(|| "unknown".to_string());
+    let full_path = path::resolve(&session.cwd, path);
+    if let Some(interrupted) = session_cache.take(&username, &full_path.to_string_lossy()).await {
+        if start_pos <= interrupted.offset {
+            slog::info!(
+                logger,
+                "RETR: Resuming interrupted download of {:?} at {} (got to {})",
+                path,
+                start_pos,
+                interrupted.offset
+            );
+            return None;
+        }
+    }
+    let user = session.user.clone();
+    let len = match session.storage.metadata((*user).as_ref()?, &full_path).await {
+        Ok(metadata) => metadata.len(),
+        // The transfer reports the error.
+        Err(_) => return None,
+    };
+    if start_pos <= len {
+        return None;
+    }
+    slog::info!(logger, "RETR: Refusing REST offset {} beyond the end of {:?} ({} bytes)", start_pos, path, len);
+    session.start_pos = 0;
+    Some(Reply::new_with_string(
+        ReplyCode::InvalidRestParameter,
+        format!("Invalid REST parameter: {} is beyond the end of the file ({} bytes)", start_pos, len),
+    ))
+}
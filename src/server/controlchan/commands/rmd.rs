@@ -5,6 +5,7 @@
 // or as a subdirectory of the current working directory (if
 // the pathname is relative).
 
+use crate::server::path;
 use crate::{
     auth::UserDetail,
     server::{
@@ -42,13 +43,13 @@ where
     async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
         let session = args.session.lock().await;
         let storage: Arc<Storage> = Arc::clone(&session.storage);
-        let path = session.cwd.join(self.path.clone());
+        let path = path::resolve(&session.cwd, self.path.clone());
         let path_str = path.to_string_lossy().to_string();
         let tx = args.tx_control_chan.clone();
         let logger = args.logger;
         if let Err(err) = storage.rmd((*session.user).as_ref().unwrap(), path).await {
             slog::warn!(logger, "RMD: Failed to delete directory {}: {}", path_str, err);
-            let r = tx.send(ControlChanMsg::StorageError(err)).await;
+            let r = tx.send(ControlChanMsg::StorageError(err.with_context("RMD", path_str.as_str()))).await;
             if let Err(e) = r {
                 slog::warn!(logger, "RMD: Could not send internal message to notify of RMD error: {}", e);
             }
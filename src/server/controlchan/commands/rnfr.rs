@@ -1,5 +1,6 @@
 //! The RFC 959 Rename From (`RNFR`) command
 
+use crate::server::path;
 use crate::{
     auth::UserDetail,
     server::controlchan::{
@@ -33,7 +34,7 @@ where
     #[tracing_attributes::instrument]
     async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
         let mut session = args.session.lock().await;
-        session.rename_from = Some(session.cwd.join(self.path.clone()));
+        session.rename_from = Some(path::resolve(&session.cwd, self.path.clone()));
         Ok(Reply::new(ReplyCode::FileActionPending, "Tell me, what would you like the new name to be?"))
     }
 }
@@ -1,5 +1,6 @@
 //! The RFC 959 Rename To (`RNTO`) command
 
+use crate::server::path;
 use crate::server::ControlChanMsg;
 use crate::storage::{Metadata, StorageBackend};
 use crate::{
@@ -44,7 +45,7 @@ where
 
         let (from, to) = match session.rename_from.take() {
             Some(from) => {
-                let to = session.cwd.join(self.path.clone());
+                let to = path::resolve(&session.cwd, self.path.clone());
                 (from, to)
             }
             None => return Ok(Reply::new(ReplyCode::TransientFileError, "Please tell me what file you want to rename first")),
@@ -60,7 +61,7 @@ where
                 }
             }
             Err(err) => {
-                if let Err(err) = tx_control_chan.send(ControlChanMsg::StorageError(err)).await {
+                if let Err(err) = tx_control_chan.send(ControlChanMsg::StorageError(err.with_context("RNTO", &to))).await {
                     slog::warn!(logger, "RNTO: Could not send internal message to notify of RNTO failure: {}", err);
                 }
             }
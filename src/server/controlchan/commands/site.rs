@@ -0,0 +1,89 @@
+//! The RFC 959 Site Parameters (`SITE`) command
+//
+// This command is used by the server to provide services specific to his system that are
+// essential to file transfer but not sufficiently universal to be included as commands in the
+// protocol. The nature of these services and the specification of their syntax can be stated in
+// a reply to the HELP SITE command.
+//
+// The subcommands other than CHOWN, SYMLINK, CPFR and CPTO, including the built-in MD5, are looked
+// up in the SiteCommandRegistry.
+
+use crate::{
+    auth::UserDetail,
+    server::{
+        chancomms::ControlChanMsg,
+        controlchan::{
+            error::ControlChanError,
+            handler::{CommandContext, CommandHandler},
+            Reply, ReplyCode,
+        },
+        ftpserver::site::{SiteCall, SiteReply, SiteReplyCode},
+    },
+    storage::{Metadata, StorageBackend},
+};
+use async_trait::async_trait;
+
+#[derive(Debug)]
+pub struct Site {
+    name: String,
+    arguments: String,
+}
+
+impl Site {
+    pub fn new(name: String, arguments: String) -> Self {
+        Site { name, arguments }
+    }
+}
+
+#[async_trait]
+impl<Storage, User> CommandHandler<Storage, User> for Site
+where
+    User: UserDetail + 'static,
+    Storage: StorageBackend<User> + 'static,
+    Storage::Metadata: Metadata,
+{
+    #[tracing_attributes::instrument]
+    async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
+        let call = {
+            let session = args.session.lock().await;
+            SiteCall {
+                arguments: self.arguments.clone(),
+                cwd: session.cwd.clone(),
+                username: session.username.clone(),
+                user: session.user.clone(),
+                storage: session.storage.clone(),
+            }
+        };
+        let future = match args.site_commands.call(&self.name, call) {
+            Some(future) => future,
+            None => return Ok(Reply::new(ReplyCode::CommandSyntaxError, "Command not implemented")),
+        };
+
+        let tx = args.tx_control_chan.clone();
+        let logger = args.logger;
+        let name = self.name.clone();
+        // Run it in the background like the storage operations of the other commands.
+        tokio::spawn(async move {
+            let reply = to_reply(future.await);
+            if let Err(err) = tx.send(ControlChanMsg::CommandChannelReply(reply)).await {
+                slog::warn!(logger, "SITE {}: Could not send internal message to notify of the result: {}", name, err);
+            }
+        });
+        Ok(Reply::none())
+    }
+}
+
+fn to_reply(reply: SiteReply) -> Reply {
+    let code = match reply.code {
+        SiteReplyCode::Okay => ReplyCode::CommandOkay,
+        SiteReplyCode::FileStatus => ReplyCode::FileStatus,
+        SiteReplyCode::SyntaxError => ReplyCode::ParameterSyntaxError,
+        SiteReplyCode::NotAvailable => ReplyCode::CommandNotImplemented,
+        SiteReplyCode::FileError => ReplyCode::FileError,
+        SiteReplyCode::Reply(code) => code,
+    };
+    match reply.message.contains('\n') {
+        true => Reply::multiline(code).line(reply.message).build(),
+        false => Reply::new_with_string(code, reply.message),
+    }
+}
@@ -1,3 +1,4 @@
+use crate::server::path;
 use crate::{
     auth::UserDetail,
     server::{
@@ -37,7 +38,7 @@ where
         let session = args.session.lock().await;
         let user = session.user.clone();
         let storage: Arc<Storage> = Arc::clone(&session.storage);
-        let path = session.cwd.join(self.path.clone());
+        let path = path::resolve(&session.cwd, self.path.clone());
         let tx_success: Sender<ControlChanMsg> = args.tx_control_chan.clone();
         let tx_fail: Sender<ControlChanMsg> = args.tx_control_chan.clone();
         let logger = args.logger;
@@ -59,7 +60,7 @@ where
                 }
                 Err(err) => {
                     slog::warn!(logger, "SIZE: Command failed for file {:?}: {}", &path, err);
-                    if let Err(err) = tx_fail.send(ControlChanMsg::StorageError(err)).await {
+                    if let Err(err) = tx_fail.send(ControlChanMsg::StorageError(err.with_context("SIZE", &path))).await {
                         slog::warn!(logger, "SIZE: Could not send internal message to notify of SIZE failure: {}", err);
                     }
                 }
@@ -17,6 +17,7 @@
 // should include current values of all transfer parameters and
 // the status of connections.
 
+use crate::server::{encoding, path};
 use crate::{
     auth::UserDetail,
     server::{
@@ -27,20 +28,19 @@ use crate::{
             Reply, ReplyCode,
         },
     },
-    storage::{Error, ErrorKind, Metadata, StorageBackend},
+    storage::{Fileinfo, Metadata, StorageBackend},
 };
 use async_trait::async_trait;
-use bytes::Bytes;
 use std::sync::Arc;
 use tokio::sync::mpsc::Sender;
 
 #[derive(Debug)]
 pub struct Stat {
-    path: Option<Bytes>,
+    path: Option<String>,
 }
 
 impl Stat {
-    pub fn new(path: Option<Bytes>) -> Self {
+    pub fn new(path: Option<String>) -> Self {
         Stat { path }
     }
 }
@@ -57,6 +57,21 @@ where
         match self.path.clone() {
             None => {
                 let session = args.session.lock().await;
+                // During a transfer the client wants to know how far it got.
+                if let Some(transfer) = session.activity.transfer() {
+                    let elapsed = transfer.started.elapsed().unwrap_or_default();
+                    return Ok(Reply::multiline(ReplyCode::FileStatus)
+                        .line("Transfer in progress:")
+                        .line(format!("{} {}", transfer.command, transfer.path))
+                        .line(format!(
+                            "{} bytes transferred in {} seconds ({} bytes/s)",
+                            transfer.bytes,
+                            elapsed.as_secs(),
+                            transfer.bytes_per_second()
+                        ))
+                        .line("End of status")
+                        .build());
+                }
                 let text: Vec<String> = vec![
                     "server status:".to_string(),
                     format!("powered by libunftp: {}", env!("CARGO_PKG_VERSION")),
@@ -66,43 +81,51 @@ where
                     format!("client addr: {}", session.source),
                     format!("ftps configured: {}", args.tls_configured),
                     format!("cmd channel in tls mode: {}", session.cmd_tls),
-                    format!("data channel in tls mode: {}", session.data_tls),
-                    format!("cwd: {}", session.cwd.to_string_lossy()),
+                    format!("data channel in tls mode: {}", session.data_tls()),
+                    format!("data channel protection: {:?}", session.data_protection),
+                    format!("cwd: {}", encoding::path_to_text(session.cwd.as_os_str())),
                     format!("rename from path: {:?}", session.rename_from),
                     format!("offset for REST: {}", session.start_pos),
+                    format!("data transfer in progress: {}", session.transfer_in_progress()),
                 ];
                 Ok(Reply::new_multiline(ReplyCode::SystemStatus, text))
             }
             Some(path) => {
-                let path: &str = std::str::from_utf8(&path)?;
-                let path_str = path.to_string();
-                let path = path.to_owned();
+                let path_str = path.clone();
 
                 let session = args.session.lock().await;
                 let user = session.user.clone();
                 let storage = Arc::clone(&session.storage);
+                let path = path::resolve(&session.cwd, path);
 
-                let tx_success: Sender<ControlChanMsg> = args.tx_control_chan.clone();
-                let tx_fail: Sender<ControlChanMsg> = args.tx_control_chan.clone();
+                let tx: Sender<ControlChanMsg> = args.tx_control_chan.clone();
                 let logger = args.logger;
 
                 tokio::spawn(async move {
-                    match storage.list_vec((*user).as_ref().unwrap(), path).await {
+                    let user = (*user).as_ref().unwrap();
+                    // Like LIST, a directory is listed and a file is described by a line of its own.
+                    let result = match storage.metadata(user, &path).await {
+                        Ok(metadata) if metadata.is_dir() => storage.list_vec(user, &path).await,
+                        Ok(metadata) => Ok(vec![Fileinfo { path: path.clone(), metadata }.to_string()]),
+                        Err(err) => Err(err),
+                    };
+                    let msg = match result {
                         Ok(lines) => {
                             slog::info!(logger, "STAT: Successfully listed file or directory {:?}", path_str);
-                            if let Err(err) = tx_success
-                                .send(ControlChanMsg::CommandChannelReply(Reply::new_multiline(ReplyCode::CommandOkay, lines)))
-                                .await
-                            {
-                                slog::warn!(logger, "STAT: Could not send internal message to notify of STAT success: {}", err);
-                            }
+                            let reply = Reply::multiline(ReplyCode::FileStatus)
+                                .line(format!("Status of {}:", path_str))
+                                .lines(lines)
+                                .line("End of status")
+                                .build();
+                            ControlChanMsg::CommandChannelReply(reply)
                         }
-                        Err(e) => {
-                            slog::info!(logger, "STAT: Failure listing file or directory {:?}", path_str);
-                            if let Err(err) = tx_fail.send(ControlChanMsg::StorageError(Error::new(ErrorKind::LocalError, e))).await {
-                                slog::warn!(logger, "STAT: Could not send internal message to notify of STAT failure: {}", err);
-                            }
+                        Err(err) => {
+                            slog::info!(logger, "STAT: Failure listing file or directory {:?}: {}", path_str, err);
+                            ControlChanMsg::StorageError(err.with_context("STAT", path_str.as_str()))
                         }
+                    };
+                    if let Err(err) = tx.send(msg).await {
+                        slog::warn!(logger, "STAT: Could not send internal message to notify of STAT result: {}", err);
                     }
                 });
                 Ok(Reply::none())
@@ -35,17 +35,24 @@ where
     async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
         let mut session = args.session.lock().await;
 
-        let (cmd, path): (DataChanCmd, String) = match args.parsed_command.clone() {
-            Command::Stor { path } => {
-                let path_clone = path.clone();
-                (DataChanCmd::Stor { path }, path_clone)
-            }
-            _ => panic!("Programmer error, expected command to be LIST"),
+        let path = match args.parsed_command.clone() {
+            Command::Stor { path } => path,
+            _ => panic!("Programmer error, expected command to be STOR"),
         };
 
         let logger = args.logger;
+        // Checked uploads have to be received as a whole.
+        let vetted = session.upload_hook.is_some() || session.upload_scanner.is_some();
+        if vetted && session.start_pos > 0 {
+            session.take_start_pos();
+            return Ok(Reply::new(ReplyCode::InvalidRestParameter, "Uploads can't be resumed on this server"));
+        }
         match session.data_cmd_tx.take() {
             Some(tx) => {
+                let cmd = DataChanCmd::Stor {
+                    path,
+                    start_pos: session.take_start_pos(),
+                };
                 tokio::spawn(async move {
                     if let Err(err) = tx.send(cmd).await {
                         slog::warn!(logger, "STOR: could not notify data channel to respond with STOR. {}", err);
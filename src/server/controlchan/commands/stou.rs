@@ -1,6 +1,6 @@
 //! The RFC 959 Store File Uniquely (`STOU`) command
 
-use crate::server::chancomms::DataChanCmd;
+use crate::server::chancomms::{ControlChanMsg, DataChanCmd};
 use crate::{
     auth::UserDetail,
     server::controlchan::{
@@ -11,10 +11,8 @@ use crate::{
     storage::{Metadata, StorageBackend},
 };
 use async_trait::async_trait;
-use std::path::Path;
-use uuid::Uuid;
+use std::sync::Arc;
 
-// TODO: Write functional test for STOU command.
 #[derive(Debug)]
 pub struct Stou;
 
@@ -28,21 +26,40 @@ where
     #[tracing_attributes::instrument]
     async fn handle(&self, args: CommandContext<Storager, User>) -> Result<Reply, ControlChanError> {
         let mut session = args.session.lock().await;
-        let uuid: String = Uuid::new_v4().to_string();
-        let filename: &Path = std::path::Path::new(&uuid);
-        let path: String = session.cwd.join(filename).to_string_lossy().to_string();
+        let dir = session.cwd.clone();
         let logger = args.logger;
         match session.data_cmd_tx.take() {
             Some(tx) => {
+                let storage = Arc::clone(&session.storage);
+                let user = session.user.clone();
+                let tx_control_chan = args.tx_control_chan.clone();
                 tokio::spawn(async move {
-                    if let Err(err) = tx.send(DataChanCmd::Stor { path }).await {
-                        slog::warn!(logger, "STOU: could not send Stor command over data channel. {}", err);
+                    // RFC 1123 wants the name in the preliminary reply already, so the storage back-end
+                    // claims it with an empty file that the upload replaces.
+                    let name = match storage.put_unique((*user).as_ref().unwrap(), tokio::io::empty(), &dir).await {
+                        Ok((name, _)) => name,
+                        Err(err) => {
+                            slog::warn!(logger, "STOU: could not create a unique file in {:?}: {}", dir, err);
+                            let msg = ControlChanMsg::StorageError(err.with_context("STOU", dir.clone()));
+                            if let Err(err) = tx_control_chan.send(msg).await {
+                                slog::warn!(logger, "STOU: could not send internal message to notify of STOU failure: {}", err);
+                            }
+                            return;
+                        }
+                    };
+                    let reply = Reply::new_with_string(ReplyCode::FileStatusOkay, format!("FILE: {}", name));
+                    if let Err(err) = tx_control_chan.send(ControlChanMsg::CommandChannelReply(reply)).await {
+                        slog::warn!(logger, "STOU: could not send internal message with the unique name: {}", err);
+                    }
+                    let path = dir.join(name).to_string_lossy().to_string();
+                    if let Err(err) = tx.send(DataChanCmd::Stou { path }).await {
+                        slog::warn!(logger, "STOU: could not send Stou command over data channel. {}", err);
                     }
                 });
-                Ok(Reply::new_with_string(ReplyCode::FileStatusOkay, filename.to_string_lossy().to_string()))
+                Ok(Reply::none())
             }
             None => {
-                slog::warn!(logger, "STOU: no data connection established for STOU file {:?}", path);
+                slog::warn!(logger, "STOU: no data connection established for STOU in {:?}", dir);
                 Ok(Reply::new(ReplyCode::CantOpenDataConnection, "No data connection established"))
             }
         }
@@ -0,0 +1,71 @@
+//! The `SITE SYMLINK` command, which creates a symbolic link like ln(1) -s does:
+//! `SITE SYMLINK <target> <link>`. The target is stored as given, so relative targets are resolved
+//! from the directory of the link. Only available with storage back-ends that advertise
+//! [`FEATURE_SYMLINK`].
+
+use crate::server::path;
+use crate::{
+    auth::UserDetail,
+    server::{
+        chancomms::ControlChanMsg,
+        controlchan::{
+            error::ControlChanError,
+            handler::{CommandContext, CommandHandler},
+            Reply, ReplyCode,
+        },
+    },
+    storage::{StorageBackend, FEATURE_SYMLINK},
+};
+use async_trait::async_trait;
+use std::{path::PathBuf, sync::Arc};
+use tokio::sync::mpsc::Sender;
+
+#[derive(Debug)]
+pub struct Symlink {
+    target: PathBuf,
+    link: PathBuf,
+}
+
+impl Symlink {
+    pub fn new(target: PathBuf, link: PathBuf) -> Self {
+        Symlink { target, link }
+    }
+}
+
+#[async_trait]
+impl<Storage, User> CommandHandler<Storage, User> for Symlink
+where
+    User: UserDetail + 'static,
+    Storage: StorageBackend<User> + 'static,
+{
+    #[tracing_attributes::instrument]
+    async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
+        if args.storage_features & FEATURE_SYMLINK == 0 {
+            return Ok(Reply::new(ReplyCode::CommandNotImplemented, "Not supported by the selected storage back-end."));
+        }
+
+        let session = args.session.lock().await;
+        let user = session.user.clone();
+        let storage = Arc::clone(&session.storage);
+        let target = self.target.clone();
+        let link = path::resolve(&session.cwd, self.link.clone());
+        let tx: Sender<ControlChanMsg> = args.tx_control_chan.clone();
+        let logger = args.logger;
+        tokio::spawn(async move {
+            let msg = match storage.symlink((*user).as_ref().unwrap(), &target, &link).await {
+                Ok(()) => {
+                    slog::info!(logger, "SITE SYMLINK: Created link {:?} to {:?}", link, target);
+                    ControlChanMsg::CommandChannelReply(Reply::new(ReplyCode::CommandOkay, "SITE SYMLINK command successful"))
+                }
+                Err(err) => {
+                    slog::warn!(logger, "SITE SYMLINK: Failed to create link {:?} to {:?}: {}", link, target, err);
+                    ControlChanMsg::StorageError(err.with_context("SITE SYMLINK", &link))
+                }
+            };
+            if let Err(err) = tx.send(msg).await {
+                slog::warn!(logger, "SITE SYMLINK: Could not send internal message to notify of the result: {}", err);
+            }
+        });
+        Ok(Reply::none())
+    }
+}
@@ -25,6 +25,9 @@
 // Format parameter is changed, and later just the first
 // argument is changed, Format then returns to the Non-print
 // default.
+//
+// Unlike RFC 959 we default to Image, which is what clients expect nowadays. Of the formats only
+// Non-print is supported.
 
 use crate::{
     auth::UserDetail,
@@ -37,8 +40,33 @@ use crate::{
 };
 use async_trait::async_trait;
 
+/// The parameter that can be given to the `TYPE` command. We support the `Ascii` and `Image`
+/// representation types, the latter also when it is asked for as local bytes of 8 bits.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum TypeParam {
+    /// Text with CRLF line endings on the wire, stored with the line endings of the server.
+    Ascii,
+    /// Text in the EBCDIC character set.
+    Ebcdic,
+    /// Binary data, sent as is.
+    Image,
+    /// Binary data in bytes of the given size.
+    Local {
+        /// The number of bits in a byte.
+        byte_size: u8,
+    },
+}
+
 #[derive(Debug)]
-pub struct Type;
+pub struct Type {
+    params: TypeParam,
+}
+
+impl Type {
+    pub fn new(params: TypeParam) -> Self {
+        Type { params }
+    }
+}
 
 #[async_trait]
 impl<Storage, User> CommandHandler<Storage, User> for Type
@@ -48,7 +76,21 @@ where
     Storage::Metadata: Metadata,
 {
     #[tracing_attributes::instrument]
-    async fn handle(&self, _args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
-        Ok(Reply::new(ReplyCode::CommandOkay, "Always in binary mode"))
+    async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
+        let mut session = args.session.lock().await;
+        match &self.params {
+            TypeParam::Ascii => {
+                session.ascii = true;
+                Ok(Reply::new(ReplyCode::CommandOkay, "Switching to ASCII mode"))
+            }
+            TypeParam::Image | TypeParam::Local { byte_size: 8 } => {
+                session.ascii = false;
+                Ok(Reply::new(ReplyCode::CommandOkay, "Switching to Binary mode"))
+            }
+            _ => Ok(Reply::new(
+                ReplyCode::CommandNotImplementedForParameter,
+                "Only the ASCII and Image types are supported",
+            )),
+        }
     }
 }
@@ -1,6 +1,7 @@
 use crate::auth::{AuthenticationError, Credentials};
 use crate::{
     auth::UserDetail,
+    options::ConnectionDecision,
     server::{
         controlchan::{
             error::ControlChanError,
@@ -51,8 +52,20 @@ where
                         },
                     )
                     .await;
-                match auth_result {
-                    Ok(user_detail) => {
+                let rejection = match (&auth_result, &session.connection_policy) {
+                    (Ok(user_detail), Some(policy)) => match policy.check_login(session.source.ip(), user_detail).await {
+                        ConnectionDecision::Reject(reason) => Some(reason),
+                        ConnectionDecision::Allow => None,
+                    },
+                    _ => None,
+                };
+                match (auth_result, rejection) {
+                    (Ok(_), Some(reason)) => {
+                        let (shown_user, shown_ip) = (session.redaction.user(username_str), session.redaction.ip(session.source.ip()));
+                        slog::warn!(args.logger, "USER: User {} may not log in from {}: {}", shown_user, shown_ip, reason);
+                        Ok(Reply::new(ReplyCode::NotLoggedIn, "Invalid credentials"))
+                    }
+                    (Ok(user_detail), None) => {
                         let user = username_str;
                         // Using Arc::get_mut means that this won't work if the Session is
                         // currently servicing multiple commands concurrently.  But it shouldn't
@@ -68,18 +81,21 @@ where
                             }
                             Some(Ok(())) => {
                                 session.username = Some(user.to_string());
+                                session.activity.set_username(session.username.clone());
                                 session.state = SessionState::WaitCmd;
+                                session.activity.set_authenticated();
                                 session.user = Arc::new(Some(user_detail));
                                 Ok(Reply::new(ReplyCode::UserLoggedInViaCert, "User logged in"))
                             }
                         }
                     }
-                    Err(_e) => Ok(Reply::new(ReplyCode::NotLoggedIn, "Invalid credentials")),
+                    (Err(_e), _) => Ok(Reply::new(ReplyCode::NotLoggedIn, "Invalid credentials")),
                 }
             }
             (SessionState::New, None, _) | (SessionState::New, Some(_), false) => {
                 let user = std::str::from_utf8(&self.username)?;
                 session.username = Some(user.to_string());
+                session.activity.set_username(session.username.clone());
                 session.state = SessionState::WaitPass;
                 Ok(Reply::new(ReplyCode::NeedPassword, "Password Required"))
             }
@@ -250,6 +266,7 @@ mod tests {
                 tx_proxyloop: None,
                 logger: slog::Logger::root(slog::Discard {}, o!()),
                 sitemd5: Default::default(),
+                site_commands: Default::default(),
             }
         }
     }
@@ -1,53 +1,169 @@
 use crate::{
     auth::{Authenticator, UserDetail},
     metrics::MetricsMiddleware,
-    notification::{DataListener, PresenceListener},
+    notification::{DataListener, PresenceListener, TransferLogListener, UploadHook, UploadScanner},
     options::ActivePassiveMode,
     server::{
-        chancomms::{ControlChanMsg, ProxyLoopMsg, ProxyLoopSender},
+        chancomms::{ControlChanMsg, DataChanCmd, ProxyLoopMsg, ProxyLoopSender},
         controlchan::{
             active_passive::ActivePassiveEnforcerMiddleware,
             auth::AuthMiddleware,
             codecs::FtpCodec,
             command::Command,
+            command_policy::CommandPolicyMiddleware,
             commands,
             error::ControlChanError,
             error::ControlChanErrorKind,
-            ftps::{FtpsControlChanEnforcerMiddleware, FtpsDataChanEnforcerMiddleware},
+            ftps::{FtpsControlChanEnforcerMiddleware, FtpsDataChanEnforcerMiddleware, TlsFirstMiddleware},
             handler::{CommandContext, CommandHandler},
+            layers::LayersMiddleware,
+            limits::CommandLimiter,
             log::LoggingMiddleware,
             middleware::ControlChanMiddleware,
             notify::EventDispatcherMiddleware,
+            path_filter::PathFilterMiddleware,
+            quirks::{Fingerprint, QuirksMiddleware},
+            transfer_queue::TransferQueueMiddleware,
             Reply, ReplyCode,
         },
+        encoding::Charset,
         failed_logins::FailedLoginsCache,
-        ftpserver::options::{FtpsRequired, PassiveHost, SiteMd5},
+        ftpserver::options::{
+            AccessMode, CachedConnectionPolicy, ClientQuirks, CommandLimits, CommandPolicy, ConnectionDecision, ConnectionInfo, DataConnectionSource, Encoding,
+            EventDelivery, FtpsRequired, GreetingFn, MessageCatalog, ModeZ, PartialUploads, PassiveHost, PassivePortMapper, RecursiveListing, Redaction,
+            SessionCache, SiteMd5, SocketOptions, TlsFirst, UploadChecksum, UploadConflicts,
+        },
+        ftpserver::{middleware::Middleware, site::SiteCommands},
         proxy_protocol::ProxyConnection,
         session::SharedSession,
+        sessions::{ScratchDir, SessionActivity, SessionContext, SessionRegistry},
         shutdown,
         tls::FtpsConfig,
         Event, Session, SessionState,
     },
-    storage::{ErrorKind, Metadata, StorageBackend},
+    storage::{Metadata, PathFilter, StorageBackend},
 };
 use async_trait::async_trait;
 use futures_util::{SinkExt, StreamExt};
-use rustls::ServerConnection;
-use std::{net::SocketAddr, ops::Range, sync::Arc, time::Duration};
+use std::{
+    io,
+    net::{Ipv4Addr, SocketAddr},
+    ops::Range,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+#[cfg(feature = "ftps")]
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::{
-    io::{AsyncRead, AsyncWrite},
+    io::{AsyncRead, AsyncWrite, ReadBuf},
     net::TcpStream,
+    runtime::Handle,
     sync::{
         mpsc::{channel, Receiver, Sender},
-        Mutex,
+        oneshot, Mutex,
     },
     task::JoinHandle,
 };
+#[cfg(feature = "ftps")]
+use tokio_rustls::server::TlsStream;
 use tokio_util::codec::{Decoder, Framed};
 
-trait AsyncReadAsyncWriteSendUnpin: AsyncRead + AsyncWrite + Send + Unpin {}
+// The control connection, in plaintext or secured with TLS by AUTH TLS. It is kept typed so that
+// CCC can take the TCP stream out of the TLS stream again.
+enum ControlStream {
+    Plain(TcpStream),
+    #[cfg(feature = "ftps")]
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl AsyncRead for ControlStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ControlStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(feature = "ftps")]
+            ControlStream::Tls(stream) => Pin::new(stream.as_mut()).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for ControlStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            ControlStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(feature = "ftps")]
+            ControlStream::Tls(stream) => Pin::new(stream.as_mut()).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ControlStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(feature = "ftps")]
+            ControlStream::Tls(stream) => Pin::new(stream.as_mut()).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ControlStream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(feature = "ftps")]
+            ControlStream::Tls(stream) => Pin::new(stream.as_mut()).poll_shutdown(cx),
+        }
+    }
+}
+
+// Ends TLS on the control connection for CCC the way RFC 4217 describes it: the server sends its
+// close_notify alert after the reply to CCC and waits for the one of the client, after which both
+// sides talk plaintext over the same TCP connection.
+#[cfg(feature = "ftps")]
+async fn clear_tls(mut stream: TlsStream<TcpStream>) -> io::Result<TcpStream> {
+    stream.get_mut().1.send_close_notify();
+    stream.flush().await?;
+    let mut buf = [0u8; 512];
+    match tokio::time::timeout(Duration::from_secs(10), stream.read(&mut buf)).await {
+        Ok(Ok(0)) => Ok(stream.into_inner().0),
+        // The client may not send commands before it cleared TLS on its side.
+        Ok(Ok(_)) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "received data before the close_notify of the client",
+        )),
+        Ok(Err(err)) => Err(err),
+        Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "no close_notify from the client")),
+    }
+}
 
-impl<T: AsyncRead + AsyncWrite + Send + Unpin> AsyncReadAsyncWriteSendUnpin for T {}
+// Takes the TCP stream out of the control connection again after CCC.
+async fn into_plaintext(stream: ControlStream) -> io::Result<TcpStream> {
+    match stream {
+        #[cfg(feature = "ftps")]
+        ControlStream::Tls(stream) => clear_tls(*stream).await,
+        ControlStream::Plain(_) => panic!("Control channel is in plaintext already. Illegal program state"),
+    }
+}
+
+// Wraps the control connection in TLS after AUTH TLS and returns the certificates the client
+// presented, if any. The handshake has to complete within the given time.
+#[cfg_attr(not(feature = "ftps"), allow(unused_variables))]
+async fn accept_tls(io: TcpStream, ftps_config: &FtpsConfig, timeout: Duration) -> io::Result<(ControlStream, Option<Vec<crate::auth::ClientCert>>)> {
+    match ftps_config {
+        #[cfg(feature = "ftps")]
+        FtpsConfig::On { tls_config, .. } => {
+            let acceptor: tokio_rustls::TlsAcceptor = tls_config.clone().into();
+            let stream = tokio::time::timeout(timeout, acceptor.accept(io))
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "the TLS handshake timed out"))??;
+            let certs = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .map(|certs| certs.iter().map(|c| crate::auth::ClientCert(c.as_ref().to_vec())).collect());
+            Ok((ControlStream::Tls(Box::new(stream)), certs))
+        }
+        _ => panic!("Could not create TLS acceptor. Illegal program state"),
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Config<Storage, User>
@@ -57,20 +173,58 @@ where
 {
     pub storage: Storage,
     pub greeting: &'static str,
+    pub encoding: Encoding,
+    pub data_connection_source: DataConnectionSource,
+    pub control_socket_options: SocketOptions,
+    pub data_socket_options: SocketOptions,
+    pub data_bind_address: Option<Ipv4Addr>,
+    pub passive_port_mapper: Option<Arc<dyn PassivePortMapper>>,
+    pub data_runtime: Option<Handle>,
+    pub recursive_listing: RecursiveListing,
+    pub client_quirks: ClientQuirks,
+    pub mdtm_setter: bool,
+    pub redaction: Redaction,
+    pub middleware: Arc<Vec<Arc<dyn Middleware>>>,
+    pub login_message: Option<String>,
+    pub greeting_provider: Option<GreetingFn>,
+    pub message_catalog: Option<Arc<dyn MessageCatalog>>,
+    pub access_mode: AccessMode,
+    pub command_policy: CommandPolicy,
+    pub mode_z: ModeZ,
+    pub upload_scanner: Option<Arc<dyn UploadScanner>>,
+    pub upload_hook: Option<Arc<dyn UploadHook>>,
+    pub transfer_log: Option<Arc<dyn TransferLogListener>>,
+    pub session_cache: Option<Arc<dyn SessionCache>>,
+    pub progress_interval: Option<Duration>,
+    pub event_delivery: Option<EventDelivery>,
+    pub upload_checksum: UploadChecksum,
+    pub partial_uploads: PartialUploads,
+    pub upload_conflicts: UploadConflicts,
     pub authenticator: Arc<dyn Authenticator<User>>,
     pub passive_ports: Range<u16>,
     pub passive_host: PassiveHost,
     pub ftps_config: FtpsConfig,
     pub collect_metrics: bool,
     pub idle_session_timeout: Duration,
+    pub data_stall_timeout: Option<Duration>,
+    pub max_session_duration: Option<Duration>,
+    pub command_limits: CommandLimits,
+    pub max_unauthenticated_sessions: Option<usize>,
+    pub connection_policy: Option<Arc<CachedConnectionPolicy>>,
     pub logger: slog::Logger,
     pub ftps_required_control_chan: FtpsRequired,
     pub ftps_required_data_chan: FtpsRequired,
+    pub ftps_tls_first: TlsFirst,
+    pub ftps_handshake_timeout: Duration,
     pub site_md5: SiteMd5,
     pub data_listener: Arc<dyn DataListener>,
     pub presence_listener: Arc<dyn PresenceListener>,
+    pub path_filter: Arc<dyn PathFilter>,
     pub active_passive_mode: ActivePassiveMode,
     pub binder: Arc<std::sync::Mutex<Option<Box<dyn crate::options::Binder>>>>,
+    pub sessions: Arc<SessionRegistry>,
+    pub max_connections: Option<usize>,
+    pub site_commands: SiteCommands<Storage, User>,
 }
 
 /// Does TCP processing when an FTP client connects
@@ -90,39 +244,162 @@ where
 {
     let Config {
         storage,
+        encoding,
+        upload_hook,
+        upload_scanner,
+        transfer_log,
+        session_cache,
+        progress_interval,
+        event_delivery,
+        upload_checksum,
+        partial_uploads,
+        upload_conflicts,
+        mode_z,
+        recursive_listing,
+        client_quirks,
+        mdtm_setter,
+        redaction,
+        data_connection_source,
+        control_socket_options,
+        data_socket_options,
+        data_bind_address,
+        passive_port_mapper,
+        data_runtime,
         authenticator,
         passive_ports,
         passive_host,
         ftps_config,
         ftps_required_control_chan,
         ftps_required_data_chan,
+        ftps_tls_first,
+        ftps_handshake_timeout,
         collect_metrics,
         idle_session_timeout,
+        data_stall_timeout,
+        max_session_duration,
+        command_limits,
+        max_unauthenticated_sessions,
+        connection_policy,
         logger,
         site_md5: sitemd5,
         data_listener,
         presence_listener,
+        path_filter,
         active_passive_mode,
         binder,
+        sessions,
+        max_connections,
+        command_policy,
+        access_mode,
+        message_catalog,
+        greeting_provider,
+        login_message,
+        site_commands,
+        middleware,
         ..
     } = config;
 
+    if let Err(err) = control_socket_options.apply(&tcp_stream) {
+        slog::warn!(logger, "Could not set the socket options of the control connection: {}", err);
+    }
+
+    #[cfg(feature = "ftps")]
     let tls_configured = matches!(ftps_config, FtpsConfig::On { .. });
+    #[cfg(not(feature = "ftps"))]
+    let tls_configured = false;
     let storage_features = storage.supported_features();
     let (control_msg_tx, mut control_msg_rx): (Sender<ControlChanMsg>, Receiver<ControlChanMsg>) = channel(1);
     let local_addr = tcp_stream.local_addr()?;
+    let charset = Charset::new(encoding);
+    let source = proxy_connection.map(|p| p.source).unwrap_or(tcp_stream.peer_addr()?);
+    let refusal = match (max_connections, max_unauthenticated_sessions) {
+        (Some(max), _) if sessions.count() >= max => Some(format!("the maximum of {} sessions is reached", max)),
+        (_, Some(max)) if sessions.count_unauthenticated() >= max => Some(format!("the maximum of {} sessions that didn't log in is reached", max)),
+        _ => None,
+    };
+    if let Some(reason) = refusal {
+        slog::warn!(logger, "Refusing connection from {}: {}", redaction.addr(source), reason);
+        let mut reply_sink = FtpCodec::new(charset, command_limits.max_line_length).framed(ControlStream::Plain(tcp_stream));
+        reply_sink
+            .send(Reply::new(ReplyCode::ServiceNotAvailable, "Too many connections, try again later"))
+            .await?;
+        return Err(ControlChanErrorKind::TooManyConnections.into());
+    }
+    let rejection = if sessions.is_banned(source.ip()) {
+        Some("the address is banned".to_string())
+    } else if sessions.is_draining() {
+        Some("the server is draining".to_string())
+    } else {
+        match &connection_policy {
+            Some(policy) => match policy.check(source.ip()).await {
+                ConnectionDecision::Reject(reason) => Some(reason),
+                ConnectionDecision::Allow => None,
+            },
+            None => None,
+        }
+    };
+    if let Some(reason) = rejection {
+        slog::warn!(logger, "Refusing connection from {}: {}", redaction.addr(source), reason);
+        let mut reply_sink = FtpCodec::new(charset, command_limits.max_line_length).framed(ControlStream::Plain(tcp_stream));
+        reply_sink
+            .send(Reply::new(ReplyCode::ServiceNotAvailable, "Service not available, closing control connection"))
+            .await?;
+        return Err(ControlChanErrorKind::ConnectionRejected.into());
+    }
+    let activity = Arc::new(SessionActivity::new(source));
     let mut session: Session<Storage, User> = Session::new(Arc::new(storage), tcp_stream.peer_addr()?)
         .ftps(ftps_config.clone())
+        .charset(charset.clone())
         .metrics(collect_metrics)
+        .data_stall_timeout(data_stall_timeout)
+        .partial_uploads(partial_uploads)
+        .upload_conflicts(upload_conflicts)
+        .upload_checksum(upload_checksum)
+        .upload_hook(upload_hook)
+        .upload_scanner(upload_scanner)
+        .transfer_log(transfer_log)
+        .session_cache(session_cache)
+        .progress_events(progress_interval.map(|interval| (data_listener.clone(), interval)))
+        .file_attributes(data_listener.file_attributes())
+        .mode_z(mode_z)
+        .recursive_listing(recursive_listing)
+        .mdtm_setter(mdtm_setter)
+        .redaction(redaction)
+        .connection_policy(connection_policy)
+        .data_connection_source(data_connection_source)
+        .data_socket_options(data_socket_options)
+        .data_bind_address(data_bind_address)
+        .passive_port_mapper(passive_port_mapper)
+        .data_runtime(data_runtime)
+        .message_catalog(message_catalog)
         .control_msg_tx(control_msg_tx.clone())
         .proxy_connection(proxy_connection)
+        .activity(activity.clone())
         .failed_logins(failed_logins);
+    let scratch = match session.storage.session_scratch() {
+        Some(base) => match ScratchDir::create(&base, session.trace_id) {
+            Ok(scratch) => Some(scratch),
+            Err(err) => {
+                slog::warn!(logger, "Could not create a scratch directory for the session in {:?}: {}", base, err);
+                None
+            }
+        },
+        None => None,
+    };
+    if let Some(storage) = Arc::get_mut(&mut session.storage) {
+        storage.set_session_context(SessionContext::new(
+            session.trace_id,
+            activity.clone(),
+            scratch.as_ref().map(|scratch| scratch.path().to_path_buf()),
+        ));
+    }
+    let registration = sessions.register(session.trace_id, activity.clone());
     if let Some(b) = binder.lock().unwrap().take() {
         session = session.binder(b);
     }
 
     let mut logger = logger.new(
-        slog::o!("trace-id" => format!("{}", session.trace_id), "source" => format!("{}", session.proxy_control.map(|p| p.source).unwrap_or(session.source))),
+        slog::o!("trace-id" => format!("{}", session.trace_id), "source" => format!("{}", redaction.addr(session.proxy_control.map(|p| p.source).unwrap_or(session.source)))),
     );
 
     let shared_session: SharedSession<Storage, User> = Arc::new(Mutex::new(session));
@@ -139,15 +416,43 @@ where
         storage_features,
         tx_proxy_loop: proxyloop_msg_tx.clone(),
         sitemd5,
+        login_message,
+        site_commands,
     };
 
-    let event_chain = EventDispatcherMiddleware::new(data_listener, presence_listener, event_chain);
+    let event_chain = EventDispatcherMiddleware::new(
+        data_listener,
+        presence_listener,
+        activity.clone(),
+        redaction,
+        event_delivery,
+        logger.clone(),
+        event_chain,
+    );
+
+    let event_chain = TransferQueueMiddleware {
+        session: shared_session.clone(),
+        max_held: command_limits.max_held_commands,
+        next: event_chain,
+    };
+
+    let event_chain = PathFilterMiddleware {
+        filter: path_filter,
+        next: event_chain,
+    };
 
     let event_chain = ActivePassiveEnforcerMiddleware {
         mode: active_passive_mode,
         next: event_chain,
     };
 
+    let event_chain = CommandPolicyMiddleware {
+        session: shared_session.clone(),
+        policy: command_policy,
+        access_mode,
+        next: event_chain,
+    };
+
     let event_chain = AuthMiddleware {
         session: shared_session.clone(),
         next: event_chain,
@@ -165,9 +470,32 @@ where
         next: event_chain,
     };
 
+    let event_chain = TlsFirstMiddleware {
+        tls_first: ftps_tls_first,
+        secured: false,
+        next: event_chain,
+    };
+
+    let event_chain = LayersMiddleware {
+        session: shared_session.clone(),
+        source,
+        layers: middleware,
+        next: event_chain,
+    };
+
+    let event_chain = QuirksMiddleware {
+        session: shared_session.clone(),
+        client_quirks,
+        fingerprint: Fingerprint::default(),
+        collect_metrics,
+        logger: logger.clone(),
+        next: event_chain,
+    };
+
     let event_chain = LoggingMiddleware {
         logger: logger.clone(),
         sequence_nr: 0,
+        redaction,
         next: event_chain,
     };
 
@@ -176,14 +504,37 @@ where
         next: event_chain,
     };
 
-    let codec = FtpCodec::new();
-    let cmd_and_reply_stream: Framed<Box<dyn AsyncReadAsyncWriteSendUnpin>, FtpCodec> = codec.framed(Box::new(tcp_stream));
+    // Clients can't be recognised before their first command is decoded.
+    let bare_cr = client_quirks == ClientQuirks::Apply;
+    let codec = FtpCodec::new(charset.clone(), command_limits.max_line_length).bare_cr(bare_cr);
+    let cmd_and_reply_stream: Framed<ControlStream, FtpCodec> = codec.framed(ControlStream::Plain(tcp_stream));
     let (mut reply_sink, mut command_source) = cmd_and_reply_stream.split();
 
-    reply_sink.send(Reply::new(ReplyCode::ServiceReady, config.greeting)).await?;
-    reply_sink.flush().await?;
+    // With TlsFirst::RequiredHideGreeting the greeting is only sent once the TLS handshake completed.
+    let greeting = match greeting_provider {
+        Some(provider) => (provider.0)(&ConnectionInfo { source, local_addr }),
+        None => config.greeting.to_string(),
+    };
+    let greeting = match greeting.contains('\n') {
+        true => Reply::multiline(ReplyCode::ServiceReady).line(greeting).build(),
+        false => Reply::new_with_string(ReplyCode::ServiceReady, greeting),
+    };
+    let mut withheld_greeting = None;
+    if ftps_tls_first == TlsFirst::RequiredHideGreeting {
+        withheld_greeting = Some(greeting);
+    } else {
+        reply_sink.send(greeting).await?;
+        reply_sink.flush().await?;
+    }
+
+    // The session is closed at this point in time, even if a transfer is in progress.
+    let session_deadline = max_session_duration.map(|duration| tokio::time::Instant::now() + duration);
+    let mut limiter = CommandLimiter::new(command_limits);
 
     let jh = tokio::spawn(async move {
+        // Lists the session and keeps its scratch directory until the control loop ends.
+        let _registration = registration;
+        let _scratch = scratch;
         // The control channel event loop
         slog::info!(logger, "Starting control loop");
         loop {
@@ -191,32 +542,62 @@ where
                 #[allow(unused_assignments)]
                 let mut incoming = None;
                 let mut timeout_delay = Box::pin(tokio::time::sleep(idle_session_timeout));
-                tokio::select! {
-                    cmd = command_source.next() => {
-                        match cmd {
-                            Some(cmd_result) => incoming = Some(cmd_result.map(Event::Command)),
-                            None => {
-                                slog::info!(logger, "Control connection was closed.");
-                                incoming = Some(Ok(Event::InternalMsg(ControlChanMsg::ExitControlLoop)))
+                // Commands held back during a transfer are replayed once it finished, after the
+                // messages that the data channel sent about it.
+                let (held, transfer_in_progress, transfer) = {
+                    let session = shared_session.lock().await;
+                    (!session.held_commands.is_empty(), session.transfer_in_progress(), session.transfer.clone())
+                };
+                if held && !transfer_in_progress {
+                    let msg = control_msg_rx.try_recv().unwrap_or(ControlChanMsg::ReplayHeldCommand);
+                    incoming = Some(Ok(Event::InternalMsg(msg)));
+                } else {
+                    tokio::select! {
+                        cmd = command_source.next() => {
+                            match cmd {
+                                Some(Ok(cmd)) => {
+                                    let logged_in = limiter.counts_logins() && shared_session.lock().await.state == SessionState::WaitCmd;
+                                    incoming = Some(limiter.check(logged_in).map(|_| Event::Command(cmd)))
+                                }
+                                Some(Err(err)) => incoming = Some(Err(err)),
+                                None => {
+                                    slog::info!(logger, "Control connection was closed.");
+                                    incoming = Some(Ok(Event::InternalMsg(ControlChanMsg::ExitControlLoop)))
+                                }
+                            }
+                        },
+                        Some(msg) = control_msg_rx.recv() => {
+                            incoming = Some(Ok(Event::InternalMsg(msg)));
+                        },
+                        _ = &mut timeout_delay => {
+                            // Transfers in progress are guarded by the data stall timeout instead.
+                            let session = shared_session.lock().await;
+                            match session.data_busy {
+                                true => incoming = None,
+                                false => incoming = Some(Err(ControlChanError::new(ControlChanErrorKind::ControlChannelTimeout)))
+                            };
+                        },
+                        _ = sleep_until(session_deadline) => {
+                            incoming = Some(Err(ControlChanError::new(ControlChanErrorKind::SessionExpired)))
+                        },
+                        _ = activity.killed() => {
+                            slog::info!(logger, "Closing control connection because the session was terminated");
+                            // Stop the transfer in progress as well.
+                            if let Some(tx) = &shared_session.lock().await.data_abort_tx {
+                                let _ = tx.try_send(oneshot::channel().0);
                             }
+                            incoming = Some(Err(ControlChanError::new(ControlChanErrorKind::SessionTerminated)))
+                        },
+                        _ = transfer_closed(transfer), if held => {
+                            // Loop again to replay the held commands.
+                        },
+                        _ = shutdown.listen() => {
+                            slog::info!(logger, "Closing open control connection because of shutdown signal");
+                            incoming = Some(Ok(Event::InternalMsg(ControlChanMsg::ExitControlLoop)))
+                            // TODO: Do we want to wait a bit for a data transfer to complete i.e. session.data_busy is true?
                         }
-                    },
-                    Some(msg) = control_msg_rx.recv() => {
-                        incoming = Some(Ok(Event::InternalMsg(msg)));
-                    },
-                    _ = &mut timeout_delay => {
-                        let session = shared_session.lock().await;
-                        match session.data_busy {
-                            true => incoming = None,
-                            false => incoming = Some(Err(ControlChanError::new(ControlChanErrorKind::ControlChannelTimeout)))
-                        };
-                    },
-                    _ = shutdown.listen() => {
-                        slog::info!(logger, "Closing open control connection because of shutdown signal");
-                        incoming = Some(Ok(Event::InternalMsg(ControlChanMsg::ExitControlLoop)))
-                        // TODO: Do we want to wait a bit for a data transfer to complete i.e. session.data_busy is true?
-                    }
-                };
+                    };
+                }
                 incoming
             };
             match incoming {
@@ -238,22 +619,21 @@ where
 
                         // Get back the original TCP Stream
                         let codec_io = reply_sink.reunite(command_source).unwrap();
-                        let io = codec_io.into_inner();
+                        #[cfg_attr(not(feature = "ftps"), allow(clippy::infallible_destructuring_match))]
+                        let io = match codec_io.into_inner() {
+                            ControlStream::Plain(io) => io,
+                            #[cfg(feature = "ftps")]
+                            ControlStream::Tls(_) => panic!("Control channel is secured already. Illegal program state"),
+                        };
 
                         // Wrap in TLS Stream
-                        let acceptor: tokio_rustls::TlsAcceptor = match ftps_config.clone() {
-                            FtpsConfig::On { tls_config } => tls_config.into(),
-                            _ => panic!("Could not create TLS acceptor. Illegal program state"),
-                        };
-                        let accepted = acceptor.accept(io).await;
-                        let io: Box<dyn AsyncReadAsyncWriteSendUnpin> = match accepted {
-                            Ok(stream) => {
-                                let s: &ServerConnection = stream.get_ref().1;
-                                if let Some(certs) = s.peer_certificates() {
+                        let io = match accept_tls(io, &ftps_config, ftps_handshake_timeout).await {
+                            Ok((stream, certs)) => {
+                                if let Some(certs) = certs {
                                     let mut session = shared_session.lock().await;
-                                    session.cert_chain = Some(certs.iter().map(|c| crate::auth::ClientCert(c.as_ref().to_vec())).collect());
+                                    session.cert_chain = Some(certs);
                                 }
-                                Box::new(stream)
+                                stream
                             }
                             Err(err) => {
                                 slog::warn!(logger, "Closing control channel. Could not upgrade to TLS: {}", err);
@@ -262,33 +642,87 @@ where
                         };
 
                         // Wrap in codec again and get sink + source
-                        let codec = FtpCodec::new();
+                        let codec = FtpCodec::new(charset.clone(), command_limits.max_line_length).bare_cr(bare_cr);
                         let cmd_and_reply_stream = codec.framed(io);
                         let (sink, src) = cmd_and_reply_stream.split();
                         reply_sink = sink;
                         command_source = src;
+
+                        if let Some(greeting) = withheld_greeting.take() {
+                            if let Err(err) = reply_sink.send(greeting).await {
+                                slog::warn!(logger, "Could not send greeting after TLS upgrade: {:?}. Closing control connection", err);
+                                return;
+                            }
+                        }
                     }
 
                     if let Event::Command(Command::User { username }) = &event {
-                        let s: String = String::from_utf8_lossy(username).into();
+                        let s: String = redaction.user(&String::from_utf8_lossy(username));
                         logger = logger.new(slog::o!("username" => s));
                     }
 
-                    // TODO: Handle Event::InternalMsg(InternalMsg::PlaintextControlChannel)
-
+                    let clear_command = match &event {
+                        Event::Command(Command::Ccc) => true,
+                        Event::InternalMsg(ControlChanMsg::ReplayHeldCommand) => {
+                            matches!(shared_session.lock().await.held_commands.front(), Some(Event::Command(Command::Ccc)))
+                        }
+                        _ => false,
+                    };
+                    let mut clear_tls_now = false;
                     let handle_result = match event_chain.handle(event).await {
+                        // Told to the client, unlike the errors of the handlers.
+                        Err(e) if e.kind() == &ControlChanErrorKind::TooManyHeldCommands => {
+                            let (reply, _) = handle_control_channel_error(logger.clone(), e);
+                            let _ = reply_sink.send(translate(&shared_session, reply).await).await;
+                            return;
+                        }
                         Err(e) => Err(e),
-                        Ok(reply) => reply_sink.send(reply).await,
+                        Ok(reply) => {
+                            clear_tls_now = clear_command
+                                && matches!(
+                                    reply,
+                                    Reply::CodeAndMsg {
+                                        code: ReplyCode::CommandOkay,
+                                        ..
+                                    }
+                                );
+                            reply_sink.send(translate(&shared_session, reply).await).await
+                        }
                     };
 
                     if let Err(chan_err) = handle_result {
                         slog::warn!(logger, "Event handler chain error: {:?}. Closing control connection", chan_err);
                         return;
                     }
+
+                    if clear_tls_now {
+                        slog::info!(logger, "Downgrading control channel to plaintext");
+
+                        // The reply to CCC has been flushed to the client already.
+                        let codec_io = reply_sink.reunite(command_source).unwrap();
+                        let io = match into_plaintext(codec_io.into_inner()).await {
+                            Ok(io) => io,
+                            Err(err) => {
+                                slog::warn!(logger, "Closing control channel. Could not downgrade to plaintext: {}", err);
+                                return;
+                            }
+                        };
+
+                        let codec = FtpCodec::new(charset.clone(), command_limits.max_line_length).bare_cr(bare_cr);
+                        let cmd_and_reply_stream = codec.framed(ControlStream::Plain(io));
+                        let (sink, src) = cmd_and_reply_stream.split();
+                        reply_sink = sink;
+                        command_source = src;
+
+                        if let Err(err) = event_chain.handle(Event::InternalMsg(ControlChanMsg::PlaintextControlChannel)).await {
+                            slog::warn!(logger, "Event handler chain error: {:?}. Closing control connection", err);
+                            return;
+                        }
+                    }
                 }
                 Some(Err(e)) => {
                     let (reply, close_connection) = handle_control_channel_error(logger.clone(), e);
-                    let result = reply_sink.send(reply).await;
+                    let result = reply_sink.send(translate(&shared_session, reply).await).await;
                     if result.is_err() {
                         slog::warn!(logger, "Could not send error reply to client");
                         return;
@@ -304,6 +738,36 @@ where
     Ok(jh)
 }
 
+// Waits until the data channel finished the transfer, or forever if there is none.
+async fn transfer_closed(transfer: Option<Sender<DataChanCmd>>) {
+    match transfer {
+        Some(tx) => tx.closed().await,
+        None => std::future::pending().await,
+    }
+}
+
+// Waits until the given deadline, or forever if there is none.
+// Translates the reply to the language the client chose with LANG.
+async fn translate<Storage, User>(session: &SharedSession<Storage, User>, reply: Reply) -> Reply
+where
+    User: UserDetail + 'static,
+    Storage: StorageBackend<User> + 'static,
+    Storage::Metadata: Metadata,
+{
+    let session = session.lock().await;
+    match (&session.message_catalog, &session.language) {
+        (Some(catalog), Some(language)) => reply.translate(catalog.as_ref(), language),
+        _ => reply,
+    }
+}
+
+async fn sleep_until(deadline: Option<tokio::time::Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
 // gets the reply to be sent to the client and tells if the connection should be closed.
 fn handle_control_channel_error(logger: slog::Logger, error: ControlChanError) -> (Reply, bool) {
     slog::warn!(logger, "Control channel error: {:?}", error);
@@ -315,6 +779,32 @@ fn handle_control_channel_error(logger: slog::Logger, error: ControlChanError) -
             Reply::new(ReplyCode::ClosingControlConnection, "Session timed out. Closing control connection"),
             true,
         ),
+        ControlChanErrorKind::SessionExpired => (
+            Reply::new(
+                ReplyCode::ClosingControlConnection,
+                "Maximum session duration reached. Closing control connection",
+            ),
+            true,
+        ),
+        ControlChanErrorKind::LineTooLong => (Reply::new(ReplyCode::CommandSyntaxError, "Command line too long"), true),
+        ControlChanErrorKind::TooManyCommands | ControlChanErrorKind::TooManyCommandsBeforeLogin => (
+            Reply::new(ReplyCode::ServiceNotAvailable, "Too many commands. Closing control connection"),
+            true,
+        ),
+        ControlChanErrorKind::TooManyHeldCommands => (
+            Reply::new(
+                ReplyCode::ServiceNotAvailable,
+                "Too many commands during the transfer. Closing control connection",
+            ),
+            true,
+        ),
+        ControlChanErrorKind::SessionTerminated => (
+            Reply::new(
+                ReplyCode::ClosingControlConnection,
+                "Session terminated by the administrator. Closing control connection",
+            ),
+            true,
+        ),
         _ => (Reply::new(ReplyCode::LocalError, "Unknown internal server error, please try again later"), true),
     }
 }
@@ -337,6 +827,8 @@ where
     storage_features: u32,
     tx_proxy_loop: Option<ProxyLoopSender<Storage, User>>,
     sitemd5: SiteMd5,
+    login_message: Option<String>,
+    site_commands: SiteCommands<Storage, User>,
 }
 
 impl<Storage, User> PrimaryEventHandler<Storage, User>
@@ -353,62 +845,59 @@ where
         match msg {
             NotFound => Ok(Reply::new(ReplyCode::FileError, "File not found")),
             PermissionDenied => Ok(Reply::new(ReplyCode::FileError, "Permision denied")),
-            SentData { .. } => {
-                let mut session = self.session.lock().await;
-                session.start_pos = 0;
-                Ok(Reply::new(ReplyCode::ClosingDataConnection, "Successfully sent"))
-            }
+            SentData { .. } => Ok(Reply::new(ReplyCode::ClosingDataConnection, "Successfully sent")),
             WriteFailed => Ok(Reply::new(ReplyCode::TransientFileError, "Failed to write file")),
             ConnectionReset => Ok(Reply::new(ReplyCode::ConnectionClosed, "Datachannel unexpectedly closed")),
-            WrittenData { .. } => {
-                let mut session = self.session.lock().await;
-                session.start_pos = 0;
-                Ok(Reply::new(ReplyCode::ClosingDataConnection, "File successfully written"))
-            }
+            WrittenData { unique_name, .. } => match unique_name {
+                // RFC 1123 asks for the name in this form.
+                Some(name) => Ok(Reply::new_with_string(ReplyCode::FileActionOkay, format!("FILE: {}", name))),
+                None => Ok(Reply::new(ReplyCode::ClosingDataConnection, "File successfully written")),
+            },
+            UploadRejected { reason } => Ok(Reply::new_with_string(ReplyCode::FileError, format!("Upload rejected: {}", reason))),
+            UploadScanFailed { reason } => Ok(Reply::new_with_string(ReplyCode::LocalError, format!("Upload failed the scan: {}", reason))),
             DataConnectionClosedAfterStor => Ok(Reply::new(ReplyCode::FileActionOkay, "unFTP holds your data for you")),
             DirectorySuccessfullyListed => Ok(Reply::new(ReplyCode::ClosingDataConnection, "Listed the directory")),
             DirectoryListFailure => Ok(Reply::new(ReplyCode::ClosingDataConnection, "Failed to list the directory")),
             CwdSuccess => Ok(Reply::new(ReplyCode::FileActionOkay, "Successfully changed working directory")),
             DelFileSuccess { .. } | RmDirSuccess { .. } => Ok(Reply::new(ReplyCode::FileActionOkay, "Successfully removed")),
             DelFail => Ok(Reply::new(ReplyCode::TransientFileError, "Failed to delete the file")),
-            ExitControlLoop => Ok(Reply::none()),
+            ExitControlLoop | ReplayHeldCommand => Ok(Reply::none()),
             SecureControlChannel => {
                 let mut session = self.session.lock().await;
-                session.cmd_tls = true;
+                session.secure_control_channel();
                 Ok(Reply::none())
             }
             PlaintextControlChannel => {
                 let mut session = self.session.lock().await;
-                session.cmd_tls = false;
+                session.clear_control_channel();
                 Ok(Reply::none())
             }
             MkDirSuccess { path } => Ok(Reply::new_with_string(ReplyCode::DirCreated, path)),
             MkdirFail => Ok(Reply::new(ReplyCode::FileError, "Failed to create directory")),
             RenameSuccess { .. } => Ok(Reply::new(ReplyCode::FileActionOkay, "Renamed")),
+            CopySuccess { .. } => Ok(Reply::new(ReplyCode::FileActionOkay, "Copy successful")),
             AuthSuccess { .. } => {
                 let mut session = self.session.lock().await;
                 session.state = WaitCmd;
-                Ok(Reply::new(ReplyCode::UserLoggedIn, "User logged in, proceed"))
+                session.activity.set_authenticated();
+                let message = (*session.user)
+                    .as_ref()
+                    .and_then(|user| user.login_message())
+                    .or_else(|| self.login_message.clone());
+                match message {
+                    Some(message) => Ok(Reply::multiline(ReplyCode::UserLoggedIn).line(message).line("User logged in, proceed").build()),
+                    None => Ok(Reply::new(ReplyCode::UserLoggedIn, "User logged in, proceed")),
+                }
             }
             AuthFailed => {
                 let mut session = self.session.lock().await;
                 session.state = New; // According to RFC 959, a PASS command MUST precede a USER command
                 Ok(Reply::new(ReplyCode::NotLoggedIn, "Authentication failed"))
             }
-            StorageError(error_type) => match error_type.kind() {
-                ErrorKind::ExceededStorageAllocationError => Ok(Reply::new(ReplyCode::ExceededStorageAllocation, "Exceeded storage allocation")),
-                ErrorKind::FileNameNotAllowedError => Ok(Reply::new(ReplyCode::BadFileName, "File name not allowed")),
-                ErrorKind::InsufficientStorageSpaceError => Ok(Reply::new(ReplyCode::OutOfSpace, "Insufficient storage space")),
-                ErrorKind::LocalError => Ok(Reply::new(ReplyCode::LocalError, "Local error")),
-                ErrorKind::PageTypeUnknown => Ok(Reply::new(ReplyCode::PageTypeUnknown, "Page type unknown")),
-                ErrorKind::TransientFileNotAvailable => Ok(Reply::new(ReplyCode::TransientFileError, "File not found")),
-                ErrorKind::PermanentFileNotAvailable => Ok(Reply::new(ReplyCode::FileError, "File not found")),
-                ErrorKind::PermanentDirectoryNotAvailable => Ok(Reply::new(ReplyCode::FileError, "Directory not found")),
-                ErrorKind::PermanentDirectoryNotEmpty => Ok(Reply::new(ReplyCode::FileError, "Directory not empty")),
-                ErrorKind::PermissionDenied => Ok(Reply::new(ReplyCode::FileError, "Permission denied")),
-                ErrorKind::CommandNotImplemented => Ok(Reply::new(ReplyCode::CommandNotImplemented, "Command not implemented")),
-                ErrorKind::ConnectionClosed => Ok(Reply::new(ReplyCode::ConnectionClosed, "Connection closed")),
-            },
+            StorageError(error) => {
+                slog::debug!(self.logger, "Replying to {}", error);
+                Ok(Reply::from(&error))
+            }
             CommandChannelReply(reply) => Ok(reply),
         }
     }
@@ -428,6 +917,7 @@ where
             tx_proxyloop: self.tx_proxy_loop.clone(),
             logger: self.logger.clone(),
             sitemd5: self.sitemd5,
+            site_commands: self.site_commands.clone(),
         };
 
         let handler: Box<dyn CommandHandler<Storage, User>> = match cmd {
@@ -436,10 +926,11 @@ where
             Command::Syst => Box::new(commands::Syst),
             Command::Stat { path } => Box::new(commands::Stat::new(path)),
             Command::Acct { .. } => Box::new(commands::Acct),
-            Command::Type => Box::new(commands::Type),
+            Command::Type { type_ } => Box::new(commands::Type::new(type_)),
             Command::Stru { structure } => Box::new(commands::Stru::new(structure)),
             Command::Mode { mode } => Box::new(commands::Mode::new(mode)),
             Command::Help => Box::new(commands::Help),
+            Command::Lang { language } => Box::new(commands::Lang::new(language)),
             Command::Noop => Box::new(commands::Noop),
             Command::Pasv => Box::new(commands::Pasv::new()),
             Command::Port { addr } => Box::new(commands::Port::new(addr)),
@@ -467,8 +958,13 @@ where
             Command::Prot { param } => Box::new(commands::Prot::new(param)),
             Command::Size { file } => Box::new(commands::Size::new(file)),
             Command::Rest { offset } => Box::new(commands::Rest::new(offset)),
-            Command::Mdtm { file } => Box::new(commands::Mdtm::new(file)),
-            Command::Md5 { file } => Box::new(commands::Md5::new(file)),
+            Command::Mdtm { file, modified } => Box::new(commands::Mdtm::new(file, modified)),
+            Command::Md5 { file } => Box::new(commands::Site::new("MD5".to_string(), file.to_string_lossy().into_owned())),
+            Command::Site { name, arguments } => Box::new(commands::Site::new(name, arguments)),
+            Command::Chown { owner, file } => Box::new(commands::Chown::new(owner, file)),
+            Command::Symlink { target, link } => Box::new(commands::Symlink::new(target, link)),
+            Command::Cpfr { file } => Box::new(commands::Cpfr::new(file)),
+            Command::Cpto { file } => Box::new(commands::Cpto::new(file)),
             Command::Other { .. } => return Ok(Reply::new(ReplyCode::CommandSyntaxError, "Command not implemented")),
         };
 
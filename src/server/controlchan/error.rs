@@ -52,6 +52,30 @@ pub enum ControlChanErrorKind {
     /// The timer on the Control Channel elapsed.
     #[display(fmt = "Encountered read timeout on the control channel")]
     ControlChannelTimeout,
+    /// The maximum duration of the session elapsed.
+    #[display(fmt = "Maximum session duration reached")]
+    SessionExpired,
+    /// The maximum number of sessions was reached when the client connected.
+    #[display(fmt = "Too many connections")]
+    TooManyConnections,
+    /// The [`ConnectionPolicy`](crate::options::ConnectionPolicy) rejected the client.
+    #[display(fmt = "Connection rejected by policy")]
+    ConnectionRejected,
+    /// The client sent a command line longer than the configured maximum.
+    #[display(fmt = "Command line too long")]
+    LineTooLong,
+    /// The client sent more commands per second than allowed.
+    #[display(fmt = "Too many commands")]
+    TooManyCommands,
+    /// The client sent more commands before logging in than allowed.
+    #[display(fmt = "Too many commands before login")]
+    TooManyCommandsBeforeLogin,
+    /// The client sent more commands during a data transfer than are held back.
+    #[display(fmt = "Too many commands during a transfer")]
+    TooManyHeldCommands,
+    /// The session was terminated through the ServerHandle.
+    #[display(fmt = "Session terminated")]
+    SessionTerminated,
     /// The control channel is out of sync e.g. expecting username in session after USER command but found none.
     #[display(fmt = "Control channel in illegal state")]
     IllegalState,
@@ -3,8 +3,8 @@ use async_trait::async_trait;
 use crate::{
     auth::UserDetail,
     server::{
-        controlchan::error::ControlChanError, controlchan::middleware::ControlChanMiddleware, ftpserver::options::FtpsRequired, session::SharedSession,
-        Command, ControlChanErrorKind, Event, Reply, ReplyCode,
+        chancomms::ControlChanMsg, controlchan::commands::AuthParam, controlchan::error::ControlChanError, controlchan::middleware::ControlChanMiddleware,
+        ftpserver::options::FtpsRequired, ftpserver::options::TlsFirst, session::SharedSession, Command, ControlChanErrorKind, Event, Reply, ReplyCode,
     },
     storage::{Metadata, StorageBackend},
 };
@@ -110,10 +110,10 @@ where
         match (self.ftps_requirement, event) {
             (FtpsRequired::None, event) => self.next.handle(event).await,
             (FtpsRequired::All, event) => match event {
-                Event::Command(Command::Pasv) => {
+                Event::Command(ref cmd) if uses_data_channel(cmd) => {
                     let is_tls = async {
                         let session = self.session.lock().await;
-                        session.data_tls
+                        session.data_tls()
                     }
                     .await;
                     match is_tls {
@@ -124,10 +124,10 @@ where
                 _ => self.next.handle(event).await,
             },
             (FtpsRequired::Accounts, event) => match event {
-                Event::Command(Command::Pasv) => {
+                Event::Command(ref cmd) if uses_data_channel(cmd) => {
                     let (is_tls, username_opt) = async {
                         let session = self.session.lock().await;
-                        (session.cmd_tls, session.username.clone())
+                        (session.data_tls(), session.username.clone())
                     }
                     .await;
 
@@ -144,6 +144,46 @@ where
     }
 }
 
+// Commands that open a data connection or transfer data over it. A client that switched back to
+// PROT C after opening the data connection is still refused when it starts the transfer.
+fn uses_data_channel(cmd: &Command) -> bool {
+    matches!(
+        cmd,
+        Command::Pasv | Command::Port { .. } | Command::Retr { .. } | Command::Stor { .. } | Command::Stou | Command::List { .. } | Command::Nlst { .. }
+    )
+}
+
+// Middleware that requires AUTH TLS to be the very first command on the control channel.
+pub struct TlsFirstMiddleware<Next>
+where
+    Next: ControlChanMiddleware,
+{
+    pub tls_first: TlsFirst,
+    pub secured: bool,
+    pub next: Next,
+}
+
+#[async_trait]
+impl<Next> ControlChanMiddleware for TlsFirstMiddleware<Next>
+where
+    Next: ControlChanMiddleware,
+{
+    async fn handle(&mut self, event: Event) -> Result<Reply, ControlChanError> {
+        if !self.tls_first.is_required() || self.secured {
+            return self.next.handle(event).await;
+        }
+        match event {
+            Event::Command(Command::Auth { protocol: AuthParam::Tls }) => self.next.handle(event).await,
+            Event::Command(_) => Ok(Reply::new(ReplyCode::FileError, "AUTH TLS is required before any other command")),
+            Event::InternalMsg(ControlChanMsg::SecureControlChannel) => {
+                self.secured = true;
+                self.next.handle(event).await
+            }
+            Event::InternalMsg(_) => self.next.handle(event).await,
+        }
+    }
+}
+
 fn is_anonymous_user(username: impl AsRef<[u8]>) -> Result<bool, std::str::Utf8Error> {
     let username_str = std::str::from_utf8(username.as_ref())?;
     Ok(username_str == "anonymous")
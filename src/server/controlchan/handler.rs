@@ -3,7 +3,10 @@ use crate::{
     server::{
         chancomms::ProxyLoopSender,
         controlchan::{command::Command, error::ControlChanError, Reply},
-        ftpserver::options::{PassiveHost, SiteMd5},
+        ftpserver::{
+            options::{PassiveHost, SiteMd5},
+            site::SiteCommands,
+        },
         session::SharedSession,
         ControlChanMsg,
     },
@@ -44,4 +47,5 @@ where
     pub tx_proxyloop: Option<ProxyLoopSender<Storage, User>>,
     pub logger: slog::Logger,
     pub sitemd5: SiteMd5,
+    pub site_commands: SiteCommands<Storage, User>,
 }
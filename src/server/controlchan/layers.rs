@@ -0,0 +1,53 @@
+use crate::{
+    auth::UserDetail,
+    middleware::{Middleware, Next, Request},
+    server::{
+        controlchan::{error::ControlChanError, middleware::ControlChanMiddleware},
+        session::SharedSession,
+        Event, Reply,
+    },
+    storage::{Metadata, StorageBackend},
+};
+use async_trait::async_trait;
+use std::{net::SocketAddr, sync::Arc};
+
+// Control channel middleware that runs the commands through the middleware the libunftp user
+// registered with the ServerBuilder, in the order it was registered. Internal messages are passed
+// on as is.
+pub struct LayersMiddleware<Storage, User, Next>
+where
+    User: UserDetail + 'static,
+    Storage: StorageBackend<User> + 'static,
+    Storage::Metadata: Metadata,
+    Next: ControlChanMiddleware,
+{
+    pub session: SharedSession<Storage, User>,
+    pub source: SocketAddr,
+    pub layers: Arc<Vec<Arc<dyn Middleware>>>,
+    pub next: Next,
+}
+
+#[async_trait]
+impl<Storage, User, Inner> ControlChanMiddleware for LayersMiddleware<Storage, User, Inner>
+where
+    User: UserDetail + 'static,
+    Storage: StorageBackend<User> + 'static,
+    Storage::Metadata: Metadata,
+    Inner: ControlChanMiddleware,
+{
+    async fn handle(&mut self, event: Event) -> Result<Reply, ControlChanError> {
+        match event {
+            Event::Command(command) if !self.layers.is_empty() => {
+                let username = self.session.lock().await.username.clone();
+                let request = Request {
+                    command,
+                    username,
+                    source: self.source,
+                };
+                let response = Next::new(&self.layers, &mut self.next).run(request).await?;
+                Ok(response.0)
+            }
+            event => self.next.handle(event).await,
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use super::{error::ControlChanError, ControlChanErrorKind};
+use crate::options::CommandLimits;
+use std::time::{Duration, Instant};
+
+// Enforces the command rate limits of the [`CommandLimits`](crate::options::CommandLimits) for a
+// single session. The rate is counted in windows of a second, which is precise enough to tell a
+// flooding client from a busy one.
+#[derive(Debug)]
+pub struct CommandLimiter {
+    limits: CommandLimits,
+    window_start: Instant,
+    in_window: u32,
+    before_login: u32,
+}
+
+impl CommandLimiter {
+    pub fn new(limits: CommandLimits) -> Self {
+        CommandLimiter {
+            limits,
+            window_start: Instant::now(),
+            in_window: 0,
+            before_login: 0,
+        }
+    }
+
+    // Tells if the login state of the session is needed by `check`, so that the session only needs
+    // to be locked when it is.
+    pub fn counts_logins(&self) -> bool {
+        self.limits.commands_before_login.is_some()
+    }
+
+    // Counts a command that was received and returns an error when it exceeds one of the limits.
+    pub fn check(&mut self, logged_in: bool) -> Result<(), ControlChanError> {
+        self.check_at(Instant::now(), logged_in)
+    }
+
+    fn check_at(&mut self, now: Instant, logged_in: bool) -> Result<(), ControlChanError> {
+        if let Some(max) = self.limits.commands_per_second {
+            if now.duration_since(self.window_start) >= Duration::from_secs(1) {
+                self.window_start = now;
+                self.in_window = 0;
+            }
+            self.in_window += 1;
+            if self.in_window > max {
+                return Err(ControlChanErrorKind::TooManyCommands.into());
+            }
+        }
+        if let Some(max) = self.limits.commands_before_login.filter(|_| !logged_in) {
+            self.before_login += 1;
+            if self.before_login > max {
+                return Err(ControlChanErrorKind::TooManyCommandsBeforeLogin.into());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CommandLimiter;
+    use crate::options::CommandLimits;
+    use crate::server::controlchan::ControlChanErrorKind;
+    use pretty_assertions::assert_eq;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn limits_commands_per_second_and_before_login() {
+        let mut limiter = CommandLimiter::new(CommandLimits::new().commands_per_second(2));
+        let now = Instant::now();
+        assert!(limiter.check_at(now, false).is_ok());
+        assert!(limiter.check_at(now, false).is_ok());
+        assert_eq!(limiter.check_at(now, false).unwrap_err().kind(), &ControlChanErrorKind::TooManyCommands);
+        assert!(limiter.check_at(now + Duration::from_secs(1), false).is_ok());
+
+        let mut limiter = CommandLimiter::new(CommandLimits::new().commands_before_login(2));
+        assert!(limiter.counts_logins());
+        assert!(limiter.check(false).is_ok());
+        assert!(limiter.check(false).is_ok());
+        assert!(limiter.check(true).is_ok());
+        assert_eq!(limiter.check(false).unwrap_err().kind(), &ControlChanErrorKind::TooManyCommandsBeforeLogin);
+    }
+}
@@ -2,13 +2,16 @@ use super::error::{ParseErrorKind, Result};
 use crate::server::{
     controlchan::{
         command::Command,
-        commands::{AuthParam, ModeParam, Opt, ProtParam, StruParam},
+        commands::{AuthParam, ModeParam, Opt, ProtParam, StruParam, TypeParam},
     },
+    encoding,
     password::Password,
 };
 
 use bytes::Bytes;
-use std::str;
+use chrono::NaiveDateTime;
+use std::{borrow::Cow, str, time::SystemTime};
+use zeroize::Zeroize;
 
 /// Parse the given bytes into a [`Command`].
 ///
@@ -17,35 +20,51 @@ pub fn parse<T>(line: T) -> Result<Command>
 where
     T: AsRef<[u8]> + Into<Bytes>,
 {
-    let vec = line.into().to_vec();
-    let (cmd_token, cmd_params) = split_token_params(&vec);
-    let cmd_token = normalize(cmd_token)?;
+    // The line is only borrowed from here on. Paths and other arguments are copied once, into the
+    // command, and the verb is upper cased on the stack.
+    let line: Bytes = line.into();
+    let (cmd_token, cmd_params) = split_token_params(&line);
+    let mut verb_buf = [0u8; VERB_BUF_LEN];
+    let cmd_token = normalize(cmd_token, &mut verb_buf)?;
 
     let cmd = match &*cmd_token {
         "USER" => {
-            let username = parse_to_eol(cmd_params)?;
+            let username = to_bytes(&line, parse_to_eol(cmd_params)?);
             Command::User { username }
         }
         "PASS" => {
-            let password = parse_to_eol(cmd_params)?;
+            let password = parse_to_eol(cmd_params)?.into_owned();
             Command::Pass {
                 password: Password::new(password),
             }
         }
         "ACCT" => {
-            let account = parse_to_eol(cmd_params)?;
+            let account = to_bytes(&line, parse_to_eol(cmd_params)?);
             Command::Acct { account }
         }
         "SYST" => Command::Syst,
         "STAT" => {
             let params = parse_to_eol(cmd_params)?;
-            let path = if !params.is_empty() { Some(params) } else { None };
+            let path = if !params.is_empty() { Some(to_string(&params)) } else { None };
             Command::Stat { path }
         }
         "TYPE" => {
-            // We don't care about text format conversion, so we'll ignore the params and we're
-            // just always in binary mode.
-            Command::Type
+            let params = parse_to_eol(cmd_params)?.to_ascii_uppercase();
+            let params: Vec<&[u8]> = params.split(|&b| b == b' ').filter(|param| !param.is_empty()).collect();
+            let type_ = match params.as_slice() {
+                // Only the Non-print format is supported for the text types.
+                [b"A"] | [b"A", b"N"] => TypeParam::Ascii,
+                [b"E"] | [b"E", b"N"] => TypeParam::Ebcdic,
+                [b"I"] => TypeParam::Image,
+                [b"L", byte_size] => TypeParam::Local {
+                    byte_size: str::from_utf8(byte_size)
+                        .ok()
+                        .and_then(|byte_size| byte_size.parse().ok())
+                        .ok_or(ParseErrorKind::InvalidCommand)?,
+                },
+                _ => return Err(ParseErrorKind::InvalidCommand.into()),
+            };
+            Command::Type { type_ }
         }
         "STRU" => {
             let params = parse_to_eol(cmd_params)?;
@@ -68,6 +87,7 @@ where
                 Some(b'S') => Command::Mode { mode: ModeParam::Stream },
                 Some(b'B') => Command::Mode { mode: ModeParam::Block },
                 Some(b'C') => Command::Mode { mode: ModeParam::Compressed },
+                Some(b'Z') => Command::Mode { mode: ModeParam::Deflate },
                 _ => return Err(ParseErrorKind::InvalidCommand.into()),
             }
         }
@@ -92,45 +112,40 @@ where
             if params.is_empty() {
                 return Err(ParseErrorKind::InvalidCommand.into());
             }
-            let addr = String::from_utf8_lossy(&params);
-            Command::Port { addr: addr.to_string() }
+            Command::Port { addr: to_string(&params) }
         }
         "RETR" => {
             let path = parse_to_eol(cmd_params)?;
             if path.is_empty() {
                 return Err(ParseErrorKind::InvalidCommand.into());
             }
-            let path = String::from_utf8_lossy(&path);
-            // TODO: Can we do this without allocation?
-            Command::Retr { path: path.to_string() }
+            Command::Retr { path: to_string(&path) }
         }
         "STOR" => {
             let path = parse_to_eol(cmd_params)?;
             if path.is_empty() {
                 return Err(ParseErrorKind::InvalidCommand.into());
             }
-            // TODO:: Can we do this without allocation?
-            let path = String::from_utf8_lossy(&path);
-            Command::Stor { path: path.to_string() }
+            Command::Stor { path: to_string(&path) }
         }
         "LIST" => {
             let line = parse_to_eol(cmd_params)?;
             let path = line
                 .split(|&b| b == b' ')
                 .filter(|s| !line.is_empty() && !s.starts_with(b"-"))
-                .map(|s| String::from_utf8_lossy(s).to_string())
+                .map(to_string)
                 .next();
-            // Note that currently we just throw arguments away.
-            Command::List { options: None, path }
+            let options = list_options(line.split(|&b| b == b' '));
+            Command::List { options, path }
         }
         "NLST" => {
-            let path = parse_to_eol(cmd_params)?;
-            let path = if path.is_empty() {
-                None
-            } else {
-                Some(String::from_utf8_lossy(&path).to_string())
-            };
-            Command::Nlst { path }
+            let line = parse_to_eol(cmd_params)?;
+            // Only leading arguments are options, so that a path can still start with a dash.
+            let flags = line.split(|&b| b == b' ').take_while(|s| s.len() > 1 && s.starts_with(b"-")).count();
+            let options = list_options(line.split(|&b| b == b' ').take(flags));
+            let path = line.splitn(flags + 1, |&b| b == b' ').nth(flags).unwrap_or_default();
+            let path = if path.is_empty() { None } else { Some(to_string(path)) };
+            Command::Nlst { options, path }
         }
         "FEAT" => {
             let params = parse_to_eol(cmd_params)?;
@@ -139,6 +154,16 @@ where
             }
             Command::Feat
         }
+        "LANG" => {
+            let params = parse_to_eol(cmd_params)?;
+            let language = String::from_utf8_lossy(&params).trim().to_string();
+            if language.contains(' ') {
+                return Err(ParseErrorKind::InvalidCommand.into());
+            }
+            Command::Lang {
+                language: if language.is_empty() { None } else { Some(language) },
+            }
+        }
         "PWD" | "XPWD" => {
             let params = parse_to_eol(cmd_params)?;
             if !params.is_empty() {
@@ -151,7 +176,7 @@ where
             if path.is_empty() {
                 return Err(ParseErrorKind::InvalidCommand.into());
             }
-            let path = String::from_utf8_lossy(&path).to_string();
+            let path = to_string(&path);
             let path = path.into();
             Command::Cwd { path }
         }
@@ -184,7 +209,7 @@ where
                 return Err(ParseErrorKind::InvalidCommand.into());
             }
 
-            let path = String::from_utf8_lossy(&path).to_string();
+            let path = to_string(&path);
             Command::Dele { path }
         }
         "RMD" => {
@@ -193,7 +218,7 @@ where
                 return Err(ParseErrorKind::InvalidCommand.into());
             }
 
-            let path = String::from_utf8_lossy(&path).to_string();
+            let path = to_string(&path);
             Command::Rmd { path }
         }
         "QUIT" | "BYE" => {
@@ -210,7 +235,7 @@ where
                 return Err(ParseErrorKind::InvalidCommand.into());
             }
 
-            let path = String::from_utf8_lossy(&params).to_string();
+            let path = to_string(&params);
             let path = path.into();
             Command::Mkd { path }
         }
@@ -235,7 +260,7 @@ where
                 return Err(ParseErrorKind::InvalidCommand.into());
             }
 
-            let file = String::from_utf8_lossy(&params).to_string();
+            let file = to_string(&params);
             let file = file.into();
             Command::Rnfr { file }
         }
@@ -245,7 +270,7 @@ where
                 return Err(ParseErrorKind::InvalidCommand.into());
             }
 
-            let file = String::from_utf8_lossy(&params).to_string();
+            let file = to_string(&params);
             let file = file.into();
             Command::Rnto { file }
         }
@@ -254,9 +279,9 @@ where
             if params.len() > 3 {
                 return Err(ParseErrorKind::InvalidCommand.into());
             }
-            match str::from_utf8(&params)?.to_string().to_uppercase().as_str() {
-                "TLS" => Command::Auth { protocol: AuthParam::Tls },
-                "SSL" => Command::Auth { protocol: AuthParam::Ssl },
+            match str::from_utf8(&params)? {
+                protocol if protocol.eq_ignore_ascii_case("TLS") => Command::Auth { protocol: AuthParam::Tls },
+                protocol if protocol.eq_ignore_ascii_case("SSL") => Command::Auth { protocol: AuthParam::Ssl },
                 _ => return Err(ParseErrorKind::InvalidCommand.into()),
             }
         }
@@ -266,8 +291,7 @@ where
                 return Err(ParseErrorKind::InvalidCommand.into());
             }
 
-            let size = String::from_utf8_lossy(&params).to_string();
-            if size != "0" {
+            if &params[..] != b"0" {
                 return Err(ParseErrorKind::InvalidCommand.into());
             }
 
@@ -303,7 +327,7 @@ where
             if params.is_empty() {
                 return Err(ParseErrorKind::InvalidCommand.into());
             }
-            let file = String::from_utf8_lossy(&params).to_string().into();
+            let file = to_string(&params).into();
             Command::Size { file }
         }
         "REST" => {
@@ -312,8 +336,7 @@ where
                 return Err(ParseErrorKind::InvalidCommand.into());
             }
 
-            let offset = String::from_utf8_lossy(&params).to_string();
-            if let Ok(val) = offset.parse::<u64>() {
+            if let Some(val) = str::from_utf8(&params).ok().and_then(|offset| offset.parse::<u64>().ok()) {
                 Command::Rest { offset: val }
             } else {
                 return Err(ParseErrorKind::InvalidCommand.into());
@@ -325,12 +348,23 @@ where
                 return Err(ParseErrorKind::InvalidCommand.into());
             }
 
-            let file = String::from_utf8_lossy(&params).to_string().into();
-            Command::Mdtm { file }
+            // The setter form starts with the time, which can't be mistaken for a path of its own
+            // since there is a space after it.
+            match split_mdtm_time(&params) {
+                Some((modified, path)) => Command::Mdtm {
+                    file: to_string(path).into(),
+                    modified: Some(modified),
+                },
+                None => Command::Mdtm {
+                    file: to_string(&params).into(),
+                    modified: None,
+                },
+            }
         }
         "SITE" => {
             let (cmd_token, cmd_params) = split_token_params(cmd_params);
-            let cmd_token = normalize(cmd_token)?;
+            let mut verb_buf = [0u8; VERB_BUF_LEN];
+            let cmd_token = normalize(cmd_token, &mut verb_buf)?;
 
             match &*cmd_token {
                 "MD5" => {
@@ -339,14 +373,54 @@ where
                         return Err(ParseErrorKind::InvalidCommand.into());
                     }
 
-                    let file = String::from_utf8_lossy(&params).to_string().into();
+                    let file = to_string(&params).into();
                     Command::Md5 { file }
                 }
+                "CHOWN" => {
+                    let params = parse_to_eol(cmd_params)?;
+                    let (owner, file) = split_token_params(&params);
+                    if owner.is_empty() || file.is_empty() {
+                        return Err(ParseErrorKind::InvalidCommand.into());
+                    }
+
+                    Command::Chown {
+                        owner: to_string(owner),
+                        file: to_string(file).into(),
+                    }
+                }
+                "SYMLINK" => {
+                    let params = parse_to_eol(cmd_params)?;
+                    let (target, link) = split_token_params(&params);
+                    if target.is_empty() || link.is_empty() {
+                        return Err(ParseErrorKind::InvalidCommand.into());
+                    }
+
+                    Command::Symlink {
+                        target: to_string(target).into(),
+                        link: to_string(link).into(),
+                    }
+                }
+                "CPFR" | "CPTO" => {
+                    let params = parse_to_eol(cmd_params)?;
+                    if params.is_empty() {
+                        return Err(ParseErrorKind::InvalidCommand.into());
+                    }
+
+                    let file = to_string(&params).into();
+                    match &*cmd_token {
+                        "CPFR" => Command::Cpfr { file },
+                        _ => Command::Cpto { file },
+                    }
+                }
+                "" => Command::Other {
+                    command_name: cmd_token.into_owned(),
+                    arguments: to_string(&parse_to_eol(cmd_params)?),
+                },
                 _ => {
                     let params = parse_to_eol(cmd_params)?;
-                    Command::Other {
-                        command_name: cmd_token,
-                        arguments: String::from_utf8_lossy(&params).to_string(),
+                    Command::Site {
+                        name: cmd_token.into_owned(),
+                        arguments: to_string(&params),
                     }
                 }
             }
@@ -354,12 +428,19 @@ where
         _ => {
             let params = parse_to_eol(cmd_params)?;
             Command::Other {
-                command_name: cmd_token,
-                arguments: String::from_utf8_lossy(&params).to_string(),
+                command_name: cmd_token.into_owned(),
+                arguments: to_string(&params),
             }
         }
     };
 
+    // The password was copied out of the line, wipe it if nothing else refers to it.
+    if let Command::Pass { .. } = cmd {
+        if let Ok(mut line) = line.try_into_mut() {
+            line[..].zeroize();
+        }
+    }
+
     Ok(cmd)
 }
 
@@ -370,43 +451,42 @@ fn split_token_params(vec: &[u8]) -> (&[u8], &[u8]) {
     (token, params)
 }
 
-fn parse_to_eol(line: &[u8]) -> Result<Bytes> {
-    let mut dest: Vec<u8> = Vec::new();
+// Returns the line up to the end of line, leaving out the NUL that follows a bare CR. The result
+// borrows from the line unless there is such a NUL to leave out.
+fn parse_to_eol(line: &[u8]) -> Result<Cow<'_, [u8]>> {
+    let mut dest = Cow::Borrowed(&line[..0]);
 
     if line.is_empty() || line[0] == b'\n' {
-        return Ok(Bytes::from(dest));
+        return Ok(dest);
     } else if line.len() == 1 {
         return Err(ParseErrorKind::InvalidEol.into());
     } else if line.len() == 2 {
         return match (line[0], line[1]) {
-            (b'\r', b'\n') => Ok(dest.into()),
-            (b'\n', _) => Ok(dest.into()),
-            (_, b'\n') => {
-                dest.push(line[0]);
-                Ok(dest.into())
-            }
+            (b'\r', b'\n') => Ok(dest),
+            (b'\n', _) => Ok(dest),
+            (_, b'\n') => Ok(Cow::Borrowed(&line[..1])),
             (_, _) => Err(ParseErrorKind::InvalidEol.into()),
         };
     }
-    dest.push(line[0]);
+    push(&mut dest, line, 0);
 
     let mut i = 1;
     while i < line.len() - 1 {
         match (line[i - 1], line[i], line[i + 1]) {
-            (_, b'\r', b'\0') => dest.push(line[i]),
+            (_, b'\r', b'\0') => push(&mut dest, line, i),
             (b'\r', b'\0', _) => {} // skip the NUL byte
             (_, b'\r', b'\n') => {
-                return Ok(dest.into());
+                return Ok(dest);
             }
             (_, _, b'\n') => {
-                dest.push(line[i]);
-                return Ok(dest.into());
+                push(&mut dest, line, i);
+                return Ok(dest);
             }
             (_, b'\r', _) => {
                 return Err(ParseErrorKind::InvalidEol.into());
             }
             (_, _, _) => {
-                dest.push(line[i]);
+                push(&mut dest, line, i);
             }
         }
         i += 1;
@@ -414,6 +494,99 @@ fn parse_to_eol(line: &[u8]) -> Result<Bytes> {
     Err(ParseErrorKind::InvalidEol.into())
 }
 
-fn normalize(token: &[u8]) -> Result<String> {
-    Ok(str::from_utf8(token).map(|t| t.to_uppercase())?)
+// Appends the byte at the given index of the line. As long as no byte was left out the result
+// stays a slice of the line.
+fn push<'a>(dest: &mut Cow<'a, [u8]>, line: &'a [u8], index: usize) {
+    match dest {
+        Cow::Borrowed(slice) if slice.len() == index => *dest = Cow::Borrowed(&line[..=index]),
+        _ => dest.to_mut().push(line[index]),
+    }
+}
+
+// Turns the result of parse_to_eol into Bytes that share the memory of the line if it was borrowed.
+fn to_bytes(line: &Bytes, params: Cow<'_, [u8]>) -> Bytes {
+    match params {
+        Cow::Borrowed(params) if !params.is_empty() => line.slice_ref(params),
+        params => Bytes::from(params.into_owned()),
+    }
+}
+
+// Splits `YYYYMMDDHHMMSS[.sss] path` into the time, which is in UTC, and the path.
+fn split_mdtm_time(params: &[u8]) -> Option<(SystemTime, &[u8])> {
+    let space = params.iter().position(|b| *b == b' ')?;
+    let (time, path) = (&params[..space], &params[space + 1..]);
+    let (time, fraction) = match time.iter().position(|b| *b == b'.') {
+        Some(dot) => (&time[..dot], &time[dot + 1..]),
+        None => (time, &b""[..]),
+    };
+    if time.len() != 14 || path.is_empty() || !time.iter().chain(fraction).all(u8::is_ascii_digit) {
+        return None;
+    }
+    let time = NaiveDateTime::parse_from_str(str::from_utf8(time).ok()?, "%Y%m%d%H%M%S").ok()?;
+    let millis: u32 = str::from_utf8(fraction)
+        .ok()?
+        .chars()
+        .chain("000".chars())
+        .take(3)
+        .collect::<String>()
+        .parse()
+        .ok()?;
+    let time = time.and_utc() + chrono::Duration::milliseconds(millis.into());
+    Some((time.into(), path))
+}
+
+// Paths that aren't valid UTF-8 keep their bytes as raw byte characters.
+fn to_string(bytes: &[u8]) -> String {
+    encoding::decode_path(bytes)
+}
+
+// Joins the arguments of LIST or NLST that are options, like -la, or returns None if there are none.
+fn list_options<'a, I: Iterator<Item = &'a [u8]>>(arguments: I) -> Option<String> {
+    let options: Vec<String> = arguments.filter(|s| s.len() > 1 && s.starts_with(b"-")).map(to_string).collect();
+    match options.is_empty() {
+        true => None,
+        false => Some(options.join(" ")),
+    }
+}
+
+// The verbs and SITE subcommands libunftp knows fit in this many bytes.
+const VERB_BUF_LEN: usize = 8;
+
+// Upper cases the command name. Short ASCII names, which includes every known one, are upper cased
+// into the given buffer so that only unknown commands cost an allocation.
+fn normalize<'a>(token: &[u8], buf: &'a mut [u8; VERB_BUF_LEN]) -> Result<Cow<'a, str>> {
+    let token = str::from_utf8(token)?;
+    if token.len() > buf.len() || !token.is_ascii() {
+        return Ok(Cow::Owned(token.to_uppercase()));
+    }
+    let buf = &mut buf[..token.len()];
+    buf.copy_from_slice(token.as_bytes());
+    buf.make_ascii_uppercase();
+    let buf: &'a [u8] = buf;
+    Ok(Cow::Borrowed(str::from_utf8(buf)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn borrows_arguments_from_the_line() {
+        assert!(matches!(parse_to_eol(b"some file.txt\r\n").unwrap(), Cow::Borrowed(b"some file.txt")));
+        assert!(matches!(parse_to_eol(b"a\n").unwrap(), Cow::Borrowed(b"a")));
+        let with_nul = parse_to_eol(b"a\r\0b\r\n").unwrap();
+        assert!(matches!(with_nul, Cow::Owned(_)));
+        assert_eq!(&*with_nul, b"a\rb");
+    }
+
+    #[test]
+    fn upper_cases_verbs_on_the_stack() {
+        let mut buf = [0u8; VERB_BUF_LEN];
+        assert!(matches!(normalize(b"symlink", &mut buf).unwrap(), Cow::Borrowed("SYMLINK")));
+        let mut buf = [0u8; VERB_BUF_LEN];
+        assert_eq!(normalize(b"averylongverb", &mut buf).unwrap(), "AVERYLONGVERB");
+        let mut buf = [0u8; VERB_BUF_LEN];
+        assert_eq!(normalize("ünknown".as_bytes(), &mut buf).unwrap(), "ÜNKNOWN");
+    }
 }
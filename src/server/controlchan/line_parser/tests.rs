@@ -1,11 +1,12 @@
 use super::error::{ParseError, ParseErrorKind, Result};
 use crate::server::controlchan::{
     command::Command,
-    commands::{AuthParam, ModeParam, Opt, StruParam},
+    commands::{AuthParam, ModeParam, Opt, StruParam, TypeParam},
     line_parser::parser::parse,
 };
 
 use pretty_assertions::assert_eq;
+use std::time::{Duration, SystemTime};
 
 #[test]
 fn parse_user_cmd_crnl() {
@@ -88,6 +89,34 @@ fn parse_acct() {
     assert_eq!(parse(input).unwrap(), Command::Acct { account: "Teddy".into() });
 }
 
+#[test]
+fn parse_type() {
+    assert_eq!(parse("TYPE A\r\n").unwrap(), Command::Type { type_: TypeParam::Ascii });
+    assert_eq!(parse("TYPE a n\r\n").unwrap(), Command::Type { type_: TypeParam::Ascii });
+    assert_eq!(parse("TYPE I\r\n").unwrap(), Command::Type { type_: TypeParam::Image });
+    assert_eq!(
+        parse("TYPE L 8\r\n").unwrap(),
+        Command::Type {
+            type_: TypeParam::Local { byte_size: 8 }
+        }
+    );
+    assert_eq!(parse("TYPE A T\r\n"), Err(ParseError::from(ParseErrorKind::InvalidCommand)));
+    assert_eq!(parse("TYPE L\r\n"), Err(ParseError::from(ParseErrorKind::InvalidCommand)));
+    assert_eq!(parse("TYPE\r\n"), Err(ParseError::from(ParseErrorKind::InvalidCommand)));
+}
+
+#[test]
+fn parse_lang() {
+    assert_eq!(parse("LANG\r\n").unwrap(), Command::Lang { language: None });
+    assert_eq!(
+        parse("LANG fr-CA\r\n").unwrap(),
+        Command::Lang {
+            language: Some("fr-CA".to_string())
+        }
+    );
+    assert_eq!(parse("LANG fr en\r\n"), Err(ParseError::from(ParseErrorKind::InvalidCommand)));
+}
+
 #[test]
 fn parse_stru_no_params() {
     let input = "STRU\r\n";
@@ -142,6 +171,12 @@ fn parse_mode_c() {
     assert_eq!(parse(input).unwrap(), Command::Mode { mode: ModeParam::Compressed });
 }
 
+#[test]
+fn parse_mode_z() {
+    let input = "MODE Z\r\n";
+    assert_eq!(parse(input).unwrap(), Command::Mode { mode: ModeParam::Deflate });
+}
+
 #[test]
 fn parse_mode_garbage() {
     let input = "MODE SKDJF\r\n";
@@ -199,32 +234,39 @@ fn parse_port() {
 fn parse_list() {
     struct Test {
         input: &'static str,
+        expected_options: Option<&'static str>,
         expected_path: Option<&'static str>,
     }
 
     let tests = [
         Test {
             input: "LIST\r\n",
+            expected_options: None,
             expected_path: None,
         },
         Test {
             input: "LIST tmp\r\n",
+            expected_options: None,
             expected_path: Some("tmp"),
         },
         Test {
             input: "LIST -la\r\n",
+            expected_options: Some("-la"),
             expected_path: None,
         },
         Test {
             input: "LIST -la tmp\r\n",
+            expected_options: Some("-la"),
             expected_path: Some("tmp"),
         },
         Test {
             input: "LIST -la -x tmp\r\n",
+            expected_options: Some("-la -x"),
             expected_path: Some("tmp"),
         },
         Test {
             input: "LIST -la -x tmp*\r\n",
+            expected_options: Some("-la -x"),
             expected_path: Some("tmp*"),
         },
     ];
@@ -233,13 +275,26 @@ fn parse_list() {
         assert_eq!(
             parse(test.input),
             Ok(Command::List {
-                options: None,
+                options: test.expected_options.map(|s| s.to_string()),
                 path: test.expected_path.map(|s| s.to_string()),
             })
         );
     }
 }
 
+#[test]
+fn parse_nlst() {
+    let nlst = |options: Option<&str>, path: Option<&str>| Command::Nlst {
+        options: options.map(String::from),
+        path: path.map(String::from),
+    };
+    assert_eq!(parse("NLST\r\n"), Ok(nlst(None, None)));
+    assert_eq!(parse("NLST my dir\r\n"), Ok(nlst(None, Some("my dir"))));
+    assert_eq!(parse("NLST -R\r\n"), Ok(nlst(Some("-R"), None)));
+    assert_eq!(parse("NLST -R -a my dir\r\n"), Ok(nlst(Some("-R -a"), Some("my dir"))));
+    assert_eq!(parse("NLST tmp -R\r\n"), Ok(nlst(None, Some("tmp -R"))));
+}
+
 #[test]
 fn parse_feat() {
     let input = "FEAT\r\n";
@@ -513,7 +568,32 @@ fn parse_mdtm() {
         },
         Test {
             input: "MDTM file.txt\r\n",
-            expected: Ok(Command::Mdtm { file: "file.txt".into() }),
+            expected: Ok(Command::Mdtm {
+                file: "file.txt".into(),
+                modified: None,
+            }),
+        },
+        Test {
+            input: "MDTM 20240229123456 my file.txt\r\n",
+            expected: Ok(Command::Mdtm {
+                file: "my file.txt".into(),
+                modified: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_709_210_096)),
+            }),
+        },
+        Test {
+            input: "MDTM 20240229123456.5 file.txt\r\n",
+            expected: Ok(Command::Mdtm {
+                file: "file.txt".into(),
+                modified: Some(SystemTime::UNIX_EPOCH + Duration::from_millis(1_709_210_096_500)),
+            }),
+        },
+        // Not a valid time, so it is part of the path.
+        Test {
+            input: "MDTM 20241399123456 file.txt\r\n",
+            expected: Ok(Command::Mdtm {
+                file: "20241399123456 file.txt".into(),
+                modified: None,
+            }),
         },
     ];
     for test in tests.iter() {
@@ -546,6 +626,81 @@ fn parse_md5() {
     }
 }
 
+#[test]
+fn parse_site_chown() {
+    struct Test {
+        input: &'static str,
+        expected: Result<Command>,
+    }
+    let tests = [
+        Test {
+            input: "SITE CHOWN ftp\r\n",
+            expected: Err(ParseErrorKind::InvalidCommand.into()),
+        },
+        Test {
+            input: "SITE CHOWN ftp:users my file.txt\r\n",
+            expected: Ok(Command::Chown {
+                owner: "ftp:users".into(),
+                file: "my file.txt".into(),
+            }),
+        },
+    ];
+    for test in tests.iter() {
+        assert_eq!(parse(test.input), test.expected);
+    }
+}
+
+#[test]
+fn parse_site_symlink() {
+    struct Test {
+        input: &'static str,
+        expected: Result<Command>,
+    }
+    let tests = [
+        Test {
+            input: "SITE SYMLINK target.txt\r\n",
+            expected: Err(ParseErrorKind::InvalidCommand.into()),
+        },
+        Test {
+            input: "SITE SYMLINK ../data/target.txt my link\r\n",
+            expected: Ok(Command::Symlink {
+                target: "../data/target.txt".into(),
+                link: "my link".into(),
+            }),
+        },
+    ];
+    for test in tests.iter() {
+        assert_eq!(parse(test.input), test.expected);
+    }
+}
+
+#[test]
+fn parse_site_copy() {
+    struct Test {
+        input: &'static str,
+        expected: Result<Command>,
+    }
+    let tests = [
+        Test {
+            input: "SITE CPFR\r\n",
+            expected: Err(ParseErrorKind::InvalidCommand.into()),
+        },
+        Test {
+            input: "SITE CPFR file.txt\r\n",
+            expected: Ok(Command::Cpfr { file: "file.txt".into() }),
+        },
+        Test {
+            input: "site cpto copy of file.txt\r\n",
+            expected: Ok(Command::Cpto {
+                file: "copy of file.txt".into(),
+            }),
+        },
+    ];
+    for test in tests.iter() {
+        assert_eq!(parse(test.input), test.expected);
+    }
+}
+
 #[test]
 fn parse_site() {
     struct Test {
@@ -567,6 +722,20 @@ fn parse_site() {
                 arguments: "".to_string(),
             }),
         },
+        Test {
+            input: "site quota /home/alice\r\n",
+            expected: Ok(Command::Site {
+                name: "QUOTA".to_string(),
+                arguments: "/home/alice".to_string(),
+            }),
+        },
+        Test {
+            input: "SITE RMDIR -R old backups\r\n",
+            expected: Ok(Command::Site {
+                name: "RMDIR".to_string(),
+                arguments: "-R old backups".to_string(),
+            }),
+        },
     ];
     for test in tests.iter() {
         assert_eq!(parse(test.input), test.expected);
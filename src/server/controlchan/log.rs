@@ -1,3 +1,4 @@
+use crate::options::Redaction;
 use crate::server::{
     controlchan::{error::ControlChanError, middleware::ControlChanMiddleware},
     Command, Event, Reply,
@@ -12,6 +13,7 @@ where
 {
     pub logger: slog::Logger,
     pub sequence_nr: u64,
+    pub redaction: Redaction,
     pub next: Next,
 }
 
@@ -22,16 +24,34 @@ where
 {
     async fn handle(&mut self, event: Event) -> Result<Reply, ControlChanError> {
         self.sequence_nr += 1;
-        if let Event::Command(Command::User { username }) = &event {
-            let s: String = String::from_utf8_lossy(username).into();
-            self.logger = self.logger.new(slog::o!("username" => s));
-        }
-        slog::debug!(self.logger, "Control channel event {:?}", event; "seq" => self.sequence_nr);
+        let user_event;
+        let shown_event = match &event {
+            Event::Command(Command::User { username }) => {
+                let s: String = self.redaction.user(&String::from_utf8_lossy(username));
+                self.logger = self.logger.new(slog::o!("username" => s.clone()));
+                user_event = Event::Command(Command::User { username: s.into() });
+                &user_event
+            }
+            _ => &event,
+        };
+        let cmd = match &event {
+            Event::Command(command) => command.name().to_string(),
+            Event::InternalMsg(_) => String::from("internal"),
+        };
+        slog::debug!(self.logger, "Control channel event {:?}", shown_event; "seq" => self.sequence_nr, "cmd" => &cmd);
         let result = self.next.handle(event).await;
         match &result {
-            Ok(reply) => slog::debug!(self.logger, "Control channel reply {:?}", reply; "seq" => self.sequence_nr),
-            Err(error) => slog::warn!(self.logger, "Control channel error {:?}", error; "seq" => self.sequence_nr),
+            Ok(reply) => slog::debug!(self.logger, "Control channel reply {:?}", reply; "seq" => self.sequence_nr, "cmd" => &cmd, "reply" => reply_code(reply)),
+            Err(error) => slog::warn!(self.logger, "Control channel error {:?}", error; "seq" => self.sequence_nr, "cmd" => &cmd),
         };
         result
     }
 }
+
+// The reply code as logged, 0 when no reply is sent yet.
+fn reply_code(reply: &Reply) -> u32 {
+    match reply {
+        Reply::None => 0,
+        Reply::CodeAndMsg { code, .. } | Reply::MultiLine { code, .. } => *code as u32,
+    }
+}
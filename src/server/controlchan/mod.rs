@@ -11,17 +11,25 @@ pub(super) mod commands;
 mod active_passive;
 mod auth;
 mod codecs;
+mod command_policy;
 mod control_loop;
 mod error;
 mod ftps;
+mod layers;
+mod limits;
 mod line_parser;
 mod log;
 mod middleware;
 mod notify;
+mod path_filter;
+pub(crate) mod quirks;
+mod transfer_queue;
 
 use command::Command;
 pub(crate) use control_loop::{spawn as spawn_loop, Config as LoopConfig};
 pub(crate) use error::{ControlChanError, ControlChanErrorKind};
 pub(crate) use event::Event;
+#[cfg(feature = "bench")]
+pub(crate) use line_parser::parse as parse_command;
 pub(crate) use middleware::ControlChanMiddleware;
 pub(crate) use reply::{Reply, ReplyCode};
@@ -1,14 +1,16 @@
-use std::sync::Arc;
+use std::{future::Future, sync::Arc};
 
 use crate::{
     notification,
     notification::event::PresenceListener,
-    notification::DataListener,
+    notification::{DataListener, EventError},
+    options::{EventDelivery, Redaction},
     server::session::TraceId,
+    server::sessions::SessionActivity,
     server::ControlChanMsg,
     server::{
         controlchan::{error::ControlChanError, middleware::ControlChanMiddleware},
-        Event, Reply,
+        Event, Reply, ReplyCode,
     },
 };
 
@@ -23,7 +25,12 @@ where
     data_listener: Arc<dyn DataListener>,
     presence_listener: Arc<dyn PresenceListener>,
     next: Next,
-    sequence_nr: u64,
+    // Hands out the sequence numbers, which are shared with the progress events of the data channel.
+    activity: Arc<SessionActivity>,
+    redaction: Redaction,
+    // Set when the session waits for the listeners.
+    delivery: Option<EventDelivery>,
+    logger: slog::Logger,
     username: String,
     trace_id: TraceId,
 }
@@ -32,12 +39,23 @@ impl<Next> EventDispatcherMiddleware<Next>
 where
     Next: ControlChanMiddleware,
 {
-    pub fn new(data_listener: Arc<dyn DataListener>, presence_listener: Arc<dyn PresenceListener>, next: Next) -> Self {
+    pub fn new(
+        data_listener: Arc<dyn DataListener>,
+        presence_listener: Arc<dyn PresenceListener>,
+        activity: Arc<SessionActivity>,
+        redaction: Redaction,
+        delivery: Option<EventDelivery>,
+        logger: slog::Logger,
+        next: Next,
+    ) -> Self {
         EventDispatcherMiddleware {
             data_listener,
             presence_listener,
             next,
-            sequence_nr: 0,
+            activity,
+            redaction,
+            delivery,
+            logger,
             username: "unknown".to_string(),
             trace_id: TraceId::new(),
         }
@@ -50,10 +68,11 @@ where
     Next: ControlChanMiddleware,
 {
     async fn handle(&mut self, event: Event) -> Result<Reply, ControlChanError> {
+        let mut failed_transfer = false;
         let events = if let Event::InternalMsg(msg) = &event {
             let presence_event = match msg {
                 ControlChanMsg::AuthSuccess { username, trace_id } => {
-                    self.username.clone_from(username);
+                    self.username = self.redaction.user(username);
                     self.trace_id = *trace_id;
                     Some(notification::PresenceEvent::LoggedIn)
                 }
@@ -61,13 +80,32 @@ where
                 _ => None,
             };
             let data_event = match msg {
-                ControlChanMsg::SentData { path, bytes } => Some(notification::DataEvent::Got {
+                ControlChanMsg::SentData {
+                    path,
+                    bytes,
+                    request_id,
+                    attributes,
+                } => Some(notification::DataEvent::Got {
                     path: String::from(path),
                     bytes: *bytes,
+                    request_id: request_id.clone(),
+                    attributes: attributes.clone(),
                 }),
-                ControlChanMsg::WrittenData { path, bytes } => Some(notification::DataEvent::Put {
+                ControlChanMsg::WrittenData {
+                    path,
+                    bytes,
+                    duration,
+                    checksum,
+                    request_id,
+                    attributes,
+                    ..
+                } => Some(notification::DataEvent::Put {
                     path: String::from(path),
                     bytes: *bytes,
+                    duration: *duration,
+                    checksum: checksum.clone(),
+                    request_id: request_id.clone(),
+                    attributes: attributes.clone(),
                 }),
                 ControlChanMsg::RmDirSuccess { path } => Some(notification::DataEvent::RemovedDir { path: String::from(path) }),
                 ControlChanMsg::DelFileSuccess { path } => Some(notification::DataEvent::Deleted { path: String::from(path) }),
@@ -76,6 +114,10 @@ where
                     from: old_path.clone(),
                     to: new_path.clone(),
                 }),
+                ControlChanMsg::CopySuccess { from, to } => Some(notification::DataEvent::Copied {
+                    from: from.clone(),
+                    to: to.clone(),
+                }),
                 _ => None,
             };
             (data_event, presence_event)
@@ -86,20 +128,100 @@ where
         match events {
             (None, None) => {}
             _ => {
-                self.sequence_nr += 1;
                 let m = notification::EventMeta {
                     username: self.username.clone(),
                     trace_id: self.trace_id.to_string(),
-                    sequence_number: self.sequence_nr,
+                    sequence_number: self.activity.next_event_sequence(),
                 };
-                match events {
-                    (Some(event), None) => self.data_listener.receive_data_event(event, m).await,
-                    (None, Some(event)) => self.presence_listener.receive_presence_event(event, m).await,
+                match (events, self.delivery) {
+                    ((Some(event), None), None) => self.data_listener.receive_data_event(event, m).await,
+                    ((None, Some(event)), None) => self.presence_listener.receive_presence_event(event, m).await,
+                    ((Some(event), None), Some(delivery)) => {
+                        let listener = &self.data_listener;
+                        if let Err(err) = deliver(&delivery, || listener.try_receive_data_event(event.clone(), m.clone())).await {
+                            slog::warn!(self.logger, "Could not deliver data event {:?}: {}", event, err);
+                            failed_transfer =
+                                delivery.fail_transfer && matches!(event, notification::DataEvent::Got { .. } | notification::DataEvent::Put { .. });
+                        }
+                    }
+                    ((None, Some(event)), Some(delivery)) => {
+                        let listener = &self.presence_listener;
+                        if let Err(err) = deliver(&delivery, || listener.try_receive_presence_event(event.clone(), m.clone())).await {
+                            slog::warn!(self.logger, "Could not deliver presence event {:?}: {}", event, err);
+                        }
+                    }
                     _ => {}
                 }
             }
         }
 
-        self.next.handle(event).await
+        let reply = self.next.handle(event).await;
+        match failed_transfer {
+            true => Ok(Reply::new(
+                ReplyCode::LocalError,
+                "Transfer completed but it could not be registered, please try again",
+            )),
+            false => reply,
+        }
+    }
+}
+
+// Calls the listener until it handled the event or the attempts run out.
+async fn deliver<F, Fut>(delivery: &EventDelivery, mut attempt: F) -> Result<(), EventError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<(), EventError>>,
+{
+    let mut backoff = delivery.backoff;
+    let mut attempts = 1;
+    loop {
+        let result = match tokio::time::timeout(delivery.timeout, attempt()).await {
+            Ok(result) => result,
+            Err(_) => Err(EventError::new(format!("timed out after {:?}", delivery.timeout))),
+        };
+        match result {
+            Err(_) if attempts < delivery.attempts => {
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+                attempts += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::deliver;
+    use crate::notification::EventError;
+    use crate::options::EventDelivery;
+    use pretty_assertions::assert_eq;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn retries_failed_and_slow_deliveries() {
+        let delivery = EventDelivery::new(Duration::from_millis(50)).retries(3, Duration::from_millis(1));
+        let calls = AtomicU32::new(0);
+        // Times out, fails and then succeeds.
+        let attempt = || async {
+            match calls.fetch_add(1, Ordering::SeqCst) {
+                0 => tokio::time::sleep(Duration::from_secs(1)).await,
+                1 => return Err(EventError::new("queue unavailable")),
+                _ => {}
+            }
+            Ok(())
+        };
+        assert!(deliver(&delivery, attempt).await.is_ok());
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        let calls = AtomicU32::new(0);
+        let failing = || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err(EventError::new("queue unavailable"))
+        };
+        let err = deliver(&delivery.retries(2, Duration::ZERO), failing).await.unwrap_err();
+        assert_eq!(err.to_string(), "queue unavailable");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
     }
 }
@@ -0,0 +1,36 @@
+use crate::{
+    server::{
+        controlchan::{error::ControlChanError, middleware::ControlChanMiddleware},
+        Event, Reply, ReplyCode,
+    },
+    storage::PathFilter,
+};
+use async_trait::async_trait;
+use std::sync::Arc;
+
+// Control channel middleware that runs the paths supplied by the client through the configured
+// [`PathFilter`](crate::storage::PathFilter), replacing them with the filtered paths or rejecting
+// the command.
+pub struct PathFilterMiddleware<Next>
+where
+    Next: ControlChanMiddleware,
+{
+    pub filter: Arc<dyn PathFilter>,
+    pub next: Next,
+}
+
+#[async_trait]
+impl<Next> ControlChanMiddleware for PathFilterMiddleware<Next>
+where
+    Next: ControlChanMiddleware,
+{
+    async fn handle(&mut self, event: Event) -> Result<Reply, ControlChanError> {
+        match event {
+            Event::Command(command) => match command.try_map_paths(|path| self.filter.filter(path)) {
+                Ok(command) => self.next.handle(Event::Command(command)).await,
+                Err(err) => Ok(Reply::new_with_string(ReplyCode::BadFileName, err.to_string())),
+            },
+            event => self.next.handle(event).await,
+        }
+    }
+}
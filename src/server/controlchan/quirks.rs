@@ -0,0 +1,270 @@
+//! Recognises the FTP client from the commands it sends, so that the session can work around the
+//! quirks of clients that don't quite follow the RFCs.
+
+use crate::{
+    auth::UserDetail,
+    metrics,
+    options::ClientQuirks,
+    server::{
+        controlchan::{error::ControlChanError, middleware::ControlChanMiddleware},
+        session::SharedSession,
+        Command, Event, Reply,
+    },
+    storage::{Metadata, StorageBackend},
+};
+use async_trait::async_trait;
+use chrono::{Datelike, Utc};
+use std::fmt;
+
+// The number of commands looked at before giving up on recognising the client. Clients show who
+// they are while they set up the session.
+const OBSERVED_COMMANDS: usize = 16;
+
+// A client that was recognised.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientKind {
+    FileZilla,
+    WinScp,
+    // Windows Explorer, Internet Explorer and ftp.exe.
+    Microsoft,
+    // A client that named itself with CLNT but isn't one of the above.
+    Other(String),
+}
+
+impl ClientKind {
+    // Recognises the client by the name it gave with CLNT.
+    fn from_name(name: &str) -> Self {
+        let lower = name.to_ascii_lowercase();
+        if lower.contains("filezilla") {
+            ClientKind::FileZilla
+        } else if lower.contains("winscp") {
+            ClientKind::WinScp
+        } else if lower.contains("microsoft") || lower.contains("windows") {
+            ClientKind::Microsoft
+        } else {
+            ClientKind::Other(name.chars().take(64).collect())
+        }
+    }
+
+    // The label for the client in the metrics. Names given with CLNT are not used so that clients
+    // can't add labels at will.
+    pub fn label(&self) -> &'static str {
+        match self {
+            ClientKind::FileZilla => "filezilla",
+            ClientKind::WinScp => "winscp",
+            ClientKind::Microsoft => "microsoft",
+            ClientKind::Other(_) => "other",
+        }
+    }
+
+    // The workarounds that the client needs.
+    pub fn quirks(&self) -> Quirks {
+        match self {
+            ClientKind::Microsoft => Quirks { dos_listing: true },
+            _ => Quirks::default(),
+        }
+    }
+}
+
+impl fmt::Display for ClientKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientKind::FileZilla => write!(f, "FileZilla"),
+            ClientKind::WinScp => write!(f, "WinSCP"),
+            ClientKind::Microsoft => write!(f, "a Microsoft client"),
+            ClientKind::Other(name) => write!(f, "{:?}", name),
+        }
+    }
+}
+
+// The workarounds that are enabled for the session.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quirks {
+    // LIST output is in the MS-DOS format instead of the one of `ls -l`.
+    pub dos_listing: bool,
+}
+
+// Looks at the first commands of a session to tell which client sent them.
+#[derive(Debug, Default)]
+pub struct Fingerprint {
+    observed: usize,
+    after_feat: bool,
+}
+
+impl Fingerprint {
+    // Looks at the next command, returns the client once it is recognised. A client is recognised
+    // at most once.
+    pub fn observe(&mut self, cmd: &Command) -> Option<ClientKind> {
+        if self.observed >= OBSERVED_COMMANDS {
+            return None;
+        }
+        self.observed += 1;
+        let kind = match cmd {
+            Command::Other { command_name, arguments } if command_name == "CLNT" => Some(ClientKind::from_name(arguments)),
+            // WinSCP prefers MLSD and MLST, which other clients only use once FEAT announced them.
+            Command::Other { command_name, .. } if command_name == "MLSD" || command_name == "MLST" => Some(ClientKind::WinScp),
+            // FileZilla asks for the features right before the system type.
+            Command::Syst if self.after_feat => Some(ClientKind::FileZilla),
+            _ => None,
+        };
+        self.after_feat = matches!(cmd, Command::Feat);
+        if kind.is_some() {
+            self.observed = OBSERVED_COMMANDS;
+        }
+        kind
+    }
+}
+
+// Control channel middleware that recognises the client and, if so configured, enables the
+// workarounds for it in the session.
+pub struct QuirksMiddleware<Storage, User, Next>
+where
+    User: UserDetail + 'static,
+    Storage: StorageBackend<User> + 'static,
+    Storage::Metadata: Metadata,
+    Next: ControlChanMiddleware,
+{
+    pub session: SharedSession<Storage, User>,
+    pub client_quirks: ClientQuirks,
+    pub fingerprint: Fingerprint,
+    pub collect_metrics: bool,
+    pub logger: slog::Logger,
+    pub next: Next,
+}
+
+#[async_trait]
+impl<Storage, User, Next> ControlChanMiddleware for QuirksMiddleware<Storage, User, Next>
+where
+    User: UserDetail + 'static,
+    Storage: StorageBackend<User> + 'static,
+    Storage::Metadata: Metadata,
+    Next: ControlChanMiddleware,
+{
+    async fn handle(&mut self, event: Event) -> Result<Reply, ControlChanError> {
+        if let (Event::Command(cmd), true) = (&event, self.client_quirks != ClientQuirks::Off) {
+            if let Some(kind) = self.fingerprint.observe(cmd) {
+                let quirks = kind.quirks();
+                slog::info!(self.logger, "Recognised the client as {}, workarounds: {:?}", kind, quirks);
+                if self.collect_metrics {
+                    metrics::inc_client(kind.label());
+                }
+                if self.client_quirks == ClientQuirks::Apply {
+                    self.session.lock().await.quirks = quirks;
+                }
+            }
+        }
+        self.next.handle(event).await
+    }
+}
+
+// Rewrites a listing in the format of `ls -l`, as produced by the storage back-ends, into the
+// MS-DOS format that IIS uses. Lines that aren't entries, like the headers of recursive listings,
+// are kept as they are.
+pub fn dos_listing(listing: &str) -> String {
+    let year = Utc::now().year();
+    let mut result = String::with_capacity(listing.len());
+    for line in listing.split_terminator("\r\n") {
+        match dos_entry(line, year) {
+            Some(entry) => result.push_str(&entry),
+            None => result.push_str(line),
+        }
+        result.push_str("\r\n");
+    }
+    result
+}
+
+// Rewrites one `ls -l` line, e.g. `-rw-r--r-- 1 0 0 1024 Oct 16 13:05 name`. The time stands
+// for a date in the current year, like `ls` does.
+fn dos_entry(line: &str, year: i32) -> Option<String> {
+    let mut rest = line;
+    let mut fields = [""; 8];
+    for field in fields.iter_mut() {
+        rest = rest.trim_start_matches(' ');
+        let end = rest.find(' ')?;
+        *field = &rest[..end];
+        rest = &rest[end..];
+    }
+    // The name follows after a single space and may start with spaces of its own.
+    let name = rest.strip_prefix(' ')?;
+    let [mode, _, _, _, size, month, day, time_or_year] = fields;
+    let month = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"]
+        .iter()
+        .position(|m| *m == month)?
+        + 1;
+    let day: u32 = day.parse().ok()?;
+    let (year, hour, minute) = match time_or_year.split_once(':') {
+        Some((hour, minute)) => (year, hour.parse::<u32>().ok()?, minute.parse::<u32>().ok()?),
+        None => (time_or_year.parse().ok()?, 0, 0),
+    };
+    let (hour, meridiem) = match hour {
+        0 => (12, "AM"),
+        1..=11 => (hour, "AM"),
+        12 => (12, "PM"),
+        _ => (hour - 12, "PM"),
+    };
+    let date = format!("{:02}-{:02}-{:02}  {:02}:{:02}{}", month, day, year.rem_euclid(100), hour, minute, meridiem);
+    Some(match mode.chars().next()? {
+        'd' => format!("{}       <DIR>          {}", date, name),
+        'l' => format!("{}{:>21} {}", date, size, name.split(" -> ").next().unwrap_or(name)),
+        _ => format!("{}{:>21} {}", date, size, name),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn other(name: &str, arguments: &str) -> Command {
+        Command::Other {
+            command_name: name.to_string(),
+            arguments: arguments.to_string(),
+        }
+    }
+
+    #[test]
+    fn recognises_clients() {
+        let mut fingerprint = Fingerprint::default();
+        assert_eq!(fingerprint.observe(&Command::Feat), None);
+        assert_eq!(fingerprint.observe(&Command::Syst), Some(ClientKind::FileZilla));
+        // Only once per session.
+        assert_eq!(fingerprint.observe(&other("MLSD", "")), None);
+
+        let mut fingerprint = Fingerprint::default();
+        assert_eq!(fingerprint.observe(&Command::Syst), None);
+        assert_eq!(fingerprint.observe(&Command::Feat), None);
+        assert_eq!(fingerprint.observe(&other("MLSD", "")), Some(ClientKind::WinScp));
+
+        let mut fingerprint = Fingerprint::default();
+        assert_eq!(fingerprint.observe(&other("CLNT", "Microsoft Windows")), Some(ClientKind::Microsoft));
+        let mut fingerprint = Fingerprint::default();
+        assert_eq!(fingerprint.observe(&other("CLNT", "lftp 4.9")), Some(ClientKind::Other("lftp 4.9".to_string())));
+
+        let mut fingerprint = Fingerprint::default();
+        for _ in 0..OBSERVED_COMMANDS {
+            assert_eq!(fingerprint.observe(&Command::Noop), None);
+        }
+        assert_eq!(fingerprint.observe(&other("CLNT", "FileZilla")), None);
+    }
+
+    #[test]
+    fn rewrites_listings_in_dos_format() {
+        assert_eq!(
+            dos_entry("drwxr-xr-x            1            0            0              0 Oct 16 13:05 some dir", 2026),
+            Some("10-16-26  01:05PM       <DIR>          some dir".to_string())
+        );
+        assert_eq!(
+            dos_entry("-rw-r--r--            1            0            0           1024  Jan 02 2019  spaced", 2026),
+            Some("01-02-19  12:00AM                 1024  spaced".to_string())
+        );
+        assert_eq!(
+            dos_entry(
+                "lrwxrwxrwx            1            0            0              6 Mar 01 00:30 link -> target",
+                2026
+            ),
+            Some("03-01-26  12:30AM                    6 link".to_string())
+        );
+        assert_eq!(dos_entry("-rw-r--r-- 1 0 0 1 --- -- --:-- unknown", 2026), None);
+        assert_eq!(dos_listing("/dir:\r\n\r\n"), "/dir:\r\n\r\n");
+    }
+}
@@ -1,3 +1,7 @@
+use crate::{
+    options::MessageCatalog,
+    storage::{self, ErrorKind},
+};
 use std::fmt;
 
 /// A reply to the FTP client
@@ -112,10 +116,76 @@ pub enum ReplyCode {
     PageTypeUnknown = 551,
     ExceededStorageAllocation = 552,
     BadFileName = 553,
+    InvalidRestParameter = 554, // RFC 3659
 
     Resp533 = 533,
 }
 
+impl ReplyCode {
+    // The error reply with the given code, if it is one of ours.
+    fn from_error_code(code: u32) -> Option<ReplyCode> {
+        use ReplyCode::*;
+        [
+            ServiceNotAvailable,
+            CantOpenDataConnection,
+            ConnectionClosed,
+            TransientFileError,
+            LocalError,
+            OutOfSpace,
+            CommandSyntaxError,
+            ParameterSyntaxError,
+            CommandNotImplemented,
+            BadCommandSequence,
+            CommandNotImplementedForParameter,
+            NotLoggedIn,
+            NeedAccountToStore,
+            Resp533,
+            FtpsRequired,
+            FileError,
+            PageTypeUnknown,
+            ExceededStorageAllocation,
+            BadFileName,
+        ]
+        .into_iter()
+        .find(|reply_code| *reply_code as u32 == code)
+    }
+}
+
+// The reply for each kind of storage error. Every command that reports a storage error to the
+// client goes through here, so that the same failure gets the same reply everywhere.
+fn storage_error_reply(kind: ErrorKind) -> (ReplyCode, &'static str) {
+    match kind {
+        ErrorKind::ExceededStorageAllocationError => (ReplyCode::ExceededStorageAllocation, "Exceeded storage allocation"),
+        ErrorKind::FileNameNotAllowedError => (ReplyCode::BadFileName, "File name not allowed"),
+        ErrorKind::InsufficientStorageSpaceError => (ReplyCode::OutOfSpace, "Insufficient storage space"),
+        ErrorKind::LocalError => (ReplyCode::LocalError, "Local error"),
+        ErrorKind::PageTypeUnknown => (ReplyCode::PageTypeUnknown, "Page type unknown"),
+        ErrorKind::TransientFileNotAvailable => (ReplyCode::TransientFileError, "File not found"),
+        ErrorKind::PermanentFileNotAvailable => (ReplyCode::FileError, "File not found"),
+        ErrorKind::PermanentDirectoryNotAvailable => (ReplyCode::FileError, "Directory not found"),
+        ErrorKind::PermanentDirectoryNotEmpty => (ReplyCode::FileError, "Directory not empty"),
+        ErrorKind::PermissionDenied => (ReplyCode::FileError, "Permission denied"),
+        ErrorKind::CommandNotImplemented => (ReplyCode::CommandNotImplemented, "Command not implemented"),
+        ErrorKind::ConnectionClosed => (ReplyCode::ConnectionClosed, "Connection closed"),
+    }
+}
+
+// Uses the reply the back-end suggested, if any, and the one for the kind of the error otherwise.
+impl From<&storage::Error> for Reply {
+    fn from(err: &storage::Error) -> Self {
+        let (mut code, mut message) = storage_error_reply(err.kind());
+        if let Some((suggested_code, suggested_message)) = err.suggested_reply() {
+            code = ReplyCode::from_error_code(suggested_code).unwrap_or(code);
+            // Only the first line, so that it can't pass for a reply of its own.
+            let suggested_message = suggested_message.lines().next().unwrap_or_default().trim();
+            if !suggested_message.is_empty() {
+                message = suggested_message;
+            }
+        }
+        Reply::new(code, message)
+    }
+}
+
 impl Reply {
     pub fn new(code: ReplyCode, message: &str) -> Self {
         Reply::CodeAndMsg {
@@ -133,14 +203,83 @@ impl Reply {
         I: IntoIterator,
         I::Item: std::fmt::Display,
     {
-        Reply::MultiLine {
-            code,
-            lines: lines.into_iter().map(|item| format!("{}", item)).collect(),
-        }
+        Reply::multiline(code).lines(lines).build()
+    }
+
+    // Starts building a multi-line reply
+    pub fn multiline(code: ReplyCode) -> MultiLineBuilder {
+        MultiLineBuilder { code, lines: vec![] }
     }
 
     // A no-reply
     pub fn none() -> Self {
         Reply::None
     }
+
+    // Translates the message of the reply to the language, leaving the text the catalog has no
+    // translation for in English.
+    pub fn translate(self, catalog: &dyn MessageCatalog, language: &str) -> Self {
+        let translate = |code: ReplyCode, msg: String| catalog.translate(language, code as u32, &msg).unwrap_or(msg);
+        match self {
+            Reply::None => Reply::None,
+            Reply::CodeAndMsg { code, msg } => Reply::CodeAndMsg {
+                code,
+                msg: translate(code, msg),
+            },
+            Reply::MultiLine { code, lines } => Reply::multiline(code).lines(lines.into_iter().map(|line| translate(code, line))).build(),
+        }
+    }
+}
+
+// Builds a Reply::MultiLine. Text that contains line breaks is split into lines of its own, so
+// that a line can't end the reply early or be taken for a reply of its own by the client. The
+// padding of lines that start with a digit is done when the reply is encoded.
+#[derive(Debug)]
+pub struct MultiLineBuilder {
+    code: ReplyCode,
+    lines: Vec<String>,
+}
+
+impl MultiLineBuilder {
+    // Adds a line
+    pub fn line<T: fmt::Display>(mut self, line: T) -> Self {
+        let line = line.to_string();
+        self.lines.extend(line.split('\n').map(|part| part.trim_end_matches('\r').replace('\r', " ")));
+        self
+    }
+
+    // Adds several lines
+    pub fn lines<I>(self, lines: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: fmt::Display,
+    {
+        lines.into_iter().fold(self, |builder, line| builder.line(line))
+    }
+
+    pub fn build(self) -> Reply {
+        Reply::MultiLine {
+            code: self.code,
+            lines: self.lines,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn replies_to_storage_errors() {
+        let err = storage::Error::new(ErrorKind::PermanentDirectoryNotEmpty, "rmdir failed").with_context("RMD", "/a");
+        assert_eq!(Reply::from(&err), Reply::new(ReplyCode::FileError, "Directory not empty"));
+        assert_eq!(err.to_string(), "storage error: 550 The directory is not empty (RMD /a): rmdir failed");
+
+        let err = storage::Error::from(ErrorKind::LocalError).with_reply(452, "Quota of the bucket reached\r\n226 Fake");
+        assert_eq!(Reply::from(&err), Reply::new(ReplyCode::OutOfSpace, "Quota of the bucket reached"));
+
+        let err = storage::Error::from(ErrorKind::PermissionDenied).with_reply(250, "");
+        assert_eq!(Reply::from(&err), Reply::new(ReplyCode::FileError, "Permission denied"));
+    }
 }
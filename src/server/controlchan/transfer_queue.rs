@@ -0,0 +1,87 @@
+use crate::{
+    auth::UserDetail,
+    server::{
+        controlchan::{error::ControlChanError, middleware::ControlChanMiddleware, ControlChanErrorKind},
+        session::SharedSession,
+        Command, ControlChanMsg, Event, Reply,
+    },
+    storage::{Metadata, StorageBackend},
+};
+use async_trait::async_trait;
+
+// Control channel middleware that holds back commands received while a data transfer is in progress
+// until the transfer completed. Only the commands that are safe to run alongside a transfer (NOOP,
+// STAT, ABOR and QUIT) are handled straight away so that clients can keep the session alive, ask
+// for its status or abort the transfer. The held commands are kept in the session, the control loop
+// replays them one by one with ControlChanMsg::ReplayHeldCommand once the transfer finished. At most
+// max_held commands are held, sending more is an error that ends the session.
+pub struct TransferQueueMiddleware<Storage, User, Next>
+where
+    User: UserDetail + 'static,
+    Storage: StorageBackend<User> + 'static,
+    Storage::Metadata: Metadata,
+    Next: ControlChanMiddleware,
+{
+    pub session: SharedSession<Storage, User>,
+    pub max_held: usize,
+    pub next: Next,
+}
+
+#[async_trait]
+impl<Storage, User, Next> ControlChanMiddleware for TransferQueueMiddleware<Storage, User, Next>
+where
+    User: UserDetail + 'static,
+    Storage: StorageBackend<User> + 'static,
+    Storage::Metadata: Metadata,
+    Next: ControlChanMiddleware,
+{
+    async fn handle(&mut self, event: Event) -> Result<Reply, ControlChanError> {
+        match event {
+            Event::InternalMsg(ControlChanMsg::ReplayHeldCommand) => {
+                let held = self.session.lock().await.held_commands.pop_front();
+                match held {
+                    Some(event) => self.dispatch(event).await,
+                    None => Ok(Reply::none()),
+                }
+            }
+            Event::InternalMsg(_)
+            | Event::Command(Command::Noop)
+            | Event::Command(Command::Stat { .. })
+            | Event::Command(Command::Abor)
+            | Event::Command(Command::Quit) => self.next.handle(event).await,
+            _ => {
+                let mut session = self.session.lock().await;
+                // Commands may not overtake the ones held before them either.
+                if session.transfer_in_progress() || !session.held_commands.is_empty() {
+                    if session.held_commands.len() >= self.max_held {
+                        return Err(ControlChanErrorKind::TooManyHeldCommands.into());
+                    }
+                    session.held_commands.push_back(event);
+                    return Ok(Reply::none());
+                }
+                drop(session);
+                self.dispatch(event).await
+            }
+        }
+    }
+}
+
+impl<Storage, User, Next> TransferQueueMiddleware<Storage, User, Next>
+where
+    User: UserDetail + 'static,
+    Storage: StorageBackend<User> + 'static,
+    Storage::Metadata: Metadata,
+    Next: ControlChanMiddleware,
+{
+    // Handles the command and keeps track of the transfer it starts. The transfer commands take the
+    // data command sender from the session to hand the transfer to the data channel.
+    async fn dispatch(&mut self, event: Event) -> Result<Reply, ControlChanError> {
+        let data_cmd_tx = self.session.lock().await.data_cmd_tx.clone();
+        let reply = self.next.handle(event).await;
+        let mut session = self.session.lock().await;
+        if data_cmd_tx.is_some() && session.data_cmd_tx.is_none() {
+            session.transfer = data_cmd_tx;
+        }
+        reply
+    }
+}
@@ -1,21 +1,44 @@
 //! Contains code pertaining to the FTP *data* channel
 
 use super::{
-    chancomms::{ControlChanMsg, DataChanMsg},
+    ascii::{AsciiReader, AsciiWriter},
+    chancomms::{ControlChanMsg, DataAbort, DataChanMsg},
+    controlchan::{quirks, Reply, ReplyCode},
+    encoding::{self, Charset},
+    glob, path,
     tls::FtpsConfig,
 };
-use crate::server::session::SharedSession;
+use crate::server::session::{SharedSession, TraceId};
+use crate::server::sessions::{SessionActivity, TransferInfo};
 use crate::{
     auth::UserDetail,
-    storage::{Error, ErrorKind, Metadata, StorageBackend},
+    notification::{
+        CompletedUpload, DataEvent, DataListener, EventMeta, TransferDirection, TransferLogListener, TransferRecord, UploadHook, UploadRejection, UploadScanner,
+    },
+    options::{InterruptedTransfer, ModeZ, PartialUploads, RecursiveListing, Redaction, SessionCache, UploadChecksum, UploadConflicts},
+    storage::{temp_upload_path, Error, ErrorKind, Metadata, StorageBackend},
 };
 
 use crate::server::chancomms::DataChanCmd;
-use std::{path::PathBuf, sync::Arc};
-use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+use async_compression::{
+    tokio::{bufread::ZlibDecoder, write::ZlibEncoder},
+    Level,
+};
+use bytes::Bytes;
+use md5::{Digest, Md5};
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, ReadBuf};
 use tokio::net::TcpStream;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::task::JoinHandle;
+#[cfg(feature = "ftps")]
 use tokio_rustls::TlsAcceptor;
+use tokio_util::sync::PollSender;
 
 use crate::metrics;
 
@@ -27,29 +50,82 @@ where
     User: UserDetail,
 {
     pub user: Arc<Option<User>>,
-    pub socket: TcpStream,
+    pub socket: StallGuard<TcpStream>,
     pub control_msg_tx: Sender<ControlChanMsg>,
     pub storage: Arc<Storage>,
     pub cwd: PathBuf,
     pub ftps_mode: FtpsConfig,
     pub logger: slog::Logger,
     pub data_cmd_rx: Option<Receiver<DataChanCmd>>,
-    pub data_abort_rx: Option<Receiver<()>>,
+    pub data_abort_rx: Option<Receiver<DataAbort>>,
+    pub charset: Charset,
+    pub partial_uploads: PartialUploads,
+    pub upload_conflicts: UploadConflicts,
+    pub upload_checksum: UploadChecksum,
+    pub upload_hook: Option<Arc<dyn UploadHook>>,
+    pub upload_scanner: Option<Arc<dyn UploadScanner>>,
+    pub transfer_log: Option<Arc<dyn TransferLogListener>>,
+    pub session_cache: Option<Arc<dyn SessionCache>>,
+    pub progress_events: Option<(Arc<dyn DataListener>, Duration)>,
+    // Whether to look up the attributes of the file for the data event.
+    pub file_attributes: bool,
+    pub username: String,
+    pub trace_id: TraceId,
+    // Identifies the transfer in the logs and data events.
+    pub request_id: String,
+    // The compression level if the client switched to MODE Z.
+    pub deflate: Option<u32>,
+    // Whether the client switched to TYPE A.
+    pub ascii: bool,
+    pub activity: Arc<SessionActivity>,
+    pub recursive_listing: RecursiveListing,
+    // Whether LIST output is rewritten in the MS-DOS format for the client.
+    pub dos_listing: bool,
+    // How the user and the client address appear in the notifications.
+    pub redaction: Redaction,
 }
 
 use std::fmt;
+use std::future::Future;
 use std::pin::Pin;
 use std::task::{Context, Poll};
-use std::time::Instant;
+use std::time::{Instant, SystemTime};
+
+// Tells the data listener how far the transfer got, every interval until it is aborted.
+async fn report_progress(activity: Arc<SessionActivity>, listener: Arc<dyn DataListener>, username: String, trace_id: String, interval: Duration) {
+    let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+    loop {
+        ticks.tick().await;
+        let Some(transfer) = activity.transfer() else {
+            continue;
+        };
+        let event = DataEvent::Progress {
+            command: transfer.command.clone(),
+            path: transfer.path.clone(),
+            bytes: transfer.bytes,
+            duration: transfer.started.elapsed().unwrap_or_default(),
+            bytes_per_second: transfer.bytes_per_second(),
+        };
+        let meta = EventMeta {
+            username: username.clone(),
+            trace_id: trace_id.clone(),
+            sequence_number: activity.next_event_sequence(),
+        };
+        listener.receive_data_event(event, meta).await;
+    }
+}
 
+// Counts the bytes moved for the metrics and the ServerHandle.
 struct MeasuringWriter<W> {
     writer: W,
     command: &'static str,
+    activity: Arc<SessionActivity>,
 }
 
 struct MeasuringReader<R> {
     reader: R,
     command: &'static str,
+    activity: Arc<SessionActivity>,
 }
 
 impl<W: AsyncWrite + Unpin> AsyncWrite for MeasuringWriter<W> {
@@ -59,6 +135,7 @@ impl<W: AsyncWrite + Unpin> AsyncWrite for MeasuringWriter<W> {
         let result = Pin::new(&mut this.writer).poll_write(cx, buf);
         if let Poll::Ready(Ok(bytes_written)) = &result {
             metrics::inc_sent_bytes(*bytes_written, this.command);
+            this.activity.add_bytes(*bytes_written as u64);
         }
 
         result
@@ -82,20 +159,297 @@ impl<R: AsyncRead + Unpin> AsyncRead for MeasuringReader<R> {
         if let Poll::Ready(Ok(())) = &result {
             let bytes_read = buf.filled().len();
             metrics::inc_received_bytes(bytes_read, this.command);
+            this.activity.add_bytes(bytes_read as u64);
+        }
+        result
+    }
+}
+
+// Wraps the data connection and fails reads and writes with a `TimedOut` error once no bytes could
+// be moved for the stall timeout, so that stalled transfers don't keep the session busy forever.
+#[derive(Debug)]
+struct StallGuard<S> {
+    inner: S,
+    timeout: Option<Duration>,
+    deadline: Option<Pin<Box<tokio::time::Sleep>>>,
+}
+
+impl<S> StallGuard<S> {
+    fn new(inner: S, timeout: Option<Duration>) -> Self {
+        Self {
+            inner,
+            timeout,
+            deadline: None,
+        }
+    }
+
+    // Called with the result of polling the inner stream: progress resets the deadline while a
+    // pending operation starts it, turning into an error once it passed.
+    fn guard<T>(&mut self, cx: &mut Context<'_>, result: Poll<std::io::Result<T>>) -> Poll<std::io::Result<T>> {
+        let timeout = match (self.timeout, &result) {
+            (Some(timeout), Poll::Pending) => timeout,
+            _ => {
+                self.deadline = None;
+                return result;
+            }
+        };
+        let deadline = self.deadline.get_or_insert_with(|| Box::pin(tokio::time::sleep(timeout)));
+        match deadline.as_mut().poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(stalled(timeout))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+fn stalled(timeout: Duration) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::TimedOut, format!("no data transferred for {}", HumanDuration(timeout)))
+}
+
+// The maximum amount of bytes passed to a single sendfile call, so that a transfer doesn't occupy
+// the blocking thread for too long.
+#[cfg(target_os = "linux")]
+const SENDFILE_MAX_CHUNK: usize = 1024 * 1024;
+
+// Sends the file from the given position with sendfile(2), so that the data goes from the file to
+// the socket without passing through user space. Like the StallGuard it fails when the client
+// doesn't accept any data for the data stall timeout. The bytes are counted as they go, for the
+// progress of the transfer.
+#[cfg(target_os = "linux")]
+async fn send_file(socket: &StallGuard<TcpStream>, file: std::fs::File, start_pos: u64, activity: &SessionActivity) -> std::io::Result<u64> {
+    use std::os::fd::AsFd;
+
+    let length = file.metadata()?.len();
+    let mut offset = libc::off_t::try_from(start_pos).map_err(|_| std::io::Error::from(std::io::ErrorKind::InvalidInput))?;
+    let mut sent: u64 = 0;
+    // sendfile reads from disk, so it runs on the blocking thread pool with its own handle to the socket.
+    let file = Arc::new(file);
+    let out = Arc::new(socket.inner.as_fd().try_clone_to_owned()?);
+    while (offset as u64) < length {
+        let count = (length - offset as u64).min(SENDFILE_MAX_CHUNK as u64) as usize;
+        match socket.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, socket.inner.writable()).await.map_err(|_| stalled(timeout))??,
+            None => socket.inner.writable().await?,
+        }
+        let (file, out) = (file.clone(), out.clone());
+        let (result, new_offset) = tokio::task::spawn_blocking(move || {
+            let result = nix::sys::sendfile::sendfile(out.as_fd(), file.as_fd(), Some(&mut offset), count).map_err(std::io::Error::from);
+            (result, offset)
+        })
+        .await?;
+        offset = new_offset;
+        match result {
+            // The file got shorter in the meantime.
+            Ok(0) => break,
+            Ok(bytes) => {
+                sent += bytes as u64;
+                metrics::inc_sent_bytes(bytes, "retr");
+                activity.add_bytes(bytes as u64);
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                // Clears the readiness that tokio cached, so that writable() waits for the client.
+                let _ = socket.inner.try_io(tokio::io::Interest::WRITABLE, || Err::<(), _>(err));
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(sent)
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn send_file(_socket: &StallGuard<TcpStream>, _file: std::fs::File, _start_pos: u64, _activity: &SessionActivity) -> std::io::Result<u64> {
+    Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for StallGuard<S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        this.guard(cx, result)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for StallGuard<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_write(cx, buf);
+        this.guard(cx, result)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_flush(cx);
+        this.guard(cx, result)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+// Feeds the data read from the client into a hasher on its way to the storage back-end, so that the
+// checksum of an upload is known once it was stored.
+struct ChecksumReader<R> {
+    reader: R,
+    hasher: Option<Arc<std::sync::Mutex<Md5>>>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ChecksumReader<R> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let filled_before = buf.filled().len();
+        let result = Pin::new(&mut this.reader).poll_read(cx, buf);
+        if let (Poll::Ready(Ok(())), Some(hasher)) = (&result, &this.hasher) {
+            hasher.lock().unwrap().update(&buf.filled()[filled_before..]);
         }
         result
     }
 }
 
+// The number of chunks that may be queued up for the upload scanner before reading from the client
+// waits for it.
+const SCAN_QUEUE_CHUNKS: usize = 16;
+
+// Tees the data read from the client off to the upload scanner on its way to the storage back-end.
+// The end of the upload is withheld from the storage back-end until the scanner gave its verdict, so
+// that a rejected upload fails instead of completing.
+struct ScanningReader<R> {
+    reader: R,
+    sender: PollSender<Bytes>,
+    verdict: Option<JoinHandle<Result<(), UploadRejection>>>,
+    // Set when the scanner rejected the upload, to tell this apart from other errors.
+    rejection: Arc<std::sync::Mutex<Option<String>>>,
+}
+
+impl<R> ScanningReader<R> {
+    fn new(reader: R, scanner: Arc<dyn UploadScanner>, path: String) -> Self {
+        let (tx, rx) = tokio::sync::mpsc::channel(SCAN_QUEUE_CHUNKS);
+        ScanningReader {
+            reader,
+            sender: PollSender::new(tx),
+            verdict: Some(tokio::spawn(async move { scanner.scan(path, rx).await })),
+            rejection: Arc::new(std::sync::Mutex::new(None)),
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ScanningReader<R> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        // Make sure the chunk can be passed on before reading it. If the scanner stopped receiving,
+        // the data is just not passed on anymore; its verdict is checked at the end.
+        if !this.sender.is_closed() && this.sender.poll_reserve(cx).is_pending() {
+            return Poll::Pending;
+        }
+        let filled_before = buf.filled().len();
+        match Pin::new(&mut this.reader).poll_read(cx, buf) {
+            Poll::Ready(Ok(())) => {}
+            other => return other,
+        }
+        let chunk = &buf.filled()[filled_before..];
+        if !chunk.is_empty() {
+            let _ = this.sender.send_item(Bytes::copy_from_slice(chunk));
+            return Poll::Ready(Ok(()));
+        }
+
+        // The client finished sending, so let the scanner know and wait for its verdict. The slot
+        // reserved above has to be given back for the channel to close.
+        this.sender.close();
+        this.sender.abort_send();
+        let verdict = match this.verdict.as_mut() {
+            Some(verdict) => verdict,
+            None => return Poll::Ready(Ok(())),
+        };
+        let result = match Pin::new(verdict).poll(cx) {
+            Poll::Pending => return Poll::Pending,
+            Poll::Ready(result) => result,
+        };
+        this.verdict = None;
+        let reason = match result {
+            Ok(Ok(())) => return Poll::Ready(Ok(())),
+            Ok(Err(rejection)) => rejection.to_string(),
+            Err(err) => format!("Upload scanner failed: {}", err),
+        };
+        *this.rejection.lock().unwrap() = Some(reason.clone());
+        Poll::Ready(Err(std::io::Error::new(std::io::ErrorKind::Other, reason)))
+    }
+}
+
 impl<W> MeasuringWriter<W> {
-    fn new(writer: W, command: &'static str) -> MeasuringWriter<W> {
-        Self { writer, command }
+    fn new(writer: W, command: &'static str, activity: Arc<SessionActivity>) -> MeasuringWriter<W> {
+        Self { writer, command, activity }
     }
 }
 
 impl<R> MeasuringReader<R> {
-    fn new(reader: R, command: &'static str) -> MeasuringReader<R> {
-        Self { reader, command }
+    fn new(reader: R, command: &'static str, activity: Arc<SessionActivity>) -> MeasuringReader<R> {
+        Self { reader, command, activity }
+    }
+}
+
+// Holds what is needed to clean up after a transfer that the client aborted with ABOR.
+struct AbortedTransfer<Storage, User>
+where
+    Storage: StorageBackend<User>,
+    User: UserDetail,
+{
+    user: Arc<Option<User>>,
+    storage: Arc<Storage>,
+    // Set for uploads only. The file the data was written to, which is a temporary one for uploads
+    // that a hook or scanner checks.
+    stor_path: Option<PathBuf>,
+    partial_uploads: PartialUploads,
+    control_msg_tx: Sender<ControlChanMsg>,
+    logger: slog::Logger,
+}
+
+impl<Storage, User> AbortedTransfer<Storage, User>
+where
+    Storage: StorageBackend<User> + 'static,
+    Storage::Metadata: Metadata,
+    User: UserDetail + 'static,
+{
+    fn new(executor: &DataCommandExecutor<Storage, User>, command: &DataChanCmd) -> Self {
+        AbortedTransfer {
+            user: executor.user.clone(),
+            storage: executor.storage.clone(),
+            stor_path: match command {
+                DataChanCmd::Stor { path, .. } if executor.vetted() => Some(temp_upload_path(path::resolve(&executor.cwd, path))),
+                DataChanCmd::Stor { path, .. } => Some(path::resolve(&executor.cwd, path)),
+                _ => None,
+            },
+            // Unchecked data can't be resumed from.
+            partial_uploads: match executor.vetted() {
+                true => PartialUploads::Delete,
+                false => executor.partial_uploads,
+            },
+            control_msg_tx: executor.control_msg_tx.clone(),
+            logger: executor.logger.clone(),
+        }
+    }
+
+    // Lets the storage back-end clean up an interrupted upload and then replies to the aborted
+    // command with 426 and to ABOR with 226, as RFC 959 prescribes.
+    async fn handle(self) {
+        slog::info!(self.logger, "Data transfer aborted");
+        if let (Some(path), Some(user)) = (&self.stor_path, (*self.user).as_ref()) {
+            if let Err(err) = self.storage.abort_put(user, path).await {
+                slog::warn!(self.logger, "Storage back-end could not abort upload to {:?}: {:?}", path, err);
+            }
+            if self.partial_uploads == PartialUploads::Delete {
+                match self.storage.del(user, path).await {
+                    Ok(()) => slog::info!(self.logger, "Deleted partially stored file {:?}", path),
+                    Err(err) => slog::warn!(self.logger, "Could not delete partially stored file {:?}: {:?}", path, err),
+                }
+            }
+        }
+        self.reply(Reply::new(ReplyCode::ConnectionClosed, "Connection closed; transfer aborted")).await;
+        self.reply(Reply::new(ReplyCode::ClosingDataConnection, "ABOR command successful")).await;
+    }
+
+    async fn reply(&self, reply: Reply) {
+        if let Err(err) = self.control_msg_tx.send(ControlChanMsg::CommandChannelReply(reply)).await {
+            slog::warn!(self.logger, "Could not send internal message to reply to ABOR: {}", err);
+        }
     }
 }
 
@@ -106,17 +460,35 @@ where
     User: UserDetail + 'static,
 {
     async fn execute(mut self, session_arc: SharedSession<Storage, User>) {
+        let _connection = metrics::track_data_connection();
         let mut data_cmd_rx = self.data_cmd_rx.take().unwrap();
         let mut data_abort_rx = self.data_abort_rx.take().unwrap();
         let mut timeout_delay = Box::pin(tokio::time::sleep(std::time::Duration::from_secs(5 * 60)));
         // TODO: Use configured timeout
         tokio::select! {
             Some(command) = data_cmd_rx.recv() => {
-                let session = session_arc.lock().await;
-                self.handle_incoming(DataChanMsg::ExternalCommand(command), session.start_pos).await;
+                let aborted = AbortedTransfer::new(&self, &command);
+                tokio::select! {
+                    _ = self.handle_incoming(DataChanMsg::ExternalCommand(command)) => {
+                        // The client may have sent ABOR just when the transfer completed.
+                        if let Ok(ack) = data_abort_rx.try_recv() {
+                            aborted.reply(Reply::new(ReplyCode::ClosingDataConnection, "Transfer already completed")).await;
+                            let _ = ack.send(());
+                        }
+                    },
+                    Some(ack) = data_abort_rx.recv() => {
+                        aborted.handle().await;
+                        let _ = ack.send(());
+                    },
+                }
             },
-            Some(_) = data_abort_rx.recv() => {
-                self.handle_incoming(DataChanMsg::Abort, 0).await;
+            Some(ack) = data_abort_rx.recv() => {
+                let reply = Reply::new(ReplyCode::ClosingDataConnection, "Closed data channel");
+                if let Err(err) = self.control_msg_tx.send(ControlChanMsg::CommandChannelReply(reply)).await {
+                    slog::warn!(self.logger, "Could not send internal message to reply to ABOR: {}", err);
+                }
+                self.handle_incoming(DataChanMsg::Abort).await;
+                let _ = ack.send(());
             },
             _ = &mut timeout_delay => {
                 slog::warn!(self.logger, "Data channel connection timed out");
@@ -127,7 +499,7 @@ where
     }
 
     #[tracing_attributes::instrument]
-    async fn handle_incoming(self, incoming: DataChanMsg, start_pos: u64) {
+    async fn handle_incoming(self, incoming: DataChanMsg) {
         match incoming {
             DataChanMsg::Abort => {
                 slog::info!(self.logger, "Data channel abort received");
@@ -135,38 +507,99 @@ where
             DataChanMsg::ExternalCommand(command) => {
                 let p = command.path().unwrap_or_default();
                 slog::debug!(self.logger, "Data channel command received: {:?}", command; "path" => p);
-                self.execute_command(command, start_pos).await;
+                self.execute_command(command).await;
             }
         }
     }
 
     #[tracing_attributes::instrument]
-    async fn execute_command(self, cmd: DataChanCmd, start_pos: u64) {
+    async fn execute_command(mut self, cmd: DataChanCmd) {
+        let activity = self.activity.clone();
+        let command = match &cmd {
+            DataChanCmd::Retr { .. } => "RETR",
+            DataChanCmd::Stor { .. } => "STOR",
+            DataChanCmd::Stou { .. } => "STOU",
+            DataChanCmd::List { .. } => "LIST",
+            DataChanCmd::Nlst { .. } => "NLST",
+        };
+        self.request_id = TraceId::new().to_string();
+        self.logger = self.logger.new(slog::o!("cmd" => command, "request-id" => self.request_id.clone()));
+        activity.set_transfer(Some(TransferInfo {
+            command: command.to_string(),
+            path: cmd.path().unwrap_or_default(),
+            bytes: 0,
+            started: SystemTime::now(),
+        }));
+        let progress = self.progress_events.clone().map(|(listener, interval)| {
+            tokio::spawn(report_progress(
+                activity.clone(),
+                listener,
+                self.redaction.user(&self.username),
+                self.trace_id.to_string(),
+                interval,
+            ))
+        });
         match cmd {
-            DataChanCmd::Retr { path } => {
+            DataChanCmd::Retr { path, start_pos } => {
                 self.exec_retr(path, start_pos).await;
             }
-            DataChanCmd::Stor { path } => {
-                self.exec_stor(path, start_pos).await;
+            DataChanCmd::Stor { path, start_pos } => {
+                self.exec_stor(path, start_pos, false).await;
             }
-            DataChanCmd::List { path, .. } => {
-                self.exec_list_variant(path, ListCommand::List).await;
+            DataChanCmd::Stou { path } => {
+                self.exec_stor(path, 0, true).await;
             }
-            DataChanCmd::Nlst { path } => {
-                self.exec_list_variant(path, ListCommand::Nlst).await;
+            DataChanCmd::List { options, path } => {
+                self.exec_list_variant(path, ListCommand::List, recursive(&options)).await;
             }
+            DataChanCmd::Nlst { options, path } => {
+                self.exec_list_variant(path, ListCommand::Nlst, recursive(&options)).await;
+            }
+        }
+        if let Some(progress) = progress {
+            progress.abort();
         }
+        activity.set_transfer(None);
     }
 
     #[tracing_attributes::instrument]
     async fn exec_retr(self, path: String, start_pos: u64) {
         let path_copy = path.clone();
-        let path = self.cwd.join(path);
+        let path = path::resolve(&self.cwd, path);
         let tx: Sender<ControlChanMsg> = self.control_msg_tx.clone();
-        let mut output = Self::writer(self.socket, self.ftps_mode, "retr").await;
+        let user = (*self.user).as_ref().unwrap();
+
+        // Plain files are sent by the kernel when there is no TLS, compression or line ending
+        // translation in between. If the back-end can't provide the file get_into is used, which
+        // also reports any error.
+        #[cfg(not(target_family = "wasm"))]
+        let file = match self.ftps_mode {
+            FtpsConfig::Off if cfg!(target_os = "linux") && self.deflate.is_none() && !self.ascii => match self.storage.get_file(user, &path).await {
+                Ok(file) => file,
+                Err(err) => {
+                    slog::debug!(self.logger, "Could not open {:?} for sendfile, falling back to get_into: {:?}", &path_copy, err);
+                    None
+                }
+            },
+            _ => None,
+        };
+        #[cfg(target_family = "wasm")]
+        let file: Option<std::fs::File> = None;
 
         let start_time = Instant::now();
-        let result = self.storage.get_into((*self.user).as_ref().unwrap(), path, start_pos, &mut output).await;
+        let (result, mut output) = match file {
+            Some(file) => {
+                let result = send_file(&self.socket, file, start_pos, &self.activity).await.map_err(Error::from);
+                (result, Box::new(self.socket) as Box<dyn AsyncWrite + Send + Unpin + Sync>)
+            }
+            None => {
+                let mut output = Self::writer(self.socket, self.ftps_mode, self.deflate, "retr", self.activity.clone()).await;
+                if self.ascii {
+                    output = Box::new(AsciiWriter::new(output));
+                }
+                (self.storage.get_into(user, path.clone(), start_pos, &mut output).await, output)
+            }
+        };
 
         if let Err(err) = output.shutdown().await {
             match err.kind() {
@@ -197,11 +630,33 @@ where
                 if start_pos == 0 {
                     metrics::inc_transferred("retr", "success");
                 }
+                metrics::inc_backend_bytes("get", "out", bytes_copied);
+
+                if let Some(transfer_log) = &self.transfer_log {
+                    let record = TransferRecord {
+                        finished: SystemTime::now(),
+                        duration,
+                        remote_host: self.redaction.ip(self.activity.source().ip()),
+                        bytes: bytes_copied,
+                        path: path.to_string_lossy().into_owned(),
+                        ascii: self.ascii,
+                        direction: TransferDirection::Outgoing,
+                        username: self.redaction.user(&self.username),
+                        trace_id: self.trace_id.to_string(),
+                    };
+                    transfer_log.receive_transfer(record).await;
+                }
 
+                let attributes = match self.file_attributes {
+                    true => Self::attributes(&self.storage, user, &path, &self.logger).await,
+                    false => vec![],
+                };
                 if let Err(err) = tx
                     .send(ControlChanMsg::SentData {
                         bytes: bytes_copied,
                         path: path_copy,
+                        request_id: self.request_id,
+                        attributes,
                     })
                     .await
                 {
@@ -244,33 +699,129 @@ where
                     categorize_and_register_error(&self.logger, &err, "retr");
                 }
 
-                if let Err(err) = tx.send(ControlChanMsg::StorageError(err)).await {
+                // The client may reconnect and resume the download.
+                if let Some(session_cache) = &self.session_cache {
+                    let sent = self.activity.transfer().map_or(0, |transfer| transfer.bytes);
+                    let transfer = InterruptedTransfer {
+                        offset: start_pos + sent,
+                        interrupted: SystemTime::now(),
+                    };
+                    session_cache.store(&self.username, &path.to_string_lossy(), transfer).await;
+                }
+
+                if let Err(err) = tx.send(ControlChanMsg::StorageError(err.with_context("RETR", path_copy.as_str()))).await {
                     slog::warn!(self.logger, "Could not notify control channel of error with RETR: {:?}", err);
                 }
             }
         }
     }
 
+    // Stores the upload at the given path. If unique is set the path is the file that STOU created
+    // for it, which is reported back to the client.
     #[tracing_attributes::instrument]
-    async fn exec_stor(self, path: String, start_pos: u64) {
+    async fn exec_stor(self, path: String, start_pos: u64, unique: bool) {
         let path_copy = path.clone();
-        let path = self.cwd.join(path);
+        let path = path::resolve(&self.cwd, path);
         let tx = self.control_msg_tx.clone();
+        // Held until the upload completed. Unique names don't conflict.
+        let _lock = match unique {
+            true => None,
+            false => match self.upload_conflicts.acquire(&path, &self.trace_id.to_string()).await {
+                Some(lock) => lock,
+                None => {
+                    slog::info!(self.logger, "Refusing STOR {:?}, another session is uploading to it", &path_copy);
+                    let reply = Reply::new(ReplyCode::TransientFileError, "File is being uploaded by another session");
+                    if let Err(err) = tx.send(ControlChanMsg::CommandChannelReply(reply)).await {
+                        slog::error!(self.logger, "Could not notify control channel of refused STOR: {:?}", err);
+                    }
+                    return;
+                }
+            },
+        };
+        // A resumed upload only covers part of the file so there is no point in computing a checksum.
+        let hasher = match self.upload_checksum {
+            UploadChecksum::Md5 if start_pos == 0 => Some(Arc::new(std::sync::Mutex::new(Md5::new()))),
+            _ => None,
+        };
+        // Uploads that are checked are stored under a temporary name until they passed, so that a
+        // rejection only removes what this upload wrote.
+        let staged = match self.vetted() {
+            true => temp_upload_path(&path),
+            false => path.clone(),
+        };
+        let mut reader = Self::reader(self.socket, self.ftps_mode, self.deflate, "stor", self.activity.clone()).await;
+        if self.ascii {
+            reader = Box::new(AsciiReader::new(reader));
+        }
+        let input = ChecksumReader {
+            reader,
+            hasher: hasher.clone(),
+        };
+        let (input, scan_rejection) = match &self.upload_scanner {
+            Some(scanner) => {
+                let input = ScanningReader::new(input, scanner.clone(), path.to_string_lossy().into_owned());
+                let rejection = input.rejection.clone();
+                (Box::new(input) as Box<dyn AsyncRead + Send + Unpin + Sync>, Some(rejection))
+            }
+            None => (Box::new(input) as Box<dyn AsyncRead + Send + Unpin + Sync>, None),
+        };
 
         let start_time = Instant::now();
-        let put_result = self
-            .storage
-            .put(
-                (*self.user).as_ref().unwrap(),
-                Self::reader(self.socket, self.ftps_mode, "stor").await,
-                path,
-                start_pos,
-            )
-            .await;
+        let put_result = self.storage.put((*self.user).as_ref().unwrap(), input, staged.clone(), start_pos).await;
         let duration = start_time.elapsed();
 
+        if let Some(reason) = scan_rejection.and_then(|rejection| rejection.lock().unwrap().take()) {
+            slog::warn!(self.logger, "Upload scanner rejected STOR {:?}: {}", &path_copy, reason);
+            // The storage back-end may have kept what it received before the read failed.
+            Self::discard_upload(&self.storage, (*self.user).as_ref().unwrap(), &self.logger, &staged, &path, unique).await;
+            if start_pos == 0 {
+                metrics::inc_transferred("stor", "rejected");
+            }
+            if let Err(err) = tx.send(ControlChanMsg::UploadScanFailed { reason }).await {
+                slog::error!(self.logger, "Could not notify control channel of rejected STOR: {:?}", err);
+            }
+            return;
+        }
+
         match put_result {
             Ok(bytes) => {
+                let unique_name = match unique {
+                    true => path.file_name().map(|name| name.to_string_lossy().into_owned()),
+                    false => None,
+                };
+                let checksum = hasher.map(|hasher| format!("{:x}", hasher.lock().unwrap().clone().finalize()));
+                if let Some(hook) = &self.upload_hook {
+                    let upload = CompletedUpload {
+                        username: self.username.clone(),
+                        trace_id: self.trace_id.to_string(),
+                        path: path.to_string_lossy().into_owned(),
+                        staged_path: staged.to_string_lossy().into_owned(),
+                        bytes,
+                        duration,
+                        checksum: checksum.clone(),
+                    };
+                    if let Err(rejection) = hook.pre_complete(&upload).await {
+                        slog::warn!(self.logger, "Upload hook rejected STOR {:?}: {}", &path_copy, rejection);
+                        Self::discard_upload(&self.storage, (*self.user).as_ref().unwrap(), &self.logger, &staged, &path, unique).await;
+                        if start_pos == 0 {
+                            metrics::inc_transferred("stor", "rejected");
+                        }
+                        if let Err(err) = tx.send(ControlChanMsg::UploadRejected { reason: rejection.to_string() }).await {
+                            slog::error!(self.logger, "Could not notify control channel of rejected STOR: {:?}", err);
+                        }
+                        return;
+                    }
+                }
+                if staged != path {
+                    if let Err(err) = self.storage.rename((*self.user).as_ref().unwrap(), staged.clone(), path.clone()).await {
+                        slog::warn!(self.logger, "Could not move checked upload {:?} to its name: {:?}", &path_copy, err);
+                        Self::discard_upload(&self.storage, (*self.user).as_ref().unwrap(), &self.logger, &staged, &path, unique).await;
+                        if let Err(err) = tx.send(ControlChanMsg::StorageError(err.with_context("STOR", path_copy.as_str()))).await {
+                            slog::error!(self.logger, "Could not notify control channel of error with STOR: {:?}", err);
+                        }
+                        return;
+                    }
+                }
                 slog::info!(
                     self.logger,
                     "Successful STOR {:?}; Duration {}; Bytes copied {}; Transfer speed {}; start_pos={}",
@@ -285,37 +836,107 @@ where
                 if start_pos == 0 {
                     metrics::inc_transferred("stor", "success");
                 }
+                metrics::inc_backend_bytes("put", "in", bytes);
+
+                if let Some(transfer_log) = &self.transfer_log {
+                    let record = TransferRecord {
+                        finished: SystemTime::now(),
+                        duration,
+                        remote_host: self.redaction.ip(self.activity.source().ip()),
+                        bytes,
+                        path: path.to_string_lossy().into_owned(),
+                        ascii: self.ascii,
+                        direction: TransferDirection::Incoming,
+                        username: self.redaction.user(&self.username),
+                        trace_id: self.trace_id.to_string(),
+                    };
+                    transfer_log.receive_transfer(record).await;
+                }
 
-                if let Err(err) = tx.send(ControlChanMsg::WrittenData { bytes, path: path_copy }).await {
+                let attributes = match self.file_attributes {
+                    true => Self::attributes(&self.storage, (*self.user).as_ref().unwrap(), &path, &self.logger).await,
+                    false => vec![],
+                };
+                if let Err(err) = tx
+                    .send(ControlChanMsg::WrittenData {
+                        bytes,
+                        path: path_copy,
+                        duration,
+                        checksum,
+                        unique_name,
+                        request_id: self.request_id,
+                        attributes,
+                    })
+                    .await
+                {
                     slog::error!(self.logger, "Could not notify control channel of successful STOR: {:?}", err);
                 }
             }
             Err(err) => {
                 slog::warn!(self.logger, "Error during STOR transfer after {}: {:?}", HumanDuration(duration), err);
+                // The staged file of a checked upload and the file that STOU created are of no use
+                // without the data.
+                Self::discard_upload(&self.storage, (*self.user).as_ref().unwrap(), &self.logger, &staged, &path, unique).await;
 
                 // only register transfer errors for a single file transfer once
                 if start_pos == 0 {
                     categorize_and_register_error(&self.logger, &err, "stor");
                 }
 
-                if let Err(err) = tx.send(ControlChanMsg::StorageError(err)).await {
+                if let Err(err) = tx.send(ControlChanMsg::StorageError(err.with_context("STOR", path_copy.as_str()))).await {
                     slog::error!(self.logger, "Could not notify control channel of error with STOR: {:?}", err);
                 }
             }
         }
     }
 
+    // True if uploads are checked by a hook or scanner before they complete.
+    fn vetted(&self) -> bool {
+        self.upload_hook.is_some() || self.upload_scanner.is_some()
+    }
+
+    // Removes what a failed or rejected upload wrote: the temporary file a checked upload was
+    // staged in and the file that STOU created for it. A file that existed under the name before
+    // is left alone.
+    async fn discard_upload(storage: &Storage, user: &User, logger: &slog::Logger, staged: &Path, path: &Path, unique: bool) {
+        if staged != path {
+            if let Err(err) = storage.del(user, staged).await {
+                slog::debug!(logger, "Could not delete the staged upload {:?}: {:?}", staged, err);
+            }
+        }
+        if unique {
+            if let Err(err) = storage.del(user, path).await {
+                slog::debug!(logger, "Could not delete the unique file {:?}: {:?}", path, err);
+            }
+        }
+    }
+
+    // The attributes of the transferred file for the data event.
+    async fn attributes(storage: &Storage, user: &User, path: &Path, logger: &slog::Logger) -> Vec<(String, String)> {
+        match storage.metadata(user, path).await {
+            Ok(metadata) => metadata.attributes(),
+            Err(err) => {
+                slog::debug!(logger, "Could not get the attributes of {:?}: {:?}", path, err);
+                vec![]
+            }
+        }
+    }
+
     #[tracing_attributes::instrument]
-    async fn exec_list_variant(self, path: Option<String>, command: ListCommand) {
-        let path = self.resolve_path(path);
+    async fn exec_list_variant(self, path: Option<String>, command: ListCommand, recursive: bool) {
+        let (path, pattern) = self.resolve_list_path(path);
         let tx = self.control_msg_tx.clone();
-        let mut output = Self::writer(self.socket, self.ftps_mode.clone(), command.as_lower_str()).await;
+        let mut output = Self::writer(self.socket, self.ftps_mode.clone(), self.deflate, command.as_lower_str(), self.activity.clone()).await;
 
         let start_time = Instant::now();
 
-        let list_result = match command {
-            ListCommand::List => self.storage.list_fmt((*self.user).as_ref().unwrap(), path.clone()).await,
-            ListCommand::Nlst => self
+        let list_result = match (command, pattern, self.recursive_listing) {
+            (_, Some(pattern), _) => Self::list_matching(&self.storage, (*self.user).as_ref().unwrap(), path.clone(), &pattern, command).await,
+            (_, None, RecursiveListing::Enabled { max_depth, max_entries }) if recursive => {
+                Self::list_recursive(&self.storage, (*self.user).as_ref().unwrap(), path.clone(), command, max_depth, max_entries).await
+            }
+            (ListCommand::List, None, _) => self.storage.list_fmt((*self.user).as_ref().unwrap(), path.clone()).await,
+            (ListCommand::Nlst, None, _) => self
                 .storage
                 .nlst((*self.user).as_ref().unwrap(), path.clone())
                 .await
@@ -323,9 +944,21 @@ where
         };
 
         match list_result {
-            Ok(cursor) => {
+            Ok(mut cursor) => {
                 slog::debug!(self.logger, "Copying future for {}", command.as_str());
-                let mut input = cursor;
+                if self.dos_listing && matches!(command, ListCommand::List) {
+                    cursor = std::io::Cursor::new(quirks::dos_listing(&String::from_utf8_lossy(cursor.get_ref())).into_bytes());
+                }
+                // Listings are produced in UTF-8, convert them if the client uses another character set
+                // or they hold names that aren't valid UTF-8.
+                let encoded = match self.charset.encode(&String::from_utf8_lossy(cursor.get_ref())) {
+                    Cow::Owned(encoded) => Some(encoded),
+                    Cow::Borrowed(_) => None,
+                };
+                let mut input = match encoded {
+                    Some(encoded) => std::io::Cursor::new(encoded),
+                    None => cursor,
+                };
                 let result = tokio::io::copy(&mut input, &mut output).await;
 
                 if let Err(err) = output.shutdown().await {
@@ -352,6 +985,7 @@ where
                             TransferSpeed(bytes as f64 / duration.as_secs_f64()),
                         );
                         metrics::inc_transferred(command.as_lower_str(), "success");
+                        metrics::inc_backend_bytes(command.as_lower_str(), "out", bytes);
                         if let Err(err) = tx.send(ControlChanMsg::DirectorySuccessfullyListed).await {
                             slog::error!(self.logger, "Could not notify control channel of error with {}: {:?}", command.as_str(), err);
                         }
@@ -386,23 +1020,45 @@ where
 
                 categorize_and_register_error(&self.logger, &err, command.as_lower_str());
 
-                if let Err(err) = tx.send(ControlChanMsg::StorageError(err)).await {
+                if let Err(err) = tx.send(ControlChanMsg::StorageError(err.with_context(command.as_str(), &path))).await {
                     slog::error!(self.logger, "Could not notify control channel of error with {}: {:?}", command.as_str(), err);
                 }
             }
         }
     }
 
+    // Sets up the stream that data is sent to the client on: encrypted if TLS is used, and compressed
+    // with the given level in MODE Z. Shutting it down finishes the compressed stream.
     #[tracing_attributes::instrument]
-    async fn writer(socket: TcpStream, ftps_mode: FtpsConfig, command: &'static str) -> Box<dyn AsyncWrite + Send + Unpin + Sync> {
+    async fn writer(
+        socket: StallGuard<TcpStream>,
+        ftps_mode: FtpsConfig,
+        deflate: Option<u32>,
+        command: &'static str,
+        activity: Arc<SessionActivity>,
+    ) -> Box<dyn AsyncWrite + Send + Unpin + Sync> {
+        let writer = Self::plain_writer(socket, ftps_mode, command, activity).await;
+        match deflate {
+            Some(level) => Box::new(ZlibEncoder::with_quality(writer, Level::Precise(level as i32))),
+            None => writer,
+        }
+    }
+
+    async fn plain_writer(
+        socket: StallGuard<TcpStream>,
+        ftps_mode: FtpsConfig,
+        command: &'static str,
+        activity: Arc<SessionActivity>,
+    ) -> Box<dyn AsyncWrite + Send + Unpin + Sync> {
         match ftps_mode {
-            FtpsConfig::Off => Box::new(MeasuringWriter::new(socket, command)) as Box<dyn AsyncWrite + Send + Unpin + Sync>,
+            FtpsConfig::Off => Box::new(MeasuringWriter::new(socket, command, activity)) as Box<dyn AsyncWrite + Send + Unpin + Sync>,
             FtpsConfig::Building { .. } => panic!("Illegal state"),
-            FtpsConfig::On { tls_config } => {
+            #[cfg(feature = "ftps")]
+            FtpsConfig::On { tls_config, .. } => {
                 let io = async move {
                     let acceptor: TlsAcceptor = tls_config.into();
                     let tls_stream = acceptor.accept(socket).await.unwrap();
-                    MeasuringWriter::new(tls_stream, command)
+                    MeasuringWriter::new(tls_stream, command, activity)
                 }
                 .await;
                 Box::new(io) as Box<dyn AsyncWrite + Send + Unpin + Sync>
@@ -410,16 +1066,38 @@ where
         }
     }
 
+    // Sets up the stream that data is received from the client on, decrypting and decompressing it
+    // as needed.
     #[tracing_attributes::instrument]
-    async fn reader(socket: TcpStream, ftps_mode: FtpsConfig, command: &'static str) -> Box<dyn AsyncRead + Send + Unpin + Sync> {
+    async fn reader(
+        socket: StallGuard<TcpStream>,
+        ftps_mode: FtpsConfig,
+        deflate: Option<u32>,
+        command: &'static str,
+        activity: Arc<SessionActivity>,
+    ) -> Box<dyn AsyncRead + Send + Unpin + Sync> {
+        let reader = Self::plain_reader(socket, ftps_mode, command, activity).await;
+        match deflate {
+            Some(_) => Box::new(ZlibDecoder::new(BufReader::new(reader))),
+            None => reader,
+        }
+    }
+
+    async fn plain_reader(
+        socket: StallGuard<TcpStream>,
+        ftps_mode: FtpsConfig,
+        command: &'static str,
+        activity: Arc<SessionActivity>,
+    ) -> Box<dyn AsyncRead + Send + Unpin + Sync> {
         match ftps_mode {
-            FtpsConfig::Off => Box::new(MeasuringReader::new(socket, command)) as Box<dyn AsyncRead + Send + Unpin + Sync>,
+            FtpsConfig::Off => Box::new(MeasuringReader::new(socket, command, activity)) as Box<dyn AsyncRead + Send + Unpin + Sync>,
             FtpsConfig::Building { .. } => panic!("Illegal state"),
-            FtpsConfig::On { tls_config } => {
+            #[cfg(feature = "ftps")]
+            FtpsConfig::On { tls_config, .. } => {
                 let io = async move {
                     let acceptor: TlsAcceptor = tls_config.into();
                     let tls_stream = acceptor.accept(socket).await.unwrap();
-                    MeasuringReader::new(tls_stream, command)
+                    MeasuringReader::new(tls_stream, command, activity)
                 }
                 .await;
                 Box::new(io) as Box<dyn AsyncRead + Send + Unpin + Sync>
@@ -427,15 +1105,98 @@ where
         }
     }
 
-    fn resolve_path(&self, path: Option<String>) -> PathBuf {
-        match path {
-            Some(path) => {
-                if path == "." {
-                    self.cwd.clone()
-                } else {
-                    self.cwd.join(path)
+    // Lists the given directory and only keeps the entries whose file name matches the pattern,
+    // formatted in the same way as list_fmt and nlst do.
+    async fn list_matching(
+        storage: &Storage,
+        user: &User,
+        path: PathBuf,
+        pattern: &glob::Pattern,
+        command: ListCommand,
+    ) -> Result<std::io::Cursor<Vec<u8>>, Error> {
+        let list = storage.list(user, path).await.map_err(|e| match command {
+            ListCommand::List => e,
+            ListCommand::Nlst => Error::new(ErrorKind::PermanentDirectoryNotAvailable, e),
+        })?;
+
+        let buffer = list
+            .iter()
+            .filter_map(|fi| {
+                let name = encoding::path_to_text(fi.path.file_name()?);
+                if !pattern.matches(&name) {
+                    return None;
                 }
+                Some(match command {
+                    ListCommand::List => format!("{}\r\n", fi),
+                    ListCommand::Nlst => format!("{}\r\n", name),
+                })
+            })
+            .collect::<String>();
+
+        Ok(std::io::Cursor::new(buffer.into_bytes()))
+    }
+
+    // Lists the tree under the directory for LIST -R and NLST -R, breadth first, descending at most
+    // max_depth levels. NLST gives the path of every entry relative to the directory, LIST gives a
+    // section per directory like `ls -R` does. Fails if the tree holds more than max_entries entries.
+    async fn list_recursive(
+        storage: &Storage,
+        user: &User,
+        path: PathBuf,
+        command: ListCommand,
+        max_depth: usize,
+        max_entries: usize,
+    ) -> Result<std::io::Cursor<Vec<u8>>, Error> {
+        let mut buffer = String::new();
+        let mut entries = 0;
+        let mut pending = VecDeque::from([(PathBuf::new(), 0)]);
+        while let Some((relative, depth)) = pending.pop_front() {
+            let list = storage.list(user, path.join(&relative)).await.map_err(|e| match command {
+                ListCommand::List => e,
+                ListCommand::Nlst => Error::new(ErrorKind::PermanentDirectoryNotAvailable, e),
+            })?;
+            entries += list.len();
+            if entries > max_entries {
+                return Err(Error::new(
+                    ErrorKind::LocalError,
+                    format!("Recursive listing holds more than {} entries", max_entries),
+                ));
+            }
+            if let ListCommand::List = command {
+                buffer.push_str(&format!("{}:\r\n", encoding::path_to_text(Path::new(".").join(&relative).as_os_str())));
             }
+            for fi in list {
+                let Some(name) = fi.path.file_name() else { continue };
+                let entry = relative.join(name);
+                match command {
+                    ListCommand::List => buffer.push_str(&format!("{}\r\n", fi)),
+                    ListCommand::Nlst => buffer.push_str(&format!("{}\r\n", encoding::path_to_text(entry.as_os_str()))),
+                }
+                if fi.metadata.is_dir() && depth < max_depth {
+                    pending.push_back((entry, depth + 1));
+                }
+            }
+            if let ListCommand::List = command {
+                buffer.push_str("\r\n");
+            }
+        }
+
+        Ok(std::io::Cursor::new(buffer.into_bytes()))
+    }
+
+    // Resolves the path of a LIST or NLST command. If its last component contains wildcards
+    // (e.g. `LIST *.csv`) the parent directory is returned together with the compiled pattern.
+    // Patterns exceeding the limits have already been refused on the control channel.
+    fn resolve_list_path(&self, path: Option<String>) -> (PathBuf, Option<glob::Pattern>) {
+        match path.as_deref().map(glob::compile_path) {
+            Some(Ok(Some((dir, pattern)))) => (self.resolve_path(dir.map(String::from)), Some(pattern)),
+            _ => (self.resolve_path(path), None),
+        }
+    }
+
+    fn resolve_path(&self, path: Option<String>) -> PathBuf {
+        match path {
+            Some(path) => path::resolve(&self.cwd, path),
             None => self.cwd.clone(),
         }
     }
@@ -458,7 +1219,7 @@ where
     // We introduce a block scope here to keep the lock on the session minimal. We basically copy the needed info
     // out and then unlock.
 
-    let command_executor = {
+    let (command_executor, data_runtime) = {
         let mut session = session_arc.lock().await;
 
         match socket.peer_addr() {
@@ -490,8 +1251,22 @@ where
             }
         }
 
+        if let Err(err) = session.data_socket_options.apply(&socket) {
+            slog::warn!(logger, "Could not set the socket options of the data connection: {}", err);
+        }
+
+        if let Some(ref runtime) = session.data_runtime {
+            socket = match register_with(socket, runtime) {
+                Ok(socket) => socket,
+                Err(err) => {
+                    slog::error!(logger, "Couldn't move the data connection to the data runtime: {:?}", err);
+                    return;
+                }
+            };
+        }
+
         let username = session.username.as_ref().cloned().unwrap_or_else(|| String::from("unknown"));
-        let logger = logger.new(slog::o!("username" => username));
+        let logger = logger.new(slog::o!("username" => session.redaction.user(&username)));
         let control_msg_tx: Sender<ControlChanMsg> = match session.control_msg_tx {
             Some(ref tx) => tx.clone(),
             None => {
@@ -513,10 +1288,10 @@ where
                 return;
             }
         };
-        let ftps_mode = if session.data_tls { session.ftps_config.clone() } else { FtpsConfig::Off };
+        let ftps_mode = if session.data_tls() { session.ftps_config.clone() } else { FtpsConfig::Off };
         let command_executor = DataCommandExecutor {
             user: session.user.clone(),
-            socket,
+            socket: StallGuard::new(socket, session.data_stall_timeout),
             control_msg_tx,
             storage: Arc::clone(&session.storage),
             cwd: session.cwd.clone(),
@@ -524,16 +1299,49 @@ where
             logger,
             data_abort_rx: Some(data_abort_rx),
             data_cmd_rx: Some(data_cmd_rx),
+            charset: session.charset.clone(),
+            partial_uploads: session.partial_uploads,
+            upload_conflicts: session.upload_conflicts.clone(),
+            upload_checksum: session.upload_checksum,
+            upload_hook: session.upload_hook.clone(),
+            upload_scanner: session.upload_scanner.clone(),
+            transfer_log: session.transfer_log.clone(),
+            session_cache: session.session_cache.clone(),
+            progress_events: session.progress_events.clone(),
+            file_attributes: session.file_attributes,
+            username: session.username.clone().unwrap_or_else(|| "unknown".to_string()),
+            trace_id: session.trace_id,
+            request_id: String::new(),
+            deflate: match session.mode_z {
+                ModeZ::Enabled { level } if session.deflate => Some(level),
+                _ => None,
+            },
+            ascii: session.ascii,
+            activity: session.activity.clone(),
+            recursive_listing: session.recursive_listing,
+            dos_listing: session.quirks.dos_listing,
+            redaction: session.redaction,
         };
 
         // The control channel need to know if the data channel is busy so that it doesn't time out
         // while the session is still in progress.
         session.data_busy = true;
 
-        command_executor
+        (command_executor, session.data_runtime.clone())
     };
 
-    tokio::spawn(command_executor.execute(session_arc));
+    match data_runtime {
+        Some(runtime) => runtime.spawn(command_executor.execute(session_arc)),
+        None => tokio::spawn(command_executor.execute(session_arc)),
+    };
+}
+
+// Registers the socket with the I/O driver of the given runtime, so that the transfer doesn't wake
+// the runtime of the control channel.
+fn register_with(socket: TcpStream, runtime: &tokio::runtime::Handle) -> std::io::Result<TcpStream> {
+    let socket = socket.into_std()?;
+    let _guard = runtime.enter();
+    TcpStream::from_std(socket)
 }
 
 use std::time::Duration;
@@ -640,7 +1448,15 @@ fn categorize_and_register_error(logger: &slog::Logger, err: &Error, command: &'
     }
 }
 
-#[derive(Debug)]
+// Tells if the options of a LIST or NLST command ask for a recursive listing, like -R or -lR.
+fn recursive(options: &Option<String>) -> bool {
+    options
+        .iter()
+        .flat_map(|options| options.split(' '))
+        .any(|option| option.starts_with('-') && !option.starts_with("--") && option.contains('R'))
+}
+
+#[derive(Debug, Clone, Copy)]
 enum ListCommand {
     List,
     Nlst,
@@ -660,3 +1476,130 @@ impl ListCommand {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tokio::io::AsyncReadExt;
+
+    #[test]
+    fn detects_recursive_listings() {
+        assert!(recursive(&Some("-R".to_string())));
+        assert!(recursive(&Some("-la -R".to_string())));
+        assert!(recursive(&Some("-lR".to_string())));
+        assert!(!recursive(&Some("-la".to_string())));
+        assert!(!recursive(&Some("--Recursive".to_string())));
+        assert!(!recursive(&None));
+    }
+
+    #[tokio::test]
+    async fn stall_guard_times_out_without_progress() {
+        let (mut client, server) = tokio::io::duplex(64);
+        let mut guard = StallGuard::new(server, Some(Duration::from_millis(50)));
+        let mut buf = [0u8; 4];
+
+        client.write_all(b"data").await.unwrap();
+        assert_eq!(guard.read(&mut buf).await.unwrap(), 4);
+
+        let err = guard.read(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    }
+
+    #[tokio::test]
+    async fn moves_data_connections_to_another_runtime() {
+        let data_runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("data")
+            .enable_all()
+            .build()
+            .unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+
+        let mut server = register_with(server, data_runtime.handle()).unwrap();
+        let sent = data_runtime.spawn(async move {
+            server.write_all(b"data").await.unwrap();
+            std::thread::current().name().map(String::from)
+        });
+        let mut buf = [0u8; 4];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"data");
+        assert_eq!(sent.await.unwrap().as_deref(), Some("data"));
+        data_runtime.shutdown_background();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn send_file_sends_from_the_start_position() {
+        let path = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::write(&path, b"hello world").unwrap();
+        let file = std::fs::File::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        let socket = StallGuard::new(server, Some(Duration::from_secs(5)));
+        let activity = SessionActivity::new("127.0.0.1:4321".parse().unwrap());
+        activity.set_transfer(Some(TransferInfo {
+            command: "RETR".to_string(),
+            path: "hello.txt".to_string(),
+            bytes: 0,
+            started: SystemTime::now(),
+        }));
+
+        assert_eq!(send_file(&socket, file, 6, &activity).await.unwrap(), 5);
+        assert_eq!(activity.transfer().unwrap().bytes, 5);
+        drop(socket);
+        let mut received = Vec::new();
+        client.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, b"world");
+    }
+
+    #[tokio::test]
+    async fn checksum_reader_hashes_what_passes_through() {
+        let hasher = Arc::new(std::sync::Mutex::new(Md5::new()));
+        let mut reader = ChecksumReader {
+            reader: &b"hello"[..],
+            hasher: Some(hasher.clone()),
+        };
+        let mut content = Vec::new();
+        reader.read_to_end(&mut content).await.unwrap();
+
+        assert_eq!(content, b"hello");
+        assert_eq!(format!("{:x}", hasher.lock().unwrap().clone().finalize()), "5d41402abc4b2a76b9719d911017c592");
+    }
+
+    #[derive(Debug)]
+    struct RejectsVirus;
+
+    #[async_trait::async_trait]
+    impl UploadScanner for RejectsVirus {
+        async fn scan(&self, _path: String, mut data: Receiver<Bytes>) -> Result<(), UploadRejection> {
+            let mut content = Vec::new();
+            while let Some(chunk) = data.recv().await {
+                content.extend(chunk);
+            }
+            match content.windows(5).any(|window| window == b"virus") {
+                true => Err(UploadRejection::new("Virus found")),
+                false => Ok(()),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn scanning_reader_fails_rejected_uploads_at_the_end() {
+        let mut reader = ScanningReader::new(&b"harmless"[..], Arc::new(RejectsVirus), "/a.txt".to_string());
+        let mut content = Vec::new();
+        reader.read_to_end(&mut content).await.unwrap();
+        assert_eq!(content, b"harmless");
+        assert_eq!(*reader.rejection.lock().unwrap(), None);
+
+        let mut reader = ScanningReader::new(&b"a virus"[..], Arc::new(RejectsVirus), "/b.txt".to_string());
+        let err = reader.read_to_end(&mut Vec::new()).await.unwrap_err();
+        assert_eq!(err.to_string(), "Virus found");
+        assert_eq!(*reader.rejection.lock().unwrap(), Some("Virus found".to_string()));
+    }
+}
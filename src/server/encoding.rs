@@ -0,0 +1,280 @@
+//! Decoding and encoding of the text exchanged with clients, according to the
+//! [`Encoding`](crate::options::Encoding) option.
+//!
+//! Everything inside libunftp, including the paths handed to the storage back-end, is Unicode.
+//! Lines received on the control channel are converted to UTF-8 before being parsed and replies
+//! and directory listings are converted back to the character set of the client before being
+//! sent. For clients using Latin-1 this means file names make the round trip unchanged.
+//!
+//! Bytes of paths that aren't valid UTF-8, sent by a client while UTF-8 is in use or found in a
+//! name on the storage back-end, are carried through libunftp as the characters U+EF80 to U+EFFF of
+//! the private use area, one for each of the bytes 0x80 to 0xFF. Names that contain these characters
+//! themselves carry the bytes of their UTF-8 encoding the same way, so that they can't be mistaken
+//! for raw bytes. The raw bytes are put back before a path reaches the storage back-end and before
+//! text reaches the client, so that all names make the round trip unchanged. On platforms other
+//! than Unix the back-end only gets the bytes back if they make up valid UTF-8, since paths have to
+//! be Unicode there.
+
+use crate::options::Encoding;
+use std::{
+    borrow::Cow,
+    ffi::OsStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+// The raw byte characters are this plus the byte.
+const RAW_BYTES: u32 = 0xEF00;
+
+/// The character set used in a session. Clones share their state so that the control channel
+/// codec, the data channel and the `OPTS UTF8` command all agree on the character set in use.
+#[derive(Debug, Clone)]
+pub struct Charset {
+    encoding: Encoding,
+    // Whether UTF-8 is currently in use. It only ever changes in Encoding::Auto mode.
+    utf8: Arc<AtomicBool>,
+}
+
+impl Charset {
+    pub fn new(encoding: Encoding) -> Self {
+        Charset {
+            encoding,
+            utf8: Arc::new(AtomicBool::new(encoding != Encoding::Latin1)),
+        }
+    }
+
+    /// Tells whether UTF-8 can be used at all i.e. whether it should be advertised in the FEAT
+    /// response.
+    pub fn supports_utf8(&self) -> bool {
+        self.encoding != Encoding::Latin1
+    }
+
+    /// Tells whether UTF-8 is in use at the moment.
+    pub fn is_utf8(&self) -> bool {
+        self.utf8.load(Ordering::Relaxed)
+    }
+
+    /// Handles `OPTS UTF8 ON|OFF`, returning false if the requested mode is not available.
+    pub fn set_utf8(&self, on: bool) -> bool {
+        match (self.encoding, on) {
+            (Encoding::Utf8, true) | (Encoding::Latin1, false) => true,
+            (Encoding::Utf8, false) | (Encoding::Latin1, true) => false,
+            (Encoding::Auto, on) => {
+                self.utf8.store(on, Ordering::Relaxed);
+                true
+            }
+        }
+    }
+
+    /// Converts a line received from the client to UTF-8. In auto mode the session switches over to
+    /// Latin-1 as soon as a line is received that is not valid UTF-8.
+    pub fn decode<'a>(&self, line: &'a [u8]) -> Cow<'a, [u8]> {
+        if line.is_ascii() {
+            return Cow::Borrowed(line);
+        }
+        match self.encoding {
+            Encoding::Utf8 => Cow::Borrowed(line),
+            Encoding::Latin1 => Cow::Owned(latin1_to_utf8(line)),
+            Encoding::Auto => {
+                if self.is_utf8() && std::str::from_utf8(line).is_ok() {
+                    Cow::Borrowed(line)
+                } else {
+                    self.utf8.store(false, Ordering::Relaxed);
+                    Cow::Owned(latin1_to_utf8(line))
+                }
+            }
+        }
+    }
+
+    /// Converts text that is sent to the client to the character set in use. Characters that
+    /// cannot be represented in Latin-1 are replaced by a question mark and raw byte characters
+    /// become the bytes they stand for.
+    pub fn encode<'a>(&self, text: &'a str) -> Cow<'a, [u8]> {
+        let utf8 = self.is_utf8();
+        if text.is_ascii() || (utf8 && !text.chars().any(|c| raw_byte(c).is_some())) {
+            return Cow::Borrowed(text.as_bytes());
+        }
+        let mut encoded = Vec::with_capacity(text.len());
+        for c in text.chars() {
+            match raw_byte(c) {
+                Some(byte) => encoded.push(byte),
+                None if utf8 => encoded.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+                None => encoded.push(u8::try_from(c).unwrap_or(b'?')),
+            }
+        }
+        Cow::Owned(encoded)
+    }
+}
+
+impl Default for Charset {
+    fn default() -> Self {
+        Charset::new(Encoding::default())
+    }
+}
+
+fn latin1_to_utf8(bytes: &[u8]) -> Vec<u8> {
+    bytes.iter().map(|b| char::from(*b)).collect::<String>().into_bytes()
+}
+
+// The byte a raw byte character stands for.
+fn raw_byte(c: char) -> Option<u8> {
+    match u32::from(c) {
+        code @ 0xEF80..=0xEFFF => u8::try_from(code - RAW_BYTES).ok(),
+        _ => None,
+    }
+}
+
+/// Converts the bytes of a path to text, turning the bytes that aren't valid UTF-8 into raw byte
+/// characters.
+pub(crate) fn decode_path(mut bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len());
+    loop {
+        match std::str::from_utf8(bytes) {
+            Ok(valid) => {
+                push_escaped(&mut text, valid);
+                return text;
+            }
+            Err(err) => {
+                let (valid, rest) = bytes.split_at(err.valid_up_to());
+                push_escaped(&mut text, std::str::from_utf8(valid).unwrap_or_default());
+                let invalid = err.error_len().unwrap_or(rest.len());
+                push_raw(&mut text, &rest[..invalid]);
+                bytes = &rest[invalid..];
+            }
+        }
+    }
+}
+
+// Appends raw byte characters for the bytes. Bytes below 0x80 are always valid UTF-8 and never
+// passed here.
+fn push_raw(text: &mut String, bytes: &[u8]) {
+    text.extend(bytes.iter().filter_map(|byte| char::from_u32(RAW_BYTES + u32::from(*byte))));
+}
+
+// Appends valid text, escaping the characters that would be taken for raw byte characters.
+fn push_escaped(text: &mut String, valid: &str) {
+    for c in valid.chars() {
+        match raw_byte(c) {
+            Some(_) => push_raw(text, c.encode_utf8(&mut [0; 4]).as_bytes()),
+            None => text.push(c),
+        }
+    }
+}
+
+fn has_raw_bytes(text: &str) -> bool {
+    text.chars().any(|c| raw_byte(c).is_some())
+}
+
+/// Converts a path or name of the storage back-end to text for the client.
+pub(crate) fn path_to_text(path: &OsStr) -> Cow<'_, str> {
+    if let Some(text) = path.to_str().filter(|text| !has_raw_bytes(text)) {
+        return Cow::Borrowed(text);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Cow::Owned(decode_path(path.as_bytes()))
+    }
+    #[cfg(not(unix))]
+    Cow::Owned(decode_path(path.to_string_lossy().as_bytes()))
+}
+
+/// Turns the raw byte characters in a part of a path sent by the client back into bytes.
+pub(crate) fn text_to_path(path: &OsStr) -> Cow<'_, OsStr> {
+    let Some(text) = path.to_str().filter(|text| has_raw_bytes(text)) else {
+        return Cow::Borrowed(path);
+    };
+    let mut bytes = Vec::with_capacity(text.len());
+    for c in text.chars() {
+        match raw_byte(c) {
+            Some(byte) => bytes.push(byte),
+            None => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt;
+        Cow::Owned(std::ffi::OsString::from_vec(bytes))
+    }
+    #[cfg(not(unix))]
+    match String::from_utf8(bytes) {
+        Ok(text) => Cow::Owned(text.into()),
+        Err(_) => Cow::Borrowed(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const LATIN1_LINE: &[u8] = b"RETR caf\xe9.txt\r\n";
+
+    #[test]
+    fn utf8_passes_bytes_through() {
+        let charset = Charset::new(Encoding::Utf8);
+        assert_eq!(charset.decode(LATIN1_LINE), Cow::Borrowed(LATIN1_LINE));
+        assert_eq!(&*charset.encode("café"), "café".as_bytes());
+        assert!(!charset.set_utf8(false));
+    }
+
+    #[test]
+    fn latin1_round_trips() {
+        let charset = Charset::new(Encoding::Latin1);
+        let decoded = charset.decode(LATIN1_LINE);
+        assert_eq!(std::str::from_utf8(&decoded).unwrap(), "RETR café.txt\r\n");
+        assert_eq!(&*charset.encode("RETR café.txt\r\n"), LATIN1_LINE);
+        assert_eq!(&*charset.encode("€"), b"?");
+        assert!(!charset.set_utf8(true));
+    }
+
+    #[test]
+    fn auto_switches_to_latin1() {
+        let charset = Charset::new(Encoding::Auto);
+        let utf8_line = "RETR café.txt\r\n".as_bytes();
+        assert_eq!(charset.decode(utf8_line), Cow::Borrowed(utf8_line));
+        assert!(charset.is_utf8());
+
+        let decoded = charset.decode(LATIN1_LINE);
+        assert_eq!(std::str::from_utf8(&decoded).unwrap(), "RETR café.txt\r\n");
+        assert!(!charset.is_utf8());
+        assert_eq!(&*charset.encode("café"), b"caf\xe9");
+
+        assert!(charset.set_utf8(true));
+        assert!(charset.clone().is_utf8());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn raw_bytes_round_trip() {
+        use std::os::unix::ffi::OsStrExt;
+        let name = OsStr::from_bytes(b"caf\xe9.txt");
+
+        let text = decode_path(name.as_bytes());
+        assert_eq!(path_to_text(name), text);
+        assert_eq!(text_to_path(OsStr::new(&text)), name);
+        assert_eq!(&*Charset::new(Encoding::Utf8).encode(&text), b"caf\xe9.txt");
+        assert_eq!(&*Charset::new(Encoding::Latin1).encode(&text), b"caf\xe9.txt");
+        assert_eq!(text_to_path(OsStr::new("café.txt")), OsStr::new("café.txt"));
+    }
+
+    #[test]
+    fn decodes_invalid_bytes_anywhere() {
+        let text = decode_path(b"\xffa\xc3\xa9\xe9b\xe2\x82");
+        assert_eq!(text, "\u{efff}aé\u{efe9}b\u{efe2}\u{ef82}");
+        assert_eq!(&*Charset::new(Encoding::Utf8).encode(&text), b"\xffa\xc3\xa9\xe9b\xe2\x82");
+    }
+
+    #[test]
+    fn escapes_names_in_the_raw_byte_range() {
+        let name = "a\u{ef80}b";
+        // Sent by a client and found on the back-end alike.
+        let text = decode_path(name.as_bytes());
+        assert_eq!(text, "a\u{efee}\u{efbe}\u{ef80}b");
+        assert_eq!(path_to_text(OsStr::new(name)), text);
+        assert_eq!(text_to_path(OsStr::new(&text)), OsStr::new(name));
+        assert_eq!(&*Charset::new(Encoding::Utf8).encode(&text), name.as_bytes());
+    }
+}
@@ -1,31 +1,69 @@
+#[cfg(feature = "admin")]
+pub mod admin;
 mod chosen;
+#[cfg(feature = "config")]
+pub mod config;
 pub mod error;
+#[cfg(feature = "geoip")]
+mod geoip;
+pub mod handle;
+pub mod health;
 mod listen;
+#[cfg(feature = "proxy-protocol")]
 mod listen_proxied;
+pub mod middleware;
 pub mod options;
+mod privileges;
+pub mod site;
 
 use super::{
     controlchan,
     failed_logins::FailedLoginsCache,
-    ftpserver::{error::ServerError, error::ShutdownError, options::FtpsRequired, options::SiteMd5},
+    ftpserver::{error::ServerError, error::ShutdownError, options::FtpsRequired, options::SiteMd5, options::TlsFirst},
     shutdown,
     tls::FtpsConfig,
 };
 use crate::options::ActivePassiveMode;
+#[cfg(feature = "proxy-protocol")]
+use crate::server::proxy_protocol::ProxyProtocolSwitchboard;
 use crate::{
     auth::{anonymous::AnonymousAuthenticator, Authenticator, UserDetail},
-    notification::{nop::NopListener, DataListener, PresenceListener},
-    options::{FailedLoginsPolicy, FtpsClientAuth, TlsFlags},
-    server::shutdown::Notifier,
-    server::{
-        proxy_protocol::{ProxyMode, ProxyProtocolSwitchboard},
-        tls,
+    notification::{nop::NopListener, DataListener, PresenceListener, TransferLogListener, UploadHook, UploadScanner},
+    options::{
+        AccessMode, CachedConnectionPolicy, ClientQuirks, CommandLimits, CommandPolicy, ConnectionPolicy, DataConnectionDelegate, DataConnectionSource,
+        Dotfiles, Encoding, EventDelivery, FailedLoginsPolicy, FtpsClientAuth, GreetingFn, ListingOrder, MessageCatalog, ModeZ, PartialUploads,
+        PassivePortMapper, RecursiveListing, Redaction, SessionCache, SocketOptions, TlsFlags, UploadChecksum, UploadConflictPolicy, UploadConflicts,
+        UploadLocks,
     },
-    storage::{Metadata, StorageBackend},
+    server::sessions::SessionRegistry,
+    server::shutdown::Notifier,
+    server::{proxy_protocol::ProxyMode, tls},
+    storage::{DefaultPathFilter, Metadata, PathFilter, StorageBackend},
 };
-use options::{PassiveHost, DEFAULT_GREETING, DEFAULT_IDLE_SESSION_TIMEOUT_SECS};
+use handle::{ConfigHandle, RuntimeOptions, ServerHandle};
+use health::HealthCheck;
+use middleware::Middleware;
+#[cfg(unix)]
+use options::ConnectionHelper;
+use options::{ConnectionInfo, ListenerOptions, PassiveHost, DEFAULT_GREETING, DEFAULT_IDLE_SESSION_TIMEOUT_SECS};
+use privileges::Privileges;
+use site::{SiteCommandContext, SiteCommandRegistry, SiteReply};
 use slog::*;
-use std::{ffi::OsString, fmt::Debug, future::Future, net::SocketAddr, ops::Range, path::PathBuf, pin::Pin, sync::Arc, time::Duration};
+use std::{
+    ffi::OsString,
+    fmt::Debug,
+    future::Future,
+    net::{Ipv4Addr, SocketAddr},
+    ops::Range,
+    path::PathBuf,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    time::Duration,
+};
+use tokio::runtime::Handle;
 
 /// An instance of an FTP(S) server. It aggregates an [`Authenticator`](crate::auth::Authenticator)
 /// implementation that will be used for authentication, and a [`StorageBackend`](crate::storage::StorageBackend)
@@ -56,25 +94,68 @@ where
 {
     storage: Arc<dyn (Fn() -> Storage) + Send + Sync>,
     greeting: &'static str,
+    encoding: Encoding,
+    data_connection_source: DataConnectionSource,
+    control_socket_options: SocketOptions,
+    data_socket_options: SocketOptions,
+    data_bind_address: Option<Ipv4Addr>,
+    passive_port_mapper: Option<Arc<dyn PassivePortMapper>>,
+    data_runtime: Option<Handle>,
+    recursive_listing: RecursiveListing,
+    listing_order: ListingOrder,
+    listing_dot_entries: bool,
+    client_quirks: ClientQuirks,
+    mdtm_setter: bool,
+    redaction: Redaction,
+    middleware: Arc<Vec<Arc<dyn Middleware>>>,
+    site_commands: SiteCommandRegistry<Storage, User>,
+    login_message: Option<String>,
+    greeting_provider: Option<GreetingFn>,
+    message_catalog: Option<Arc<dyn MessageCatalog>>,
+    access_mode: AccessMode,
+    command_policy: CommandPolicy,
+    mode_z: ModeZ,
+    upload_scanner: Option<Arc<dyn UploadScanner>>,
+    upload_hook: Option<Arc<dyn UploadHook>>,
+    transfer_log: Option<Arc<dyn TransferLogListener>>,
+    session_cache: Option<Arc<dyn SessionCache>>,
+    progress_interval: Option<Duration>,
+    event_delivery: Option<EventDelivery>,
+    upload_checksum: UploadChecksum,
+    atomic_uploads: bool,
+    partial_uploads: PartialUploads,
+    upload_conflicts: UploadConflicts,
+    dotfiles: Dotfiles,
     authenticator: Arc<dyn Authenticator<User>>,
     data_listener: Arc<dyn DataListener>,
     presence_listener: Arc<dyn PresenceListener>,
+    path_filter: Arc<dyn PathFilter>,
     passive_ports: Range<u16>,
     passive_host: PassiveHost,
     collect_metrics: bool,
     ftps_mode: FtpsConfig,
     ftps_required_control_chan: FtpsRequired,
     ftps_required_data_chan: FtpsRequired,
+    ftps_tls_first: TlsFirst,
+    ftps_handshake_timeout: Duration,
     idle_session_timeout: std::time::Duration,
+    data_stall_timeout: Option<Duration>,
+    max_session_duration: Option<Duration>,
     proxy_protocol_mode: ProxyMode,
     logger: slog::Logger,
     site_md5: SiteMd5,
     shutdown: Pin<Box<dyn Future<Output = options::Shutdown> + Send + Sync>>,
     failed_logins_policy: Option<FailedLoginsPolicy>,
+    command_limits: CommandLimits,
+    max_unauthenticated_sessions: Option<usize>,
+    connection_policy: Option<Arc<CachedConnectionPolicy>>,
     active_passive_mode: ActivePassiveMode,
-    connection_helper: Option<OsString>,
-    connection_helper_args: Vec<OsString>,
+    connection_delegate: Option<Arc<dyn DataConnectionDelegate>>,
+    privileges: Privileges,
     binder: Arc<std::sync::Mutex<Option<Box<dyn crate::options::Binder>>>>,
+    listening: Arc<AtomicBool>,
+    sessions: Arc<SessionRegistry>,
+    runtime_options: Arc<RwLock<RuntimeOptions>>,
 }
 
 /// Used to create [`Server`]s.  
@@ -85,9 +166,42 @@ where
 {
     storage: Arc<dyn (Fn() -> Storage) + Send + Sync>,
     greeting: &'static str,
+    encoding: Encoding,
+    data_connection_source: DataConnectionSource,
+    control_socket_options: SocketOptions,
+    data_socket_options: SocketOptions,
+    data_bind_address: Option<Ipv4Addr>,
+    passive_port_mapper: Option<Arc<dyn PassivePortMapper>>,
+    data_runtime: Option<Handle>,
+    recursive_listing: RecursiveListing,
+    listing_order: ListingOrder,
+    listing_dot_entries: bool,
+    client_quirks: ClientQuirks,
+    mdtm_setter: bool,
+    redaction: Redaction,
+    middleware: Arc<Vec<Arc<dyn Middleware>>>,
+    site_commands: SiteCommandRegistry<Storage, User>,
+    login_message: Option<String>,
+    greeting_provider: Option<GreetingFn>,
+    message_catalog: Option<Arc<dyn MessageCatalog>>,
+    access_mode: AccessMode,
+    command_policy: CommandPolicy,
+    mode_z: ModeZ,
+    upload_scanner: Option<Arc<dyn UploadScanner>>,
+    upload_hook: Option<Arc<dyn UploadHook>>,
+    transfer_log: Option<Arc<dyn TransferLogListener>>,
+    session_cache: Option<Arc<dyn SessionCache>>,
+    progress_interval: Option<Duration>,
+    event_delivery: Option<EventDelivery>,
+    upload_checksum: UploadChecksum,
+    atomic_uploads: bool,
+    partial_uploads: PartialUploads,
+    upload_conflicts: UploadConflicts,
+    dotfiles: Dotfiles,
     authenticator: Arc<dyn Authenticator<User>>,
     data_listener: Arc<dyn DataListener>,
     presence_listener: Arc<dyn PresenceListener>,
+    path_filter: Arc<dyn PathFilter>,
     passive_ports: Range<u16>,
     passive_host: PassiveHost,
     collect_metrics: bool,
@@ -97,15 +211,24 @@ where
     ftps_tls_flags: TlsFlags,
     ftps_client_auth: FtpsClientAuth,
     ftps_trust_store: PathBuf,
+    ftps_tls_settings: tls::TlsSettings,
+    ftps_tls_first: TlsFirst,
+    ftps_handshake_timeout: Duration,
     idle_session_timeout: std::time::Duration,
+    data_stall_timeout: Option<Duration>,
+    max_session_duration: Option<Duration>,
     proxy_protocol_mode: ProxyMode,
     logger: slog::Logger,
     site_md5: SiteMd5,
     shutdown: Pin<Box<dyn Future<Output = options::Shutdown> + Send + Sync>>,
     failed_logins_policy: Option<FailedLoginsPolicy>,
+    max_connections: Option<usize>,
+    command_limits: CommandLimits,
+    max_unauthenticated_sessions: Option<usize>,
+    connection_policy: Option<Arc<CachedConnectionPolicy>>,
     active_passive_mode: ActivePassiveMode,
-    connection_helper: Option<OsString>,
-    connection_helper_args: Vec<OsString>,
+    connection_delegate: Option<Arc<dyn DataConnectionDelegate>>,
+    privileges: Privileges,
     binder: Option<Box<dyn crate::options::Binder>>,
 }
 
@@ -137,14 +260,49 @@ where
         ServerBuilder {
             storage: Arc::from(sbe_generator),
             greeting: DEFAULT_GREETING,
+            encoding: Encoding::default(),
+            data_connection_source: DataConnectionSource::default(),
+            control_socket_options: SocketOptions::default(),
+            data_socket_options: SocketOptions::default(),
+            data_bind_address: None,
+            passive_port_mapper: None,
+            data_runtime: None,
+            recursive_listing: RecursiveListing::default(),
+            listing_order: ListingOrder::default(),
+            listing_dot_entries: false,
+            client_quirks: ClientQuirks::default(),
+            mdtm_setter: true,
+            redaction: Redaction::None,
+            middleware: Arc::new(Vec::new()),
+            site_commands: SiteCommandRegistry::new(),
+            login_message: None,
+            greeting_provider: None,
+            message_catalog: None,
+            access_mode: AccessMode::default(),
+            command_policy: CommandPolicy::default(),
+            mode_z: ModeZ::default(),
+            upload_scanner: None,
+            upload_hook: None,
+            transfer_log: None,
+            session_cache: None,
+            progress_interval: None,
+            event_delivery: None,
+            upload_checksum: UploadChecksum::default(),
+            atomic_uploads: false,
+            partial_uploads: PartialUploads::default(),
+            upload_conflicts: UploadConflicts::default(),
+            dotfiles: Dotfiles::default(),
             authenticator,
             data_listener: Arc::new(NopListener {}),
             presence_listener: Arc::new(NopListener {}),
+            path_filter: Arc::new(DefaultPathFilter),
             passive_ports,
             passive_host: options::DEFAULT_PASSIVE_HOST,
             ftps_mode: FtpsConfig::Off,
             collect_metrics: false,
             idle_session_timeout: Duration::from_secs(DEFAULT_IDLE_SESSION_TIMEOUT_SECS),
+            data_stall_timeout: None,
+            max_session_duration: None,
             proxy_protocol_mode: ProxyMode::Off,
             logger: slog::Logger::root(slog_stdlog::StdLog {}.fuse(), slog::o!()),
             ftps_required_control_chan: options::DEFAULT_FTPS_REQUIRE,
@@ -152,12 +310,19 @@ where
             ftps_tls_flags: TlsFlags::default(),
             ftps_client_auth: FtpsClientAuth::default(),
             ftps_trust_store: options::DEFAULT_FTPS_TRUST_STORE.into(),
+            ftps_tls_settings: tls::TlsSettings::default(),
+            ftps_tls_first: TlsFirst::default(),
+            ftps_handshake_timeout: Duration::from_secs(10),
             site_md5: SiteMd5::default(),
             shutdown: Box::pin(futures_util::future::pending()),
             failed_logins_policy: None,
+            max_connections: None,
+            command_limits: CommandLimits::default(),
+            max_unauthenticated_sessions: None,
+            connection_policy: None,
             active_passive_mode: ActivePassiveMode::default(),
-            connection_helper: None,
-            connection_helper_args: Vec::new(),
+            connection_delegate: None,
+            privileges: Privileges::default(),
             binder: None,
         }
     }
@@ -208,157 +373,1107 @@ where
     pub fn build(self) -> std::result::Result<Server<Storage, User>, ServerError> {
         let ftps_mode = match self.ftps_mode {
             FtpsConfig::Off => FtpsConfig::Off,
-            FtpsConfig::Building { certs_file, key_file } => FtpsConfig::On {
-                tls_config: tls::new_config(certs_file, key_file, self.ftps_tls_flags, self.ftps_client_auth, self.ftps_trust_store.clone())?,
-            },
-            FtpsConfig::On { tls_config } => FtpsConfig::On { tls_config },
+            #[cfg(feature = "ftps")]
+            FtpsConfig::Building { certs_file, key_file } => {
+                let (tls_config, certificates) = tls::new_config(
+                    certs_file,
+                    key_file,
+                    self.ftps_tls_flags,
+                    self.ftps_client_auth,
+                    self.ftps_trust_store.clone(),
+                    &self.ftps_tls_settings,
+                )?;
+                FtpsConfig::On { tls_config, certificates }
+            }
+            #[cfg(feature = "ftps")]
+            ftps_mode @ FtpsConfig::On { .. } => ftps_mode,
+            #[cfg(not(feature = "ftps"))]
+            FtpsConfig::Building { .. } => return Err(tls::ConfigError::FtpsNotCompiled.into()),
         };
+        if self.ftps_tls_first.is_required() && matches!(ftps_mode, FtpsConfig::Off) {
+            return Err(tls::ConfigError::TlsFirstWithoutFtps.into());
+        }
+        if self.passive_ports.is_empty() {
+            return Err(error::EmptyPassivePorts(self.passive_ports).into());
+        }
         let binder = Arc::new(std::sync::Mutex::new(self.binder));
+        let runtime_options = Arc::new(RwLock::new(RuntimeOptions {
+            greeting: None,
+            idle_session_timeout: self.idle_session_timeout,
+            passive_host: self.passive_host.clone(),
+            passive_ports: self.passive_ports.clone(),
+            max_connections: self.max_connections,
+        }));
         Ok(Server {
             storage: self.storage,
             greeting: self.greeting,
+            encoding: self.encoding,
+            data_connection_source: self.data_connection_source,
+            control_socket_options: self.control_socket_options,
+            data_socket_options: self.data_socket_options,
+            data_bind_address: self.data_bind_address,
+            passive_port_mapper: self.passive_port_mapper,
+            data_runtime: self.data_runtime,
+            recursive_listing: self.recursive_listing,
+            listing_order: self.listing_order,
+            listing_dot_entries: self.listing_dot_entries,
+            client_quirks: self.client_quirks,
+            mdtm_setter: self.mdtm_setter,
+            redaction: self.redaction,
+            middleware: self.middleware,
+            site_commands: self.site_commands.with_builtins(self.site_md5),
+            login_message: self.login_message,
+            greeting_provider: self.greeting_provider,
+            message_catalog: self.message_catalog,
+            access_mode: self.access_mode,
+            command_policy: self.command_policy,
+            mode_z: self.mode_z,
+            upload_scanner: self.upload_scanner,
+            upload_hook: self.upload_hook,
+            transfer_log: self.transfer_log,
+            session_cache: self.session_cache,
+            progress_interval: self.progress_interval,
+            event_delivery: self.event_delivery,
+            upload_checksum: self.upload_checksum,
+            atomic_uploads: self.atomic_uploads,
+            partial_uploads: self.partial_uploads,
+            upload_conflicts: self.upload_conflicts,
+            dotfiles: self.dotfiles,
             authenticator: self.authenticator,
             data_listener: self.data_listener,
             presence_listener: self.presence_listener,
+            path_filter: self.path_filter,
             passive_ports: self.passive_ports,
             passive_host: self.passive_host,
             collect_metrics: self.collect_metrics,
             ftps_mode,
             ftps_required_control_chan: self.ftps_required_control_chan,
             ftps_required_data_chan: self.ftps_required_data_chan,
+            ftps_tls_first: self.ftps_tls_first,
+            ftps_handshake_timeout: self.ftps_handshake_timeout,
             idle_session_timeout: self.idle_session_timeout,
+            data_stall_timeout: self.data_stall_timeout,
+            max_session_duration: self.max_session_duration,
             proxy_protocol_mode: self.proxy_protocol_mode,
             logger: self.logger,
             site_md5: self.site_md5,
             shutdown: self.shutdown,
             failed_logins_policy: self.failed_logins_policy,
+            command_limits: self.command_limits,
+            max_unauthenticated_sessions: self.max_unauthenticated_sessions,
+            connection_policy: self.connection_policy,
             active_passive_mode: self.active_passive_mode,
-            connection_helper: self.connection_helper,
-            connection_helper_args: self.connection_helper_args,
+            connection_delegate: self.connection_delegate,
+            privileges: self.privileges,
             binder,
+            listening: Arc::new(AtomicBool::new(false)),
+            sessions: Arc::new(SessionRegistry::default()),
+            runtime_options,
         })
     }
 
-    /// Enables FTPS by configuring the path to the certificates file and the private key file. Both
-    /// should be in PEM format.
+    /// Sets the character set that clients use for path names. The default is UTF-8.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use libunftp::options::Encoding;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///              .encoding(Encoding::Auto)
+    ///              .build();
+    /// ```
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Makes uploads atomic for any storage back-end: files are stored under a temporary name and
+    /// only renamed to their final name once the upload completed, so that consumers never pick up
+    /// half-written files. See [AtomicUploads](crate::storage::AtomicUploads) for details. This is
+    /// off by default.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///              .atomic_uploads(true)
+    ///              .build();
+    /// ```
+    pub fn atomic_uploads(mut self, enabled: bool) -> Self {
+        self.atomic_uploads = enabled;
+        self
+    }
+
+    /// Sets what happens to the partially stored file when a client aborts an upload with `ABOR`.
+    /// By default it is kept so that the upload can be resumed. Either way the storage back-end
+    /// is told about the aborted upload through
+    /// [StorageBackend::abort_put](crate::storage::StorageBackend::abort_put).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use libunftp::options::PartialUploads;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///              .partial_uploads(PartialUploads::Delete)
+    ///              .build();
+    /// ```
+    pub fn partial_uploads(mut self, partial_uploads: PartialUploads) -> Self {
+        self.partial_uploads = partial_uploads;
+        self
+    }
+
+    /// Sets what happens when a session uploads to a path that another session is uploading to.
+    /// By default both uploads go ahead and the last one to complete wins. The paths are locked in
+    /// memory, see [upload_locks](Self::upload_locks) to lock them across servers.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use libunftp::options::UploadConflictPolicy;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///              .upload_conflict_policy(UploadConflictPolicy::Reject)
+    ///              .build();
+    /// ```
+    pub fn upload_conflict_policy(mut self, policy: UploadConflictPolicy) -> Self {
+        self.upload_conflicts.policy = policy;
+        self
+    }
+
+    /// Sets the [`UploadLocks`](crate::options::UploadLocks) that carry out the
+    /// [upload conflict policy](Self::upload_conflict_policy), for instance to lock paths in a
+    /// store that all instances of the server share. By default they are locked in memory.
+    pub fn upload_locks(mut self, locks: impl UploadLocks + 'static) -> Self {
+        self.upload_conflicts.locks = Arc::new(locks);
+        self
+    }
+
+    /// Sets whether files and directories whose name starts with a dot are visible to clients. When
+    /// hidden they are left out of directory listings and can't be accessed. The default is to show
+    /// them. The setting can be overridden per user with
+    /// [UserDetail::dotfiles](crate::auth::UserDetail::dotfiles).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use libunftp::options::Dotfiles;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///              .dotfiles(Dotfiles::Hide)
+    ///              .build();
+    /// ```
+    pub fn dotfiles(mut self, dotfiles: Dotfiles) -> Self {
+        self.dotfiles = dotfiles;
+        self
+    }
+
+    /// Sets which FTP commands clients may use. Commands that aren't allowed are answered with a
+    /// 502 reply before they reach the storage back-end. The default is to allow all commands. The
+    /// setting can be overridden per user with
+    /// [UserDetail::command_policy](crate::auth::UserDetail::command_policy).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use libunftp::options::CommandPolicy;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// // A drop box: files can be uploaded but not removed.
+    /// let server = Server::with_fs("/tmp")
+    ///              .command_policy(CommandPolicy::deny(["DELE", "RMD", "RNFR", "RNTO"]))
+    ///              .build();
+    /// ```
+    pub fn command_policy(mut self, policy: CommandPolicy) -> Self {
+        self.command_policy = policy;
+        self
+    }
+
+    /// Sets whether clients may read back what is stored. With
+    /// [AccessMode::UploadOnly](crate::options::AccessMode::UploadOnly) the server is a drop box:
+    /// clients can upload files and create directories but not download or list anything. The
+    /// default is [AccessMode::ReadWrite](crate::options::AccessMode::ReadWrite). The setting can
+    /// be overridden per user with [UserDetail::access_mode](crate::auth::UserDetail::access_mode).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use libunftp::options::AccessMode;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///              .access_mode(AccessMode::UploadOnly)
+    ///              .build();
+    /// ```
+    pub fn access_mode(mut self, access_mode: AccessMode) -> Self {
+        self.access_mode = access_mode;
+        self
+    }
+
+    /// Sets the catalog of translated reply messages that clients can switch to with the `LANG`
+    /// command (RFC 2640). The languages of the catalog are advertised in the `FEAT` reply.
+    /// Without a catalog, the default, replies are in English and `LANG` only accepts English.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use libunftp::options::MessageCatalog;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// #[derive(Debug)]
+    /// struct French;
+    ///
+    /// impl MessageCatalog for French {
+    ///     fn languages(&self) -> Vec<String> {
+    ///         vec!["fr".to_string()]
+    ///     }
+    ///
+    ///     fn translate(&self, _language: &str, _code: u32, message: &str) -> Option<String> {
+    ///         (message == "Successfully did nothing").then(|| "Rien fait, avec succès".to_string())
+    ///     }
+    /// }
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///              .message_catalog(French)
+    ///              .build();
+    /// ```
+    pub fn message_catalog<C: MessageCatalog + 'static>(mut self, catalog: C) -> Self {
+        self.message_catalog = Some(Arc::new(catalog));
+        self
+    }
+
+    /// Enables FTPS by configuring the path to the certificates file and the private key file. Both
+    /// should be in PEM format.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///              .ftps("/srv/unftp/server.certs", "/srv/unftp/server.key");
+    /// ```
+    pub fn ftps<P: Into<PathBuf>>(mut self, certs_file: P, key_file: P) -> Self {
+        self.ftps_mode = FtpsConfig::Building {
+            certs_file: certs_file.into(),
+            key_file: key_file.into(),
+        };
+        self
+    }
+
+    /// Allows switching on Mutual TLS. For this to work the trust anchors also needs to be set using
+    /// the [ftps_trust_store](crate::ServerBuilder::ftps_trust_store) method.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use unftp_sbe_fs::ServerExt;
+    /// use libunftp::options::FtpsClientAuth;
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///              .ftps("/srv/unftp/server.certs", "/srv/unftp/server.key")
+    ///              .ftps_client_auth(FtpsClientAuth::Require)
+    ///              .ftps_trust_store("/srv/unftp/trusted.pem");
+    /// ```
+    pub fn ftps_client_auth<C>(mut self, auth: C) -> Self
+    where
+        C: Into<FtpsClientAuth>,
+    {
+        self.ftps_client_auth = auth.into();
+        self
+    }
+
+    /// Configures whether client connections may use plaintext mode or not.
+    pub fn ftps_required<R>(mut self, for_control_chan: R, for_data_chan: R) -> Self
+    where
+        R: Into<FtpsRequired>,
+    {
+        self.ftps_required_control_chan = for_control_chan.into();
+        self.ftps_required_data_chan = for_data_chan.into();
+        self
+    }
+
+    /// Requires clients to send `AUTH TLS` as their very first command, optionally hiding the
+    /// greeting until the TLS handshake completed. This is meant for strict hardening profiles
+    /// that don't allow anything to be sent in plaintext, while still using explicit FTPS. FTPS
+    /// needs to be enabled with the [ftps](crate::ServerBuilder::ftps) method for this to work.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use libunftp::options::TlsFirst;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///              .ftps("/srv/unftp/server.certs", "/srv/unftp/server.key")
+    ///              .ftps_tls_first(TlsFirst::RequiredHideGreeting);
+    /// ```
+    pub fn ftps_tls_first<T>(mut self, tls_first: T) -> Self
+    where
+        T: Into<TlsFirst>,
+    {
+        self.ftps_tls_first = tls_first.into();
+        self
+    }
+
+    /// Sets the time in seconds that clients get to complete the TLS handshake after `AUTH TLS`.
+    /// Clients that are slower are disconnected, so that they can't hold on to a connection
+    /// without ever finishing the handshake. The default is 10 seconds.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///              .ftps("/srv/unftp/server.certs", "/srv/unftp/server.key")
+    ///              .ftps_handshake_timeout(5);
+    /// ```
+    pub fn ftps_handshake_timeout(mut self, secs: u64) -> Self {
+        self.ftps_handshake_timeout = Duration::from_secs(secs);
+        self
+    }
+
+    /// Sets the certificates to use when verifying client certificates in Mutual TLS mode. This
+    /// should point to certificates in a PEM formatted file. For this to have any effect MTLS needs
+    /// to be switched on via the [ftps_client_auth](crate::ServerBuilder::ftps_client_auth) method.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///              .ftps("/srv/unftp/server.certs", "/srv/unftp/server.key")
+    ///              .ftps_client_auth(true)
+    ///              .ftps_trust_store("/srv/unftp/trusted.pem");
+    /// ```
+    pub fn ftps_trust_store<P>(mut self, trust: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        self.ftps_trust_store = trust.into();
+        self
+    }
+
+    /// Switches TLS features on or off. Leaving [`TlsFlags::V1_2`] out makes TLS 1.3 the minimum
+    /// protocol version.
+    ///
+    /// # Example
+    ///
+    /// This example enables only TLS v1.3 and allows TLS session resumption with tickets.
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use unftp_sbe_fs::ServerExt;
+    /// use libunftp::options::TlsFlags;
+    ///
+    /// let mut server = Server::with_fs("/tmp")
+    ///                  .greeting("Welcome to my FTP Server")
+    ///                  .ftps("/srv/unftp/server.certs", "/srv/unftp/server.key")
+    ///                  .ftps_tls_flags(TlsFlags::V1_3 | TlsFlags::RESUMPTION_TICKETS);
+    /// ```
+    pub fn ftps_tls_flags(mut self, flags: TlsFlags) -> Self {
+        self.ftps_tls_flags = flags;
+        self
+    }
+
+    /// Restricts the cipher suites that may be negotiated to the given ones, named like the IANA
+    /// does, e.g. `TLS13_AES_256_GCM_SHA384` or `TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384`. By default
+    /// all suites of rustls are allowed. [`build`](ServerBuilder::build) fails on a suite that rustls
+    /// doesn't know, or that the enabled protocol versions can't use.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let mut server = Server::with_fs("/tmp")
+    ///                  .ftps("/srv/unftp/server.certs", "/srv/unftp/server.key")
+    ///                  .ftps_cipher_suites(["TLS13_AES_256_GCM_SHA384", "TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384"]);
+    /// ```
+    pub fn ftps_cipher_suites<I, S>(mut self, suites: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.ftps_tls_settings.cipher_suites = Some(suites.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Sets the ALPN protocols the server offers during the TLS handshake, in order of preference,
+    /// e.g. `ftp`. None are offered by default.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let mut server = Server::with_fs("/tmp")
+    ///                  .ftps("/srv/unftp/server.certs", "/srv/unftp/server.key")
+    ///                  .ftps_alpn(["ftp"]);
+    /// ```
+    pub fn ftps_alpn<I, S>(mut self, protocols: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.ftps_tls_settings.alpn_protocols = protocols.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Appends the TLS session keys to the given file in the NSS key log format, so that tools
+    /// like Wireshark can decrypt captured sessions. Anyone who can read the file can decrypt the
+    /// sessions, so only use this for debugging.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let mut server = Server::with_fs("/tmp")
+    ///                  .ftps("/srv/unftp/server.certs", "/srv/unftp/server.key")
+    ///                  .ftps_key_log_file("/tmp/unftp-keys.log");
+    /// ```
+    pub fn ftps_key_log_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.ftps_tls_settings.key_log_file = Some(path.into());
+        self
+    }
+
+    /// Set the greeting that will be sent to the client after connecting.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// // Use it in a builder-like pattern:
+    /// let server = Server::with_fs("/tmp")
+    ///     .greeting("Welcome to my FTP Server")
+    ///     .build();
+    //
+    /// // Or instead if you prefer:
+    /// let mut server = Server::with_fs("/tmp");
+    /// server.greeting("Welcome to my FTP Server").build();
+    /// ```
+    pub fn greeting(mut self, greeting: &'static str) -> Self {
+        self.greeting = greeting;
+        self
+    }
+
+    /// Sets a function that composes the greeting of every connection, for instance to include
+    /// the host name, an upcoming maintenance window or text that depends on the listener the
+    /// client connected to. It takes precedence over [greeting](ServerBuilder::greeting). A
+    /// greeting that spans several lines is sent as a multi-line reply.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use libunftp::options::ConnectionInfo;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///     .greeting_provider(|info: &ConnectionInfo| {
+    ///         format!("Welcome {}\nMaintenance tonight from 22:00 UTC", info.source.ip())
+    ///     })
+    ///     .build();
+    /// ```
+    pub fn greeting_provider<F>(mut self, provider: F) -> Self
+    where
+        F: Fn(&ConnectionInfo) -> String + Send + Sync + 'static,
+    {
+        self.greeting_provider = Some(GreetingFn(Arc::new(provider)));
+        self
+    }
+
+    /// Sets a message, like a usage policy or a migration notice, that is sent to clients as part of
+    /// the `230` reply when they logged in. It may span several lines. The message can be set per
+    /// user with [UserDetail::login_message](crate::auth::UserDetail::login_message). By default no
+    /// message is sent.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///     .login_message("This server moves to ftp.example.com on 1 March.\nPlease update your bookmarks.")
+    ///     .build();
+    /// ```
+    pub fn login_message<M: Into<String>>(mut self, message: M) -> Self {
+        self.login_message = Some(message.into());
+        self
+    }
+
+    /// Registers a custom `SITE` subcommand. The handler gets the arguments of the command and
+    /// access to the session's user and storage back-end through the [SiteCommandContext] and
+    /// returns the [SiteReply] for the client. The name is case insensitive. Registering `MD5` or
+    /// `RMDIR` replaces the built-in `SITE MD5` or `SITE RMDIR`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use libunftp::site::SiteReply;
+    /// use libunftp::storage::{Metadata, StorageBackend};
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// // SITE AGE <file> tells how many seconds ago a file was modified.
+    /// let server = Server::with_fs("/tmp")
+    ///     .site_command("AGE", |ctx| async move {
+    ///         let path = match ctx.resolve(&ctx.arguments) {
+    ///             Ok(path) => path,
+    ///             Err(reply) => return reply,
+    ///         };
+    ///         match ctx.storage().metadata(ctx.user(), &path).await {
+    ///             Ok(metadata) => match metadata.modified().map(|modified| modified.elapsed()) {
+    ///                 Ok(Ok(age)) => SiteReply::ok(format!("{} seconds", age.as_secs())),
+    ///                 _ => SiteReply::failed("Unknown modification time"),
+    ///             },
+    ///             Err(err) => err.into(),
+    ///         }
+    ///     })
+    ///     .build();
+    /// ```
+    pub fn site_command<F, Fut>(mut self, name: &str, handler: F) -> Self
+    where
+        F: Fn(SiteCommandContext<Storage, User>) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = SiteReply> + Send + 'static,
+        Storage: 'static,
+        User: 'static,
+    {
+        self.site_commands.register(name, handler);
+        self
+    }
+
+    /// Adds a [Middleware] that intercepts the commands of the clients and the replies to them,
+    /// e.g. to rate limit, audit or rewrite paths. Middleware runs in the order it was added, the
+    /// first one added sees the commands first.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use async_trait::async_trait;
+    /// use libunftp::middleware::{Middleware, MiddlewareError, Next, Request, Response};
+    /// use libunftp::Server;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// // Logs every command that failed.
+    /// #[derive(Debug)]
+    /// struct Audit;
+    ///
+    /// #[async_trait]
+    /// impl Middleware for Audit {
+    ///     async fn handle(&self, request: Request, next: Next<'_>) -> Result<Response, MiddlewareError> {
+    ///         let verb = request.verb().to_string();
+    ///         let source = request.source();
+    ///         let response = next.run(request).await?;
+    ///         if response.code().map_or(false, |code| code >= 400) {
+    ///             println!("{} from {} failed: {:?}", verb, source, response.lines());
+    ///         }
+    ///         Ok(response)
+    ///     }
+    /// }
+    ///
+    /// let server = Server::with_fs("/tmp").middleware(Audit).build();
+    /// ```
+    pub fn middleware<M: Middleware + 'static>(mut self, middleware: M) -> Self {
+        Arc::make_mut(&mut self.middleware).push(Arc::new(middleware));
+        self
+    }
+
+    /// Set the idle session timeout in seconds. The default is 600 seconds. Sessions with a data
+    /// transfer in progress are not considered idle, see
+    /// [data_stall_timeout](ServerBuilder::data_stall_timeout) for the timeout that applies to them.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// // Use it in a builder-like pattern:
+    /// let mut server = Server::with_fs("/tmp").idle_session_timeout(600);
+    ///
+    /// // Or instead if you prefer:
+    /// let mut server = Server::with_fs("/tmp");
+    /// server.idle_session_timeout(600);
+    /// ```
+    pub fn idle_session_timeout(mut self, secs: u64) -> Self {
+        self.idle_session_timeout = Duration::from_secs(secs);
+        self
+    }
+
+    /// Sets the time in seconds after which a data transfer is aborted if no bytes could be sent or
+    /// received in the meantime. By default stalled transfers are not aborted.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let mut server = Server::with_fs("/tmp").data_stall_timeout(120);
+    /// ```
+    pub fn data_stall_timeout(mut self, secs: u64) -> Self {
+        self.data_stall_timeout = Some(Duration::from_secs(secs));
+        self
+    }
+
+    /// Sets the maximum duration of a session in seconds. When it elapses the control connection is
+    /// closed, also if a data transfer is still in progress. By default there is no maximum.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let mut server = Server::with_fs("/tmp").max_session_duration(8 * 3600);
+    /// ```
+    pub fn max_session_duration(mut self, secs: u64) -> Self {
+        self.max_session_duration = Some(Duration::from_secs(secs));
+        self
+    }
+
+    /// Sets the structured logger ([slog](https://crates.io/crates/slog)::Logger) to use
+    pub fn logger<L: Into<Option<slog::Logger>>>(mut self, logger: L) -> Self {
+        self.logger = logger.into().unwrap_or_else(|| slog::Logger::root(slog_stdlog::StdLog {}.fuse(), slog::o!()));
+        self
+    }
+
+    /// Enables the collection of prometheus metrics. They are registered in the default registry,
+    /// use a [`MetricsCollector`](crate::MetricsCollector) to add them to another one.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// // Use it in a builder-like pattern:
+    /// let mut builder = Server::with_fs("/tmp").metrics();
+    ///
+    /// // Or instead if you prefer:
+    /// let mut builder = Server::with_fs("/tmp");
+    /// builder.metrics();
+    /// ```
+    #[cfg(feature = "prometheus")]
+    pub fn metrics(mut self) -> Self {
+        self.collect_metrics = true;
+        self
+    }
+
+    /// Sets an [`DataListener`](crate::notification::DataListener) that will
+    /// be notified of data changes that happen in a user's session.
+    pub fn notify_data(mut self, listener: impl DataListener + 'static) -> Self {
+        self.data_listener = Arc::new(listener);
+        self
+    }
+
+    /// Sets an [`PresenceListener`](crate::notification::PresenceListener) that will
+    /// be notified of user logins and logouts
+    pub fn notify_presence(mut self, listener: impl PresenceListener + 'static) -> Self {
+        self.presence_listener = Arc::new(listener);
+        self
+    }
+
+    /// Makes the [`DataListener`](crate::notification::DataListener) set with
+    /// [notify_data](crate::ServerBuilder::notify_data) receive a
+    /// [`DataEvent::Progress`](crate::notification::DataEvent::Progress) every `secs` seconds while
+    /// a transfer runs, e.g. to show how far it got in a user interface. By default no progress is
+    /// reported.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///              .transfer_progress_interval(5)
+    ///              .build();
+    /// ```
+    pub fn transfer_progress_interval(mut self, secs: u64) -> Self {
+        self.progress_interval = Some(Duration::from_secs(secs));
+        self
+    }
+
+    /// Makes the sessions wait until the listeners set with
+    /// [notify_data](crate::ServerBuilder::notify_data) and
+    /// [notify_presence](crate::ServerBuilder::notify_presence) handled an event, with a timeout,
+    /// retries and optionally failing the transfer when the event couldn't be delivered. See
+    /// [`EventDelivery`](crate::options::EventDelivery). By default the outcome of the listeners
+    /// isn't checked.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use libunftp::options::EventDelivery;
+    /// use std::time::Duration;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///              .event_delivery(EventDelivery::new(Duration::from_secs(5)).fail_transfer())
+    ///              .build();
+    /// ```
+    pub fn event_delivery(mut self, delivery: EventDelivery) -> Self {
+        self.event_delivery = Some(delivery);
+        self
+    }
+
+    /// Sets an [`UploadHook`](crate::notification::UploadHook) that inspects every upload after it
+    /// was stored but before the client is told it completed. The hook can reject the upload, in
+    /// which case the file is deleted and the client receives a `550` reply. Uploads can't be
+    /// resumed with `REST` while a hook is set.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use libunftp::notification::{CompletedUpload, UploadHook, UploadRejection};
+    /// use unftp_sbe_fs::ServerExt;
+    /// use async_trait::async_trait;
+    ///
+    /// #[derive(Debug)]
+    /// struct Scanner;
+    ///
+    /// #[async_trait]
+    /// impl UploadHook for Scanner {
+    ///     async fn pre_complete(&self, upload: &CompletedUpload) -> Result<(), UploadRejection> {
+    ///         Ok(())
+    ///     }
+    /// }
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///              .upload_hook(Scanner)
+    ///              .build();
+    /// ```
+    pub fn upload_hook(mut self, hook: impl UploadHook + 'static) -> Self {
+        self.upload_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Sets an [`UploadScanner`](crate::notification::UploadScanner) that gets the data of every
+    /// upload while it is being received, for instance to scan it for viruses. Uploads it rejects
+    /// fail with a `451` reply and the stored file is deleted. With the `clamav` feature enabled the
+    /// `ClamAvScanner` can be used for this, with the `icap` feature the `IcapScanner`. Uploads can't be resumed with `REST` while a scanner
+    /// is set.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use libunftp::notification::{UploadRejection, UploadScanner};
+    /// use unftp_sbe_fs::ServerExt;
+    /// use async_trait::async_trait;
+    /// use bytes::Bytes;
+    /// use tokio::sync::mpsc::Receiver;
+    ///
+    /// #[derive(Debug)]
+    /// struct Scanner;
+    ///
+    /// #[async_trait]
+    /// impl UploadScanner for Scanner {
+    ///     async fn scan(&self, _path: String, _data: Receiver<Bytes>) -> Result<(), UploadRejection> {
+    ///         Ok(())
+    ///     }
+    /// }
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///              .upload_scanner(Scanner)
+    ///              .build();
+    /// ```
+    pub fn upload_scanner(mut self, scanner: impl UploadScanner + 'static) -> Self {
+        self.upload_scanner = Some(Arc::new(scanner));
+        self
+    }
+
+    /// Sets a [`TransferLogListener`](crate::notification::TransferLogListener) that is told about
+    /// every completed download and upload, along with the client's address and the transfer
+    /// type. Use [`XferLogFile`](crate::notification::XferLogFile) to write them to a file in the
+    /// xferlog format of wu-ftpd.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use libunftp::notification::XferLogFile;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let log = XferLogFile::open(std::env::temp_dir().join("xferlog")).unwrap();
+    /// let server = Server::with_fs("/tmp")
+    ///              .transfer_log(log)
+    ///              .build();
+    /// ```
+    pub fn transfer_log(mut self, listener: impl TransferLogListener + 'static) -> Self {
+        self.transfer_log = Some(Arc::new(listener));
+        self
+    }
+
+    /// Sets the checksum that is computed over the data of uploads while they are received. It is
+    /// reported in [`DataEvent::Put`](crate::notification::DataEvent::Put) events and passed to the
+    /// [`UploadHook`](crate::notification::UploadHook). By default no checksum is computed.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use libunftp::options::UploadChecksum;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///              .upload_checksum(UploadChecksum::Md5)
+    ///              .build();
+    /// ```
+    pub fn upload_checksum(mut self, checksum: UploadChecksum) -> Self {
+        self.upload_checksum = checksum;
+        self
+    }
+
+    /// Allows clients to compress data transfers with `MODE Z`, which deflates the data in a zlib
+    /// stream (RFC 1950 and 1951). This saves bandwidth for text-heavy transfers over slow links,
+    /// at the cost of CPU time on both ends. Clients opt in per session by sending `MODE Z`, and
+    /// switch back with `MODE S`. Disabled by default.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use libunftp::options::ModeZ;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///              .mode_z(ModeZ::Enabled { level: 6 })
+    ///              .build();
+    /// ```
+    pub fn mode_z(mut self, mode_z: ModeZ) -> Self {
+        self.mode_z = mode_z;
+        self
+    }
+
+    /// Sets whether `LIST -R` and `NLST -R` list the whole tree under a directory in one transfer,
+    /// as some synchronization tools ask for, and the limits that keep such a listing from walking
+    /// a huge tree. Enabled by default with a depth of 16 and at most 10000 entries.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use libunftp::options::RecursiveListing;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///              .recursive_listing(RecursiveListing::Enabled { max_depth: 4, max_entries: 1000 })
+    ///              .build();
+    /// ```
+    pub fn recursive_listing(mut self, recursive_listing: RecursiveListing) -> Self {
+        self.recursive_listing = recursive_listing;
+        self
+    }
+
+    /// Sets the TCP options of the control connections, like `TCP_NODELAY`, `SO_KEEPALIVE` and
+    /// the socket buffer sizes. By default the operating system decides.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use libunftp::options::SocketOptions;
+    /// use std::time::Duration;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///              .control_socket_options(SocketOptions::new().nodelay(true).keepalive(Duration::from_secs(60)))
+    ///              .build();
+    /// ```
+    pub fn control_socket_options(mut self, options: SocketOptions) -> Self {
+        self.control_socket_options = options;
+        self
+    }
+
+    /// Sets the TCP options of the data connections, passive as well as active. Larger buffers let
+    /// transfers over links with a high latency use larger TCP windows. By default the operating
+    /// system decides.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use libunftp::options::SocketOptions;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///              .data_socket_options(SocketOptions::new().send_buffer_size(8 << 20).recv_buffer_size(8 << 20))
+    ///              .build();
+    /// ```
+    pub fn data_socket_options(mut self, options: SocketOptions) -> Self {
+        self.data_socket_options = options;
+        self
+    }
+
+    /// Sets the local address that data connections use, for hosts with several network
+    /// interfaces where data must go through a dedicated one. Passive data ports listen on this
+    /// address instead of the one the control connection came in on, and active data connections
+    /// are made from it. With [`PassiveHost::FromConnection`](options::PassiveHost::FromConnection)
+    /// the `PASV` reply advertises this address. Not used in proxy protocol mode.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use std::net::Ipv4Addr;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///              .data_bind_address(Ipv4Addr::new(10, 0, 1, 5))
+    ///              .build();
+    /// ```
+    pub fn data_bind_address(mut self, address: Ipv4Addr) -> Self {
+        self.data_bind_address = Some(address);
+        self
+    }
+
+    /// Sets the [`PassivePortMapper`](options::PassivePortMapper) that turns the passive data port
+    /// libunftp listens on into the address and port advertised in the `PASV` reply. Use it when a
+    /// NAT gateway translates the port as well as the address, so that the port the client connects
+    /// to differs from the one the server listens on. The address given to the mapper is the one
+    /// chosen with [`passive_host`](Self::passive_host). By default the port is advertised as is.
     ///
     /// # Example
     ///
     /// ```rust
+    /// use libunftp::options::PortOffset;
     /// use libunftp::Server;
     /// use unftp_sbe_fs::ServerExt;
     ///
+    /// // The gateway forwards ports 30000-30999 to 50000-50999 on the server.
     /// let server = Server::with_fs("/tmp")
-    ///              .ftps("/srv/unftp/server.certs", "/srv/unftp/server.key");
+    ///              .passive_ports(50000..50999)
+    ///              .passive_port_mapper(PortOffset(-20000))
+    ///              .build();
     /// ```
-    pub fn ftps<P: Into<PathBuf>>(mut self, certs_file: P, key_file: P) -> Self {
-        self.ftps_mode = FtpsConfig::Building {
-            certs_file: certs_file.into(),
-            key_file: key_file.into(),
-        };
+    pub fn passive_port_mapper<M: PassivePortMapper + 'static>(mut self, mapper: M) -> Self {
+        self.passive_port_mapper = Some(Arc::new(mapper));
         self
     }
 
-    /// Allows switching on Mutual TLS. For this to work the trust anchors also needs to be set using
-    /// the [ftps_trust_store](crate::ServerBuilder::ftps_trust_store) method.
+    /// Runs the data connections on the given tokio runtime instead of the one the server was
+    /// started on. Transfers, with the TLS, compression and storage back-end work they bring, then
+    /// can't slow down the handling of commands on the control connections. The runtime needs its
+    /// I/O and time drivers enabled and must outlive the server.
     ///
     /// # Example
     ///
     /// ```rust
     /// use libunftp::Server;
     /// use unftp_sbe_fs::ServerExt;
-    /// use libunftp::options::FtpsClientAuth;
     ///
+    /// let transfers = tokio::runtime::Builder::new_multi_thread()
+    ///     .thread_name("ftp-data")
+    ///     .enable_all()
+    ///     .build()
+    ///     .unwrap();
     /// let server = Server::with_fs("/tmp")
-    ///              .ftps("/srv/unftp/server.certs", "/srv/unftp/server.key")
-    ///              .ftps_client_auth(FtpsClientAuth::Require)
-    ///              .ftps_trust_store("/srv/unftp/trusted.pem");
+    ///              .data_runtime(transfers.handle().clone())
+    ///              .build();
     /// ```
-    pub fn ftps_client_auth<C>(mut self, auth: C) -> Self
-    where
-        C: Into<FtpsClientAuth>,
-    {
-        self.ftps_client_auth = auth.into();
-        self
-    }
-
-    /// Configures whether client connections may use plaintext mode or not.
-    pub fn ftps_required<R>(mut self, for_control_chan: R, for_data_chan: R) -> Self
-    where
-        R: Into<FtpsRequired>,
-    {
-        self.ftps_required_control_chan = for_control_chan.into();
-        self.ftps_required_data_chan = for_data_chan.into();
+    pub fn data_runtime(mut self, runtime: Handle) -> Self {
+        self.data_runtime = Some(runtime);
         self
     }
 
-    /// Sets the certificates to use when verifying client certificates in Mutual TLS mode. This
-    /// should point to certificates in a PEM formatted file. For this to have any effect MTLS needs
-    /// to be switched on via the [ftps_client_auth](crate::ServerBuilder::ftps_client_auth) method.
+    /// Sets the order in which directory listings give their entries, so that clients see the
+    /// same order whatever the storage back-end returns. The default is to keep the order of the
+    /// back-end.
     ///
     /// # Example
     ///
     /// ```rust
     /// use libunftp::Server;
+    /// use libunftp::options::ListingOrder;
     /// use unftp_sbe_fs::ServerExt;
     ///
     /// let server = Server::with_fs("/tmp")
-    ///              .ftps("/srv/unftp/server.certs", "/srv/unftp/server.key")
-    ///              .ftps_client_auth(true)
-    ///              .ftps_trust_store("/srv/unftp/trusted.pem");
+    ///              .listing_order(ListingOrder::Name)
+    ///              .build();
     /// ```
-    pub fn ftps_trust_store<P>(mut self, trust: P) -> Self
-    where
-        P: Into<PathBuf>,
-    {
-        self.ftps_trust_store = trust.into();
+    pub fn listing_order(mut self, order: ListingOrder) -> Self {
+        self.listing_order = order;
         self
     }
 
-    /// Switches TLS features on or off.
+    /// Sets whether the listings of `LIST`, `NLST` and `STAT` start with `.` and `..` entries for
+    /// the directory and its parent, as some older clients expect. Listings with wildcards or the
+    /// `-R` flag leave them out. Off by default.
     ///
     /// # Example
     ///
-    /// This example enables only TLS v1.3 and allows TLS session resumption with tickets.
-    ///
     /// ```rust
     /// use libunftp::Server;
     /// use unftp_sbe_fs::ServerExt;
-    /// use libunftp::options::TlsFlags;
     ///
-    /// let mut server = Server::with_fs("/tmp")
-    ///                  .greeting("Welcome to my FTP Server")
-    ///                  .ftps("/srv/unftp/server.certs", "/srv/unftp/server.key")
-    ///                  .ftps_tls_flags(TlsFlags::V1_3 | TlsFlags::RESUMPTION_TICKETS);
+    /// let server = Server::with_fs("/tmp")
+    ///              .listing_dot_entries(true)
+    ///              .build();
     /// ```
-    pub fn ftps_tls_flags(mut self, flags: TlsFlags) -> Self {
-        self.ftps_tls_flags = flags;
+    pub fn listing_dot_entries(mut self, enabled: bool) -> Self {
+        self.listing_dot_entries = enabled;
         self
     }
 
-    /// Set the greeting that will be sent to the client after connecting.
+    /// Sets whether libunftp recognises the FTP client from the commands it sends, and whether it
+    /// works around the quirks of the clients it recognises. Recognised clients are logged and,
+    /// with [metrics](ServerBuilder::metrics) enabled, counted per client. Off by default.
     ///
     /// # Example
     ///
     /// ```rust
     /// use libunftp::Server;
+    /// use libunftp::options::ClientQuirks;
     /// use unftp_sbe_fs::ServerExt;
     ///
-    /// // Use it in a builder-like pattern:
     /// let server = Server::with_fs("/tmp")
-    ///     .greeting("Welcome to my FTP Server")
-    ///     .build();
-    //
-    /// // Or instead if you prefer:
-    /// let mut server = Server::with_fs("/tmp");
-    /// server.greeting("Welcome to my FTP Server").build();
+    ///              .client_quirks(ClientQuirks::Apply)
+    ///              .build();
     /// ```
-    pub fn greeting(mut self, greeting: &'static str) -> Self {
-        self.greeting = greeting;
+    pub fn client_quirks(mut self, client_quirks: ClientQuirks) -> Self {
+        self.client_quirks = client_quirks;
         self
     }
 
-    /// Set the idle session timeout in seconds. The default is 600 seconds.
+    /// Sets whether clients may change the modification time of a file with the non-standard
+    /// `MDTM YYYYMMDDHHMMSS path` form of `MDTM` that several clients use to preserve the times
+    /// of uploaded files. The time is in UTC and the storage back-end needs to implement
+    /// [`StorageBackend::set_modified`](crate::storage::StorageBackend::set_modified). Clients
+    /// that try while it is disabled receive a `504` reply. Enabled by default.
     ///
     /// # Example
     ///
@@ -366,55 +1481,74 @@ where
     /// use libunftp::Server;
     /// use unftp_sbe_fs::ServerExt;
     ///
-    /// // Use it in a builder-like pattern:
-    /// let mut server = Server::with_fs("/tmp").idle_session_timeout(600);
-    ///
-    /// // Or instead if you prefer:
-    /// let mut server = Server::with_fs("/tmp");
-    /// server.idle_session_timeout(600);
+    /// let server = Server::with_fs("/tmp")
+    ///              .mdtm_setter(false)
+    ///              .build();
     /// ```
-    pub fn idle_session_timeout(mut self, secs: u64) -> Self {
-        self.idle_session_timeout = Duration::from_secs(secs);
+    pub fn mdtm_setter(mut self, enabled: bool) -> Self {
+        self.mdtm_setter = enabled;
         self
     }
 
-    /// Sets the structured logger ([slog](https://crates.io/crates/slog)::Logger) to use
-    pub fn logger<L: Into<Option<slog::Logger>>>(mut self, logger: L) -> Self {
-        self.logger = logger.into().unwrap_or_else(|| slog::Logger::root(slog_stdlog::StdLog {}.fuse(), slog::o!()));
-        self
-    }
-
-    /// Enables the collection of prometheus metrics.
+    /// Sets how the usernames and IP addresses of clients appear in the logs and in the events
+    /// passed to the listeners of the [`notification`](crate::notification) module, for
+    /// deployments that may not store personal data in plain text. By default they are shown as
+    /// they are. See [`Redaction`](crate::options::Redaction) for the choices.
     ///
     /// # Example
     ///
     /// ```rust
     /// use libunftp::Server;
+    /// use libunftp::options::Redaction;
     /// use unftp_sbe_fs::ServerExt;
     ///
-    /// // Use it in a builder-like pattern:
-    /// let mut builder = Server::with_fs("/tmp").metrics();
-    ///
-    /// // Or instead if you prefer:
-    /// let mut builder = Server::with_fs("/tmp");
-    /// builder.metrics();
+    /// let server = Server::with_fs("/tmp")
+    ///              .redaction(Redaction::Hash)
+    ///              .build();
     /// ```
-    pub fn metrics(mut self) -> Self {
-        self.collect_metrics = true;
+    pub fn redaction(mut self, redaction: Redaction) -> Self {
+        self.redaction = redaction;
         self
     }
 
-    /// Sets an [`DataListener`](crate::notification::DataListener) that will
-    /// be notified of data changes that happen in a user's session.
-    pub fn notify_data(mut self, listener: impl DataListener + 'static) -> Self {
-        self.data_listener = Arc::new(listener);
+    /// Sets whether the passive data connection has to come from the same IP address as the control
+    /// connection. Requiring it protects plain FTP sessions against data connection theft but breaks
+    /// clients behind NAT setups that use another address for the data connection. Any address is
+    /// allowed by default. Connections through the proxy protocol are always matched to their
+    /// session by source IP.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use libunftp::options::DataConnectionSource;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///              .data_connection_source(DataConnectionSource::SameIp)
+    ///              .build();
+    /// ```
+    pub fn data_connection_source(mut self, data_connection_source: DataConnectionSource) -> Self {
+        self.data_connection_source = data_connection_source;
         self
     }
 
-    /// Sets an [`PresenceListener`](crate::notification::PresenceListener) that will
-    /// be notified of user logins and logouts
-    pub fn notify_presence(mut self, listener: impl PresenceListener + 'static) -> Self {
-        self.presence_listener = Arc::new(listener);
+    /// Sets the [`PathFilter`](crate::storage::PathFilter) that every path supplied by a client
+    /// is passed through before it reaches the storage back-end. Paths rejected by the filter
+    /// result in a `553` reply. By default the [`DefaultPathFilter`](crate::storage::DefaultPathFilter)
+    /// is used.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use libunftp::storage::DefaultPathFilter;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let builder = Server::with_fs("/tmp").path_filter(DefaultPathFilter);
+    /// ```
+    pub fn path_filter(mut self, filter: impl PathFilter + 'static) -> Self {
+        self.path_filter = Arc::new(filter);
         self
     }
 
@@ -466,6 +1600,19 @@ where
     ///              .passive_host("ftp.myserver.org")
     ///              .build();
     /// ```
+    ///
+    /// Or look the IP up at the time of every `PASV`, for servers behind dynamic NAT, with a
+    /// [`PassiveHostResolver`](options::PassiveHostResolver) that keeps the address for a minute:
+    ///
+    /// ```rust
+    /// use libunftp::{Server,options};
+    /// use unftp_sbe_fs::ServerExt;
+    /// use std::time::Duration;
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///              .passive_host(options::PassiveHost::resolver(options::InstanceMetadata::gce(Duration::from_secs(60))))
+    ///              .build();
+    /// ```
     pub fn passive_host<H: Into<PassiveHost>>(mut self, host_option: H) -> Self {
         self.passive_host = host_option.into();
         self
@@ -481,6 +1628,7 @@ where
     }
 
     /// Sets the range of passive ports that we'll use for passive connections.
+    /// [`build`](ServerBuilder::build) fails if the range is empty.
     ///
     /// # Example
     ///
@@ -529,6 +1677,7 @@ where
     ///     .proxy_protocol_mode(2121)
     ///     .build();
     /// ```
+    #[cfg(feature = "proxy-protocol")]
     pub fn proxy_protocol_mode(mut self, external_control_port: u16) -> Self {
         self.proxy_protocol_mode = external_control_port.into();
         self
@@ -592,16 +1741,57 @@ where
     /// accepted connections to be serviced by a different program.  After accepting a connection,
     /// the Server will execute the provided helper process.  Any provided arguments will be passed
     /// to the helper process.  After those arguments, the Server will pass an integer, which is
-    /// the file descriptor number of the connected socket.
+    /// the file descriptor number of the connected socket. See [`ConnectionHelper`] for what the
+    /// helper is expected to do.
     ///
     /// # Arguments
     ///
     /// - `path` - Path to the helper executable
     /// - `args` - Optional arguments to pass to the helper executable.
     #[cfg(unix)]
-    pub fn connection_helper(mut self, path: OsString, args: Vec<OsString>) -> Self {
-        self.connection_helper = Some(path);
-        self.connection_helper_args = args;
+    pub fn connection_helper(self, path: OsString, args: Vec<OsString>) -> Self {
+        self.connection_delegate(ConnectionHelper::new(path, args))
+    }
+
+    /// Makes [`Server::listen`] switch to the given system user and group once it is bound to its
+    /// port, so that the server can be started as root to listen on a privileged port like 21
+    /// without serving sessions as root. The process fails to start if the user or group don't
+    /// exist or the switch fails.
+    ///
+    /// Passive ports are bound after the switch, so they must not be privileged. Active mode data
+    /// connections are made from ephemeral ports rather than from port 20, with or without this
+    /// option, so nothing else needs to be bound as root.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/srv/ftp").run_as("ftp", "ftp");
+    /// ```
+    #[cfg(unix)]
+    pub fn run_as(mut self, user: impl Into<String>, group: impl Into<String>) -> Self {
+        self.privileges.run_as = Some(privileges::RunAs {
+            user: user.into(),
+            group: group.into(),
+        });
+        self
+    }
+
+    /// Sets the [`Sandbox`](crate::options::Sandbox) that restricts the process once it is ready to
+    /// serve sessions. On Linux [`NoNewPrivileges`](crate::options::NoNewPrivileges) is provided;
+    /// seccomp filters, pledge or Capsicum are applied by implementing the trait.
+    #[cfg(feature = "sandbox")]
+    pub fn sandbox(mut self, sandbox: impl crate::options::Sandbox + 'static) -> Self {
+        self.privileges.sandbox = Some(Arc::new(sandbox));
+        self
+    }
+
+    /// Hands the connections accepted by [`Server::listen`] to the given
+    /// [`DataConnectionDelegate`] instead of serving them in this process.
+    pub fn connection_delegate(mut self, delegate: impl DataConnectionDelegate + 'static) -> Self {
+        self.connection_delegate = Some(Arc::new(delegate));
         self
     }
 
@@ -652,6 +1842,109 @@ where
         self.failed_logins_policy = Some(policy);
         self
     }
+
+    /// Sets the maximum number of sessions that may be connected at the same time. Clients that
+    /// connect while the maximum is reached receive a `421` reply and are disconnected. By default
+    /// there is no maximum. It can be changed while the server runs with a
+    /// [`ConfigHandle`](crate::ConfigHandle).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///     .max_connections(100)
+    ///     .build();
+    /// ```
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.max_connections = Some(max);
+        self
+    }
+
+    /// Limits the length of command lines and how many commands a client may send. Without this
+    /// only the line length is limited, see [`CommandLimits`](crate::options::CommandLimits).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use libunftp::options::CommandLimits;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///     .command_limits(CommandLimits::new().commands_per_second(20).commands_before_login(10))
+    ///     .build();
+    /// ```
+    pub fn command_limits(mut self, limits: CommandLimits) -> Self {
+        self.command_limits = limits;
+        self
+    }
+
+    /// Sets the maximum number of sessions that may be connected at the same time without having
+    /// logged in. Clients that connect while the maximum is reached receive a `421` reply and are
+    /// disconnected, so that clients which connect and then stall can't take up all the
+    /// connections. Sessions that logged in don't count. By default there is no maximum.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///     .max_connections(500)
+    ///     .max_unauthenticated_sessions(50)
+    ///     .build();
+    /// ```
+    pub fn max_unauthenticated_sessions(mut self, max: usize) -> Self {
+        self.max_unauthenticated_sessions = Some(max);
+        self
+    }
+
+    /// Sets the [`ConnectionPolicy`](crate::options::ConnectionPolicy) that decides whether a
+    /// client may connect, for instance with a reputation lookup like the
+    /// [`DnsBlocklist`](crate::options::DnsBlocklist). It is asked before the client is greeted;
+    /// clients it rejects receive a `421` reply and are disconnected. By default all clients may
+    /// connect.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use libunftp::options::DnsBlocklist;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///     .connection_policy(DnsBlocklist::new(["zen.spamhaus.org"]))
+    ///     .build();
+    /// ```
+    pub fn connection_policy(mut self, policy: impl ConnectionPolicy + 'static) -> Self {
+        self.connection_policy = Some(Arc::new(CachedConnectionPolicy::new(Arc::new(policy))));
+        self
+    }
+
+    /// Sets a [`SessionCache`] that remembers interrupted downloads, so that clients that
+    /// reconnect and resume them with `REST` and `RETR` have their offset checked consistently.
+    /// Off by default.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use libunftp::options::MemorySessionCache;
+    /// use std::time::Duration;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///     .session_cache(MemorySessionCache::new(Duration::from_secs(120)))
+    ///     .build();
+    /// ```
+    pub fn session_cache(mut self, cache: impl SessionCache + 'static) -> Self {
+        self.session_cache = Some(Arc::new(cache));
+        self
+    }
 }
 
 impl<Storage, User> Server<Storage, User>
@@ -674,6 +1967,22 @@ where
         ServerBuilder::new(sbe_generator)
     }
 
+    /// Returns a [`HealthCheck`] that reports whether the server accepts connections and whether
+    /// its storage back-end responds, to be used by liveness and readiness probes.
+    pub fn health(&self) -> HealthCheck<Storage, User> {
+        HealthCheck::new(self.storage.clone(), self.listening.clone())
+    }
+
+    /// Returns a [`ServerHandle`] to manage the sessions of the server while it runs.
+    pub fn handle(&self) -> ServerHandle {
+        ServerHandle::new(self.sessions.clone(), self.ftps_mode.clone())
+    }
+
+    /// Returns a [`ConfigHandle`] to change some of the options of the server while it runs.
+    pub fn config_handle(&self) -> ConfigHandle {
+        ConfigHandle::new(self.runtime_options.clone())
+    }
+
     /// Runs the main FTP process asynchronously. Should be started in a async runtime context.
     ///
     /// # Example
@@ -694,55 +2003,120 @@ where
     ///
     #[tracing_attributes::instrument]
     pub async fn listen<T: Into<String> + Debug>(self, bind_address: T) -> std::result::Result<(), ServerError> {
+        self.listen_all(vec![ListenerOptions::new(bind_address)]).await
+    }
+
+    /// Like [`listen`](Server::listen) but listens on several addresses at once, each with its own
+    /// greeting, FTPS requirements, TLS-first mode or proxy protocol mode if needed. The sessions on all of them share
+    /// the storage back-end, the authenticator and the other options of the server. Fails as soon
+    /// as one of the addresses fails.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use libunftp::options::{FtpsRequired, ListenerOptions};
+    /// use unftp_sbe_fs::ServerExt;
+    /// use tokio::runtime::Runtime;
+    ///
+    /// let mut rt = Runtime::new().unwrap();
+    /// rt.spawn(async {
+    ///     let server = Server::with_fs("/srv/ftp").build().unwrap();
+    ///     server.listen_all(vec![
+    ///         ListenerOptions::new("0.0.0.0:2121").ftps_required(FtpsRequired::All, FtpsRequired::All),
+    ///         ListenerOptions::new("127.0.0.1:2122").greeting("Welcome, neighbour"),
+    ///     ]).await
+    /// });
+    /// // ...
+    /// drop(rt);
+    /// ```
+    #[tracing_attributes::instrument]
+    pub async fn listen_all<I>(self, listeners: I) -> std::result::Result<(), ServerError>
+    where
+        I: IntoIterator<Item = ListenerOptions> + Debug,
+    {
         let logger = self.logger.clone();
-        let bind_address: SocketAddr = bind_address.into().parse()?;
         let shutdown_notifier = Arc::new(shutdown::Notifier::new());
 
         let failed_logins = self.failed_logins_policy.as_ref().map(|policy| FailedLoginsCache::new(policy.clone()));
 
-        let listen_future = match self.proxy_protocol_mode {
-            ProxyMode::On { external_control_port } => Box::pin(
-                listen_proxied::ProxyProtocolListener {
-                    bind_address,
-                    external_control_port,
-                    logger: self.logger.clone(),
-                    options: (&self).into(),
-                    proxy_protocol_switchboard: Some(ProxyProtocolSwitchboard::new(self.logger.clone(), self.passive_ports.clone())),
-                    shutdown_topic: shutdown_notifier.clone(),
-                    failed_logins: failed_logins.clone(),
-                }
-                .listen(),
-            ) as Pin<Box<dyn Future<Output = std::result::Result<(), ServerError>> + Send>>,
-            ProxyMode::Off => Box::pin(
-                listen::Listener {
-                    bind_address,
-                    logger: self.logger.clone(),
-                    options: (&self).into(),
-                    shutdown_topic: shutdown_notifier.clone(),
-                    failed_logins: failed_logins.clone(),
-                    connection_helper: self.connection_helper.clone(),
-                    connection_helper_args: self.connection_helper_args.clone(),
-                }
-                .listen(),
-            ) as Pin<Box<dyn Future<Output = std::result::Result<(), ServerError>> + Send>>,
-        };
+        // All addresses are bound before the privileges are dropped, so that each may be a privileged port.
+        let mut bound = vec![];
+        for listener in listeners {
+            if listener.ftps_tls_first.is_some_and(|tls_first| tls_first.is_required()) && matches!(self.ftps_mode, FtpsConfig::Off) {
+                return Err(tls::ConfigError::TlsFirstWithoutFtps.into());
+            }
+            let bind_address: SocketAddr = listener.bind_address.parse()?;
+            bound.push((tokio::net::TcpListener::bind(bind_address).await?, listener));
+        }
+        self.privileges.drop(&logger)?;
+        self.listening.store(true, Ordering::Relaxed);
+
+        let mut listen_futures = vec![];
+        for (tcp_listener, listener) in bound {
+            let mut options: chosen::OptionsHolder<Storage, User> = (&self).into();
+            if let Some(greeting) = listener.greeting {
+                options.greeting = greeting;
+                options.greeting_provider = None;
+            }
+            if let Some((control_chan, data_chan)) = listener.ftps_required {
+                options.ftps_required_control_chan = control_chan;
+                options.ftps_required_data_chan = data_chan;
+            }
+            if let Some(tls_first) = listener.ftps_tls_first {
+                options.ftps_tls_first = tls_first;
+            }
+            #[cfg(feature = "proxy-protocol")]
+            let proxy_protocol_mode = listener.proxy_protocol_mode.map(ProxyMode::from).unwrap_or(self.proxy_protocol_mode);
+            #[cfg(not(feature = "proxy-protocol"))]
+            let proxy_protocol_mode = self.proxy_protocol_mode;
+            listen_futures.push(match proxy_protocol_mode {
+                #[cfg(feature = "proxy-protocol")]
+                ProxyMode::On { external_control_port } => Box::pin(
+                    listen_proxied::ProxyProtocolListener {
+                        external_control_port,
+                        logger: self.logger.clone(),
+                        options,
+                        proxy_protocol_switchboard: Some(ProxyProtocolSwitchboard::new(self.logger.clone(), self.passive_ports.clone())),
+                        shutdown_topic: shutdown_notifier.clone(),
+                        failed_logins: failed_logins.clone(),
+                    }
+                    .listen(tcp_listener),
+                ) as Pin<Box<dyn Future<Output = std::result::Result<(), ServerError>> + Send>>,
+                ProxyMode::Off => Box::pin(
+                    listen::Listener {
+                        logger: self.logger.clone(),
+                        options,
+                        shutdown_topic: shutdown_notifier.clone(),
+                        failed_logins: failed_logins.clone(),
+                        connection_delegate: self.connection_delegate.clone(),
+                    }
+                    .listen(tcp_listener),
+                ) as Pin<Box<dyn Future<Output = std::result::Result<(), ServerError>> + Send>>,
+            });
+        }
+        let listen_future = futures_util::future::try_join_all(listen_futures);
 
         let sweeper_fut = if let Some(ref failed_logins) = failed_logins {
             Box::pin(failed_logins.sweeper(self.logger.clone(), shutdown_notifier.clone())) as Pin<Box<dyn futures_util::Future<Output = ()> + Send>>
         } else {
             Box::pin(futures_util::future::pending()) as Pin<Box<dyn futures_util::Future<Output = ()> + Send>>
         };
-        tokio::select! {
-            result = listen_future => result,
+        let listening = self.listening.clone();
+        let result = tokio::select! {
+            result = listen_future => result.map(|_| ()),
             _ = sweeper_fut => {
                 Ok(())
             },
             opts = self.shutdown => {
                 slog::debug!(logger, "Shutting down within {:?}", opts.grace_period);
+                listening.store(false, Ordering::Relaxed);
                 shutdown_notifier.notify().await;
                 Self::shutdown_linger(logger, shutdown_notifier, opts.grace_period).await
             }
-        }
+        };
+        listening.store(false, Ordering::Relaxed);
+        result
     }
 
     /// Service a newly established connection as a control connection.
@@ -750,12 +2124,24 @@ where
     /// Use this method instead of [`listen`](Server::listen) if you want to listen for and accept
     /// new connections yourself, instead of using libunftp to do it.
     pub async fn service(self, tcp_stream: tokio::net::TcpStream) -> std::result::Result<(), crate::server::ControlChanError> {
+        if let Err(err) = self.privileges.enter_sandbox(&self.logger) {
+            slog::error!(self.logger, "Not serving the connection: {}", err);
+            return Err(crate::server::ControlChanErrorKind::InternalServerError.into());
+        }
         let failed_logins = self.failed_logins_policy.as_ref().map(|policy| FailedLoginsCache::new(policy.clone()));
         let options: chosen::OptionsHolder<Storage, User> = (&self).into();
         let shutdown_notifier = Arc::new(shutdown::Notifier::new());
         let shutdown_listener = shutdown_notifier.subscribe().await;
         slog::debug!(self.logger, "Servicing control connection from");
-        let result = controlchan::spawn_loop::<Storage, User>((&options).into(), tcp_stream, None, None, shutdown_listener, failed_logins.clone()).await;
+        let result = controlchan::spawn_loop::<chosen::SessionStorage<Storage>, User>(
+            (&options).into(),
+            tcp_stream,
+            None,
+            None,
+            shutdown_listener,
+            failed_logins.clone(),
+        )
+        .await;
         match result {
             Err(err) => {
                 slog::error!(self.logger, "Could not spawn control channel loop: {:?}", err);
@@ -811,17 +2197,58 @@ where
             ftps_config: server.ftps_mode.clone(),
             collect_metrics: server.collect_metrics,
             greeting: server.greeting,
-            idle_session_timeout: server.idle_session_timeout,
+            encoding: server.encoding,
+            data_connection_source: server.data_connection_source,
+            control_socket_options: server.control_socket_options,
+            data_socket_options: server.data_socket_options,
+            data_bind_address: server.data_bind_address,
+            passive_port_mapper: server.passive_port_mapper.clone(),
+            data_runtime: server.data_runtime.clone(),
+            recursive_listing: server.recursive_listing,
+            listing_order: server.listing_order,
+            listing_dot_entries: server.listing_dot_entries,
+            client_quirks: server.client_quirks,
+            mdtm_setter: server.mdtm_setter,
+            redaction: server.redaction,
+            middleware: server.middleware.clone(),
+            site_commands: server.site_commands.clone(),
+            login_message: server.login_message.clone(),
+            greeting_provider: server.greeting_provider.clone(),
+            message_catalog: server.message_catalog.clone(),
+            access_mode: server.access_mode,
+            command_policy: server.command_policy.clone(),
+            mode_z: server.mode_z,
+            upload_scanner: server.upload_scanner.clone(),
+            upload_hook: server.upload_hook.clone(),
+            transfer_log: server.transfer_log.clone(),
+            session_cache: server.session_cache.clone(),
+            progress_interval: server.progress_interval,
+            event_delivery: server.event_delivery,
+            upload_checksum: server.upload_checksum,
+            atomic_uploads: server.atomic_uploads,
+            partial_uploads: server.partial_uploads,
+            upload_conflicts: server.upload_conflicts.clone(),
+            dotfiles: server.dotfiles,
+            data_stall_timeout: server.data_stall_timeout,
+            max_session_duration: server.max_session_duration,
+            command_limits: server.command_limits,
+            max_unauthenticated_sessions: server.max_unauthenticated_sessions,
+            connection_policy: server.connection_policy.clone(),
+            #[cfg(feature = "proxy-protocol")]
             passive_ports: server.passive_ports.clone(),
-            passive_host: server.passive_host.clone(),
             logger: server.logger.new(slog::o!()),
             ftps_required_control_chan: server.ftps_required_control_chan,
             ftps_required_data_chan: server.ftps_required_data_chan,
+            ftps_tls_first: server.ftps_tls_first,
+            ftps_handshake_timeout: server.ftps_handshake_timeout,
             site_md5: server.site_md5,
             data_listener: server.data_listener.clone(),
             presence_listener: server.presence_listener.clone(),
+            path_filter: server.path_filter.clone(),
             active_passive_mode: server.active_passive_mode,
             binder: server.binder.clone(),
+            sessions: server.sessions.clone(),
+            runtime_options: server.runtime_options.clone(),
         }
     }
 }
@@ -837,6 +2264,32 @@ where
             .field("collect_metrics", &self.collect_metrics)
             .field("active_passive_mode", &self.active_passive_mode)
             .field("greeting", &self.greeting)
+            .field("encoding", &self.encoding)
+            .field("data_connection_source", &self.data_connection_source)
+            .field("control_socket_options", &self.control_socket_options)
+            .field("data_socket_options", &self.data_socket_options)
+            .field("data_bind_address", &self.data_bind_address)
+            .field("passive_port_mapper", &self.passive_port_mapper)
+            .field("data_runtime", &self.data_runtime)
+            .field("recursive_listing", &self.recursive_listing)
+            .field("listing_order", &self.listing_order)
+            .field("listing_dot_entries", &self.listing_dot_entries)
+            .field("client_quirks", &self.client_quirks)
+            .field("mdtm_setter", &self.mdtm_setter)
+            .field("redaction", &self.redaction)
+            .field("middleware", &self.middleware)
+            .field("site_commands", &self.site_commands)
+            .field("login_message", &self.login_message)
+            .field("greeting_provider", &self.greeting_provider)
+            .field("message_catalog", &self.message_catalog)
+            .field("access_mode", &self.access_mode)
+            .field("command_policy", &self.command_policy)
+            .field("mode_z", &self.mode_z)
+            .field("upload_checksum", &self.upload_checksum)
+            .field("atomic_uploads", &self.atomic_uploads)
+            .field("partial_uploads", &self.partial_uploads)
+            .field("upload_conflicts", &self.upload_conflicts)
+            .field("dotfiles", &self.dotfiles)
             .field("logger", &self.logger)
             .field("metrics", &self.collect_metrics)
             .field("passive_ports", &self.passive_ports)
@@ -846,10 +2299,22 @@ where
             .field("ftps_required_control_chan", &self.ftps_required_control_chan)
             .field("ftps_required_data_chan", &self.ftps_required_data_chan)
             .field("ftps_tls_flags", &self.ftps_tls_flags)
+            .field("ftps_tls_first", &self.ftps_tls_first)
+            .field("ftps_handshake_timeout", &self.ftps_handshake_timeout)
             .field("ftps_trust_store", &self.ftps_trust_store)
+            .field("ftps_tls_settings", &self.ftps_tls_settings)
             .field("idle_session_timeout", &self.idle_session_timeout)
+            .field("data_stall_timeout", &self.data_stall_timeout)
+            .field("max_session_duration", &self.max_session_duration)
+            .field("progress_interval", &self.progress_interval)
+            .field("event_delivery", &self.event_delivery)
             .field("proxy_protocol_mode", &self.proxy_protocol_mode)
             .field("failed_logins_policy", &self.failed_logins_policy)
+            .field("command_limits", &self.command_limits)
+            .field("max_unauthenticated_sessions", &self.max_unauthenticated_sessions)
+            .field("connection_policy", &self.connection_policy)
+            .field("session_cache", &self.session_cache)
+            .field("max_connections", &self.max_connections)
             .finish()
     }
 }
@@ -865,6 +2330,32 @@ where
             .field("collect_metrics", &self.collect_metrics)
             .field("active_passive_mode", &self.active_passive_mode)
             .field("greeting", &self.greeting)
+            .field("encoding", &self.encoding)
+            .field("data_connection_source", &self.data_connection_source)
+            .field("control_socket_options", &self.control_socket_options)
+            .field("data_socket_options", &self.data_socket_options)
+            .field("data_bind_address", &self.data_bind_address)
+            .field("passive_port_mapper", &self.passive_port_mapper)
+            .field("data_runtime", &self.data_runtime)
+            .field("recursive_listing", &self.recursive_listing)
+            .field("listing_order", &self.listing_order)
+            .field("listing_dot_entries", &self.listing_dot_entries)
+            .field("client_quirks", &self.client_quirks)
+            .field("mdtm_setter", &self.mdtm_setter)
+            .field("redaction", &self.redaction)
+            .field("middleware", &self.middleware)
+            .field("site_commands", &self.site_commands)
+            .field("login_message", &self.login_message)
+            .field("greeting_provider", &self.greeting_provider)
+            .field("message_catalog", &self.message_catalog)
+            .field("access_mode", &self.access_mode)
+            .field("command_policy", &self.command_policy)
+            .field("mode_z", &self.mode_z)
+            .field("upload_checksum", &self.upload_checksum)
+            .field("atomic_uploads", &self.atomic_uploads)
+            .field("partial_uploads", &self.partial_uploads)
+            .field("upload_conflicts", &self.upload_conflicts)
+            .field("dotfiles", &self.dotfiles)
             .field("logger", &self.logger)
             .field("metrics", &self.collect_metrics)
             .field("passive_ports", &self.passive_ports)
@@ -872,9 +2363,19 @@ where
             .field("ftps_mode", &self.ftps_mode)
             .field("ftps_required_control_chan", &self.ftps_required_control_chan)
             .field("ftps_required_data_chan", &self.ftps_required_data_chan)
+            .field("ftps_tls_first", &self.ftps_tls_first)
+            .field("ftps_handshake_timeout", &self.ftps_handshake_timeout)
             .field("idle_session_timeout", &self.idle_session_timeout)
+            .field("data_stall_timeout", &self.data_stall_timeout)
+            .field("max_session_duration", &self.max_session_duration)
+            .field("progress_interval", &self.progress_interval)
+            .field("event_delivery", &self.event_delivery)
             .field("proxy_protocol_mode", &self.proxy_protocol_mode)
             .field("failed_logins_policy", &self.failed_logins_policy)
+            .field("command_limits", &self.command_limits)
+            .field("max_unauthenticated_sessions", &self.max_unauthenticated_sessions)
+            .field("connection_policy", &self.connection_policy)
+            .field("session_cache", &self.session_cache)
             .finish()
     }
 }
@@ -0,0 +1,316 @@
+//! Contains the [`AdminApi`], an HTTP API to manage a running server, enabled with the `admin`
+//! feature.
+
+use super::{error::ServerError, handle::ServerHandle};
+use crate::server::sessions::{SessionInfo, TransferInfo, UserStats};
+use chrono::{DateTime, SecondsFormat, Utc};
+use hyper::{
+    header::{AUTHORIZATION, CONTENT_TYPE},
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, StatusCode,
+};
+use ring::constant_time::verify_slices_are_equal;
+use serde_json::{json, Value};
+use slog::Drain;
+use std::{
+    convert::Infallible,
+    fmt::{self, Debug},
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use thiserror::Error;
+
+/// Serves a JSON API over HTTP to manage a running [`Server`](crate::Server), for instance from
+/// operations tooling during a rollout. It is a thin layer over the [`ServerHandle`] and offers
+/// these routes:
+///
+/// | Route                        | Does                                                            |
+/// |------------------------------|-----------------------------------------------------------------|
+/// | `GET /sessions`              | Lists the connected sessions                                    |
+/// | `DELETE /sessions/:id`       | Terminates a session                                            |
+/// | `GET /users`                 | Lists the transfer statistics per user                          |
+/// | `GET /bans`                  | Lists the banned IP addresses                                   |
+/// | `POST /bans/:ip`             | Bans an IP address and terminates its sessions                  |
+/// | `DELETE /bans/:ip`           | Lifts the ban of an IP address                                  |
+/// | `POST /tls/reload`           | Reads the FTPS certificate and key again                        |
+/// | `GET /drain`                 | Tells if the server is in drain mode                            |
+/// | `POST /drain`                | Refuses new connections while the connected sessions carry on   |
+/// | `POST /drain?deadline=:secs` | Also terminates the sessions still connected after the deadline |
+/// | `DELETE /drain`              | Accepts new connections again                                   |
+///
+/// The API has no TLS of its own, so bind it to a loopback or otherwise private address. With a
+/// [token](AdminApi::token) set, requests need an `Authorization: Bearer <token>` header. Without
+/// one, the API only listens on loopback addresses.
+///
+/// Requires the `admin` feature.
+///
+/// # Example
+///
+/// ```no_run
+/// use libunftp::Server;
+/// use libunftp::admin::AdminApi;
+/// use unftp_sbe_fs::ServerExt;
+///
+/// # async fn run() {
+/// let server = Server::with_fs("/srv/ftp").build().unwrap();
+/// let admin = AdminApi::new(server.handle()).token("s3cr3t");
+/// tokio::spawn(admin.listen("127.0.0.1:8080"));
+/// server.listen("0.0.0.0:2121").await.unwrap();
+/// # }
+/// ```
+pub struct AdminApi {
+    handle: ServerHandle,
+    token: Option<String>,
+    logger: slog::Logger,
+}
+
+impl AdminApi {
+    /// Creates an API that manages the server of the given handle.
+    pub fn new(handle: ServerHandle) -> Self {
+        AdminApi {
+            handle,
+            token: None,
+            logger: slog::Logger::root(slog_stdlog::StdLog {}.fuse(), slog::o!()),
+        }
+    }
+
+    /// Requires requests to carry the given bearer token.
+    pub fn token<T: Into<String>>(mut self, token: T) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Sets the logger that the changes made through the API are logged to. By default they go to
+    /// the `log` crate.
+    pub fn logger(mut self, logger: slog::Logger) -> Self {
+        self.logger = logger;
+        self
+    }
+
+    /// Serves the API on the given address until the future is dropped. Fails if the address
+    /// isn't a loopback address and no [token](AdminApi::token) is set.
+    pub async fn listen<T: Into<String> + Debug>(self, bind_address: T) -> Result<(), ServerError> {
+        let addr: SocketAddr = bind_address.into().parse()?;
+        if self.token.is_none() && !addr.ip().is_loopback() {
+            return Err(TokenRequired(addr).into());
+        }
+        let logger = self.logger.clone();
+        let api = Arc::new(self);
+        let make_service = make_service_fn(move |_| {
+            let api = api.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let api = api.clone();
+                    async move { Ok::<_, Infallible>(api.serve(request)) }
+                }))
+            }
+        });
+        let server = hyper::Server::try_bind(&addr).map_err(io_error)?;
+        slog::info!(logger, "Admin API listening on {}", addr);
+        server.serve(make_service).await.map_err(io_error)?;
+        Ok(())
+    }
+
+    fn serve(&self, request: Request<Body>) -> Response<Body> {
+        let (status, body) = match self.authorized(&request) {
+            true => self.route(request.method(), request.uri().path(), request.uri().query()),
+            false => (StatusCode::UNAUTHORIZED, json!({ "error": "missing or wrong bearer token" })),
+        };
+        Response::builder()
+            .status(status)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .expect("the response is valid")
+    }
+
+    fn authorized(&self, request: &Request<Body>) -> bool {
+        match &self.token {
+            Some(token) => request
+                .headers()
+                .get(AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .is_some_and(|presented| verify_slices_are_equal(presented.as_bytes(), token.as_bytes()).is_ok()),
+            None => true,
+        }
+    }
+
+    // Carries the request out, returning the status and the body of the reply.
+    fn route(&self, method: &Method, path: &str, query: Option<&str>) -> (StatusCode, Value) {
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        match (method, segments.as_slice()) {
+            (&Method::GET, ["sessions"]) => ok(Value::Array(self.handle.sessions().iter().map(session_json).collect())),
+            (&Method::DELETE, ["sessions", id]) => match self.handle.kill_session(id) {
+                true => {
+                    slog::info!(self.logger, "Admin API terminated session {}", id);
+                    ok(json!({ "terminated": id }))
+                }
+                false => error(StatusCode::NOT_FOUND, format!("no session {}", id)),
+            },
+            (&Method::GET, ["users"]) => ok(Value::Array(self.handle.user_stats().iter().map(user_json).collect())),
+            (&Method::GET, ["bans"]) => ok(json!(self.handle.banned_ips())),
+            (&Method::POST, ["bans", ip]) => match ip.parse::<IpAddr>() {
+                Ok(ip) => {
+                    let terminated = self.handle.ban_ip(ip);
+                    slog::info!(self.logger, "Admin API banned {}, terminating {} sessions", ip, terminated);
+                    ok(json!({ "banned": ip, "terminated_sessions": terminated }))
+                }
+                Err(_) => error(StatusCode::BAD_REQUEST, format!("invalid IP address {}", ip)),
+            },
+            (&Method::DELETE, ["bans", ip]) => match ip.parse::<IpAddr>() {
+                Ok(ip) if self.handle.unban_ip(ip) => {
+                    slog::info!(self.logger, "Admin API lifted the ban of {}", ip);
+                    ok(json!({ "unbanned": ip }))
+                }
+                Ok(ip) => error(StatusCode::NOT_FOUND, format!("{} isn't banned", ip)),
+                Err(_) => error(StatusCode::BAD_REQUEST, format!("invalid IP address {}", ip)),
+            },
+            (&Method::POST, ["tls", "reload"]) => match self.handle.reload_certificates() {
+                Ok(()) => {
+                    slog::info!(self.logger, "Admin API reloaded the TLS certificate");
+                    ok(json!({ "reloaded": true }))
+                }
+                Err(err) => error(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+            },
+            (&Method::GET, ["drain"]) => ok(self.drain_json()),
+            (&Method::POST, ["drain"]) => match query.and_then(|query| query.strip_prefix("deadline=")) {
+                Some(secs) => match secs.parse() {
+                    Ok(secs) => {
+                        let handle = self.handle.clone();
+                        let logger = self.logger.clone();
+                        slog::info!(logger, "Admin API put the server in drain mode with a deadline of {} seconds", secs);
+                        tokio::spawn(async move {
+                            let terminated = handle.drain_for(Duration::from_secs(secs)).await;
+                            slog::info!(logger, "Drain ended, terminated {} sessions", terminated);
+                        });
+                        ok(self.drain_json())
+                    }
+                    Err(_) => error(StatusCode::BAD_REQUEST, format!("invalid deadline {}", secs)),
+                },
+                None => {
+                    self.handle.drain();
+                    slog::info!(self.logger, "Admin API put the server in drain mode");
+                    ok(self.drain_json())
+                }
+            },
+            (&Method::DELETE, ["drain"]) => {
+                self.handle.resume();
+                slog::info!(self.logger, "Admin API took the server out of drain mode");
+                ok(self.drain_json())
+            }
+            _ => error(StatusCode::NOT_FOUND, format!("no route {} {}", method, path)),
+        }
+    }
+
+    fn drain_json(&self) -> Value {
+        json!({ "draining": self.handle.is_draining(), "sessions": self.handle.sessions().len() })
+    }
+}
+
+fn io_error(err: hyper::Error) -> ServerError {
+    std::io::Error::new(std::io::ErrorKind::Other, err).into()
+}
+
+fn ok(body: Value) -> (StatusCode, Value) {
+    (StatusCode::OK, body)
+}
+
+fn error(status: StatusCode, message: String) -> (StatusCode, Value) {
+    (status, json!({ "error": message }))
+}
+
+fn time_json(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+fn session_json(session: &SessionInfo) -> Value {
+    json!({
+        "id": session.id,
+        "username": session.username,
+        "source": session.source.to_string(),
+        "started": time_json(session.started),
+        "bytes": session.bytes,
+        "transfer": session.transfer.as_ref().map(transfer_json),
+    })
+}
+
+fn transfer_json(transfer: &TransferInfo) -> Value {
+    json!({
+        "command": transfer.command,
+        "path": transfer.path,
+        "bytes": transfer.bytes,
+        "bytes_per_second": transfer.bytes_per_second(),
+        "started": time_json(transfer.started),
+    })
+}
+
+fn user_json(user: &UserStats) -> Value {
+    json!({
+        "username": user.username,
+        "active_sessions": user.active_sessions,
+        "total_sessions": user.total_sessions,
+        "bytes": user.bytes,
+    })
+}
+
+// Returned when the API would listen on an address that isn't a loopback address without a token.
+#[derive(Error, Debug)]
+#[error("the admin API needs a token to listen on {0}, which isn't a loopback address")]
+pub(super) struct TokenRequired(SocketAddr);
+
+impl Debug for AdminApi {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AdminApi")
+            .field("handle", &self.handle)
+            .field("token", &self.token.as_ref().map(|_| "********"))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AdminApi;
+    use crate::server::{ftpserver::handle::ServerHandle, sessions::SessionRegistry, tls::FtpsConfig};
+    use hyper::{header::AUTHORIZATION, Body, Method, Request, StatusCode};
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    #[test]
+    fn routes_to_the_server_handle() {
+        let handle = ServerHandle::new(Arc::new(SessionRegistry::default()), FtpsConfig::Off);
+        let api = AdminApi::new(handle.clone());
+        assert_eq!(api.route(&Method::GET, "/sessions", None), (StatusCode::OK, json!([])));
+        assert_eq!(
+            api.route(&Method::POST, "/bans/192.0.2.7", None),
+            (StatusCode::OK, json!({ "banned": "192.0.2.7", "terminated_sessions": 0 }))
+        );
+        assert_eq!(api.route(&Method::GET, "/bans/", None), (StatusCode::OK, json!(["192.0.2.7"])));
+        assert_eq!(api.route(&Method::POST, "/bans/nonsense", None).0, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            api.route(&Method::POST, "/drain", None),
+            (StatusCode::OK, json!({ "draining": true, "sessions": 0 }))
+        );
+        assert!(handle.is_draining());
+        assert_eq!(api.route(&Method::POST, "/tls/reload", None).0, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(api.route(&Method::DELETE, "/sessions/0x1", None).0, StatusCode::NOT_FOUND);
+        assert_eq!(api.route(&Method::PUT, "/users", None).0, StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn checks_the_token() {
+        let api = AdminApi::new(ServerHandle::new(Arc::new(SessionRegistry::default()), FtpsConfig::Off)).token("s3cr3t");
+        let request = |authorization: &str| Request::builder().header(AUTHORIZATION, authorization).body(Body::empty()).unwrap();
+        assert!(api.authorized(&request("Bearer s3cr3t")));
+        assert!(!api.authorized(&request("Bearer s3cr3")));
+        assert!(!api.authorized(&request("Bearer s3cr3t!")));
+        assert!(!api.authorized(&Request::new(Body::empty())));
+    }
+
+    #[tokio::test]
+    async fn refuses_public_addresses_without_a_token() {
+        let api = AdminApi::new(ServerHandle::new(Arc::new(SessionRegistry::default()), FtpsConfig::Off));
+        assert!(api.listen("0.0.0.0:0").await.is_err());
+    }
+}
@@ -1,17 +1,34 @@
 //! Represents the chosen options that the libunftp user opted for.
 
-use crate::notification::{DataListener, PresenceListener};
+use super::{handle::RuntimeOptions, middleware::Middleware, site::SiteCommandRegistry};
+use crate::notification::{DataListener, PresenceListener, TransferLogListener, UploadHook, UploadScanner};
 use crate::options::ActivePassiveMode;
 use crate::storage::Metadata;
 use crate::{
     auth::Authenticator,
     auth::UserDetail,
-    options::{FtpsRequired, PassiveHost, SiteMd5},
+    options::{
+        AccessMode, CachedConnectionPolicy, ClientQuirks, CommandLimits, CommandPolicy, DataConnectionSource, Dotfiles, Encoding, EventDelivery, FtpsRequired,
+        GreetingFn, ListingOrder, MessageCatalog, ModeZ, PartialUploads, PassivePortMapper, RecursiveListing, Redaction, SessionCache, SiteMd5, SocketOptions,
+        TlsFirst, UploadChecksum, UploadConflicts,
+    },
     server::controlchan,
+    server::sessions::SessionRegistry,
     server::tls::FtpsConfig,
-    storage::StorageBackend,
+    storage::{dotfiles::DotfileFilter, listing::DirectoryListing, upload_only::UploadOnlyFilter, AtomicUploads, PathFilter, StorageBackend},
 };
-use std::{ops::Range, sync::Arc, time::Duration};
+#[cfg(feature = "proxy-protocol")]
+use std::ops::Range;
+use std::{
+    net::Ipv4Addr,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio::runtime::Handle;
+
+// The storage back-end as sessions see it: the one chosen by the libunftp user, wrapped in the
+// layers that implement the server-wide storage options.
+pub(super) type SessionStorage<Storage> = UploadOnlyFilter<DirectoryListing<DotfileFilter<AtomicUploads<Storage>>>>;
 
 // Holds the options the libunftp user opted for.
 pub struct OptionsHolder<Storage, User>
@@ -21,23 +38,65 @@ where
 {
     pub storage: Arc<dyn (Fn() -> Storage) + Send + Sync>,
     pub greeting: &'static str,
+    pub encoding: Encoding,
+    pub data_connection_source: DataConnectionSource,
+    pub control_socket_options: SocketOptions,
+    pub data_socket_options: SocketOptions,
+    pub data_bind_address: Option<Ipv4Addr>,
+    pub passive_port_mapper: Option<Arc<dyn PassivePortMapper>>,
+    pub data_runtime: Option<Handle>,
+    pub recursive_listing: RecursiveListing,
+    pub client_quirks: ClientQuirks,
+    pub mdtm_setter: bool,
+    pub redaction: Redaction,
+    pub middleware: Arc<Vec<Arc<dyn Middleware>>>,
+    pub site_commands: SiteCommandRegistry<Storage, User>,
+    pub login_message: Option<String>,
+    pub greeting_provider: Option<GreetingFn>,
+    pub message_catalog: Option<Arc<dyn MessageCatalog>>,
+    pub access_mode: AccessMode,
+    pub command_policy: CommandPolicy,
+    pub mode_z: ModeZ,
+    pub upload_scanner: Option<Arc<dyn UploadScanner>>,
+    pub upload_hook: Option<Arc<dyn UploadHook>>,
+    pub transfer_log: Option<Arc<dyn TransferLogListener>>,
+    pub session_cache: Option<Arc<dyn SessionCache>>,
+    pub progress_interval: Option<Duration>,
+    pub event_delivery: Option<EventDelivery>,
+    pub upload_checksum: UploadChecksum,
+    pub atomic_uploads: bool,
+    pub partial_uploads: PartialUploads,
+    pub upload_conflicts: UploadConflicts,
+    pub dotfiles: Dotfiles,
+    pub listing_order: ListingOrder,
+    pub listing_dot_entries: bool,
     pub authenticator: Arc<dyn Authenticator<User>>,
+    // Only the proxy loop reads this, sessions get the passive ports from the runtime options.
+    #[cfg(feature = "proxy-protocol")]
     pub passive_ports: Range<u16>,
-    pub passive_host: PassiveHost,
     pub ftps_config: FtpsConfig,
     pub collect_metrics: bool,
-    pub idle_session_timeout: Duration,
+    pub data_stall_timeout: Option<Duration>,
+    pub max_session_duration: Option<Duration>,
+    pub command_limits: CommandLimits,
+    pub max_unauthenticated_sessions: Option<usize>,
+    pub connection_policy: Option<Arc<CachedConnectionPolicy>>,
     pub logger: slog::Logger,
     pub ftps_required_control_chan: FtpsRequired,
     pub ftps_required_data_chan: FtpsRequired,
+    pub ftps_tls_first: TlsFirst,
+    pub ftps_handshake_timeout: Duration,
     pub site_md5: SiteMd5,
     pub data_listener: Arc<dyn DataListener>,
     pub presence_listener: Arc<dyn PresenceListener>,
+    pub path_filter: Arc<dyn PathFilter>,
     pub active_passive_mode: ActivePassiveMode,
     pub binder: Arc<std::sync::Mutex<Option<Box<dyn crate::options::Binder>>>>,
+    pub sessions: Arc<SessionRegistry>,
+    pub runtime_options: Arc<RwLock<RuntimeOptions>>,
 }
 
-impl<Storage, User> From<&OptionsHolder<Storage, User>> for controlchan::LoopConfig<Storage, User>
+impl<Storage, User> From<&OptionsHolder<Storage, User>> for controlchan::LoopConfig<SessionStorage<Storage>, User>
 where
     User: UserDetail + 'static,
     Storage: StorageBackend<User> + 'static,
@@ -46,23 +105,73 @@ where
     fn from(server: &OptionsHolder<Storage, User>) -> Self {
         // So this is when you create a new storage backend?
         // XXX Shouldn't instantiate storage until _after_ successful auth.
+        let runtime = server.runtime_options.read().unwrap().clone();
+        let greeting_provider = match runtime.greeting {
+            Some(greeting) => Some(GreetingFn(Arc::new(move |_: &_| greeting.clone()))),
+            None => server.greeting_provider.clone(),
+        };
         controlchan::LoopConfig {
             authenticator: server.authenticator.clone(),
-            storage: (server.storage)(),
+            storage: UploadOnlyFilter::new(
+                DirectoryListing::new(
+                    DotfileFilter::new(AtomicUploads::with_enabled((server.storage)(), server.atomic_uploads), server.dotfiles),
+                    server.listing_order,
+                    server.listing_dot_entries,
+                ),
+                server.access_mode,
+            ),
             ftps_config: server.ftps_config.clone(),
             collect_metrics: server.collect_metrics,
             greeting: server.greeting,
-            idle_session_timeout: server.idle_session_timeout,
-            passive_ports: server.passive_ports.clone(),
-            passive_host: server.passive_host.clone(),
+            encoding: server.encoding,
+            data_connection_source: server.data_connection_source,
+            control_socket_options: server.control_socket_options,
+            data_socket_options: server.data_socket_options,
+            data_bind_address: server.data_bind_address,
+            passive_port_mapper: server.passive_port_mapper.clone(),
+            data_runtime: server.data_runtime.clone(),
+            recursive_listing: server.recursive_listing,
+            client_quirks: server.client_quirks,
+            mdtm_setter: server.mdtm_setter,
+            redaction: server.redaction,
+            middleware: server.middleware.clone(),
+            login_message: server.login_message.clone(),
+            greeting_provider,
+            message_catalog: server.message_catalog.clone(),
+            access_mode: server.access_mode,
+            command_policy: server.command_policy.clone(),
+            mode_z: server.mode_z,
+            upload_scanner: server.upload_scanner.clone(),
+            upload_hook: server.upload_hook.clone(),
+            transfer_log: server.transfer_log.clone(),
+            session_cache: server.session_cache.clone(),
+            progress_interval: server.progress_interval,
+            event_delivery: server.event_delivery,
+            upload_checksum: server.upload_checksum,
+            partial_uploads: server.partial_uploads,
+            upload_conflicts: server.upload_conflicts.clone(),
+            idle_session_timeout: runtime.idle_session_timeout,
+            data_stall_timeout: server.data_stall_timeout,
+            max_session_duration: server.max_session_duration,
+            command_limits: server.command_limits,
+            max_unauthenticated_sessions: server.max_unauthenticated_sessions,
+            connection_policy: server.connection_policy.clone(),
+            passive_ports: runtime.passive_ports,
+            passive_host: runtime.passive_host,
+            max_connections: runtime.max_connections,
             logger: server.logger.new(slog::o!()),
             ftps_required_control_chan: server.ftps_required_control_chan,
             ftps_required_data_chan: server.ftps_required_data_chan,
+            ftps_tls_first: server.ftps_tls_first,
+            ftps_handshake_timeout: server.ftps_handshake_timeout,
             site_md5: server.site_md5,
             data_listener: server.data_listener.clone(),
             presence_listener: server.presence_listener.clone(),
+            path_filter: server.path_filter.clone(),
             active_passive_mode: server.active_passive_mode,
             binder: server.binder.clone(),
+            sessions: server.sessions.clone(),
+            site_commands: server.site_commands.clone().into_session_commands(server.path_filter.clone()),
         }
     }
 }
@@ -0,0 +1,503 @@
+//! Contains the [`ServerConfig`] that can be read from a configuration file with serde and turned
+//! into a [`ServerBuilder`](crate::ServerBuilder) with
+//! [`ServerBuilder::from_config`](crate::ServerBuilder::from_config).
+//!
+//! The settings are named after the builder methods that they stand for, and all of them may be
+//! left out, in which case the builder's defaults apply. Durations are given in seconds. Options
+//! with a fixed set of values are spelled in snake case, e.g. `upload_only` for
+//! [`AccessMode::UploadOnly`](crate::options::AccessMode::UploadOnly).
+//!
+//! An example in TOML:
+//!
+//! ```toml
+//! greeting = "Welcome to our FTP server"
+//! passive_ports = { start = 50000, end = 51000 }
+//! passive_host = "ftp.example.com"
+//! idle_session_timeout = 600
+//! access_mode = "upload_only"
+//! mode_z = { enabled = { level = 6 } }
+//!
+//! [ftps]
+//! certs_file = "/etc/unftp/server.certs"
+//! key_file = "/etc/unftp/server.key"
+//! required_control_chan = "accounts"
+//!
+//! [failed_logins]
+//! max_attempts = 5
+//! expires_after = 300
+//! block_by = "ip"
+//!
+//! [backend]
+//! root = "/srv/ftp"
+//! ```
+
+use super::ServerBuilder;
+use crate::{
+    auth::{anonymous::AnonymousAuthenticator, Authenticator, UserDetail},
+    options::{
+        AccessMode, ActivePassiveMode, ClientQuirks, CommandLimits, DataConnectionSource, Dotfiles, Encoding, EventDelivery, FailedLoginsBlock,
+        FailedLoginsPolicy, FtpsClientAuth, FtpsRequired, ListingOrder, MemorySessionCache, ModeZ, PartialUploads, RecursiveListing, Redaction, SiteMd5,
+        SocketOptions, TlsFirst, UploadChecksum, UploadConflictPolicy,
+    },
+    storage::{Metadata, StorageBackend},
+};
+use serde::Deserialize;
+use std::{net::Ipv4Addr, ops::Range, path::PathBuf, time::Duration};
+
+/// The settings of a server, to be deserialized from e.g. a TOML or YAML file. See the
+/// [module documentation](self) for an example.
+///
+/// The `backend` section is deserialized into the type `B` and handed to the function that
+/// creates the storage back-end, so that the back-end can be chosen and configured in the same
+/// file. It needs to implement `Default`, which is used when the section is left out.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig<B = ()> {
+    /// See [`ServerBuilder::greeting`](crate::ServerBuilder::greeting).
+    pub greeting: Option<String>,
+    /// See [`ServerBuilder::login_message`](crate::ServerBuilder::login_message).
+    pub login_message: Option<String>,
+    /// See [`ServerBuilder::encoding`](crate::ServerBuilder::encoding).
+    pub encoding: Option<Encoding>,
+    /// See [`ServerBuilder::passive_ports`](crate::ServerBuilder::passive_ports).
+    pub passive_ports: Option<Range<u16>>,
+    /// An IPv4 address or a DNS name, see [`ServerBuilder::passive_host`](crate::ServerBuilder::passive_host).
+    pub passive_host: Option<String>,
+    /// See [`ServerBuilder::active_passive_mode`](crate::ServerBuilder::active_passive_mode).
+    pub active_passive_mode: Option<ActivePassiveMode>,
+    /// In seconds, see [`ServerBuilder::idle_session_timeout`](crate::ServerBuilder::idle_session_timeout).
+    pub idle_session_timeout: Option<u64>,
+    /// In seconds, see [`ServerBuilder::data_stall_timeout`](crate::ServerBuilder::data_stall_timeout).
+    pub data_stall_timeout: Option<u64>,
+    /// In seconds, see [`ServerBuilder::max_session_duration`](crate::ServerBuilder::max_session_duration).
+    pub max_session_duration: Option<u64>,
+    /// See [`ServerBuilder::atomic_uploads`](crate::ServerBuilder::atomic_uploads).
+    pub atomic_uploads: Option<bool>,
+    /// See [`ServerBuilder::partial_uploads`](crate::ServerBuilder::partial_uploads).
+    pub partial_uploads: Option<PartialUploads>,
+    /// See [`ServerBuilder::upload_conflict_policy`](crate::ServerBuilder::upload_conflict_policy).
+    pub upload_conflict_policy: Option<UploadConflictPolicy>,
+    /// See [`ServerBuilder::dotfiles`](crate::ServerBuilder::dotfiles).
+    pub dotfiles: Option<Dotfiles>,
+    /// See [`ServerBuilder::access_mode`](crate::ServerBuilder::access_mode).
+    pub access_mode: Option<AccessMode>,
+    /// See [`ServerBuilder::upload_checksum`](crate::ServerBuilder::upload_checksum).
+    pub upload_checksum: Option<UploadChecksum>,
+    /// See [`ServerBuilder::mode_z`](crate::ServerBuilder::mode_z).
+    pub mode_z: Option<ModeZ>,
+    /// See [`ServerBuilder::recursive_listing`](crate::ServerBuilder::recursive_listing).
+    pub recursive_listing: Option<RecursiveListing>,
+    /// See [`ServerBuilder::listing_order`](crate::ServerBuilder::listing_order).
+    pub listing_order: Option<ListingOrder>,
+    /// See [`ServerBuilder::listing_dot_entries`](crate::ServerBuilder::listing_dot_entries).
+    pub listing_dot_entries: Option<bool>,
+    /// See [`ServerBuilder::client_quirks`](crate::ServerBuilder::client_quirks).
+    pub client_quirks: Option<ClientQuirks>,
+    /// See [`ServerBuilder::mdtm_setter`](crate::ServerBuilder::mdtm_setter).
+    pub mdtm_setter: Option<bool>,
+    /// See [`ServerBuilder::redaction`](crate::ServerBuilder::redaction).
+    pub redaction: Option<Redaction>,
+    /// See [`ServerBuilder::data_connection_source`](crate::ServerBuilder::data_connection_source).
+    pub data_connection_source: Option<DataConnectionSource>,
+    /// See [`ServerBuilder::control_socket_options`](crate::ServerBuilder::control_socket_options).
+    pub control_socket: Option<SocketSettings>,
+    /// See [`ServerBuilder::data_socket_options`](crate::ServerBuilder::data_socket_options).
+    pub data_socket: Option<SocketSettings>,
+    /// See [`ServerBuilder::data_bind_address`](crate::ServerBuilder::data_bind_address).
+    pub data_bind_address: Option<Ipv4Addr>,
+    /// See [`ServerBuilder::sitemd5`](crate::ServerBuilder::sitemd5).
+    pub sitemd5: Option<SiteMd5>,
+    /// See [`ServerBuilder::max_connections`](crate::ServerBuilder::max_connections).
+    pub max_connections: Option<usize>,
+    /// See [`ServerBuilder::command_limits`](crate::ServerBuilder::command_limits).
+    pub command_limits: Option<CommandLimits>,
+    /// See [`ServerBuilder::max_unauthenticated_sessions`](crate::ServerBuilder::max_unauthenticated_sessions).
+    pub max_unauthenticated_sessions: Option<usize>,
+    /// See [`ServerBuilder::transfer_progress_interval`](crate::ServerBuilder::transfer_progress_interval).
+    pub transfer_progress_interval: Option<u64>,
+    /// In seconds, how long a [`MemorySessionCache`] remembers interrupted downloads, see
+    /// [`ServerBuilder::session_cache`](crate::ServerBuilder::session_cache).
+    pub session_cache: Option<u64>,
+    /// See [`ServerBuilder::event_delivery`](crate::ServerBuilder::event_delivery).
+    pub event_delivery: Option<EventDeliverySettings>,
+    /// See [`ServerBuilder::metrics`](crate::ServerBuilder::metrics).
+    #[cfg(feature = "prometheus")]
+    pub metrics: Option<bool>,
+    /// The external control port, see [`ServerBuilder::proxy_protocol_mode`](crate::ServerBuilder::proxy_protocol_mode).
+    #[cfg(feature = "proxy-protocol")]
+    pub proxy_protocol_mode: Option<u16>,
+    /// Enables FTPS.
+    pub ftps: Option<FtpsSettings>,
+    /// See [`ServerBuilder::failed_logins_policy`](crate::ServerBuilder::failed_logins_policy).
+    pub failed_logins: Option<FailedLoginsSettings>,
+    /// The settings of the storage back-end.
+    #[serde(default)]
+    pub backend: B,
+}
+
+/// The `ftps` section of the [`ServerConfig`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FtpsSettings {
+    /// The PEM file with the certificate chain, see [`ServerBuilder::ftps`](crate::ServerBuilder::ftps).
+    pub certs_file: PathBuf,
+    /// The PEM file with the private key, see [`ServerBuilder::ftps`](crate::ServerBuilder::ftps).
+    pub key_file: PathBuf,
+    /// See [`ServerBuilder::ftps_required`](crate::ServerBuilder::ftps_required).
+    pub required_control_chan: Option<FtpsRequired>,
+    /// See [`ServerBuilder::ftps_required`](crate::ServerBuilder::ftps_required).
+    pub required_data_chan: Option<FtpsRequired>,
+    /// See [`ServerBuilder::ftps_tls_first`](crate::ServerBuilder::ftps_tls_first).
+    pub tls_first: Option<TlsFirst>,
+    /// See [`ServerBuilder::ftps_handshake_timeout`](crate::ServerBuilder::ftps_handshake_timeout).
+    pub handshake_timeout: Option<u64>,
+    /// See [`ServerBuilder::ftps_client_auth`](crate::ServerBuilder::ftps_client_auth).
+    pub client_auth: Option<FtpsClientAuth>,
+    /// See [`ServerBuilder::ftps_trust_store`](crate::ServerBuilder::ftps_trust_store).
+    pub trust_store: Option<PathBuf>,
+}
+
+/// The `failed_logins` section of the [`ServerConfig`], see [`FailedLoginsPolicy`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FailedLoginsSettings {
+    /// The number of consecutive failed logins after which the client is blocked.
+    pub max_attempts: u32,
+    /// In seconds, how long after the last failed login the block is lifted.
+    pub expires_after: u64,
+    /// What is blocked.
+    pub block_by: FailedLoginsBlock,
+}
+
+/// The `control_socket` and `data_socket` sections of the [`ServerConfig`], see [`SocketOptions`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SocketSettings {
+    /// Whether to set `TCP_NODELAY`.
+    pub nodelay: Option<bool>,
+    /// In seconds, how long the connection is idle before keepalive probes are sent.
+    pub keepalive: Option<u64>,
+    /// The size in bytes of the send buffer.
+    pub send_buffer_size: Option<u32>,
+    /// The size in bytes of the receive buffer.
+    pub recv_buffer_size: Option<u32>,
+}
+
+impl From<SocketSettings> for SocketOptions {
+    fn from(settings: SocketSettings) -> Self {
+        let mut options = SocketOptions::new();
+        if let Some(enabled) = settings.nodelay {
+            options = options.nodelay(enabled);
+        }
+        if let Some(secs) = settings.keepalive {
+            options = options.keepalive(Duration::from_secs(secs));
+        }
+        if let Some(bytes) = settings.send_buffer_size {
+            options = options.send_buffer_size(bytes);
+        }
+        if let Some(bytes) = settings.recv_buffer_size {
+            options = options.recv_buffer_size(bytes);
+        }
+        options
+    }
+}
+
+/// The `event_delivery` section of the [`ServerConfig`], see [`EventDelivery`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EventDeliverySettings {
+    /// In seconds, how long to wait for a listener to handle an event.
+    pub timeout: u64,
+    /// How many times to try to deliver an event in total.
+    pub attempts: Option<u32>,
+    /// In seconds, how long to wait before the first retry. Defaults to 1.
+    pub backoff: Option<u64>,
+    /// Whether to fail the transfer when its event couldn't be delivered.
+    pub fail_transfer: Option<bool>,
+}
+
+impl<Storage, User> ServerBuilder<Storage, User>
+where
+    Storage: StorageBackend<User> + 'static,
+    Storage::Metadata: Metadata,
+    User: UserDetail + 'static,
+{
+    /// Constructs a [`ServerBuilder`] from the given [`ServerConfig`], with an
+    /// [`AnonymousAuthenticator`](crate::auth::AnonymousAuthenticator). The storage back-end is
+    /// created with the given function from the `backend` section of the configuration. Options
+    /// that can't be expressed in a configuration file, like the authenticator or listeners, can be
+    /// set on the returned builder.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::{config::ServerConfig, ServerBuilder};
+    /// use std::path::PathBuf;
+    /// use unftp_sbe_fs::Filesystem;
+    ///
+    /// #[derive(Debug, Default, serde::Deserialize)]
+    /// struct Backend {
+    ///     root: PathBuf,
+    /// }
+    ///
+    /// let config: ServerConfig<Backend> = serde_json::from_str(r#"{
+    ///     "passive_ports": { "start": 50000, "end": 51000 },
+    ///     "idle_session_timeout": 600,
+    ///     "backend": { "root": "/srv/ftp" }
+    /// }"#).unwrap();
+    /// let server = ServerBuilder::from_config(config, |backend: &Backend| Filesystem::new(&backend.root))
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn from_config<B, F>(config: ServerConfig<B>, storage: F) -> Self
+    where
+        AnonymousAuthenticator: Authenticator<User>,
+        B: Send + Sync + 'static,
+        F: Fn(&B) -> Storage + Send + Sync + 'static,
+    {
+        let ServerConfig {
+            greeting,
+            login_message,
+            encoding,
+            passive_ports,
+            passive_host,
+            active_passive_mode,
+            idle_session_timeout,
+            data_stall_timeout,
+            max_session_duration,
+            atomic_uploads,
+            partial_uploads,
+            upload_conflict_policy,
+            dotfiles,
+            access_mode,
+            upload_checksum,
+            mode_z,
+            recursive_listing,
+            listing_order,
+            listing_dot_entries,
+            client_quirks,
+            mdtm_setter,
+            redaction,
+            data_connection_source,
+            control_socket,
+            data_socket,
+            data_bind_address,
+            sitemd5,
+            max_connections,
+            command_limits,
+            max_unauthenticated_sessions,
+            transfer_progress_interval,
+            session_cache,
+            event_delivery,
+            #[cfg(feature = "prometheus")]
+            metrics,
+            #[cfg(feature = "proxy-protocol")]
+            proxy_protocol_mode,
+            ftps,
+            failed_logins,
+            backend,
+        } = config;
+
+        let mut builder = ServerBuilder::new(Box::new(move || storage(&backend)));
+        if let Some(greeting) = greeting {
+            // The greeting method wants a static string, which a configuration file can't provide.
+            builder = builder.greeting_provider(move |_: &_| greeting.clone());
+        }
+        if let Some(message) = login_message {
+            builder = builder.login_message(message);
+        }
+        if let Some(encoding) = encoding {
+            builder = builder.encoding(encoding);
+        }
+        if let Some(range) = passive_ports {
+            builder = builder.passive_ports(range);
+        }
+        if let Some(host) = passive_host {
+            builder = builder.passive_host(host.as_str());
+        }
+        if let Some(mode) = active_passive_mode {
+            builder = builder.active_passive_mode(mode);
+        }
+        if let Some(secs) = idle_session_timeout {
+            builder = builder.idle_session_timeout(secs);
+        }
+        if let Some(secs) = data_stall_timeout {
+            builder = builder.data_stall_timeout(secs);
+        }
+        if let Some(secs) = max_session_duration {
+            builder = builder.max_session_duration(secs);
+        }
+        if let Some(enabled) = atomic_uploads {
+            builder = builder.atomic_uploads(enabled);
+        }
+        if let Some(partial_uploads) = partial_uploads {
+            builder = builder.partial_uploads(partial_uploads);
+        }
+        if let Some(policy) = upload_conflict_policy {
+            builder = builder.upload_conflict_policy(policy);
+        }
+        if let Some(dotfiles) = dotfiles {
+            builder = builder.dotfiles(dotfiles);
+        }
+        if let Some(access_mode) = access_mode {
+            builder = builder.access_mode(access_mode);
+        }
+        if let Some(checksum) = upload_checksum {
+            builder = builder.upload_checksum(checksum);
+        }
+        if let Some(mode_z) = mode_z {
+            builder = builder.mode_z(mode_z);
+        }
+        if let Some(recursive_listing) = recursive_listing {
+            builder = builder.recursive_listing(recursive_listing);
+        }
+        if let Some(order) = listing_order {
+            builder = builder.listing_order(order);
+        }
+        if let Some(enabled) = listing_dot_entries {
+            builder = builder.listing_dot_entries(enabled);
+        }
+        if let Some(client_quirks) = client_quirks {
+            builder = builder.client_quirks(client_quirks);
+        }
+        if let Some(enabled) = mdtm_setter {
+            builder = builder.mdtm_setter(enabled);
+        }
+        if let Some(redaction) = redaction {
+            builder = builder.redaction(redaction);
+        }
+        if let Some(source) = data_connection_source {
+            builder = builder.data_connection_source(source);
+        }
+        if let Some(sitemd5) = sitemd5 {
+            builder = builder.sitemd5(sitemd5);
+        }
+        if let Some(max) = max_connections {
+            builder = builder.max_connections(max);
+        }
+        if let Some(limits) = command_limits {
+            builder = builder.command_limits(limits);
+        }
+        if let Some(max) = max_unauthenticated_sessions {
+            builder = builder.max_unauthenticated_sessions(max);
+        }
+        if let Some(secs) = transfer_progress_interval {
+            builder = builder.transfer_progress_interval(secs);
+        }
+        if let Some(secs) = session_cache {
+            builder = builder.session_cache(MemorySessionCache::new(Duration::from_secs(secs)));
+        }
+        if let Some(settings) = event_delivery {
+            let mut delivery = EventDelivery::new(Duration::from_secs(settings.timeout));
+            if let Some(attempts) = settings.attempts {
+                delivery = delivery.retries(attempts, Duration::from_secs(settings.backoff.unwrap_or(1)));
+            }
+            if settings.fail_transfer.unwrap_or(false) {
+                delivery = delivery.fail_transfer();
+            }
+            builder = builder.event_delivery(delivery);
+        }
+        #[cfg(feature = "prometheus")]
+        if metrics == Some(true) {
+            builder = builder.metrics();
+        }
+        #[cfg(feature = "proxy-protocol")]
+        if let Some(port) = proxy_protocol_mode {
+            builder = builder.proxy_protocol_mode(port);
+        }
+        if let Some(ftps) = ftps {
+            builder = builder.ftps(ftps.certs_file, ftps.key_file);
+            if let Some(required) = ftps.required_control_chan {
+                builder.ftps_required_control_chan = required;
+            }
+            if let Some(required) = ftps.required_data_chan {
+                builder.ftps_required_data_chan = required;
+            }
+            if let Some(tls_first) = ftps.tls_first {
+                builder = builder.ftps_tls_first(tls_first);
+            }
+            if let Some(secs) = ftps.handshake_timeout {
+                builder = builder.ftps_handshake_timeout(secs);
+            }
+            if let Some(client_auth) = ftps.client_auth {
+                builder = builder.ftps_client_auth(client_auth);
+            }
+            if let Some(trust_store) = ftps.trust_store {
+                builder = builder.ftps_trust_store(trust_store);
+            }
+        }
+        if let Some(settings) = control_socket {
+            builder = builder.control_socket_options(settings.into());
+        }
+        if let Some(settings) = data_socket {
+            builder = builder.data_socket_options(settings.into());
+        }
+        if let Some(address) = data_bind_address {
+            builder = builder.data_bind_address(address);
+        }
+        if let Some(policy) = failed_logins {
+            builder = builder.failed_logins_policy(FailedLoginsPolicy::new(
+                policy.max_attempts,
+                Duration::from_secs(policy.expires_after),
+                policy.block_by,
+            ));
+        }
+        builder
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ServerConfig;
+    use crate::options::{AccessMode, CommandLimits, FailedLoginsBlock, FtpsRequired, ModeZ, SocketOptions};
+    use pretty_assertions::assert_eq;
+    use std::{net::Ipv4Addr, path::PathBuf, time::Duration};
+
+    #[derive(Debug, Default, serde::Deserialize, PartialEq)]
+    struct Backend {
+        root: PathBuf,
+    }
+
+    #[test]
+    fn deserializes_a_config() {
+        let config: ServerConfig<Backend> = serde_json::from_str(
+            r#"{
+                "greeting": "Hello",
+                "passive_ports": { "start": 50000, "end": 51000 },
+                "access_mode": "upload_only",
+                "mode_z": { "enabled": { "level": 6 } },
+                "ftps": { "certs_file": "server.certs", "key_file": "server.key", "required_control_chan": "accounts" },
+                "failed_logins": { "max_attempts": 5, "expires_after": 300, "block_by": "user_and_ip" },
+                "command_limits": { "commands_per_second": 20 },
+                "data_socket": { "nodelay": true, "keepalive": 60, "recv_buffer_size": 4194304 },
+                "data_bind_address": "10.0.1.5",
+                "backend": { "root": "/srv/ftp" }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(Some("Hello".to_string()), config.greeting);
+        assert_eq!(Some(50000..51000), config.passive_ports);
+        assert_eq!(Some(AccessMode::UploadOnly), config.access_mode);
+        assert_eq!(Some(ModeZ::Enabled { level: 6 }), config.mode_z);
+        let ftps = config.ftps.unwrap();
+        assert_eq!(PathBuf::from("server.key"), ftps.key_file);
+        assert_eq!(Some(FtpsRequired::Accounts), ftps.required_control_chan);
+        assert_eq!(None, ftps.required_data_chan);
+        assert!(matches!(config.failed_logins.unwrap().block_by, FailedLoginsBlock::UserAndIP));
+        assert_eq!(Some(CommandLimits::new().commands_per_second(20)), config.command_limits);
+        assert_eq!(
+            SocketOptions::new().nodelay(true).keepalive(Duration::from_secs(60)).recv_buffer_size(4 << 20),
+            config.data_socket.unwrap().into()
+        );
+        assert_eq!(Some(Ipv4Addr::new(10, 0, 1, 5)), config.data_bind_address);
+        assert_eq!(PathBuf::from("/srv/ftp"), config.backend.root);
+    }
+
+    #[test]
+    fn leaves_out_what_is_not_configured() {
+        let config: ServerConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(None, config.idle_session_timeout);
+        assert!(config.ftps.is_none());
+        assert!(serde_json::from_str::<ServerConfig>(r#"{ "idle_timeout": 600 }"#).is_err());
+    }
+}
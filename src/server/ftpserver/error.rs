@@ -3,6 +3,7 @@
 use crate::BoxError;
 
 use std::net::AddrParseError;
+use std::ops::Range;
 use thiserror::Error;
 
 /// Error returned by the [`Server.listen`](crate::Server::listen()) method
@@ -41,6 +42,35 @@ impl From<super::tls::ConfigError> for ServerError {
     }
 }
 
+impl From<super::tls::FtpsNotAvailable> for ServerError {
+    fn from(e: super::tls::FtpsNotAvailable) -> Self {
+        ServerError::new(e.to_string(), e)
+    }
+}
+
+impl From<super::privileges::PrivilegeError> for ServerError {
+    fn from(e: super::privileges::PrivilegeError) -> Self {
+        ServerError::new(e.to_string(), e)
+    }
+}
+
+#[cfg(feature = "admin")]
+impl From<super::admin::TokenRequired> for ServerError {
+    fn from(e: super::admin::TokenRequired) -> Self {
+        ServerError::new(e.to_string(), e)
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("the passive port range {0:?} is empty")]
+pub(crate) struct EmptyPassivePorts(pub Range<u16>);
+
+impl From<EmptyPassivePorts> for ServerError {
+    fn from(e: EmptyPassivePorts) -> Self {
+        ServerError::new(e.to_string(), e)
+    }
+}
+
 #[derive(Error, Debug)]
 #[error("shutdown error: {msg}")]
 pub struct ShutdownError {
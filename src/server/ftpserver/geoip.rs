@@ -0,0 +1,151 @@
+//! Contains the [`GeoIpPolicy`], the connection policy of the `geoip` feature.
+
+use super::options::{ConnectionDecision, ConnectionPolicy, GeoRestriction};
+use crate::auth::UserDetail;
+use async_trait::async_trait;
+use maxminddb::{geoip2, Reader};
+use std::{fmt, io, net::IpAddr, path::Path};
+
+/// A [`ConnectionPolicy`] that allows or denies clients by the country and the network
+/// (autonomous system) they connect from, as described by a [`GeoRestriction`]. The country and
+/// network are looked up in MaxMind databases in the `.mmdb` format, like the free GeoLite2
+/// databases. Users can have their own restriction, see
+/// [`UserDetail::geo_restriction`](crate::auth::UserDetail::geo_restriction), which is checked
+/// when they log in.
+///
+/// Enabled with the `geoip` feature.
+///
+/// # Example
+///
+/// ```no_run
+/// use libunftp::Server;
+/// use libunftp::options::{GeoIpPolicy, GeoRestriction};
+/// use unftp_sbe_fs::ServerExt;
+///
+/// let policy = GeoIpPolicy::new(GeoRestriction::new().allow_countries(["NL", "BE", "DE"]))
+///     .country_database("/var/lib/GeoIP/GeoLite2-Country.mmdb")
+///     .unwrap();
+/// let server = Server::with_fs("/srv/ftp").connection_policy(policy).build();
+/// ```
+pub struct GeoIpPolicy {
+    countries: Option<Reader<Vec<u8>>>,
+    asns: Option<Reader<Vec<u8>>>,
+    restriction: GeoRestriction,
+}
+
+impl GeoIpPolicy {
+    /// Creates a policy that applies the given restriction to all clients. Without databases
+    /// nothing is known about the clients, so set at least one of them.
+    pub fn new(restriction: GeoRestriction) -> Self {
+        GeoIpPolicy {
+            countries: None,
+            asns: None,
+            restriction,
+        }
+    }
+
+    /// Reads the database to look the country up in, a GeoIP2 or GeoLite2 Country or City database.
+    pub fn country_database<P: AsRef<Path>>(mut self, path: P) -> io::Result<Self> {
+        self.countries = Some(open(path)?);
+        Ok(self)
+    }
+
+    /// Reads the database to look the autonomous system number up in, a GeoLite2 ASN database.
+    pub fn asn_database<P: AsRef<Path>>(mut self, path: P) -> io::Result<Self> {
+        self.asns = Some(open(path)?);
+        Ok(self)
+    }
+
+    // Returns the ISO code of the country and the number of the autonomous system of the address,
+    // as far as they are known.
+    fn locate(&self, source: IpAddr) -> (Option<String>, Option<u32>) {
+        let country = self
+            .countries
+            .as_ref()
+            .and_then(|db| db.lookup::<geoip2::Country>(source).ok())
+            .and_then(|record| record.country?.iso_code.map(str::to_string));
+        let asn = self
+            .asns
+            .as_ref()
+            .and_then(|db| db.lookup::<geoip2::Asn>(source).ok())
+            .and_then(|record| record.autonomous_system_number);
+        (country, asn)
+    }
+
+    fn decide(&self, restriction: &GeoRestriction, source: IpAddr) -> ConnectionDecision {
+        let (country, asn) = self.locate(source);
+        match restriction.allows(country.as_deref(), asn) {
+            true => ConnectionDecision::Allow,
+            false => ConnectionDecision::Reject(format!(
+                "not allowed from country {} and AS {}",
+                country.as_deref().unwrap_or("unknown"),
+                asn.map_or_else(|| "unknown".to_string(), |asn| asn.to_string())
+            )),
+        }
+    }
+}
+
+fn open<P: AsRef<Path>>(path: P) -> io::Result<Reader<Vec<u8>>> {
+    Reader::open_readfile(path).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))
+}
+
+#[async_trait]
+impl ConnectionPolicy for GeoIpPolicy {
+    async fn check(&self, source: IpAddr) -> ConnectionDecision {
+        self.decide(&self.restriction, source)
+    }
+
+    async fn check_login(&self, source: IpAddr, user: &dyn UserDetail) -> ConnectionDecision {
+        match user.geo_restriction() {
+            Some(restriction) => self.decide(&restriction, source),
+            None => ConnectionDecision::Allow,
+        }
+    }
+}
+
+impl fmt::Debug for GeoIpPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GeoIpPolicy")
+            .field("countries", &self.countries.is_some())
+            .field("asns", &self.asns.is_some())
+            .field("restriction", &self.restriction)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GeoIpPolicy;
+    use crate::auth::UserDetail;
+    use crate::options::{ConnectionDecision, ConnectionPolicy, GeoRestriction};
+    use pretty_assertions::assert_eq;
+    use std::fmt;
+
+    #[derive(Debug)]
+    struct Traveller;
+
+    impl UserDetail for Traveller {
+        fn geo_restriction(&self) -> Option<GeoRestriction> {
+            Some(GeoRestriction::new().allow_countries(["NL"]))
+        }
+    }
+
+    impl fmt::Display for Traveller {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "Traveller")
+        }
+    }
+
+    #[tokio::test]
+    async fn applies_the_restriction_of_the_user_at_login() {
+        // Without databases nothing is known about the client, so it isn't on any allow list.
+        let policy = GeoIpPolicy::new(GeoRestriction::new().deny_countries(["XX"]));
+        let source = "192.0.2.1".parse().unwrap();
+        assert_eq!(policy.check(source).await, ConnectionDecision::Allow);
+        assert_eq!(policy.check_login(source, &crate::auth::DefaultUser).await, ConnectionDecision::Allow);
+        assert_eq!(
+            policy.check_login(source, &Traveller).await,
+            ConnectionDecision::Reject("not allowed from country unknown and AS unknown".to_string())
+        );
+    }
+}
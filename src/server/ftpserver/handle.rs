@@ -0,0 +1,257 @@
+//! Contains the [`ServerHandle`] used to manage the sessions of a running server and the
+//! [`ConfigHandle`] used to change its options.
+
+use super::{
+    error::{EmptyPassivePorts, ServerError},
+    options::PassiveHost,
+    tls::FtpsConfig,
+};
+use crate::server::sessions::{SessionInfo, SessionRegistry, UserStats};
+use std::{
+    net::IpAddr,
+    ops::Range,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+/// Manages the sessions of a [`Server`](crate::Server) while it runs, for instance from an admin
+/// panel. Obtained with [`Server::handle`](crate::Server::handle) before the server is started,
+/// it can be cloned and used for as long as the server runs.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::Server;
+/// use unftp_sbe_fs::ServerExt;
+///
+/// # async fn kick() {
+/// let server = Server::with_fs("/srv/ftp").build().unwrap();
+/// let handle = server.handle();
+/// tokio::spawn(server.listen("127.0.0.1:2121"));
+///
+/// for session in handle.sessions() {
+///     if session.username.as_deref() == Some("mallory") {
+///         handle.kill_session(&session.id);
+///     }
+/// }
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ServerHandle {
+    sessions: Arc<SessionRegistry>,
+    ftps_mode: FtpsConfig,
+}
+
+impl ServerHandle {
+    pub(super) fn new(sessions: Arc<SessionRegistry>, ftps_mode: FtpsConfig) -> Self {
+        ServerHandle { sessions, ftps_mode }
+    }
+
+    /// Lists the sessions that are connected to the server, the oldest first.
+    pub fn sessions(&self) -> Vec<SessionInfo> {
+        self.sessions.list()
+    }
+
+    /// Terminates the session with the given [id](SessionInfo::id), aborting its data transfer
+    /// if one is in progress. The client is told that the session was terminated before its
+    /// connection is closed. Returns `false` if there is no such session.
+    pub fn kill_session(&self, id: &str) -> bool {
+        self.sessions.kill(id)
+    }
+
+    /// Returns the transfer statistics of the users that logged in since the server started,
+    /// sorted by name.
+    pub fn user_stats(&self) -> Vec<UserStats> {
+        self.sessions.user_stats()
+    }
+
+    /// Refuses new connections from the given address and terminates the sessions connected
+    /// from it. Returns the number of sessions terminated. The ban lasts until
+    /// [`unban_ip`](Self::unban_ip) is called or the server stops.
+    pub fn ban_ip(&self, ip: IpAddr) -> usize {
+        self.sessions.ban(ip)
+    }
+
+    /// Lifts the ban of the given address. Returns `false` if it wasn't banned.
+    pub fn unban_ip(&self, ip: IpAddr) -> bool {
+        self.sessions.unban(ip)
+    }
+
+    /// Lists the banned addresses.
+    pub fn banned_ips(&self) -> Vec<IpAddr> {
+        self.sessions.banned()
+    }
+
+    /// Puts the server in drain mode, for a graceful rollout: new connections are answered with
+    /// `421` while the sessions that are connected carry on. See [`drain_for`](Self::drain_for) to
+    /// wait for them to end.
+    pub fn drain(&self) {
+        self.sessions.drain();
+    }
+
+    /// Puts the server in drain mode like [`drain`](Self::drain) and waits for the connected
+    /// sessions to end, at most for the given deadline. The sessions that are still connected then
+    /// are terminated, aborting their transfers. Returns the number of sessions terminated, so
+    /// `0` when all of them ended in time. Calling [`resume`](Self::resume) in the meantime stops
+    /// the wait without terminating any session.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use std::time::Duration;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// # async fn deploy() {
+    /// let server = Server::with_fs("/srv/ftp").build().unwrap();
+    /// let handle = server.handle();
+    /// tokio::spawn(server.listen("127.0.0.1:2121"));
+    ///
+    /// // On SIGTERM, once the load balancer stopped sending clients:
+    /// let aborted = handle.drain_for(Duration::from_secs(300)).await;
+    /// println!("Stopping, {} sessions were aborted", aborted);
+    /// # }
+    /// ```
+    pub async fn drain_for(&self, deadline: Duration) -> usize {
+        let resumed = self.sessions.drain();
+        tokio::select! {
+            _ = self.sessions.all_ended() => 0,
+            _ = resumed.cancelled() => 0,
+            _ = tokio::time::sleep(deadline) => self.sessions.kill_all(),
+        }
+    }
+
+    /// Accepts new connections again after [`drain`](Self::drain).
+    pub fn resume(&self) {
+        self.sessions.resume()
+    }
+
+    /// Tells if the server is in drain mode.
+    pub fn is_draining(&self) -> bool {
+        self.sessions.is_draining()
+    }
+
+    /// Reads the certificate and private key from the files given to
+    /// [`ServerBuilder::ftps`](crate::ServerBuilder::ftps) again, for instance after they were
+    /// renewed. New TLS connections use the new certificate. Fails if FTPS isn't enabled or the
+    /// files can't be read, in which case the old certificate stays in use.
+    pub fn reload_certificates(&self) -> Result<(), ServerError> {
+        match &self.ftps_mode {
+            #[cfg(feature = "ftps")]
+            FtpsConfig::On { certificates, .. } => Ok(certificates.reload()?),
+            _ => Err(super::tls::FtpsNotAvailable.into()),
+        }
+    }
+}
+
+// The options that can be changed with the ConfigHandle while the server runs. Every new session
+// takes a copy of them.
+#[derive(Debug, Clone)]
+pub(crate) struct RuntimeOptions {
+    // Replaces the configured greeting and greeting provider if set.
+    pub greeting: Option<String>,
+    pub idle_session_timeout: Duration,
+    pub passive_host: PassiveHost,
+    pub passive_ports: Range<u16>,
+    pub max_connections: Option<usize>,
+}
+
+/// Changes a subset of the options of a [`Server`](crate::Server) while it runs, for instance to
+/// tune limits during an incident. Obtained with [`Server::config_handle`](crate::Server::config_handle)
+/// before the server is started, it can be cloned and used for as long as the server runs.
+///
+/// The changes apply to sessions that start afterwards, sessions that are already connected keep
+/// the options they started with.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::Server;
+/// use unftp_sbe_fs::ServerExt;
+///
+/// # async fn tune() {
+/// let server = Server::with_fs("/srv/ftp").build().unwrap();
+/// let config = server.config_handle();
+/// tokio::spawn(server.listen("127.0.0.1:2121"));
+///
+/// config.set_max_connections(Some(50));
+/// config.set_idle_session_timeout(60);
+/// config.set_greeting("We're experiencing high load, transfers may be slow");
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ConfigHandle {
+    options: Arc<RwLock<RuntimeOptions>>,
+}
+
+impl ConfigHandle {
+    pub(super) fn new(options: Arc<RwLock<RuntimeOptions>>) -> Self {
+        ConfigHandle { options }
+    }
+
+    /// Sets the greeting, like [`ServerBuilder::greeting`](crate::ServerBuilder::greeting). It
+    /// takes the place of the greeting provider and of the greetings of the listeners.
+    pub fn set_greeting<G: Into<String>>(&self, greeting: G) {
+        self.options.write().unwrap().greeting = Some(greeting.into());
+    }
+
+    /// Sets the idle session timeout in seconds, like
+    /// [`ServerBuilder::idle_session_timeout`](crate::ServerBuilder::idle_session_timeout).
+    pub fn set_idle_session_timeout(&self, secs: u64) {
+        self.options.write().unwrap().idle_session_timeout = Duration::from_secs(secs);
+    }
+
+    /// Sets the IP address advertised in the `PASV` reply, like
+    /// [`ServerBuilder::passive_host`](crate::ServerBuilder::passive_host).
+    pub fn set_passive_host<H: Into<PassiveHost>>(&self, host: H) {
+        self.options.write().unwrap().passive_host = host.into();
+    }
+
+    /// Sets the range of passive ports, like
+    /// [`ServerBuilder::passive_ports`](crate::ServerBuilder::passive_ports). This has no effect
+    /// in PROXY protocol mode, where the ports are reserved when the server starts. An empty range
+    /// is refused and leaves the current one in place.
+    pub fn set_passive_ports(&self, range: Range<u16>) -> Result<(), ServerError> {
+        if range.is_empty() {
+            return Err(EmptyPassivePorts(range).into());
+        }
+        self.options.write().unwrap().passive_ports = range;
+        Ok(())
+    }
+
+    /// Sets the maximum number of sessions, like
+    /// [`ServerBuilder::max_connections`](crate::ServerBuilder::max_connections). `None` lifts the
+    /// limit.
+    pub fn set_max_connections(&self, max: Option<usize>) {
+        self.options.write().unwrap().max_connections = max;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{
+        session::TraceId,
+        sessions::{SessionActivity, SessionRegistry},
+    };
+
+    #[tokio::test]
+    async fn resuming_stops_drain_for() {
+        let sessions = Arc::new(SessionRegistry::default());
+        let activity = Arc::new(SessionActivity::new("127.0.0.1:1234".parse().unwrap()));
+        let _registration = sessions.register(TraceId::new(), activity.clone());
+        let handle = ServerHandle::new(sessions, FtpsConfig::Off);
+
+        let draining = tokio::spawn({
+            let handle = handle.clone();
+            async move { handle.drain_for(Duration::from_millis(200)).await }
+        });
+        while !handle.is_draining() {
+            tokio::task::yield_now().await;
+        }
+        handle.resume();
+        assert_eq!(draining.await.unwrap(), 0);
+        assert!(tokio::time::timeout(Duration::from_millis(300), activity.killed()).await.is_err());
+        assert_eq!(handle.sessions().len(), 1);
+    }
+}
@@ -0,0 +1,102 @@
+//! Contains the [`HealthCheck`] handle used to probe a running server.
+
+use crate::{auth::UserDetail, storage::StorageBackend};
+use std::{
+    fmt::{self, Debug, Formatter},
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+/// Reports on the health of a [`Server`](crate::Server), for instance to answer the liveness and
+/// readiness probes of Kubernetes without going through an FTP session. Obtained with
+/// [`Server::health`](crate::Server::health) before the server is started, it can be cloned and
+/// used for as long as the server runs.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::Server;
+/// use unftp_sbe_fs::ServerExt;
+///
+/// # async fn probe() {
+/// let server = Server::with_fs("/srv/ftp").build().unwrap();
+/// let health = server.health();
+/// tokio::spawn(server.listen("127.0.0.1:2121"));
+///
+/// // In the handler of the probe:
+/// let status = health.check().await;
+/// if !status.is_healthy() {
+///     // Reply with 503 Service Unavailable
+/// }
+/// # }
+/// ```
+pub struct HealthCheck<Storage, User> {
+    storage: Arc<dyn (Fn() -> Storage) + Send + Sync>,
+    listening: Arc<AtomicBool>,
+    _user: PhantomData<fn() -> User>,
+}
+
+/// The outcome of a [`HealthCheck`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HealthStatus {
+    /// Whether the server is accepting control connections.
+    pub listening: bool,
+    /// Whether the storage back-end responded to [`check_health`](StorageBackend::check_health).
+    pub storage: bool,
+}
+
+impl HealthStatus {
+    /// Tells if the server is listening and its storage back-end responds.
+    pub fn is_healthy(&self) -> bool {
+        self.listening && self.storage
+    }
+}
+
+impl<Storage, User> HealthCheck<Storage, User>
+where
+    Storage: StorageBackend<User>,
+    User: UserDetail,
+{
+    pub(super) fn new(storage: Arc<dyn (Fn() -> Storage) + Send + Sync>, listening: Arc<AtomicBool>) -> Self {
+        HealthCheck {
+            storage,
+            listening,
+            _user: PhantomData,
+        }
+    }
+
+    /// Tells if the server is accepting control connections. This is cheap enough to be used as a
+    /// liveness probe.
+    pub fn is_listening(&self) -> bool {
+        self.listening.load(Ordering::Relaxed)
+    }
+
+    /// Checks if the server is accepting control connections and asks a new instance of the
+    /// storage back-end whether it is able to serve requests.
+    pub async fn check(&self) -> HealthStatus {
+        let storage = (self.storage)();
+        HealthStatus {
+            listening: self.is_listening(),
+            storage: storage.check_health().await.is_ok(),
+        }
+    }
+}
+
+impl<Storage, User> Clone for HealthCheck<Storage, User> {
+    fn clone(&self) -> Self {
+        HealthCheck {
+            storage: self.storage.clone(),
+            listening: self.listening.clone(),
+            _user: PhantomData,
+        }
+    }
+}
+
+impl<Storage, User> Debug for HealthCheck<Storage, User> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HealthCheck").field("listening", &self.listening).finish()
+    }
+}
@@ -9,7 +9,7 @@ use crate::{
         chancomms::{ProxyLoopMsg, ProxyLoopReceiver, ProxyLoopSender},
         controlchan,
         datachan::spawn_processing,
-        ftpserver::chosen::OptionsHolder,
+        ftpserver::chosen::{OptionsHolder, SessionStorage},
         proxy_protocol::{spawn_proxy_header_parsing, ProxyConnection, ProxyProtocolSwitchboard},
         session::SharedSession,
         ControlChanMsg, Reply, ReplyCode,
@@ -17,11 +17,8 @@ use crate::{
     storage::StorageBackend,
     ServerError,
 };
-use std::{
-    net::{IpAddr, SocketAddr},
-    sync::Arc,
-};
-use tokio::{io::AsyncWriteExt, sync::mpsc::channel};
+use std::{net::IpAddr, sync::Arc};
+use tokio::{io::AsyncWriteExt, net::TcpListener, sync::mpsc::channel};
 
 // ProxyProtocolListener binds to a single port and assumes connections multiplexed by the
 // [proxy protocol](https://www.haproxy.com/blog/haproxy/proxy-protocol/)
@@ -30,11 +27,10 @@ where
     Storage: StorageBackend<User>,
     User: UserDetail,
 {
-    pub bind_address: SocketAddr,
     pub logger: slog::Logger,
     pub external_control_port: u16,
     pub options: OptionsHolder<Storage, User>,
-    pub proxy_protocol_switchboard: Option<ProxyProtocolSwitchboard<Storage, User>>,
+    pub proxy_protocol_switchboard: Option<ProxyProtocolSwitchboard<SessionStorage<Storage>, User>>,
     pub shutdown_topic: Arc<shutdown::Notifier>,
     pub failed_logins: Option<Arc<FailedLoginsCache>>,
 }
@@ -44,13 +40,13 @@ where
     Storage: StorageBackend<User> + 'static,
     User: UserDetail + 'static,
 {
-    // Starts listening, returning an error if the TCP address could not be bound to.
-    pub async fn listen(mut self) -> std::result::Result<(), ServerError> {
-        let listener = tokio::net::TcpListener::bind(self.bind_address).await?;
-
+    // Accepts connections on the listener until the server shuts down. The server binds it and drops
+    // its privileges once all of its listeners are bound.
+    pub async fn listen(mut self, listener: TcpListener) -> std::result::Result<(), ServerError> {
         // this callback is used by all sessions, basically only to
         // request for a passive listening port.
-        let (proxyloop_msg_tx, mut proxyloop_msg_rx): (ProxyLoopSender<Storage, User>, ProxyLoopReceiver<Storage, User>) = channel(1);
+        let (proxyloop_msg_tx, mut proxyloop_msg_rx): (ProxyLoopSender<SessionStorage<Storage>, User>, ProxyLoopReceiver<SessionStorage<Storage>, User>) =
+            channel(1);
 
         loop {
             // The 'proxy loop' handles two kinds of events:
@@ -73,17 +69,26 @@ where
                             // we differentiate between connections for the control channel,
                             // and connections for the data channel.
                             let destination_port = connection.destination.port();
+                            let shown = ProxyConnection { source: self.options.redaction.addr(connection.source), ..connection };
                             if destination_port == self.external_control_port {
-                                slog::info!(self.logger, "Incoming control connection: {:?} ({:?})(control port: {:?})", connection, socket_addr, self.external_control_port);
-                                let params: controlchan::LoopConfig<Storage,User> = (&self.options).into();
-                                let result = controlchan::spawn_loop::<Storage,User>(params, tcp_stream, Some(connection), Some(proxyloop_msg_tx.clone()), self.shutdown_topic.subscribe().await, self.failed_logins.clone()).await;
-                                if let Err(e) = result {
-                                    slog::warn!(self.logger, "Could not spawn control channel loop for connection: {:?}", e);
-                                }
+                                slog::info!(self.logger, "Incoming control connection: {:?} ({:?})(control port: {:?})", shown, socket_addr, self.external_control_port);
+                                let params: controlchan::LoopConfig<SessionStorage<Storage>,User> = (&self.options).into();
+                                let (proxyloop_msg_tx, shutdown_listener, failed_logins, logger) = (proxyloop_msg_tx.clone(), self.shutdown_topic.subscribe().await, self.failed_logins.clone(), self.logger.clone());
+                                // Like in the non-proxy listener, the connection policy mustn't hold up the proxy loop.
+                                tokio::spawn(async move {
+                                    let result = controlchan::spawn_loop::<SessionStorage<Storage>,User>(params, tcp_stream, Some(connection), Some(proxyloop_msg_tx), shutdown_listener, failed_logins).await;
+                                    if let Err(e) = result {
+                                        slog::warn!(logger, "Could not spawn control channel loop for connection: {:?}", e);
+                                    }
+                                });
                             } else {
                                 // handle incoming data connections
-                                slog::info!(self.logger, "Incoming data connection: {:?} ({:?}) (range: {:?})", connection, socket_addr, self.options.passive_ports);
-                                if !self.options.passive_ports.contains(&destination_port) {
+                                slog::info!(self.logger, "Incoming data connection: {:?} ({:?}) (range: {:?})", shown, socket_addr, self.options.passive_ports);
+                                let reserved = match &self.proxy_protocol_switchboard {
+                                    Some(switchboard) => switchboard.is_reserved(&connection),
+                                    None => false,
+                                };
+                                if !self.options.passive_ports.contains(&destination_port) && !reserved {
                                     slog::warn!(self.logger, "Incoming proxy connection going to unconfigured port! This port is not configured as a passive listening port: port {} not in passive port range {:?}", destination_port, self.options.passive_ports);
                                     tcp_stream.shutdown().await?;
                                     continue;
@@ -118,11 +123,15 @@ where
         if let Some(switchboard) = &mut self.proxy_protocol_switchboard {
             match switchboard.get_session_by_incoming_data_connection(&connection).await {
                 Some(session) => {
-                    spawn_processing(self.logger.clone(), session, tcp_stream).await;
+                    let trace_id = session.lock().await.trace_id;
+                    let logger = self
+                        .logger
+                        .new(slog::o!("trace-id" => trace_id.to_string(), "source" => self.options.redaction.addr(connection.source).to_string()));
+                    spawn_processing(logger, session, tcp_stream).await;
                     switchboard.unregister_this(&connection);
                 }
                 None => {
-                    slog::warn!(self.logger, "Unexpected connection ({:?})", connection);
+                    slog::warn!(self.logger, "Unexpected connection ({:?})", self.options.redaction.addr(connection.source));
                     if let Err(e) = tcp_stream.shutdown().await {
                         slog::error!(self.logger, "Error during tcp_stream shutdown: {:?}", e);
                     }
@@ -131,7 +140,7 @@ where
         }
     }
 
-    async fn select_and_register_passive_port(&mut self, session_arc: SharedSession<Storage, User>) {
+    async fn select_and_register_passive_port(&mut self, session_arc: SharedSession<SessionStorage<Storage>, User>) {
         slog::info!(self.logger, "Received internal message to allocate data port");
         // 1. reserve a port
         // 2. put the session_arc and tx in the hashmap with srcip+dstport as key
@@ -151,7 +160,14 @@ where
             };
 
             let reply = match port {
-                Ok(port) => super::controlchan::commands::make_pasv_reply(&self.logger, self.options.passive_host.clone(), &destination_ip, port).await,
+                Ok(port) => {
+                    let passive_host = (*session.user)
+                        .as_ref()
+                        .and_then(|u| u.passive_host())
+                        .unwrap_or_else(|| self.options.runtime_options.read().unwrap().passive_host.clone());
+                    super::controlchan::commands::make_pasv_reply(&self.logger, passive_host, session.passive_port_mapper.as_deref(), &destination_ip, port)
+                        .await
+                }
                 Err(_) => Reply::new_with_string(ReplyCode::CantOpenDataConnection, "Local error".to_string()),
             };
 
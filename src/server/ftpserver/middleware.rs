@@ -0,0 +1,257 @@
+//! Contains the [`Middleware`] trait that lets a libunftp user intercept the commands of the
+//! clients and the replies sent back to them, see
+//! [`ServerBuilder::middleware`](crate::ServerBuilder::middleware).
+
+use crate::server::{
+    controlchan::{ControlChanError, ControlChanMiddleware, Event, Reply, ReplyCode},
+    Command,
+};
+use async_trait::async_trait;
+use std::{convert::Infallible, fmt, net::SocketAddr, sync::Arc};
+
+/// Intercepts the commands of a client on their way to the server and the replies that come back,
+/// like a [tower](https://docs.rs/tower) layer does. A middleware can inspect or change the
+/// [`Request`], pass it on with [`Next::run`] and inspect the [`Response`], or answer the client
+/// itself without running the command at all.
+///
+/// Middleware is shared by all sessions. It sees the commands after the control channel logging
+/// and before the authentication checks, so it can also act on `USER` and `PASS`.
+///
+/// # Example
+///
+/// A middleware that refuses to delete anything on Fridays:
+///
+/// ```rust
+/// use async_trait::async_trait;
+/// use libunftp::middleware::{Middleware, MiddlewareError, Next, Request, Response};
+///
+/// #[derive(Debug)]
+/// struct NoDeployFriday;
+///
+/// #[async_trait]
+/// impl Middleware for NoDeployFriday {
+///     async fn handle(&self, request: Request, next: Next<'_>) -> Result<Response, MiddlewareError> {
+///         let friday = false; // Use your favourite date library here.
+///         if friday && matches!(request.verb(), "DELE" | "RMD") {
+///             return Ok(Response::permission_denied("Not on a Friday"));
+///         }
+///         next.run(request).await
+///     }
+/// }
+/// ```
+#[async_trait]
+pub trait Middleware: Send + Sync + fmt::Debug {
+    /// Handles the request, normally by passing it on to `next` and returning what that returns.
+    async fn handle(&self, request: Request, next: Next<'_>) -> Result<Response, MiddlewareError>;
+}
+
+/// A command from a client as seen by a [`Middleware`].
+#[derive(Debug)]
+pub struct Request {
+    pub(crate) command: Command,
+    pub(crate) username: Option<String>,
+    pub(crate) source: SocketAddr,
+}
+
+impl Request {
+    /// Returns the FTP verb of the command in upper case, e.g. `RETR`. `SITE` subcommands are
+    /// named without the `SITE` prefix, e.g. `MD5`.
+    pub fn verb(&self) -> &str {
+        self.command.name()
+    }
+
+    /// Returns the name the client gave with `USER`, if it did.
+    pub fn username(&self) -> Option<&str> {
+        self.username.as_deref()
+    }
+
+    /// Returns the address of the client.
+    pub fn source(&self) -> SocketAddr {
+        self.source
+    }
+
+    /// Returns the paths the client supplied with the command, as it sent them.
+    pub fn paths(&self) -> Vec<String> {
+        let mut paths = vec![];
+        let _ = self.command.clone().try_map_paths(|path| {
+            paths.push(path.clone());
+            Ok::<_, Infallible>(path)
+        });
+        paths
+    }
+
+    /// Replaces the paths the client supplied with the command by what the given function returns
+    /// for them.
+    pub fn map_paths<F>(&mut self, mut f: F)
+    where
+        F: FnMut(String) -> String,
+    {
+        let command = std::mem::replace(&mut self.command, Command::Noop);
+        self.command = match command.try_map_paths(|path| Ok::<_, Infallible>(f(path))) {
+            Ok(command) => command,
+            Err(never) => match never {},
+        };
+    }
+}
+
+/// The reply that is sent to the client for a [`Request`].
+#[derive(Debug)]
+pub struct Response(pub(crate) Reply);
+
+impl Response {
+    /// A `200` reply, the command succeeded.
+    pub fn ok<M: Into<String>>(message: M) -> Self {
+        Response(Reply::new_with_string(ReplyCode::CommandOkay, message.into()))
+    }
+
+    /// A `421` reply, the service is not available. Suits rate limiting.
+    pub fn service_not_available<M: Into<String>>(message: M) -> Self {
+        Response(Reply::new_with_string(ReplyCode::ServiceNotAvailable, message.into()))
+    }
+
+    /// A `450` reply, the command can't be run now but may succeed later.
+    pub fn transient_error<M: Into<String>>(message: M) -> Self {
+        Response(Reply::new_with_string(ReplyCode::TransientFileError, message.into()))
+    }
+
+    /// A `502` reply, the command is not allowed.
+    pub fn not_allowed<M: Into<String>>(message: M) -> Self {
+        Response(Reply::new_with_string(ReplyCode::CommandNotImplemented, message.into()))
+    }
+
+    /// A `550` reply, the client may not do this.
+    pub fn permission_denied<M: Into<String>>(message: M) -> Self {
+        Response(Reply::new_with_string(ReplyCode::FileError, message.into()))
+    }
+
+    /// Returns the reply code, or `None` if the reply is sent later, e.g. when a transfer
+    /// completes.
+    pub fn code(&self) -> Option<u32> {
+        match &self.0 {
+            Reply::None => None,
+            Reply::CodeAndMsg { code, .. } | Reply::MultiLine { code, .. } => Some(*code as u32),
+        }
+    }
+
+    /// Returns the lines of text of the reply.
+    pub fn lines(&self) -> Vec<&str> {
+        match &self.0 {
+            Reply::None => vec![],
+            Reply::CodeAndMsg { msg, .. } => vec![msg.as_str()],
+            Reply::MultiLine { lines, .. } => lines.iter().map(String::as_str).collect(),
+        }
+    }
+}
+
+/// The error a [`Middleware`] gets from [`Next::run`] when handling the command failed in a way
+/// that ends the session. Return it as is so that the session is ended cleanly.
+#[derive(Debug)]
+pub struct MiddlewareError(ControlChanError);
+
+impl fmt::Display for MiddlewareError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for MiddlewareError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+/// The rest of the chain: the middleware registered after the current one followed by libunftp
+/// itself.
+pub struct Next<'a> {
+    layers: &'a [Arc<dyn Middleware>],
+    inner: &'a mut dyn ControlChanMiddleware,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(layers: &'a [Arc<dyn Middleware>], inner: &'a mut dyn ControlChanMiddleware) -> Self {
+        Next { layers, inner }
+    }
+
+    /// Passes the request on and returns the response.
+    pub async fn run(self, request: Request) -> Result<Response, MiddlewareError> {
+        match self.layers.split_first() {
+            Some((layer, layers)) => layer.handle(request, Next { layers, inner: self.inner }).await,
+            None => match self.inner.handle(Event::Command(request.command)).await {
+                Ok(reply) => Ok(Response(reply)),
+                Err(err) => Err(MiddlewareError(err)),
+            },
+        }
+    }
+}
+
+impl fmt::Debug for Next<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Next").field("layers", &self.layers).finish()
+    }
+}
+
+// Unwraps the error for the control loop.
+impl From<MiddlewareError> for ControlChanError {
+    fn from(err: MiddlewareError) -> Self {
+        err.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::sync::Mutex;
+
+    #[derive(Debug)]
+    struct Chroot;
+
+    #[async_trait]
+    impl Middleware for Chroot {
+        async fn handle(&self, mut request: Request, next: Next<'_>) -> Result<Response, MiddlewareError> {
+            if request.verb() == "DELE" {
+                return Ok(Response::permission_denied("No deleting"));
+            }
+            request.map_paths(|path| format!("/jail/{}", path.trim_start_matches('/')));
+            next.run(request).await
+        }
+    }
+
+    #[tokio::test]
+    async fn runs_the_layers_in_order() {
+        let seen = Arc::new(Mutex::new(vec![]));
+        let recorder = seen.clone();
+        let mut inner = move |event: Event| -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Reply, ControlChanError>> + Send>> {
+            if let Event::Command(command) = event {
+                recorder.lock().unwrap().push(command);
+            }
+            Box::pin(async { Ok(Reply::new(ReplyCode::FileActionOkay, "Done")) })
+        };
+        let layers: Vec<Arc<dyn Middleware>> = vec![Arc::new(Chroot)];
+        let request = |command| Request {
+            command,
+            username: None,
+            source: "127.0.0.1:2121".parse().unwrap(),
+        };
+
+        let response = Next::new(&layers, &mut inner)
+            .run(request(Command::Retr { path: "/a.txt".to_string() }))
+            .await
+            .unwrap();
+        assert_eq!(response.code(), Some(250));
+        assert_eq!(response.lines(), vec!["Done"]);
+
+        let response = Next::new(&layers, &mut inner)
+            .run(request(Command::Dele { path: "a.txt".to_string() }))
+            .await
+            .unwrap();
+        assert_eq!(response.code(), Some(550));
+
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![Command::Retr {
+                path: "/jail/a.txt".to_string()
+            }]
+        );
+    }
+}
@@ -1,16 +1,29 @@
 //! Contains code pertaining to the setup options that can be given to the [`ServerBuilder`](crate::ServerBuilder)
 
+use crate::auth::UserDetail;
+use crate::server::path;
 use async_trait::async_trait;
 use bitflags::bitflags;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
 use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsString,
     fmt::Formatter,
     fmt::{self, Debug, Display},
     io,
-    net::{IpAddr, Ipv4Addr},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
     ops::Range,
+    path::Path,
+    sync::{Arc, Mutex},
 };
-use tokio::net::TcpSocket;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpSocket, TcpStream},
+    sync::Notify,
+};
+
+#[cfg(feature = "geoip")]
+pub use super::geoip::GeoIpPolicy;
 
 // Once we're sure about the types of these I think its good to expose it to the API user so that
 // he/she can see what our server defaults are.
@@ -29,9 +42,178 @@ pub trait Binder: Debug + Send {
     async fn bind(&mut self, local_addr: IpAddr, passive_ports: Range<u16>) -> io::Result<TcpSocket>;
 }
 
+/// Restricts what the process serving FTP sessions is allowed to do, for instance with seccomp on
+/// Linux, pledge on OpenBSD or Capsicum on FreeBSD. Set it with
+/// [`ServerBuilder::sandbox`](crate::ServerBuilder::sandbox).
+///
+/// The sandbox is entered by [`Server::listen`](crate::Server::listen) once it is bound to its
+/// port and dropped its privileges, and by [`Server::service`](crate::Server::service) before it
+/// serves the session. The latter makes it apply per session when sessions are served by a
+/// [`ConnectionHelper`]. The sandbox must still allow what the storage back-end and the
+/// authenticator need, like opening files and making network connections.
+#[cfg(feature = "sandbox")]
+pub trait Sandbox: Debug + Send + Sync {
+    /// Enters the sandbox. Failing prevents the server from serving sessions.
+    fn enter(&self) -> io::Result<()>;
+}
+
+/// A [`Sandbox`] that sets the `no_new_privs` flag of the process on Linux, so that neither it nor
+/// the programs it starts, like a [`ConnectionHelper`], can gain privileges by executing set-user-ID
+/// or set-group-ID binaries. The flag is also what allows an unprivileged process to install a
+/// seccomp filter, which is left to a [`Sandbox`] of your own.
+#[cfg(all(feature = "sandbox", target_os = "linux"))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoNewPrivileges;
+
+#[cfg(all(feature = "sandbox", target_os = "linux"))]
+impl Sandbox for NoNewPrivileges {
+    fn enter(&self) -> io::Result<()> {
+        nix::sys::prctl::set_no_new_privs().map_err(io::Error::from)
+    }
+}
+
+/// Describes the control connection a greeting is composed for, see
+/// [ServerBuilder::greeting_provider](crate::ServerBuilder::greeting_provider).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    /// The address of the client. In proxy protocol mode this is the address the proxy reported.
+    pub source: SocketAddr,
+    /// The local address the client connected to, which tells listeners apart.
+    pub local_addr: SocketAddr,
+}
+
+/// The function set with [ServerBuilder::greeting_provider](crate::ServerBuilder::greeting_provider)
+/// that composes the greeting of a connection.
+pub type GreetingProvider = dyn Fn(&ConnectionInfo) -> String + Send + Sync;
+
+// Holds the greeting provider so that the options holding it can be Debug.
+#[derive(Clone)]
+pub(crate) struct GreetingFn(pub Arc<GreetingProvider>);
+
+impl Debug for GreetingFn {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("GreetingFn")
+    }
+}
+
+/// A catalog of translated reply messages, set with
+/// [`ServerBuilder::message_catalog`](crate::ServerBuilder::message_catalog). Clients choose one of
+/// its languages with the `LANG` command of RFC 2640, after which the replies sent to them are
+/// translated. Messages are looked up by their reply code and English text, so a catalog only needs
+/// to contain the messages it translates. Replies are sent in English until the client sends
+/// `LANG`, which also means the greeting is always in English.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::options::MessageCatalog;
+///
+/// #[derive(Debug)]
+/// struct Dutch;
+///
+/// impl MessageCatalog for Dutch {
+///     fn languages(&self) -> Vec<String> {
+///         vec!["nl".to_string()]
+///     }
+///
+///     fn translate(&self, _language: &str, _code: u32, message: &str) -> Option<String> {
+///         match message {
+///             "User logged in, proceed" => Some("Gebruiker aangemeld, ga verder".to_string()),
+///             _ => None,
+///         }
+///     }
+/// }
+/// ```
+pub trait MessageCatalog: Debug + Send + Sync {
+    /// Returns the language tags, like `fr` or `pt-BR`, that the catalog has messages for. English
+    /// is always available and needn't be listed.
+    fn languages(&self) -> Vec<String>;
+
+    /// Returns the translation to the given language, one of those returned by
+    /// [`languages`](MessageCatalog::languages), of the message sent with the given reply code.
+    /// None sends the English message.
+    fn translate(&self, language: &str, code: u32, message: &str) -> Option<String>;
+}
+
+/// Takes over the connections accepted by [`Server::listen`](crate::Server::listen), to serve them
+/// somewhere else than in the task libunftp would spawn for them: in a worker process, for
+/// instance to separate privileges, or on a different runtime. The data channels of a delegated
+/// session are opened and served by whatever serves its control connection, typically another
+/// [`Server`](crate::Server) calling [`service`](crate::Server::service).
+///
+/// Set it with [`ServerBuilder::connection_delegate`](crate::ServerBuilder::connection_delegate).
+/// [`ConnectionHelper`] is the implementation that hands connections to a helper process.
+///
+/// # Example
+///
+/// Passing connections to a task that serves them elsewhere:
+///
+/// ```rust
+/// use async_trait::async_trait;
+/// use libunftp::options::DataConnectionDelegate;
+/// use std::{io, net::SocketAddr};
+/// use tokio::{net::TcpStream, sync::mpsc::Sender};
+///
+/// #[derive(Debug)]
+/// struct Forward(Sender<TcpStream>);
+///
+/// #[async_trait]
+/// impl DataConnectionDelegate for Forward {
+///     async fn delegate(&self, stream: TcpStream, _peer: SocketAddr) -> io::Result<()> {
+///         self.0.send(stream).await.map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+///     }
+/// }
+/// ```
+#[async_trait]
+pub trait DataConnectionDelegate: Debug + Send + Sync {
+    /// Takes over the connection accepted from the given peer. An error is logged, after which the
+    /// connection is dropped.
+    async fn delegate(&self, stream: TcpStream, peer: SocketAddr) -> io::Result<()>;
+}
+
+/// A [`DataConnectionDelegate`] that serves every accepted connection with a new helper process,
+/// set with [`ServerBuilder::connection_helper`](crate::ServerBuilder::connection_helper).
+///
+/// The helper is started with the configured arguments followed by the number of the file
+/// descriptor of the connected socket, which it inherits. The server doesn't wait for the helper:
+/// it is expected to take the socket over with `std::net::TcpStream::from_raw_fd` and serve it with
+/// [`Server::service`](crate::Server::service), and exit once the session ended. The
+/// `helper` example of the `unftp-sbe-fs` crate shows a complete helper.
+#[cfg(unix)]
+#[derive(Debug, Clone)]
+pub struct ConnectionHelper {
+    path: OsString,
+    args: Vec<OsString>,
+}
+
+#[cfg(unix)]
+impl ConnectionHelper {
+    /// Creates a delegate that runs the executable at the given path with the given arguments.
+    pub fn new(path: OsString, args: Vec<OsString>) -> Self {
+        ConnectionHelper { path, args }
+    }
+}
+
+#[cfg(unix)]
+#[async_trait]
+impl DataConnectionDelegate for ConnectionHelper {
+    async fn delegate(&self, stream: TcpStream, _peer: SocketAddr) -> io::Result<()> {
+        use std::os::fd::AsRawFd;
+
+        let fd = stream.as_raw_fd();
+        nix::fcntl::fcntl(fd, nix::fcntl::FcntlArg::F_SETFD(nix::fcntl::FdFlag::empty()))?;
+        let mut child = tokio::process::Command::new(&self.path).args(self.args.iter()).arg(fd.to_string()).spawn()?;
+        // Reap the helper once the session ended.
+        tokio::spawn(async move {
+            let _ = child.wait().await;
+        });
+        Ok(())
+    }
+}
+
 /// The option to [ServerBuilder::passive_host](crate::ServerBuilder::passive_host). It allows the user to specify how the IP address
 /// communicated in the _PASV_ response is determined.
-#[derive(Debug, PartialEq, Clone, Default)]
+#[derive(Debug, Clone, Default)]
 pub enum PassiveHost {
     /// Use the IP address of the control connection
     #[default]
@@ -40,11 +222,33 @@ pub enum PassiveHost {
     Ip(Ipv4Addr),
     /// Resolve this DNS name into an IPv4 address.
     Dns(String),
+    /// Ask the [`PassiveHostResolver`] for the address at the time of every `PASV`, for servers
+    /// behind NAT whose public address changes. See [`DnsResolver`] and [`InstanceMetadata`].
+    Resolver(Arc<dyn PassiveHostResolver>),
     // We also be nice to have:
     // - PerUser(Box<dyn (Fn(Box<dyn UserDetail>) -> Ipv4Addr) + Send + Sync>) or something like
     //   that to allow a per user decision
 }
 
+impl PassiveHost {
+    /// Creates the [`PassiveHost::Resolver`] variant.
+    pub fn resolver<R: PassiveHostResolver + 'static>(resolver: R) -> Self {
+        PassiveHost::Resolver(Arc::new(resolver))
+    }
+}
+
+impl PartialEq for PassiveHost {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (PassiveHost::FromConnection, PassiveHost::FromConnection) => true,
+            (PassiveHost::Ip(a), PassiveHost::Ip(b)) => a == b,
+            (PassiveHost::Dns(a), PassiveHost::Dns(b)) => a == b,
+            (PassiveHost::Resolver(a), PassiveHost::Resolver(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
 impl Eq for PassiveHost {}
 
 impl From<Ipv4Addr> for PassiveHost {
@@ -68,9 +272,304 @@ impl From<&str> for PassiveHost {
     }
 }
 
+/// Looks up the IP address advertised in the `PASV` reply, see [`PassiveHost::Resolver`].
+///
+/// # Example
+///
+/// ```rust
+/// use async_trait::async_trait;
+/// use libunftp::options::PassiveHostResolver;
+/// use std::{io, net::Ipv4Addr};
+///
+/// #[derive(Debug)]
+/// struct FromEnv;
+///
+/// #[async_trait]
+/// impl PassiveHostResolver for FromEnv {
+///     async fn resolve(&self) -> io::Result<Ipv4Addr> {
+///         let ip = std::env::var("PUBLIC_IP").map_err(|err| io::Error::new(io::ErrorKind::NotFound, err))?;
+///         ip.parse().map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+///     }
+/// }
+/// ```
+#[async_trait]
+pub trait PassiveHostResolver: Debug + Send + Sync {
+    /// Returns the address to advertise. An error is logged and the client is answered with `425`.
+    async fn resolve(&self) -> io::Result<Ipv4Addr>;
+}
+
+// Remembers an address for a while so that not every PASV waits for a lookup.
+#[derive(Debug)]
+struct CachedAddress {
+    ttl: Duration,
+    cached: Mutex<Option<(Ipv4Addr, Instant)>>,
+}
+
+impl CachedAddress {
+    fn new(ttl: Duration) -> Self {
+        CachedAddress { ttl, cached: Mutex::new(None) }
+    }
+
+    fn get(&self) -> Option<Ipv4Addr> {
+        match *self.cached.lock().unwrap() {
+            Some((ip, at)) if at.elapsed() < self.ttl => Some(ip),
+            _ => None,
+        }
+    }
+
+    fn set(&self, ip: Ipv4Addr) -> Ipv4Addr {
+        *self.cached.lock().unwrap() = Some((ip, Instant::now()));
+        ip
+    }
+}
+
+/// A [`PassiveHostResolver`] that resolves a DNS name into an IPv4 address and uses the address
+/// for the given time before resolving the name again. Suits servers whose public address is kept
+/// up to date in DNS.
+#[derive(Debug)]
+pub struct DnsResolver {
+    name: String,
+    cache: CachedAddress,
+}
+
+impl DnsResolver {
+    /// Creates a resolver for the given host name that caches the address for `ttl`.
+    pub fn new<N: Into<String>>(name: N, ttl: Duration) -> Self {
+        DnsResolver {
+            name: name.into(),
+            cache: CachedAddress::new(ttl),
+        }
+    }
+}
+
+#[async_trait]
+impl PassiveHostResolver for DnsResolver {
+    async fn resolve(&self) -> io::Result<Ipv4Addr> {
+        if let Some(ip) = self.cache.get() {
+            return Ok(ip);
+        }
+        // The port is needed for the lookup but not used.
+        let ip = tokio::net::lookup_host((self.name.as_str(), 21))
+            .await?
+            .find_map(|addr| match addr {
+                SocketAddr::V4(addr) => Some(*addr.ip()),
+                SocketAddr::V6(_) => None,
+            })
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no IPv4 address for {}", self.name)))?;
+        Ok(self.cache.set(ip))
+    }
+}
+
+/// A [`PassiveHostResolver`] that asks the metadata service of the cloud instance the server runs
+/// on for its public IPv4 address, and uses the address for the given time before asking again.
+#[derive(Debug)]
+pub struct InstanceMetadata {
+    provider: CloudProvider,
+    cache: CachedAddress,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum CloudProvider {
+    Gce,
+    Ec2,
+}
+
+const METADATA_HOST: &str = "169.254.169.254";
+
+impl InstanceMetadata {
+    /// Asks the Google Compute Engine metadata server for the external IP of the first network
+    /// interface. Also works on GKE nodes.
+    pub fn gce(ttl: Duration) -> Self {
+        InstanceMetadata {
+            provider: CloudProvider::Gce,
+            cache: CachedAddress::new(ttl),
+        }
+    }
+
+    /// Asks the Amazon EC2 instance metadata service for the public IPv4 address, with an IMDSv2
+    /// session token.
+    pub fn ec2(ttl: Duration) -> Self {
+        InstanceMetadata {
+            provider: CloudProvider::Ec2,
+            cache: CachedAddress::new(ttl),
+        }
+    }
+
+    async fn lookup(&self) -> io::Result<String> {
+        match self.provider {
+            CloudProvider::Gce => {
+                let path = "/computeMetadata/v1/instance/network-interfaces/0/access-configs/0/external-ip";
+                http_request("GET", path, &[("Metadata-Flavor", "Google")]).await
+            }
+            CloudProvider::Ec2 => {
+                let token = http_request("PUT", "/latest/api/token", &[("X-aws-ec2-metadata-token-ttl-seconds", "60")]).await?;
+                http_request("GET", "/latest/meta-data/public-ipv4", &[("X-aws-ec2-metadata-token", token.trim())]).await
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl PassiveHostResolver for InstanceMetadata {
+    async fn resolve(&self) -> io::Result<Ipv4Addr> {
+        if let Some(ip) = self.cache.get() {
+            return Ok(ip);
+        }
+        let body = tokio::time::timeout(Duration::from_secs(2), self.lookup())
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+        let ip = body.trim().parse().map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        Ok(self.cache.set(ip))
+    }
+}
+
+// Sends a plain HTTP/1.0 request to the metadata service and returns the body of a 200 reply. The
+// service is link-local and unencrypted, so a full HTTP client would buy nothing.
+async fn http_request(method: &str, path: &str, headers: &[(&str, &str)]) -> io::Result<String> {
+    let mut stream = TcpStream::connect((METADATA_HOST, 80)).await?;
+    let mut request = format!("{} {} HTTP/1.0\r\nHost: {}\r\n", method, path, METADATA_HOST);
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("Content-Length: 0\r\n\r\n");
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+    parse_http_response(&response)
+}
+
+fn parse_http_response(response: &str) -> io::Result<String> {
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed HTTP response"))?;
+    let status = head.lines().next().unwrap_or_default();
+    match status.split_whitespace().nth(1) {
+        Some("200") => Ok(body.to_string()),
+        _ => Err(io::Error::new(io::ErrorKind::Other, format!("metadata service replied {}", status))),
+    }
+}
+
+/// Maps the passive data port that libunftp listens on to the address and port that the client
+/// must connect to, for gateways that translate ports as well as addresses. See
+/// [ServerBuilder::passive_port_mapper](crate::ServerBuilder::passive_port_mapper).
+///
+/// # Example
+///
+/// A gateway that forwards ports 30000 and up to the host on which libunftp uses the passive ports
+/// from 50000:
+///
+/// ```rust
+/// use libunftp::options::PassivePortMapper;
+/// use std::{io, net::Ipv4Addr};
+///
+/// #[derive(Debug)]
+/// struct Gateway;
+///
+/// impl PassivePortMapper for Gateway {
+///     fn map(&self, ip: Ipv4Addr, port: u16) -> io::Result<(Ipv4Addr, u16)> {
+///         Ok((ip, port - 20000))
+///     }
+/// }
+/// ```
+pub trait PassivePortMapper: Debug + Send + Sync {
+    /// Returns the address and port to advertise in the `PASV` reply, given the address that
+    /// [`PassiveHost`] chose and the port that libunftp listens on. An error is logged and the
+    /// client is answered with `425`.
+    fn map(&self, ip: Ipv4Addr, port: u16) -> io::Result<(Ipv4Addr, u16)>;
+}
+
+/// A [`PassivePortMapper`] for gateways that forward a range of ports to the passive ports of the
+/// server shifted by a fixed offset, keeping the address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortOffset(pub i32);
+
+impl PassivePortMapper for PortOffset {
+    fn map(&self, ip: Ipv4Addr, port: u16) -> io::Result<(Ipv4Addr, u16)> {
+        let mapped = i32::from(port) + self.0;
+        match u16::try_from(mapped) {
+            Ok(mapped) if mapped != 0 => Ok((ip, mapped)),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("port {} shifted by {} is not a valid port", port, self.0),
+            )),
+        }
+    }
+}
+
+/// An address for [`Server::listen_all`](crate::Server::listen_all) to listen on, with the options
+/// that differ on it from those of the rest of the server. The storage back-end, authenticator and
+/// other options are shared by all listeners.
+///
+/// # Example
+///
+/// A public port that requires FTPS next to an internal one that doesn't:
+///
+/// ```rust
+/// use libunftp::options::{FtpsRequired, ListenerOptions};
+///
+/// let public = ListenerOptions::new("0.0.0.0:21").ftps_required(FtpsRequired::All, FtpsRequired::All);
+/// let internal = ListenerOptions::new("10.0.0.5:2121").greeting("Internal FTP");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListenerOptions {
+    pub(crate) bind_address: String,
+    pub(crate) greeting: Option<&'static str>,
+    pub(crate) ftps_required: Option<(FtpsRequired, FtpsRequired)>,
+    pub(crate) ftps_tls_first: Option<TlsFirst>,
+    pub(crate) proxy_protocol_mode: Option<u16>,
+}
+
+impl ListenerOptions {
+    /// Listens on the given address, e.g. `0.0.0.0:21`, with the options of the server.
+    pub fn new<T: Into<String>>(bind_address: T) -> Self {
+        ListenerOptions {
+            bind_address: bind_address.into(),
+            greeting: None,
+            ftps_required: None,
+            ftps_tls_first: None,
+            proxy_protocol_mode: None,
+        }
+    }
+
+    /// Sends this greeting to clients connecting to this address, like
+    /// [`ServerBuilder::greeting`](crate::ServerBuilder::greeting) does for the whole server. It
+    /// takes the place of the greeting provider, if there is one.
+    pub fn greeting(mut self, greeting: &'static str) -> Self {
+        self.greeting = Some(greeting);
+        self
+    }
+
+    /// Requires FTPS on this address, like
+    /// [`ServerBuilder::ftps_required`](crate::ServerBuilder::ftps_required) does for the whole
+    /// server.
+    pub fn ftps_required<R: Into<FtpsRequired>>(mut self, for_control_chan: R, for_data_chan: R) -> Self {
+        self.ftps_required = Some((for_control_chan.into(), for_data_chan.into()));
+        self
+    }
+
+    /// Requires `AUTH TLS` as the first command on this address, optionally hiding the greeting
+    /// until the TLS handshake completed, like
+    /// [`ServerBuilder::ftps_tls_first`](crate::ServerBuilder::ftps_tls_first) does for the whole
+    /// server. FTPS has to be enabled on the server for this to work.
+    pub fn ftps_tls_first<T: Into<TlsFirst>>(mut self, tls_first: T) -> Self {
+        self.ftps_tls_first = Some(tls_first.into());
+        self
+    }
+
+    /// Expects the proxy protocol on this address, like
+    /// [`ServerBuilder::proxy_protocol_mode`](crate::ServerBuilder::proxy_protocol_mode) does for
+    /// the whole server.
+    #[cfg(feature = "proxy-protocol")]
+    pub fn proxy_protocol_mode(mut self, external_control_port: u16) -> Self {
+        self.proxy_protocol_mode = Some(external_control_port);
+        self
+    }
+}
+
 /// The option to [ServerBuilder::ftps_required](crate::ServerBuilder::ftps_required). It allows the user to specify whether clients are required
 /// to upgrade a to secure TLS connection i.e. use FTPS.
 #[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(rename_all = "snake_case"))]
 pub enum FtpsRequired {
     /// All users, including anonymous must use FTPS
     All,
@@ -105,6 +604,357 @@ impl Display for FtpsRequired {
     }
 }
 
+/// The option to [ServerBuilder::encoding](crate::ServerBuilder::encoding). It specifies the
+/// character set that clients use for path names and other text on the control channel.
+///
+/// The storage back-end gets paths in Unicode. Bytes of paths that aren't valid in the character
+/// set, and bytes of names on the back-end that aren't valid UTF-8, are passed through unchanged
+/// on Unix, so that such names can still be listed, downloaded and uploaded. They are carried as
+/// the private use characters U+EF80 to U+EFFF on the way, which path filters see as well.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum Encoding {
+    /// Text is UTF-8 encoded. Clients can't switch UTF-8 off with `OPTS UTF8 OFF`. This is the
+    /// default.
+    #[default]
+    Utf8,
+    /// Text is Latin-1 (ISO-8859-1) encoded. UTF-8 is not advertised in the FEAT response and
+    /// clients can't switch it on with `OPTS UTF8 ON`.
+    Latin1,
+    /// Text is taken to be UTF-8 until the client either sends `OPTS UTF8 OFF` or a command
+    /// that is not valid UTF-8, after which Latin-1 is used. Clients can switch back to UTF-8 with
+    /// `OPTS UTF8 ON`. This helps serving both modern and legacy clients.
+    Auto,
+}
+
+/// The option to [ServerBuilder::dotfiles](crate::ServerBuilder::dotfiles). Tells whether files
+/// and directories whose name starts with a dot are visible to clients. It can be overridden per
+/// user with [UserDetail::dotfiles](crate::auth::UserDetail::dotfiles).
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum Dotfiles {
+    /// Dotfiles are treated like any other file. This is the default.
+    #[default]
+    Show,
+    /// Dotfiles are left out of directory listings and commands that refer to them, or to anything
+    /// inside a dot-directory, fail as if the file does not exist.
+    Hide,
+}
+
+/// The option to [ServerBuilder::access_mode](crate::ServerBuilder::access_mode). Tells whether
+/// clients may read back what is stored. It can be overridden per user with
+/// [UserDetail::access_mode](crate::auth::UserDetail::access_mode).
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum AccessMode {
+    /// Files can be uploaded, downloaded and listed. This is the default.
+    #[default]
+    ReadWrite,
+    /// A drop box: files can be uploaded and directories created, but nothing can be downloaded
+    /// or listed. Commands that read (`RETR`, `LIST`, `NLST`, `SIZE`, `MDTM`, `MD5`, `CPFR` and
+    /// `STAT` with a path) are answered with `550`. The storage back-end is guarded as well, so
+    /// that files can't be read back even if their names are guessed.
+    UploadOnly,
+}
+
+/// The option to [ServerBuilder::partial_uploads](crate::ServerBuilder::partial_uploads). Tells
+/// what happens to the partially stored file when a client aborts an upload with `ABOR`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum PartialUploads {
+    /// The partially stored file is kept so that the client can resume the upload with `REST`.
+    /// This is the default.
+    #[default]
+    Keep,
+    /// The partially stored file is deleted.
+    Delete,
+}
+
+/// The option to [ServerBuilder::upload_conflict_policy](crate::ServerBuilder::upload_conflict_policy).
+/// Tells what happens when a session uploads with `STOR` to a path that another session is
+/// uploading to.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum UploadConflictPolicy {
+    /// Both uploads go ahead and the one that completes last determines the content of the file.
+    /// This is the default.
+    #[default]
+    LastWriterWins,
+    /// The upload that comes second is refused with `450`.
+    Reject,
+    /// The upload that comes second waits for the first one to complete.
+    Serialize,
+}
+
+/// Locks the paths that are uploaded to, to carry out the [`UploadConflictPolicy`]. By default
+/// the server locks them in memory with [`LocalUploadLocks`], which covers the sessions of one
+/// server. Implement it on top of the locking of a distributed store, like a lease in a database,
+/// to cover all instances of a server behind a load balancer and set it with
+/// [`ServerBuilder::upload_locks`](crate::ServerBuilder::upload_locks).
+///
+/// The paths are normalized absolute paths like `/incoming/orders.csv`. The owner identifies the
+/// session that uploads, locks are released by the owner that took them.
+#[async_trait]
+pub trait UploadLocks: Debug + Send + Sync {
+    /// Locks the path for the owner if no one else holds it, returning whether it did.
+    async fn try_lock(&self, path: &str, owner: &str) -> bool;
+
+    /// Waits until the path can be locked for the owner and locks it.
+    async fn lock(&self, path: &str, owner: &str);
+
+    /// Releases the lock that the owner holds on the path.
+    async fn unlock(&self, path: &str, owner: &str);
+}
+
+/// The [`UploadLocks`] that the server uses by default, a lock table in memory.
+#[derive(Debug, Default)]
+pub struct LocalUploadLocks {
+    // The owner of each locked path.
+    held: Mutex<HashMap<String, String>>,
+    released: Notify,
+}
+
+impl LocalUploadLocks {
+    /// Creates an empty lock table.
+    pub fn new() -> Self {
+        LocalUploadLocks::default()
+    }
+}
+
+#[async_trait]
+impl UploadLocks for LocalUploadLocks {
+    async fn try_lock(&self, path: &str, owner: &str) -> bool {
+        let mut held = self.held.lock().unwrap();
+        match held.get(path) {
+            Some(holder) => holder == owner,
+            None => {
+                held.insert(path.to_string(), owner.to_string());
+                true
+            }
+        }
+    }
+
+    async fn lock(&self, path: &str, owner: &str) {
+        loop {
+            let released = self.released.notified();
+            tokio::pin!(released);
+            // Registered before trying, so that a release in between isn't missed.
+            released.as_mut().enable();
+            if self.try_lock(path, owner).await {
+                return;
+            }
+            released.await;
+        }
+    }
+
+    async fn unlock(&self, path: &str, owner: &str) {
+        let mut held = self.held.lock().unwrap();
+        if held.get(path).is_some_and(|holder| holder == owner) {
+            held.remove(path);
+            self.released.notify_waiters();
+        }
+    }
+}
+
+// The upload conflict policy together with the locks that carry it out.
+#[derive(Debug, Clone)]
+pub(crate) struct UploadConflicts {
+    pub policy: UploadConflictPolicy,
+    pub locks: Arc<dyn UploadLocks>,
+}
+
+impl Default for UploadConflicts {
+    fn default() -> Self {
+        UploadConflicts {
+            policy: UploadConflictPolicy::default(),
+            locks: Arc::new(LocalUploadLocks::new()),
+        }
+    }
+}
+
+impl UploadConflicts {
+    // Locks the path for an upload by the owner as the policy says. Returns the lock, if one was
+    // taken, or None if the upload is refused.
+    pub(crate) async fn acquire(&self, path: &Path, owner: &str) -> Option<Option<UploadLock>> {
+        // Every spelling of a path locks the same entry.
+        let path = path::normalize(path).to_string_lossy().into_owned();
+        match self.policy {
+            UploadConflictPolicy::LastWriterWins => return Some(None),
+            UploadConflictPolicy::Reject => {
+                if !self.locks.try_lock(&path, owner).await {
+                    return None;
+                }
+            }
+            UploadConflictPolicy::Serialize => self.locks.lock(&path, owner).await,
+        }
+        Some(Some(UploadLock {
+            locks: self.locks.clone(),
+            path,
+            owner: owner.to_string(),
+        }))
+    }
+}
+
+// The lock on a path during an upload, released when dropped.
+#[derive(Debug)]
+pub(crate) struct UploadLock {
+    locks: Arc<dyn UploadLocks>,
+    path: String,
+    owner: String,
+}
+
+impl Drop for UploadLock {
+    fn drop(&mut self) {
+        let (locks, path, owner) = (self.locks.clone(), std::mem::take(&mut self.path), std::mem::take(&mut self.owner));
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(async move { locks.unlock(&path, &owner).await });
+        }
+    }
+}
+
+/// The option to [ServerBuilder::upload_checksum](crate::ServerBuilder::upload_checksum). Tells
+/// which checksum is computed over the data of uploads while they are received. It is reported in
+/// [`DataEvent::Put`](crate::notification::DataEvent::Put) events and passed to the
+/// [`UploadHook`](crate::notification::UploadHook).
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum UploadChecksum {
+    /// No checksum is computed. This is the default.
+    #[default]
+    None,
+    /// The MD5 sum is computed and reported in lowercase hexadecimal format, like the output of the
+    /// `md5sum` command.
+    Md5,
+}
+
+/// The option to [ServerBuilder::mode_z](crate::ServerBuilder::mode_z). Tells whether clients may
+/// switch to compressed data transfers with `MODE Z`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum ModeZ {
+    /// `MODE Z` is refused. This is the default.
+    #[default]
+    Disabled,
+    /// Clients may switch to `MODE Z`, after which the data of uploads, downloads and directory
+    /// listings is compressed with the given zlib compression level, from 1 (fastest) to 9 (best).
+    Enabled {
+        /// The compression level used for the data that the server sends.
+        level: u32,
+    },
+}
+
+/// The option to [ServerBuilder::recursive_listing](crate::ServerBuilder::recursive_listing).
+/// Tells whether `LIST -R` and `NLST -R` list the whole tree under a directory and how far they may
+/// go.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum RecursiveListing {
+    /// The `-R` flag is ignored and only the directory itself is listed.
+    Disabled,
+    /// Listings with the `-R` flag descend into subdirectories up to `max_depth` levels deep. A
+    /// listing that would hold more than `max_entries` entries fails instead of tying up the
+    /// storage back-end. This is the default, with a depth of 16 and 10000 entries.
+    Enabled {
+        /// How many levels of subdirectories are listed at most.
+        max_depth: usize,
+        /// How many entries a listing may hold at most.
+        max_entries: usize,
+    },
+}
+
+impl Default for RecursiveListing {
+    fn default() -> Self {
+        RecursiveListing::Enabled {
+            max_depth: 16,
+            max_entries: 10_000,
+        }
+    }
+}
+
+/// The option to [ServerBuilder::listing_order](crate::ServerBuilder::listing_order). Tells in
+/// which order directory listings give their entries.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum ListingOrder {
+    /// Entries come in the order that the storage back-end returns them in. This is the default.
+    #[default]
+    Unsorted,
+    /// Entries are sorted by name, byte by byte like `ls` does in the C locale.
+    Name,
+    /// Entries are sorted by modification time, the newest first like `ls -t` does. Entries
+    /// modified at the same time are sorted by name.
+    Modified,
+}
+
+/// The option to [ServerBuilder::client_quirks](crate::ServerBuilder::client_quirks). Tells
+/// whether libunftp tries to recognise the FTP client from the commands it sends, and whether it
+/// works around the known quirks of the clients it recognises.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum ClientQuirks {
+    /// Clients are not looked at. This is the default.
+    #[default]
+    Off,
+    /// The client is recognised and logged, and counted in the `ftp_clients_total` metric if
+    /// metrics are enabled. Nothing changes in how the session is served.
+    Detect,
+    /// The client is recognised like with `Detect` and the workarounds for it are enabled:
+    /// clients that identify as a Microsoft client get `LIST` output in the MS-DOS format. Since
+    /// a client can't be recognised before its first command, command lines that end in a bare
+    /// carriage return are accepted from all clients.
+    Apply,
+}
+
+/// The option to [ServerBuilder::data_connection_source](crate::ServerBuilder::data_connection_source).
+/// Tells which clients may connect to the passive data port that `PASV` opened.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum DataConnectionSource {
+    /// Any address may connect. This is the default, since clients behind some NAT setups make the
+    /// data connection from another address than the control connection.
+    #[default]
+    Any,
+    /// Only the IP address of the control connection may connect. A connection from elsewhere is
+    /// refused and the transfer command that follows is answered with `425`, so that the data of
+    /// a session can't be taken by whoever connects to the port first when TLS is not in use.
+    SameIp,
+}
+
+/// The option to [ServerBuilder::ftps_tls_first](crate::ServerBuilder::ftps_tls_first). Tells
+/// whether clients have to secure the control channel with `AUTH TLS` before doing anything else.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum TlsFirst {
+    /// Clients may send other commands before upgrading to TLS. Whether they have to upgrade at all
+    /// is determined by [ServerBuilder::ftps_required](crate::ServerBuilder::ftps_required). This
+    /// is the default.
+    #[default]
+    Off,
+    /// `AUTH TLS` has to be the very first command. Any other command sent before the TLS
+    /// handshake completed is answered with a 550 reply. The greeting is sent in plaintext as usual.
+    Required,
+    /// Like [`Required`](TlsFirst::Required) but the 220 greeting is withheld until the TLS
+    /// handshake completed, so that nothing about the server is revealed in plaintext. Clients need
+    /// to send `AUTH TLS` without waiting for the greeting and will then receive it over the secured
+    /// connection.
+    RequiredHideGreeting,
+}
+
+impl TlsFirst {
+    pub(crate) fn is_required(&self) -> bool {
+        !matches!(self, TlsFirst::Off)
+    }
+}
+
+impl From<bool> for TlsFirst {
+    fn from(on: bool) -> Self {
+        match on {
+            true => TlsFirst::Required,
+            false => TlsFirst::Off,
+        }
+    }
+}
+
 bitflags! {
     /// Used to configure TLS options employed for FTPS
     #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -133,6 +983,7 @@ impl Default for TlsFlags {
 /// The option to [ServerBuilder::ftps_client_auth](crate::ServerBuilder::ftps_client_auth). Tells if and how mutual TLS (client certificate
 /// authentication) should be handled.
 #[derive(Debug, PartialEq, Clone, Copy, Default)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(rename_all = "snake_case"))]
 pub enum FtpsClientAuth {
     /// Mutual TLS is switched off and the server won't ask the client for a certificate in the TLS
     /// protocol. This is the default.
@@ -162,6 +1013,7 @@ impl From<bool> for FtpsClientAuth {
 /// The options for [ServerBuilder::sitemd5](crate::ServerBuilder::sitemd5).
 /// Allow MD5 either to be used by all, logged in users only or no one.
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(rename_all = "snake_case"))]
 pub enum SiteMd5 {
     /// Enabled for all users, including anonymous
     All,
@@ -215,12 +1067,236 @@ impl Default for Shutdown {
     }
 }
 
+/// The option to [ServerBuilder::command_limits](crate::ServerBuilder::command_limits). Limits
+/// what a client may send on the control channel, so that it can't flood the server with long
+/// lines or many commands. Clients that exceed a limit are disconnected.
+///
+/// By default the length of a command line is limited to 8 KiB and at most 32 commands are held
+/// back while a data transfer is in progress.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::options::CommandLimits;
+///
+/// let limits = CommandLimits::new()
+///     .max_line_length(1024)
+///     .commands_per_second(50)
+///     .commands_before_login(10);
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default, deny_unknown_fields))]
+pub struct CommandLimits {
+    pub(crate) max_line_length: usize,
+    pub(crate) commands_per_second: Option<u32>,
+    pub(crate) commands_before_login: Option<u32>,
+    pub(crate) max_held_commands: usize,
+}
+
+impl CommandLimits {
+    /// Creates the default limits.
+    pub fn new() -> Self {
+        CommandLimits::default()
+    }
+
+    /// Sets the maximum length in bytes of a command line, including the line ending. Clients that
+    /// send a longer line receive a `500` reply and are disconnected.
+    pub fn max_line_length(mut self, bytes: usize) -> Self {
+        self.max_line_length = bytes;
+        self
+    }
+
+    /// Sets the maximum number of commands that a session may send within a second.
+    pub fn commands_per_second(mut self, max: u32) -> Self {
+        self.commands_per_second = Some(max);
+        self
+    }
+
+    /// Sets the maximum number of commands that a client may send before it logged in,
+    /// e.g. to stop clients that keep sending `USER` and `PASS` on the same connection.
+    pub fn commands_before_login(mut self, max: u32) -> Self {
+        self.commands_before_login = Some(max);
+        self
+    }
+
+    /// Sets the maximum number of commands that are held back while a data transfer is in
+    /// progress, to run once it completed. Clients that send more receive a `421` reply and are
+    /// disconnected.
+    pub fn max_held_commands(mut self, max: usize) -> Self {
+        self.max_held_commands = max;
+        self
+    }
+}
+
+impl Default for CommandLimits {
+    fn default() -> Self {
+        CommandLimits {
+            max_line_length: 8192,
+            commands_per_second: None,
+            commands_before_login: None,
+            max_held_commands: 32,
+        }
+    }
+}
+
+/// The option to [ServerBuilder::control_socket_options](crate::ServerBuilder::control_socket_options)
+/// and [ServerBuilder::data_socket_options](crate::ServerBuilder::data_socket_options). Tunes the
+/// TCP sockets of the connections, e.g. for links with a high latency that need larger windows.
+/// Options that aren't set keep the defaults of the operating system.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::options::SocketOptions;
+/// use std::time::Duration;
+///
+/// let options = SocketOptions::new()
+///     .nodelay(true)
+///     .keepalive(Duration::from_secs(60))
+///     .recv_buffer_size(4 * 1024 * 1024);
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct SocketOptions {
+    pub(crate) nodelay: Option<bool>,
+    pub(crate) keepalive: Option<Duration>,
+    pub(crate) send_buffer_size: Option<u32>,
+    pub(crate) recv_buffer_size: Option<u32>,
+}
+
+impl SocketOptions {
+    /// Creates options that change nothing.
+    pub fn new() -> Self {
+        SocketOptions::default()
+    }
+
+    /// Sets `TCP_NODELAY`, so that small writes are sent straight away instead of being collected
+    /// into larger segments.
+    pub fn nodelay(mut self, enabled: bool) -> Self {
+        self.nodelay = Some(enabled);
+        self
+    }
+
+    /// Enables `SO_KEEPALIVE`, with the time that the connection is idle before the first probe is
+    /// sent.
+    pub fn keepalive(mut self, idle: Duration) -> Self {
+        self.keepalive = Some(idle);
+        self
+    }
+
+    /// Sets the size in bytes of the send buffer, `SO_SNDBUF`.
+    pub fn send_buffer_size(mut self, bytes: u32) -> Self {
+        self.send_buffer_size = Some(bytes);
+        self
+    }
+
+    /// Sets the size in bytes of the receive buffer, `SO_RCVBUF`. For passive and active data
+    /// connections it is set before the connection is made, so that it counts for the TCP window
+    /// that is agreed on.
+    pub fn recv_buffer_size(mut self, bytes: u32) -> Self {
+        self.recv_buffer_size = Some(bytes);
+        self
+    }
+
+    // Applies the options to a connection.
+    pub(crate) fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+        if let Some(enabled) = self.nodelay {
+            stream.set_nodelay(enabled)?;
+        }
+        let socket = socket2::SockRef::from(stream);
+        if let Some(idle) = self.keepalive {
+            socket.set_tcp_keepalive(&socket2::TcpKeepalive::new().with_time(idle))?;
+        }
+        if let Some(bytes) = self.send_buffer_size {
+            socket.set_send_buffer_size(bytes as usize)?;
+        }
+        if let Some(bytes) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(bytes as usize)?;
+        }
+        Ok(())
+    }
+
+    // Applies the buffer sizes to a socket before it listens or connects. Accepted connections
+    // inherit them.
+    pub(crate) fn apply_buffers(&self, socket: &TcpSocket) -> io::Result<()> {
+        if let Some(bytes) = self.send_buffer_size {
+            socket.set_send_buffer_size(bytes)?;
+        }
+        if let Some(bytes) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(bytes)?;
+        }
+        Ok(())
+    }
+}
+
+/// Makes the sessions wait until the [`DataListener`](crate::notification::DataListener) and the
+/// [`PresenceListener`](crate::notification::PresenceListener) handled an event before they go on,
+/// for when the event must be stored durably before the client is told that its transfer
+/// completed. The listeners are then called with
+/// [`try_receive_data_event`](crate::notification::DataListener::try_receive_data_event) and
+/// [`try_receive_presence_event`](crate::notification::PresenceListener::try_receive_presence_event).
+/// A call that fails or doesn't finish in time can be retried. If the event still isn't delivered
+/// it is dropped, or, with [`fail_transfer`](EventDelivery::fail_transfer), the client receives a
+/// `451` reply instead of the `226` for its transfer.
+///
+/// Used with [`ServerBuilder::event_delivery`](crate::ServerBuilder::event_delivery).
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::options::EventDelivery;
+/// use std::time::Duration;
+///
+/// let delivery = EventDelivery::new(Duration::from_secs(5))
+///     .retries(3, Duration::from_millis(200))
+///     .fail_transfer();
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct EventDelivery {
+    pub(crate) timeout: Duration,
+    pub(crate) attempts: u32,
+    pub(crate) backoff: Duration,
+    pub(crate) fail_transfer: bool,
+}
+
+impl EventDelivery {
+    /// Waits at most the given time for a listener to handle an event. By default an event is
+    /// delivered once and dropped if that fails.
+    pub fn new(timeout: Duration) -> Self {
+        EventDelivery {
+            timeout,
+            attempts: 1,
+            backoff: Duration::ZERO,
+            fail_transfer: false,
+        }
+    }
+
+    /// Tries to deliver an event the given number of times in total, waiting `backoff` before the
+    /// first retry and twice as long before every next one.
+    pub fn retries(mut self, attempts: u32, backoff: Duration) -> Self {
+        self.attempts = attempts.max(1);
+        self.backoff = backoff;
+        self
+    }
+
+    /// Fails a transfer when its [`DataEvent::Got`](crate::notification::DataEvent::Got) or
+    /// [`DataEvent::Put`](crate::notification::DataEvent::Put) event couldn't be delivered: the
+    /// client receives a `451` reply instead of the `226`. An uploaded file stays on the storage
+    /// back-end, so the client can simply upload it again.
+    pub fn fail_transfer(mut self) -> Self {
+        self.fail_transfer = true;
+        self
+    }
+}
+
 #[derive(Debug, Clone)]
 /// Variants for failed logins protection policy
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(rename_all = "snake_case"))]
 pub enum FailedLoginsBlock {
     /// User plus source IP address blocking
+    #[cfg_attr(feature = "config", serde(rename = "user_and_ip"))]
     UserAndIP,
     /// Block a source IP regardless of user
+    #[cfg_attr(feature = "config", serde(rename = "ip"))]
     IP,
     /// Block the user regardless of source IP
     User,
@@ -258,6 +1334,7 @@ impl Default for FailedLoginsPolicy {
 /// [ServerBuilder::active_passive_mode](crate::ServerBuilder::active_passive_mode).  This allows
 /// to switch active / passive mode on or off.
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(rename_all = "snake_case"))]
 pub enum ActivePassiveMode {
     /// Only passive mode is enabled
     #[default]
@@ -267,3 +1344,580 @@ pub enum ActivePassiveMode {
     /// Both is enabled
     ActiveAndPassive,
 }
+
+/// The options for [ServerBuilder::command_policy](crate::ServerBuilder::command_policy) and
+/// [UserDetail::command_policy](crate::auth::UserDetail::command_policy). Tells which FTP commands
+/// a client may use. Commands are named by their verb, without the `SITE` prefix for site commands
+/// (e.g. `DELE`, `RMD` or `CHOWN`) and compared case insensitively. The commands needed to log in
+/// and out (`USER`, `PASS` and `QUIT`) are always allowed.
+///
+/// A command that isn't allowed is answered with `502 Command not allowed`.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub enum CommandPolicy {
+    /// All commands are allowed
+    #[default]
+    AllowAll,
+    /// All commands except the listed ones are allowed
+    Deny(HashSet<String>),
+    /// Only the listed commands are allowed
+    AllowOnly(HashSet<String>),
+}
+
+impl CommandPolicy {
+    /// Creates a policy that forbids the given commands, for instance `["DELE", "RMD"]` for
+    /// upload-only accounts.
+    pub fn deny<I, S>(commands: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        CommandPolicy::Deny(commands.into_iter().map(|c| c.as_ref().to_uppercase()).collect())
+    }
+
+    /// Creates a policy that only allows the given commands.
+    pub fn allow_only<I, S>(commands: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        CommandPolicy::AllowOnly(commands.into_iter().map(|c| c.as_ref().to_uppercase()).collect())
+    }
+
+    /// Tells if the command with the given name may be used.
+    pub fn allows(&self, command: &str) -> bool {
+        let command = command.to_uppercase();
+        if matches!(command.as_str(), "USER" | "PASS" | "QUIT") {
+            return true;
+        }
+        match self {
+            CommandPolicy::AllowAll => true,
+            CommandPolicy::Deny(denied) => !denied.contains(&command),
+            CommandPolicy::AllowOnly(allowed) => allowed.contains(&command),
+        }
+    }
+}
+
+/// What a [`ConnectionPolicy`] decided about a client that connected.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ConnectionDecision {
+    /// The client may go on and log in.
+    Allow,
+    /// The client is answered with `421` and disconnected. The reason is logged.
+    Reject(String),
+}
+
+/// Decides whether clients may connect, before they are greeted and before they try to log in.
+/// Suits reputation checks like a DNS-based blocklist, see [`DnsBlocklist`], or a lookup with an
+/// internal service. Set it with
+/// [`ServerBuilder::connection_policy`](crate::ServerBuilder::connection_policy).
+///
+/// The decision for an address is remembered for [`cache_ttl`](ConnectionPolicy::cache_ttl), so
+/// that clients that connect often don't wait for a lookup every time.
+///
+/// # Example
+///
+/// ```rust
+/// use async_trait::async_trait;
+/// use libunftp::options::{ConnectionDecision, ConnectionPolicy};
+/// use std::net::IpAddr;
+///
+/// #[derive(Debug)]
+/// struct NoLoopback;
+///
+/// #[async_trait]
+/// impl ConnectionPolicy for NoLoopback {
+///     async fn check(&self, source: IpAddr) -> ConnectionDecision {
+///         match source.is_loopback() {
+///             true => ConnectionDecision::Reject("loopback address".to_string()),
+///             false => ConnectionDecision::Allow,
+///         }
+///     }
+/// }
+/// ```
+#[async_trait]
+pub trait ConnectionPolicy: Debug + Send + Sync {
+    /// Decides about the client with the given address. Implementations decide themselves what to
+    /// do when a lookup fails.
+    async fn check(&self, source: IpAddr) -> ConnectionDecision;
+
+    /// Decides whether the given user, who just authenticated, may log in from the given address.
+    /// This allows per-user rules, like the [`UserDetail::geo_restriction`](crate::auth::UserDetail::geo_restriction)
+    /// that the `GeoIpPolicy` applies. Rejected users receive a `530` reply. This default
+    /// implementation allows all users.
+    async fn check_login(&self, _source: IpAddr, _user: &dyn UserDetail) -> ConnectionDecision {
+        ConnectionDecision::Allow
+    }
+
+    /// How long the decision for an address is remembered. Five minutes by default, zero checks
+    /// every connection.
+    fn cache_ttl(&self) -> Duration {
+        Duration::from_secs(300)
+    }
+}
+
+// Puts the cache in front of a ConnectionPolicy.
+pub(crate) struct CachedConnectionPolicy {
+    policy: Arc<dyn ConnectionPolicy>,
+    cache: Option<moka::sync::Cache<IpAddr, ConnectionDecision>>,
+}
+
+impl CachedConnectionPolicy {
+    pub(crate) fn new(policy: Arc<dyn ConnectionPolicy>) -> Self {
+        let ttl = policy.cache_ttl();
+        let cache = (!ttl.is_zero()).then(|| moka::sync::CacheBuilder::new(100_000).time_to_live(ttl).build());
+        CachedConnectionPolicy { policy, cache }
+    }
+
+    pub(crate) async fn check(&self, source: IpAddr) -> ConnectionDecision {
+        if let Some(decision) = self.cache.as_ref().and_then(|cache| cache.get(&source)) {
+            return decision;
+        }
+        let decision = self.policy.check(source).await;
+        if let Some(cache) = &self.cache {
+            cache.insert(source, decision.clone());
+        }
+        decision
+    }
+
+    // Logins aren't cached, the decision depends on the user.
+    pub(crate) async fn check_login(&self, source: IpAddr, user: &dyn UserDetail) -> ConnectionDecision {
+        self.policy.check_login(source, user).await
+    }
+}
+
+impl Debug for CachedConnectionPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CachedConnectionPolicy").field("policy", &self.policy).finish()
+    }
+}
+
+/// A [`ConnectionPolicy`] that rejects clients whose address is listed in one of the given DNS
+/// blocklists (DNSBL), for instance `zen.spamhaus.org`. An address counts as listed when the
+/// lookup of its reversed form in the zone returns an address in `127.0.0.0/8`. Lookups that
+/// fail or take longer than the timeout, two seconds by default, let the client through.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::Server;
+/// use libunftp::options::DnsBlocklist;
+/// use std::time::Duration;
+/// use unftp_sbe_fs::ServerExt;
+///
+/// let server = Server::with_fs("/tmp")
+///              .connection_policy(DnsBlocklist::new(["zen.spamhaus.org"]).timeout(Duration::from_secs(1)))
+///              .build();
+/// ```
+#[derive(Debug, Clone)]
+pub struct DnsBlocklist {
+    zones: Vec<String>,
+    timeout: Duration,
+}
+
+impl DnsBlocklist {
+    /// Creates a policy that looks the clients up in the given zones.
+    pub fn new<I, S>(zones: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        DnsBlocklist {
+            zones: zones.into_iter().map(Into::into).collect(),
+            timeout: Duration::from_secs(2),
+        }
+    }
+
+    /// Sets how long to wait for the lookup in a zone.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    // The name to look up: the octets of IPv4 addresses and the nibbles of IPv6 addresses in
+    // reverse order, followed by the zone.
+    fn query_name(source: IpAddr, zone: &str) -> String {
+        let labels: Vec<String> = match source {
+            IpAddr::V4(ip) => ip.octets().iter().rev().map(|octet| octet.to_string()).collect(),
+            IpAddr::V6(ip) => ip
+                .octets()
+                .iter()
+                .rev()
+                .flat_map(|byte| [byte & 0xf, byte >> 4])
+                .map(|nibble| format!("{:x}", nibble))
+                .collect(),
+        };
+        format!("{}.{}", labels.join("."), zone)
+    }
+}
+
+#[async_trait]
+impl ConnectionPolicy for DnsBlocklist {
+    async fn check(&self, source: IpAddr) -> ConnectionDecision {
+        for zone in &self.zones {
+            let name = DnsBlocklist::query_name(source, zone);
+            // The port is needed for the lookup but not used.
+            let lookup = tokio::time::timeout(self.timeout, tokio::net::lookup_host((name.as_str(), 0))).await;
+            if let Ok(Ok(mut addrs)) = lookup {
+                if addrs.any(|addr| matches!(addr.ip(), IpAddr::V4(ip) if ip.octets()[0] == 127)) {
+                    return ConnectionDecision::Reject(format!("listed in {}", zone));
+                }
+            }
+        }
+        ConnectionDecision::Allow
+    }
+}
+
+/// Restricts the countries and networks (autonomous systems) that clients may connect from, for
+/// the `GeoIpPolicy` of the `geoip` feature. It is used server-wide and can be overridden per
+/// user with [`UserDetail::geo_restriction`](crate::auth::UserDetail::geo_restriction).
+///
+/// Countries are given by their ISO 3166-1 alpha-2 code, like `NL`, and compared case
+/// insensitively. A client is let through when neither its country nor its network is denied and,
+/// if there is an allow list, its country or network is on it. Clients whose country or network
+/// isn't known can't be on an allow list.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::options::GeoRestriction;
+///
+/// // Only from the Netherlands and Belgium, but not from this hosting provider.
+/// let restriction = GeoRestriction::new().allow_countries(["NL", "BE"]).deny_asns([64496]);
+/// assert!(restriction.allows(Some("nl"), Some(1136)));
+/// assert!(!restriction.allows(Some("NL"), Some(64496)));
+/// assert!(!restriction.allows(None, None));
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct GeoRestriction {
+    allowed_countries: HashSet<String>,
+    denied_countries: HashSet<String>,
+    allowed_asns: HashSet<u32>,
+    denied_asns: HashSet<u32>,
+}
+
+impl GeoRestriction {
+    /// Creates a restriction that lets everybody through.
+    pub fn new() -> Self {
+        GeoRestriction::default()
+    }
+
+    /// Adds the given countries to the allow list.
+    pub fn allow_countries<I, S>(mut self, countries: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.allowed_countries.extend(countries.into_iter().map(|c| c.as_ref().to_uppercase()));
+        self
+    }
+
+    /// Adds the given countries to the deny list.
+    pub fn deny_countries<I, S>(mut self, countries: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.denied_countries.extend(countries.into_iter().map(|c| c.as_ref().to_uppercase()));
+        self
+    }
+
+    /// Adds the given autonomous system numbers to the allow list.
+    pub fn allow_asns<I: IntoIterator<Item = u32>>(mut self, asns: I) -> Self {
+        self.allowed_asns.extend(asns);
+        self
+    }
+
+    /// Adds the given autonomous system numbers to the deny list.
+    pub fn deny_asns<I: IntoIterator<Item = u32>>(mut self, asns: I) -> Self {
+        self.denied_asns.extend(asns);
+        self
+    }
+
+    /// Tells if a client from the given country and autonomous system may connect.
+    pub fn allows(&self, country: Option<&str>, asn: Option<u32>) -> bool {
+        let country = country.map(str::to_uppercase);
+        let denied = country.as_ref().is_some_and(|c| self.denied_countries.contains(c)) || asn.is_some_and(|a| self.denied_asns.contains(&a));
+        let allow_listed = country.as_ref().is_some_and(|c| self.allowed_countries.contains(c)) || asn.is_some_and(|a| self.allowed_asns.contains(&a));
+        !denied && (allow_listed || (self.allowed_countries.is_empty() && self.allowed_asns.is_empty()))
+    }
+}
+
+/// The option to [ServerBuilder::redaction](crate::ServerBuilder::redaction). Tells how the
+/// usernames and the IP addresses of clients appear in the logs and in the events passed to the
+/// listeners in the [`notification`](crate::notification) module, so that no personal data is
+/// stored in plain text where that isn't allowed. The metrics have no labels with usernames or
+/// addresses. Authenticators, storage back-ends, upload hooks and middleware still see the real
+/// values.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum Redaction {
+    /// Usernames and addresses are shown as they are. This is the default.
+    #[default]
+    None,
+    /// Only the first character of usernames is shown and the last part of addresses is zeroed:
+    /// the last byte of IPv4 addresses and all but the first 48 bits of IPv6 addresses.
+    Truncate,
+    /// Usernames and addresses are replaced by a keyed SHA-256 hash, so that the entries of the
+    /// same user or address can still be correlated. Hashed addresses are shown as IPv6 addresses
+    /// in the `fd00::/8` range. The key is chosen randomly when the process starts, so the hashes
+    /// change after a restart and can't be reversed by hashing all possible addresses.
+    Hash,
+}
+
+lazy_static::lazy_static! {
+    static ref REDACTION_KEY: [u8; 32] = {
+        let mut key = [0; 32];
+        getrandom::getrandom(&mut key).expect("Error generating random redaction key");
+        key
+    };
+}
+
+impl Redaction {
+    // Returns the username as it may be shown.
+    pub(crate) fn user(&self, username: &str) -> String {
+        match self {
+            Redaction::None => username.to_string(),
+            Redaction::Truncate => username.chars().take(1).chain("***".chars()).collect(),
+            Redaction::Hash => Redaction::hash(username.as_bytes())[..8].iter().map(|byte| format!("{:02x}", byte)).collect(),
+        }
+    }
+
+    // Returns the IP address as it may be shown.
+    pub(crate) fn ip(&self, ip: IpAddr) -> IpAddr {
+        match (self, ip) {
+            (Redaction::None, _) => ip,
+            (Redaction::Truncate, IpAddr::V4(ip)) => IpAddr::V4((u32::from(ip) & 0xffff_ff00).into()),
+            (Redaction::Truncate, IpAddr::V6(ip)) => IpAddr::V6((u128::from(ip) & !0u128 << 80).into()),
+            (Redaction::Hash, _) => {
+                let hash = match ip {
+                    IpAddr::V4(ip) => Redaction::hash(&ip.octets()),
+                    IpAddr::V6(ip) => Redaction::hash(&ip.octets()),
+                };
+                let mut octets = [0; 16];
+                octets.copy_from_slice(&hash[..16]);
+                octets[0] = 0xfd;
+                IpAddr::V6(octets.into())
+            }
+        }
+    }
+
+    // Returns the socket address as it may be shown. The port is kept.
+    pub(crate) fn addr(&self, addr: SocketAddr) -> SocketAddr {
+        SocketAddr::new(self.ip(addr.ip()), addr.port())
+    }
+
+    fn hash(value: &[u8]) -> [u8; 32] {
+        use sha2::{Digest, Sha256};
+        let mut hasher = Sha256::new();
+        hasher.update(*REDACTION_KEY);
+        hasher.update(value);
+        hasher.finalize().into()
+    }
+}
+
+/// A download that was interrupted, as remembered by a [`SessionCache`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterruptedTransfer {
+    /// How far the file was sent: the offset the download started at plus the bytes sent.
+    pub offset: u64,
+    /// When the download was interrupted.
+    pub interrupted: SystemTime,
+}
+
+/// Remembers the downloads that were interrupted, so that a client that reconnects and resumes
+/// them with `REST` and `RETR` is treated consistently, for instance a mobile client behind a
+/// flaky connection. Set it with [`ServerBuilder::session_cache`](crate::ServerBuilder::session_cache).
+///
+/// With a session cache in place the offset given with `REST` is checked before `RETR` starts
+/// the transfer: an offset up to where the interrupted download of the same user got is accepted
+/// right away, other offsets are checked against the size of the file and answered with `554`
+/// when they lie beyond its end. Without a session cache the offset isn't checked.
+///
+/// [`MemorySessionCache`] keeps the downloads in memory. Implement the trait to share them
+/// between the instances of a server behind a load balancer.
+#[async_trait]
+pub trait SessionCache: Debug + Send + Sync {
+    /// Remembers the interrupted download of the file at the given path by the given user.
+    async fn store(&self, username: &str, path: &str, transfer: InterruptedTransfer);
+
+    /// Returns and forgets the interrupted download of the file at the given path by the given
+    /// user, if it is remembered.
+    async fn take(&self, username: &str, path: &str) -> Option<InterruptedTransfer>;
+}
+
+/// A [`SessionCache`] that keeps the interrupted downloads in memory for the given time.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::Server;
+/// use libunftp::options::MemorySessionCache;
+/// use std::time::Duration;
+/// use unftp_sbe_fs::ServerExt;
+///
+/// let server = Server::with_fs("/tmp")
+///              .session_cache(MemorySessionCache::new(Duration::from_secs(120)))
+///              .build();
+/// ```
+#[derive(Debug)]
+pub struct MemorySessionCache {
+    transfers: moka::sync::Cache<(String, String), InterruptedTransfer>,
+}
+
+impl MemorySessionCache {
+    /// Creates a cache that remembers interrupted downloads for the given time.
+    pub fn new(window: Duration) -> Self {
+        MemorySessionCache {
+            transfers: moka::sync::CacheBuilder::new(100_000).time_to_live(window).build(),
+        }
+    }
+}
+
+#[async_trait]
+impl SessionCache for MemorySessionCache {
+    async fn store(&self, username: &str, path: &str, transfer: InterruptedTransfer) {
+        self.transfers.insert((username.to_string(), path.to_string()), transfer);
+    }
+
+    async fn take(&self, username: &str, path: &str) -> Option<InterruptedTransfer> {
+        self.transfers.remove(&(username.to_string(), path.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn applies_socket_options() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let options = SocketOptions::new()
+            .nodelay(true)
+            .keepalive(Duration::from_secs(30))
+            .send_buffer_size(256 * 1024);
+        options.apply(&client).unwrap();
+        assert!(client.nodelay().unwrap());
+        let socket = socket2::SockRef::from(&client);
+        assert!(socket.keepalive().unwrap());
+        assert!(socket.send_buffer_size().unwrap() >= 256 * 1024);
+        // Nothing is changed by default.
+        let client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        SocketOptions::new().apply(&client).unwrap();
+        assert!(!client.nodelay().unwrap());
+    }
+
+    #[test]
+    fn parses_metadata_responses() {
+        let ok = "HTTP/1.0 200 OK\r\nContent-Type: text/plain\r\n\r\n203.0.113.7";
+        assert_eq!(parse_http_response(ok).unwrap(), "203.0.113.7");
+        let not_found = "HTTP/1.0 404 Not Found\r\n\r\n";
+        assert_eq!(
+            parse_http_response(not_found).unwrap_err().to_string(),
+            "metadata service replied HTTP/1.0 404 Not Found"
+        );
+        assert!(parse_http_response("garbage").is_err());
+    }
+
+    #[test]
+    fn shifts_passive_ports() {
+        let ip = Ipv4Addr::new(203, 0, 113, 7);
+        assert_eq!(PortOffset(10000).map(ip, 50000).unwrap(), (ip, 60000));
+        assert_eq!(PortOffset(-20000).map(ip, 50000).unwrap(), (ip, 30000));
+        assert!(PortOffset(20000).map(ip, 50000).is_err());
+        assert!(PortOffset(-50000).map(ip, 50000).is_err());
+    }
+
+    #[derive(Debug)]
+    struct CountingPolicy(std::sync::atomic::AtomicUsize);
+
+    #[async_trait]
+    impl ConnectionPolicy for CountingPolicy {
+        async fn check(&self, _source: IpAddr) -> ConnectionDecision {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            ConnectionDecision::Reject("counted".to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn remembers_connection_decisions() {
+        let policy = Arc::new(CountingPolicy(Default::default()));
+        let cached = CachedConnectionPolicy::new(policy.clone());
+        let ip: IpAddr = "192.168.1.10".parse().unwrap();
+        assert_eq!(cached.check(ip).await, ConnectionDecision::Reject("counted".to_string()));
+        assert_eq!(cached.check(ip).await, ConnectionDecision::Reject("counted".to_string()));
+        cached.check("192.168.1.11".parse().unwrap()).await;
+        assert_eq!(policy.0.load(std::sync::atomic::Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn forgets_resumed_downloads() {
+        let cache = MemorySessionCache::new(Duration::from_secs(60));
+        let transfer = InterruptedTransfer {
+            offset: 1024,
+            interrupted: SystemTime::now(),
+        };
+        cache.store("alice", "/big.iso", transfer.clone()).await;
+        assert_eq!(cache.take("bob", "/big.iso").await, None);
+        assert_eq!(cache.take("alice", "/big.iso").await, Some(transfer));
+        assert_eq!(cache.take("alice", "/big.iso").await, None);
+    }
+
+    #[tokio::test]
+    async fn applies_the_upload_conflict_policy() {
+        let conflicts = UploadConflicts {
+            policy: UploadConflictPolicy::Reject,
+            ..UploadConflicts::default()
+        };
+        let first = conflicts.acquire(Path::new("/in/./a.csv"), "0x1").await.unwrap();
+        assert!(first.is_some());
+        assert!(conflicts.acquire(Path::new("/in/sub/../a.csv"), "0x2").await.is_none());
+        assert!(conflicts.acquire(Path::new("/in/b.csv"), "0x2").await.is_some());
+
+        let serialized = UploadConflicts {
+            policy: UploadConflictPolicy::Serialize,
+            ..conflicts.clone()
+        };
+        let waiting = tokio::spawn(async move { serialized.acquire(Path::new("/in/a.csv"), "0x3").await.map(|lock| lock.is_some()) });
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+        drop(first);
+        assert_eq!(waiting.await.unwrap(), Some(true));
+    }
+
+    #[test]
+    fn builds_dnsbl_query_names() {
+        assert_eq!(
+            DnsBlocklist::query_name("192.168.1.10".parse().unwrap(), "zen.spamhaus.org"),
+            "10.1.168.192.zen.spamhaus.org"
+        );
+        assert_eq!(
+            DnsBlocklist::query_name("2001:db8::1".parse().unwrap(), "dnsbl.example"),
+            "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.dnsbl.example"
+        );
+    }
+
+    #[test]
+    fn redacts_usernames_and_addresses() {
+        let v4: IpAddr = "192.168.1.10".parse().unwrap();
+        let v6: IpAddr = "2001:db8:1234:5678::1".parse().unwrap();
+        assert_eq!(Redaction::None.user("alice"), "alice");
+        assert_eq!(Redaction::Truncate.user("alice"), "a***");
+        assert_eq!(Redaction::Truncate.ip(v4), "192.168.1.0".parse::<IpAddr>().unwrap());
+        assert_eq!(Redaction::Truncate.ip(v6), "2001:db8:1234::".parse::<IpAddr>().unwrap());
+        assert_eq!(Redaction::Hash.user("alice"), Redaction::Hash.user("alice"));
+        assert_ne!(Redaction::Hash.user("alice"), Redaction::Hash.user("bob"));
+        assert_eq!(Redaction::Hash.user("alice").len(), 16);
+        let hashed = Redaction::Hash.addr("192.168.1.10:2121".parse().unwrap());
+        assert_eq!(hashed.port(), 2121);
+        assert!(matches!(hashed.ip(), IpAddr::V6(ip) if ip.octets()[0] == 0xfd));
+    }
+
+    #[cfg(all(feature = "sandbox", target_os = "linux"))]
+    #[test]
+    fn no_new_privileges_sets_the_flag() {
+        NoNewPrivileges.enter().unwrap();
+        assert!(nix::sys::prctl::get_no_new_privs().unwrap());
+    }
+}
@@ -0,0 +1,89 @@
+//! Contains the code that gives up privileges once the server is bound to its port, see
+//! [`ServerBuilder::run_as`](crate::ServerBuilder::run_as) and
+//! [`ServerBuilder::sandbox`](crate::ServerBuilder::sandbox).
+
+#[cfg(feature = "sandbox")]
+use crate::options::Sandbox;
+#[cfg(feature = "sandbox")]
+use std::sync::Arc;
+use thiserror::Error;
+
+/// The error returned when the privileges could not be dropped.
+#[derive(Error, Debug)]
+#[error("could not drop privileges: {msg}")]
+pub struct PrivilegeError {
+    msg: String,
+}
+
+impl PrivilegeError {
+    fn new(msg: impl Into<String>) -> Self {
+        PrivilegeError { msg: msg.into() }
+    }
+}
+
+// The system user and group to switch to.
+#[cfg(unix)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct RunAs {
+    pub user: String,
+    pub group: String,
+}
+
+#[cfg(unix)]
+impl RunAs {
+    // Switches the process, including all of its threads, to the user and group. The supplementary
+    // groups are replaced by the group.
+    fn apply(&self) -> Result<(), PrivilegeError> {
+        use nix::unistd::{setgid, setuid, Group, Uid, User};
+
+        let group = Group::from_name(&self.group)
+            .map_err(|err| PrivilegeError::new(format!("could not look up group {}: {}", self.group, err)))?
+            .ok_or_else(|| PrivilegeError::new(format!("no such group: {}", self.group)))?;
+        let user = User::from_name(&self.user)
+            .map_err(|err| PrivilegeError::new(format!("could not look up user {}: {}", self.user, err)))?
+            .ok_or_else(|| PrivilegeError::new(format!("no such user: {}", self.user)))?;
+        #[cfg(not(target_vendor = "apple"))]
+        nix::unistd::setgroups(&[group.gid]).map_err(|err| PrivilegeError::new(format!("setgroups failed: {}", err)))?;
+        setgid(group.gid).map_err(|err| PrivilegeError::new(format!("setgid failed: {}", err)))?;
+        setuid(user.uid).map_err(|err| PrivilegeError::new(format!("setuid failed: {}", err)))?;
+        // Make sure there is no way back.
+        if !user.uid.is_root() && setuid(Uid::from_raw(0)).is_ok() {
+            return Err(PrivilegeError::new("root privileges could be regained"));
+        }
+        Ok(())
+    }
+}
+
+// What the listener does once it is bound to its port, before accepting connections.
+#[derive(Debug, Clone, Default)]
+pub(super) struct Privileges {
+    #[cfg(unix)]
+    pub run_as: Option<RunAs>,
+    #[cfg(feature = "sandbox")]
+    pub sandbox: Option<Arc<dyn Sandbox>>,
+}
+
+impl Privileges {
+    // Switches to the configured user and enters the sandbox.
+    pub fn drop(&self, logger: &slog::Logger) -> Result<(), PrivilegeError> {
+        #[cfg(unix)]
+        if let Some(run_as) = &self.run_as {
+            run_as.apply()?;
+            slog::info!(logger, "Dropped privileges, running as {}:{}", run_as.user, run_as.group);
+        }
+        self.enter_sandbox(logger)
+    }
+
+    // Only enters the sandbox, for processes that serve a single session.
+    #[cfg_attr(not(feature = "sandbox"), allow(unused_variables))]
+    pub fn enter_sandbox(&self, logger: &slog::Logger) -> Result<(), PrivilegeError> {
+        #[cfg(feature = "sandbox")]
+        if let Some(sandbox) = &self.sandbox {
+            sandbox
+                .enter()
+                .map_err(|err| PrivilegeError::new(format!("could not enter the sandbox: {}", err)))?;
+            slog::info!(logger, "Entered sandbox {:?}", sandbox);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,357 @@
+//! Contains the [`SiteCommandRegistry`] that holds the `SITE` subcommands of a server, see
+//! [`ServerBuilder::site_command`](crate::ServerBuilder::site_command).
+
+use super::{chosen::SessionStorage, options::SiteMd5};
+use crate::{
+    auth::UserDetail,
+    server::controlchan::{Reply, ReplyCode},
+    server::path,
+    storage::{Error, PathFilter, StorageBackend, FEATURE_SITEMD5},
+};
+use futures_util::future::BoxFuture;
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug, Formatter},
+    future::Future,
+    path::PathBuf,
+    sync::Arc,
+};
+
+/// What a `SITE` subcommand registered with a [`SiteCommandRegistry`] gets to work with.
+pub struct SiteCommandContext<Storage, User>
+where
+    Storage: StorageBackend<User>,
+    User: UserDetail,
+{
+    /// Everything the client sent after the name of the subcommand.
+    pub arguments: String,
+    /// The current working directory of the session.
+    pub cwd: PathBuf,
+    /// The name the client logged in with.
+    pub username: Option<String>,
+    user: Arc<Option<User>>,
+    storage: Arc<SessionStorage<Storage>>,
+    path_filter: Arc<dyn PathFilter>,
+}
+
+impl<Storage, User> SiteCommandContext<Storage, User>
+where
+    Storage: StorageBackend<User>,
+    User: UserDetail,
+{
+    /// Returns the logged in user. `SITE` commands are only run for logged in users.
+    pub fn user(&self) -> &User {
+        (*self.user).as_ref().expect("SITE commands require a logged in user")
+    }
+
+    /// Resolves a path given in the arguments against the working directory and normalizes it
+    /// the way the built-in commands do, e.g. `sub//dir/../file/` becomes `<cwd>/sub/file`. Like
+    /// for the built-in commands, the path goes through the
+    /// [path filter](crate::ServerBuilder::path_filter) first. If the filter rejects it, the
+    /// error is the `553` reply to send.
+    pub fn resolve<P: AsRef<str>>(&self, path: P) -> Result<PathBuf, SiteReply> {
+        match self.path_filter.filter(path.as_ref().to_string()) {
+            Ok(path) => Ok(path::resolve(&self.cwd, path)),
+            Err(err) => Err(SiteReply::new(SiteReplyCode::Reply(ReplyCode::BadFileName), err.to_string())),
+        }
+    }
+
+    /// Returns the storage back-end of the session. It applies the options of the server, like
+    /// [dotfiles](crate::ServerBuilder::dotfiles), like it does for the built-in commands.
+    pub fn storage(&self) -> &impl StorageBackend<User, Metadata = Storage::Metadata> {
+        self.storage.as_ref()
+    }
+}
+
+/// The reply a `SITE` subcommand sends to the client. A message that spans several lines is sent
+/// as a multi-line reply.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SiteReply {
+    pub(crate) code: SiteReplyCode,
+    pub(crate) message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SiteReplyCode {
+    Okay,
+    FileStatus,
+    SyntaxError,
+    NotAvailable,
+    FileError,
+    // The reply for a storage error.
+    Reply(ReplyCode),
+}
+
+impl SiteReply {
+    /// A `200` reply, the command succeeded.
+    pub fn ok<M: Into<String>>(message: M) -> Self {
+        SiteReply::new(SiteReplyCode::Okay, message)
+    }
+
+    /// A `213` reply, with the status of a file.
+    pub fn file_status<M: Into<String>>(message: M) -> Self {
+        SiteReply::new(SiteReplyCode::FileStatus, message)
+    }
+
+    /// A `501` reply, the arguments are not valid.
+    pub fn syntax_error<M: Into<String>>(message: M) -> Self {
+        SiteReply::new(SiteReplyCode::SyntaxError, message)
+    }
+
+    /// A `502` reply, the command is not available.
+    pub fn not_available<M: Into<String>>(message: M) -> Self {
+        SiteReply::new(SiteReplyCode::NotAvailable, message)
+    }
+
+    /// A `550` reply, the command failed.
+    pub fn failed<M: Into<String>>(message: M) -> Self {
+        SiteReply::new(SiteReplyCode::FileError, message)
+    }
+
+    fn new<M: Into<String>>(code: SiteReplyCode, message: M) -> Self {
+        SiteReply { code, message: message.into() }
+    }
+}
+
+impl From<Error> for SiteReply {
+    // Replies like the built-in commands do when the storage back-end fails.
+    fn from(err: Error) -> Self {
+        match Reply::from(&err) {
+            Reply::CodeAndMsg { code, msg } => SiteReply::new(SiteReplyCode::Reply(code), msg),
+            _ => SiteReply::new(SiteReplyCode::Reply(ReplyCode::LocalError), "Local error"),
+        }
+    }
+}
+
+type SiteHandler<Storage, User> = Arc<dyn Fn(SiteCommandContext<Storage, User>) -> BoxFuture<'static, SiteReply> + Send + Sync>;
+
+/// Holds the `SITE` subcommands of a server, by name. Register them with
+/// [`ServerBuilder::site_command`](crate::ServerBuilder::site_command). The built-in `SITE MD5` and
+/// `SITE RMDIR` are registered as well and can be replaced by registering a subcommand with their
+/// name.
+pub struct SiteCommandRegistry<Storage, User>
+where
+    Storage: StorageBackend<User>,
+    User: UserDetail,
+{
+    commands: HashMap<String, SiteHandler<Storage, User>>,
+}
+
+impl<Storage, User> SiteCommandRegistry<Storage, User>
+where
+    Storage: StorageBackend<User> + 'static,
+    User: UserDetail + 'static,
+{
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        SiteCommandRegistry { commands: HashMap::new() }
+    }
+
+    /// Registers the handler of the subcommand with the given name, which is case insensitive,
+    /// replacing any handler registered for it before.
+    pub fn register<F, Fut>(&mut self, name: &str, handler: F)
+    where
+        F: Fn(SiteCommandContext<Storage, User>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = SiteReply> + Send + 'static,
+    {
+        self.commands.insert(
+            name.to_uppercase(),
+            Arc::new(move |context| Box::pin(handler(context)) as BoxFuture<'static, SiteReply>),
+        );
+    }
+
+    /// Tells if a subcommand with the given name is registered.
+    pub fn contains(&self, name: &str) -> bool {
+        self.commands.contains_key(&name.to_uppercase())
+    }
+
+    /// Returns the names of the registered subcommands.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.commands.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    // Registers the built-in SITE MD5 and SITE RMDIR unless the libunftp user provided their own.
+    pub(super) fn with_builtins(mut self, site_md5: SiteMd5) -> Self {
+        if !self.contains("MD5") {
+            self.register("MD5", move |context| md5(context, site_md5));
+        }
+        if !self.contains("RMDIR") {
+            self.register("RMDIR", rmdir);
+        }
+        self
+    }
+
+    // Runs the subcommand with the given name, or returns None if there is no such subcommand.
+    fn call(&self, name: &str, call: SiteCall<SessionStorage<Storage>, User>, path_filter: &Arc<dyn PathFilter>) -> Option<BoxFuture<'static, SiteReply>> {
+        let handler = self.commands.get(&name.to_uppercase())?;
+        Some(handler(SiteCommandContext {
+            arguments: call.arguments,
+            cwd: call.cwd,
+            username: call.username,
+            user: call.user,
+            storage: call.storage,
+            path_filter: path_filter.clone(),
+        }))
+    }
+
+    // Turns the registry into the form the control loop uses, where the storage back-end is the
+    // one of the session.
+    pub(super) fn into_session_commands(self, path_filter: Arc<dyn PathFilter>) -> SiteCommands<SessionStorage<Storage>, User> {
+        SiteCommands(Arc::new(move |name, call| self.call(name, call, &path_filter)))
+    }
+}
+
+// The invocation of a SITE subcommand by a session.
+pub(crate) struct SiteCall<Storage, User> {
+    pub arguments: String,
+    pub cwd: PathBuf,
+    pub username: Option<String>,
+    pub user: Arc<Option<User>>,
+    pub storage: Arc<Storage>,
+}
+
+type SiteDispatch<Storage, User> = dyn Fn(&str, SiteCall<Storage, User>) -> Option<BoxFuture<'static, SiteReply>> + Send + Sync;
+
+// The SITE subcommands as the control loop sees them, typed by the storage back-end of the session.
+pub(crate) struct SiteCommands<Storage, User>(Arc<SiteDispatch<Storage, User>>);
+
+impl<Storage, User> SiteCommands<Storage, User> {
+    // Runs the subcommand with the given name, or returns None if there is no such subcommand.
+    pub fn call(&self, name: &str, call: SiteCall<Storage, User>) -> Option<BoxFuture<'static, SiteReply>> {
+        (self.0)(name, call)
+    }
+}
+
+impl<Storage, User> Clone for SiteCommands<Storage, User> {
+    fn clone(&self) -> Self {
+        SiteCommands(self.0.clone())
+    }
+}
+
+impl<Storage, User> Default for SiteCommands<Storage, User> {
+    fn default() -> Self {
+        SiteCommands(Arc::new(|_, _| None))
+    }
+}
+
+impl<Storage, User> Debug for SiteCommands<Storage, User> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("SiteCommands")
+    }
+}
+
+impl<Storage, User> Default for SiteCommandRegistry<Storage, User>
+where
+    Storage: StorageBackend<User> + 'static,
+    User: UserDetail + 'static,
+{
+    fn default() -> Self {
+        SiteCommandRegistry::new()
+    }
+}
+
+impl<Storage, User> Clone for SiteCommandRegistry<Storage, User>
+where
+    Storage: StorageBackend<User>,
+    User: UserDetail,
+{
+    fn clone(&self) -> Self {
+        SiteCommandRegistry {
+            commands: self.commands.clone(),
+        }
+    }
+}
+
+impl<Storage, User> Debug for SiteCommandRegistry<Storage, User>
+where
+    Storage: StorageBackend<User>,
+    User: UserDetail,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.commands.keys()).finish()
+    }
+}
+
+// The built-in SITE MD5: replies with the MD5 hash of a file, if the SiteMd5 option allows the
+// user to ask for it.
+async fn md5<Storage, User>(context: SiteCommandContext<Storage, User>, site_md5: SiteMd5) -> SiteReply
+where
+    Storage: StorageBackend<User>,
+    User: UserDetail,
+{
+    let allowed = match site_md5 {
+        SiteMd5::All => true,
+        SiteMd5::Accounts => !matches!(context.username.as_deref(), Some("anonymous") | Some("ftp")),
+        SiteMd5::None => false,
+    };
+    if !allowed {
+        return SiteReply::not_available("Command is not available.");
+    }
+    if context.storage().supported_features() & FEATURE_SITEMD5 == 0 {
+        return SiteReply::not_available("Not supported by the selected storage back-end.");
+    }
+    let path = match context.resolve(&context.arguments) {
+        Ok(path) => path,
+        Err(reply) => return reply,
+    };
+    match context.storage().md5(context.user(), &path).await {
+        Ok(md5) => SiteReply::file_status(format!("{}    {}", md5, path.display())),
+        Err(err) => err.into(),
+    }
+}
+
+// The built-in SITE RMDIR: removes a directory like RMD does or, with -R, the directory with
+// everything in it. The latter saves clients from deleting a tree file by file.
+async fn rmdir<Storage, User>(context: SiteCommandContext<Storage, User>) -> SiteReply
+where
+    Storage: StorageBackend<User>,
+    User: UserDetail,
+{
+    let (recursive, dir) = match rmdir_arguments(&context.arguments) {
+        Some(arguments) => arguments,
+        None => return SiteReply::syntax_error("Usage: SITE RMDIR [-R] <directory>"),
+    };
+    let path = match context.resolve(dir) {
+        Ok(path) => path,
+        Err(reply) => return reply,
+    };
+    let result = match recursive {
+        true => context.storage().rmd_recursive(context.user(), &path).await,
+        false => context.storage().rmd(context.user(), &path).await,
+    };
+    match result {
+        Ok(()) => SiteReply::ok(format!("Removed {}", path.display())),
+        Err(err) => err.into(),
+    }
+}
+
+// Splits the arguments of SITE RMDIR into the -R flag and the directory. Returns None if there is
+// no directory, also when there is only the flag.
+fn rmdir_arguments(arguments: &str) -> Option<(bool, &str)> {
+    let arguments = arguments.trim();
+    let (recursive, dir) = match arguments.split_once(' ') {
+        Some((flag, dir)) if flag.eq_ignore_ascii_case("-R") => (true, dir.trim()),
+        _ if arguments.eq_ignore_ascii_case("-R") => (true, ""),
+        _ => (false, arguments),
+    };
+    match dir.is_empty() {
+        true => None,
+        false => Some((recursive, dir)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_rmdir_arguments() {
+        assert_eq!(rmdir_arguments("dir"), Some((false, "dir")));
+        assert_eq!(rmdir_arguments("-r my dir "), Some((true, "my dir")));
+        assert_eq!(rmdir_arguments("-R"), None);
+        assert_eq!(rmdir_arguments("-R  "), None);
+        assert_eq!(rmdir_arguments(""), None);
+    }
+}
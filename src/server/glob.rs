@@ -0,0 +1,354 @@
+//! Shell-style wildcard matching used to filter directory listings, e.g. `LIST *.csv` or
+//! `NLST report-??.txt`.
+//!
+//! Only the last component of a path may contain wildcards. The supported syntax is:
+//!
+//! - `*` matches any sequence of characters (including none)
+//! - `?` matches exactly one character
+//! - `[abc]`, `[a-z]` match one character out of a set, `[!abc]` or `[^abc]` negates the set
+//! - `\` escapes the character that follows it
+//!
+//! Like in most shells, a wildcard never matches a leading dot: `*` does not list `.hidden` but
+//! `.*` does.
+//!
+//! Patterns come straight from the client, so compilation puts limits on their length and
+//! complexity. All patterns should be compiled through [`compile_path`] so that the control
+//! channel can refuse pathological patterns up front and the data channel applies the exact same
+//! rules.
+
+use std::fmt;
+
+/// The maximum length, in characters, of a pattern.
+pub const MAX_PATTERN_LEN: usize = 255;
+
+/// The maximum number of `*` wildcards in a pattern. Every star is a possible backtracking point
+/// while matching.
+pub const MAX_SEQUENCE_WILDCARDS: usize = 8;
+
+/// The maximum number of characters and ranges listed in all character classes of a pattern.
+pub const MAX_CLASS_MEMBERS: usize = 64;
+
+// The budget for the worst case matching cost of a pattern, estimated per character of the name
+// being matched as the number of tokens times the number of stars they may have to backtrack to.
+const MATCH_COST_BUDGET: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Literal(char),
+    AnyChar,
+    AnySequence,
+    Class { negated: bool, ranges: Vec<(char, char)> },
+}
+
+/// A compiled wildcard pattern matching a single file name.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Pattern {
+    original: String,
+    tokens: Vec<Token>,
+}
+
+/// The reason a pattern could not be compiled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatternError {
+    /// A `[` was not followed by a closing `]`.
+    UnclosedClass,
+    /// The pattern ends with a lone escape character.
+    TrailingEscape,
+    /// The pattern is longer than [`MAX_PATTERN_LEN`].
+    TooLong,
+    /// The pattern exceeds one of the complexity limits.
+    TooComplex,
+}
+
+impl PatternError {
+    /// Tells whether the pattern was refused because of the limits on its length and complexity
+    /// rather than because of a syntax error.
+    pub fn is_limit(&self) -> bool {
+        matches!(self, PatternError::TooLong | PatternError::TooComplex)
+    }
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatternError::UnclosedClass => write!(f, "unclosed character class"),
+            PatternError::TrailingEscape => write!(f, "pattern ends with an escape character"),
+            PatternError::TooLong => write!(f, "pattern is longer than {} characters", MAX_PATTERN_LEN),
+            PatternError::TooComplex => write!(f, "pattern is too complex"),
+        }
+    }
+}
+
+impl std::error::Error for PatternError {}
+
+impl fmt::Debug for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Pattern").field(&self.original).finish()
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.original)
+    }
+}
+
+impl Pattern {
+    /// Compiles the given wildcard pattern.
+    pub fn new(pattern: &str) -> Result<Pattern, PatternError> {
+        if pattern.chars().count() > MAX_PATTERN_LEN {
+            return Err(PatternError::TooLong);
+        }
+        let mut tokens = Vec::new();
+        let mut chars = pattern.chars().peekable();
+        while let Some(c) = chars.next() {
+            let token = match c {
+                '*' => {
+                    // Consecutive stars are equivalent to a single one.
+                    if tokens.last() == Some(&Token::AnySequence) {
+                        continue;
+                    }
+                    Token::AnySequence
+                }
+                '?' => Token::AnyChar,
+                '\\' => Token::Literal(chars.next().ok_or(PatternError::TrailingEscape)?),
+                '[' => {
+                    let negated = matches!(chars.peek(), Some('!') | Some('^'));
+                    if negated {
+                        chars.next();
+                    }
+                    let mut ranges = Vec::new();
+                    let mut first = true;
+                    loop {
+                        let start = match chars.next() {
+                            None => return Err(PatternError::UnclosedClass),
+                            // A ']' right after the opening bracket is taken literally.
+                            Some(']') if !first => break,
+                            Some('\\') => chars.next().ok_or(PatternError::TrailingEscape)?,
+                            Some(c) => c,
+                        };
+                        first = false;
+                        let mut lookahead = chars.clone();
+                        match (lookahead.next(), lookahead.next()) {
+                            (Some('-'), Some(end)) if end != ']' => {
+                                chars.next();
+                                chars.next();
+                                ranges.push((start, end));
+                            }
+                            _ => ranges.push((start, start)),
+                        }
+                    }
+                    Token::Class { negated, ranges }
+                }
+                c => Token::Literal(c),
+            };
+            tokens.push(token);
+        }
+        check_complexity(&tokens)?;
+        Ok(Pattern {
+            original: pattern.to_string(),
+            tokens,
+        })
+    }
+
+    /// Tells whether the given file name matches this pattern.
+    pub fn matches(&self, name: &str) -> bool {
+        if name.starts_with('.') && !matches!(self.tokens.first(), Some(Token::Literal('.'))) {
+            return false;
+        }
+        let name: Vec<char> = name.chars().collect();
+
+        // Iterative matching that only ever backtracks to the most recent '*'. This keeps the
+        // worst case at O(pattern * name) instead of exponential.
+        let (mut t, mut n) = (0, 0);
+        let mut backtrack: Option<(usize, usize)> = None;
+        while n < name.len() {
+            match self.tokens.get(t) {
+                Some(Token::AnySequence) => {
+                    backtrack = Some((t, n));
+                    t += 1;
+                    continue;
+                }
+                Some(token) if token.matches(name[n]) => {
+                    t += 1;
+                    n += 1;
+                    continue;
+                }
+                _ => {}
+            }
+            match backtrack {
+                Some((star_t, star_n)) => {
+                    backtrack = Some((star_t, star_n + 1));
+                    t = star_t + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            }
+        }
+        self.tokens[t..].iter().all(|token| *token == Token::AnySequence)
+    }
+}
+
+impl Token {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Token::Literal(l) => *l == c,
+            Token::AnyChar => true,
+            Token::AnySequence => true,
+            Token::Class { negated, ranges } => ranges.iter().any(|(start, end)| *start <= c && c <= *end) != *negated,
+        }
+    }
+}
+
+fn check_complexity(tokens: &[Token]) -> Result<(), PatternError> {
+    let stars = tokens.iter().filter(|t| **t == Token::AnySequence).count();
+    let class_members: usize = tokens
+        .iter()
+        .map(|t| match t {
+            Token::Class { ranges, .. } => ranges.len(),
+            _ => 0,
+        })
+        .sum();
+    if stars > MAX_SEQUENCE_WILDCARDS || class_members > MAX_CLASS_MEMBERS || tokens.len() * (stars + 1) > MATCH_COST_BUDGET {
+        return Err(PatternError::TooComplex);
+    }
+    Ok(())
+}
+
+/// Tells whether the given string contains unescaped wildcard characters.
+pub fn has_wildcards(s: &str) -> bool {
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '*' | '?' | '[' => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Splits a client supplied path into the directory to list and the wildcard pattern to filter
+/// its entries with. Returns `None` if the last path component contains no wildcards.
+pub fn split_path(path: &str) -> Option<(Option<&str>, &str)> {
+    let (dir, last) = match path.rfind('/') {
+        Some(0) => (Some("/"), &path[1..]),
+        Some(idx) => (Some(&path[..idx]), &path[idx + 1..]),
+        None => (None, path),
+    };
+    if has_wildcards(last) {
+        Some((dir, last))
+    } else {
+        None
+    }
+}
+
+/// Compiles the wildcard pattern in the last component of a client supplied LIST or NLST path.
+///
+/// Returns `Ok(None)` if the path should be used as is: when it contains no wildcards or when they
+/// do not form a valid pattern (e.g. a file named `report[1`). Returns an error if the pattern
+/// exceeds the length or complexity limits. Otherwise the directory to list is returned together
+/// with the pattern to filter its entries with.
+pub fn compile_path(path: &str) -> Result<Option<(Option<&str>, Pattern)>, PatternError> {
+    let (dir, pattern) = match split_path(path) {
+        Some(split) => split,
+        None => return Ok(None),
+    };
+    match Pattern::new(pattern) {
+        Ok(pattern) => Ok(Some((dir, pattern))),
+        Err(err) if err.is_limit() => Err(err),
+        Err(_) => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn matches(pattern: &str, name: &str) -> bool {
+        Pattern::new(pattern).unwrap().matches(name)
+    }
+
+    #[test]
+    fn star_matches_any_sequence() {
+        assert!(matches("*.csv", "report.csv"));
+        assert!(!matches("*.csv", ".csv"));
+        assert!(matches("*", ""));
+        assert!(matches("a*b*c", "aXXbYYc"));
+        assert!(!matches("*.csv", "report.csv.bak"));
+        assert!(matches("*.csv*", "report.csv.bak"));
+    }
+
+    #[test]
+    fn question_mark_matches_single_char() {
+        assert!(matches("file-??.txt", "file-01.txt"));
+        assert!(!matches("file-??.txt", "file-1.txt"));
+    }
+
+    #[test]
+    fn character_classes() {
+        assert!(matches("[abc].txt", "b.txt"));
+        assert!(!matches("[abc].txt", "d.txt"));
+        assert!(matches("[a-z]1", "q1"));
+        assert!(!matches("[!a-z]1", "q1"));
+        assert!(matches("[^a-z]1", "Q1"));
+        assert!(matches("[]]", "]"));
+        assert!(matches("[a-]", "-"));
+    }
+
+    #[test]
+    fn escapes_are_literal() {
+        assert!(matches("\\*.txt", "*.txt"));
+        assert!(!matches("\\*.txt", "a.txt"));
+        assert!(!has_wildcards("\\*.txt"));
+    }
+
+    #[test]
+    fn leading_dot_must_be_explicit() {
+        assert!(!matches("*", ".profile"));
+        assert!(!matches("?profile", ".profile"));
+        assert!(matches(".*", ".profile"));
+    }
+
+    #[test]
+    fn malformed_patterns() {
+        assert_eq!(Pattern::new("[abc"), Err(PatternError::UnclosedClass));
+        assert_eq!(Pattern::new("abc\\"), Err(PatternError::TrailingEscape));
+    }
+
+    #[test]
+    fn limits() {
+        assert_eq!(Pattern::new(&"a".repeat(MAX_PATTERN_LEN + 1)), Err(PatternError::TooLong));
+        assert_eq!(Pattern::new(&"*a".repeat(MAX_SEQUENCE_WILDCARDS + 1)), Err(PatternError::TooComplex));
+        assert_eq!(Pattern::new(&"[abcdefgh]".repeat(MAX_CLASS_MEMBERS / 8 + 1)), Err(PatternError::TooComplex));
+        assert_eq!(Pattern::new(&format!("{}*?*?*?*", "?".repeat(200))), Err(PatternError::TooComplex));
+        // Runs of stars collapse into a single one and do not count against the limit.
+        assert!(Pattern::new(&"*".repeat(MAX_PATTERN_LEN)).is_ok());
+    }
+
+    #[test]
+    fn pathological_pattern_matches_fast() {
+        let pattern = Pattern::new("*a*a*a*a*a*a*a*b").unwrap();
+        assert!(!pattern.matches(&"a".repeat(10_000)));
+    }
+
+    #[test]
+    fn compiles_path() {
+        assert_eq!(compile_path("dir/*.csv"), Ok(Some((Some("dir"), Pattern::new("*.csv").unwrap()))));
+        assert_eq!(compile_path("dir/file.csv"), Ok(None));
+        assert_eq!(compile_path("report[1"), Ok(None));
+        assert_eq!(compile_path(&"*a".repeat(MAX_SEQUENCE_WILDCARDS + 1)), Err(PatternError::TooComplex));
+    }
+
+    #[test]
+    fn splits_path() {
+        assert_eq!(split_path("*.csv"), Some((None, "*.csv")));
+        assert_eq!(split_path("/*.csv"), Some((Some("/"), "*.csv")));
+        assert_eq!(split_path("data/2023/*.csv"), Some((Some("data/2023"), "*.csv")));
+        assert_eq!(split_path("data/*/file.csv"), None);
+        assert_eq!(split_path("data"), None);
+    }
+}
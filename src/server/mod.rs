@@ -1,13 +1,18 @@
 //! Contains the [`Server`](crate::Server) struct that is used to configure and control an FTP server instance.
 
+mod ascii;
 mod chancomms;
 pub(crate) mod controlchan;
 mod datachan;
+pub(crate) mod encoding;
 mod failed_logins;
 pub(crate) mod ftpserver;
+mod glob;
 mod password;
+pub(crate) mod path;
 mod proxy_protocol;
 mod session;
+pub(crate) mod sessions;
 pub(crate) mod shutdown;
 mod tls;
 
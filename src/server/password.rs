@@ -1,15 +1,17 @@
-use bytes::Bytes;
 use std::convert;
 use std::fmt;
+use zeroize::Zeroizing;
 
+// The password of the PASS command. It owns its bytes, rather than referring to the command line,
+// so that they are wiped when it is dropped.
 #[derive(PartialEq, Eq, Clone)]
 pub struct Password {
-    bytes: Bytes,
+    bytes: Zeroizing<Vec<u8>>,
 }
 
 impl Password {
-    pub fn new(bytes: Bytes) -> Self {
-        Password { bytes }
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Password { bytes: Zeroizing::new(bytes) }
     }
 }
 
@@ -27,7 +29,7 @@ impl fmt::Debug for Password {
 
 impl convert::From<&str> for Password {
     fn from(s: &str) -> Self {
-        Self::new(String::from(s).into())
+        Self::new(s.as_bytes().to_vec())
     }
 }
 
@@ -60,6 +62,6 @@ mod tests {
     }
 
     fn password() -> Password {
-        Password::new(SECRET.into())
+        Password::new(SECRET.as_bytes().to_vec())
     }
 }
@@ -0,0 +1,136 @@
+//! Normalizes the paths that clients send before they reach the storage back-end, so that every
+//! back-end sees `dir//sub/./file`, `dir/sub/file/` and `/dir/other/../sub/file` as the same path.
+
+use super::encoding;
+use std::path::{Component, Path, PathBuf};
+
+/// Resolves a path sent by the client against the current working directory of the session and
+/// normalizes the result. An absolute path replaces the working directory.
+pub(crate) fn resolve<P: AsRef<Path>>(cwd: &Path, path: P) -> PathBuf {
+    normalize(cwd.join(path))
+}
+
+/// Normalizes a path without looking at the storage back-end: duplicate separators, trailing
+/// separators and `.` segments are dropped and `..` removes the segment before it. A path never
+/// climbs above its root, `..` at the root or at the start of a relative path is dropped. A
+/// relative path that ends up empty becomes `.`. Raw byte characters become the bytes they stand
+/// for, see the [`encoding`] module.
+pub(crate) fn normalize<P: AsRef<Path>>(path: P) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.as_ref().components() {
+        match component {
+            Component::RootDir => normalized.push(Component::RootDir),
+            Component::Normal(part) => normalized.push(encoding::text_to_path(part)),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            // Drive letters only exist on Windows and have no meaning in an FTP path.
+            Component::CurDir | Component::Prefix(_) => {}
+        }
+    }
+    if normalized.as_os_str().is_empty() {
+        normalized.push(Component::CurDir);
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn normalized(path: &str) -> String {
+        normalize(path).to_string_lossy().into_owned()
+    }
+
+    fn resolved(cwd: &str, path: &str) -> String {
+        resolve(Path::new(cwd), path).to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn keeps_normal_paths() {
+        assert_eq!(normalized("/"), "/");
+        assert_eq!(normalized("/dir"), "/dir");
+        assert_eq!(normalized("/dir/sub/file.txt"), "/dir/sub/file.txt");
+        assert_eq!(normalized("file.txt"), "file.txt");
+        assert_eq!(normalized("dir/file.txt"), "dir/file.txt");
+    }
+
+    #[test]
+    fn collapses_duplicate_separators() {
+        assert_eq!(normalized("//"), "/");
+        assert_eq!(normalized("dir//sub"), "dir/sub");
+        assert_eq!(normalized("/dir///sub//file"), "/dir/sub/file");
+        assert_eq!(normalized("///dir"), "/dir");
+    }
+
+    #[test]
+    fn drops_trailing_separators() {
+        assert_eq!(normalized("dir/"), "dir");
+        assert_eq!(normalized("/dir/sub/"), "/dir/sub");
+        assert_eq!(normalized("/dir/sub//"), "/dir/sub");
+        assert_eq!(normalized("/dir/./"), "/dir");
+    }
+
+    #[test]
+    fn drops_current_dir_segments() {
+        assert_eq!(normalized("."), ".");
+        assert_eq!(normalized("./"), ".");
+        assert_eq!(normalized("./file"), "file");
+        assert_eq!(normalized("/./dir/./file"), "/dir/file");
+        assert_eq!(normalized("dir//sub/./file"), "dir/sub/file");
+        assert_eq!(normalized("/."), "/");
+    }
+
+    #[test]
+    fn resolves_parent_dir_segments() {
+        assert_eq!(normalized("/dir/.."), "/");
+        assert_eq!(normalized("/dir/sub/.."), "/dir");
+        assert_eq!(normalized("/dir/sub/../other/file"), "/dir/other/file");
+        assert_eq!(normalized("/dir/sub/../../file"), "/file");
+        assert_eq!(normalized("dir/.."), ".");
+        assert_eq!(normalized("dir/sub/../file"), "dir/file");
+        assert_eq!(normalized("/dir/./../file"), "/file");
+    }
+
+    #[test]
+    fn never_climbs_above_the_root() {
+        assert_eq!(normalized("/.."), "/");
+        assert_eq!(normalized("/../.."), "/");
+        assert_eq!(normalized("/../etc/passwd"), "/etc/passwd");
+        assert_eq!(normalized("/dir/../../../etc"), "/etc");
+        assert_eq!(normalized(".."), ".");
+        assert_eq!(normalized("../file"), "file");
+        assert_eq!(normalized("../../dir/file"), "dir/file");
+    }
+
+    #[test]
+    fn resolves_against_the_working_directory() {
+        assert_eq!(resolved("/", "file"), "/file");
+        assert_eq!(resolved("/home", "file"), "/home/file");
+        assert_eq!(resolved("/home", "./file"), "/home/file");
+        assert_eq!(resolved("/home", "."), "/home");
+        assert_eq!(resolved("/home", ""), "/home");
+        assert_eq!(resolved("/home", "sub/"), "/home/sub");
+        assert_eq!(resolved("/home/user", ".."), "/home");
+        assert_eq!(resolved("/home/user", "../other//file"), "/home/other/file");
+        assert_eq!(resolved("/home", "../../.."), "/");
+    }
+
+    #[test]
+    fn absolute_paths_replace_the_working_directory() {
+        assert_eq!(resolved("/home", "/file"), "/file");
+        assert_eq!(resolved("/home", "/other/./dir/"), "/other/dir");
+        assert_eq!(resolved("/home", "//other"), "/other");
+        assert_eq!(resolved("/home", "/"), "/");
+    }
+
+    #[test]
+    fn keeps_names_that_only_look_special() {
+        assert_eq!(normalized("/dir/..."), "/dir/...");
+        assert_eq!(normalized("/dir/.hidden"), "/dir/.hidden");
+        assert_eq!(normalized("/dir/..hidden/file"), "/dir/..hidden/file");
+        assert_eq!(normalized("/dir/file."), "/dir/file.");
+        assert_eq!(normalized("/dir/ spaced /file"), "/dir/ spaced /file");
+    }
+}
@@ -1,3 +1,7 @@
+// Without the proxy-protocol feature the switchboard is still part of the session and passive
+// mode plumbing, but nothing ever reads a PROXY header or fills it.
+#![cfg_attr(not(feature = "proxy-protocol"), allow(dead_code, unused_imports))]
+
 use super::{
     chancomms::{ProxyLoopMsg, ProxyLoopSender},
     session::SharedSession,
@@ -5,6 +9,7 @@ use super::{
 use crate::{auth::UserDetail, storage::StorageBackend};
 use bytes::Bytes;
 use dashmap::{mapref::entry::Entry, DashMap};
+#[cfg(feature = "proxy-protocol")]
 use proxy_protocol::{parse, version1::ProxyAddresses, ParseError, ProxyHeader};
 use std::net::{IpAddr, SocketAddr, SocketAddrV4};
 use std::ops::Range;
@@ -14,9 +19,13 @@ use tokio::io::AsyncReadExt;
 #[derive(Clone, Copy, Debug)]
 pub(super) enum ProxyMode {
     Off,
-    On { external_control_port: u16 },
+    #[cfg(feature = "proxy-protocol")]
+    On {
+        external_control_port: u16,
+    },
 }
 
+#[cfg(feature = "proxy-protocol")]
 impl From<u16> for ProxyMode {
     fn from(port: u16) -> Self {
         ProxyMode::On { external_control_port: port }
@@ -32,6 +41,7 @@ enum ProxyError {
     HeaderSize,
     #[error("header does not match the supported proxy protocol v1")]
     NotProxyHdr,
+    #[cfg(feature = "proxy-protocol")]
     #[error("proxy protocol parse error")]
     DecodeError(#[from] ParseError),
     #[error("only IPv4 is supported")]
@@ -63,6 +73,7 @@ pub(crate) struct ProxyConnection {
 /// If the header size is invalid, or the header does not end with a CR-LF sequence, the function returns a `ProxyError`
 /// with the reason for the failure. If there is a problem reading from the TCP stream, the function returns a `ProxyError::ReadError`.
 /// If the header cannot be parsed, the function returns a `ProxyError::DecodeError`.
+#[cfg(feature = "proxy-protocol")]
 #[tracing_attributes::instrument]
 pub(self) async fn read_proxy_header(tcp_stream: &mut tokio::net::TcpStream) -> Result<ProxyHeader, ProxyError> {
     // Create two vectors to hold the data read from the TCP stream
@@ -113,6 +124,7 @@ pub(self) async fn read_proxy_header(tcp_stream: &mut tokio::net::TcpStream) ->
 
 /// Takes a tcp stream and reads the proxy protocol header
 /// Sends the extracted proxy connection information (source ip+port, destination ip+port) to the proxy loop
+#[cfg(feature = "proxy-protocol")]
 #[tracing_attributes::instrument]
 pub(super) fn spawn_proxy_header_parsing<Storage, User>(logger: slog::Logger, mut tcp_stream: tokio::net::TcpStream, tx: ProxyLoopSender<Storage, User>)
 where
@@ -173,6 +185,19 @@ impl From<&ProxyConnection> for ProxyHashKey {
     }
 }
 
+/// A passive port that a session reserved, together with the passive port range that applied to
+/// the session at the time. Keeping the range here lets the proxy loop check incoming data
+/// connections without waiting for the lock on the session.
+#[derive(Debug)]
+struct Reservation<S, U>
+where
+    S: StorageBackend<U>,
+    U: UserDetail,
+{
+    session: SharedSession<S, U>,
+    port_range: Range<u16>,
+}
+
 /// Connect clients to the right data channel
 #[derive(Debug)]
 pub(super) struct ProxyProtocolSwitchboard<S, U>
@@ -180,7 +205,7 @@ where
     S: StorageBackend<U>,
     U: UserDetail,
 {
-    switchboard: DashMap<ProxyHashKey, Option<SharedSession<S, U>>>,
+    switchboard: DashMap<ProxyHashKey, Option<Reservation<S, U>>>,
     port_range: Range<u16>,
     logger: slog::Logger,
 }
@@ -191,6 +216,7 @@ pub(super) enum ProxyProtocolError {
     EntryNotAvailable,
     // EntryCreationFailed,
     MaxRetriesError,
+    EmptyPortRange,
 }
 
 impl<S, U> ProxyProtocolSwitchboard<S, U>
@@ -207,17 +233,37 @@ where
         }
     }
 
-    pub async fn try_and_claim(&mut self, hash: ProxyHashKey, session_arc: SharedSession<S, U>) -> Result<(), ProxyProtocolError> {
+    pub async fn try_and_claim(&mut self, hash: ProxyHashKey, session_arc: SharedSession<S, U>, port_range: Range<u16>) -> Result<(), ProxyProtocolError> {
         // Atomically insert the key and value into the switchboard hashmap
         match self.switchboard.entry(hash) {
             Entry::Occupied(_) => Err(ProxyProtocolError::EntryNotAvailable),
             Entry::Vacant(entry) => {
-                entry.insert(Some(session_arc));
+                entry.insert(Some(Reservation {
+                    session: session_arc,
+                    port_range,
+                }));
                 Ok(())
             }
         }
     }
 
+    /// Tells whether a session has reserved the port this connection is going to, within the
+    /// passive port range that applied to that session when it reserved the port
+    pub fn is_reserved(&self, connection: &ProxyConnection) -> bool {
+        match self.switchboard.get(&connection.into()) {
+            Some(entry) => entry
+                .value()
+                .as_ref()
+                .is_some_and(|reservation| reservation.port_range.contains(&connection.destination.port())),
+            None => false,
+        }
+    }
+
+    // The user may have its own passive port range.
+    fn port_range_for(&self, user: Option<&U>) -> Range<u16> {
+        user.and_then(|u| u.passive_ports()).unwrap_or_else(|| self.port_range.clone())
+    }
+
     /// Unregister this specific connection
     pub fn unregister_this(&mut self, connection: &ProxyConnection) {
         let hash = connection.into();
@@ -237,36 +283,41 @@ where
         let hash: ProxyHashKey = connection.into();
 
         match self.switchboard.get(&hash) {
-            Some(session) => session.clone(),
+            Some(entry) => entry.value().as_ref().map(|reservation| reservation.session.clone()),
             None => None,
         }
     }
 
-    /// Find the next available port within the specified range (inclusive of the upper limit).
+    /// Find the next available port within the specified range (exclusive of the upper limit).
     /// The reserved port is associated with the source ip of the client and the associated session, using a hashmap
     ///
     //#[tracing_attributes::instrument]
     pub async fn reserve_next_free_port(&mut self, session_arc: SharedSession<S, U>) -> Result<u16, ProxyProtocolError> {
-        let range_size = self.port_range.end - self.port_range.start;
+        let mut session = session_arc.lock().await;
+        let port_range = self.port_range_for((*session.user).as_ref());
+        if port_range.is_empty() {
+            slog::warn!(self.logger, "Can't reserve a passive port in the empty range {:?}", port_range);
+            return Err(ProxyProtocolError::EmptyPortRange);
+        }
+        let range_size = u32::from(port_range.end - port_range.start);
 
         let randomized_initial_port = {
             let mut data = [0; 2];
             getrandom::getrandom(&mut data).expect("Error generating random free port to reserve");
-            u16::from_ne_bytes(data)
+            u32::from(u16::from_ne_bytes(data))
         };
 
         // Claims the next available listening port
         // The search starts at randomized_initial_port.
         // If a port is already claimed, the loop continues to the next port until an available port is found.
         // The function returns the first available port it finds or an error if no ports are available.
-        let mut session = session_arc.lock().await;
-        for i in 0..=range_size {
-            let port = self.port_range.start + ((randomized_initial_port + i) % range_size);
+        for i in 0..range_size {
+            let port = port_range.start + ((randomized_initial_port + i) % range_size) as u16;
             slog::debug!(self.logger, "Trying if port {} is available", port);
             if let Some(proxy_control_connection) = session.proxy_control {
                 let hash = ProxyHashKey::new(proxy_control_connection.source.ip(), port);
 
-                match &self.try_and_claim(hash.clone(), session_arc.clone()).await {
+                match &self.try_and_claim(hash.clone(), session_arc.clone(), port_range.clone()).await {
                     Ok(_) => {
                         // Remove and disassociate existing passive channels
                         if let Some(active_datachan_hash) = &session.proxy_active_datachan {
@@ -293,7 +344,7 @@ where
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "proxy-protocol"))]
 mod tests {
     use super::ProxyError;
     use proxy_protocol::{version1::ProxyAddresses, ProxyHeader};
@@ -1,21 +1,31 @@
 //! The session module implements per-connection session handling and currently also
 //! implements the handling for the *data* channel.
 
-use super::{chancomms::ControlChanMsg, tls::FtpsConfig};
+use super::{chancomms::ControlChanMsg, controlchan::quirks::Quirks, encoding::Charset, tls::FtpsConfig};
 use crate::auth::UserDetail;
-use crate::server::chancomms::DataChanCmd;
+use crate::server::chancomms::{DataAbort, DataChanCmd};
 use crate::server::failed_logins::FailedLoginsCache;
 use crate::server::proxy_protocol::{ProxyConnection, ProxyHashKey};
+use crate::server::sessions::SessionActivity;
+use crate::server::Event;
 use crate::{
     metrics,
+    notification::{DataListener, TransferLogListener, UploadHook, UploadScanner},
+    options::{
+        CachedConnectionPolicy, DataConnectionSource, MessageCatalog, ModeZ, PartialUploads, PassivePortMapper, RecursiveListing, Redaction, SessionCache,
+        SocketOptions, UploadChecksum, UploadConflicts,
+    },
     storage::{Metadata, StorageBackend},
 };
 use std::{
+    collections::VecDeque,
     fmt::{Debug, Formatter},
-    net::SocketAddr,
+    net::{Ipv4Addr, SocketAddr},
     path::PathBuf,
     sync::Arc,
+    time::Duration,
 };
+use tokio::runtime::Handle;
 use tokio::sync::mpsc::{Receiver, Sender};
 
 // TraceId is an identifier used to correlate logs statements together.
@@ -51,6 +61,21 @@ pub enum SessionState {
     WaitCmd,
 }
 
+// Where the session is in the RFC 4217 sequence that protects the data channel: PBSZ has to follow
+// AUTH TLS before PROT may set the protection level. Securing the control channel starts the
+// sequence over, clearing it with CCC leaves the protection level as it is.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum DataProtection {
+    // No PBSZ since the control channel was secured, PROT is refused.
+    NoBufferSize,
+    // PBSZ was accepted and PROT may follow. Data is sent in the clear until it does.
+    BufferSize,
+    // PROT C, data is sent in the clear.
+    Clear,
+    // PROT P, data is sent over TLS.
+    Private,
+}
+
 // The session shared via an asynchronous lock
 pub type SharedSession<S, U> = Arc<tokio::sync::Mutex<Session<S, U>>>;
 
@@ -77,9 +102,9 @@ where
     // The data loop uses this receive messages from the control loop
     pub data_cmd_rx: Option<Receiver<DataChanCmd>>,
     // The control loop uses this to ask the data loop to exit.
-    pub data_abort_tx: Option<Sender<()>>,
+    pub data_abort_tx: Option<Sender<DataAbort>>,
     // The data loop listens to this so it can know when to exit.
-    pub data_abort_rx: Option<Receiver<()>>,
+    pub data_abort_rx: Option<Receiver<DataAbort>>,
     // This may not be needed here...
     pub control_msg_tx: Option<Sender<ControlChanMsg>>,
     // The socket address of the client on the control channel
@@ -87,24 +112,27 @@ where
     // The control connection over the proxy protocol
     pub(crate) proxy_control: Option<ProxyConnection>,
     // Points to the hashkey of the data connection
+    #[cfg_attr(not(feature = "proxy-protocol"), allow(dead_code))]
     pub(crate) proxy_active_datachan: Option<ProxyHashKey>,
     // Current working directory
     pub cwd: std::path::PathBuf,
     // After a RNFR command this will hold the source path used by the RNTO command.
     pub rename_from: Option<PathBuf>,
+    // After a SITE CPFR command this will hold the source path used by the SITE CPTO command.
+    pub copy_from: Option<PathBuf>,
     // This may need some work...
     pub state: SessionState,
     // Tells if FTPS/TLS security is available to the session or not. The variables cmd_tls and
-    // data_tls tells if the channels are actually encrypted or not.
+    // data_protection tell if the channels are actually encrypted or not.
     pub ftps_config: FtpsConfig,
     // True if the command channel is in secure mode at the moment. Changed by AUTH and CCC commands.
     pub cmd_tls: bool,
-    // True if the data channel is in secure mode at the moment. Changed by the PROT command.
-    pub data_tls: bool,
+    // The protection of the data channel, changed by the PBSZ and PROT commands.
+    pub data_protection: DataProtection,
     // True if metrics for prometheus are updated.
     pub collect_metrics: bool,
     // The starting byte for a STOR or RETR command. Set by the _Restart of Interrupted Transfer (REST)_
-    // command to support resume functionality.
+    // command to support resume functionality. See take_start_pos.
     pub start_pos: u64,
     // Tells if the data loop is running. The control channel need to know if the data channel is
     // busy so that it doesn't time out while the session is still in progress.
@@ -115,6 +143,63 @@ where
     pub failed_logins: Option<Arc<FailedLoginsCache>>,
     // An optional functor that can bind a socket
     pub binder: Option<Box<dyn crate::options::Binder>>,
+    // The character set used to talk to the client. Changed by the OPTS UTF8 command.
+    pub charset: Charset,
+    // Aborts data transfers that didn't move any bytes for this long.
+    pub data_stall_timeout: Option<Duration>,
+    // The data command sender of the transfer in progress. The data channel drops its receiver once
+    // the transfer finished, which closes it.
+    pub transfer: Option<Sender<DataChanCmd>>,
+    // The commands received during a transfer that may not run alongside it. The control loop
+    // replays them once the transfer finished.
+    pub held_commands: VecDeque<Event>,
+    // What to do with partially stored files when an upload is aborted.
+    pub partial_uploads: PartialUploads,
+    pub upload_conflicts: UploadConflicts,
+    // The checksum computed over uploaded data.
+    pub upload_checksum: UploadChecksum,
+    // Inspects uploads before the client is told they completed.
+    pub upload_hook: Option<Arc<dyn UploadHook>>,
+    // Gets the data of uploads while they are being received.
+    pub upload_scanner: Option<Arc<dyn UploadScanner>>,
+    pub transfer_log: Option<Arc<dyn TransferLogListener>>,
+    pub session_cache: Option<Arc<dyn SessionCache>>,
+    // Where to report the progress of transfers to and how often.
+    pub progress_events: Option<(Arc<dyn DataListener>, Duration)>,
+    // Whether the data events of transfers include the attributes of the file.
+    pub file_attributes: bool,
+    // Whether the client may switch to compressed transfers.
+    pub mode_z: ModeZ,
+    // How far LIST -R and NLST -R may go.
+    pub recursive_listing: RecursiveListing,
+    // The workarounds for the client, set once the client is recognised.
+    pub quirks: Quirks,
+    // Whether MDTM may be used to change the modification time of files.
+    pub mdtm_setter: bool,
+    // How usernames and addresses appear in the logs and notifications.
+    pub redaction: Redaction,
+    // Decides whether a user may log in from where the session comes from.
+    pub connection_policy: Option<Arc<CachedConnectionPolicy>>,
+    // Who may connect to the passive data port.
+    pub data_connection_source: DataConnectionSource,
+    // The TCP options of the data connections.
+    pub data_socket_options: SocketOptions,
+    // The local address of the data connections, if not the one of the control connection.
+    pub data_bind_address: Option<Ipv4Addr>,
+    // Translates the passive port for the PASV reply.
+    pub passive_port_mapper: Option<Arc<dyn PassivePortMapper>>,
+    // The runtime that data connections run on, if not the one of the control channel.
+    pub data_runtime: Option<Handle>,
+    // True if data transfers are compressed. Changed by the MODE command.
+    pub deflate: bool,
+    // True if files are transferred with line ending translation. Changed by the TYPE command.
+    pub ascii: bool,
+    // What the ServerHandle shows of the session.
+    pub activity: Arc<SessionActivity>,
+    // The translations of the replies.
+    pub message_catalog: Option<Arc<dyn MessageCatalog>>,
+    // The language chosen with the LANG command, None for English.
+    pub language: Option<String>,
 }
 
 impl<Storage, User> Session<Storage, User>
@@ -139,16 +224,46 @@ where
             proxy_active_datachan: None,
             cwd: "/".into(),
             rename_from: None,
+            copy_from: None,
             state: SessionState::New,
             ftps_config: FtpsConfig::Off,
             cmd_tls: false,
-            data_tls: false,
+            data_protection: DataProtection::NoBufferSize,
             collect_metrics: false,
             start_pos: 0,
             data_busy: false,
             cert_chain: None,
             failed_logins: None,
             binder: None,
+            charset: Charset::default(),
+            data_stall_timeout: None,
+            transfer: None,
+            held_commands: VecDeque::new(),
+            partial_uploads: PartialUploads::default(),
+            upload_conflicts: UploadConflicts::default(),
+            upload_checksum: UploadChecksum::default(),
+            upload_hook: None,
+            upload_scanner: None,
+            transfer_log: None,
+            session_cache: None,
+            progress_events: None,
+            file_attributes: false,
+            mode_z: ModeZ::default(),
+            recursive_listing: RecursiveListing::default(),
+            quirks: Quirks::default(),
+            mdtm_setter: true,
+            redaction: Redaction::None,
+            connection_policy: None,
+            data_connection_source: DataConnectionSource::default(),
+            data_socket_options: SocketOptions::default(),
+            data_bind_address: None,
+            passive_port_mapper: None,
+            data_runtime: None,
+            deflate: false,
+            ascii: false,
+            activity: Arc::new(SessionActivity::new(source)),
+            message_catalog: None,
+            language: None,
         }
     }
 
@@ -157,6 +272,116 @@ where
         self
     }
 
+    pub fn charset(mut self, charset: Charset) -> Self {
+        self.charset = charset;
+        self
+    }
+
+    pub fn data_stall_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.data_stall_timeout = timeout;
+        self
+    }
+
+    pub fn partial_uploads(mut self, partial_uploads: PartialUploads) -> Self {
+        self.partial_uploads = partial_uploads;
+        self
+    }
+
+    pub fn upload_conflicts(mut self, upload_conflicts: UploadConflicts) -> Self {
+        self.upload_conflicts = upload_conflicts;
+        self
+    }
+
+    pub fn upload_checksum(mut self, upload_checksum: UploadChecksum) -> Self {
+        self.upload_checksum = upload_checksum;
+        self
+    }
+
+    pub fn upload_hook(mut self, upload_hook: Option<Arc<dyn UploadHook>>) -> Self {
+        self.upload_hook = upload_hook;
+        self
+    }
+
+    pub fn upload_scanner(mut self, upload_scanner: Option<Arc<dyn UploadScanner>>) -> Self {
+        self.upload_scanner = upload_scanner;
+        self
+    }
+
+    pub fn transfer_log(mut self, transfer_log: Option<Arc<dyn TransferLogListener>>) -> Self {
+        self.transfer_log = transfer_log;
+        self
+    }
+
+    pub fn session_cache(mut self, session_cache: Option<Arc<dyn SessionCache>>) -> Self {
+        self.session_cache = session_cache;
+        self
+    }
+
+    pub fn progress_events(mut self, progress_events: Option<(Arc<dyn DataListener>, Duration)>) -> Self {
+        self.progress_events = progress_events;
+        self
+    }
+
+    pub fn file_attributes(mut self, file_attributes: bool) -> Self {
+        self.file_attributes = file_attributes;
+        self
+    }
+
+    pub fn mode_z(mut self, mode_z: ModeZ) -> Self {
+        self.mode_z = mode_z;
+        self
+    }
+
+    pub fn recursive_listing(mut self, recursive_listing: RecursiveListing) -> Self {
+        self.recursive_listing = recursive_listing;
+        self
+    }
+
+    pub fn mdtm_setter(mut self, enabled: bool) -> Self {
+        self.mdtm_setter = enabled;
+        self
+    }
+
+    pub fn redaction(mut self, redaction: Redaction) -> Self {
+        self.redaction = redaction;
+        self
+    }
+
+    pub fn connection_policy(mut self, policy: Option<Arc<CachedConnectionPolicy>>) -> Self {
+        self.connection_policy = policy;
+        self
+    }
+
+    pub fn data_connection_source(mut self, data_connection_source: DataConnectionSource) -> Self {
+        self.data_connection_source = data_connection_source;
+        self
+    }
+
+    pub fn data_socket_options(mut self, options: SocketOptions) -> Self {
+        self.data_socket_options = options;
+        self
+    }
+
+    pub fn data_bind_address(mut self, address: Option<Ipv4Addr>) -> Self {
+        self.data_bind_address = address;
+        self
+    }
+
+    pub fn passive_port_mapper(mut self, mapper: Option<Arc<dyn PassivePortMapper>>) -> Self {
+        self.passive_port_mapper = mapper;
+        self
+    }
+
+    pub fn data_runtime(mut self, runtime: Option<Handle>) -> Self {
+        self.data_runtime = runtime;
+        self
+    }
+
+    pub fn message_catalog(mut self, message_catalog: Option<Arc<dyn MessageCatalog>>) -> Self {
+        self.message_catalog = message_catalog;
+        self
+    }
+
     pub fn metrics(mut self, collect_metrics: bool) -> Self {
         if collect_metrics {
             metrics::inc_session();
@@ -180,10 +405,74 @@ where
         self
     }
 
+    pub fn activity(mut self, activity: Arc<SessionActivity>) -> Self {
+        self.activity = activity;
+        self
+    }
+
     pub fn failed_logins(mut self, failed_logins: Option<Arc<FailedLoginsCache>>) -> Self {
         self.failed_logins = failed_logins;
         self
     }
+
+    // Takes the offset set by REST for the RETR or STOR that is handed to the data channel. Only the
+    // transfer uses it up, so it survives the PASV, PORT, TYPE and other commands that clients send
+    // between REST and the transfer, in whatever order.
+    pub fn take_start_pos(&mut self) -> u64 {
+        std::mem::take(&mut self.start_pos)
+    }
+
+    // True from the moment a transfer command was handed to the data channel until the data
+    // channel finished it.
+    pub fn transfer_in_progress(&self) -> bool {
+        self.transfer.as_ref().is_some_and(|tx| !tx.is_closed())
+    }
+
+    // True if data is sent over TLS, after PROT P.
+    pub fn data_tls(&self) -> bool {
+        self.data_protection == DataProtection::Private
+    }
+
+    // AUTH TLS secured the control channel. The data channel has to be negotiated anew with PBSZ
+    // and PROT.
+    pub fn secure_control_channel(&mut self) {
+        self.cmd_tls = true;
+        self.set_data_protection(DataProtection::NoBufferSize);
+        self.activity.set_cmd_tls(true);
+    }
+
+    // CCC cleared the control channel. The data channel keeps its protection.
+    pub fn clear_control_channel(&mut self) {
+        self.cmd_tls = false;
+        self.activity.set_cmd_tls(false);
+    }
+
+    // PBSZ was received. Fails if the control channel was never secured, a protection level that
+    // PROT set before stays as it is.
+    pub fn set_buffer_size(&mut self) -> Result<(), DataProtection> {
+        match self.data_protection {
+            DataProtection::NoBufferSize if !self.cmd_tls => Err(self.data_protection),
+            DataProtection::NoBufferSize => {
+                self.set_data_protection(DataProtection::BufferSize);
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // PROT set the protection level. Fails if PBSZ didn't come first.
+    pub fn protect_data(&mut self, private: bool) -> Result<(), DataProtection> {
+        if self.data_protection == DataProtection::NoBufferSize {
+            return Err(self.data_protection);
+        }
+        self.set_data_protection(if private { DataProtection::Private } else { DataProtection::Clear });
+        Ok(())
+    }
+
+    fn set_data_protection(&mut self, protection: DataProtection) {
+        self.data_protection = protection;
+        self.activity.set_data_tls(self.data_tls());
+    }
 }
 
 impl<Storage, User> Drop for Session<Storage, User>
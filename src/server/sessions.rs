@@ -0,0 +1,580 @@
+//! Keeps track of the sessions of a server, so that they can be listed and terminated through the
+//! [`ServerHandle`](crate::ServerHandle).
+
+use super::session::TraceId;
+use dashmap::{DashMap, DashSet};
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    path::{Component, Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::SystemTime,
+};
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+
+/// Describes a session that is connected to the server, as returned by
+/// [`ServerHandle::sessions`](crate::ServerHandle::sessions).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionInfo {
+    /// Identifies the session. It is the trace id that the log statements of the session carry.
+    pub id: String,
+    /// The name the client logged in with, if it sent one yet.
+    pub username: Option<String>,
+    /// The address of the client.
+    pub source: SocketAddr,
+    /// When the client connected.
+    pub started: SystemTime,
+    /// The data transfer that is in progress, if any.
+    pub transfer: Option<TransferInfo>,
+    /// The number of bytes sent and received on the data connections of the session so far.
+    pub bytes: u64,
+}
+
+/// The transfer statistics of a user, as returned by
+/// [`ServerHandle::user_stats`](crate::ServerHandle::user_stats). They count the sessions in which
+/// the user logged in since the server started.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserStats {
+    /// The name of the user.
+    pub username: String,
+    /// The number of sessions of the user that are connected now.
+    pub active_sessions: usize,
+    /// The number of sessions of the user, including those that ended.
+    pub total_sessions: u64,
+    /// The number of bytes the user sent and received on data connections.
+    pub bytes: u64,
+}
+
+/// Describes a data transfer in progress, part of a [`SessionInfo`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferInfo {
+    /// The FTP command that started the transfer, like `RETR` or `LIST`.
+    pub command: String,
+    /// The path that is transferred or listed.
+    pub path: String,
+    /// The number of bytes transferred so far.
+    pub bytes: u64,
+    /// When the transfer started.
+    pub started: SystemTime,
+}
+
+impl TransferInfo {
+    /// The average transfer rate since the transfer started.
+    pub fn bytes_per_second(&self) -> u64 {
+        let millis = self.started.elapsed().unwrap_or_default().as_millis().max(1);
+        (self.bytes as u128 * 1000 / millis) as u64
+    }
+}
+
+/// Describes the session a storage back-end serves, as handed to it through
+/// [`StorageBackend::set_session_context`](crate::storage::StorageBackend::set_session_context).
+/// It follows the session as it goes, so the working directory and TLS state it returns are those
+/// of the moment it is asked.
+#[derive(Debug, Clone)]
+pub struct SessionContext {
+    id: String,
+    activity: Arc<SessionActivity>,
+    scratch_dir: Option<PathBuf>,
+}
+
+impl SessionContext {
+    pub(crate) fn new(trace_id: TraceId, activity: Arc<SessionActivity>, scratch_dir: Option<PathBuf>) -> Self {
+        SessionContext {
+            id: trace_id.to_string(),
+            activity,
+            scratch_dir,
+        }
+    }
+
+    /// Identifies the session. It is the trace id that the log statements of the session carry.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The address of the client.
+    pub fn source(&self) -> SocketAddr {
+        self.activity.source
+    }
+
+    /// The name the client logged in with, if it sent one yet.
+    pub fn username(&self) -> Option<String> {
+        self.activity.username.lock().unwrap().clone()
+    }
+
+    /// The current working directory of the client.
+    pub fn cwd(&self) -> PathBuf {
+        self.activity.cwd.lock().unwrap().clone()
+    }
+
+    /// Tells if the control channel is encrypted at the moment.
+    pub fn control_tls(&self) -> bool {
+        self.activity.cmd_tls.load(Ordering::Relaxed)
+    }
+
+    /// Tells if data transfers are encrypted at the moment, as set with the `PROT` command.
+    pub fn data_tls(&self) -> bool {
+        self.activity.data_tls.load(Ordering::Relaxed)
+    }
+
+    /// The scratch directory of the session, if the back-end asked for one with
+    /// [`StorageBackend::session_scratch`](crate::storage::StorageBackend::session_scratch). It is
+    /// removed when the session ends.
+    pub fn scratch_dir(&self) -> Option<&Path> {
+        self.scratch_dir.as_deref()
+    }
+
+    /// Returns the path of a file in the scratch directory of the session. Fails if there is no
+    /// scratch directory or if the name would lead outside of it, because it is absolute or goes up
+    /// with `..`. Subdirectories are not created.
+    pub fn scratch_file<P: AsRef<Path>>(&self, name: P) -> io::Result<PathBuf> {
+        let dir = self
+            .scratch_dir
+            .as_ref()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "the session has no scratch directory"))?;
+        let name = name.as_ref();
+        if name.as_os_str().is_empty() || !name.components().all(|component| matches!(component, Component::Normal(_))) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?} leads outside of the scratch directory", name),
+            ));
+        }
+        Ok(dir.join(name))
+    }
+}
+
+// The scratch directory of a session, removed with its content when dropped.
+#[derive(Debug)]
+pub(crate) struct ScratchDir {
+    path: PathBuf,
+}
+
+impl ScratchDir {
+    // Creates the directory for the session beneath the given one, accessible to the server
+    // process only.
+    pub fn create(base: &Path, trace_id: TraceId) -> io::Result<Self> {
+        let path = base.join(format!("unftp-session-{}", trace_id));
+        let mut builder = std::fs::DirBuilder::new();
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        std::fs::create_dir_all(base)?;
+        builder.create(&path)?;
+        Ok(ScratchDir { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let path = std::mem::take(&mut self.path);
+        let remove = move || {
+            let _ = std::fs::remove_dir_all(path);
+        };
+        // Removing many spilled files can take a while, so it doesn't hold up a runtime thread.
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => drop(runtime.spawn_blocking(remove)),
+            Err(_) => remove(),
+        }
+    }
+}
+
+// What the session shares with the registry and the storage back-end. The control and data
+// channels keep it up to date.
+#[derive(Debug)]
+pub(crate) struct SessionActivity {
+    source: SocketAddr,
+    started: SystemTime,
+    username: Mutex<Option<String>>,
+    cwd: Mutex<PathBuf>,
+    authenticated: AtomicBool,
+    cmd_tls: AtomicBool,
+    data_tls: AtomicBool,
+    transfer: Mutex<Option<TransferInfo>>,
+    transfer_bytes: AtomicU64,
+    bytes: AtomicU64,
+    event_sequence: AtomicU64,
+    kill: CancellationToken,
+}
+
+impl SessionActivity {
+    pub fn new(source: SocketAddr) -> Self {
+        SessionActivity {
+            source,
+            started: SystemTime::now(),
+            username: Mutex::new(None),
+            cwd: Mutex::new(PathBuf::from("/")),
+            authenticated: AtomicBool::new(false),
+            cmd_tls: AtomicBool::new(false),
+            data_tls: AtomicBool::new(false),
+            transfer: Mutex::new(None),
+            transfer_bytes: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            event_sequence: AtomicU64::new(0),
+            kill: CancellationToken::new(),
+        }
+    }
+
+    pub fn source(&self) -> SocketAddr {
+        self.source
+    }
+
+    pub fn set_username(&self, username: Option<String>) {
+        *self.username.lock().unwrap() = username;
+    }
+
+    pub fn set_cwd(&self, cwd: PathBuf) {
+        *self.cwd.lock().unwrap() = cwd;
+    }
+
+    pub fn set_authenticated(&self) {
+        self.authenticated.store(true, Ordering::Relaxed);
+    }
+
+    pub fn set_cmd_tls(&self, secure: bool) {
+        self.cmd_tls.store(secure, Ordering::Relaxed);
+    }
+
+    pub fn set_data_tls(&self, secure: bool) {
+        self.data_tls.store(secure, Ordering::Relaxed);
+    }
+
+    pub fn set_transfer(&self, transfer: Option<TransferInfo>) {
+        let mut current = self.transfer.lock().unwrap();
+        self.transfer_bytes.store(0, Ordering::Relaxed);
+        *current = transfer;
+    }
+
+    // Returns the transfer in progress with the bytes it moved so far.
+    pub fn transfer(&self) -> Option<TransferInfo> {
+        let mut transfer = self.transfer.lock().unwrap().clone()?;
+        transfer.bytes = self.transfer_bytes.load(Ordering::Relaxed);
+        Some(transfer)
+    }
+
+    pub fn add_bytes(&self, bytes: u64) {
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.transfer_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    // Numbers the events sent to the listeners of the session.
+    pub fn next_event_sequence(&self) -> u64 {
+        self.event_sequence.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    // Completes once the session was terminated through the ServerHandle.
+    pub async fn killed(&self) {
+        self.kill.cancelled().await
+    }
+
+    // The name of the user if the session logged in.
+    fn logged_in_user(&self) -> Option<String> {
+        match self.authenticated.load(Ordering::Relaxed) {
+            true => self.username.lock().unwrap().clone(),
+            false => None,
+        }
+    }
+
+    fn info(&self, id: &str) -> SessionInfo {
+        SessionInfo {
+            id: id.to_string(),
+            username: self.username.lock().unwrap().clone(),
+            source: self.source,
+            started: self.started,
+            transfer: self.transfer(),
+            bytes: self.bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// The sessions of a server, by id, and what the ServerHandle manages about them.
+#[derive(Debug, Default)]
+pub(crate) struct SessionRegistry {
+    sessions: DashMap<String, Arc<SessionActivity>>,
+    // The statistics of the sessions that ended, by user.
+    ended: DashMap<String, UserStats>,
+    banned: DashSet<IpAddr>,
+    // Set while the server drains, cancelled when it resumes.
+    drain: Mutex<Option<CancellationToken>>,
+    // Woken when a session ends.
+    session_ended: Notify,
+}
+
+impl SessionRegistry {
+    // Adds the session until the returned Registration is dropped.
+    pub fn register(self: &Arc<Self>, trace_id: TraceId, activity: Arc<SessionActivity>) -> Registration {
+        let id = trace_id.to_string();
+        self.sessions.insert(id.clone(), activity);
+        Registration { registry: self.clone(), id }
+    }
+
+    pub fn count(&self) -> usize {
+        self.sessions.len()
+    }
+
+    // Counts the sessions that didn't log in yet.
+    pub fn count_unauthenticated(&self) -> usize {
+        self.sessions
+            .iter()
+            .filter(|entry| !entry.value().authenticated.load(Ordering::Relaxed))
+            .count()
+    }
+
+    pub fn list(&self) -> Vec<SessionInfo> {
+        let mut sessions: Vec<SessionInfo> = self.sessions.iter().map(|entry| entry.value().info(entry.key())).collect();
+        sessions.sort_by_key(|session| session.started);
+        sessions
+    }
+
+    pub fn kill(&self, id: &str) -> bool {
+        match self.sessions.get(id) {
+            Some(activity) => {
+                activity.kill.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Refuses new sessions from the address and terminates those that are connected, returning
+    // their number.
+    pub fn ban(&self, ip: IpAddr) -> usize {
+        self.banned.insert(ip);
+        let connected: Vec<_> = self
+            .sessions
+            .iter()
+            .filter(|entry| entry.value().source.ip() == ip)
+            .map(|entry| entry.value().clone())
+            .collect();
+        for activity in &connected {
+            activity.kill.cancel();
+        }
+        connected.len()
+    }
+
+    pub fn unban(&self, ip: IpAddr) -> bool {
+        self.banned.remove(&ip).is_some()
+    }
+
+    pub fn is_banned(&self, ip: IpAddr) -> bool {
+        self.banned.contains(&ip)
+    }
+
+    pub fn banned(&self) -> Vec<IpAddr> {
+        let mut banned: Vec<IpAddr> = self.banned.iter().map(|ip| *ip).collect();
+        banned.sort();
+        banned
+    }
+
+    // Puts the server in drain mode, if it isn't yet. Returns the token that is cancelled when it
+    // resumes.
+    pub fn drain(&self) -> CancellationToken {
+        self.drain.lock().unwrap().get_or_insert_with(CancellationToken::new).clone()
+    }
+
+    pub fn resume(&self) {
+        if let Some(resumed) = self.drain.lock().unwrap().take() {
+            resumed.cancel();
+        }
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.drain.lock().unwrap().is_some()
+    }
+
+    // Terminates all sessions, returning their number.
+    pub fn kill_all(&self) -> usize {
+        self.sessions.iter().map(|entry| entry.value().kill.cancel()).count()
+    }
+
+    // Waits until no session is connected.
+    pub async fn all_ended(&self) {
+        loop {
+            let ended = self.session_ended.notified();
+            tokio::pin!(ended);
+            // Registered before checking, so that a session that ends in between isn't missed.
+            ended.as_mut().enable();
+            if self.sessions.is_empty() {
+                return;
+            }
+            ended.await;
+        }
+    }
+
+    // Adds up the sessions that ended and those that are connected, by user.
+    pub fn user_stats(&self) -> Vec<UserStats> {
+        let mut stats: std::collections::BTreeMap<String, UserStats> = self.ended.iter().map(|entry| (entry.key().clone(), entry.value().clone())).collect();
+        for entry in self.sessions.iter() {
+            let activity = entry.value();
+            let Some(username) = activity.logged_in_user() else {
+                continue;
+            };
+            let user = stats.entry(username.clone()).or_insert_with(|| UserStats::new(username));
+            user.active_sessions += 1;
+            user.total_sessions += 1;
+            user.bytes += activity.bytes.load(Ordering::Relaxed);
+        }
+        stats.into_values().collect()
+    }
+}
+
+impl UserStats {
+    fn new(username: String) -> Self {
+        UserStats {
+            username,
+            active_sessions: 0,
+            total_sessions: 0,
+            bytes: 0,
+        }
+    }
+}
+
+// Removes the session from the registry when dropped.
+pub(crate) struct Registration {
+    registry: Arc<SessionRegistry>,
+    id: String,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let Some((_, activity)) = self.registry.sessions.remove(&self.id) else {
+            return;
+        };
+        self.registry.session_ended.notify_waiters();
+        if let Some(username) = activity.logged_in_user() {
+            let mut user = self.registry.ended.entry(username.clone()).or_insert_with(|| UserStats::new(username));
+            user.total_sessions += 1;
+            user.bytes += activity.bytes.load(Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn registers_and_kills_sessions() {
+        let registry = Arc::new(SessionRegistry::default());
+        let activity = Arc::new(SessionActivity::new("127.0.0.1:4321".parse().unwrap()));
+        let trace_id = TraceId::new();
+        let registration = registry.register(trace_id, activity.clone());
+
+        activity.set_username(Some("alice".to_string()));
+        activity.set_cwd(PathBuf::from("/uploads"));
+        activity.set_data_tls(true);
+        activity.add_bytes(42);
+        assert_eq!(registry.count_unauthenticated(), 1);
+        activity.set_authenticated();
+        assert_eq!(registry.count_unauthenticated(), 0);
+        let sessions = registry.list();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].id, trace_id.to_string());
+        assert_eq!(sessions[0].username.as_deref(), Some("alice"));
+        assert_eq!(sessions[0].bytes, 42);
+
+        let context = SessionContext::new(trace_id, activity.clone(), None);
+        assert_eq!(context.id(), trace_id.to_string());
+        assert_eq!(context.username().as_deref(), Some("alice"));
+        assert_eq!(context.cwd(), PathBuf::from("/uploads"));
+        assert!(!context.control_tls());
+        assert!(context.data_tls());
+
+        assert!(!registry.kill("unknown"));
+        assert!(registry.kill(&trace_id.to_string()));
+        activity.killed().await;
+
+        drop(registration);
+        assert_eq!(registry.list(), vec![]);
+    }
+
+    #[test]
+    fn creates_and_removes_scratch_directories() {
+        let base = std::env::temp_dir().join(format!("libunftp-scratch-{}", std::process::id()));
+        let trace_id = TraceId::new();
+        let scratch = ScratchDir::create(&base, trace_id).unwrap();
+        let context = SessionContext::new(
+            trace_id,
+            Arc::new(SessionActivity::new("127.0.0.1:1234".parse().unwrap())),
+            Some(scratch.path().to_path_buf()),
+        );
+        let spill = context.scratch_file("spill.bin").unwrap();
+        assert_eq!(spill.parent(), Some(scratch.path()));
+        std::fs::write(&spill, b"spilled").unwrap();
+        assert!(context.scratch_file("../escape").is_err());
+        assert!(context.scratch_file("/etc/passwd").is_err());
+
+        let path = scratch.path().to_path_buf();
+        drop(scratch);
+        assert!(!path.exists());
+        std::fs::remove_dir(base).unwrap();
+    }
+
+    #[tokio::test]
+    async fn waits_for_the_sessions_to_end() {
+        let registry = Arc::new(SessionRegistry::default());
+        let activity = Arc::new(SessionActivity::new("127.0.0.1:1234".parse().unwrap()));
+        let registration = registry.register(TraceId::new(), activity.clone());
+        let waiting = registry.clone();
+        let ended = tokio::spawn(async move { waiting.all_ended().await });
+        assert_eq!(registry.kill_all(), 1);
+        activity.killed().await;
+        assert!(!ended.is_finished());
+        drop(registration);
+        ended.await.unwrap();
+    }
+
+    #[test]
+    fn resuming_cancels_the_drain() {
+        let registry = SessionRegistry::default();
+        let resumed = registry.drain();
+        assert!(registry.is_draining());
+        assert!(!registry.drain().is_cancelled());
+        registry.resume();
+        assert!(!registry.is_draining());
+        assert!(resumed.is_cancelled());
+        assert!(!registry.drain().is_cancelled());
+    }
+
+    #[test]
+    fn bans_and_counts_per_user() {
+        let registry = Arc::new(SessionRegistry::default());
+        let alice = || {
+            let activity = Arc::new(SessionActivity::new("192.0.2.7:4321".parse().unwrap()));
+            activity.set_username(Some("alice".to_string()));
+            activity.set_authenticated();
+            activity
+        };
+        let first = alice();
+        first.add_bytes(10);
+        drop(registry.register(TraceId::new(), first));
+        let second = alice();
+        second.add_bytes(5);
+        let _registration = registry.register(TraceId::new(), second.clone());
+        // Sessions that didn't log in aren't counted.
+        let _anonymous = registry.register(TraceId::new(), Arc::new(SessionActivity::new("192.0.2.8:1234".parse().unwrap())));
+        assert_eq!(
+            registry.user_stats(),
+            vec![UserStats {
+                username: "alice".to_string(),
+                active_sessions: 1,
+                total_sessions: 2,
+                bytes: 15,
+            }]
+        );
+
+        let ip = "192.0.2.7".parse().unwrap();
+        assert_eq!(registry.ban(ip), 1);
+        assert!(second.kill.is_cancelled());
+        assert!(registry.is_banned(ip));
+        assert_eq!(registry.banned(), vec![ip]);
+        assert!(registry.unban(ip));
+        assert!(!registry.is_banned(ip));
+    }
+}
@@ -1,17 +1,25 @@
+#[cfg(feature = "ftps")]
 use crate::options::{FtpsClientAuth, TlsFlags};
+#[cfg(feature = "ftps")]
 use rustls::{
-    crypto::{aws_lc_rs, aws_lc_rs::Ticketer},
+    crypto::{aws_lc_rs, aws_lc_rs::Ticketer, CryptoProvider},
     pki_types::{CertificateDer, PrivateKeyDer},
-    server::{ClientCertVerifierBuilder, NoServerSessionStorage, StoresServerSessions, WebPkiClientVerifier},
+    server::{ClientCertVerifierBuilder, ClientHello, NoServerSessionStorage, ResolvesServerCert, StoresServerSessions, WebPkiClientVerifier},
+    sign::CertifiedKey,
     version::{TLS12, TLS13},
-    NoKeyLog, RootCertStore, ServerConfig, SupportedProtocolVersion,
+    KeyLog, NoKeyLog, RootCertStore, ServerConfig, SupportedProtocolVersion,
 };
 use std::{
     fmt::{self, Display, Formatter},
-    fs::File,
-    io::{self, BufReader},
-    path::{Path, PathBuf},
-    sync::Arc,
+    io,
+    path::PathBuf,
+};
+#[cfg(feature = "ftps")]
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufReader, Write},
+    path::Path,
+    sync::{Arc, Mutex, RwLock},
     time::Duration,
 };
 use thiserror::Error;
@@ -20,8 +28,16 @@ use thiserror::Error;
 #[derive(Clone)]
 pub enum FtpsConfig {
     Off,
-    Building { certs_file: PathBuf, key_file: PathBuf },
-    On { tls_config: Arc<ServerConfig> },
+    #[cfg_attr(not(feature = "ftps"), allow(dead_code))]
+    Building {
+        certs_file: PathBuf,
+        key_file: PathBuf,
+    },
+    #[cfg(feature = "ftps")]
+    On {
+        tls_config: Arc<ServerConfig>,
+        certificates: Arc<CertificateResolver>,
+    },
 }
 
 impl fmt::Debug for FtpsConfig {
@@ -29,6 +45,7 @@ impl fmt::Debug for FtpsConfig {
         match self {
             FtpsConfig::Off => write!(f, "Off"),
             FtpsConfig::Building { .. } => write!(f, "Building"),
+            #[cfg(feature = "ftps")]
             FtpsConfig::On { .. } => write!(f, "On"),
         }
     }
@@ -55,26 +72,48 @@ pub enum ConfigError {
     #[error("error reading key/cert input")]
     Load(#[from] io::Error),
 
+    #[cfg(feature = "ftps")]
     #[error("error building root certs")]
     RootCerts(rustls::Error),
 
+    #[cfg(feature = "ftps")]
     #[error("error initialising Rustls")]
     RustlsInit(#[from] rustls::Error),
 
+    #[cfg(feature = "ftps")]
     #[error("error initialising the client cert verifier")]
     ClientVerifier(#[from] rustls::server::VerifierBuilderError),
+
+    #[error("FTPS needs to be enabled to require AUTH TLS as the first command")]
+    TlsFirstWithoutFtps,
+
+    #[error("unknown or unsupported cipher suite {0}")]
+    UnknownCipherSuite(String),
+
+    #[cfg(not(feature = "ftps"))]
+    #[error("FTPS support is not compiled in, enable the ftps feature")]
+    FtpsNotCompiled,
 }
 
+// The settings of the TLS configuration that go beyond the TlsFlags.
+#[derive(Debug, Clone, Default)]
+pub struct TlsSettings {
+    // The names of the cipher suites that may be negotiated, None for the defaults of rustls.
+    pub cipher_suites: Option<Vec<String>>,
+    pub alpn_protocols: Vec<String>,
+    // Where to write the session keys to, for debugging with e.g. Wireshark.
+    pub key_log_file: Option<PathBuf>,
+}
+
+#[cfg(feature = "ftps")]
 pub fn new_config<P: AsRef<Path>>(
     certs_file: P,
     key_file: P,
     flags: TlsFlags,
     client_auth: FtpsClientAuth,
     trust_store: P,
-) -> Result<Arc<ServerConfig>, ConfigError> {
-    let certs: Vec<CertificateDer> = load_certs(certs_file)?;
-    let privkey: PrivateKeyDer = load_private_key(key_file)?;
-
+    settings: &TlsSettings,
+) -> Result<(Arc<ServerConfig>, Arc<CertificateResolver>), ConfigError> {
     let client_auther = match client_auth {
         FtpsClientAuth::Off => Ok(WebPkiClientVerifier::no_client_auth()),
         FtpsClientAuth::Request => {
@@ -96,13 +135,27 @@ pub fn new_config<P: AsRef<Path>>(
         versions.push(&TLS13)
     }
 
-    let provider = Arc::new(aws_lc_rs::default_provider());
+    let mut provider = aws_lc_rs::default_provider();
+    if let Some(names) = &settings.cipher_suites {
+        provider.cipher_suites = names
+            .iter()
+            .map(|name| {
+                provider
+                    .cipher_suites
+                    .iter()
+                    .find(|suite| suite.suite().as_str().is_some_and(|suite| suite.eq_ignore_ascii_case(name)))
+                    .copied()
+                    .ok_or_else(|| ConfigError::UnknownCipherSuite(name.clone()))
+            })
+            .collect::<Result<_, _>>()?;
+    }
+    let provider = Arc::new(provider);
+    let certificates = Arc::new(CertificateResolver::new(certs_file.as_ref(), key_file.as_ref(), provider.clone())?);
     let mut config = ServerConfig::builder_with_provider(provider)
         .with_protocol_versions(&versions)
         .map_err(ConfigError::RustlsInit)?
         .with_client_cert_verifier(client_auther)
-        .with_single_cert(certs, privkey)
-        .map_err(ConfigError::RustlsInit)?; // No SNI, single certificate
+        .with_cert_resolver(certificates.clone()); // No SNI, single certificate
 
     // Support session resumption with server side state (Session IDs)
     config.session_storage = if flags.contains(TlsFlags::RESUMPTION_SESS_ID) {
@@ -114,12 +167,89 @@ pub fn new_config<P: AsRef<Path>>(
     if flags.contains(TlsFlags::RESUMPTION_TICKETS) {
         config.ticketer = Ticketer::new().map_err(ConfigError::RustlsInit)?;
     };
-    // Don't allow dumping session keys
-    config.key_log = Arc::new(NoKeyLog {});
+    config.alpn_protocols = settings.alpn_protocols.iter().map(|protocol| protocol.as_bytes().to_vec()).collect();
+    // Don't allow dumping session keys unless asked for
+    config.key_log = match &settings.key_log_file {
+        Some(path) => Arc::new(KeyLogFile::open(path)?),
+        None => Arc::new(NoKeyLog {}),
+    };
+
+    Ok((Arc::new(config), certificates))
+}
+
+// Hands out the certificate of the server. It can be read again from the same files, so that a
+// renewed certificate is used without restarting the server.
+#[cfg(feature = "ftps")]
+#[derive(Debug)]
+pub struct CertificateResolver {
+    certs_file: PathBuf,
+    key_file: PathBuf,
+    provider: Arc<CryptoProvider>,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+#[cfg(feature = "ftps")]
+impl CertificateResolver {
+    fn new(certs_file: &Path, key_file: &Path, provider: Arc<CryptoProvider>) -> Result<Self, ConfigError> {
+        let current = load_certified_key(certs_file, key_file, &provider)?;
+        Ok(CertificateResolver {
+            certs_file: certs_file.to_path_buf(),
+            key_file: key_file.to_path_buf(),
+            provider,
+            current: RwLock::new(current),
+        })
+    }
+
+    // Reads the certificate and key again. New TLS handshakes use them, established connections
+    // aren't affected. The old certificate stays in use if the files can't be read.
+    pub fn reload(&self) -> Result<(), ConfigError> {
+        let reloaded = load_certified_key(&self.certs_file, &self.key_file, &self.provider)?;
+        *self.current.write().unwrap() = reloaded;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "ftps")]
+impl ResolvesServerCert for CertificateResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+#[cfg(feature = "ftps")]
+fn load_certified_key(certs_file: &Path, key_file: &Path, provider: &CryptoProvider) -> Result<Arc<CertifiedKey>, ConfigError> {
+    let certs: Vec<CertificateDer> = load_certs(certs_file)?;
+    let privkey: PrivateKeyDer = load_private_key(key_file)?;
+    let key = provider.key_provider.load_private_key(privkey).map_err(ConfigError::RustlsInit)?;
+    let certified = CertifiedKey::new(certs, key);
+    certified.keys_match().map_err(ConfigError::RustlsInit)?;
+    Ok(Arc::new(certified))
+}
+
+// Writes the session keys in the NSS key log format, like rustls::KeyLogFile does for the file named by
+// SSLKEYLOGFILE.
+#[cfg(feature = "ftps")]
+#[derive(Debug)]
+struct KeyLogFile(Mutex<File>);
 
-    Ok(Arc::new(config))
+#[cfg(feature = "ftps")]
+impl KeyLogFile {
+    fn open(path: &Path) -> Result<Self, ConfigError> {
+        Ok(KeyLogFile(Mutex::new(OpenOptions::new().append(true).create(true).open(path)?)))
+    }
+}
+
+#[cfg(feature = "ftps")]
+impl KeyLog for KeyLogFile {
+    fn log(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+        let hex = |bytes: &[u8]| bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+        let line = format!("{} {} {}\n", label, hex(client_random), hex(secret));
+        // A failure to write the debugging aid isn't worth failing the handshake for.
+        let _ = self.0.lock().unwrap().write_all(line.as_bytes());
+    }
 }
 
+#[cfg(feature = "ftps")]
 fn root_cert_store<P: AsRef<Path>>(trust_pem: P) -> Result<RootCertStore, ConfigError> {
     let mut store = RootCertStore::empty();
     let certs = load_certs(trust_pem)?;
@@ -129,6 +259,7 @@ fn root_cert_store<P: AsRef<Path>>(trust_pem: P) -> Result<RootCertStore, Config
     Ok(store)
 }
 
+#[cfg(feature = "ftps")]
 fn load_certs<P: AsRef<Path>>(filename: P) -> Result<Vec<CertificateDer<'static>>, ConfigError> {
     let certfile: File = File::open(filename)?;
     let mut reader: BufReader<File> = BufReader::new(certfile);
@@ -142,6 +273,7 @@ fn load_certs<P: AsRef<Path>>(filename: P) -> Result<Vec<CertificateDer<'static>
     Ok(res)
 }
 
+#[cfg(feature = "ftps")]
 fn load_private_key<P: AsRef<Path>>(filename: P) -> Result<PrivateKeyDer<'static>, ConfigError> {
     use rustls::pki_types::PrivateKeyDer;
     use rustls_pemfile::{read_one, Item};
@@ -163,12 +295,14 @@ fn load_private_key<P: AsRef<Path>>(filename: P) -> Result<PrivateKeyDer<'static
     Err(ConfigError::NoPrivateKey)
 }
 
+#[cfg(feature = "ftps")]
 /// Stores the session IDs server side.
 #[derive(Debug)]
 struct TlsSessionCache {
     cache: moka::sync::Cache<Vec<u8>, Vec<u8>>,
 }
 
+#[cfg(feature = "ftps")]
 impl TlsSessionCache {
     /// Make a new TlsSessionCache.  `size` is the maximum
     /// number of stored sessions.
@@ -180,6 +314,7 @@ impl TlsSessionCache {
     }
 }
 
+#[cfg(feature = "ftps")]
 impl StoresServerSessions for TlsSessionCache {
     fn put(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
         self.cache.insert(key, value);
@@ -0,0 +1,283 @@
+use super::{Error, Fileinfo, Metadata, Result, StorageBackend, FEATURE_ATOMIC_UPLOADS};
+use crate::{auth::UserDetail, SessionContext};
+use async_trait::async_trait;
+use std::{
+    fmt::Debug,
+    io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// Returns the temporary path under which an upload to the given path is stored until it
+/// completed: `.in.<name>.` in the same directory.
+pub fn temp_upload_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let path = path.as_ref();
+    let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    path.with_file_name(format!(".in.{}.", name))
+}
+
+/// A [`StorageBackend`] that wraps another one to make uploads atomic: [`put`](StorageBackend::put)
+/// writes to a [temporary name](temp_upload_path) and renames the file to its final name only once
+/// the upload completed successfully. This prevents consumers from picking up half-written files.
+///
+/// Uploads that resume at an offset (after `REST`) are written to the final name directly, and
+/// back-ends that already advertise [`FEATURE_ATOMIC_UPLOADS`] are left to do it themselves.
+///
+/// This wrapper can be used for individual back-ends. To make uploads atomic for any back-end use
+/// [ServerBuilder::atomic_uploads](crate::ServerBuilder::atomic_uploads) instead.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::ServerBuilder;
+/// use libunftp::storage::AtomicUploads;
+/// use unftp_sbe_fs::Filesystem;
+///
+/// let server = ServerBuilder::new(Box::new(move || AtomicUploads::new(Filesystem::new("/tmp"))));
+/// ```
+#[derive(Debug)]
+pub struct AtomicUploads<Storage> {
+    inner: Storage,
+    enabled: bool,
+}
+
+impl<Storage> AtomicUploads<Storage> {
+    /// Wraps the given storage back-end.
+    pub fn new(inner: Storage) -> Self {
+        AtomicUploads { inner, enabled: true }
+    }
+
+    // Used for the server-wide option, where the wrapper is always present.
+    pub(crate) fn with_enabled(inner: Storage, enabled: bool) -> Self {
+        AtomicUploads { inner, enabled }
+    }
+}
+
+#[async_trait]
+impl<Storage, User> StorageBackend<User> for AtomicUploads<Storage>
+where
+    Storage: StorageBackend<User>,
+    User: UserDetail,
+{
+    type Metadata = Storage::Metadata;
+
+    fn enter(&mut self, user_detail: &User) -> io::Result<()> {
+        self.inner.enter(user_detail)
+    }
+
+    fn set_session_context(&mut self, context: SessionContext) {
+        self.inner.set_session_context(context)
+    }
+
+    fn session_scratch(&self) -> Option<PathBuf> {
+        self.inner.session_scratch()
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn supported_features(&self) -> u32 {
+        self.inner.supported_features()
+    }
+
+    async fn metadata<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<Self::Metadata> {
+        self.inner.metadata(user, path).await
+    }
+
+    async fn metadata_many<P>(&self, user: &User, paths: Vec<P>) -> Vec<Result<Self::Metadata>>
+    where
+        P: AsRef<Path> + Send + Debug,
+    {
+        self.inner.metadata_many(user, paths).await
+    }
+
+    async fn md5<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<String>
+    where
+        P: AsRef<Path> + Send + Debug,
+    {
+        self.inner.md5(user, path).await
+    }
+
+    async fn list<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<Vec<Fileinfo<PathBuf, Self::Metadata>>>
+    where
+        <Self as StorageBackend<User>>::Metadata: Metadata,
+    {
+        self.inner.list(user, path).await
+    }
+
+    async fn list_fmt<P>(&self, user: &User, path: P) -> std::result::Result<io::Cursor<Vec<u8>>, Error>
+    where
+        P: AsRef<Path> + Send + Debug,
+        Self::Metadata: Metadata + 'static,
+    {
+        self.inner.list_fmt(user, path).await
+    }
+
+    async fn list_vec<P>(&self, user: &User, path: P) -> std::result::Result<Vec<String>, Error>
+    where
+        P: AsRef<Path> + Send + Debug,
+        Self::Metadata: Metadata + 'static,
+    {
+        self.inner.list_vec(user, path).await
+    }
+
+    async fn nlst<P>(&self, user: &User, path: P) -> std::result::Result<io::Cursor<Vec<u8>>, io::Error>
+    where
+        P: AsRef<Path> + Send + Debug,
+        Self::Metadata: Metadata + 'static,
+    {
+        self.inner.nlst(user, path).await
+    }
+
+    async fn get_into<'a, P, W: ?Sized>(&self, user: &User, path: P, start_pos: u64, output: &'a mut W) -> Result<u64>
+    where
+        W: tokio::io::AsyncWrite + Unpin + Sync + Send,
+        P: AsRef<Path> + Send + Debug,
+    {
+        self.inner.get_into(user, path, start_pos, output).await
+    }
+
+    async fn get<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P, start_pos: u64) -> Result<Box<dyn tokio::io::AsyncRead + Send + Sync + Unpin>> {
+        self.inner.get(user, path, start_pos).await
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    async fn get_file<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<Option<std::fs::File>> {
+        self.inner.get_file(user, path).await
+    }
+
+    async fn put<P: AsRef<Path> + Send + Debug, R: tokio::io::AsyncRead + Send + Sync + Unpin + 'static>(
+        &self,
+        user: &User,
+        input: R,
+        path: P,
+        start_pos: u64,
+    ) -> Result<u64> {
+        if !self.enabled || start_pos > 0 || self.inner.supported_features() & FEATURE_ATOMIC_UPLOADS != 0 {
+            return self.inner.put(user, input, path, start_pos).await;
+        }
+        let path = path.as_ref().to_path_buf();
+        let temp_path = temp_upload_path(&path);
+        let result = match self.inner.put(user, input, temp_path.clone(), 0).await {
+            Ok(bytes) => self.inner.rename(user, temp_path.clone(), path).await.map(|()| bytes),
+            Err(err) => Err(err),
+        };
+        if result.is_err() {
+            let _ = self.inner.del(user, temp_path).await;
+        }
+        result
+    }
+
+    async fn put_unique<P: AsRef<Path> + Send + Debug, R: tokio::io::AsyncRead + Send + Sync + Unpin + 'static>(
+        &self,
+        user: &User,
+        input: R,
+        dir: P,
+    ) -> Result<(String, u64)> {
+        if !self.enabled || self.inner.supported_features() & FEATURE_ATOMIC_UPLOADS != 0 {
+            return self.inner.put_unique(user, input, dir).await;
+        }
+        // The inner back-end creates an empty file exclusively to claim the name, which the
+        // temporary file then replaces.
+        let (name, _) = self.inner.put_unique(user, tokio::io::empty(), dir.as_ref()).await?;
+        let path = dir.as_ref().join(&name);
+        match self.put(user, input, path.clone(), 0).await {
+            Ok(bytes) => Ok((name, bytes)),
+            Err(err) => {
+                let _ = self.inner.del(user, path).await;
+                Err(err)
+            }
+        }
+    }
+
+    async fn abort_put<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        if !self.enabled || self.inner.supported_features() & FEATURE_ATOMIC_UPLOADS != 0 {
+            return self.inner.abort_put(user, path).await;
+        }
+        // The upload went to the temporary name, unless it resumed an earlier one.
+        let temp_path = temp_upload_path(path.as_ref());
+        if self.inner.metadata(user, &temp_path).await.is_err() {
+            return self.inner.abort_put(user, path).await;
+        }
+        self.inner.abort_put(user, &temp_path).await?;
+        self.inner.del(user, temp_path).await
+    }
+
+    async fn del<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.inner.del(user, path).await
+    }
+
+    async fn mkd<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.inner.mkd(user, path).await
+    }
+
+    async fn rename<P: AsRef<Path> + Send + Debug>(&self, user: &User, from: P, to: P) -> Result<()> {
+        self.inner.rename(user, from, to).await
+    }
+
+    async fn copy<P: AsRef<Path> + Send + Debug>(&self, user: &User, from: P, to: P) -> Result<()> {
+        if !self.enabled || self.inner.supported_features() & FEATURE_ATOMIC_UPLOADS != 0 {
+            return self.inner.copy(user, from, to).await;
+        }
+        // Copies go through a temporary file as well, to not expose a partial copy.
+        let to = to.as_ref().to_path_buf();
+        let temp_path = temp_upload_path(&to);
+        let result = match self.inner.copy(user, from.as_ref().to_path_buf(), temp_path.clone()).await {
+            Ok(()) => self.inner.rename(user, temp_path.clone(), to).await,
+            Err(err) => Err(err),
+        };
+        if result.is_err() {
+            let _ = self.inner.del(user, temp_path).await;
+        }
+        result
+    }
+
+    async fn chown<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P, uid: Option<u32>, gid: Option<u32>) -> Result<()> {
+        self.inner.chown(user, path, uid, gid).await
+    }
+
+    async fn symlink<P: AsRef<Path> + Send + Debug>(&self, user: &User, target: P, link: P) -> Result<()> {
+        self.inner.symlink(user, target, link).await
+    }
+
+    async fn set_modified<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P, modified: SystemTime) -> Result<()> {
+        self.inner.set_modified(user, path, modified).await
+    }
+
+    async fn check_health(&self) -> Result<()> {
+        self.inner.check_health().await
+    }
+
+    async fn rmd<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.inner.rmd(user, path).await
+    }
+
+    async fn rmd_recursive<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.inner.rmd_recursive(user, path).await
+    }
+
+    async fn cwd<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.inner.cwd(user, path).await
+    }
+
+    async fn change_dir<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<PathBuf> {
+        self.inner.change_dir(user, path).await
+    }
+
+    fn current_dir(&self, user: &User) -> Option<PathBuf> {
+        self.inner.current_dir(user)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn temp_path_is_hidden_sibling() {
+        assert_eq!(temp_upload_path("/dir/report.csv"), PathBuf::from("/dir/.in.report.csv."));
+        assert_eq!(temp_upload_path("report.csv"), PathBuf::from(".in.report.csv."));
+    }
+}
@@ -0,0 +1,700 @@
+//! A [`StorageBackend`] that fronts a slow back-end, like one that stores files in the cloud, with a
+//! cache on local disk.
+
+use super::{Error, Fileinfo, Metadata, Result, StorageBackend, FEATURE_ATOMIC_UPLOADS};
+use crate::{auth::UserDetail, server::encoding, SessionContext};
+use async_trait::async_trait;
+use bytes::{Buf, Bytes, BytesMut};
+use md5::{Digest, Md5};
+use slog::Drain;
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex, PoisonError},
+    task::{ready, Context, Poll},
+    time::SystemTime,
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, ReadBuf},
+    sync::mpsc,
+};
+
+// The subdirectory of the directory given to the cache that holds its files.
+const CACHE_SUBDIR: &str = "libunftp-cache";
+
+// The extensions of the files the cache manages in its directory. Only these are cleaned up.
+const CACHE_EXTENSION: &str = "cache";
+const PARTIAL_EXTENSION: &str = "part";
+
+// The size of the chunks in which a file is fetched into the cache, and how many of them may be
+// fetched ahead of the download that is served from them.
+const FILL_CHUNK_SIZE: usize = 64 * 1024;
+const FILL_CHUNKS_AHEAD: usize = 16;
+
+/// A cache on local disk for downloads and, optionally, uploads. It is shared by the [`Cached`]
+/// back-ends of all sessions, so create it once and pass clones of it to them.
+///
+/// The cache keeps its files in a `libunftp-cache` subdirectory of the directory it is given and
+/// keeps track of them in memory. **The subdirectory belongs to the cache:** the cache files that
+/// it finds there when it is created are left over from an earlier run and are removed, so don't
+/// give two caches the same directory.
+#[derive(Debug, Clone)]
+pub struct DiskCache {
+    state: Arc<CacheState>,
+}
+
+#[derive(Debug)]
+struct CacheState {
+    dir: PathBuf,
+    max_size: u64,
+    index: Mutex<Index>,
+}
+
+#[derive(Debug, Default)]
+struct Index {
+    entries: HashMap<String, CacheEntry>,
+    size: u64,
+    // Increases with every use of an entry, to tell which entry was used least recently.
+    clock: u64,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    file: PathBuf,
+    len: u64,
+    modified: SystemTime,
+    last_used: u64,
+}
+
+impl DiskCache {
+    /// Creates a cache that keeps at most `max_size` bytes in the `libunftp-cache` subdirectory of
+    /// the given directory, which are created if needed. When the cache is full the least recently
+    /// used files are evicted. Files larger than the cache are never cached.
+    pub fn new<P: Into<PathBuf>>(dir: P, max_size: u64) -> io::Result<Self> {
+        let dir = dir.into().join(CACHE_SUBDIR);
+        std::fs::create_dir_all(&dir)?;
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == CACHE_EXTENSION || ext == PARTIAL_EXTENSION) {
+                std::fs::remove_file(path)?;
+            }
+        }
+        Ok(DiskCache {
+            state: Arc::new(CacheState {
+                dir,
+                max_size,
+                index: Mutex::new(Index::default()),
+            }),
+        })
+    }
+
+    // A panic while holding the lock can't leave the index in a state that does harm, so
+    // poisoning is ignored.
+    fn index(&self) -> std::sync::MutexGuard<'_, Index> {
+        self.state.index.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // A new file in the cache directory to write to, before it is added to the cache.
+    fn partial_path(&self) -> PathBuf {
+        self.state.dir.join(format!("{}.{}", super::unique_file_name(), PARTIAL_EXTENSION))
+    }
+
+    // Returns the cached file for the key if it is still the same version as the one on the
+    // back-end, judging by its size and modification time. Outdated files are removed.
+    fn lookup(&self, key: &str, len: u64, modified: SystemTime) -> Option<PathBuf> {
+        let mut index = self.index();
+        index.clock += 1;
+        let clock = index.clock;
+        let entry = index.entries.get_mut(key)?;
+        if entry.len == len && entry.modified == modified {
+            entry.last_used = clock;
+            return Some(entry.file.clone());
+        }
+        drop(index);
+        self.remove(key);
+        None
+    }
+
+    // Moves the partial file into the cache under the given key and evicts the least recently
+    // used files if the cache grew too big.
+    fn insert(&self, key: &str, partial: &Path, len: u64, modified: SystemTime) -> io::Result<PathBuf> {
+        let file = self.state.dir.join(format!("{:x}.{}", Md5::digest(key.as_bytes()), CACHE_EXTENSION));
+        let mut index = self.index();
+        std::fs::rename(partial, &file)?;
+        index.clock += 1;
+        let entry = CacheEntry {
+            file: file.clone(),
+            len,
+            modified,
+            last_used: index.clock,
+        };
+        index.size += len;
+        if let Some(replaced) = index.entries.insert(key.to_string(), entry) {
+            index.size -= replaced.len;
+        }
+        while index.size > self.state.max_size {
+            let Some(oldest) = index.entries.iter().min_by_key(|(_, entry)| entry.last_used).map(|(key, _)| key.clone()) else {
+                break;
+            };
+            if let Some(evicted) = index.entries.remove(&oldest) {
+                index.size -= evicted.len;
+                // Sessions that are reading the file keep their handle to it.
+                let _ = std::fs::remove_file(evicted.file);
+            }
+        }
+        Ok(file)
+    }
+
+    fn remove(&self, key: &str) {
+        let mut index = self.index();
+        if let Some(removed) = index.entries.remove(key) {
+            index.size -= removed.len;
+            let _ = std::fs::remove_file(removed.file);
+        }
+    }
+
+    // Fetches a file into the cache in the background and returns the download of it, which is
+    // served from the chunks of the file as they arrive and skips the first `start_pos` bytes. The
+    // fetch carries on when the download is dropped, so that the file still ends up in the cache.
+    async fn fill<R>(&self, key: String, modified: SystemTime, mut reader: R, start_pos: u64) -> io::Result<Filling>
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+    {
+        let partial = self.partial_path();
+        let file = tokio::fs::File::create(&partial).await?;
+        let (tx, rx) = mpsc::channel(FILL_CHUNKS_AHEAD);
+        let cache = self.clone();
+        tokio::spawn(async move {
+            let mut file = Some(file);
+            let mut download = Some(tx);
+            let mut len = 0u64;
+            loop {
+                let mut chunk = BytesMut::with_capacity(FILL_CHUNK_SIZE);
+                match reader.read_buf(&mut chunk).await {
+                    Ok(0) => break,
+                    Ok(read) => len += read as u64,
+                    Err(err) => {
+                        if let Some(tx) = download {
+                            let _ = tx.send(Err(err)).await;
+                        }
+                        let _ = tokio::fs::remove_file(&partial).await;
+                        return;
+                    }
+                }
+                let chunk = chunk.freeze();
+                // Failing to write the cache file only means that the file isn't cached.
+                if let Some(writer) = &mut file {
+                    if writer.write_all(&chunk).await.is_err() {
+                        file = None;
+                    }
+                }
+                if let Some(tx) = &download {
+                    if tx.send(Ok(chunk)).await.is_err() {
+                        download = None;
+                    }
+                }
+                if file.is_none() && download.is_none() {
+                    break;
+                }
+            }
+            // The size recorded is the one fetched, a file that changed during the fetch is then
+            // fetched again next time.
+            let cached = match file {
+                Some(mut file) if len <= cache.state.max_size => file.flush().await.is_ok() && cache.insert(&key, &partial, len, modified).is_ok(),
+                _ => false,
+            };
+            if !cached {
+                let _ = tokio::fs::remove_file(&partial).await;
+            }
+            if let Some(tx) = download {
+                let _ = tx.send(Ok(Bytes::new())).await;
+            }
+        });
+        Ok(Filling {
+            chunks: rx,
+            chunk: Bytes::new(),
+            skip: start_pos,
+        })
+    }
+}
+
+// A download served while the file is fetched into the cache. The fetch ends it with an empty
+// chunk, so that a fetch that stopped without one shows up as an error rather than a short file.
+struct Filling {
+    chunks: mpsc::Receiver<io::Result<Bytes>>,
+    chunk: Bytes,
+    skip: u64,
+}
+
+impl AsyncRead for Filling {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        loop {
+            if !this.chunk.is_empty() {
+                let len = this.chunk.len().min(buf.remaining());
+                buf.put_slice(&this.chunk.split_to(len));
+                return Poll::Ready(Ok(()));
+            }
+            match ready!(this.chunks.poll_recv(cx)) {
+                Some(Ok(chunk)) if chunk.is_empty() => return Poll::Ready(Ok(())),
+                Some(Ok(mut chunk)) => {
+                    let skipped = this.skip.min(chunk.len() as u64);
+                    chunk.advance(skipped as usize);
+                    this.skip -= skipped;
+                    this.chunk = chunk;
+                }
+                Some(Err(err)) => return Poll::Ready(Err(err)),
+                None => return Poll::Ready(Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Fetching the file into the cache stopped"))),
+            }
+        }
+    }
+}
+
+// What the cache has for a file of the wrapped back-end.
+enum Lookup {
+    // The file can't be cached, for instance because it is larger than the cache.
+    Uncacheable,
+    Hit(tokio::fs::File),
+    Miss { key: String, modified: SystemTime },
+}
+
+/// A [`StorageBackend`] that wraps a slow one, for instance one that stores files in the cloud,
+/// and keeps copies of the downloaded files in a [`DiskCache`] on local disk. Repeated downloads of
+/// a file are served from the cache for as long as the size and modification time that the wrapped
+/// back-end reports for it stay the same.
+///
+/// With [write-back](Cached::write_back) enabled uploads are stored in the cache first and sent to
+/// the wrapped back-end in the background. Cached files are kept per user, since users may see
+/// different files under the same path. The users are told apart by their `Display` representation.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::ServerBuilder;
+/// use libunftp::storage::{Cached, DiskCache};
+/// use unftp_sbe_fs::Filesystem;
+///
+/// let cache = DiskCache::new(std::env::temp_dir().join("ftp-cache"), 1024 * 1024 * 1024).unwrap();
+/// let server = ServerBuilder::new(Box::new(move || Cached::new(Filesystem::new("/srv/ftp"), cache.clone())));
+/// ```
+#[derive(Debug)]
+pub struct Cached<Storage> {
+    inner: Arc<Storage>,
+    cache: DiskCache,
+    write_back: bool,
+    logger: slog::Logger,
+}
+
+impl<Storage> Cached<Storage> {
+    /// Wraps the given storage back-end.
+    pub fn new(inner: Storage, cache: DiskCache) -> Self {
+        Cached {
+            inner: Arc::new(inner),
+            cache,
+            write_back: false,
+            logger: slog::Logger::root(slog_stdlog::StdLog {}.fuse(), slog::o!()),
+        }
+    }
+
+    /// Makes uploads complete as soon as they are stored in the cache. They are then sent to the
+    /// wrapped back-end in the background, which means that the client gets its reply before the
+    /// file shows up on the back-end and that the upload is lost if sending it fails or the server
+    /// stops before it was sent. Uploads that resume at an offset are always sent directly.
+    pub fn write_back(mut self, enabled: bool) -> Self {
+        self.write_back = enabled;
+        self
+    }
+
+    /// Sets the logger used to report failed write-back uploads. Defaults to the `log` crate.
+    pub fn logger(mut self, logger: slog::Logger) -> Self {
+        self.logger = logger;
+        self
+    }
+}
+
+// The key of a file in the cache: its path from the root of the back-end. Users with a home
+// directory only see the part of the back-end in it, so their paths are taken from their home.
+// Users aren't told apart by name, since several can go by the same one, like DefaultUser does.
+fn cache_key<User: UserDetail>(user: &User, path: &Path) -> String {
+    let path = path.strip_prefix("/").unwrap_or(path);
+    let path = user.home().unwrap_or(Path::new("/")).join(path);
+    encoding::path_to_text(path.as_os_str()).into_owned()
+}
+
+impl<Storage> Cached<Storage> {
+    // Looks the file up in the cache.
+    async fn lookup<User, P>(&self, user: &User, path: P) -> Result<Lookup>
+    where
+        Storage: StorageBackend<User>,
+        User: UserDetail,
+        P: AsRef<Path> + Send + Debug,
+    {
+        let path = path.as_ref();
+        let metadata = self.inner.metadata(user, path).await?;
+        let modified = match metadata.modified() {
+            Ok(modified) if metadata.is_file() && metadata.len() <= self.cache.state.max_size => modified,
+            _ => return Ok(Lookup::Uncacheable),
+        };
+        let key = cache_key(user, path);
+        if let Some(file) = self.cache.lookup(&key, metadata.len(), modified) {
+            match tokio::fs::File::open(file).await {
+                Ok(file) => return Ok(Lookup::Hit(file)),
+                // Evicted in the meantime.
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(Lookup::Miss { key, modified })
+    }
+
+    // Writes the upload to the cache, and sends it to the wrapped back-end in the background.
+    async fn put_back<User, R>(&self, user: &User, input: R, path: PathBuf) -> Result<u64>
+    where
+        Storage: StorageBackend<User> + 'static,
+        User: UserDetail + Clone + 'static,
+        R: tokio::io::AsyncRead + Send + Sync + Unpin + 'static,
+    {
+        let partial = self.cache.partial_path();
+        let mut reader = input;
+        let stored = async {
+            let mut file = tokio::fs::File::create(&partial).await?;
+            let len = tokio::io::copy(&mut reader, &mut file).await?;
+            file.flush().await?;
+            Ok::<u64, Error>(len)
+        }
+        .await;
+        let len = match stored {
+            Ok(len) => len,
+            Err(err) => {
+                let _ = tokio::fs::remove_file(&partial).await;
+                return Err(err);
+            }
+        };
+
+        let inner = Arc::clone(&self.inner);
+        let cache = self.cache.clone();
+        let logger = self.logger.clone();
+        let user = user.clone();
+        tokio::spawn(async move {
+            let uploaded = match tokio::fs::File::open(&partial).await {
+                Ok(file) => inner.put(&user, file, &path, 0).await,
+                Err(err) => Err(err.into()),
+            };
+            if let Err(err) = uploaded {
+                slog::error!(logger, "Failed to write back upload of {:?} for user {}: {}", path, user, err);
+                let _ = tokio::fs::remove_file(&partial).await;
+                return;
+            }
+            // Keep the upload as the cached copy when it matches what the back-end has now.
+            match inner
+                .metadata(&user, &path)
+                .await
+                .and_then(|metadata| Ok((metadata.len(), metadata.modified()?)))
+            {
+                Ok((stored_len, modified)) if stored_len == len && len <= cache.state.max_size => {
+                    if cache.insert(&cache_key(&user, &path), &partial, len, modified).is_err() {
+                        let _ = tokio::fs::remove_file(&partial).await;
+                    }
+                }
+                _ => {
+                    let _ = tokio::fs::remove_file(&partial).await;
+                }
+            }
+        });
+        Ok(len)
+    }
+}
+
+#[async_trait]
+impl<Storage, User> StorageBackend<User> for Cached<Storage>
+where
+    Storage: StorageBackend<User> + 'static,
+    User: UserDetail + Clone + 'static,
+{
+    type Metadata = Storage::Metadata;
+
+    // Called on login, before any upload could have started to share the wrapped back-end.
+    fn enter(&mut self, user_detail: &User) -> io::Result<()> {
+        match Arc::get_mut(&mut self.inner) {
+            Some(inner) => inner.enter(user_detail),
+            None => Err(io::Error::new(io::ErrorKind::Other, "Storage back-end in use by a write-back upload")),
+        }
+    }
+
+    // Called before the session starts, when nothing shares the wrapped back-end yet.
+    fn set_session_context(&mut self, context: SessionContext) {
+        if let Some(inner) = Arc::get_mut(&mut self.inner) {
+            inner.set_session_context(context)
+        }
+    }
+
+    fn session_scratch(&self) -> Option<PathBuf> {
+        self.inner.session_scratch()
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    // Uploads that are written back are only sent to the wrapped back-end once complete.
+    fn supported_features(&self) -> u32 {
+        if self.write_back {
+            self.inner.supported_features() | FEATURE_ATOMIC_UPLOADS
+        } else {
+            self.inner.supported_features()
+        }
+    }
+
+    async fn metadata<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<Self::Metadata> {
+        self.inner.metadata(user, path).await
+    }
+
+    async fn metadata_many<P>(&self, user: &User, paths: Vec<P>) -> Vec<Result<Self::Metadata>>
+    where
+        P: AsRef<Path> + Send + Debug,
+    {
+        self.inner.metadata_many(user, paths).await
+    }
+
+    async fn md5<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<String>
+    where
+        P: AsRef<Path> + Send + Debug,
+    {
+        self.inner.md5(user, path).await
+    }
+
+    async fn list<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<Vec<Fileinfo<PathBuf, Self::Metadata>>>
+    where
+        <Self as StorageBackend<User>>::Metadata: Metadata,
+    {
+        self.inner.list(user, path).await
+    }
+
+    async fn list_fmt<P>(&self, user: &User, path: P) -> std::result::Result<io::Cursor<Vec<u8>>, Error>
+    where
+        P: AsRef<Path> + Send + Debug,
+        Self::Metadata: Metadata + 'static,
+    {
+        self.inner.list_fmt(user, path).await
+    }
+
+    async fn list_vec<P>(&self, user: &User, path: P) -> std::result::Result<Vec<String>, Error>
+    where
+        P: AsRef<Path> + Send + Debug,
+        Self::Metadata: Metadata + 'static,
+    {
+        self.inner.list_vec(user, path).await
+    }
+
+    async fn nlst<P>(&self, user: &User, path: P) -> std::result::Result<io::Cursor<Vec<u8>>, io::Error>
+    where
+        P: AsRef<Path> + Send + Debug,
+        Self::Metadata: Metadata + 'static,
+    {
+        self.inner.nlst(user, path).await
+    }
+
+    async fn get<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P, start_pos: u64) -> Result<Box<dyn tokio::io::AsyncRead + Send + Sync + Unpin>> {
+        match self.lookup(user, path.as_ref()).await? {
+            Lookup::Hit(mut file) => {
+                if start_pos > 0 {
+                    file.seek(io::SeekFrom::Start(start_pos)).await?;
+                }
+                Ok(Box::new(file))
+            }
+            Lookup::Miss { key, modified } => {
+                let reader = self.inner.get(user, path, 0).await?;
+                Ok(Box::new(self.cache.fill(key, modified, reader, start_pos).await?))
+            }
+            Lookup::Uncacheable => self.inner.get(user, path, start_pos).await,
+        }
+    }
+
+    // Cached files are local, so they can be sent without copying them through user space. Files
+    // that aren't cached yet are left to get, which serves them while it fetches them.
+    async fn get_file<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<Option<std::fs::File>> {
+        match self.lookup(user, path.as_ref()).await? {
+            Lookup::Hit(file) => Ok(Some(file.into_std().await)),
+            Lookup::Miss { .. } => Ok(None),
+            Lookup::Uncacheable => self.inner.get_file(user, path).await,
+        }
+    }
+
+    async fn put<P: AsRef<Path> + Send + Debug, R: tokio::io::AsyncRead + Send + Sync + Unpin + 'static>(
+        &self,
+        user: &User,
+        input: R,
+        path: P,
+        start_pos: u64,
+    ) -> Result<u64> {
+        self.cache.remove(&cache_key(user, path.as_ref()));
+        if self.write_back && start_pos == 0 {
+            return self.put_back(user, input, path.as_ref().to_path_buf()).await;
+        }
+        self.inner.put(user, input, path, start_pos).await
+    }
+
+    async fn put_unique<P: AsRef<Path> + Send + Debug, R: tokio::io::AsyncRead + Send + Sync + Unpin + 'static>(
+        &self,
+        user: &User,
+        input: R,
+        dir: P,
+    ) -> Result<(String, u64)> {
+        self.inner.put_unique(user, input, dir).await
+    }
+
+    async fn abort_put<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.inner.abort_put(user, path).await
+    }
+
+    async fn del<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.cache.remove(&cache_key(user, path.as_ref()));
+        self.inner.del(user, path).await
+    }
+
+    async fn mkd<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.inner.mkd(user, path).await
+    }
+
+    async fn rename<P: AsRef<Path> + Send + Debug>(&self, user: &User, from: P, to: P) -> Result<()> {
+        self.cache.remove(&cache_key(user, from.as_ref()));
+        self.cache.remove(&cache_key(user, to.as_ref()));
+        self.inner.rename(user, from, to).await
+    }
+
+    async fn copy<P: AsRef<Path> + Send + Debug>(&self, user: &User, from: P, to: P) -> Result<()> {
+        self.cache.remove(&cache_key(user, to.as_ref()));
+        self.inner.copy(user, from, to).await
+    }
+
+    async fn chown<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P, uid: Option<u32>, gid: Option<u32>) -> Result<()> {
+        self.inner.chown(user, path, uid, gid).await
+    }
+
+    async fn symlink<P: AsRef<Path> + Send + Debug>(&self, user: &User, target: P, link: P) -> Result<()> {
+        self.inner.symlink(user, target, link).await
+    }
+
+    async fn set_modified<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P, modified: SystemTime) -> Result<()> {
+        self.cache.remove(&cache_key(user, path.as_ref()));
+        self.inner.set_modified(user, path, modified).await
+    }
+
+    async fn check_health(&self) -> Result<()> {
+        self.inner.check_health().await
+    }
+
+    async fn rmd<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.inner.rmd(user, path).await
+    }
+
+    async fn rmd_recursive<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.inner.rmd_recursive(user, path).await
+    }
+
+    async fn cwd<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.inner.cwd(user, path).await
+    }
+
+    async fn change_dir<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<PathBuf> {
+        self.inner.change_dir(user, path).await
+    }
+
+    fn current_dir(&self, user: &User) -> Option<PathBuf> {
+        self.inner.current_dir(user)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::time::Duration;
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let dir = std::env::temp_dir().join(format!("libunftp-cache-{}", super::super::unique_file_name()));
+        let cache = DiskCache::new(&dir, 10).unwrap();
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1);
+        for key in ["a", "b"] {
+            let partial = cache.partial_path();
+            std::fs::write(&partial, b"12345").unwrap();
+            cache.insert(key, &partial, 5, modified).unwrap();
+        }
+        assert!(cache.lookup("a", 5, modified).is_some());
+
+        let partial = cache.partial_path();
+        std::fs::write(&partial, b"12345").unwrap();
+        cache.insert("c", &partial, 5, modified).unwrap();
+        assert!(cache.lookup("b", 5, modified).is_none());
+        assert!(cache.lookup("a", 5, modified).is_some());
+        // A different version of the file on the back-end makes the cached copy outdated.
+        assert!(cache.lookup("c", 6, modified).is_none());
+        assert_eq!(cache.index().size, 5);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn keys_on_the_path_in_the_back_end() {
+        #[derive(Debug)]
+        struct HomeUser(PathBuf);
+
+        impl UserDetail for HomeUser {
+            fn home(&self) -> Option<&Path> {
+                Some(&self.0)
+            }
+        }
+
+        impl std::fmt::Display for HomeUser {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "HomeUser")
+            }
+        }
+
+        let alice = HomeUser(PathBuf::from("/home/alice"));
+        let bob = HomeUser(PathBuf::from("/home/bob"));
+        assert_eq!(cache_key(&alice, Path::new("/report.txt")), "/home/alice/report.txt");
+        assert_ne!(cache_key(&alice, Path::new("/report.txt")), cache_key(&bob, Path::new("/report.txt")));
+        assert_eq!(
+            cache_key(&crate::auth::DefaultUser, Path::new("/home/alice/report.txt")),
+            "/home/alice/report.txt"
+        );
+    }
+
+    #[tokio::test]
+    async fn serves_downloads_while_filling_the_cache() {
+        let dir = std::env::temp_dir().join(format!("libunftp-cache-{}", super::super::unique_file_name()));
+        let cache = DiskCache::new(&dir, 100).unwrap();
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1);
+        let (mut backend, reader) = tokio::io::duplex(64);
+        let mut download = cache.fill("a".to_string(), modified, reader, 0).await.unwrap();
+
+        backend.write_all(b"hello").await.unwrap();
+        let mut start = [0u8; 5];
+        download.read_exact(&mut start).await.unwrap();
+        assert_eq!(&start, b"hello");
+        assert!(cache.lookup("a", 5, modified).is_none());
+
+        backend.write_all(b" world").await.unwrap();
+        drop(backend);
+        let mut rest = String::new();
+        download.read_to_string(&mut rest).await.unwrap();
+        assert_eq!(rest, " world");
+        assert!(cache.lookup("a", 11, modified).is_some());
+
+        // Resumed downloads skip what the client already has.
+        let (mut backend, reader) = tokio::io::duplex(64);
+        let mut download = cache.fill("b".to_string(), modified, reader, 3).await.unwrap();
+        backend.write_all(b"hello world").await.unwrap();
+        drop(backend);
+        let mut rest = String::new();
+        download.read_to_string(&mut rest).await.unwrap();
+        assert_eq!(rest, "lo world");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
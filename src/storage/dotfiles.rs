@@ -0,0 +1,273 @@
+//! A [`StorageBackend`] that wraps another one and hides dotfiles according to the
+//! [`Dotfiles`](crate::options::Dotfiles) option, so that the policy applies to every back-end in
+//! the same way.
+
+use super::{Error, ErrorKind, Fileinfo, Metadata, Result, StorageBackend};
+use crate::{auth::UserDetail, options::Dotfiles, server::encoding, SessionContext};
+use async_trait::async_trait;
+use std::{
+    ffi::OsStr,
+    fmt::{Debug, Write},
+    io,
+    path::{Component, Path, PathBuf},
+    time::SystemTime,
+};
+
+// Wraps the storage back-end chosen by the libunftp user. With dotfiles hidden, listings leave
+// them out and operations on them fail as if they do not exist. Otherwise every call is passed on
+// to the inner back-end as is.
+#[derive(Debug)]
+pub(crate) struct DotfileFilter<Storage> {
+    inner: Storage,
+    dotfiles: Dotfiles,
+}
+
+impl<Storage> DotfileFilter<Storage> {
+    pub fn new(inner: Storage, dotfiles: Dotfiles) -> Self {
+        DotfileFilter { inner, dotfiles }
+    }
+
+    fn hides<User: UserDetail>(&self, user: &User) -> bool {
+        user.dotfiles().unwrap_or(self.dotfiles) == Dotfiles::Hide
+    }
+
+    // Fails if dotfiles are hidden for the user and the path refers to one.
+    fn check<User: UserDetail, P: AsRef<Path>>(&self, user: &User, path: P) -> Result<P> {
+        if self.hides(user) && is_hidden(path.as_ref()) {
+            return Err(Error::from(ErrorKind::PermanentFileNotAvailable));
+        }
+        Ok(path)
+    }
+}
+
+// Tells if the path refers to a dotfile or to something inside a dot-directory.
+fn is_hidden(path: &Path) -> bool {
+    path.components()
+        .any(|component| matches!(component, Component::Normal(name) if is_dotfile(name)))
+}
+
+fn is_dotfile(name: &OsStr) -> bool {
+    name.as_encoded_bytes().starts_with(b".")
+}
+
+#[async_trait]
+impl<Storage, User> StorageBackend<User> for DotfileFilter<Storage>
+where
+    Storage: StorageBackend<User>,
+    User: UserDetail,
+{
+    type Metadata = Storage::Metadata;
+
+    fn enter(&mut self, user_detail: &User) -> io::Result<()> {
+        self.inner.enter(user_detail)
+    }
+
+    fn set_session_context(&mut self, context: SessionContext) {
+        self.inner.set_session_context(context)
+    }
+
+    fn session_scratch(&self) -> Option<PathBuf> {
+        self.inner.session_scratch()
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn supported_features(&self) -> u32 {
+        self.inner.supported_features()
+    }
+
+    async fn metadata<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<Self::Metadata> {
+        self.inner.metadata(user, self.check(user, path)?).await
+    }
+
+    async fn metadata_many<P>(&self, user: &User, paths: Vec<P>) -> Vec<Result<Self::Metadata>>
+    where
+        P: AsRef<Path> + Send + Debug,
+    {
+        if !self.hides(user) {
+            return self.inner.metadata_many(user, paths).await;
+        }
+        let (hidden, visible): (Vec<_>, Vec<_>) = paths.into_iter().enumerate().partition(|(_, path)| is_hidden(path.as_ref()));
+        let (positions, visible): (Vec<usize>, Vec<P>) = visible.into_iter().unzip();
+        let mut result: Vec<Option<Result<Self::Metadata>>> = (0..hidden.len() + positions.len()).map(|_| None).collect();
+        for (position, _) in hidden {
+            result[position] = Some(Err(Error::from(ErrorKind::PermanentFileNotAvailable)));
+        }
+        for (position, metadata) in positions.into_iter().zip(self.inner.metadata_many(user, visible).await) {
+            result[position] = Some(metadata);
+        }
+        result.into_iter().flatten().collect()
+    }
+
+    async fn md5<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<String>
+    where
+        P: AsRef<Path> + Send + Debug,
+    {
+        self.inner.md5(user, self.check(user, path)?).await
+    }
+
+    async fn list<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<Vec<Fileinfo<PathBuf, Self::Metadata>>>
+    where
+        <Self as StorageBackend<User>>::Metadata: Metadata,
+    {
+        let list = self.inner.list(user, self.check(user, path)?).await?;
+        if !self.hides(user) {
+            return Ok(list);
+        }
+        Ok(list.into_iter().filter(|fi| !fi.path.file_name().is_some_and(is_dotfile)).collect())
+    }
+
+    // The formatted listings are only passed on to the inner back-end when nothing needs to be
+    // left out, otherwise they are built from the filtered list like the default implementations do.
+
+    async fn list_fmt<P>(&self, user: &User, path: P) -> std::result::Result<io::Cursor<Vec<u8>>, Error>
+    where
+        P: AsRef<Path> + Send + Debug,
+        Self::Metadata: Metadata + 'static,
+    {
+        if !self.hides(user) {
+            return self.inner.list_fmt(user, path).await;
+        }
+        let buffer = self.list(user, path).await?.iter().fold(String::new(), |mut buf, fi| {
+            let _ = write!(buf, "{}\r\n", fi);
+            buf
+        });
+        Ok(io::Cursor::new(buffer.into_bytes()))
+    }
+
+    async fn list_vec<P>(&self, user: &User, path: P) -> std::result::Result<Vec<String>, Error>
+    where
+        P: AsRef<Path> + Send + Debug,
+        Self::Metadata: Metadata + 'static,
+    {
+        if !self.hides(user) {
+            return self.inner.list_vec(user, path).await;
+        }
+        Ok(self.list(user, path).await?.iter().map(|fi| fi.to_string()).collect())
+    }
+
+    async fn nlst<P>(&self, user: &User, path: P) -> std::result::Result<io::Cursor<Vec<u8>>, io::Error>
+    where
+        P: AsRef<Path> + Send + Debug,
+        Self::Metadata: Metadata + 'static,
+    {
+        if !self.hides(user) {
+            return self.inner.nlst(user, path).await;
+        }
+        let list = self.list(user, path).await.map_err(|_| io::Error::from(io::ErrorKind::Other))?;
+        let buffer = list.iter().fold(String::new(), |mut buf, fi| {
+            let _ = write!(buf, "{}\r\n", encoding::path_to_text(fi.path.file_name().unwrap_or_default()));
+            buf
+        });
+        Ok(io::Cursor::new(buffer.into_bytes()))
+    }
+
+    async fn get_into<'a, P, W: ?Sized>(&self, user: &User, path: P, start_pos: u64, output: &'a mut W) -> Result<u64>
+    where
+        W: tokio::io::AsyncWrite + Unpin + Sync + Send,
+        P: AsRef<Path> + Send + Debug,
+    {
+        self.inner.get_into(user, self.check(user, path)?, start_pos, output).await
+    }
+
+    async fn get<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P, start_pos: u64) -> Result<Box<dyn tokio::io::AsyncRead + Send + Sync + Unpin>> {
+        self.inner.get(user, self.check(user, path)?, start_pos).await
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    async fn get_file<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<Option<std::fs::File>> {
+        self.inner.get_file(user, self.check(user, path)?).await
+    }
+
+    async fn put<P: AsRef<Path> + Send + Debug, R: tokio::io::AsyncRead + Send + Sync + Unpin + 'static>(
+        &self,
+        user: &User,
+        input: R,
+        path: P,
+        start_pos: u64,
+    ) -> Result<u64> {
+        self.inner.put(user, input, self.check(user, path)?, start_pos).await
+    }
+
+    async fn put_unique<P: AsRef<Path> + Send + Debug, R: tokio::io::AsyncRead + Send + Sync + Unpin + 'static>(
+        &self,
+        user: &User,
+        input: R,
+        dir: P,
+    ) -> Result<(String, u64)> {
+        self.inner.put_unique(user, input, self.check(user, dir)?).await
+    }
+
+    async fn abort_put<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.inner.abort_put(user, self.check(user, path)?).await
+    }
+
+    async fn del<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.inner.del(user, self.check(user, path)?).await
+    }
+
+    async fn mkd<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.inner.mkd(user, self.check(user, path)?).await
+    }
+
+    async fn rename<P: AsRef<Path> + Send + Debug>(&self, user: &User, from: P, to: P) -> Result<()> {
+        self.inner.rename(user, self.check(user, from)?, self.check(user, to)?).await
+    }
+
+    async fn copy<P: AsRef<Path> + Send + Debug>(&self, user: &User, from: P, to: P) -> Result<()> {
+        self.inner.copy(user, self.check(user, from)?, self.check(user, to)?).await
+    }
+
+    async fn chown<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P, uid: Option<u32>, gid: Option<u32>) -> Result<()> {
+        self.inner.chown(user, self.check(user, path)?, uid, gid).await
+    }
+
+    async fn symlink<P: AsRef<Path> + Send + Debug>(&self, user: &User, target: P, link: P) -> Result<()> {
+        self.inner.symlink(user, self.check(user, target)?, self.check(user, link)?).await
+    }
+
+    async fn set_modified<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P, modified: SystemTime) -> Result<()> {
+        self.inner.set_modified(user, self.check(user, path)?, modified).await
+    }
+
+    async fn check_health(&self) -> Result<()> {
+        self.inner.check_health().await
+    }
+
+    async fn rmd<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.inner.rmd(user, self.check(user, path)?).await
+    }
+
+    async fn rmd_recursive<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.inner.rmd_recursive(user, self.check(user, path)?).await
+    }
+
+    async fn cwd<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.inner.cwd(user, self.check(user, path)?).await
+    }
+
+    async fn change_dir<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<PathBuf> {
+        self.inner.change_dir(user, self.check(user, path)?).await
+    }
+
+    fn current_dir(&self, user: &User) -> Option<PathBuf> {
+        self.inner.current_dir(user)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_hidden_paths() {
+        assert!(is_hidden(Path::new("/.ssh")));
+        assert!(is_hidden(Path::new("/home/.config/app.toml")));
+        assert!(is_hidden(Path::new(".profile")));
+        assert!(!is_hidden(Path::new("/home/user/file.txt")));
+        assert!(!is_hidden(Path::new("./dir/../file.txt")));
+        assert!(!is_hidden(Path::new("/")));
+    }
+}
@@ -1,16 +1,28 @@
 use crate::BoxError;
 use derive_more::Display;
-use thiserror::Error;
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
 
 /// The Error returned by storage backends. Storage backend implementations should choose the
 /// `ErrorKind` chosen for errors carefully since that will determine what is returned to the FTP
-/// client.
-#[derive(Debug, Error)]
-#[error("storage error: {kind}")]
+/// client, unless they [suggest a reply](Error::with_reply) themselves.
+///
+/// Besides the kind and the error of the back-end, an error can tell which operation failed on
+/// which path. libunftp adds this [context](Error::with_context) for the errors it reports.
+#[derive(Debug)]
 pub struct Error {
     kind: ErrorKind,
-    #[source]
     source: Option<BoxError>,
+    details: Option<Box<Details>>,
+}
+
+#[derive(Debug, Default)]
+struct Details {
+    operation: Option<&'static str>,
+    path: Option<PathBuf>,
+    reply: Option<(u32, String)>,
 }
 
 impl Error {
@@ -22,9 +34,43 @@ impl Error {
         Error {
             kind,
             source: Some(error.into()),
+            details: None,
         }
     }
 
+    /// Adds the operation that failed, like the FTP command, and the path it failed on.
+    pub fn with_context<P: Into<PathBuf>>(mut self, operation: &'static str, path: P) -> Error {
+        let details = self.details.get_or_insert_with(Default::default);
+        details.operation = Some(operation);
+        details.path = Some(path.into());
+        self
+    }
+
+    /// Suggests the reply that is sent to the client instead of the one for the
+    /// [kind](Error::kind). The message should be a single line. A code that is not a 4xx or 5xx
+    /// reply code known to libunftp is ignored in favour of the one for the kind, but the message is
+    /// still used.
+    pub fn with_reply<M: Into<String>>(mut self, code: u32, message: M) -> Error {
+        self.details.get_or_insert_with(Default::default).reply = Some((code, message.into()));
+        self
+    }
+
+    /// The operation that failed, if known.
+    pub fn operation(&self) -> Option<&str> {
+        self.details.as_ref()?.operation
+    }
+
+    /// The path the operation failed on, if known.
+    pub fn path(&self) -> Option<&Path> {
+        self.details.as_ref()?.path.as_deref()
+    }
+
+    /// The reply code and message the back-end suggested, if any.
+    pub fn suggested_reply(&self) -> Option<(u32, &str)> {
+        let (code, message) = self.details.as_ref()?.reply.as_ref()?;
+        Some((*code, message.as_str()))
+    }
+
     /// Detailed information about what the FTP server should do with the failure
     pub fn kind(&self) -> ErrorKind {
         self.kind
@@ -38,7 +84,34 @@ impl Error {
 
 impl From<ErrorKind> for Error {
     fn from(kind: ErrorKind) -> Error {
-        Error { kind, source: None }
+        Error {
+            kind,
+            source: None,
+            details: None,
+        }
+    }
+}
+
+// For example: storage error: 550 Permission denied (DELE /a/b.txt): Operation not permitted
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "storage error: {}", self.kind)?;
+        match (self.operation(), self.path()) {
+            (Some(operation), Some(path)) => write!(f, " ({} {})", operation, path.display())?,
+            (Some(operation), None) => write!(f, " ({})", operation)?,
+            (None, Some(path)) => write!(f, " ({})", path.display())?,
+            (None, None) => {}
+        }
+        if let Some(source) = &self.source {
+            write!(f, ": {}", source)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source.as_ref().map(|source| source.as_ref() as &(dyn std::error::Error + 'static))
     }
 }
 
@@ -0,0 +1,298 @@
+//! A [`StorageBackend`] that measures the operations of another one, to tell a slow back-end apart
+//! from a slow FTP layer.
+
+use super::{Error, ErrorKind, Fileinfo, Result, StorageBackend};
+use crate::{auth::UserDetail, metrics, SessionContext};
+use async_trait::async_trait;
+use std::{
+    fmt::Debug,
+    future::Future,
+    io,
+    path::{Path, PathBuf},
+    time::{Instant, SystemTime},
+};
+use tokio::io::AsyncWrite;
+
+/// A [`StorageBackend`] that wraps another one and records, per operation, how long it took and
+/// whether it failed. The durations go into the `ftp_backend_operation_duration_seconds` histogram
+/// and the failures into the `ftp_backend_operation_errors_total` counter, labelled with the
+/// [`name`](StorageBackend::name) of the wrapped back-end, the operation and, for failures, the
+/// [`ErrorKind`]. The metrics are only gathered when libunftp is built with the `prometheus`
+/// feature.
+///
+/// `get` is done once the back-end returned the reader, so its time is the time to the first byte.
+/// `get_into`, `put` and `put_unique` include the whole transfer.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::ServerBuilder;
+/// use libunftp::storage::{Instrumented, Retrying};
+/// use unftp_sbe_fs::Filesystem;
+///
+/// // Measures the back-end itself, not the retries around it.
+/// let server = ServerBuilder::new(Box::new(move || Retrying::new(Instrumented::new(Filesystem::new("/srv/ftp")))));
+/// ```
+#[derive(Debug)]
+pub struct Instrumented<Storage> {
+    inner: Storage,
+}
+
+impl<Storage> Instrumented<Storage> {
+    /// Wraps the given storage back-end.
+    pub fn new(inner: Storage) -> Self {
+        Instrumented { inner }
+    }
+}
+
+// The label of an error kind in the metrics.
+fn kind_label(kind: ErrorKind) -> &'static str {
+    match kind {
+        ErrorKind::TransientFileNotAvailable => "transient_file_not_available",
+        ErrorKind::PermanentFileNotAvailable => "permanent_file_not_available",
+        ErrorKind::PermanentDirectoryNotAvailable => "permanent_directory_not_available",
+        ErrorKind::PermanentDirectoryNotEmpty => "permanent_directory_not_empty",
+        ErrorKind::PermissionDenied => "permission_denied",
+        ErrorKind::ConnectionClosed => "connection_closed",
+        ErrorKind::LocalError => "local_error",
+        ErrorKind::PageTypeUnknown => "page_type_unknown",
+        ErrorKind::InsufficientStorageSpaceError => "insufficient_storage_space",
+        ErrorKind::ExceededStorageAllocationError => "exceeded_storage_allocation",
+        ErrorKind::FileNameNotAllowedError => "file_name_not_allowed",
+        ErrorKind::CommandNotImplemented => "command_not_implemented",
+    }
+}
+
+// Runs the operation and records how long it took and how it ended.
+async fn observe<T, Fut>(backend: &str, operation: &'static str, operation_fut: Fut) -> Result<T>
+where
+    Fut: Future<Output = Result<T>>,
+{
+    let start = Instant::now();
+    let result = operation_fut.await;
+    metrics::add_backend_duration(backend, operation, start.elapsed());
+    if let Err(ref err) = result {
+        metrics::inc_backend_error(backend, operation, kind_label(err.kind()));
+    }
+    result
+}
+
+#[async_trait]
+impl<Storage, User> StorageBackend<User> for Instrumented<Storage>
+where
+    Storage: StorageBackend<User>,
+    User: UserDetail,
+{
+    type Metadata = Storage::Metadata;
+
+    fn enter(&mut self, user_detail: &User) -> io::Result<()> {
+        self.inner.enter(user_detail)
+    }
+
+    fn set_session_context(&mut self, context: SessionContext) {
+        self.inner.set_session_context(context)
+    }
+
+    fn session_scratch(&self) -> Option<PathBuf> {
+        self.inner.session_scratch()
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn supported_features(&self) -> u32 {
+        self.inner.supported_features()
+    }
+
+    async fn metadata<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<Self::Metadata> {
+        observe(self.inner.name(), "metadata", self.inner.metadata(user, path)).await
+    }
+
+    // Measures the batch as a whole and counts every path that failed.
+    async fn metadata_many<P>(&self, user: &User, paths: Vec<P>) -> Vec<Result<Self::Metadata>>
+    where
+        P: AsRef<Path> + Send + Debug,
+    {
+        let start = Instant::now();
+        let result = self.inner.metadata_many(user, paths).await;
+        metrics::add_backend_duration(self.inner.name(), "metadata_many", start.elapsed());
+        for err in result.iter().filter_map(|metadata| metadata.as_ref().err()) {
+            metrics::inc_backend_error(self.inner.name(), "metadata_many", kind_label(err.kind()));
+        }
+        result
+    }
+
+    async fn md5<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<String>
+    where
+        P: AsRef<Path> + Send + Debug,
+    {
+        observe(self.inner.name(), "md5", self.inner.md5(user, path)).await
+    }
+
+    async fn list<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<Vec<Fileinfo<PathBuf, Self::Metadata>>>
+    where
+        <Self as StorageBackend<User>>::Metadata: super::Metadata,
+    {
+        observe(self.inner.name(), "list", self.inner.list(user, path)).await
+    }
+
+    async fn list_fmt<P>(&self, user: &User, path: P) -> std::result::Result<io::Cursor<Vec<u8>>, Error>
+    where
+        P: AsRef<Path> + Send + Debug,
+        Self::Metadata: super::Metadata + 'static,
+    {
+        observe(self.inner.name(), "list_fmt", self.inner.list_fmt(user, path)).await
+    }
+
+    async fn list_vec<P>(&self, user: &User, path: P) -> std::result::Result<Vec<String>, Error>
+    where
+        P: AsRef<Path> + Send + Debug,
+        Self::Metadata: super::Metadata + 'static,
+    {
+        observe(self.inner.name(), "list_vec", self.inner.list_vec(user, path)).await
+    }
+
+    async fn nlst<P>(&self, user: &User, path: P) -> std::result::Result<io::Cursor<Vec<u8>>, io::Error>
+    where
+        P: AsRef<Path> + Send + Debug,
+        Self::Metadata: super::Metadata + 'static,
+    {
+        let start = Instant::now();
+        let result = self.inner.nlst(user, path).await;
+        metrics::add_backend_duration(self.inner.name(), "nlst", start.elapsed());
+        if result.is_err() {
+            metrics::inc_backend_error(self.inner.name(), "nlst", "io_error");
+        }
+        result
+    }
+
+    async fn get_into<'a, P, W: ?Sized>(&self, user: &User, path: P, start_pos: u64, output: &'a mut W) -> Result<u64>
+    where
+        W: AsyncWrite + Unpin + Sync + Send,
+        P: AsRef<Path> + Send + Debug,
+    {
+        observe(self.inner.name(), "get_into", self.inner.get_into(user, path, start_pos, output)).await
+    }
+
+    async fn get<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P, start_pos: u64) -> Result<Box<dyn tokio::io::AsyncRead + Send + Sync + Unpin>> {
+        observe(self.inner.name(), "get", self.inner.get(user, path, start_pos)).await
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    async fn get_file<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<Option<std::fs::File>> {
+        observe(self.inner.name(), "get_file", self.inner.get_file(user, path)).await
+    }
+
+    async fn put<P: AsRef<Path> + Send + Debug, R: tokio::io::AsyncRead + Send + Sync + Unpin + 'static>(
+        &self,
+        user: &User,
+        input: R,
+        path: P,
+        start_pos: u64,
+    ) -> Result<u64> {
+        observe(self.inner.name(), "put", self.inner.put(user, input, path, start_pos)).await
+    }
+
+    async fn put_unique<P: AsRef<Path> + Send + Debug, R: tokio::io::AsyncRead + Send + Sync + Unpin + 'static>(
+        &self,
+        user: &User,
+        input: R,
+        dir: P,
+    ) -> Result<(String, u64)> {
+        observe(self.inner.name(), "put_unique", self.inner.put_unique(user, input, dir)).await
+    }
+
+    async fn abort_put<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        observe(self.inner.name(), "abort_put", self.inner.abort_put(user, path)).await
+    }
+
+    async fn del<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        observe(self.inner.name(), "del", self.inner.del(user, path)).await
+    }
+
+    async fn mkd<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        observe(self.inner.name(), "mkd", self.inner.mkd(user, path)).await
+    }
+
+    async fn rename<P: AsRef<Path> + Send + Debug>(&self, user: &User, from: P, to: P) -> Result<()> {
+        observe(self.inner.name(), "rename", self.inner.rename(user, from, to)).await
+    }
+
+    async fn copy<P: AsRef<Path> + Send + Debug>(&self, user: &User, from: P, to: P) -> Result<()> {
+        observe(self.inner.name(), "copy", self.inner.copy(user, from, to)).await
+    }
+
+    async fn chown<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P, uid: Option<u32>, gid: Option<u32>) -> Result<()> {
+        observe(self.inner.name(), "chown", self.inner.chown(user, path, uid, gid)).await
+    }
+
+    async fn symlink<P: AsRef<Path> + Send + Debug>(&self, user: &User, target: P, link: P) -> Result<()> {
+        observe(self.inner.name(), "symlink", self.inner.symlink(user, target, link)).await
+    }
+
+    async fn set_modified<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P, modified: SystemTime) -> Result<()> {
+        observe(self.inner.name(), "set_modified", self.inner.set_modified(user, path, modified)).await
+    }
+
+    async fn check_health(&self) -> Result<()> {
+        observe(self.inner.name(), "check_health", self.inner.check_health()).await
+    }
+
+    async fn rmd<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        observe(self.inner.name(), "rmd", self.inner.rmd(user, path)).await
+    }
+
+    async fn rmd_recursive<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        observe(self.inner.name(), "rmd_recursive", self.inner.rmd_recursive(user, path)).await
+    }
+
+    async fn cwd<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        observe(self.inner.name(), "cwd", self.inner.cwd(user, path)).await
+    }
+
+    async fn change_dir<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<PathBuf> {
+        observe(self.inner.name(), "change_dir", self.inner.change_dir(user, path)).await
+    }
+
+    fn current_dir(&self, user: &User) -> Option<PathBuf> {
+        self.inner.current_dir(user)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn passes_results_on() {
+        let result = observe("test", "metadata", async { Ok("done") }).await;
+        assert_eq!(result.unwrap(), "done");
+        let result: Result<()> = observe("test", "metadata", async { Err(Error::from(ErrorKind::PermissionDenied)) }).await;
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::PermissionDenied);
+    }
+
+    #[cfg(feature = "prometheus")]
+    #[tokio::test]
+    async fn records_durations_and_errors() {
+        use prometheus::core::Collector;
+
+        let _: Result<()> = observe("instrumented-test", "del", async { Err(Error::from(ErrorKind::TransientFileNotAvailable)) }).await;
+        let _ = observe("instrumented-test", "del", async { Ok(()) }).await;
+        let families = crate::MetricsCollector::new().collect();
+        let family = |name: &str| families.iter().find(|family| family.get_name() == name).unwrap();
+        let metric = |name: &str| {
+            family(name)
+                .get_metric()
+                .iter()
+                .find(|metric| metric.get_label().iter().any(|label| label.get_value() == "instrumented-test"))
+                .unwrap()
+                .clone()
+        };
+        assert_eq!(metric("ftp_backend_operation_duration_seconds").get_histogram().get_sample_count(), 2);
+        let errors = metric("ftp_backend_operation_errors_total");
+        assert_eq!(errors.get_counter().get_value(), 1.0);
+        assert!(errors.get_label().iter().any(|label| label.get_value() == "transient_file_not_available"));
+    }
+}
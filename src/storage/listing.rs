@@ -0,0 +1,363 @@
+//! A [`StorageBackend`] that wraps another one and shapes directory listings according to the
+//! [`ListingOrder`](crate::options::ListingOrder) and
+//! [dot entries](crate::ServerBuilder::listing_dot_entries) options, so that clients see the same
+//! listings whatever the back-end.
+
+use super::{Error, ErrorKind, Fileinfo, Metadata, Result, StorageBackend};
+use crate::{auth::UserDetail, options::ListingOrder, server::encoding, SessionContext};
+use async_trait::async_trait;
+use std::{
+    cmp::Ordering,
+    fmt::{Debug, Write},
+    io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+// Wraps the storage back-end. Lists are sorted in the configured order and the formatted listings
+// start with `.` and `..` if asked for. These virtual entries are left out of `list` itself, since
+// recursive listings and the server's own walks over directories would descend into them.
+// Otherwise every call is passed on to the inner back-end as is.
+#[derive(Debug)]
+pub(crate) struct DirectoryListing<Storage> {
+    inner: Storage,
+    order: ListingOrder,
+    dot_entries: bool,
+}
+
+impl<Storage> DirectoryListing<Storage> {
+    pub fn new(inner: Storage, order: ListingOrder, dot_entries: bool) -> Self {
+        DirectoryListing { inner, order, dot_entries }
+    }
+
+    // Tells if the formatted listings of the inner back-end can be passed on as they are.
+    fn passes_through(&self) -> bool {
+        self.order == ListingOrder::Unsorted && !self.dot_entries
+    }
+
+    // The entries of a formatted listing: the sorted list, preceded by `.` and `..` when the path
+    // is a directory and dot entries are on.
+    async fn entries<User, P>(&self, user: &User, path: P) -> Result<Vec<Fileinfo<PathBuf, Storage::Metadata>>>
+    where
+        User: UserDetail,
+        Storage: StorageBackend<User>,
+        Storage::Metadata: Metadata,
+        P: AsRef<Path> + Send + Debug,
+    {
+        let path = path.as_ref();
+        let list = self.list(user, path).await?;
+        if !self.dot_entries {
+            return Ok(list);
+        }
+        // Both are looked up at once, so back-ends that batch lookups need a single round trip. The
+        // root is its own parent.
+        let mut lookups = self.inner.metadata_many(user, vec![path, path.parent().unwrap_or(path)]).await.into_iter();
+        let (current, parent) = match (lookups.next(), lookups.next()) {
+            (Some(current), Some(parent)) => (current?, parent),
+            _ => return Err(Error::from(ErrorKind::LocalError)),
+        };
+        if !current.is_dir() {
+            return Ok(list);
+        }
+        let mut entries = vec![Fileinfo {
+            path: PathBuf::from("."),
+            metadata: current,
+        }];
+        if let Ok(metadata) = parent {
+            entries.push(Fileinfo {
+                path: PathBuf::from(".."),
+                metadata,
+            });
+        }
+        entries.extend(list);
+        Ok(entries)
+    }
+}
+
+// Orders two entries of a listing. Names are compared byte by byte, like `ls` does in the C
+// locale. Modification times go from newest to oldest, entries without one last.
+fn compare<M: Metadata>(order: ListingOrder, a: &Fileinfo<PathBuf, M>, b: &Fileinfo<PathBuf, M>) -> Ordering {
+    let by_name = || {
+        let name = |fi: &Fileinfo<PathBuf, M>| fi.path.file_name().map(|name| name.as_encoded_bytes().to_vec()).unwrap_or_default();
+        name(a).cmp(&name(b))
+    };
+    match order {
+        ListingOrder::Unsorted => Ordering::Equal,
+        ListingOrder::Name => by_name(),
+        ListingOrder::Modified => match (a.metadata.modified().ok(), b.metadata.modified().ok()) {
+            (Some(a), Some(b)) => b.cmp(&a),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        }
+        .then_with(by_name),
+    }
+}
+
+#[async_trait]
+impl<Storage, User> StorageBackend<User> for DirectoryListing<Storage>
+where
+    Storage: StorageBackend<User>,
+    User: UserDetail,
+{
+    type Metadata = Storage::Metadata;
+
+    fn enter(&mut self, user_detail: &User) -> io::Result<()> {
+        self.inner.enter(user_detail)
+    }
+
+    fn set_session_context(&mut self, context: SessionContext) {
+        self.inner.set_session_context(context)
+    }
+
+    fn session_scratch(&self) -> Option<PathBuf> {
+        self.inner.session_scratch()
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn supported_features(&self) -> u32 {
+        self.inner.supported_features()
+    }
+
+    async fn metadata<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<Self::Metadata> {
+        self.inner.metadata(user, path).await
+    }
+
+    async fn metadata_many<P>(&self, user: &User, paths: Vec<P>) -> Vec<Result<Self::Metadata>>
+    where
+        P: AsRef<Path> + Send + Debug,
+    {
+        self.inner.metadata_many(user, paths).await
+    }
+
+    async fn md5<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<String>
+    where
+        P: AsRef<Path> + Send + Debug,
+    {
+        self.inner.md5(user, path).await
+    }
+
+    async fn list<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<Vec<Fileinfo<PathBuf, Self::Metadata>>>
+    where
+        <Self as StorageBackend<User>>::Metadata: Metadata,
+    {
+        let mut list = self.inner.list(user, path).await?;
+        if self.order != ListingOrder::Unsorted {
+            list.sort_by(|a, b| compare(self.order, a, b));
+        }
+        Ok(list)
+    }
+
+    // The formatted listings are only passed on to the inner back-end when they don't need to be
+    // reshaped, otherwise they are built from the entries like the default implementations do.
+
+    async fn list_fmt<P>(&self, user: &User, path: P) -> std::result::Result<io::Cursor<Vec<u8>>, Error>
+    where
+        P: AsRef<Path> + Send + Debug,
+        Self::Metadata: Metadata + 'static,
+    {
+        if self.passes_through() {
+            return self.inner.list_fmt(user, path).await;
+        }
+        let buffer = self.entries(user, path).await?.iter().fold(String::new(), |mut buf, fi| {
+            let _ = write!(buf, "{}\r\n", fi);
+            buf
+        });
+        Ok(io::Cursor::new(buffer.into_bytes()))
+    }
+
+    async fn list_vec<P>(&self, user: &User, path: P) -> std::result::Result<Vec<String>, Error>
+    where
+        P: AsRef<Path> + Send + Debug,
+        Self::Metadata: Metadata + 'static,
+    {
+        if self.passes_through() {
+            return self.inner.list_vec(user, path).await;
+        }
+        Ok(self.entries(user, path).await?.iter().map(|fi| fi.to_string()).collect())
+    }
+
+    async fn nlst<P>(&self, user: &User, path: P) -> std::result::Result<io::Cursor<Vec<u8>>, io::Error>
+    where
+        P: AsRef<Path> + Send + Debug,
+        Self::Metadata: Metadata + 'static,
+    {
+        if self.passes_through() {
+            return self.inner.nlst(user, path).await;
+        }
+        let entries = self.entries(user, path).await.map_err(|_| io::Error::from(io::ErrorKind::Other))?;
+        let buffer = entries.iter().fold(String::new(), |mut buf, fi| {
+            let _ = write!(
+                buf,
+                "{}\r\n",
+                fi.path
+                    .components()
+                    .next_back()
+                    .map(|name| encoding::path_to_text(name.as_os_str()))
+                    .unwrap_or_default()
+            );
+            buf
+        });
+        Ok(io::Cursor::new(buffer.into_bytes()))
+    }
+
+    async fn get_into<'a, P, W: ?Sized>(&self, user: &User, path: P, start_pos: u64, output: &'a mut W) -> Result<u64>
+    where
+        W: tokio::io::AsyncWrite + Unpin + Sync + Send,
+        P: AsRef<Path> + Send + Debug,
+    {
+        self.inner.get_into(user, path, start_pos, output).await
+    }
+
+    async fn get<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P, start_pos: u64) -> Result<Box<dyn tokio::io::AsyncRead + Send + Sync + Unpin>> {
+        self.inner.get(user, path, start_pos).await
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    async fn get_file<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<Option<std::fs::File>> {
+        self.inner.get_file(user, path).await
+    }
+
+    async fn put<P: AsRef<Path> + Send + Debug, R: tokio::io::AsyncRead + Send + Sync + Unpin + 'static>(
+        &self,
+        user: &User,
+        input: R,
+        path: P,
+        start_pos: u64,
+    ) -> Result<u64> {
+        self.inner.put(user, input, path, start_pos).await
+    }
+
+    async fn put_unique<P: AsRef<Path> + Send + Debug, R: tokio::io::AsyncRead + Send + Sync + Unpin + 'static>(
+        &self,
+        user: &User,
+        input: R,
+        dir: P,
+    ) -> Result<(String, u64)> {
+        self.inner.put_unique(user, input, dir).await
+    }
+
+    async fn abort_put<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.inner.abort_put(user, path).await
+    }
+
+    async fn del<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.inner.del(user, path).await
+    }
+
+    async fn mkd<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.inner.mkd(user, path).await
+    }
+
+    async fn rename<P: AsRef<Path> + Send + Debug>(&self, user: &User, from: P, to: P) -> Result<()> {
+        self.inner.rename(user, from, to).await
+    }
+
+    async fn copy<P: AsRef<Path> + Send + Debug>(&self, user: &User, from: P, to: P) -> Result<()> {
+        self.inner.copy(user, from, to).await
+    }
+
+    async fn chown<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P, uid: Option<u32>, gid: Option<u32>) -> Result<()> {
+        self.inner.chown(user, path, uid, gid).await
+    }
+
+    async fn symlink<P: AsRef<Path> + Send + Debug>(&self, user: &User, target: P, link: P) -> Result<()> {
+        self.inner.symlink(user, target, link).await
+    }
+
+    async fn set_modified<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P, modified: SystemTime) -> Result<()> {
+        self.inner.set_modified(user, path, modified).await
+    }
+
+    async fn check_health(&self) -> Result<()> {
+        self.inner.check_health().await
+    }
+
+    async fn rmd<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.inner.rmd(user, path).await
+    }
+
+    async fn rmd_recursive<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.inner.rmd_recursive(user, path).await
+    }
+
+    async fn cwd<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.inner.cwd(user, path).await
+    }
+
+    async fn change_dir<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<PathBuf> {
+        self.inner.change_dir(user, path).await
+    }
+
+    fn current_dir(&self, user: &User) -> Option<PathBuf> {
+        self.inner.current_dir(user)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::ErrorKind;
+    use std::time::Duration;
+
+    struct Meta {
+        modified: Option<SystemTime>,
+    }
+
+    impl Metadata for Meta {
+        fn len(&self) -> u64 {
+            0
+        }
+
+        fn is_dir(&self) -> bool {
+            false
+        }
+
+        fn is_file(&self) -> bool {
+            true
+        }
+
+        fn is_symlink(&self) -> bool {
+            false
+        }
+
+        fn modified(&self) -> Result<SystemTime> {
+            self.modified.ok_or_else(|| Error::from(ErrorKind::LocalError))
+        }
+
+        fn gid(&self) -> u32 {
+            0
+        }
+
+        fn uid(&self) -> u32 {
+            0
+        }
+    }
+
+    fn sorted(order: ListingOrder, entries: &[(&str, Option<u64>)]) -> Vec<String> {
+        let mut list: Vec<Fileinfo<PathBuf, Meta>> = entries
+            .iter()
+            .map(|(name, secs)| Fileinfo {
+                path: PathBuf::from("/dir").join(name),
+                metadata: Meta {
+                    modified: secs.map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs)),
+                },
+            })
+            .collect();
+        list.sort_by(|a, b| compare(order, a, b));
+        list.iter()
+            .map(|fi| encoding::path_to_text(fi.path.file_name().unwrap()).into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn sorts_listings() {
+        let entries = [("b.txt", Some(20)), ("B.txt", None), ("a.txt", Some(10)), ("c.txt", Some(20))];
+        assert_eq!(sorted(ListingOrder::Unsorted, &entries), vec!["b.txt", "B.txt", "a.txt", "c.txt"]);
+        assert_eq!(sorted(ListingOrder::Name, &entries), vec!["B.txt", "a.txt", "b.txt", "c.txt"]);
+        assert_eq!(sorted(ListingOrder::Modified, &entries), vec!["b.txt", "c.txt", "a.txt", "B.txt"]);
+    }
+}
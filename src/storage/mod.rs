@@ -159,13 +159,45 @@
 //! # struct Vfs{};
 //! # impl Vfs { fn new() -> Filesystem { Filesystem::new("/") } }
 //! let vfs_provider = Box::new(|| Vfs::new());
-//! let server = libunftp::Server::new(vfs_provider);
+//! let server = libunftp::ServerBuilder::new(vfs_provider);
 //! ```
 //!
 //! [`Server`]: ../struct.Server.html
 
+pub(crate) mod atomic;
+pub use atomic::{temp_upload_path, AtomicUploads};
+
+// Keeps its cache on the local disk through tokio::fs, which isn't there on WebAssembly.
+#[cfg(not(target_family = "wasm"))]
+pub(crate) mod cached;
+#[cfg(not(target_family = "wasm"))]
+pub use cached::{Cached, DiskCache};
+
+pub(crate) mod dotfiles;
+
 pub(crate) mod error;
 pub use error::{Error, ErrorKind};
 
+pub(crate) mod instrumented;
+pub use instrumented::Instrumented;
+
+pub(crate) mod listing;
+
+pub(crate) mod path_filter;
+pub use path_filter::{DefaultPathFilter, PathFilter, PathFilterError, MAX_NAME_LEN, MAX_PATH_LEN};
+
+pub(crate) mod retrying;
+pub use retrying::{RetryPolicy, Retrying};
+
+#[cfg(feature = "test-util")]
+pub(crate) mod scripted;
+#[cfg(feature = "test-util")]
+pub use scripted::{Operation, Rule, Script, Scripted};
+
 pub(crate) mod storage_backend;
-pub use storage_backend::{Fileinfo, Metadata, Permissions, Result, StorageBackend, FEATURE_RESTART, FEATURE_SITEMD5};
+pub use storage_backend::{
+    unique_file_name, Fileinfo, Metadata, Permissions, Result, StorageBackend, FEATURE_ATOMIC_UPLOADS, FEATURE_CHOWN, FEATURE_RESTART, FEATURE_SITEMD5,
+    FEATURE_SYMLINK,
+};
+
+pub(crate) mod upload_only;
@@ -0,0 +1,91 @@
+use std::fmt::Debug;
+use thiserror::Error;
+
+/// The maximum length in bytes of a single path component accepted by the [`DefaultPathFilter`].
+pub const MAX_NAME_LEN: usize = 255;
+
+/// The maximum length in bytes of a complete path accepted by the [`DefaultPathFilter`].
+pub const MAX_PATH_LEN: usize = 4096;
+
+/// Returned by a [`PathFilter`] to reject a path. The message is sent to the client along with a
+/// `553` reply code.
+#[derive(Debug, Error)]
+#[error("{message}")]
+pub struct PathFilterError {
+    message: String,
+}
+
+impl PathFilterError {
+    /// Creates a new error with the message that should be shown to the client.
+    pub fn new<M: Into<String>>(message: M) -> Self {
+        PathFilterError { message: message.into() }
+    }
+}
+
+/// Inspects every path supplied by a client (e.g. with `STOR`, `RETR`, `MKD` or `RNTO`) before it is
+/// handed to the [`StorageBackend`](crate::storage::StorageBackend). A filter can reject the path or
+/// rewrite it, for instance to apply Unicode normalization, strip control characters or enforce a
+/// naming policy.
+///
+/// Set it with [`ServerBuilder::path_filter`](crate::ServerBuilder::path_filter). The
+/// [`DefaultPathFilter`] is used if none is set.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::storage::{DefaultPathFilter, PathFilter, PathFilterError};
+///
+/// #[derive(Debug)]
+/// struct NoSpaces;
+///
+/// impl PathFilter for NoSpaces {
+///     fn filter(&self, path: String) -> Result<String, PathFilterError> {
+///         let path = DefaultPathFilter.filter(path)?;
+///         if path.contains(' ') {
+///             return Err(PathFilterError::new("File names may not contain spaces"));
+///         }
+///         Ok(path)
+///     }
+/// }
+/// ```
+pub trait PathFilter: Send + Sync + Debug {
+    /// Returns the path to use, which may be the given path as is, or an error if the path is not
+    /// allowed.
+    fn filter(&self, path: String) -> Result<String, PathFilterError>;
+}
+
+/// The [`PathFilter`] used by default. It rejects paths containing NUL bytes, path components
+/// longer than [`MAX_NAME_LEN`] bytes and paths longer than [`MAX_PATH_LEN`] bytes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultPathFilter;
+
+impl PathFilter for DefaultPathFilter {
+    fn filter(&self, path: String) -> Result<String, PathFilterError> {
+        if path.contains('\0') {
+            return Err(PathFilterError::new("File name contains a NUL byte"));
+        }
+        if path.len() > MAX_PATH_LEN || path.split('/').any(|name| name.len() > MAX_NAME_LEN) {
+            return Err(PathFilterError::new("File name too long"));
+        }
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn default_filter_accepts_regular_paths() {
+        assert_eq!(DefaultPathFilter.filter("/some/dir/file.txt".to_string()).unwrap(), "/some/dir/file.txt");
+        assert_eq!(DefaultPathFilter.filter("a".repeat(MAX_NAME_LEN)).unwrap().len(), MAX_NAME_LEN);
+    }
+
+    #[test]
+    fn default_filter_rejects_nul_and_overlong_names() {
+        assert!(DefaultPathFilter.filter("file\0.txt".to_string()).is_err());
+        assert!(DefaultPathFilter.filter(format!("dir/{}", "a".repeat(MAX_NAME_LEN + 1))).is_err());
+        assert!(DefaultPathFilter.filter("a/".repeat(MAX_PATH_LEN / 2 + 1)).is_err());
+    }
+}
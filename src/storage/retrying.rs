@@ -0,0 +1,465 @@
+//! A [`StorageBackend`] that retries the idempotent operations of another one when they fail with
+//! [`ErrorKind::TransientFileNotAvailable`].
+
+use super::{Error, ErrorKind, Fileinfo, Result, StorageBackend, FEATURE_RESTART};
+use crate::{auth::UserDetail, SessionContext};
+use async_trait::async_trait;
+use std::{
+    fmt::Debug,
+    future::Future,
+    io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, SystemTime},
+};
+use tokio::io::AsyncWrite;
+
+/// How often and how patiently [`Retrying`] retries an operation. The wait before a retry doubles
+/// with every attempt, starting at the initial backoff, up to the maximum backoff. With jitter,
+/// which is on by default, a random part of up to half of every wait is left out so that sessions
+/// that failed at the same moment don't retry in lockstep.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    jitter: bool,
+}
+
+impl RetryPolicy {
+    /// Makes at most the given number of attempts, including the first one, waiting 100
+    /// milliseconds before the first retry and at most 5 seconds before the others.
+    pub fn new(attempts: u32) -> Self {
+        RetryPolicy {
+            attempts: attempts.max(1),
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            jitter: true,
+        }
+    }
+
+    /// Never retries.
+    pub fn never() -> Self {
+        RetryPolicy::new(1)
+    }
+
+    /// Sets the wait before the first retry and the longest wait before any retry.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max.max(initial);
+        self
+    }
+
+    /// Enables or disables the jitter.
+    pub fn jitter(mut self, enabled: bool) -> Self {
+        self.jitter = enabled;
+        self
+    }
+
+    // The longest wait before the given retry, counting from zero.
+    fn max_delay(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry);
+        self.initial_backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+
+    fn delay(&self, retry: u32) -> Duration {
+        let delay = self.max_delay(retry);
+        if !self.jitter {
+            return delay;
+        }
+        let mut random = [0u8; 4];
+        if getrandom::getrandom(&mut random).is_err() {
+            return delay;
+        }
+        let fraction = u32::from_ne_bytes(random) as f64 / u32::MAX as f64;
+        delay.mul_f64(1.0 - fraction / 2.0)
+    }
+}
+
+/// Retries three times.
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::new(3)
+    }
+}
+
+/// A [`StorageBackend`] that wraps another one, typically one that stores files in the cloud, and
+/// retries its idempotent operations when they fail with
+/// [`TransientFileNotAvailable`](ErrorKind::TransientFileNotAvailable), waiting longer after every
+/// attempt. Every other error, and every other operation, is passed on as is.
+///
+/// The operations are retried according to the [`RetryPolicy`] of their class:
+///
+/// - metadata: `metadata`, `metadata_many`, `cwd` and `change_dir`
+/// - list: `list`, `list_fmt` and `list_vec`
+/// - read: `get`, `get_into`, `get_file` and `md5`. A download with `get_into` that failed after
+///   part of the file was sent continues where it stopped if the wrapped back-end supports
+///   [`FEATURE_RESTART`], and is not retried otherwise.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::ServerBuilder;
+/// use libunftp::storage::{RetryPolicy, Retrying};
+/// use std::time::Duration;
+/// use unftp_sbe_fs::Filesystem;
+///
+/// let server = ServerBuilder::new(Box::new(move || {
+///     Retrying::new(Filesystem::new("/srv/ftp"))
+///         .policy(RetryPolicy::new(4))
+///         .read_policy(RetryPolicy::new(6).backoff(Duration::from_millis(250), Duration::from_secs(10)))
+/// }));
+/// ```
+#[derive(Debug)]
+pub struct Retrying<Storage> {
+    inner: Storage,
+    metadata: RetryPolicy,
+    list: RetryPolicy,
+    read: RetryPolicy,
+}
+
+impl<Storage> Retrying<Storage> {
+    /// Wraps the given storage back-end, with the [default policy](RetryPolicy::default) for every
+    /// class of operations.
+    pub fn new(inner: Storage) -> Self {
+        Retrying {
+            inner,
+            metadata: RetryPolicy::default(),
+            list: RetryPolicy::default(),
+            read: RetryPolicy::default(),
+        }
+    }
+
+    /// Sets the policy of every class of operations.
+    pub fn policy(self, policy: RetryPolicy) -> Self {
+        self.metadata_policy(policy).list_policy(policy).read_policy(policy)
+    }
+
+    /// Sets the policy of the operations that look up metadata.
+    pub fn metadata_policy(mut self, policy: RetryPolicy) -> Self {
+        self.metadata = policy;
+        self
+    }
+
+    /// Sets the policy of the operations that list directories.
+    pub fn list_policy(mut self, policy: RetryPolicy) -> Self {
+        self.list = policy;
+        self
+    }
+
+    /// Sets the policy of the operations that read files.
+    pub fn read_policy(mut self, policy: RetryPolicy) -> Self {
+        self.read = policy;
+        self
+    }
+}
+
+fn is_transient(err: &Error) -> bool {
+    err.kind() == ErrorKind::TransientFileNotAvailable
+}
+
+// Runs the operation until it succeeds, fails with an error that is not transient, or the policy
+// allows no more attempts.
+async fn retry<T, F, Fut>(policy: RetryPolicy, mut operation: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut attempt = 1;
+    loop {
+        match operation().await {
+            Err(err) if is_transient(&err) && attempt < policy.attempts => {
+                tokio::time::sleep(policy.delay(attempt - 1)).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+// Counts the bytes a download passed to the output, to know where to continue after a failure.
+struct CountingWriter<'a, W: ?Sized> {
+    inner: &'a mut W,
+    written: u64,
+}
+
+impl<W: AsyncWrite + Unpin + ?Sized> AsyncWrite for CountingWriter<'_, W> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut *this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = result {
+            this.written += n as u64;
+        }
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[async_trait]
+impl<Storage, User> StorageBackend<User> for Retrying<Storage>
+where
+    Storage: StorageBackend<User>,
+    User: UserDetail,
+{
+    type Metadata = Storage::Metadata;
+
+    fn enter(&mut self, user_detail: &User) -> io::Result<()> {
+        self.inner.enter(user_detail)
+    }
+
+    fn set_session_context(&mut self, context: SessionContext) {
+        self.inner.set_session_context(context)
+    }
+
+    fn session_scratch(&self) -> Option<PathBuf> {
+        self.inner.session_scratch()
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn supported_features(&self) -> u32 {
+        self.inner.supported_features()
+    }
+
+    async fn metadata<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<Self::Metadata> {
+        let path = path.as_ref();
+        retry(self.metadata, || self.inner.metadata(user, path)).await
+    }
+
+    // Looks up all paths at once and then retries the ones that failed one by one.
+    async fn metadata_many<P>(&self, user: &User, paths: Vec<P>) -> Vec<Result<Self::Metadata>>
+    where
+        P: AsRef<Path> + Send + Debug,
+    {
+        let paths: Vec<PathBuf> = paths.iter().map(|path| path.as_ref().to_path_buf()).collect();
+        let mut result = self.inner.metadata_many(user, paths.clone()).await;
+        if self.metadata.attempts > 1 {
+            let policy = RetryPolicy {
+                attempts: self.metadata.attempts - 1,
+                ..self.metadata
+            };
+            for (metadata, path) in result.iter_mut().zip(&paths) {
+                if matches!(metadata, Err(err) if is_transient(err)) {
+                    tokio::time::sleep(self.metadata.delay(0)).await;
+                    *metadata = retry(policy, || self.inner.metadata(user, path)).await;
+                }
+            }
+        }
+        result
+    }
+
+    async fn md5<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<String>
+    where
+        P: AsRef<Path> + Send + Debug,
+    {
+        let path = path.as_ref();
+        retry(self.read, || self.inner.md5(user, path)).await
+    }
+
+    async fn list<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<Vec<Fileinfo<PathBuf, Self::Metadata>>>
+    where
+        <Self as StorageBackend<User>>::Metadata: super::Metadata,
+    {
+        let path = path.as_ref();
+        retry(self.list, || self.inner.list(user, path)).await
+    }
+
+    async fn list_fmt<P>(&self, user: &User, path: P) -> std::result::Result<io::Cursor<Vec<u8>>, Error>
+    where
+        P: AsRef<Path> + Send + Debug,
+        Self::Metadata: super::Metadata + 'static,
+    {
+        let path = path.as_ref();
+        retry(self.list, || self.inner.list_fmt(user, path)).await
+    }
+
+    async fn list_vec<P>(&self, user: &User, path: P) -> std::result::Result<Vec<String>, Error>
+    where
+        P: AsRef<Path> + Send + Debug,
+        Self::Metadata: super::Metadata + 'static,
+    {
+        let path = path.as_ref();
+        retry(self.list, || self.inner.list_vec(user, path)).await
+    }
+
+    async fn nlst<P>(&self, user: &User, path: P) -> std::result::Result<io::Cursor<Vec<u8>>, io::Error>
+    where
+        P: AsRef<Path> + Send + Debug,
+        Self::Metadata: super::Metadata + 'static,
+    {
+        self.inner.nlst(user, path).await
+    }
+
+    async fn get_into<'a, P, W: ?Sized>(&self, user: &User, path: P, start_pos: u64, output: &'a mut W) -> Result<u64>
+    where
+        W: AsyncWrite + Unpin + Sync + Send,
+        P: AsRef<Path> + Send + Debug,
+    {
+        let path = path.as_ref();
+        let resumable = self.inner.supported_features() & FEATURE_RESTART != 0;
+        let mut output = CountingWriter { inner: output, written: 0 };
+        let mut attempt = 1;
+        loop {
+            let written = output.written;
+            match self.inner.get_into(user, path, start_pos + written, &mut output).await {
+                Err(err) if is_transient(&err) && attempt < self.read.attempts && (resumable || output.written == 0) => {
+                    tokio::time::sleep(self.read.delay(attempt - 1)).await;
+                    attempt += 1;
+                }
+                Ok(_) => return Ok(output.written),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    async fn get<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P, start_pos: u64) -> Result<Box<dyn tokio::io::AsyncRead + Send + Sync + Unpin>> {
+        let path = path.as_ref();
+        retry(self.read, || self.inner.get(user, path, start_pos)).await
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    async fn get_file<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<Option<std::fs::File>> {
+        let path = path.as_ref();
+        retry(self.read, || self.inner.get_file(user, path)).await
+    }
+
+    async fn put<P: AsRef<Path> + Send + Debug, R: tokio::io::AsyncRead + Send + Sync + Unpin + 'static>(
+        &self,
+        user: &User,
+        input: R,
+        path: P,
+        start_pos: u64,
+    ) -> Result<u64> {
+        self.inner.put(user, input, path, start_pos).await
+    }
+
+    async fn put_unique<P: AsRef<Path> + Send + Debug, R: tokio::io::AsyncRead + Send + Sync + Unpin + 'static>(
+        &self,
+        user: &User,
+        input: R,
+        dir: P,
+    ) -> Result<(String, u64)> {
+        self.inner.put_unique(user, input, dir).await
+    }
+
+    async fn abort_put<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.inner.abort_put(user, path).await
+    }
+
+    async fn del<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.inner.del(user, path).await
+    }
+
+    async fn mkd<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.inner.mkd(user, path).await
+    }
+
+    async fn rename<P: AsRef<Path> + Send + Debug>(&self, user: &User, from: P, to: P) -> Result<()> {
+        self.inner.rename(user, from, to).await
+    }
+
+    async fn copy<P: AsRef<Path> + Send + Debug>(&self, user: &User, from: P, to: P) -> Result<()> {
+        self.inner.copy(user, from, to).await
+    }
+
+    async fn chown<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P, uid: Option<u32>, gid: Option<u32>) -> Result<()> {
+        self.inner.chown(user, path, uid, gid).await
+    }
+
+    async fn symlink<P: AsRef<Path> + Send + Debug>(&self, user: &User, target: P, link: P) -> Result<()> {
+        self.inner.symlink(user, target, link).await
+    }
+
+    async fn set_modified<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P, modified: SystemTime) -> Result<()> {
+        self.inner.set_modified(user, path, modified).await
+    }
+
+    async fn check_health(&self) -> Result<()> {
+        self.inner.check_health().await
+    }
+
+    async fn rmd<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.inner.rmd(user, path).await
+    }
+
+    async fn rmd_recursive<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.inner.rmd_recursive(user, path).await
+    }
+
+    async fn cwd<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        let path = path.as_ref();
+        retry(self.metadata, || self.inner.cwd(user, path)).await
+    }
+
+    async fn change_dir<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<PathBuf> {
+        let path = path.as_ref();
+        retry(self.metadata, || self.inner.change_dir(user, path)).await
+    }
+
+    fn current_dir(&self, user: &User) -> Option<PathBuf> {
+        self.inner.current_dir(user)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[test]
+    fn backs_off_exponentially_up_to_the_maximum() {
+        let policy = RetryPolicy::new(10).backoff(Duration::from_millis(100), Duration::from_secs(1)).jitter(false);
+        let delays: Vec<Duration> = (0..6).map(|retry| policy.delay(retry)).collect();
+        assert_eq!(delays, [100, 200, 400, 800, 1000, 1000].map(Duration::from_millis));
+
+        let jittered = RetryPolicy::new(10).backoff(Duration::from_millis(100), Duration::from_secs(1));
+        for retry in 0..6 {
+            let delay = jittered.delay(retry);
+            assert!(delay >= policy.delay(retry) / 2 && delay <= policy.delay(retry), "{:?}", delay);
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_transient_errors_only() {
+        let policy = RetryPolicy::new(3).backoff(Duration::from_millis(1), Duration::from_millis(1));
+        let calls = AtomicU32::new(0);
+        let result = retry(policy, || async {
+            match calls.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => Err(Error::from(ErrorKind::TransientFileNotAvailable)),
+                _ => Ok("done"),
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), "done");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        let calls = AtomicU32::new(0);
+        let result: Result<()> = retry(policy, || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err(Error::from(ErrorKind::TransientFileNotAvailable))
+        })
+        .await;
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::TransientFileNotAvailable);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        let calls = AtomicU32::new(0);
+        let result: Result<()> = retry(policy, || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err(Error::from(ErrorKind::PermanentFileNotAvailable))
+        })
+        .await;
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::PermanentFileNotAvailable);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
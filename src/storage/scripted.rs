@@ -0,0 +1,530 @@
+//! A [`StorageBackend`] that fails, stalls or cuts off the operations of another one as a test
+//! scripts it, to check how the server and its clients deal with a misbehaving back-end.
+
+use super::{Error, ErrorKind, Fileinfo, Result, StorageBackend};
+use crate::{auth::UserDetail, server::path, SessionContext};
+use async_trait::async_trait;
+use std::{
+    fmt::Debug,
+    io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll},
+    time::{Duration, SystemTime},
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf, Take};
+
+/// The operations of a [`StorageBackend`] that a [`Rule`] can apply to. Methods that do the same
+/// thing in different ways share an operation, e.g. `get`, `get_into` and `get_file` are all
+/// [`Get`](Operation::Get).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    /// `metadata` and `metadata_many`.
+    Metadata,
+    /// `md5`.
+    Md5,
+    /// `list`, `list_fmt`, `list_vec` and `nlst`.
+    List,
+    /// `get`, `get_into` and `get_file`.
+    Get,
+    /// `put`, `put_unique` and `abort_put`.
+    Put,
+    /// `del`.
+    Del,
+    /// `mkd`.
+    Mkd,
+    /// `rmd` and `rmd_recursive`.
+    Rmd,
+    /// `rename`, for the path renamed from.
+    Rename,
+    /// `copy`, for the path copied from.
+    Copy,
+    /// `chown`.
+    Chown,
+    /// `symlink`, for the target.
+    Symlink,
+    /// `set_modified`.
+    SetModified,
+    /// `cwd` and `change_dir`.
+    Cwd,
+}
+
+/// Tells [`Scripted`] what to do when an operation on a path is called. A rule without a delay,
+/// failure or truncation passes the call on as is.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::storage::{ErrorKind, Operation, Rule};
+/// use std::time::Duration;
+///
+/// // The first two downloads of the report fail, the third one works.
+/// let flaky = Rule::path("/report.csv").operation(Operation::Get).fail(ErrorKind::TransientFileNotAvailable).times(2);
+/// // Every operation on the archive takes two seconds.
+/// let slow = Rule::path("/archive.zip").delay(Duration::from_secs(2));
+/// // Downloads of the image break off after the first kilobyte.
+/// let cut = Rule::path("/image.png").operation(Operation::Get).truncate_after(1024);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    path: Option<PathBuf>,
+    operation: Option<Operation>,
+    delay: Option<Duration>,
+    error: Option<ErrorKind>,
+    truncate_after: Option<u64>,
+    times: Option<u32>,
+}
+
+impl Rule {
+    /// Applies to the given path, as the back-end sees it, for every operation.
+    pub fn path<P: AsRef<Path>>(path: P) -> Self {
+        Rule {
+            path: Some(path::normalize(path)),
+            ..Rule::any_path()
+        }
+    }
+
+    /// Applies to every path and every operation.
+    pub fn any_path() -> Self {
+        Rule {
+            path: None,
+            operation: None,
+            delay: None,
+            error: None,
+            truncate_after: None,
+            times: None,
+        }
+    }
+
+    /// Only applies to the given operation.
+    pub fn operation(mut self, operation: Operation) -> Self {
+        self.operation = Some(operation);
+        self
+    }
+
+    /// Waits this long before the operation is passed on or fails.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+
+    /// Fails the operation with an error of this kind, without passing it on.
+    pub fn fail(mut self, kind: ErrorKind) -> Self {
+        self.error = Some(kind);
+        self
+    }
+
+    /// Lets downloads read this many bytes and then fails them with an I/O error, as if the
+    /// back-end went away in the middle of the transfer. Overrides [`fail`](Self::fail) for
+    /// [`Get`](Operation::Get), other operations aren't affected.
+    pub fn truncate_after(mut self, bytes: u64) -> Self {
+        self.truncate_after = Some(bytes);
+        self
+    }
+
+    /// Applies this many times and is forgotten after that. By default a rule applies forever.
+    pub fn times(mut self, times: u32) -> Self {
+        self.times = Some(times);
+        self
+    }
+
+    fn matches(&self, operation: Operation, path: &Path) -> bool {
+        (self.operation.is_none() || self.operation == Some(operation)) && (self.path.is_none() || self.path.as_deref() == Some(path))
+    }
+}
+
+/// The rules of a [`Scripted`] back-end and the calls that were made to it. Clones share both, so
+/// that a test can change the rules and look at the calls while the server runs.
+#[derive(Debug, Clone, Default)]
+pub struct Script {
+    state: Arc<Mutex<ScriptState>>,
+}
+
+#[derive(Debug, Default)]
+struct ScriptState {
+    rules: Vec<Rule>,
+    calls: Vec<(Operation, PathBuf)>,
+}
+
+impl Script {
+    /// Creates a script without rules.
+    pub fn new() -> Self {
+        Script::default()
+    }
+
+    /// Adds a rule. When several rules match a call, the one that was added first applies.
+    pub fn add(&self, rule: Rule) {
+        self.state.lock().unwrap().rules.push(rule);
+    }
+
+    /// Removes all rules.
+    pub fn clear(&self) {
+        self.state.lock().unwrap().rules.clear();
+    }
+
+    /// Returns the operations that were called so far, with their paths, in the order they were
+    /// called.
+    pub fn calls(&self) -> Vec<(Operation, PathBuf)> {
+        self.state.lock().unwrap().calls.clone()
+    }
+
+    // Records the call and returns the rule that applies to it, using up one of its times.
+    fn next(&self, operation: Operation, path: &Path) -> Option<Rule> {
+        let path = path::normalize(path);
+        let mut state = self.state.lock().unwrap();
+        state.calls.push((operation, path.clone()));
+        let index = state.rules.iter().position(|rule| rule.matches(operation, &path))?;
+        let rule = state.rules[index].clone();
+        match rule.times {
+            Some(1) => {
+                state.rules.remove(index);
+            }
+            Some(times) => state.rules[index].times = Some(times - 1),
+            None => {}
+        }
+        Some(rule)
+    }
+
+    // Tells whether a rule applies to the call, without recording it.
+    fn applies(&self, operation: Operation, path: &Path) -> bool {
+        let path = path::normalize(path);
+        self.state.lock().unwrap().rules.iter().any(|rule| rule.matches(operation, &path))
+    }
+}
+
+/// A [`StorageBackend`] that wraps another one and delays, fails or cuts off its operations as
+/// the rules in a [`Script`] say. Calls that no rule applies to are passed on as is. Only built
+/// with the `test-util` feature.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::ServerBuilder;
+/// use libunftp::storage::{ErrorKind, Rule, Script, Scripted};
+/// use unftp_sbe_fs::Filesystem;
+///
+/// let script = Script::new();
+/// script.add(Rule::path("/busy.txt").fail(ErrorKind::TransientFileNotAvailable));
+/// let scripted = script.clone();
+/// let server = ServerBuilder::new(Box::new(move || Scripted::new(Filesystem::new("/srv/ftp"), scripted.clone())));
+/// ```
+#[derive(Debug)]
+pub struct Scripted<Storage> {
+    inner: Storage,
+    script: Script,
+}
+
+impl<Storage> Scripted<Storage> {
+    /// Wraps the given storage back-end, following the given script.
+    pub fn new(inner: Storage, script: Script) -> Self {
+        Scripted { inner, script }
+    }
+
+    // Looks up the rule for the call and waits or fails as it says. Returns the rule so that
+    // downloads can be cut off.
+    async fn enter_call(&self, operation: Operation, path: &Path) -> Result<Option<Rule>> {
+        let rule = self.script.next(operation, path);
+        if let Some(ref rule) = rule {
+            if let Some(delay) = rule.delay {
+                tokio::time::sleep(delay).await;
+            }
+            if let Some(kind) = rule.error {
+                if operation != Operation::Get || rule.truncate_after.is_none() {
+                    return Err(Error::new(kind, "scripted failure"));
+                }
+            }
+        }
+        Ok(rule)
+    }
+}
+
+// Ends a download with an error after the given number of bytes.
+struct Truncated<R> {
+    inner: Take<R>,
+}
+
+impl<R: AsyncRead + Unpin> Truncated<R> {
+    fn new(inner: R, bytes: u64) -> Self {
+        Truncated { inner: inner.take(bytes) }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Truncated<R> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        if buf.filled().len() == before && buf.remaining() > 0 && this.inner.limit() == 0 {
+            return Poll::Ready(Err(io::Error::new(io::ErrorKind::UnexpectedEof, "scripted partial read")));
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[async_trait]
+impl<Storage, User> StorageBackend<User> for Scripted<Storage>
+where
+    Storage: StorageBackend<User>,
+    User: UserDetail,
+{
+    type Metadata = Storage::Metadata;
+
+    fn enter(&mut self, user_detail: &User) -> io::Result<()> {
+        self.inner.enter(user_detail)
+    }
+
+    fn set_session_context(&mut self, context: SessionContext) {
+        self.inner.set_session_context(context)
+    }
+
+    fn session_scratch(&self) -> Option<PathBuf> {
+        self.inner.session_scratch()
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn supported_features(&self) -> u32 {
+        self.inner.supported_features()
+    }
+
+    async fn metadata<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<Self::Metadata> {
+        self.enter_call(Operation::Metadata, path.as_ref()).await?;
+        self.inner.metadata(user, path).await
+    }
+
+    // Not passed on as a whole, so that every path follows its own rule.
+    async fn metadata_many<P>(&self, user: &User, paths: Vec<P>) -> Vec<Result<Self::Metadata>>
+    where
+        P: AsRef<Path> + Send + Debug,
+    {
+        let mut result = Vec::with_capacity(paths.len());
+        for path in paths {
+            result.push(self.metadata(user, path).await);
+        }
+        result
+    }
+
+    async fn md5<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<String>
+    where
+        P: AsRef<Path> + Send + Debug,
+    {
+        self.enter_call(Operation::Md5, path.as_ref()).await?;
+        self.inner.md5(user, path).await
+    }
+
+    async fn list<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<Vec<Fileinfo<PathBuf, Self::Metadata>>>
+    where
+        <Self as StorageBackend<User>>::Metadata: super::Metadata,
+    {
+        self.enter_call(Operation::List, path.as_ref()).await?;
+        self.inner.list(user, path).await
+    }
+
+    async fn list_fmt<P>(&self, user: &User, path: P) -> std::result::Result<io::Cursor<Vec<u8>>, Error>
+    where
+        P: AsRef<Path> + Send + Debug,
+        Self::Metadata: super::Metadata + 'static,
+    {
+        self.enter_call(Operation::List, path.as_ref()).await?;
+        self.inner.list_fmt(user, path).await
+    }
+
+    async fn list_vec<P>(&self, user: &User, path: P) -> std::result::Result<Vec<String>, Error>
+    where
+        P: AsRef<Path> + Send + Debug,
+        Self::Metadata: super::Metadata + 'static,
+    {
+        self.enter_call(Operation::List, path.as_ref()).await?;
+        self.inner.list_vec(user, path).await
+    }
+
+    async fn nlst<P>(&self, user: &User, path: P) -> std::result::Result<io::Cursor<Vec<u8>>, io::Error>
+    where
+        P: AsRef<Path> + Send + Debug,
+        Self::Metadata: super::Metadata + 'static,
+    {
+        self.enter_call(Operation::List, path.as_ref()).await.map_err(io::Error::other)?;
+        self.inner.nlst(user, path).await
+    }
+
+    async fn get_into<'a, P, W: ?Sized>(&self, user: &User, path: P, start_pos: u64, output: &'a mut W) -> Result<u64>
+    where
+        W: AsyncWrite + Unpin + Sync + Send,
+        P: AsRef<Path> + Send + Debug,
+    {
+        let rule = self.enter_call(Operation::Get, path.as_ref()).await?;
+        match rule.and_then(|rule| rule.truncate_after) {
+            Some(bytes) => {
+                let reader = self.inner.get(user, path, start_pos).await?;
+                let mut reader = Truncated::new(reader, bytes);
+                Ok(tokio::io::copy(&mut reader, output).await?)
+            }
+            None => self.inner.get_into(user, path, start_pos, output).await,
+        }
+    }
+
+    async fn get<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P, start_pos: u64) -> Result<Box<dyn tokio::io::AsyncRead + Send + Sync + Unpin>> {
+        let rule = self.enter_call(Operation::Get, path.as_ref()).await?;
+        let reader = self.inner.get(user, path, start_pos).await?;
+        Ok(match rule.and_then(|rule| rule.truncate_after) {
+            Some(bytes) => Box::new(Truncated::new(reader, bytes)),
+            None => reader,
+        })
+    }
+
+    // Scripted downloads go through get_into, so that they can be delayed, failed and cut off.
+    #[cfg(not(target_family = "wasm"))]
+    async fn get_file<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<Option<std::fs::File>> {
+        if self.script.applies(Operation::Get, path.as_ref()) {
+            return Ok(None);
+        }
+        self.inner.get_file(user, path).await
+    }
+
+    async fn put<P: AsRef<Path> + Send + Debug, R: tokio::io::AsyncRead + Send + Sync + Unpin + 'static>(
+        &self,
+        user: &User,
+        input: R,
+        path: P,
+        start_pos: u64,
+    ) -> Result<u64> {
+        self.enter_call(Operation::Put, path.as_ref()).await?;
+        self.inner.put(user, input, path, start_pos).await
+    }
+
+    async fn put_unique<P: AsRef<Path> + Send + Debug, R: tokio::io::AsyncRead + Send + Sync + Unpin + 'static>(
+        &self,
+        user: &User,
+        input: R,
+        dir: P,
+    ) -> Result<(String, u64)> {
+        self.enter_call(Operation::Put, dir.as_ref()).await?;
+        self.inner.put_unique(user, input, dir).await
+    }
+
+    async fn abort_put<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.enter_call(Operation::Put, path.as_ref()).await?;
+        self.inner.abort_put(user, path).await
+    }
+
+    async fn del<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.enter_call(Operation::Del, path.as_ref()).await?;
+        self.inner.del(user, path).await
+    }
+
+    async fn mkd<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.enter_call(Operation::Mkd, path.as_ref()).await?;
+        self.inner.mkd(user, path).await
+    }
+
+    async fn rename<P: AsRef<Path> + Send + Debug>(&self, user: &User, from: P, to: P) -> Result<()> {
+        self.enter_call(Operation::Rename, from.as_ref()).await?;
+        self.inner.rename(user, from, to).await
+    }
+
+    async fn copy<P: AsRef<Path> + Send + Debug>(&self, user: &User, from: P, to: P) -> Result<()> {
+        self.enter_call(Operation::Copy, from.as_ref()).await?;
+        self.inner.copy(user, from, to).await
+    }
+
+    async fn chown<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P, uid: Option<u32>, gid: Option<u32>) -> Result<()> {
+        self.enter_call(Operation::Chown, path.as_ref()).await?;
+        self.inner.chown(user, path, uid, gid).await
+    }
+
+    async fn symlink<P: AsRef<Path> + Send + Debug>(&self, user: &User, target: P, link: P) -> Result<()> {
+        self.enter_call(Operation::Symlink, target.as_ref()).await?;
+        self.inner.symlink(user, target, link).await
+    }
+
+    async fn set_modified<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P, modified: SystemTime) -> Result<()> {
+        self.enter_call(Operation::SetModified, path.as_ref()).await?;
+        self.inner.set_modified(user, path, modified).await
+    }
+
+    async fn check_health(&self) -> Result<()> {
+        self.inner.check_health().await
+    }
+
+    async fn rmd<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.enter_call(Operation::Rmd, path.as_ref()).await?;
+        self.inner.rmd(user, path).await
+    }
+
+    async fn rmd_recursive<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.enter_call(Operation::Rmd, path.as_ref()).await?;
+        self.inner.rmd_recursive(user, path).await
+    }
+
+    async fn cwd<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.enter_call(Operation::Cwd, path.as_ref()).await?;
+        self.inner.cwd(user, path).await
+    }
+
+    async fn change_dir<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<PathBuf> {
+        self.enter_call(Operation::Cwd, path.as_ref()).await?;
+        self.inner.change_dir(user, path).await
+    }
+
+    fn current_dir(&self, user: &User) -> Option<PathBuf> {
+        self.inner.current_dir(user)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn applies_the_first_matching_rule_until_it_is_used_up() {
+        let script = Script::new();
+        script.add(
+            Rule::path("/dir/file")
+                .operation(Operation::Get)
+                .fail(ErrorKind::TransientFileNotAvailable)
+                .times(2),
+        );
+        script.add(Rule::any_path().delay(Duration::from_millis(5)));
+
+        let flaky = script.next(Operation::Get, Path::new("/dir/./file")).unwrap();
+        assert_eq!(flaky.error, Some(ErrorKind::TransientFileNotAvailable));
+        assert_eq!(
+            script.next(Operation::Get, Path::new("/dir/file")).unwrap().error,
+            Some(ErrorKind::TransientFileNotAvailable)
+        );
+        // Used up, the catch-all rule is next.
+        assert_eq!(script.next(Operation::Get, Path::new("/dir/file")).unwrap().error, None);
+        assert!(script.applies(Operation::Del, Path::new("/other")));
+
+        script.clear();
+        assert_eq!(script.next(Operation::Del, Path::new("/other")), None);
+        assert_eq!(
+            script.calls(),
+            vec![
+                (Operation::Get, PathBuf::from("/dir/file")),
+                (Operation::Get, PathBuf::from("/dir/file")),
+                (Operation::Get, PathBuf::from("/dir/file")),
+                (Operation::Del, PathBuf::from("/other")),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn cuts_off_reads() {
+        let mut reader = Truncated::new(&b"hello world"[..], 5);
+        let mut buf = Vec::new();
+        let err = reader.read_to_end(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(buf, b"hello");
+
+        // Shorter files end normally.
+        let mut reader = Truncated::new(&b"hi"[..], 5);
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"hi");
+    }
+}
@@ -1,8 +1,8 @@
 //! Defines the service provider interface for storage back-end implementors.
 
 use super::error::Error;
-use crate::auth::UserDetail;
 use crate::storage::ErrorKind;
+use crate::{auth::UserDetail, server::encoding, SessionContext};
 use async_trait::async_trait;
 use chrono::{
     prelude::{DateTime, Utc},
@@ -12,7 +12,7 @@ use md5::{Digest, Md5};
 use std::{
     fmt::{self, Debug, Formatter, Write},
     io,
-    path::Path,
+    path::{Path, PathBuf},
     result,
     time::SystemTime,
 };
@@ -23,10 +23,26 @@ use tokio::io::AsyncReadExt;
 pub const FEATURE_RESTART: u32 = 0b0000_0001;
 /// Whether or not this storage backend supports the SITE MD5 command
 pub const FEATURE_SITEMD5: u32 = 0b0000_0010;
+/// Tells that the storage back-end makes uploads atomic by itself i.e. that a file only appears
+/// under its final name once it has been stored completely. See
+/// [`AtomicUploads`](crate::storage::AtomicUploads).
+pub const FEATURE_ATOMIC_UPLOADS: u32 = 0b0000_0100;
+/// Whether or not this storage backend supports the SITE CHOWN command, i.e. implements
+/// [`StorageBackend::chown`].
+pub const FEATURE_CHOWN: u32 = 0b0000_1000;
+/// Whether or not this storage backend supports the SITE SYMLINK command, i.e. implements
+/// [`StorageBackend::symlink`].
+pub const FEATURE_SYMLINK: u32 = 0b0001_0000;
 
 /// Result type used by traits in this module
 pub type Result<T> = result::Result<T, Error>;
 
+/// Generates a random file name, as used for the `STOU` command by the default implementation of
+/// [`StorageBackend::put_unique`]. Back-ends can use it to generate candidate names of their own.
+pub fn unique_file_name() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
 /// Represents the metadata of a _FTP File_
 pub trait Metadata {
     /// Returns the length (size) of the file in bytes.
@@ -70,6 +86,29 @@ pub trait Metadata {
     fn readlink(&self) -> Option<&Path> {
         None
     }
+
+    /// Returns properties of the file that are specific to the storage back-end, like the
+    /// generation of a cloud storage object or the inode of a local file, as pairs of a name and a
+    /// value. They are reported to data listeners that ask for them, see
+    /// [`DataListener::file_attributes`](crate::notification::DataListener::file_attributes). The
+    /// default implementation returns none.
+    fn attributes(&self) -> Vec<(String, String)> {
+        vec![]
+    }
+
+    /// Returns an identifier that stays the same for as long as the file is the same file, also
+    /// when it is renamed, and that no other file has at the same time. It is the `unique` fact of
+    /// RFC 3659, which lets sync clients detect renames instead of downloading the file again. The
+    /// default implementation returns `None`, meaning that the back-end can't tell.
+    fn unique_id(&self) -> Option<String> {
+        None
+    }
+
+    /// Returns the time the file was created, its birth time, which is the `create` fact of RFC
+    /// 3659. The default implementation returns `None`, meaning that the back-end doesn't know.
+    fn created(&self) -> Option<SystemTime> {
+        None
+    }
 }
 
 /// Represents the permissions of a _FTP File_
@@ -133,7 +172,7 @@ where
             .unwrap_or_else(|_| "--- -- --:--".to_string());
         let basename = self.path.as_ref().components().last();
         let path = match basename {
-            Some(v) => v.as_os_str().to_string_lossy(),
+            Some(v) => encoding::path_to_text(v.as_os_str()),
             None => {
                 return Err(std::fmt::Error);
             }
@@ -141,7 +180,7 @@ where
         let perms = format!("{}", self.metadata.permissions());
         let link_target = if self.metadata.is_symlink() {
             match self.metadata.readlink() {
-                Some(t) => format!(" -> {}", t.display()),
+                Some(t) => format!(" -> {}", encoding::path_to_text(t.as_os_str())),
                 None => {
                     // We ought to log an error here, but don't have access to the logger variable
                     "".to_string()
@@ -174,6 +213,13 @@ where
 /// The `StorageBackend` trait can be implemented to create custom FTP virtual file systems. Once
 /// implemented it needs to be registered with the [`Server`] on construction.
 ///
+/// File content only goes in and out through [`AsyncRead`](tokio::io::AsyncRead) and
+/// [`AsyncWrite`](tokio::io::AsyncWrite), so that back-ends don't have to be backed by local files.
+/// The one exception, [`get_file`](Self::get_file), isn't part of the trait on WebAssembly targets.
+/// Note that this only keeps the trait free of file descriptors: libunftp itself doesn't build for
+/// targets like `wasm32-wasip1` yet, since the server needs tokio's `net`, `process` and `fs`
+/// features, nix and socket2.
+///
 /// [`Server`]: ../struct.Server.html
 #[async_trait]
 pub trait StorageBackend<User: UserDetail>: Send + Sync + Debug {
@@ -188,6 +234,25 @@ pub trait StorageBackend<User: UserDetail>: Send + Sync + Debug {
         Ok(())
     }
 
+    /// Hands the back-end the [`SessionContext`](crate::SessionContext) of the session it was
+    /// created for, before the session starts. It tells the address of the client, the working
+    /// directory and whether TLS is in use, for auditing or per-connection behaviour. Its
+    /// [id](crate::SessionContext::id) is logged by libunftp as the `trace-id` field, so a back-end
+    /// that logs it too can have its log entries correlated with those of the session. This default
+    /// implementation ignores it.
+    fn set_session_context(&mut self, _context: SessionContext) {}
+
+    /// Asks for a scratch directory for each session, for instance to stage uploads or to spill
+    /// data that doesn't fit in memory. Return the local directory to create them in. libunftp
+    /// creates a directory that only the server process may access beneath it before the session
+    /// starts, hands it over with the [`SessionContext`](crate::SessionContext::scratch_dir), and
+    /// removes it with everything in it when the session ends. Pick a directory on the same file
+    /// system as the files of the back-end to be able to rename files into place. This default
+    /// implementation asks for none.
+    fn session_scratch(&self) -> Option<PathBuf> {
+        None
+    }
+
     /// Implement to set the name of the storage back-end. By default it returns the type signature.
     fn name(&self) -> &str {
         std::any::type_name::<Self>()
@@ -204,6 +269,22 @@ pub trait StorageBackend<User: UserDetail>: Send + Sync + Debug {
     /// [`Metadata`]: ./trait.Metadata.html
     async fn metadata<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<Self::Metadata>;
 
+    /// Returns the `Metadata` for each of the given files, in the same order as the given paths.
+    ///
+    /// The default implementation calls [`metadata`](StorageBackend::metadata) for one path after
+    /// the other. Back-ends that can look up many files at once (concurrently or with a single
+    /// remote request) should override it.
+    async fn metadata_many<P>(&self, user: &User, paths: Vec<P>) -> Vec<Result<Self::Metadata>>
+    where
+        P: AsRef<Path> + Send + Debug,
+    {
+        let mut result = Vec::with_capacity(paths.len());
+        for path in paths {
+            result.push(self.metadata(user, path).await);
+        }
+        result
+    }
+
     /// Returns the MD5 hash for the given file.
     ///
     /// Whether or not you want to implement the md5 method yourself,
@@ -283,11 +364,7 @@ pub trait StorageBackend<User: UserDetail>: Send + Sync + Debug {
         let list = self.list(user, path).await.map_err(|_| std::io::Error::from(std::io::ErrorKind::Other))?;
 
         let buffer = list.iter().fold(String::new(), |mut buf, fi| {
-            let _ = write!(
-                buf,
-                "{}\r\n",
-                fi.path.file_name().unwrap_or_else(|| std::ffi::OsStr::new("")).to_str().unwrap_or("")
-            );
+            let _ = write!(buf, "{}\r\n", encoding::path_to_text(fi.path.file_name().unwrap_or_default()));
             buf
         });
 
@@ -315,6 +392,18 @@ pub trait StorageBackend<User: UserDetail>: Send + Sync + Debug {
     /// from supported_features yield 1 if a logical and operation is applied with FEATURE_RESTART.
     async fn get<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P, start_pos: u64) -> Result<Box<dyn tokio::io::AsyncRead + Send + Sync + Unpin>>;
 
+    /// Returns the given file as a file on the local filesystem, if the back-end stores it as such.
+    /// Downloads of these files over unencrypted data connections are sent with `sendfile(2)` on
+    /// Linux, so that the data doesn't have to be copied through libunftp. Back-ends that can't
+    /// provide a local file return `Ok(None)`, which is what the default implementation does, and
+    /// [`get_into`](Self::get_into) is used instead.
+    ///
+    /// Not available on WebAssembly targets.
+    #[cfg(not(target_family = "wasm"))]
+    async fn get_file<P: AsRef<Path> + Send + Debug>(&self, _user: &User, _path: P) -> Result<Option<std::fs::File>> {
+        Ok(None)
+    }
+
     /// Writes bytes from the given reader to the specified path starting at offset start_pos in the file
     async fn put<P: AsRef<Path> + Send + Debug, R: tokio::io::AsyncRead + Send + Sync + Unpin + 'static>(
         &self,
@@ -324,6 +413,35 @@ pub trait StorageBackend<User: UserDetail>: Send + Sync + Debug {
         start_pos: u64,
     ) -> Result<u64>;
 
+    /// Writes bytes from the given reader to a new file with a unique name in the given directory and
+    /// returns the chosen name along with the amount of bytes written.
+    ///
+    /// For the `STOU` command libunftp calls this with empty input to claim a name that it can
+    /// announce to the client before the transfer starts, and then uploads the data to that file with
+    /// [put](StorageBackend::put).
+    ///
+    /// The default implementation picks a name with [`unique_file_name`] and calls
+    /// [put](StorageBackend::put). Back-ends that can create files exclusively should implement
+    /// this to guarantee that no existing file is overwritten.
+    async fn put_unique<P: AsRef<Path> + Send + Debug, R: tokio::io::AsyncRead + Send + Sync + Unpin + 'static>(
+        &self,
+        user: &User,
+        input: R,
+        dir: P,
+    ) -> Result<(String, u64)> {
+        let name = unique_file_name();
+        let bytes = self.put(user, input, dir.as_ref().join(&name), 0).await?;
+        Ok((name, bytes))
+    }
+
+    /// Called when the client aborted an upload to the given path with `ABOR` while
+    /// [put](StorageBackend::put) was in progress. The `put` future has been dropped at that point.
+    /// Back-ends that upload in parts can implement this to cancel the upload. The default
+    /// implementation does nothing.
+    async fn abort_put<P: AsRef<Path> + Send + Debug>(&self, _user: &User, _path: P) -> Result<()> {
+        Ok(())
+    }
+
     /// Deletes the file at the given path.
     async fn del<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()>;
 
@@ -333,11 +451,106 @@ pub trait StorageBackend<User: UserDetail>: Send + Sync + Debug {
     /// Renames the given file to the given new filename.
     async fn rename<P: AsRef<Path> + Send + Debug>(&self, user: &User, from: P, to: P) -> Result<()>;
 
+    /// Copies the given file to the given new filename. Used for the `SITE CPFR` and `SITE CPTO`
+    /// commands.
+    ///
+    /// The default implementation streams the file through libunftp with [get](StorageBackend::get)
+    /// and [put](StorageBackend::put). Back-ends that can copy files without moving the data should
+    /// implement this.
+    async fn copy<P: AsRef<Path> + Send + Debug>(&self, user: &User, from: P, to: P) -> Result<()> {
+        let reader = self.get(user, from, 0).await?;
+        self.put(user, reader, to, 0).await.map(|_| ())
+    }
+
+    /// Changes the owner and/or the group of the given file to the given system user and group ids,
+    /// leaving out the ones that are `None`. Used for the `SITE CHOWN` command, which is only
+    /// available if the back-end advertises [`FEATURE_CHOWN`]. The default implementation returns
+    /// [`ErrorKind::CommandNotImplemented`].
+    async fn chown<P: AsRef<Path> + Send + Debug>(&self, _user: &User, _path: P, _uid: Option<u32>, _gid: Option<u32>) -> Result<()> {
+        Err(Error::from(ErrorKind::CommandNotImplemented))
+    }
+
+    /// Creates a symbolic link at `link` that points to `target`. The target is passed as the client
+    /// gave it, so a relative target is relative to the directory of the link. Used for the
+    /// `SITE SYMLINK` command, which is only available if the back-end advertises [`FEATURE_SYMLINK`].
+    /// The default implementation returns [`ErrorKind::CommandNotImplemented`].
+    async fn symlink<P: AsRef<Path> + Send + Debug>(&self, _user: &User, _target: P, _link: P) -> Result<()> {
+        Err(Error::from(ErrorKind::CommandNotImplemented))
+    }
+
+    /// Sets the modification time of the given file. Used for the setter form of the `MDTM`
+    /// command (`MDTM YYYYMMDDHHMMSS path`) that some clients use to preserve the times of the
+    /// files they upload. The default implementation returns [`ErrorKind::CommandNotImplemented`].
+    async fn set_modified<P: AsRef<Path> + Send + Debug>(&self, _user: &User, _path: P, _modified: SystemTime) -> Result<()> {
+        Err(Error::from(ErrorKind::CommandNotImplemented))
+    }
+
+    /// Tells if the storage back-end is able to serve requests, for instance if the directory or
+    /// the remote service it stores files in can be reached. Used by the
+    /// [`HealthCheck`](crate::HealthCheck) of the server. The default implementation always
+    /// succeeds.
+    async fn check_health(&self) -> Result<()> {
+        Ok(())
+    }
+
     /// Deletes the given directory.
     async fn rmd<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()>;
 
+    /// Deletes the given directory with everything in it, for the `SITE RMDIR -R` command.
+    ///
+    /// The default implementation walks the tree with [list](StorageBackend::list), deletes the
+    /// files and symbolic links with [del](StorageBackend::del) and then the directories, deepest
+    /// first, with [rmd](StorageBackend::rmd). It doesn't descend into linked directories, but
+    /// that relies on the metadata reporting links as links. It stops at the first error, leaving
+    /// what wasn't deleted yet.
+    /// Back-ends that can delete a whole tree at once, like object stores that can delete
+    /// everything under a prefix, should override it.
+    async fn rmd_recursive<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        let mut pending = vec![path.as_ref().to_path_buf()];
+        let mut dirs = vec![];
+        while let Some(dir) = pending.pop() {
+            for entry in self.list(user, &dir).await? {
+                let entry_path = dir.join(&entry.path);
+                if entry.metadata.is_dir() && !entry.metadata.is_symlink() {
+                    pending.push(entry_path);
+                } else {
+                    self.del(user, entry_path).await?;
+                }
+            }
+            dirs.push(dir);
+        }
+        for dir in dirs.into_iter().rev() {
+            self.rmd(user, dir).await?;
+        }
+        Ok(())
+    }
+
     /// Changes the working directory to the given path.
     async fn cwd<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()>;
+
+    /// Changes the working directory for the `CWD` and `CDUP` commands and returns the new working
+    /// directory, which libunftp then resolves the paths of later commands against. The path is the
+    /// one asked for, joined with the current working directory.
+    ///
+    /// Back-ends that keep a working directory of their own, for instance because they `chdir` on
+    /// a stateful connection, can implement this to change it and to return the directory they
+    /// actually ended up in, e.g. with symbolic links resolved. Together with
+    /// [current_dir](StorageBackend::current_dir) this makes the working directory the back-end's.
+    ///
+    /// The default implementation checks the path with [cwd](StorageBackend::cwd) and returns it
+    /// as is, leaving the working directory a virtual one kept by libunftp.
+    async fn change_dir<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<PathBuf> {
+        self.cwd(user, path.as_ref()).await?;
+        Ok(path.as_ref().to_path_buf())
+    }
+
+    /// Returns the working directory to report to the client for the `PWD` command, for back-ends
+    /// that keep a working directory of their own, see [change_dir](StorageBackend::change_dir).
+    /// The default implementation returns None, meaning the working directory kept by libunftp is
+    /// reported.
+    fn current_dir(&self, _user: &User) -> Option<PathBuf> {
+        None
+    }
 }
 
 // Maps IO errors to FTP errors in a sensible way.
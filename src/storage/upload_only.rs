@@ -0,0 +1,230 @@
+//! A [`StorageBackend`] that wraps another one and refuses to read from it according to the
+//! [`AccessMode`](crate::options::AccessMode) option, so that a drop box can't be read back through
+//! any back-end, whatever the command that asks.
+
+use super::{Error, ErrorKind, Fileinfo, Metadata, Result, StorageBackend};
+use crate::{auth::UserDetail, options::AccessMode, SessionContext};
+use async_trait::async_trait;
+use std::{
+    fmt::Debug,
+    io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+// Wraps the storage back-end. In upload-only mode reading files and listing directories fails
+// with PermissionDenied and only the metadata of directories is handed out, so that clients can
+// still change into them. Otherwise every call is passed on to the inner back-end as is.
+#[derive(Debug)]
+pub(crate) struct UploadOnlyFilter<Storage> {
+    inner: Storage,
+    access_mode: AccessMode,
+}
+
+impl<Storage> UploadOnlyFilter<Storage> {
+    pub fn new(inner: Storage, access_mode: AccessMode) -> Self {
+        UploadOnlyFilter { inner, access_mode }
+    }
+
+    fn upload_only<User: UserDetail>(&self, user: &User) -> bool {
+        user.access_mode().unwrap_or(self.access_mode) == AccessMode::UploadOnly
+    }
+
+    // Fails if reading is not allowed for the user.
+    fn check_read<User: UserDetail>(&self, user: &User) -> Result<()> {
+        if self.upload_only(user) {
+            return Err(Error::from(ErrorKind::PermissionDenied));
+        }
+        Ok(())
+    }
+
+    fn check_metadata<User: UserDetail, M: Metadata>(&self, user: &User, metadata: Result<M>) -> Result<M> {
+        match metadata {
+            Ok(metadata) if self.upload_only(user) && !metadata.is_dir() => Err(Error::from(ErrorKind::PermissionDenied)),
+            metadata => metadata,
+        }
+    }
+}
+
+#[async_trait]
+impl<Storage, User> StorageBackend<User> for UploadOnlyFilter<Storage>
+where
+    Storage: StorageBackend<User>,
+    User: UserDetail,
+{
+    type Metadata = Storage::Metadata;
+
+    fn enter(&mut self, user_detail: &User) -> io::Result<()> {
+        self.inner.enter(user_detail)
+    }
+
+    fn set_session_context(&mut self, context: SessionContext) {
+        self.inner.set_session_context(context)
+    }
+
+    fn session_scratch(&self) -> Option<PathBuf> {
+        self.inner.session_scratch()
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn supported_features(&self) -> u32 {
+        self.inner.supported_features()
+    }
+
+    async fn metadata<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<Self::Metadata> {
+        self.check_metadata(user, self.inner.metadata(user, path).await)
+    }
+
+    async fn metadata_many<P>(&self, user: &User, paths: Vec<P>) -> Vec<Result<Self::Metadata>>
+    where
+        P: AsRef<Path> + Send + Debug,
+    {
+        let result = self.inner.metadata_many(user, paths).await;
+        result.into_iter().map(|metadata| self.check_metadata(user, metadata)).collect()
+    }
+
+    async fn md5<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<String>
+    where
+        P: AsRef<Path> + Send + Debug,
+    {
+        self.check_read(user)?;
+        self.inner.md5(user, path).await
+    }
+
+    async fn list<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<Vec<Fileinfo<PathBuf, Self::Metadata>>>
+    where
+        <Self as StorageBackend<User>>::Metadata: Metadata,
+    {
+        self.check_read(user)?;
+        self.inner.list(user, path).await
+    }
+
+    async fn list_fmt<P>(&self, user: &User, path: P) -> std::result::Result<io::Cursor<Vec<u8>>, Error>
+    where
+        P: AsRef<Path> + Send + Debug,
+        Self::Metadata: Metadata + 'static,
+    {
+        self.check_read(user)?;
+        self.inner.list_fmt(user, path).await
+    }
+
+    async fn list_vec<P>(&self, user: &User, path: P) -> std::result::Result<Vec<String>, Error>
+    where
+        P: AsRef<Path> + Send + Debug,
+        Self::Metadata: Metadata + 'static,
+    {
+        self.check_read(user)?;
+        self.inner.list_vec(user, path).await
+    }
+
+    async fn nlst<P>(&self, user: &User, path: P) -> std::result::Result<io::Cursor<Vec<u8>>, io::Error>
+    where
+        P: AsRef<Path> + Send + Debug,
+        Self::Metadata: Metadata + 'static,
+    {
+        if self.upload_only(user) {
+            return Err(io::Error::from(io::ErrorKind::PermissionDenied));
+        }
+        self.inner.nlst(user, path).await
+    }
+
+    async fn get_into<'a, P, W: ?Sized>(&self, user: &User, path: P, start_pos: u64, output: &'a mut W) -> Result<u64>
+    where
+        W: tokio::io::AsyncWrite + Unpin + Sync + Send,
+        P: AsRef<Path> + Send + Debug,
+    {
+        self.check_read(user)?;
+        self.inner.get_into(user, path, start_pos, output).await
+    }
+
+    async fn get<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P, start_pos: u64) -> Result<Box<dyn tokio::io::AsyncRead + Send + Sync + Unpin>> {
+        self.check_read(user)?;
+        self.inner.get(user, path, start_pos).await
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    async fn get_file<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<Option<std::fs::File>> {
+        self.check_read(user)?;
+        self.inner.get_file(user, path).await
+    }
+
+    async fn put<P: AsRef<Path> + Send + Debug, R: tokio::io::AsyncRead + Send + Sync + Unpin + 'static>(
+        &self,
+        user: &User,
+        input: R,
+        path: P,
+        start_pos: u64,
+    ) -> Result<u64> {
+        self.inner.put(user, input, path, start_pos).await
+    }
+
+    async fn put_unique<P: AsRef<Path> + Send + Debug, R: tokio::io::AsyncRead + Send + Sync + Unpin + 'static>(
+        &self,
+        user: &User,
+        input: R,
+        dir: P,
+    ) -> Result<(String, u64)> {
+        self.inner.put_unique(user, input, dir).await
+    }
+
+    async fn abort_put<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.inner.abort_put(user, path).await
+    }
+
+    async fn del<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.inner.del(user, path).await
+    }
+
+    async fn mkd<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.inner.mkd(user, path).await
+    }
+
+    async fn rename<P: AsRef<Path> + Send + Debug>(&self, user: &User, from: P, to: P) -> Result<()> {
+        self.inner.rename(user, from, to).await
+    }
+
+    // A copy would make a file readable under a name of the client's choosing.
+    async fn copy<P: AsRef<Path> + Send + Debug>(&self, user: &User, from: P, to: P) -> Result<()> {
+        self.check_read(user)?;
+        self.inner.copy(user, from, to).await
+    }
+
+    async fn chown<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P, uid: Option<u32>, gid: Option<u32>) -> Result<()> {
+        self.inner.chown(user, path, uid, gid).await
+    }
+
+    async fn symlink<P: AsRef<Path> + Send + Debug>(&self, user: &User, target: P, link: P) -> Result<()> {
+        self.inner.symlink(user, target, link).await
+    }
+
+    async fn set_modified<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P, modified: SystemTime) -> Result<()> {
+        self.inner.set_modified(user, path, modified).await
+    }
+
+    async fn check_health(&self) -> Result<()> {
+        self.inner.check_health().await
+    }
+
+    async fn rmd<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.inner.rmd(user, path).await
+    }
+
+    async fn rmd_recursive<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.inner.rmd_recursive(user, path).await
+    }
+
+    async fn cwd<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        self.inner.cwd(user, path).await
+    }
+
+    async fn change_dir<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<PathBuf> {
+        self.inner.change_dir(user, path).await
+    }
+
+    fn current_dir(&self, user: &User) -> Option<PathBuf> {
+        self.inner.current_dir(user)
+    }
+}
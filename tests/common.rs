@@ -58,7 +58,7 @@ impl Authenticator<DefaultUser> for TestAuthenticator {
     async fn authenticate(&self, username: &str, creds: &Credentials) -> Result<DefaultUser, AuthenticationError> {
         return match (username, &creds.password) {
             ("test" | "testpol", Some(pwd)) => {
-                if pwd == "test" {
+                if pwd.expose_secret() == "test" {
                     Ok(DefaultUser {})
                 } else {
                     Err(AuthenticationError::BadPassword)
@@ -0,0 +1,58 @@
+#![allow(missing_docs)]
+
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::net::TcpStream;
+use unftp_sbe_fs::ServerExt;
+
+// Connects and returns the first reply along with the connection, so that the session stays open.
+async fn connect(port: u16) -> (String, BufReader<TcpStream>) {
+    let mut attempts = 0;
+    let stream = loop {
+        match TcpStream::connect(("127.0.0.1", port)).await {
+            Ok(stream) => break stream,
+            Err(err) if attempts > 20 => panic!("{}", err),
+            Err(_) => {
+                attempts += 1;
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            }
+        }
+    };
+    let mut stream = BufReader::new(stream);
+    let mut line = String::new();
+    stream.read_line(&mut line).await.unwrap();
+    (line, stream)
+}
+
+#[tokio::test]
+async fn applies_changed_options_to_new_sessions() {
+    let server = libunftp::Server::with_fs(std::env::temp_dir())
+        .greeting("Welcome")
+        .max_connections(1)
+        .build()
+        .unwrap();
+    let config = server.config_handle();
+    tokio::spawn(server.listen("127.0.0.1:2175"));
+
+    let (reply, _first) = connect(2175).await;
+    assert_eq!(reply, "220 Welcome\r\n");
+    let (reply, _) = connect(2175).await;
+    assert_eq!(reply, "421 Too many connections, try again later\r\n");
+
+    config.set_max_connections(None);
+    config.set_greeting("Busy today");
+    let (reply, _second) = connect(2175).await;
+    assert_eq!(reply, "220 Busy today\r\n");
+}
+
+#[test]
+fn refuses_empty_passive_port_ranges() {
+    #[allow(clippy::reversed_empty_ranges)]
+    let inverted = 50010..50000;
+    assert!(libunftp::Server::with_fs(std::env::temp_dir()).passive_ports(inverted.clone()).build().is_err());
+
+    let server = libunftp::Server::with_fs(std::env::temp_dir()).passive_ports(50000..50010).build().unwrap();
+    let config = server.config_handle();
+    assert!(config.set_passive_ports(50000..50000).is_err());
+    assert!(config.set_passive_ports(inverted).is_err());
+    assert!(config.set_passive_ports(50010..50020).is_ok());
+}
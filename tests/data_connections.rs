@@ -0,0 +1,41 @@
+#![allow(missing_docs)]
+
+// With DataConnectionSource::SameIp only the client that asked for the passive port may connect to it.
+
+use libunftp::options::DataConnectionSource;
+use std::net::{Ipv4Addr, SocketAddr};
+use tempfile::TempDir;
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpSocket;
+use unftp_sbe_fs::ServerExt;
+use unftp_test_util::Harness;
+
+#[tokio::test]
+async fn connections_from_other_addresses_do_not_take_the_data_port() {
+    let root = TempDir::new().unwrap();
+    std::fs::write(root.path().join("file.txt"), b"hello").unwrap();
+    let harness = Harness::start(libunftp::Server::with_fs(root.path().to_path_buf()).data_connection_source(DataConnectionSource::SameIp))
+        .await
+        .unwrap();
+    let mut client = harness.login("anonymous", "anonymous").await.unwrap();
+
+    let reply = client.cmd("PASV").await.unwrap();
+    assert_eq!(reply.code, 227);
+    let numbers: Vec<u16> = reply.text().split(|c: char| !c.is_ascii_digit()).filter_map(|n| n.parse().ok()).collect();
+    let port = numbers[numbers.len() - 2] * 256 + numbers[numbers.len() - 1];
+
+    // The client is on 127.0.0.1, so the server drops a connection from elsewhere in the loopback range.
+    let foreign = TcpSocket::new_v4().unwrap();
+    foreign.bind(SocketAddr::from((Ipv4Addr::new(127, 0, 0, 2), 0))).unwrap();
+    let mut foreign = foreign.connect(SocketAddr::from((Ipv4Addr::LOCALHOST, port))).await.unwrap();
+    let mut buf = [0; 1];
+    assert!(matches!(foreign.read(&mut buf).await, Ok(0) | Err(_)));
+
+    // The data port still waits for the client itself.
+    let mut data = client.connect_pasv(&reply).await.unwrap();
+    assert_eq!(client.cmd("RETR file.txt").await.unwrap().code, 150);
+    let mut content = Vec::new();
+    data.read_to_end(&mut content).await.unwrap();
+    assert_eq!(content, b"hello");
+    assert_eq!(client.reply().await.unwrap().code, 226);
+}
@@ -0,0 +1,171 @@
+#![allow(missing_docs)]
+#![cfg(feature = "ftps")]
+
+// FTPS on the control and data channels: AUTH TLS, PBSZ, PROT and CCC.
+
+use libunftp::auth::DefaultUser;
+use libunftp::ServerBuilder;
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, RootCertStore};
+use std::sync::Arc;
+use tempfile::TempDir;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::TlsConnector;
+use unftp_sbe_fs::{Filesystem, ServerExt};
+use unftp_test_util::Harness;
+
+type Builder = ServerBuilder<Filesystem, DefaultUser>;
+
+// A server with FTPS and a client that trusts its certificate. The directory holds the certificate
+// and the files that the server serves.
+struct Ftps {
+    harness: Harness,
+    connector: TlsConnector,
+    _root: TempDir,
+}
+
+async fn start(configure: fn(Builder) -> Builder) -> Ftps {
+    let root = TempDir::new().unwrap();
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    std::fs::write(root.path().join("server.certs"), certified.cert.pem()).unwrap();
+    std::fs::write(root.path().join("server.key"), certified.key_pair.serialize_pem()).unwrap();
+    std::fs::write(root.path().join("file.txt"), b"hello").unwrap();
+    let builder = libunftp::Server::with_fs(root.path().to_path_buf()).ftps(root.path().join("server.certs"), root.path().join("server.key"));
+    let harness = Harness::start(configure(builder)).await.unwrap();
+
+    let mut roots = RootCertStore::empty();
+    roots.add(certified.cert.der().clone()).unwrap();
+    let config = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::aws_lc_rs::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ftps {
+        harness,
+        connector: TlsConnector::from(Arc::new(config)),
+        _root: root,
+    }
+}
+
+// Reads a reply, all lines of it.
+async fn reply<S: AsyncRead + Unpin>(stream: &mut BufReader<S>) -> String {
+    let mut reply = String::new();
+    loop {
+        let start = reply.len();
+        stream.read_line(&mut reply).await.unwrap();
+        let line = &reply[start..];
+        if line.len() < 4 || line.as_bytes()[3] == b' ' {
+            return reply;
+        }
+    }
+}
+
+// Sends a command and returns the reply.
+async fn cmd<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut BufReader<S>, command: &str) -> String {
+    stream.get_mut().write_all(format!("{}\r\n", command).as_bytes()).await.unwrap();
+    reply(stream).await
+}
+
+impl Ftps {
+    async fn tls<S: AsyncRead + AsyncWrite + Unpin>(&self, stream: S) -> TlsStream<S> {
+        self.connector.connect(ServerName::try_from("localhost").unwrap(), stream).await.unwrap()
+    }
+
+    // Connects, secures the control channel with AUTH TLS and logs in.
+    async fn secure_login(&self) -> BufReader<TlsStream<TcpStream>> {
+        let mut plain = BufReader::new(TcpStream::connect(self.harness.addr()).await.unwrap());
+        assert!(reply(&mut plain).await.starts_with("220"));
+        assert!(cmd(&mut plain, "AUTH TLS").await.starts_with("234"));
+        let mut control = BufReader::new(self.tls(plain.into_inner()).await);
+        assert!(cmd(&mut control, "USER anonymous").await.starts_with("331"));
+        assert!(cmd(&mut control, "PASS anonymous").await.starts_with("230"));
+        control
+    }
+
+    // Connects to the port of a 227 reply to PASV.
+    async fn connect_pasv(&self, reply: &str) -> TcpStream {
+        assert!(reply.starts_with("227"), "{}", reply);
+        let numbers: Vec<u16> = reply[4..].split(|c: char| !c.is_ascii_digit()).filter_map(|n| n.parse().ok()).collect();
+        let port = numbers[numbers.len() - 2] * 256 + numbers[numbers.len() - 1];
+        TcpStream::connect((self.harness.addr().ip(), port)).await.unwrap()
+    }
+}
+
+#[tokio::test]
+async fn ccc_clears_the_control_channel_but_keeps_the_data_channel_protected() {
+    let ftps = start(|builder| builder).await;
+    let mut control = ftps.secure_login().await;
+    assert!(cmd(&mut control, "PBSZ 0").await.starts_with("200"));
+    assert!(cmd(&mut control, "PROT P").await.starts_with("200"));
+    assert!(cmd(&mut control, "CCC").await.starts_with("200"));
+
+    // The server sends its close_notify after the reply and waits for the one of the client.
+    let mut tls = control.into_inner();
+    assert_eq!(tls.read(&mut [0; 1]).await.unwrap(), 0);
+    tls.get_mut().1.send_close_notify();
+    tls.flush().await.unwrap();
+    let mut control = BufReader::new(tls.into_inner().0);
+
+    assert_eq!(cmd(&mut control, "PWD").await, "257 \"/\"\r\n");
+    let pasv = cmd(&mut control, "PASV").await;
+    let data = ftps.connect_pasv(&pasv).await;
+    // The server starts TLS on the data connection once it has a transfer for it.
+    assert!(cmd(&mut control, "RETR file.txt").await.starts_with("150"));
+    let mut data = ftps.tls(data).await;
+    let mut content = Vec::new();
+    data.read_to_end(&mut content).await.unwrap();
+    assert_eq!(content, b"hello");
+    assert!(reply(&mut control).await.starts_with("226"));
+}
+
+#[tokio::test]
+async fn ccc_needs_a_secure_control_channel() {
+    let ftps = start(|builder| builder).await;
+    let mut client = ftps.harness.login("anonymous", "anonymous").await.unwrap();
+    assert_eq!(client.cmd("CCC").await.unwrap().code, 533);
+    assert_eq!(client.cmd("NOOP").await.unwrap().code, 200);
+}
+
+#[tokio::test]
+async fn prot_needs_pbsz_first() {
+    let ftps = start(|builder| builder).await;
+    let mut control = ftps.secure_login().await;
+    assert!(cmd(&mut control, "PROT P").await.starts_with("503"));
+    assert!(cmd(&mut control, "PBSZ 0").await.starts_with("200"));
+    assert!(cmd(&mut control, "PROT P").await.starts_with("200"));
+}
+
+#[tokio::test]
+async fn pbsz_and_prot_need_a_secure_control_channel() {
+    let ftps = start(|builder| builder).await;
+    let mut client = ftps.harness.login("anonymous", "anonymous").await.unwrap();
+    assert_eq!(client.cmd("PBSZ 0").await.unwrap().code, 503);
+    assert_eq!(client.cmd("PROT P").await.unwrap().code, 503);
+    assert_eq!(client.cmd("PROT C").await.unwrap().code, 503);
+}
+
+#[tokio::test]
+async fn required_data_channel_protection_refuses_clear_transfers() {
+    let ftps = start(|builder| builder.ftps_required(false, true)).await;
+
+    let mut client = ftps.harness.login("anonymous", "anonymous").await.unwrap();
+    assert_eq!(client.cmd("PASV").await.unwrap().code, 534);
+    assert_eq!(client.cmd("RETR file.txt").await.unwrap().code, 534);
+
+    let mut control = ftps.secure_login().await;
+    assert!(cmd(&mut control, "PASV").await.starts_with("534"));
+    assert!(cmd(&mut control, "PBSZ 0").await.starts_with("200"));
+    assert!(cmd(&mut control, "PROT C").await.starts_with("200"));
+    assert!(cmd(&mut control, "PASV").await.starts_with("534"));
+    assert!(cmd(&mut control, "PROT P").await.starts_with("200"));
+    let pasv = cmd(&mut control, "PASV").await;
+    let data = ftps.connect_pasv(&pasv).await;
+    assert!(cmd(&mut control, "RETR file.txt").await.starts_with("150"));
+    let mut data = ftps.tls(data).await;
+    let mut content = Vec::new();
+    data.read_to_end(&mut content).await.unwrap();
+    assert_eq!(content, b"hello");
+    assert!(reply(&mut control).await.starts_with("226"));
+}
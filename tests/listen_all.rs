@@ -0,0 +1,84 @@
+#![allow(missing_docs)]
+
+use libunftp::options::ListenerOptions;
+#[cfg(feature = "ftps")]
+use libunftp::options::TlsFirst;
+#[cfg(feature = "ftps")]
+use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::net::TcpStream;
+use unftp_sbe_fs::ServerExt;
+
+async fn connect(port: u16) -> BufReader<TcpStream> {
+    let mut attempts = 0;
+    let stream = loop {
+        match TcpStream::connect(("127.0.0.1", port)).await {
+            Ok(stream) => break stream,
+            Err(err) if attempts > 20 => panic!("{}", err),
+            Err(_) => {
+                attempts += 1;
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            }
+        }
+    };
+    BufReader::new(stream)
+}
+
+async fn reply(stream: &mut BufReader<TcpStream>) -> String {
+    let mut line = String::new();
+    stream.read_line(&mut line).await.unwrap();
+    line
+}
+
+async fn greeting(port: u16) -> String {
+    reply(&mut connect(port).await).await
+}
+
+#[tokio::test]
+async fn listens_on_every_address_with_its_own_options() {
+    let server = libunftp::Server::with_fs(std::env::temp_dir()).greeting("Welcome").build().unwrap();
+    tokio::spawn(server.listen_all(vec![
+        ListenerOptions::new("127.0.0.1:2170"),
+        ListenerOptions::new("127.0.0.1:2171").greeting("Internal"),
+    ]));
+
+    assert_eq!(greeting(2170).await, "220 Welcome\r\n");
+    assert_eq!(greeting(2171).await, "220 Internal\r\n");
+}
+
+#[cfg(feature = "ftps")]
+#[tokio::test]
+async fn requires_auth_tls_first_on_the_listeners_that_ask_for_it() {
+    let dir = std::env::temp_dir().join(format!("libunftp-tls-first-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    std::fs::write(dir.join("server.certs"), certified.cert.pem()).unwrap();
+    std::fs::write(dir.join("server.key"), certified.key_pair.serialize_pem()).unwrap();
+    let server = libunftp::Server::with_fs(dir.clone())
+        .ftps(dir.join("server.certs"), dir.join("server.key"))
+        .build()
+        .unwrap();
+    tokio::spawn(server.listen_all(vec![
+        ListenerOptions::new("127.0.0.1:2172"),
+        ListenerOptions::new("127.0.0.1:2173").ftps_tls_first(TlsFirst::Required),
+    ]));
+
+    let mut open = connect(2172).await;
+    assert!(reply(&mut open).await.starts_with("220"));
+    open.get_mut().write_all(b"USER anonymous\r\n").await.unwrap();
+    assert!(reply(&mut open).await.starts_with("331"));
+
+    let mut strict = connect(2173).await;
+    assert!(reply(&mut strict).await.starts_with("220"));
+    strict.get_mut().write_all(b"USER anonymous\r\n").await.unwrap();
+    assert!(reply(&mut strict).await.starts_with("550"));
+    strict.get_mut().write_all(b"AUTH TLS\r\n").await.unwrap();
+    assert!(reply(&mut strict).await.starts_with("234"));
+}
+
+#[tokio::test]
+async fn refuses_tls_first_listeners_without_ftps() {
+    let server = libunftp::Server::with_fs(std::env::temp_dir()).build().unwrap();
+    let result = server.listen_all(vec![ListenerOptions::new("127.0.0.1:2174").ftps_tls_first(true)]).await;
+    assert!(result.is_err());
+}
@@ -0,0 +1,84 @@
+#![allow(missing_docs)]
+#![cfg(unix)]
+
+// File names that aren't valid UTF-8 reach the storage back-end and the client unchanged.
+
+use libunftp::options::Dotfiles;
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use tempfile::TempDir;
+use tokio::io::AsyncReadExt;
+use unftp_sbe_fs::ServerExt;
+use unftp_test_util::{Client, Harness};
+
+// Sends the command and returns the first line of the reply as is.
+async fn cmd(client: &mut Client, command: &[u8]) -> Vec<u8> {
+    client.send_bytes(command).await.unwrap();
+    client.raw_line().await.unwrap()
+}
+
+// Lists the names in the working directory as they were sent.
+async fn nlst(client: &mut Client) -> Vec<u8> {
+    let mut data = client.pasv().await.unwrap();
+    assert_eq!(client.cmd("NLST").await.unwrap().code, 150);
+    let mut listing = Vec::new();
+    data.read_to_end(&mut listing).await.unwrap();
+    assert_eq!(client.reply().await.unwrap().code, 226);
+    listing
+}
+
+// Downloads the file with the given name.
+async fn retr(client: &mut Client, name: &[u8]) -> Vec<u8> {
+    let mut data = client.pasv().await.unwrap();
+    assert!(cmd(client, &[b"RETR ", name].concat()).await.starts_with(b"150"));
+    let mut content = Vec::new();
+    data.read_to_end(&mut content).await.unwrap();
+    assert_eq!(client.reply().await.unwrap().code, 226);
+    content
+}
+
+#[tokio::test]
+async fn names_that_are_not_utf8_make_the_round_trip() {
+    let root = TempDir::new().unwrap();
+    std::fs::write(root.path().join(OsStr::from_bytes(b"caf\xe9.txt")), b"hello").unwrap();
+    let harness = Harness::start(libunftp::Server::with_fs(root.path().to_path_buf())).await.unwrap();
+    let mut client = harness.login("anonymous", "anonymous").await.unwrap();
+
+    assert_eq!(nlst(&mut client).await, b"caf\xe9.txt\r\n");
+    assert_eq!(retr(&mut client, b"caf\xe9.txt").await, b"hello");
+
+    let status = cmd(&mut client, b"STAT caf\xe9.txt").await;
+    assert!(status.starts_with(b"213-"), "{:?}", status);
+    assert!(client.raw_line().await.unwrap().ends_with(b" caf\xe9.txt\r\n"));
+    assert!(client.raw_line().await.unwrap().starts_with(b"213 "));
+
+    assert!(cmd(&mut client, b"MKD dir\xff").await.starts_with(b"257"));
+    assert!(cmd(&mut client, b"CWD dir\xff").await.starts_with(b"250"));
+    assert_eq!(cmd(&mut client, b"PWD").await, b"257 \"/dir\xff\"\r\n");
+    assert!(root.path().join(OsStr::from_bytes(b"dir\xff")).is_dir());
+}
+
+#[tokio::test]
+async fn names_in_the_raw_byte_range_are_kept_apart_from_raw_bytes() {
+    let root = TempDir::new().unwrap();
+    std::fs::write(root.path().join("a\u{ef80}.txt"), b"private").unwrap();
+    std::fs::write(root.path().join(OsStr::from_bytes(b"a\x80.txt")), b"raw").unwrap();
+    let harness = Harness::start(libunftp::Server::with_fs(root.path().to_path_buf())).await.unwrap();
+    let mut client = harness.login("anonymous", "anonymous").await.unwrap();
+
+    assert_eq!(retr(&mut client, "a\u{ef80}.txt".as_bytes()).await, b"private");
+    assert_eq!(retr(&mut client, b"a\x80.txt").await, b"raw");
+}
+
+#[tokio::test]
+async fn listings_without_dotfiles_keep_raw_names() {
+    let root = TempDir::new().unwrap();
+    std::fs::write(root.path().join(OsStr::from_bytes(b"caf\xe9.txt")), b"hello").unwrap();
+    std::fs::write(root.path().join(".hidden"), b"hidden").unwrap();
+    let harness = Harness::start(libunftp::Server::with_fs(root.path().to_path_buf()).dotfiles(Dotfiles::Hide))
+        .await
+        .unwrap();
+    let mut client = harness.login("anonymous", "anonymous").await.unwrap();
+
+    assert_eq!(nlst(&mut client).await, b"caf\xe9.txt\r\n");
+}
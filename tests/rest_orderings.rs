@@ -0,0 +1,85 @@
+#![allow(missing_docs)]
+
+// Clients don't agree on where REST goes: FileZilla sets up the data connection first and sends
+// REST right before RETR, while lftp and WinSCP may send REST first and TYPE or PASV after it. The
+// offset must reach the transfer in each of these orderings.
+
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use unftp_sbe_fs::ServerExt;
+use unftp_test_util::{Client, Harness};
+
+// Starts a server that serves a fresh directory and logs in to it.
+async fn start() -> (Harness, Client, TempDir) {
+    let root = TempDir::new().unwrap();
+    std::fs::write(root.path().join("hello.txt"), b"hello world").unwrap();
+    let harness = Harness::start(libunftp::Server::with_fs(root.path().to_path_buf())).await.unwrap();
+    let client = harness.login("anonymous", "anonymous").await.unwrap();
+    (harness, client, root)
+}
+
+async fn retr(client: &mut Client, mut data: TcpStream) -> String {
+    assert_eq!(client.cmd("RETR hello.txt").await.unwrap().code, 150);
+    let mut content = String::new();
+    data.read_to_string(&mut content).await.unwrap();
+    assert_eq!(client.reply().await.unwrap().code, 226);
+    content
+}
+
+#[tokio::test]
+async fn pasv_then_rest_then_retr() {
+    let (_harness, mut client, _root) = start().await;
+    assert_eq!(client.cmd("TYPE I").await.unwrap().code, 200);
+    let data = client.pasv().await.unwrap();
+    assert_eq!(client.cmd("REST 6").await.unwrap().code, 350);
+    assert_eq!(retr(&mut client, data).await, "world");
+}
+
+#[tokio::test]
+async fn rest_then_pasv_then_retr() {
+    let (_harness, mut client, _root) = start().await;
+    assert_eq!(client.cmd("REST 6").await.unwrap().code, 350);
+    let data = client.pasv().await.unwrap();
+    assert_eq!(retr(&mut client, data).await, "world");
+}
+
+#[tokio::test]
+async fn rest_then_type_then_pasv_then_retr() {
+    let (_harness, mut client, _root) = start().await;
+    assert_eq!(client.cmd("REST 6").await.unwrap().code, 350);
+    assert_eq!(client.cmd("TYPE I").await.unwrap().code, 200);
+    let data = client.pasv().await.unwrap();
+    assert_eq!(retr(&mut client, data).await, "world");
+}
+
+#[tokio::test]
+async fn rest_then_pasv_then_stor() {
+    let (_harness, mut client, root) = start().await;
+    assert_eq!(client.cmd("REST 5").await.unwrap().code, 350);
+    let mut data = client.pasv().await.unwrap();
+    assert_eq!(client.cmd("STOR hello.txt").await.unwrap().code, 150);
+    data.write_all(b", resumed").await.unwrap();
+    drop(data);
+    assert_eq!(client.reply().await.unwrap().code, 226);
+    assert_eq!(std::fs::read_to_string(root.path().join("hello.txt")).unwrap(), "hello, resumed");
+}
+
+#[tokio::test]
+async fn offset_survives_retr_without_data_connection() {
+    let (_harness, mut client, _root) = start().await;
+    assert_eq!(client.cmd("REST 6").await.unwrap().code, 350);
+    assert_eq!(client.cmd("RETR hello.txt").await.unwrap().code, 425);
+    let data = client.pasv().await.unwrap();
+    assert_eq!(retr(&mut client, data).await, "world");
+}
+
+#[tokio::test]
+async fn offset_is_used_by_one_transfer_only() {
+    let (_harness, mut client, _root) = start().await;
+    assert_eq!(client.cmd("REST 6").await.unwrap().code, 350);
+    let data = client.pasv().await.unwrap();
+    assert_eq!(retr(&mut client, data).await, "world");
+    let data = client.pasv().await.unwrap();
+    assert_eq!(retr(&mut client, data).await, "hello world");
+}
@@ -0,0 +1,63 @@
+#![allow(missing_docs)]
+
+// A misbehaving storage back-end must end up as the right reply to the client: transient failures
+// as 4xx replies that the client may retry, and a download that breaks off must not be reported
+// as complete.
+
+use libunftp::storage::{ErrorKind, Operation, Rule, Script, Scripted};
+use libunftp::ServerBuilder;
+use std::path::PathBuf;
+use tempfile::TempDir;
+use tokio::io::AsyncReadExt;
+use unftp_sbe_fs::Filesystem;
+use unftp_test_util::{Client, Harness};
+
+// Starts a server that serves a fresh directory through the script, and logs in to it.
+async fn start(script: Script) -> (Harness, Client, TempDir) {
+    let root = TempDir::new().unwrap();
+    std::fs::write(root.path().join("hello.txt"), b"hello world").unwrap();
+    let path = root.path().to_path_buf();
+    let builder = ServerBuilder::new(Box::new(move || Scripted::new(Filesystem::new(path.clone()), script.clone())));
+    let harness = Harness::start(builder).await.unwrap();
+    let client = harness.login("anonymous", "anonymous").await.unwrap();
+    (harness, client, root)
+}
+
+#[tokio::test]
+async fn transient_failures_are_replied_with_450() {
+    let script = Script::new();
+    script.add(
+        Rule::path("/hello.txt")
+            .operation(Operation::Metadata)
+            .fail(ErrorKind::TransientFileNotAvailable)
+            .times(1),
+    );
+    let (_harness, mut client, _root) = start(script.clone()).await;
+
+    let reply = client.cmd("SIZE hello.txt").await.unwrap();
+    assert_eq!(reply.code, 450, "{}", reply);
+    assert_eq!(client.size("hello.txt").await.unwrap(), 11);
+    assert_eq!(
+        script
+            .calls()
+            .into_iter()
+            .filter(|call| call == &(Operation::Metadata, PathBuf::from("/hello.txt")))
+            .count(),
+        2
+    );
+}
+
+#[tokio::test]
+async fn partial_reads_are_not_reported_as_complete() {
+    let script = Script::new();
+    script.add(Rule::path("/hello.txt").operation(Operation::Get).truncate_after(5));
+    let (_harness, mut client, _root) = start(script).await;
+
+    let mut data = client.pasv().await.unwrap();
+    assert_eq!(client.cmd("RETR hello.txt").await.unwrap().code, 150);
+    let mut content = Vec::new();
+    data.read_to_end(&mut content).await.unwrap();
+    assert_eq!(content, b"hello");
+    let reply = client.reply().await.unwrap();
+    assert_ne!(reply.code, 226, "{}", reply);
+}
@@ -0,0 +1,97 @@
+#![allow(missing_docs)]
+
+// Commands that may not run alongside a transfer are held back until it finished, without keeping
+// the control channel from answering NOOP, STAT, ABOR and QUIT in the meantime.
+
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use unftp_sbe_fs::ServerExt;
+use unftp_test_util::{Client, Harness};
+
+// Starts a server that serves a fresh directory and logs in to it.
+async fn start() -> (Harness, Client, TempDir) {
+    let root = TempDir::new().unwrap();
+    let harness = Harness::start(libunftp::Server::with_fs(root.path().to_path_buf())).await.unwrap();
+    let client = harness.login("anonymous", "anonymous").await.unwrap();
+    (harness, client, root)
+}
+
+#[tokio::test]
+async fn commands_wait_for_the_transfer_in_progress() {
+    let (_harness, mut client, root) = start().await;
+    let mut data = client.pasv().await.unwrap();
+    assert_eq!(client.cmd("STOR upload.txt").await.unwrap().code, 150);
+    data.write_all(b"hello").await.unwrap();
+
+    client.send("TYPE I").await.unwrap();
+    client.send("PWD").await.unwrap();
+    assert_eq!(client.cmd("NOOP").await.unwrap().code, 200);
+
+    data.write_all(b" world").await.unwrap();
+    drop(data);
+    assert_eq!(client.reply().await.unwrap().code, 226);
+    assert_eq!(client.reply().await.unwrap().code, 200);
+    assert_eq!(client.reply().await.unwrap().code, 257);
+    assert_eq!(std::fs::read_to_string(root.path().join("upload.txt")).unwrap(), "hello world");
+}
+
+#[tokio::test]
+async fn held_transfer_commands_run_after_the_transfer_in_progress() {
+    let (_harness, mut client, root) = start().await;
+    std::fs::write(root.path().join("hello.txt"), b"hello world").unwrap();
+    let mut data = client.pasv().await.unwrap();
+    assert_eq!(client.cmd("STOR upload.txt").await.unwrap().code, 150);
+
+    client.send("PASV").await.unwrap();
+    client.send("RETR hello.txt").await.unwrap();
+    assert_eq!(client.cmd("NOOP").await.unwrap().code, 200);
+
+    data.write_all(b"upload").await.unwrap();
+    drop(data);
+    assert_eq!(client.reply().await.unwrap().code, 226);
+    let pasv = client.reply().await.unwrap();
+    assert_eq!(pasv.code, 227);
+    let mut data = client.connect_pasv(&pasv).await.unwrap();
+    assert_eq!(client.reply().await.unwrap().code, 150);
+    let mut content = String::new();
+    data.read_to_string(&mut content).await.unwrap();
+    assert_eq!(content, "hello world");
+    assert_eq!(client.reply().await.unwrap().code, 226);
+}
+
+#[tokio::test]
+async fn abor_interrupts_the_transfer_in_progress() {
+    let (_harness, mut client, _root) = start().await;
+    let mut data = client.pasv().await.unwrap();
+    assert_eq!(client.cmd("STOR upload.txt").await.unwrap().code, 150);
+    data.write_all(b"partial").await.unwrap();
+
+    client.send("PWD").await.unwrap();
+    assert_eq!(client.cmd("ABOR").await.unwrap().code, 426);
+    assert_eq!(client.reply().await.unwrap().code, 226);
+    assert_eq!(client.reply().await.unwrap().code, 257);
+}
+
+#[tokio::test]
+async fn abor_is_answered_without_a_transfer() {
+    let (_harness, mut client, _root) = start().await;
+    let _data = client.pasv().await.unwrap();
+    assert_eq!(client.cmd("ABOR").await.unwrap().code, 226);
+    assert_eq!(client.cmd("ABOR").await.unwrap().code, 226);
+    assert_eq!(client.cmd("NOOP").await.unwrap().code, 200);
+}
+
+#[tokio::test]
+async fn too_many_held_commands_close_the_session() {
+    let (_harness, mut client, _root) = start().await;
+    let mut data = client.pasv().await.unwrap();
+    assert_eq!(client.cmd("STOR upload.txt").await.unwrap().code, 150);
+    data.write_all(b"partial").await.unwrap();
+
+    // The default limit is 32.
+    for _ in 0..32 {
+        client.send("PWD").await.unwrap();
+    }
+    assert_eq!(client.cmd("PWD").await.unwrap().code, 421);
+    assert!(client.reply().await.is_err());
+}
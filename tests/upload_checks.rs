@@ -0,0 +1,86 @@
+#![allow(missing_docs)]
+
+// Uploads that a hook or scanner rejects may only remove what they wrote themselves.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use libunftp::auth::DefaultUser;
+use libunftp::notification::{CompletedUpload, UploadHook, UploadRejection, UploadScanner};
+use libunftp::ServerBuilder;
+use std::path::Path;
+use tempfile::TempDir;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc::Receiver;
+use unftp_sbe_fs::{Filesystem, ServerExt};
+use unftp_test_util::{Client, Harness};
+
+#[derive(Debug)]
+struct Reject;
+
+#[async_trait]
+impl UploadHook for Reject {
+    async fn pre_complete(&self, _upload: &CompletedUpload) -> Result<(), UploadRejection> {
+        Err(UploadRejection::new("Not today"))
+    }
+}
+
+#[async_trait]
+impl UploadScanner for Reject {
+    async fn scan(&self, _path: String, mut data: Receiver<Bytes>) -> Result<(), UploadRejection> {
+        while data.recv().await.is_some() {}
+        Err(UploadRejection::new("Not today"))
+    }
+}
+
+type Builder = ServerBuilder<Filesystem, DefaultUser>;
+
+// Starts the server with a fresh directory and logs in to it.
+async fn start(configure: fn(Builder) -> Builder) -> (Harness, Client, TempDir) {
+    let root = TempDir::new().unwrap();
+    std::fs::write(root.path().join("report.txt"), b"old").unwrap();
+    let harness = Harness::start(configure(libunftp::Server::with_fs(root.path().to_path_buf()))).await.unwrap();
+    let client = harness.login("anonymous", "anonymous").await.unwrap();
+    (harness, client, root)
+}
+
+// Uploads the data with the given command and returns the code of the final reply.
+async fn upload(client: &mut Client, command: &str, content: &[u8]) -> u16 {
+    let mut data = client.pasv().await.unwrap();
+    assert_eq!(client.cmd(command).await.unwrap().code, 150);
+    data.write_all(content).await.unwrap();
+    drop(data);
+    client.reply().await.unwrap().code
+}
+
+fn file_names(root: &Path) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(root)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+#[tokio::test]
+async fn rejected_uploads_keep_the_existing_file() {
+    let (_harness, mut client, root) = start(|server| server.upload_hook(Reject)).await;
+    assert_eq!(upload(&mut client, "STOR report.txt", b"new").await, 550);
+    assert_eq!(std::fs::read_to_string(root.path().join("report.txt")).unwrap(), "old");
+    assert_eq!(file_names(root.path()), vec!["report.txt"]);
+}
+
+#[tokio::test]
+async fn rejected_unique_uploads_leave_no_file_behind() {
+    let (_harness, mut client, root) = start(|server| server.upload_scanner(Reject)).await;
+    assert_eq!(upload(&mut client, "STOU", b"new").await, 451);
+    assert_eq!(file_names(root.path()), vec!["report.txt"]);
+}
+
+#[tokio::test]
+async fn checked_uploads_cannot_be_resumed() {
+    let (_harness, mut client, root) = start(|server| server.upload_hook(Reject)).await;
+    let _data = client.pasv().await.unwrap();
+    assert_eq!(client.cmd("REST 3").await.unwrap().code, 350);
+    assert_eq!(client.cmd("STOR report.txt").await.unwrap().code, 554);
+    assert_eq!(std::fs::read_to_string(root.path().join("report.txt")).unwrap(), "old");
+}